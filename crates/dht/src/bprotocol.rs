@@ -161,6 +161,9 @@ struct RawMessage<BufT, Args = IgnoredAny, Resp = IgnoredAny> {
     version: Option<BufT>,
     #[serde(rename = "ip", skip_serializing_if = "Option::is_none")]
     ip: Option<CompactPeerInfo>,
+    // BEP 43: set to 1 in queries from nodes that don't answer queries.
+    #[serde(rename = "ro", default, skip_serializing_if = "Option::is_none")]
+    read_only: Option<u8>,
 }
 
 pub struct Node {
@@ -395,8 +398,11 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
     version: Option<BufT>,
     ip: Option<SocketAddrV4>,
     kind: MessageKind<BufT>,
+    read_only: bool,
 ) -> anyhow::Result<()> {
     let ip = ip.map(|ip| CompactPeerInfo { addr: ip });
+    // Only queries carry the flag.
+    let ro = if read_only { Some(1) } else { None };
     match kind {
         MessageKind::Error(e) => {
             let msg: RawMessage<BufT, (), ()> = RawMessage {
@@ -408,6 +414,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 version,
                 ip,
                 arguments: None,
+                read_only: None,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -421,6 +428,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 arguments: Some(req),
                 ip,
                 version,
+                read_only: ro,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -434,6 +442,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 arguments: Some(req),
                 ip,
                 version,
+                read_only: ro,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -447,6 +456,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 arguments: None,
                 ip,
                 version,
+                read_only: None,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -460,6 +470,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 arguments: Some(ping),
                 ip,
                 version,
+                read_only: ro,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -473,6 +484,7 @@ pub fn serialize_message<'a, W: Write, BufT: Serialize + From<&'a [u8]>>(
                 arguments: Some(announce),
                 ip,
                 version,
+                read_only: ro,
             };
             Ok(bencode::bencode_serialize_to_writer(msg, writer)?)
         }
@@ -611,7 +623,7 @@ mod tests {
             ip,
         } = dbg!(bprotocol::deserialize_message::<ByteBuf>(data).unwrap());
         let mut buf = Vec::new();
-        bprotocol::serialize_message(&mut buf, transaction_id, version, ip, kind).unwrap();
+        bprotocol::serialize_message(&mut buf, transaction_id, version, ip, kind, false).unwrap();
 
         if buf.as_slice() != data {
             write(&format!("{name}-serialized"), buf.as_slice());
@@ -636,6 +648,7 @@ mod tests {
                 code: 201,
                 description: ByteBuf(b"Some error"),
             }),
+            false,
         )
         .unwrap();
 
@@ -646,7 +659,7 @@ mod tests {
         } = bprotocol::deserialize_message::<ByteBuf>(&buf).unwrap();
 
         let mut buf2 = Vec::new();
-        bprotocol::serialize_message(&mut buf2, transaction_id, None, None, kind).unwrap();
+        bprotocol::serialize_message(&mut buf2, transaction_id, None, None, kind, false).unwrap();
 
         if buf.as_slice() != buf2.as_slice() {
             write("error-serialized", buf.as_slice());
@@ -696,11 +709,58 @@ mod tests {
             _ => panic!("wrong kind"),
         }
         let mut buf = Vec::new();
-        bprotocol::serialize_message(&mut buf, msg.transaction_id, msg.version, msg.ip, msg.kind)
-            .unwrap();
+        bprotocol::serialize_message(
+            &mut buf,
+            msg.transaction_id,
+            msg.version,
+            msg.ip,
+            msg.kind,
+            false,
+        )
+        .unwrap();
         assert_eq!(ann[..], buf[..]);
     }
 
+    #[test]
+    fn test_read_only_flag_only_in_queries() {
+        let id = librqbit_core::hash_id::Id20::new([1u8; 20]);
+        let mut query = Vec::new();
+        bprotocol::serialize_message(
+            &mut query,
+            ByteBuf(b"aa"),
+            None,
+            None,
+            bprotocol::MessageKind::GetPeersRequest(bprotocol::GetPeersRequest {
+                id,
+                info_hash: id,
+            }),
+            true,
+        )
+        .unwrap();
+        assert!(query.windows(7).any(|w| w == b"2:roi1e"));
+        assert!(matches!(
+            bprotocol::deserialize_message::<ByteBuf>(&query)
+                .unwrap()
+                .kind,
+            bprotocol::MessageKind::GetPeersRequest(_)
+        ));
+
+        let mut response = Vec::new();
+        bprotocol::serialize_message(
+            &mut response,
+            ByteBuf(b"aa"),
+            None,
+            None,
+            bprotocol::MessageKind::Response(bprotocol::Response {
+                id,
+                ..Default::default()
+            }),
+            true,
+        )
+        .unwrap();
+        assert!(!response.windows(4).any(|w| w == b"2:ro"));
+    }
+
     #[test]
     fn deserialize_bencode_packets_captured_from_wireshark() {
         debug_hex_bencode("req: find_node", FIND_NODE_REQUEST);
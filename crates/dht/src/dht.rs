@@ -1,9 +1,10 @@
 use std::{
     cmp::Reverse,
     net::SocketAddr,
+    path::PathBuf,
     str::FromStr,
     sync::{
-        atomic::{AtomicU16, Ordering},
+        atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering},
         Arc,
     },
     task::Poll,
@@ -16,7 +17,8 @@ use crate::{
         Message, MessageKind, Node, PingRequest, Response,
     },
     peer_store::PeerStore,
-    routing_table::{InsertResult, NodeStatus, RoutingTable},
+    query_rate::QueryRateMeter,
+    routing_table::{generate_random_id, InsertResult, NodeStatus, RoutingTable},
     INACTIVITY_TIMEOUT, REQUERY_INTERVAL, RESPONSE_TIMEOUT,
 };
 use anyhow::{bail, Context};
@@ -28,15 +30,19 @@ use futures::{stream::FuturesUnordered, Stream, StreamExt, TryFutureExt};
 use leaky_bucket::RateLimiter;
 use librqbit_core::{
     hash_id::Id20,
+    jitter::sleep_jittered,
     peer_id::generate_peer_id,
     spawn_utils::{spawn, spawn_with_cancel},
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 use serde::Serialize;
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::{channel, unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{channel, unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
 };
 
 use tokio_util::sync::CancellationToken;
@@ -48,6 +54,73 @@ pub struct DhtStats {
     pub id: Id20,
     pub outstanding_requests: usize,
     pub routing_table_size: usize,
+    pub read_only: bool,
+    /// Outgoing queries per second, averaged over the last few seconds.
+    pub query_rate: f64,
+    /// See [DhtConfig::max_query_rate].
+    pub max_query_rate: usize,
+    /// Routing table nodes that responded to us recently.
+    pub good_nodes: usize,
+    /// Set while [DhtState::warmup] runs.
+    pub warmup: Option<DhtWarmupProgress>,
+}
+
+/// How far [DhtState::warmup] got.
+#[derive(Debug, Serialize, Clone)]
+pub struct DhtWarmupProgress {
+    pub min_good_nodes: usize,
+    pub good_nodes: usize,
+    pub routing_table_size: usize,
+    pub elapsed_ms: u64,
+    /// Whether min_good_nodes was reached. False while it runs, or if it timed out.
+    pub reached: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DhtLookupState {
+    /// Waiting for responses from queried nodes.
+    Querying,
+    /// No requests in flight, waiting to re-query the closest nodes.
+    Waiting,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DhtLookupStats {
+    #[serde(serialize_with = "crate::utils::serialize_id20")]
+    pub info_hash: Id20,
+    pub nodes_queried: u64,
+    pub peers_found: u64,
+    pub inflight_requests: u64,
+    pub state: DhtLookupState,
+    pub elapsed_seconds: u64,
+}
+
+// Live counters of one get_peers lookup, shared between the lookup task and DhtState.
+struct ActiveLookup {
+    info_hash: Id20,
+    started: Instant,
+    nodes_queried: AtomicU64,
+    peers_found: AtomicU64,
+    inflight_requests: AtomicU64,
+}
+
+impl ActiveLookup {
+    fn stats(&self) -> DhtLookupStats {
+        let inflight_requests = self.inflight_requests.load(Ordering::Relaxed);
+        DhtLookupStats {
+            info_hash: self.info_hash,
+            nodes_queried: self.nodes_queried.load(Ordering::Relaxed),
+            peers_found: self.peers_found.load(Ordering::Relaxed),
+            inflight_requests,
+            state: if inflight_requests > 0 {
+                DhtLookupState::Querying
+            } else {
+                DhtLookupState::Waiting
+            },
+            elapsed_seconds: self.started.elapsed().as_secs(),
+        }
+    }
 }
 
 struct OutstandingRequest {
@@ -71,20 +144,33 @@ struct MaybeUsefulNode {
     returned_peers: bool,
 }
 
-fn make_rate_limiter() -> RateLimiter {
-    // TODO: move to configuration, i'm lazy.
-    let dht_queries_per_second = std::env::var("DHT_QUERIES_PER_SECOND")
-        .map(|v| v.parse().expect("couldn't parse DHT_QUERIES_PER_SECOND"))
-        .unwrap_or(250usize);
+const DEFAULT_MAX_QUERY_RATE: usize = 250;
+// How often DhtState::warmup starts another lookup until it has enough good nodes.
+const WARMUP_ROUND_INTERVAL: Duration = Duration::from_secs(1);
 
-    let per_100_ms = dht_queries_per_second / 10;
+// Clears DhtState::warmup however the warmup ends, e.g. the caller giving up on it.
+struct WarmupGuard<'a>(&'a Mutex<Option<(usize, Instant)>>);
+
+impl Drop for WarmupGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.lock() = None;
+    }
+}
+
+fn make_rate_limiter(queries_per_second: usize) -> RateLimiter {
+    // Refilled every 100ms to smooth out bursts, or less often for rates below 10/s.
+    let (refill, interval) = if queries_per_second >= 10 {
+        (queries_per_second / 10, Duration::from_millis(100))
+    } else {
+        (1, Duration::from_millis(1000 / queries_per_second as u64))
+    };
 
     RateLimiter::builder()
-        .initial(per_100_ms)
-        .max(dht_queries_per_second)
-        .interval(Duration::from_millis(100))
+        .initial(refill)
+        .max(queries_per_second)
+        .interval(interval)
         .fair(false)
-        .refill(per_100_ms)
+        .refill(refill)
         .build()
 }
 
@@ -103,10 +189,16 @@ struct RecursiveRequestCallbacksGetPeers {
     // Id20::from_str("00000fffffffffffffffffffffffffffffffffff").unwrap()
     min_distance_to_announce: Id20,
     announce_port: Option<u16>,
+    lookup: Arc<ActiveLookup>,
 }
 
 impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
-    fn on_request_start(&self, _: &RecursiveRequest<Self>, _: Id20, _: SocketAddr) {}
+    fn on_request_start(&self, _: &RecursiveRequest<Self>, _: Id20, _: SocketAddr) {
+        self.lookup.nodes_queried.fetch_add(1, Ordering::Relaxed);
+        self.lookup
+            .inflight_requests
+            .fetch_add(1, Ordering::Relaxed);
+    }
 
     fn on_request_end(
         &self,
@@ -115,6 +207,19 @@ impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
         addr: SocketAddr,
         resp: &anyhow::Result<ResponseOrError>,
     ) {
+        self.lookup
+            .inflight_requests
+            .fetch_sub(1, Ordering::Relaxed);
+        if let Ok(ResponseOrError::Response(Response {
+            values: Some(peers),
+            ..
+        })) = resp
+        {
+            self.lookup
+                .peers_found
+                .fetch_add(peers.len() as u64, Ordering::Relaxed);
+        }
+
         let announce_port = match self.announce_port {
             Some(a) => a,
             None => return,
@@ -135,12 +240,18 @@ impl RecursiveRequestCallbacks for RecursiveRequestCallbacksGetPeers {
             );
             return;
         }
+        if req.dht.is_paused() {
+            return;
+        }
         let (tid, message) = req.dht.create_request(Request::Announce {
             info_hash: req.info_hash,
             token: token.clone(),
             port: announce_port,
         });
 
+        // Announces aren't rate limited, there's at most one per node close to the info
+        // hash.
+        req.dht.query_rate.lock().record(Instant::now());
         let _ = req.dht.worker_sender.send(WorkerSendRequest {
             our_tid: Some(tid),
             message,
@@ -192,18 +303,29 @@ struct RecursiveRequest<C: RecursiveRequestCallbacks> {
 pub struct RequestPeersStream {
     rx: tokio::sync::mpsc::UnboundedReceiver<SocketAddr>,
     cancel_join_handle: tokio::task::JoinHandle<()>,
+    dht: Arc<DhtState>,
+    lookup_id: u64,
 }
 
 impl RequestPeersStream {
     fn new(dht: Arc<DhtState>, info_hash: Id20, announce_port: Option<u16>) -> Self {
         let (peer_tx, peer_rx) = unbounded_channel();
         let (node_tx, node_rx) = unbounded_channel();
+        let lookup = Arc::new(ActiveLookup {
+            info_hash,
+            started: Instant::now(),
+            nodes_queried: AtomicU64::new(0),
+            peers_found: AtomicU64::new(0),
+            inflight_requests: AtomicU64::new(0),
+        });
+        let lookup_id = dht.next_lookup_id.fetch_add(1, Ordering::Relaxed);
+        dht.active_lookups.insert(lookup_id, lookup.clone());
         let rp = Arc::new(RecursiveRequest {
             max_depth: 4,
             info_hash,
             useful_nodes_limit: 256,
             request: Request::GetPeers(info_hash),
-            dht,
+            dht: dht.clone(),
             useful_nodes: RwLock::new(Vec::new()),
             peer_tx,
             node_tx,
@@ -213,12 +335,15 @@ impl RequestPeersStream {
                 )
                 .unwrap(),
                 announce_port,
+                lookup,
             },
         });
         let join_handle = rp.request_peers_forever(node_rx);
         Self {
             rx: peer_rx,
             cancel_join_handle: join_handle,
+            dht,
+            lookup_id,
         }
     }
 }
@@ -226,6 +351,7 @@ impl RequestPeersStream {
 impl Drop for RequestPeersStream {
     fn drop(&mut self) {
         self.cancel_join_handle.abort();
+        self.dht.active_lookups.remove(&self.lookup_id);
     }
 }
 
@@ -330,6 +456,7 @@ impl RecursiveRequest<RecursiveRequestCallbacksGetPeers> {
                 // Looper adds root nodes to the queue every 60 seconds.
                 let looper = {
                     async move {
+                        let mut network_changed = this.dht.network_changed.subscribe();
                         let mut iteration = 0;
                         loop {
                             trace!("iteration {}", iteration);
@@ -342,7 +469,11 @@ impl RecursiveRequest<RecursiveRequestCallbacksGetPeers> {
                                     return Err::<(), anyhow::Error>(e);
                                 }
                             };
-                            tokio::time::sleep(sleep).await;
+                            tokio::select! {
+                                _ = sleep_jittered(sleep) => {},
+                                // Re-query right away, so that peers learn our new address.
+                                Ok(()) = network_changed.changed() => {},
+                            }
                             iteration += 1;
                         }
                     }
@@ -537,15 +668,37 @@ pub struct DhtState {
 
     // Sending requests to the worker.
     rate_limiter: RateLimiter,
+    max_query_rate: usize,
+    query_rate: Mutex<QueryRateMeter>,
     // This is to send raw messages
     worker_sender: UnboundedSender<WorkerSendRequest>,
 
     cancellation_token: CancellationToken,
 
     pub(crate) peer_store: PeerStore,
+
+    // Where the state is periodically dumped to. Only set for persistent DHT.
+    pub(crate) persistence_filename: RwLock<Option<PathBuf>>,
+
+    // get_peers lookups that are currently running, by a unique lookup id.
+    next_lookup_id: AtomicU64,
+    active_lookups: DashMap<u64, Arc<ActiveLookup>>,
+
+    // While true, no requests are sent out. Incoming queries are still answered.
+    paused: watch::Sender<bool>,
+
+    // Bumped on every network change.
+    network_changed: watch::Sender<u64>,
+
+    // BEP 43 read-only node: queries from others are ignored, and ours say so.
+    read_only: AtomicBool,
+
+    // The target and start time of the running warmup(), if any.
+    warmup: Mutex<Option<(usize, Instant)>>,
 }
 
 impl DhtState {
+    #[allow(clippy::too_many_arguments)]
     fn new_internal(
         id: Id20,
         sender: UnboundedSender<WorkerSendRequest>,
@@ -553,6 +706,8 @@ impl DhtState {
         listen_addr: SocketAddr,
         peer_store: PeerStore,
         cancellation_token: CancellationToken,
+        read_only: bool,
+        max_query_rate: usize,
     ) -> Self {
         let routing_table = routing_table.unwrap_or_else(|| RoutingTable::new(id, None));
         Self {
@@ -562,14 +717,25 @@ impl DhtState {
             routing_table: RwLock::new(routing_table),
             worker_sender: sender,
             listen_addr,
-            rate_limiter: make_rate_limiter(),
+            rate_limiter: make_rate_limiter(max_query_rate),
+            max_query_rate,
+            query_rate: Default::default(),
             peer_store,
             cancellation_token,
+            persistence_filename: RwLock::new(None),
+            next_lookup_id: AtomicU64::new(0),
+            active_lookups: Default::default(),
+            paused: watch::channel(false).0,
+            network_changed: watch::channel(0).0,
+            read_only: AtomicBool::new(read_only),
+            warmup: Mutex::new(None),
         }
     }
 
     async fn request(&self, request: Request, addr: SocketAddr) -> anyhow::Result<ResponseOrError> {
+        self.paused.subscribe().wait_for(|paused| !*paused).await?;
         self.rate_limiter.acquire_one().await;
+        self.query_rate.lock().record(Instant::now());
         let (tid, message) = self.create_request(request);
         let key = (tid, addr);
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -713,6 +879,11 @@ impl DhtState {
             _ => {}
         };
 
+        if self.is_read_only() {
+            trace!("read-only, ignoring query from {addr}: {msg:?}");
+            return Ok(());
+        }
+
         trace!("received query from {addr}: {msg:?}");
 
         match &msg.kind {
@@ -808,6 +979,26 @@ impl DhtState {
             id: self.id,
             outstanding_requests: self.inflight_by_transaction_id.len(),
             routing_table_size: self.routing_table.read().len(),
+            read_only: self.is_read_only(),
+            query_rate: self.query_rate.lock().rate(Instant::now()),
+            max_query_rate: self.max_query_rate,
+            good_nodes: self.routing_table.read().good_nodes(),
+            warmup: self
+                .warmup
+                .lock()
+                .map(|(min_good_nodes, started)| self.warmup_progress(min_good_nodes, started)),
+        }
+    }
+
+    fn warmup_progress(&self, min_good_nodes: usize, started: Instant) -> DhtWarmupProgress {
+        let table = self.routing_table.read();
+        let good_nodes = table.good_nodes();
+        DhtWarmupProgress {
+            min_good_nodes,
+            good_nodes,
+            routing_table_size: table.len(),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            reached: false,
         }
     }
 }
@@ -910,11 +1101,9 @@ impl DhtWorker {
 
         let mut futs = FuturesUnordered::new();
         let filler = async {
-            let mut interval = tokio::time::interval(INACTIVITY_TIMEOUT);
-            interval.tick().await;
             let mut iteration = 0;
             loop {
-                interval.tick().await;
+                sleep_jittered(INACTIVITY_TIMEOUT).await;
                 let mut found = 0;
                 for bucket in self.dht.routing_table.read().iter_buckets() {
                     if bucket.leaf.last_refreshed.elapsed() < INACTIVITY_TIMEOUT {
@@ -957,12 +1146,13 @@ impl DhtWorker {
 
     async fn pinger(&self) -> anyhow::Result<()> {
         let mut futs = FuturesUnordered::new();
-        let mut interval = tokio::time::interval(INACTIVITY_TIMEOUT / 4);
         let (tx, mut rx) = unbounded_channel();
         let looper = async {
             let mut iteration = 0;
             loop {
-                interval.tick().await;
+                if iteration > 0 {
+                    sleep_jittered(INACTIVITY_TIMEOUT / 4).await;
+                }
                 let mut found = 0;
                 for node in self.dht.routing_table.read().iter() {
                     if matches!(
@@ -1027,6 +1217,7 @@ impl DhtWorker {
                     message.version,
                     message.ip,
                     message.kind,
+                    self.dht.is_read_only(),
                 )
                 .unwrap();
                 if let Err(e) = socket.send_to(&buf, addr).await {
@@ -1105,6 +1296,8 @@ impl DhtWorker {
         tokio::pin!(pinger);
         tokio::pin!(bucket_refresher);
 
+        let mut network_changed = self.dht.network_changed.subscribe();
+
         loop {
             tokio::select! {
                 err = &mut framer => {
@@ -1114,6 +1307,12 @@ impl DhtWorker {
                     bootstrap_done = true;
                     result?;
                 },
+                Ok(()) = network_changed.changed() => {
+                    // Most of the routing table may be unreachable from the new network.
+                    info!("network changed, bootstrapping again");
+                    bootstrap.set(self.bootstrap(bootstrap_addrs));
+                    bootstrap_done = false;
+                },
                 err = &mut pinger => {
                     anyhow::bail!("pinger quit: {:?}", err)
                 },
@@ -1134,6 +1333,12 @@ pub struct DhtConfig {
     pub listen_addr: Option<SocketAddr>,
     pub peer_store: Option<PeerStore>,
     pub cancellation_token: Option<CancellationToken>,
+    /// Start as a BEP 43 read-only node, see [DhtState::set_read_only].
+    pub read_only: bool,
+    /// The most queries to send out per second. Lower saves bandwidth, e.g. on metered
+    /// links, at the cost of slower bootstrapping and peer lookups. Defaults to 250, or to
+    /// the DHT_QUERIES_PER_SECOND environment variable if set.
+    pub max_query_rate: Option<usize>,
 }
 
 impl DhtState {
@@ -1166,6 +1371,16 @@ impl DhtState {
             .unwrap_or_else(|| crate::DHT_BOOTSTRAP.iter().map(|v| v.to_string()).collect());
 
         let token = config.cancellation_token.take().unwrap_or_default();
+        let max_query_rate = match config.max_query_rate {
+            Some(rate) => rate,
+            None => match std::env::var("DHT_QUERIES_PER_SECOND") {
+                Ok(v) => v.parse().context("couldn't parse DHT_QUERIES_PER_SECOND")?,
+                Err(_) => DEFAULT_MAX_QUERY_RATE,
+            },
+        };
+        if max_query_rate == 0 {
+            bail!("max_query_rate must be at least 1");
+        }
 
         let (in_tx, in_rx) = unbounded_channel();
         let state = Arc::new(Self::new_internal(
@@ -1175,6 +1390,8 @@ impl DhtState {
             listen_addr,
             config.peer_store.unwrap_or_else(|| PeerStore::new(peer_id)),
             token,
+            config.read_only,
+            max_query_rate,
         ));
 
         spawn_with_cancel(error_span!("dht"), state.cancellation_token.clone(), {
@@ -1207,6 +1424,115 @@ impl DhtState {
         self.get_stats()
     }
 
+    /// Stop sending any requests (lookups, announces, routing table refreshes) until
+    /// unpaused. The node keeps answering queries from others, so it stays in the network.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.send_replace(paused);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Make this a read-only node (BEP 43): it still looks up peers and announces, but
+    /// doesn't answer queries from others, and tells them so, so that they don't add it to
+    /// their routing tables. Less traffic, e.g. for mobile or embedded use.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+        info!(read_only, "changed DHT read-only mode");
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Call when the network the host is on changes, e.g. it switched from WiFi to ethernet.
+    /// This bootstraps again and re-runs all peer lookups and announces right away.
+    pub fn on_network_change(&self) {
+        self.network_changed
+            .send_modify(|generation| *generation += 1);
+    }
+
+    /// Stats of all get_peers lookups that are currently running.
+    pub fn active_lookups(&self) -> Vec<DhtLookupStats> {
+        self.active_lookups
+            .iter()
+            .map(|lookup| lookup.value().stats())
+            .collect()
+    }
+
+    /// Fill the routing table now rather than waiting for the periodic refreshes, e.g. right
+    /// after startup so that the first magnet links resolve faster. Returns once there are
+    /// at least min_good_nodes good nodes, or after the timeout. Progress is shown in
+    /// [DhtStats::warmup] meanwhile, until it returns or the future is dropped. Fails if
+    /// another warmup is running.
+    pub async fn warmup(
+        self: &Arc<Self>,
+        min_good_nodes: usize,
+        timeout: Duration,
+    ) -> anyhow::Result<DhtWarmupProgress> {
+        let started = Instant::now();
+        let _running = {
+            let mut g = self.warmup.lock();
+            if g.is_some() {
+                bail!("a DHT warmup is already running");
+            }
+            *g = Some((min_good_nodes, started));
+            WarmupGuard(&self.warmup)
+        };
+        let deadline = tokio::time::Instant::from_std(started + timeout);
+
+        let mut futs = FuturesUnordered::new();
+        let mut round = 0;
+        let mut next_round = tokio::time::Instant::now();
+        let mut progress = self.warmup_progress(min_good_nodes, started);
+        while progress.good_nodes < min_good_nodes {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = tokio::time::sleep_until(next_round) => {
+                    // Our own id first, to fill the buckets closest to us, then random ones.
+                    let target = if round == 0 {
+                        self.id
+                    } else {
+                        generate_random_id(&self.id, 160)
+                    };
+                    let addrs = self
+                        .routing_table
+                        .read()
+                        .sorted_by_distance_from(target)
+                        .iter()
+                        .map(|n| n.addr())
+                        .take(8)
+                        .collect::<Vec<_>>();
+                    // Nothing to ask until bootstrapping finds the first nodes.
+                    if !addrs.is_empty() {
+                        futs.push(
+                            RecursiveRequest::find_node_for_routing_table(
+                                self.clone(),
+                                target,
+                                addrs.into_iter(),
+                            )
+                            .instrument(error_span!("dht_warmup", round)),
+                        );
+                        round += 1;
+                    }
+                    next_round = tokio::time::Instant::now() + WARMUP_ROUND_INTERVAL;
+                },
+                _ = futs.next(), if !futs.is_empty() => {},
+            }
+            progress = self.warmup_progress(min_good_nodes, started);
+        }
+
+        progress.reached = progress.good_nodes >= min_good_nodes;
+        info!(
+            good_nodes = progress.good_nodes,
+            reached = progress.reached,
+            "DHT warmup finished in {:?}",
+            started.elapsed()
+        );
+        Ok(progress)
+    }
+
     pub fn with_routing_table<R, F: FnOnce(&RoutingTable) -> R>(&self, f: F) -> R {
         f(&self.routing_table.read())
     }
@@ -1215,3 +1541,71 @@ impl DhtState {
         self.routing_table.read().clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use librqbit_core::hash_id::Id20;
+
+    use super::{DhtConfig, DhtLookupState, DhtState};
+
+    #[tokio::test]
+    async fn test_one_warmup_at_a_time() {
+        let dht = DhtState::with_config(DhtConfig {
+            bootstrap_addrs: Some(Vec::new()),
+            listen_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // Without nodes to ask, it runs until the timeout.
+        let first = tokio::spawn({
+            let dht = dht.clone();
+            async move { dht.warmup(8, Duration::from_secs(60)).await }
+        });
+        while dht.get_stats().warmup.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dht.warmup(8, Duration::from_secs(60)).await.is_err());
+        assert!(dht.get_stats().warmup.is_some());
+
+        // The caller going away ends it.
+        first.abort();
+        let _ = first.await;
+        assert!(dht.get_stats().warmup.is_none());
+        let progress = dht.warmup(8, Duration::from_millis(50)).await.unwrap();
+        assert!(!progress.reached);
+        assert!(dht.get_stats().warmup.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_active_lookups() {
+        let dht = DhtState::with_config(DhtConfig {
+            bootstrap_addrs: Some(Vec::new()),
+            listen_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(dht.active_lookups().is_empty());
+
+        let info_hash = Id20::new([1; 20]);
+        let stream = dht.get_peers(info_hash, None).unwrap();
+        let lookups = dht.active_lookups();
+        assert_eq!(lookups.len(), 1);
+        assert_eq!(lookups[0].info_hash, info_hash);
+        // There are no nodes to ask.
+        assert_eq!(lookups[0].nodes_queried, 0);
+        assert_eq!(lookups[0].state, DhtLookupState::Waiting);
+
+        let lookup = dht.active_lookups.iter().next().unwrap().value().clone();
+        lookup.inflight_requests.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(dht.active_lookups()[0].state, DhtLookupState::Querying);
+
+        // Gone once nobody waits for its peers.
+        drop(stream);
+        assert!(dht.active_lookups().is_empty());
+    }
+}
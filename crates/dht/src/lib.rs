@@ -2,14 +2,15 @@ mod bprotocol;
 mod dht;
 mod peer_store;
 mod persistence;
+mod query_rate;
 mod routing_table;
 mod utils;
 
 use std::sync::Arc;
 use std::time::Duration;
 
-pub use crate::dht::DhtStats;
 pub use crate::dht::{DhtConfig, DhtState, RequestPeersStream};
+pub use crate::dht::{DhtLookupState, DhtLookupStats, DhtStats, DhtWarmupProgress};
 pub use librqbit_core::hash_id::Id20;
 pub use persistence::{PersistentDht, PersistentDhtConfig};
 
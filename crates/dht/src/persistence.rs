@@ -21,6 +21,10 @@ use crate::{Dht, DhtConfig, DhtState};
 pub struct PersistentDhtConfig {
     pub dump_interval: Option<Duration>,
     pub config_filename: Option<PathBuf>,
+    /// Run as a BEP 43 read-only node, see [DhtState::set_read_only].
+    pub read_only: bool,
+    /// See [DhtConfig::max_query_rate].
+    pub max_query_rate: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,7 +38,14 @@ pub struct PersistentDht {
     // config_filename: PathBuf,
 }
 
-fn dump_dht(dht: &Dht, filename: &Path, tempfile_name: &Path) -> anyhow::Result<()> {
+fn tempfile_name(filename: &Path) -> PathBuf {
+    let file_name = format!("dht.json.tmp.{}", std::process::id());
+    let mut tmp = filename.to_owned();
+    tmp.set_file_name(file_name);
+    tmp
+}
+
+fn dump_dht(dht: &DhtState, filename: &Path, tempfile_name: &Path) -> anyhow::Result<()> {
     let file = OpenOptions::new()
         .truncate(true)
         .create(true)
@@ -129,9 +140,12 @@ impl PersistentDht {
             listen_addr,
             peer_store,
             cancellation_token,
+            read_only: config.read_only,
+            max_query_rate: config.max_query_rate,
             ..Default::default()
         };
         let dht = DhtState::with_config(dht_config).await?;
+        *dht.persistence_filename.write() = Some(config_filename);
         spawn_with_cancel(
             error_span!("dht_persistence"),
             dht.cancellation_token().clone(),
@@ -141,18 +155,20 @@ impl PersistentDht {
                     .dump_interval
                     .unwrap_or_else(|| Duration::from_secs(3));
                 async move {
-                    let tempfile_name = {
-                        let file_name = format!("dht.json.tmp.{}", std::process::id());
-                        let mut tmp = config_filename.clone();
-                        tmp.set_file_name(file_name);
-                        tmp
-                    };
-
                     loop {
                         trace!("sleeping for {:?}", &dump_interval);
                         tokio::time::sleep(dump_interval).await;
 
-                        match dump_dht(&dht, &config_filename, &tempfile_name) {
+                        // Re-read every time, as it might have been relocated. Held while
+                        // writing, so that a relocation doesn't switch files mid-dump.
+                        let config_filename = dht.persistence_filename.write();
+                        let config_filename = match config_filename.as_ref() {
+                            Some(f) => f,
+                            None => return Ok(()),
+                        };
+                        let tmp = tempfile_name(config_filename);
+
+                        match dump_dht(&dht, config_filename, &tmp) {
                             Ok(_) => trace!(filename=?config_filename, "dumped DHT"),
                             Err(e) => {
                                 error!(filename=?config_filename, "error dumping DHT: {:#}", e)
@@ -166,3 +182,29 @@ impl PersistentDht {
         Ok(dht)
     }
 }
+
+impl DhtState {
+    /// The file the DHT state is periodically dumped to. None if persistence is disabled.
+    pub fn persistence_filename(&self) -> Option<PathBuf> {
+        self.persistence_filename.read().clone()
+    }
+
+    /// Dump the DHT state to a new file, and switch all future dumps to it.
+    /// The old file is left as is.
+    pub fn relocate_persistence(&self, filename: PathBuf) -> anyhow::Result<()> {
+        // Periodic dumps wait until the switch is done.
+        let mut current = self.persistence_filename.write();
+        if current.is_none() {
+            anyhow::bail!("DHT persistence is disabled");
+        }
+        if let Some(parent) = filename.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("error creating dir {:?}", &parent))?;
+        }
+        // This also checks that the new location is writable.
+        dump_dht(self, &filename, &tempfile_name(&filename))?;
+        info!(?filename, "relocated DHT persistence");
+        *current = Some(filename);
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+// The rate reported is the average over the last full window.
+const WINDOW: Duration = Duration::from_secs(5);
+
+// Measures how many queries per second we send out, for DhtStats::query_rate.
+#[derive(Default)]
+pub(crate) struct QueryRateMeter {
+    window_start: Option<Instant>,
+    in_window: u64,
+    last_rate: f64,
+}
+
+impl QueryRateMeter {
+    fn roll(&mut self, now: Instant) {
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed < WINDOW {
+            return;
+        }
+        self.last_rate = self.in_window as f64 / elapsed.as_secs_f64();
+        self.window_start = Some(now);
+        self.in_window = 0;
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.roll(now);
+        self.in_window += 1;
+    }
+
+    pub fn rate(&mut self, now: Instant) -> f64 {
+        self.roll(now);
+        self.last_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::QueryRateMeter;
+
+    #[test]
+    fn test_query_rate() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let mut meter = QueryRateMeter::default();
+        assert_eq!(meter.rate(start), 0.);
+
+        for _ in 0..50 {
+            meter.record(secs(1));
+        }
+        // Not reported until the window is over.
+        assert_eq!(meter.rate(secs(4)), 0.);
+        assert_eq!(meter.rate(secs(5)), 10.);
+        assert_eq!(meter.rate(secs(6)), 10.);
+
+        // A quiet window brings it back down.
+        assert_eq!(meter.rate(secs(10)), 0.);
+    }
+}
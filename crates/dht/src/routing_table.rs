@@ -499,6 +499,12 @@ impl RoutingTable {
     pub fn len(&self) -> usize {
         self.size
     }
+    // The nodes that responded to us recently, see NodeStatus::Good.
+    pub fn good_nodes(&self) -> usize {
+        self.iter()
+            .filter(|n| matches!(n.status(), NodeStatus::Good))
+            .count()
+    }
     pub fn sorted_by_distance_from(&self, id: Id20) -> Vec<&RoutingTableNode> {
         let mut result = Vec::with_capacity(self.size);
         for node in self.buckets.iter() {
@@ -697,6 +703,20 @@ mod tests {
         let _: RoutingTable = serde_json::from_reader(Cursor::new(v)).unwrap();
     }
 
+    #[test]
+    fn test_good_nodes() {
+        let mut table = generate_table(Some(100));
+        assert_eq!(table.good_nodes(), 0);
+        let ids = table.iter().take(3).map(|n| n.id()).collect::<Vec<_>>();
+        for id in ids.iter() {
+            assert!(table.mark_outgoing_request(id));
+        }
+        // Two of them respond.
+        assert!(table.mark_response(&ids[0]));
+        assert!(table.mark_response(&ids[1]));
+        assert_eq!(table.good_nodes(), 2);
+    }
+
     #[test]
     fn test_generate_random_id() {
         let start = Id20::from_str("3000000000000000000000000000000000000000").unwrap();
@@ -1,18 +1,30 @@
-use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
 use clap::{Parser, ValueEnum};
 use librqbit::{
-    api::ApiAddTorrentResponse,
+    api::{ApiAddTorrentResponse, ConfigReloadFuture},
+    dht::PersistentDhtConfig,
     http_api::{HttpApi, HttpApiOptions},
     http_api_client, librqbit_spawn,
     tracing_subscriber_config_utils::{init_logging, InitLoggingOptions},
-    AddTorrent, AddTorrentOptions, AddTorrentResponse, Api, ListOnlyResponse,
-    PeerConnectionOptions, Session, SessionOptions,
+    AddTorrent, AddTorrentOptions, AddTorrentResponse, Api, DnsConfig, ErrorRetryOptions,
+    ExistingDataPolicy, FirstPieceStrategy, LinkCapacity, ListOnlyResponse, PathConflictPolicy,
+    PeerConnectionOptions, PeerSource, PowerProfile, Session, SessionOptions, SessionProfile,
+    StartupConnectRate, UploadSaturationAction, UploadSaturationOptions, VerificationMode,
+    VerifyFromMemoryOptions,
 };
+use server_config::ServerConfigReloader;
 use size_format::SizeFormatterBinary as SF;
 use tracing::{error, error_span, info, trace_span, warn};
 
+mod server_config;
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum LogLevel {
     Trace,
@@ -22,6 +34,153 @@ enum LogLevel {
     Error,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ExistingData {
+    /// Keep existing files and only download the pieces that don't match.
+    Verify,
+    /// Truncate existing files and download everything.
+    Overwrite,
+    /// Refuse to start if any of the files exist.
+    Error,
+}
+
+impl From<ExistingData> for ExistingDataPolicy {
+    fn from(value: ExistingData) -> Self {
+        match value {
+            ExistingData::Verify => ExistingDataPolicy::Verify,
+            ExistingData::Overwrite => ExistingDataPolicy::Overwrite,
+            ExistingData::Error => ExistingDataPolicy::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Power {
+    /// Don't hold back on timers.
+    Performance,
+    /// Wake up somewhat less often.
+    Balanced,
+    /// Wake up as rarely as reasonable, to save battery. Peers are found and reconnected
+    /// to slower.
+    LowPower,
+}
+
+impl From<Power> for PowerProfile {
+    fn from(value: Power) -> Self {
+        match value {
+            Power::Performance => PowerProfile::Performance,
+            Power::Balanced => PowerProfile::Balanced,
+            Power::LowPower => PowerProfile::LowPower,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Verification {
+    /// Count failed pieces towards poisoned piece detection.
+    Strict,
+    /// Record which peers sent the chunks of failed pieces, see
+    /// /torrents/{index}/verification_failures.
+    LogOnly,
+}
+
+impl From<Verification> for VerificationMode {
+    fn from(value: Verification) -> Self {
+        match value {
+            Verification::Strict => VerificationMode::Strict,
+            Verification::LogOnly => VerificationMode::LogOnly,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FirstPiece {
+    /// The first needed piece, like every later one.
+    InOrder,
+    /// A random needed piece.
+    Random,
+    /// The needed piece the fewest connected peers have.
+    Rarest,
+    /// The needed piece with the fewest bytes left, e.g. a shorter last piece.
+    QuickStart,
+}
+
+impl From<FirstPiece> for FirstPieceStrategy {
+    fn from(value: FirstPiece) -> Self {
+        match value {
+            FirstPiece::InOrder => FirstPieceStrategy::InOrder,
+            FirstPiece::Random => FirstPieceStrategy::Random,
+            FirstPiece::Rarest => FirstPieceStrategy::Rarest,
+            FirstPiece::QuickStart => FirstPieceStrategy::QuickStart,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PathConflict {
+    /// Refuse to add the torrent.
+    Reject,
+    /// Add the torrent to a subfolder named after its info hash.
+    Subfolder,
+    /// Add the torrent anyway, logging a warning.
+    Warn,
+}
+
+impl From<PathConflict> for PathConflictPolicy {
+    fn from(value: PathConflict) -> Self {
+        match value {
+            PathConflict::Reject => PathConflictPolicy::Reject,
+            PathConflict::Subfolder => PathConflictPolicy::Subfolder,
+            PathConflict::Warn => PathConflictPolicy::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Source {
+    Trackers,
+    Dht,
+    Pex,
+    Lpd,
+    /// HTTP seeds.
+    WebSeeds,
+    /// Peers given with --initial-peers.
+    Manual,
+    /// Peers saved with the session, see --persist-peers.
+    Cache,
+}
+
+impl From<Source> for PeerSource {
+    fn from(value: Source) -> Self {
+        match value {
+            Source::Trackers => PeerSource::Tracker,
+            Source::Dht => PeerSource::Dht,
+            Source::Pex => PeerSource::Pex,
+            Source::Lpd => PeerSource::Lpd,
+            Source::WebSeeds => PeerSource::WebSeed,
+            Source::Manual => PeerSource::Manual,
+            Source::Cache => PeerSource::Cache,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SaturationAction {
+    /// Only report it in the session stats.
+    Report,
+    /// Pause torrents that are only seeding.
+    PauseSeeding,
+}
+
+impl From<SaturationAction> for UploadSaturationAction {
+    fn from(value: SaturationAction) -> Self {
+        match value {
+            SaturationAction::Report => UploadSaturationAction::Report,
+            SaturationAction::PauseSeeding => UploadSaturationAction::PauseSeeding,
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(version, author, about)]
 struct Opts {
@@ -62,6 +221,11 @@ struct Opts {
     #[arg(long = "disable-dht-persistence")]
     disable_dht_persistence: bool,
 
+    /// The most DHT queries to send out per second, e.g. to save bandwidth
+    /// on metered links. Finding peers for magnets gets slower. Default 250.
+    #[arg(long = "dht-max-query-rate")]
+    dht_max_query_rate: Option<usize>,
+
     /// The connect timeout, e.g. 1s, 1.5s, 100ms etc.
     #[arg(long = "peer-connect-timeout", value_parser = parse_duration::parse, default_value="2s")]
     peer_connect_timeout: Duration,
@@ -70,6 +234,11 @@ struct Opts {
     #[arg(long = "peer-read-write-timeout" , value_parser = parse_duration::parse, default_value="10s")]
     peer_read_write_timeout: Duration,
 
+    /// Disconnect peers that keep us choked for this long while we want data from them, to
+    /// make room for others, e.g. 2m.
+    #[arg(long = "peer-choke-timeout", value_parser = parse_duration::parse)]
+    peer_choke_timeout: Option<Duration>,
+
     /// How many threads to spawn for the executor.
     #[arg(short = 't', long)]
     worker_threads: Option<usize>,
@@ -90,6 +259,269 @@ struct Opts {
     #[arg(long = "disable-upnp")]
     disable_upnp: bool,
 
+    /// How long the router should keep the UPnP port mapping, e.g. 10m.
+    /// Defaults to 60s.
+    #[arg(long = "upnp-lease-duration", value_parser = parse_duration::parse)]
+    upnp_lease_duration: Option<Duration>,
+
+    /// How often to renew the UPnP port mapping, e.g. 20s. Defaults to half
+    /// the lease duration.
+    #[arg(long = "upnp-refresh-interval", value_parser = parse_duration::parse)]
+    upnp_refresh_interval: Option<Duration>,
+
+    /// The IP to announce to trackers, e.g. when they see the wrong address
+    /// because of a proxy or VPN. Not all trackers respect it.
+    #[arg(long = "announce-ip")]
+    announce_ip: Option<IpAddr>,
+
+    /// Resolve tracker, web seed and torrent URL hostnames with this DNS
+    /// server, e.g. 10.8.0.1:53, instead of the system resolver. Can be given
+    /// several times, the servers are tried in order.
+    #[arg(long = "dns-server", conflicts_with = "dns_over_https")]
+    dns_server: Vec<SocketAddr>,
+
+    /// Resolve tracker, web seed and torrent URL hostnames with DNS over HTTPS,
+    /// e.g. https://1.1.1.1/dns-query.
+    #[arg(long = "dns-over-https")]
+    dns_over_https: Option<reqwest::Url>,
+
+    /// Limit the total number of chunk requests in flight across all torrents
+    /// and peers.
+    #[arg(long = "max-outstanding-requests")]
+    max_outstanding_requests_global: Option<usize>,
+
+    /// Limit how many torrents are being added at a time. The rest wait in
+    /// line.
+    #[arg(long = "max-concurrent-adds")]
+    max_concurrent_adds: Option<usize>,
+
+    /// Refuse to add more torrents once the session holds this many.
+    #[arg(long = "max-torrents")]
+    max_torrents: Option<usize>,
+
+    /// Download at most this many torrents at once. The rest start paused and wait in the
+    /// download queue.
+    #[arg(long = "max-active-downloads")]
+    max_active_downloads: Option<usize>,
+
+    /// Limit how many peers are contacted at a time for magnet link metadata,
+    /// across all magnets being added.
+    #[arg(long = "max-metadata-peers")]
+    max_metadata_peers: Option<usize>,
+
+    /// Stop a torrent with an error if one of its pieces fails verification
+    /// from several distinct peers.
+    #[arg(long = "pause-on-poisoned-piece")]
+    pause_on_poisoned_piece: bool,
+
+    /// What to do about pieces that fail verification. For diagnosing corrupt torrents.
+    #[arg(value_enum, long = "verification-mode", default_value = "strict")]
+    verification_mode: Verification,
+
+    /// Request pieces mostly from the peers that recently delivered data
+    /// fastest and most reliably.
+    #[arg(long = "prefer-good-peers")]
+    prefer_good_peers: bool,
+
+    /// Let a slow peer download at most this many pieces at a time, and hand
+    /// what it holds beyond that to faster peers.
+    #[arg(long = "slow-peer-piece-limit")]
+    slow_peer_piece_limit: Option<usize>,
+
+    /// Upload to at most this many interested peers per torrent at a time.
+    /// By default every peer is uploaded to.
+    #[arg(long = "upload-slots")]
+    upload_slots: Option<usize>,
+
+    /// Towards the end of a download, request a piece from at most this many
+    /// peers at once. Unlimited by default, 1 disables requesting pieces again
+    /// from faster peers.
+    #[arg(long = "endgame-max-duplicates")]
+    endgame_max_duplicates: Option<usize>,
+
+    /// Report a torrent as stalled if it has peers but hasn't verified a piece
+    /// for this long. Defaults to 10 minutes.
+    #[arg(long = "stall-timeout", value_parser = parse_duration::parse)]
+    stall_timeout: Option<Duration>,
+
+    /// How often to check that the files of live torrents are still on disk.
+    /// Torrents with deleted files stop with an error. Defaults to 1 minute,
+    /// 0 turns it off.
+    #[arg(long = "missing-files-check-interval", value_parser = parse_duration::parse)]
+    missing_files_check_interval: Option<Duration>,
+
+    /// Once a torrent is complete, announce to trackers this often instead of
+    /// what they ask for, but never more often than their "min interval".
+    #[arg(long = "seeding-announce-interval", value_parser = parse_duration::parse)]
+    seeding_announce_interval: Option<Duration>,
+
+    /// Comma-separated order in which to dial peers by where they came from,
+    /// when more are known than can be connected to, e.g. "dht,trackers".
+    /// Unlisted sources go last. By default peers are dialed as they are found.
+    #[arg(value_enum, long = "peer-source-priority", value_delimiter = ',')]
+    peer_source_priority: Vec<Source>,
+
+    /// Write files with this suffix appended (e.g. ".part") until they are fully
+    /// downloaded and verified, then rename them to their real name.
+    #[arg(long = "incomplete-suffix")]
+    incomplete_suffix: Option<String>,
+
+    /// Write files under this directory until they are fully downloaded and
+    /// verified, then move them to the output folder.
+    #[arg(long = "incomplete-dir")]
+    incomplete_dir: Option<PathBuf>,
+
+    /// The directory file sinks requested over the HTTP API may write to, e.g.
+    /// where transcoders create their FIFOs. Without it, they are refused.
+    #[arg(long = "file-sink-dir")]
+    file_sink_dir: Option<PathBuf>,
+
+    /// The directory mirrors of torrents added over the HTTP API have to be in.
+    /// Without it, they are refused.
+    #[arg(long = "mirror-dir")]
+    mirror_dir: Option<PathBuf>,
+
+    /// How often to run periodic work like stats sampling, state dumps and
+    /// announces. Coarser profiles save battery at the cost of slower ramp up.
+    #[arg(value_enum, long = "power-profile", default_value = "performance")]
+    power_profile: Power,
+
+    /// Which piece to request first from each newly connected peer.
+    #[arg(value_enum, long = "first-piece-strategy", default_value = "in-order")]
+    first_piece_strategy: FirstPiece,
+
+    /// With "--first-piece-strategy rarest", pick the first piece from each
+    /// peer randomly among the pieces at most this many peers more common than
+    /// the rarest one. Not allowed with other strategies.
+    #[arg(long = "rarest-first-randomness")]
+    rarest_first_randomness: Option<u32>,
+
+    /// Keep this many bytes per second of the link free for other traffic,
+    /// by capping download and upload below the link's capacity. The capacity
+    /// is measured unless "--link-download-bps" or "--link-upload-bps" set it.
+    #[arg(long = "reserved-headroom-bps")]
+    reserved_headroom_bps: Option<u64>,
+
+    /// The link's download capacity in bytes per second.
+    #[arg(long = "link-download-bps")]
+    link_download_bps: Option<u64>,
+
+    /// The link's upload capacity in bytes per second.
+    #[arg(long = "link-upload-bps")]
+    link_upload_bps: Option<u64>,
+
+    /// What to do when a torrent being added would write to a file another
+    /// torrent already writes to.
+    #[arg(value_enum, long = "on-path-conflict", default_value = "warn")]
+    on_path_conflict: PathConflict,
+
+    /// Keep each piece in memory until it passes the hash check, and only then
+    /// write it to disk. Uses up to one piece worth of memory per live peer.
+    #[arg(long = "verify-before-write")]
+    verify_before_write: bool,
+
+    /// Verify pieces in memory before writing them, using up to this many
+    /// bytes across all torrents, e.g. for a download folder on a NAS.
+    /// Pieces that don't fit are read back with parallel reads.
+    #[arg(long = "verify-from-memory-bytes")]
+    verify_from_memory_bytes: Option<u64>,
+
+    /// Ignore tracker responses larger than this many bytes.
+    #[arg(long = "max-tracker-response-size")]
+    max_tracker_response_size: Option<usize>,
+
+    /// Follow at most this many HTTP redirects per tracker announce, 0 to follow none.
+    /// Defaults to 5.
+    #[arg(long = "max-tracker-redirects")]
+    max_tracker_redirects: Option<usize>,
+
+    /// Stop announcing to a tracker once every announce to it failed for this long,
+    /// e.g. 7d. It can be announced to again through the HTTP API.
+    #[arg(long = "dead-tracker-timeout", value_parser = parse_duration::parse)]
+    dead_tracker_timeout: Option<Duration>,
+
+    /// Live peer connections across all torrents, shared between them by their
+    /// priority tier, which can be set through the HTTP API.
+    #[arg(long = "peer-connection-budget")]
+    peer_connection_budget: Option<usize>,
+
+    /// Leave at least this much time, e.g. 2s, between announces to the same
+    /// tracker host, so that many torrents on one tracker don't all announce at once.
+    #[arg(long = "tracker-host-min-gap", value_parser = parse_duration::parse)]
+    tracker_host_min_gap: Option<Duration>,
+
+    /// Stop and exit once there's nothing to do (no torrents, or all of them
+    /// complete and paused) for this long, e.g. 10m. Seeding keeps it running.
+    #[arg(long = "idle-shutdown", value_parser = parse_duration::parse)]
+    idle_shutdown: Option<Duration>,
+
+    /// Poll network interfaces this often, e.g. 10s, and on a change redo UPnP
+    /// port forwarding and re-announce all torrents. Disabled by default.
+    #[arg(long = "network-change-poll-interval", value_parser = parse_duration::parse)]
+    network_change_poll_interval: Option<Duration>,
+
+    /// Treat the clock jumping ahead by more than this, e.g. 1m, as waking from
+    /// sleep, and re-announce and reconnect to peers right away. Disabled by default.
+    #[arg(long = "wake-recovery-gap", value_parser = parse_duration::parse)]
+    wake_recovery_gap: Option<Duration>,
+
+    /// Upstream bandwidth of the link in bytes per second. If set, detect the
+    /// upload staying close to it, see --upload-saturation-action.
+    #[arg(long = "upload-capacity")]
+    upload_capacity: Option<u64>,
+
+    /// Fraction of --upload-capacity at which the upload counts as saturated,
+    /// above 0 and at most 1. Defaults to 0.9.
+    #[arg(long = "upload-saturation-threshold")]
+    upload_saturation_threshold: Option<f64>,
+
+    /// How long the upload has to stay saturated before acting, e.g. 30s.
+    /// Defaults to 1 minute.
+    #[arg(long = "upload-saturation-window", value_parser = parse_duration::parse)]
+    upload_saturation_window: Option<Duration>,
+
+    /// What to do once the upload stayed saturated for the window.
+    #[arg(
+        value_enum,
+        long = "upload-saturation-action",
+        default_value = "pause-seeding"
+    )]
+    upload_saturation_action: SaturationAction,
+
+    /// Restart torrents that fail to initialize up to this many times, with a
+    /// backoff, and then leave them in error. Off by default.
+    #[arg(long = "error-retries")]
+    error_retries: Option<u32>,
+
+    /// How long to wait before the first restart of a failed torrent,
+    /// doubling each time, e.g. 1m. Defaults to 30s.
+    #[arg(long = "error-retry-backoff", value_parser = parse_duration::parse)]
+    error_retry_backoff: Option<Duration>,
+
+    /// Dial at most this many peers per second across all torrents after startup,
+    /// doubling every 30s until --startup-connect-ramp is over. Unlimited by default.
+    #[arg(long = "startup-connect-rate")]
+    startup_connect_rate: Option<u32>,
+
+    /// How long --startup-connect-rate applies for, e.g. 5m. Defaults to 3m.
+    #[arg(long = "startup-connect-ramp", value_parser = parse_duration::parse)]
+    startup_connect_ramp: Option<Duration>,
+
+    /// Unix permission bits for downloaded files, in octal, e.g. 640.
+    /// Directories get the same bits plus "x" wherever "r" is set.
+    #[arg(long = "file-mode", value_parser = parse_octal_mode)]
+    file_mode: Option<u32>,
+
+    /// Unix group ID to assign to downloaded files and directories.
+    #[arg(long = "file-group")]
+    file_group: Option<u32>,
+
+    /// A profile to set the session up like another instance, e.g. saved from its GET
+    /// /profile. Its startup settings override the matching options, and its runtime
+    /// settings are applied once the session is created.
+    #[arg(long = "profile")]
+    profile: Option<PathBuf>,
+
     #[command(subcommand)]
     subcommand: SubCommand,
 }
@@ -105,6 +537,26 @@ struct ServerStartOptions {
     disable_persistence: bool,
     #[arg(long = "persistence-filename")]
     persistence_filename: Option<String>,
+
+    /// Save the best connected peers of each torrent with the session, and dial
+    /// them first on the next start.
+    #[arg(long = "persist-peers")]
+    persist_peers: bool,
+
+    /// Don't open or check the files of paused torrents on startup, only once
+    /// they're started. Speeds up starting with many paused torrents.
+    #[arg(long = "lazy-load")]
+    lazy_load: bool,
+
+    /// Serve the web UI at the root of the HTTP API address, not only at /web/.
+    #[arg(long = "webui-at-root")]
+    webui_at_root: bool,
+
+    /// A JSON file with settings applied on start, and again on SIGHUP or POST
+    /// /config/reload when they change, e.g. {"announce_enabled": false,
+    /// "dht_read_only": true, "seeding_announce_interval_secs": 3600}.
+    #[arg(long = "config")]
+    config: Option<PathBuf>,
 }
 
 #[derive(Parser)]
@@ -148,6 +600,10 @@ struct DownloadOpts {
     #[arg(long)]
     overwrite: bool,
 
+    /// What to do with files that already exist. Overrides "--overwrite".
+    #[arg(value_enum, long = "existing-data")]
+    existing_data: Option<ExistingData>,
+
     /// Exit the program once the torrents complete download.
     #[arg(short = 'e', long)]
     exit_on_finish: bool,
@@ -157,6 +613,59 @@ struct DownloadOpts {
 
     #[arg(long = "initial-peers")]
     initial_peers: Option<InitialPeers>,
+
+    /// Fully preallocate files and keep writes mostly sequential. Use this
+    /// if downloading to a FUSE or network filesystem that handles sparse
+    /// files or random writes poorly.
+    #[arg(long = "contiguous-storage")]
+    contiguous_storage: bool,
+
+    /// Only upload to peers in this subnet, e.g. "10.0.0.0/8". May be given
+    /// multiple times. Uploads to everyone if not set.
+    #[arg(long = "upload-subnet")]
+    upload_subnets: Vec<String>,
+
+    /// Keep a full copy of the data in this directory too, e.g. on another
+    /// disk. Each verified piece is written there as well, costing as many
+    /// extra disk writes as the torrent's size. May be given multiple times.
+    #[arg(long = "mirror")]
+    mirror_paths: Vec<PathBuf>,
+
+    /// A libtorrent .fastresume file from another client, e.g. qBittorrent,
+    /// to reuse its progress instead of re-checking the data. Only with a
+    /// single torrent.
+    #[arg(long = "fastresume")]
+    fastresume: Option<PathBuf>,
+
+    /// Pause the torrent once this percent of it is downloaded, e.g. 5 to
+    /// preview it. With "--filename-re", the percent of the matching files.
+    #[arg(long = "pause-at-percent")]
+    pause_at_percent: Option<f64>,
+
+    /// Switch to sequential once this percent of the torrent is downloaded,
+    /// e.g. 90 to get the end in order.
+    #[arg(long = "sequential-after-percent")]
+    sequential_after_percent: Option<f64>,
+
+    /// Download only these files, by comma-separated index, e.g. 0 for the
+    /// first one, and pause once they're complete. See "--list" for the indexes.
+    #[arg(long = "sample-files", value_delimiter = ',')]
+    sample_files: Option<Vec<usize>>,
+
+    /// Copy complete files with the same name and size from torrents already
+    /// in the session, e.g. the same release from another tracker, and check
+    /// them instead of downloading them again.
+    #[arg(long = "cross-seed")]
+    cross_seed: bool,
+}
+
+fn parse_octal_mode(s: &str) -> anyhow::Result<u32> {
+    let mode = u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .with_context(|| format!("{s:?} is not a valid octal file mode"))?;
+    if mode > 0o7777 {
+        anyhow::bail!("file mode {s:?} is out of range");
+    }
+    Ok(mode)
 }
 
 #[derive(Clone)]
@@ -254,7 +763,10 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
     let mut sopts = SessionOptions {
         disable_dht: opts.disable_dht,
         disable_dht_persistence: opts.disable_dht_persistence,
-        dht_config: None,
+        dht_config: opts.dht_max_query_rate.map(|rate| PersistentDhtConfig {
+            max_query_rate: Some(rate),
+            ..Default::default()
+        }),
         // This will be overriden by "server start" below if needed.
         persistence: false,
         persistence_filename: None,
@@ -262,6 +774,7 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
         peer_opts: Some(PeerConnectionOptions {
             connect_timeout: Some(opts.peer_connect_timeout),
             read_write_timeout: Some(opts.peer_read_write_timeout),
+            choke_timeout: opts.peer_choke_timeout,
             ..Default::default()
         }),
         listen_port_range: if !opts.disable_tcp_listen {
@@ -270,6 +783,102 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
             None
         },
         enable_upnp_port_forwarding: !opts.disable_upnp,
+        upnp_lease_duration: opts.upnp_lease_duration,
+        upnp_refresh_interval: opts.upnp_refresh_interval,
+        file_mode: opts.file_mode,
+        file_group: opts.file_group,
+        announce_ip: opts.announce_ip,
+        dns: match (opts.dns_over_https.clone(), opts.dns_server.is_empty()) {
+            (Some(url), _) => DnsConfig::DnsOverHttps(url),
+            (None, false) => DnsConfig::Servers(opts.dns_server.clone()),
+            (None, true) => DnsConfig::System,
+        },
+        idle_shutdown: opts.idle_shutdown,
+        max_outstanding_requests_global: opts.max_outstanding_requests_global,
+        max_concurrent_adds: opts.max_concurrent_adds,
+        max_torrents: opts.max_torrents,
+        max_active_downloads: opts.max_active_downloads,
+        max_metadata_peers: opts.max_metadata_peers,
+        pause_on_poisoned_piece: opts.pause_on_poisoned_piece,
+        verification_mode: opts.verification_mode.into(),
+        prefer_good_peers: opts.prefer_good_peers,
+        slow_peer_piece_limit: opts.slow_peer_piece_limit,
+        upload_slots: opts.upload_slots,
+        endgame_max_duplicates: opts.endgame_max_duplicates,
+        stall_timeout: opts.stall_timeout,
+        missing_files_check_interval: opts.missing_files_check_interval,
+        seeding_announce_interval: opts.seeding_announce_interval,
+        peer_source_priority: opts
+            .peer_source_priority
+            .iter()
+            .copied()
+            .map(PeerSource::from)
+            .collect(),
+        incomplete_suffix: opts.incomplete_suffix.clone(),
+        incomplete_dir: opts.incomplete_dir.clone(),
+        file_sink_dir: opts.file_sink_dir.clone(),
+        mirror_dir: opts.mirror_dir.clone(),
+        power_profile: opts.power_profile.into(),
+        first_piece_strategy: opts.first_piece_strategy.into(),
+        rarest_first_randomness: opts.rarest_first_randomness,
+        reserved_headroom_bps: opts.reserved_headroom_bps,
+        link_capacity: LinkCapacity {
+            download_bps: opts.link_download_bps,
+            upload_bps: opts.link_upload_bps,
+        },
+        on_path_conflict: opts.on_path_conflict.into(),
+        verify_before_write: opts.verify_before_write,
+        verify_from_memory: opts
+            .verify_from_memory_bytes
+            .map(|bytes| VerifyFromMemoryOptions {
+                max_buffered_bytes: Some(bytes),
+                parallel_reads: None,
+            }),
+        max_tracker_response_size: opts.max_tracker_response_size,
+        max_tracker_redirects: opts.max_tracker_redirects,
+        dead_tracker_timeout: opts.dead_tracker_timeout,
+        peer_connection_budget: opts.peer_connection_budget,
+        tracker_host_min_gap: opts.tracker_host_min_gap,
+        network_change_poll_interval: opts.network_change_poll_interval,
+        wake_recovery_gap: opts.wake_recovery_gap,
+        upload_saturation: opts.upload_capacity.map(|capacity_bytes_per_sec| {
+            UploadSaturationOptions {
+                capacity_bytes_per_sec,
+                threshold: opts.upload_saturation_threshold,
+                window: opts.upload_saturation_window,
+                action: opts.upload_saturation_action.into(),
+            }
+        }),
+        error_retry: opts.error_retries.map(|retries| ErrorRetryOptions {
+            retries,
+            backoff: opts.error_retry_backoff,
+        }),
+        startup_connect_rate: opts
+            .startup_connect_rate
+            .map(|initial_per_sec| StartupConnectRate {
+                initial_per_sec,
+                ramp: opts.startup_connect_ramp,
+            }),
+        ..Default::default()
+    };
+
+    let profile = opts
+        .profile
+        .as_deref()
+        .map(SessionProfile::from_file)
+        .transpose()
+        .context("error reading --profile")?;
+    if let Some(profile) = &profile {
+        profile.startup.apply_to(&mut sopts);
+    }
+    let import_profile = |session: &Session| -> anyhow::Result<()> {
+        if let Some(profile) = &profile {
+            let result = session
+                .import_profile(profile)
+                .context("error applying --profile")?;
+            info!(profile = %result.name, "applied profile");
+        }
+        Ok(())
     };
 
     let stats_printer = |session: Arc<Session>| async move {
@@ -329,27 +938,73 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
                 sopts.persistence = !start_opts.disable_persistence;
                 sopts.persistence_filename =
                     start_opts.persistence_filename.clone().map(PathBuf::from);
+                sopts.persist_peers = start_opts.persist_peers;
+                sopts.lazy_load = start_opts.lazy_load;
 
                 let session =
                     Session::new_with_opts(PathBuf::from(&start_opts.output_folder), sopts)
                         .await
                         .context("error initializing rqbit session")?;
+                import_profile(&session)?;
                 librqbit_spawn(
                     "stats_printer",
                     trace_span!("stats_printer"),
                     stats_printer(session.clone()),
                 );
-                let api = Api::new(
-                    session,
+                let mut api = Api::new(
+                    session.clone(),
                     Some(log_config.rust_log_reload_tx),
                     Some(log_config.line_broadcast),
                 );
-                let http_api = HttpApi::new(api, Some(HttpApiOptions { read_only: false }));
+                if let Some(config) = start_opts.config.clone() {
+                    let reloader = Arc::new(ServerConfigReloader::new(config, api.clone()));
+                    let applied = reloader.reload().context("error applying --config")?;
+                    info!(applied = ?applied.applied, "applied config");
+                    api = api.with_config_reload_hook({
+                        let reloader = reloader.clone();
+                        Arc::new(move || -> ConfigReloadFuture {
+                            let reloader = reloader.clone();
+                            Box::pin(async move { reloader.reload() })
+                        })
+                    });
+                    #[cfg(unix)]
+                    librqbit_spawn(
+                        "sighup_config_reload",
+                        error_span!("sighup_config_reload"),
+                        async move {
+                            let mut sighup = tokio::signal::unix::signal(
+                                tokio::signal::unix::SignalKind::hangup(),
+                            )
+                            .context("error setting up SIGHUP handler")?;
+                            while sighup.recv().await.is_some() {
+                                match reloader.reload() {
+                                    Ok(r) => {
+                                        info!(applied = ?r.applied, "reloaded config on SIGHUP")
+                                    }
+                                    Err(e) => error!("error reloading config on SIGHUP: {:#}", e),
+                                }
+                            }
+                            Ok(())
+                        },
+                    );
+                }
+                let http_api = HttpApi::new(
+                    api,
+                    Some(HttpApiOptions {
+                        read_only: false,
+                        serve_webui_at_root: start_opts.webui_at_root,
+                    }),
+                );
                 let http_api_listen_addr = opts.http_api_listen_addr;
-                http_api
-                    .make_http_api_and_run(http_api_listen_addr)
-                    .await
-                    .context("error running HTTP API")
+                tokio::select! {
+                    r = http_api.make_http_api_and_run(http_api_listen_addr) => {
+                        r.context("error running HTTP API")
+                    }
+                    _ = session.cancellation_token().cancelled() => {
+                        info!("session stopped, exiting");
+                        Ok(())
+                    }
+                }
             }
         },
         SubCommand::Download(download_opts) => {
@@ -358,15 +1013,37 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
             }
             let http_api_url = format!("http://{}", opts.http_api_listen_addr);
             let client = http_api_client::HttpApiClient::new(&http_api_url)?;
+            if download_opts.fastresume.is_some() && download_opts.torrent_path.len() > 1 {
+                anyhow::bail!("--fastresume only works with a single torrent");
+            }
+            let fastresume = download_opts
+                .fastresume
+                .as_ref()
+                .map(std::fs::read)
+                .transpose()
+                .context("error reading the fastresume file")?;
             let torrent_opts = AddTorrentOptions {
                 only_files_regex: download_opts.only_files_matching_regex.clone(),
                 overwrite: download_opts.overwrite,
+                existing_data_policy: download_opts.existing_data.map(Into::into),
                 list_only: download_opts.list,
                 force_tracker_interval: opts.force_tracker_interval,
                 output_folder: download_opts.output_folder.clone(),
                 sub_folder: download_opts.sub_folder.clone(),
                 initial_peers: download_opts.initial_peers.clone().map(|p| p.0),
                 disable_trackers: download_opts.disable_trackers,
+                contiguous_storage: download_opts.contiguous_storage,
+                upload_subnets: if download_opts.upload_subnets.is_empty() {
+                    None
+                } else {
+                    Some(download_opts.upload_subnets.clone())
+                },
+                mirror_paths: download_opts.mirror_paths.clone(),
+                fastresume,
+                pause_at_percent: download_opts.pause_at_percent,
+                sequential_after_percent: download_opts.sequential_after_percent,
+                sample_files: download_opts.sample_files.clone(),
+                cross_seed: download_opts.cross_seed,
                 ..Default::default()
             };
             let connect_to_existing = match client.validate_rqbit_server().await {
@@ -380,6 +1057,9 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
                 }
             };
             if connect_to_existing {
+                if torrent_opts.fastresume.is_some() {
+                    warn!("the HTTP API doesn't take fastresume files, the server will check the data instead");
+                }
                 for torrent_url in &download_opts.torrent_path {
                     match client
                         .add_torrent(
@@ -418,6 +1098,7 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
                 )
                 .await
                 .context("error initializing rqbit session")?;
+                import_profile(&session)?;
 
                 librqbit_spawn(
                     "stats_printer",
@@ -429,7 +1110,13 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
                     Some(log_config.rust_log_reload_tx),
                     Some(log_config.line_broadcast),
                 );
-                let http_api = HttpApi::new(api, Some(HttpApiOptions { read_only: true }));
+                let http_api = HttpApi::new(
+                    api,
+                    Some(HttpApiOptions {
+                        read_only: true,
+                        ..Default::default()
+                    }),
+                );
                 let http_api_listen_addr = opts.http_api_listen_addr;
                 librqbit_spawn(
                     "http_api",
@@ -509,10 +1196,10 @@ async fn async_main(opts: Opts) -> anyhow::Result<()> {
                         info!("All downloads completed, exiting");
                         Ok(())
                     } else {
-                        // Sleep forever.
-                        loop {
-                            tokio::time::sleep(Duration::from_secs(60)).await;
-                        }
+                        // Run until the session is stopped, e.g. by idle shutdown.
+                        session.cancellation_token().cancelled().await;
+                        info!("session stopped, exiting");
+                        Ok(())
                     }
                 } else {
                     anyhow::bail!("no torrents were added")
@@ -0,0 +1,134 @@
+// The settings file of "server start --config". It only has settings that can be changed
+// while the server runs, so reloading it never needs a restart.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use librqbit::{
+    api::{ConfigReloadResponse, SeedingAnnounceIntervalRequest},
+    Api, ApiError,
+};
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+/// Unset settings are left as they are.
+#[derive(Deserialize, Default, Clone, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct ServerConfig {
+    announce_enabled: Option<bool>,
+    dht_read_only: Option<bool>,
+    seeding_announce_interval_secs: Option<u64>,
+}
+
+impl ServerConfig {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("error reading {path:?}"))?;
+        serde_json::from_slice(&data).with_context(|| format!("error parsing {path:?}"))
+    }
+}
+
+// The settings that are set in "new" and differ from "old".
+fn changed_settings(old: &ServerConfig, new: &ServerConfig) -> Vec<&'static str> {
+    [
+        (
+            "announce_enabled",
+            new.announce_enabled.is_some() && new.announce_enabled != old.announce_enabled,
+        ),
+        (
+            "dht_read_only",
+            new.dht_read_only.is_some() && new.dht_read_only != old.dht_read_only,
+        ),
+        (
+            "seeding_announce_interval_secs",
+            new.seeding_announce_interval_secs.is_some()
+                && new.seeding_announce_interval_secs != old.seeding_announce_interval_secs,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+pub struct ServerConfigReloader {
+    path: PathBuf,
+    api: Api,
+    // What the last reload applied.
+    applied: Mutex<ServerConfig>,
+}
+
+impl ServerConfigReloader {
+    pub fn new(path: PathBuf, api: Api) -> Self {
+        Self {
+            path,
+            api,
+            applied: Default::default(),
+        }
+    }
+
+    /// Re-read the file and apply what changed since the last time.
+    pub fn reload(&self) -> Result<ConfigReloadResponse, ApiError> {
+        let new = ServerConfig::read(&self.path)?;
+        let mut applied = self.applied.lock();
+        let changed = changed_settings(&applied, &new);
+        if let Some(enabled) = new
+            .announce_enabled
+            .filter(|_| changed.contains(&"announce_enabled"))
+        {
+            self.api.api_set_announce_enabled(enabled)?;
+        }
+        if let Some(read_only) = new
+            .dht_read_only
+            .filter(|_| changed.contains(&"dht_read_only"))
+        {
+            self.api.api_dht_set_read_only(read_only)?;
+        }
+        if changed.contains(&"seeding_announce_interval_secs") {
+            self.api
+                .api_set_seeding_announce_interval(SeedingAnnounceIntervalRequest {
+                    interval_secs: new.seeding_announce_interval_secs,
+                })?;
+        }
+        *applied = new;
+        Ok(ConfigReloadResponse {
+            applied: changed.into_iter().map(|name| name.to_owned()).collect(),
+            restart_required: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_settings, ServerConfig};
+
+    #[test]
+    fn test_changed_settings() {
+        let old = ServerConfig {
+            announce_enabled: Some(true),
+            dht_read_only: None,
+            seeding_announce_interval_secs: Some(60),
+        };
+        assert!(changed_settings(&old, &old).is_empty());
+
+        let new = ServerConfig {
+            announce_enabled: Some(false),
+            dht_read_only: Some(false),
+            // Unset now, left as it was.
+            seeding_announce_interval_secs: None,
+        };
+        assert_eq!(
+            changed_settings(&old, &new),
+            vec!["announce_enabled", "dht_read_only"]
+        );
+        assert_eq!(
+            changed_settings(&ServerConfig::default(), &old),
+            vec!["announce_enabled", "seeding_announce_interval_secs"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_settings_are_rejected() {
+        assert!(serde_json::from_str::<ServerConfig>(r#"{"dht_read_only": true}"#).is_ok());
+        assert!(serde_json::from_str::<ServerConfig>(r#"{"dht_readonly": true}"#).is_err());
+    }
+}
@@ -1,9 +1,17 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
 use buffers::ByteString;
-use dht::{DhtStats, Id20};
-use futures::Stream;
+use bytes::Bytes;
+use dht::{DhtLookupStats, DhtStats, DhtWarmupProgress, Id20};
+use futures::{future::BoxFuture, Stream, StreamExt};
 use http::StatusCode;
 use librqbit_core::torrent_metainfo::TorrentMetaV1Info;
 use serde::{Deserialize, Serialize};
@@ -13,20 +21,44 @@ use tracing::warn;
 
 use crate::{
     api_error::{ApiError, ApiErrorExt},
+    connect_ramp::{ConnectRateStats, StartupConnectRate},
+    diagnose::TorrentDiagnosis,
+    peer_budget::PriorityTier,
+    peer_connection::PeerConnectionOptions,
+    profile::{ProfileImportResult, SessionProfile},
+    queue::QueueSortKey,
     session::{
-        AddTorrent, AddTorrentOptions, AddTorrentResponse, ListOnlyResponse, Session, TorrentId,
+        check_state_file_target, AddTorrent, AddTorrentOptions, AddTorrentResponse, DeleteOptions,
+        ListOnlyResponse, ListenStatus, ResolvingMagnet, Session, SessionStats, TorrentId,
+        UpnpStatus,
     },
+    stats_delta::{StatsDelta, StatsDeltaTracker},
+    storage::StorageRootStats,
+    torrent_collections::CollectionStats,
     torrent_state::{
+        deadlines::PieceDeadline,
+        events::PoisonedPiece,
         peer::stats::snapshot::{PeerStatsFilter, PeerStatsSnapshot},
+        peer_events::PeerEvent,
+        piece_latency::PieceLatencyStats,
+        swarm_health::SwarmHealth,
+        tracker_stats::{AnnounceRecord, TrackerStats},
+        utils::check_path_within,
+        verification::VerificationFailure,
         ManagedTorrentHandle,
     },
     tracing_subscriber_config_utils::LineBroadcast,
 };
 
-pub use crate::torrent_state::stats::{LiveStats, TorrentStats};
+pub use crate::torrent_state::stats::{LifetimeTransfer, LiveStats, TorrentStats};
 
 pub type Result<T> = std::result::Result<T, ApiError>;
 
+// See DhtWarmupRequest.
+const DEFAULT_DHT_WARMUP_NODES: usize = 32;
+const DEFAULT_DHT_WARMUP_TIMEOUT_SECS: u64 = 30;
+const MAX_DHT_WARMUP_TIMEOUT_SECS: u64 = 300;
+
 /// Library API for use in different web frameworks.
 /// Contains all methods you might want to expose with (de)serializable inputs/outputs.
 #[derive(Clone)]
@@ -34,8 +66,14 @@ pub struct Api {
     session: Arc<Session>,
     rust_log_reload_tx: Option<UnboundedSender<String>>,
     line_broadcast: Option<LineBroadcast>,
+    config_reload_hook: Option<ConfigReloadHook>,
 }
 
+/// Re-reads the embedder's configuration file and applies it. Librqbit itself has no
+/// config file, so [Api::api_reload_config] only works if the embedder set this.
+pub type ConfigReloadHook = Arc<dyn Fn() -> ConfigReloadFuture + Send + Sync>;
+pub type ConfigReloadFuture = BoxFuture<'static, Result<ConfigReloadResponse>>;
+
 impl Api {
     pub fn new(
         session: Arc<Session>,
@@ -46,9 +84,27 @@ impl Api {
             session,
             rust_log_reload_tx,
             line_broadcast,
+            config_reload_hook: None,
         }
     }
 
+    pub fn with_config_reload_hook(mut self, hook: ConfigReloadHook) -> Self {
+        self.config_reload_hook = Some(hook);
+        self
+    }
+
+    /// Reload the configuration from disk, see [ConfigReloadHook].
+    pub async fn api_reload_config(&self) -> Result<ConfigReloadResponse> {
+        let hook = self
+            .config_reload_hook
+            .as_ref()
+            .ok_or(ApiError::new_from_text(
+                StatusCode::NOT_IMPLEMENTED,
+                "reloading config is not supported",
+            ))?;
+        hook().await
+    }
+
     pub fn session(&self) -> &Arc<Session> {
         &self.session
     }
@@ -62,20 +118,137 @@ impl Api {
     pub fn api_torrent_list(&self) -> TorrentListResponse {
         let items = self.session.with_torrents(|torrents| {
             torrents
-                .map(|(id, mgr)| TorrentListResponseItem {
-                    id,
-                    info_hash: mgr.info().info_hash.as_string(),
-                })
+                .map(|(id, mgr)| TorrentListResponseItem::new(id, mgr))
                 .collect()
         });
         TorrentListResponse { torrents: items }
     }
 
+    /// Torrents stored under the path, see [Session::torrents_by_path].
+    pub fn api_torrents_by_path(&self, prefix: &Path) -> TorrentListResponse {
+        let items = self
+            .session
+            .torrents_by_path(prefix)
+            .into_iter()
+            .filter_map(|id| Some(TorrentListResponseItem::new(id, &self.session.get(id)?)))
+            .collect();
+        TorrentListResponse { torrents: items }
+    }
+
+    /// Pause all live torrents stored under the path, e.g. before unmounting a disk. Torrents
+    /// that are already paused are left alone, others that can't be paused, e.g. locked or
+    /// initializing ones, get an error.
+    pub fn api_torrents_pause_by_path(&self, prefix: &Path) -> TorrentsBulkUpdateResponse {
+        let results = self
+            .session
+            .torrents_by_path(prefix)
+            .into_iter()
+            .map(|id| {
+                let error = match self.mgr_handle(id) {
+                    Ok(handle) if handle.with_state(|s| s.is_paused()) => None,
+                    Ok(_) => self
+                        .api_torrent_action_pause(id)
+                        .err()
+                        .map(|e| format!("{e:#}")),
+                    Err(e) => Some(format!("{e:#}")),
+                };
+                TorrentBulkUpdateResult { id, error }
+            })
+            .collect();
+        TorrentsBulkUpdateResponse { results }
+    }
+
+    /// A report of all torrents, one row per torrent. Rows are produced as the stream is
+    /// polled, so large sessions aren't formatted in memory all at once.
+    pub fn api_export_stats(
+        &self,
+        format: StatsExportFormat,
+    ) -> impl Stream<Item = anyhow::Result<Bytes>> + Send + 'static {
+        let torrents: Vec<(TorrentId, ManagedTorrentHandle)> = self
+            .session
+            .with_torrents(|torrents| torrents.map(|(id, t)| (id, t.clone())).collect());
+        let (head, tail) = match format {
+            StatsExportFormat::Json => ("[", "]"),
+            StatsExportFormat::Csv => (TorrentStatsExportRow::CSV_HEADER, ""),
+        };
+        let rows = futures::stream::iter(torrents.into_iter().enumerate()).map(
+            move |(idx, (id, handle))| -> anyhow::Result<Bytes> {
+                let row = TorrentStatsExportRow::new(id, &handle);
+                let line = match format {
+                    StatsExportFormat::Json => {
+                        let json = serde_json::to_string(&row)?;
+                        if idx == 0 {
+                            json
+                        } else {
+                            format!(",{json}")
+                        }
+                    }
+                    StatsExportFormat::Csv => row.to_csv_line(),
+                };
+                Ok(Bytes::from(line))
+            },
+        );
+        futures::stream::once(async move { Ok(Bytes::from_static(head.as_bytes())) })
+            .chain(rows)
+            .chain(futures::stream::once(async move {
+                Ok(Bytes::from_static(tail.as_bytes()))
+            }))
+    }
+
+    /// What changed in the stats of all torrents since the previous call with "tracker", see
+    /// [StatsDelta]. The first call returns the full stats.
+    pub fn api_stats_delta(&self, tracker: &mut StatsDeltaTracker) -> Result<StatsDelta> {
+        Ok(tracker.delta(all_torrent_stats(&self.session))?)
+    }
+
+    /// [Self::api_stats_delta] every "interval". It starts with the full stats, and intervals
+    /// where nothing changed are skipped.
+    pub fn api_stats_delta_stream(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = anyhow::Result<StatsDelta>> + Send + 'static {
+        futures::stream::unfold(
+            (self.session.clone(), StatsDeltaTracker::default(), true),
+            move |(session, mut tracker, first)| async move {
+                if !first {
+                    tokio::time::sleep(interval).await;
+                }
+                loop {
+                    let delta = match tracker.delta(all_torrent_stats(&session)) {
+                        Ok(delta) => delta,
+                        Err(e) => return Some((Err(e), (session, tracker, false))),
+                    };
+                    if !delta.is_empty() {
+                        return Some((Ok(delta), (session, tracker, false)));
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            },
+        )
+    }
+
     pub fn api_torrent_details(&self, idx: TorrentId) -> Result<TorrentDetailsResponse> {
         let handle = self.mgr_handle(idx)?;
         let info_hash = handle.info().info_hash;
         let only_files = handle.only_files();
-        make_torrent_details(&info_hash, &handle.info().info, only_files.as_deref())
+        let mut details =
+            make_torrent_details(&info_hash, &handle.info().info, only_files.as_deref())?;
+        details.user_metadata = handle.user_metadata();
+        Ok(details)
+    }
+
+    /// Replace the torrent's user metadata, see [crate::ManagedTorrent::set_user_metadata].
+    pub fn api_torrent_set_metadata(
+        &self,
+        idx: TorrentId,
+        metadata: serde_json::Value,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.check_unlocked(idx)?;
+        handle
+            .set_user_metadata(metadata)
+            .with_error_status_code(StatusCode::PAYLOAD_TOO_LARGE)?;
+        Ok(Default::default())
     }
 
     pub fn api_peer_stats(
@@ -90,10 +263,209 @@ impl Api {
             .per_peer_stats_snapshot(filter))
     }
 
+    /// The bencoded info dictionary of the torrent, exactly as it hashes to the info hash.
+    /// Magnets only get an id once their metadata is resolved, so until then this fails with
+    /// "torrent not found".
+    pub fn api_torrent_raw_info(&self, idx: TorrentId) -> Result<Vec<u8>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.raw_info()?.0)
+    }
+
+    /// A magnet link with the torrent's current trackers, see
+    /// [crate::ManagedTorrent::magnet_link].
+    pub fn api_torrent_magnet(&self, idx: TorrentId, include_peers: bool) -> Result<String> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.magnet_link(include_peers))
+    }
+
+    /// A libtorrent ".fastresume" file with the torrent's progress, for moving it to another
+    /// client without re-checking. See [crate::ManagedTorrent::export_fastresume].
+    pub fn api_torrent_fastresume(&self, idx: TorrentId) -> Result<Vec<u8>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.export_fastresume()?)
+    }
+
+    /// A ZIP archive of the torrent's files, see [crate::http_api] for the options.
+    pub fn api_torrent_zip(
+        &self,
+        idx: TorrentId,
+        files: Option<Vec<usize>>,
+        wait: bool,
+    ) -> Result<impl Stream<Item = anyhow::Result<Bytes>> + Send + 'static> {
+        let handle = self.mgr_handle(idx)?;
+        let stream = handle
+            .zip_stream(files, wait)
+            .context("error creating zip")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(stream)
+    }
+
+    /// Stream file "file_idx" of the torrent to the unix socket, FIFO or file at "path" while
+    /// it downloads, optionally turning on sequential mode for it. Returns right away, the
+    /// streaming goes on in the background. See [crate::ManagedTorrent::stream_file_to].
+    /// "path" has to be in [crate::SessionOptions::file_sink_dir], relative paths are taken
+    /// from it.
+    pub fn api_torrent_file_sink(
+        &self,
+        idx: TorrentId,
+        file_idx: usize,
+        path: PathBuf,
+        sequential: bool,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        let files = handle.info().info.iter_file_lengths()?.count();
+        if file_idx >= files {
+            return Err(anyhow::anyhow!("torrent has no file {}", file_idx))
+                .with_error_status_code(StatusCode::BAD_REQUEST);
+        }
+        let dir = self
+            .session
+            .file_sink_dir()
+            .context("file sinks are disabled, set file_sink_dir to allow them")
+            .with_error_status_code(StatusCode::FORBIDDEN)?;
+        let path = dir.join(path);
+        check_path_within(dir, &path).with_error_status_code(StatusCode::BAD_REQUEST)?;
+        if sequential {
+            handle
+                .set_sequential(true)
+                .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        }
+        handle.spawn_file_sink(file_idx, path);
+        Ok(Default::default())
+    }
+
+    /// The next "n" pieces the piece picker would request, with the peers that have them.
+    #[cfg(feature = "debug-api")]
+    pub fn api_torrent_picker_plan(
+        &self,
+        idx: TorrentId,
+        n: usize,
+    ) -> Result<crate::torrent_state::picker_plan::PickerPlan> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.picker_plan(n)?)
+    }
+
+    /// A snapshot of the torrent's internal state to attach to bug reports, with tracker
+    /// passkeys redacted. Paused and failed torrents get the parts that don't need them to
+    /// be live, see [crate::ManagedTorrent::debug_dump].
+    #[cfg(feature = "debug-api")]
+    pub fn api_torrent_debug_dump(&self, idx: TorrentId) -> Result<serde_json::Value> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.debug_dump()?)
+    }
+
+    /// The bencode tree of a torrent file, or any other bencode, as JSON, without adding
+    /// anything. For finding out why a torrent doesn't parse.
+    #[cfg(feature = "debug-api")]
+    pub fn api_debug_bencode(&self, torrent_bytes: &[u8]) -> Result<serde_json::Value> {
+        if torrent_bytes.len() > crate::http_download::MAX_TORRENT_FILE_SIZE {
+            return Err(ApiError::new_from_text(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "input is larger than a torrent file can be",
+            ));
+        }
+        crate::debug_bencode::debug_bencode(torrent_bytes)
+            .with_error_status_code(StatusCode::BAD_REQUEST)
+    }
+
+    pub fn api_torrent_set_deadlines(
+        &self,
+        idx: TorrentId,
+        deadlines: Vec<PieceDeadline>,
+    ) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.check_unlocked(idx)?;
+        handle
+            .live()
+            .context("not live")?
+            .set_piece_deadlines(&deadlines)
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    pub fn api_torrent_poisoned_pieces(&self, idx: TorrentId) -> Result<Vec<PoisonedPiece>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.poisoned_pieces())
+    }
+
+    /// The last few peer disconnects and why they happened, including peers that never got
+    /// live.
+    pub fn api_recent_peer_events(&self, idx: TorrentId) -> Result<Vec<PeerEvent>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.recent_peer_events())
+    }
+
+    /// The distribution of how long the last few thousand pieces took from their first
+    /// request to verification, e.g. to tune request depth.
+    pub fn api_piece_latency(&self, idx: TorrentId) -> Result<PieceLatencyStats> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.piece_latency())
+    }
+
+    /// The last few pieces that failed verification, with the peers that sent them. Only
+    /// recorded with [crate::VerificationMode::LogOnly].
+    pub fn api_verification_failures(&self, idx: TorrentId) -> Result<Vec<VerificationFailure>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.verification_failures())
+    }
+
+    /// Announce state of each tracker, including the effective announce interval.
+    pub fn api_torrent_tracker_stats(&self, idx: TorrentId) -> Result<Vec<TrackerStats>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.tracker_stats())
+    }
+
+    /// The last announces to the torrent's trackers, with the byte counts reported and the
+    /// peers returned. Kept while the torrent is live.
+    pub fn api_torrent_announce_history(&self, idx: TorrentId) -> Result<Vec<AnnounceRecord>> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.announce_history())
+    }
+
+    /// Announce again to a tracker given up on after
+    /// [crate::SessionOptions::dead_tracker_timeout].
+    pub fn api_torrent_revive_tracker(
+        &self,
+        idx: TorrentId,
+        url: &str,
+    ) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?
+            .revive_tracker(url)
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// An estimate of how many full copies of the torrent the swarm has, from the connected
+    /// peers' pieces and the trackers' seeder counts.
+    pub fn api_torrent_swarm_health(&self, idx: TorrentId) -> Result<SwarmHealth> {
+        let handle = self.mgr_handle(idx)?;
+        Ok(handle.live().context("not live")?.swarm_health())
+    }
+
+    /// Why the torrent is or isn't downloading: no peers, peers that all choke us, waiting for
+    /// a download slot, paused, a disk error, or done.
+    pub fn api_torrent_diagnose(&self, idx: TorrentId) -> Result<TorrentDiagnosis> {
+        self.session
+            .diagnose_torrent(idx)
+            .ok_or(ApiError::torrent_not_found(idx))
+    }
+
+    /// The same for a magnet link that is being added, while its metadata isn't known yet.
+    pub fn api_magnet_diagnose(&self, info_hash: &str) -> Result<TorrentDiagnosis> {
+        let info_hash = Id20::from_str(info_hash)
+            .context("invalid info hash")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        self.session
+            .diagnose_magnet(info_hash)
+            .context("magnet link isn't being resolved")
+            .with_error_status_code(StatusCode::NOT_FOUND)
+    }
+
     pub fn api_torrent_action_pause(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
         let handle = self.mgr_handle(idx)?;
         handle
-            .pause()
+            .check_unlocked()
+            .and_then(|_| handle.pause())
             .context("error pausing torrent")
             .with_error_status_code(StatusCode::BAD_REQUEST)?;
         Ok(Default::default())
@@ -108,7 +480,263 @@ impl Api {
         Ok(Default::default())
     }
 
+    /// See [crate::ManagedTorrent::load].
+    pub fn api_torrent_action_load(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?
+            .load()
+            .context("error loading torrent")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// See [Session::force_recheck].
+    pub fn api_torrent_action_recheck(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        self.session
+            .force_recheck(&handle)
+            .context("error rechecking torrent")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Stop an initial check or recheck that's under way, see
+    /// [crate::ManagedTorrent::cancel_verify].
+    pub fn api_torrent_cancel_verify(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        let handle = self.mgr_handle(idx)?;
+        handle
+            .check_unlocked()
+            .and_then(|_| handle.cancel_verify())
+            .context("error cancelling the check")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Pause every other live torrent until [Api::api_clear_solo], see [Session::solo].
+    pub fn api_torrent_solo(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?;
+        self.session
+            .solo(idx)
+            .context("error entering solo mode")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Resume the torrents paused by [Api::api_torrent_solo].
+    pub fn api_clear_solo(&self) -> Result<EmptyJsonResponse> {
+        self.session
+            .clear_solo()
+            .context("error leaving solo mode")?;
+        Ok(Default::default())
+    }
+
+    /// Apply the same changes to several torrents. Each torrent is updated independently, so
+    /// e.g. an unknown id doesn't stop the others from being updated.
+    pub fn api_torrents_bulk_update(
+        &self,
+        ids: Vec<TorrentId>,
+        changes: TorrentOptionsPatch,
+    ) -> TorrentsBulkUpdateResponse {
+        let results = ids
+            .into_iter()
+            .map(|id| {
+                let error = match self.mgr_handle(id) {
+                    Ok(handle) => changes.apply(&handle).err().map(|e| format!("{e:#}")),
+                    Err(e) => Some(format!("{e:#}")),
+                };
+                TorrentBulkUpdateResult { id, error }
+            })
+            .collect();
+        TorrentsBulkUpdateResponse { results }
+    }
+
+    /// All collections by name, with the ids of their torrents, see
+    /// [Session::create_collection].
+    pub fn api_collections(&self) -> BTreeMap<String, Vec<TorrentId>> {
+        self.session.collections()
+    }
+
+    pub fn api_collection_create(&self, name: &str) -> Result<EmptyJsonResponse> {
+        self.session
+            .create_collection(name)
+            .context("error creating collection")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Remove the collection, leaving its torrents in the session.
+    pub fn api_collection_delete(&self, name: &str) -> Result<EmptyJsonResponse> {
+        self.session
+            .delete_collection(name)
+            .context("error deleting collection")
+            .with_error_status_code(StatusCode::NOT_FOUND)?;
+        Ok(Default::default())
+    }
+
+    pub fn api_collection_add(&self, name: &str, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?;
+        self.session
+            .add_to_collection(name, idx)
+            .context("error adding to collection")
+            .with_error_status_code(StatusCode::NOT_FOUND)?;
+        Ok(Default::default())
+    }
+
+    pub fn api_collection_remove(&self, name: &str, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.session
+            .remove_from_collection(name, idx)
+            .context("error removing from collection")
+            .with_error_status_code(StatusCode::NOT_FOUND)?;
+        Ok(Default::default())
+    }
+
+    fn collection_members(&self, name: &str) -> Result<Vec<TorrentId>> {
+        self.session
+            .collection(name)
+            .with_error_status_code(StatusCode::NOT_FOUND)
+    }
+
+    /// The stats of the collection's torrents added up.
+    pub fn api_collection_stats(&self, name: &str) -> Result<CollectionStats> {
+        let mut stats = CollectionStats::default();
+        for id in self.collection_members(name)? {
+            if let Some(handle) = self.session.get(id) {
+                stats.add(&handle.stats());
+            }
+        }
+        Ok(stats)
+    }
+
+    // Runs "action" on each torrent of the collection, independently like
+    // api_torrents_bulk_update.
+    fn collection_bulk_action(
+        &self,
+        name: &str,
+        action: impl Fn(TorrentId) -> Result<EmptyJsonResponse>,
+    ) -> Result<TorrentsBulkUpdateResponse> {
+        let results = self
+            .collection_members(name)?
+            .into_iter()
+            .map(|id| TorrentBulkUpdateResult {
+                id,
+                error: action(id).err().map(|e| format!("{e:#}")),
+            })
+            .collect();
+        Ok(TorrentsBulkUpdateResponse { results })
+    }
+
+    /// Pause the collection's live torrents. Ones that are paused already are left alone.
+    pub fn api_collection_pause(&self, name: &str) -> Result<TorrentsBulkUpdateResponse> {
+        self.collection_bulk_action(name, |id| {
+            let handle = self.mgr_handle(id)?;
+            if handle.with_state(|s| s.is_paused()) {
+                return Ok(Default::default());
+            }
+            self.api_torrent_action_pause(id)
+        })
+    }
+
+    /// Start the collection's paused torrents. Ones that are live already are left alone.
+    pub fn api_collection_start(&self, name: &str) -> Result<TorrentsBulkUpdateResponse> {
+        self.collection_bulk_action(name, |id| {
+            let handle = self.mgr_handle(id)?;
+            if !handle.with_state(|s| s.is_paused()) {
+                return Ok(Default::default());
+            }
+            self.api_torrent_action_start(id)
+        })
+    }
+
+    /// Remove the collection's torrents from the session, and their files with
+    /// "delete_files". The collection stays, empty, unless some torrents couldn't be removed,
+    /// e.g. locked ones.
+    pub fn api_collection_delete_torrents(
+        &self,
+        name: &str,
+        delete_files: bool,
+    ) -> Result<TorrentsBulkUpdateResponse> {
+        self.collection_bulk_action(name, |id| {
+            if delete_files {
+                self.api_torrent_action_delete(id)
+            } else {
+                self.api_torrent_action_forget(id)
+            }
+        })
+    }
+
+    /// Lock or unlock the torrent, see [crate::ManagedTorrent::set_locked].
+    pub fn api_torrent_set_locked(
+        &self,
+        idx: TorrentId,
+        locked: bool,
+    ) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?.set_locked(locked);
+        Ok(Default::default())
+    }
+
+    /// Give the torrent priority for a while, see [Session::boost].
+    pub fn api_torrent_boost(
+        &self,
+        idx: TorrentId,
+        duration: Duration,
+    ) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?;
+        self.session
+            .boost(idx, duration)
+            .context("error boosting torrent")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Weight the torrent's share of the session's peer connection budget, see
+    /// [crate::SessionOptions::peer_connection_budget]. The share shows in the torrent's
+    /// live stats as "peer_limit".
+    pub fn api_torrent_set_priority_tier(
+        &self,
+        idx: TorrentId,
+        tier: PriorityTier,
+    ) -> Result<EmptyJsonResponse> {
+        self.check_unlocked(idx)?;
+        self.session.set_priority_tier(idx, tier)?;
+        Ok(Default::default())
+    }
+
+    /// Upload to at most this many peers at a time, or with None, as many as the session
+    /// allows. See [crate::ManagedTorrent::set_upload_slots].
+    pub fn api_torrent_set_upload_slots(
+        &self,
+        idx: TorrentId,
+        slots: Option<usize>,
+    ) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?
+            .set_upload_slots(slots)
+            .context("error setting upload slots")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// Peer timeouts for this torrent's new connections, over the session's ones. See
+    /// [crate::ManagedTorrent::set_peer_opts].
+    pub fn api_torrent_set_peer_opts(
+        &self,
+        idx: TorrentId,
+        opts: PeerConnectionOptions,
+    ) -> Result<EmptyJsonResponse> {
+        self.mgr_handle(idx)?
+            .set_peer_opts(opts)
+            .context("error setting peer timeouts")
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    fn check_unlocked(&self, idx: TorrentId) -> Result<()> {
+        self.mgr_handle(idx)?
+            .check_unlocked()
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(())
+    }
+
     pub fn api_torrent_action_forget(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.check_unlocked(idx)?;
         self.session
             .delete(idx, false)
             .context("error forgetting torrent")?;
@@ -116,8 +744,23 @@ impl Api {
     }
 
     pub fn api_torrent_action_delete(&self, idx: TorrentId) -> Result<EmptyJsonResponse> {
+        self.api_torrent_action_delete_with_opts(
+            idx,
+            DeleteOptions {
+                delete_files: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn api_torrent_action_delete_with_opts(
+        &self,
+        idx: TorrentId,
+        opts: DeleteOptions,
+    ) -> Result<EmptyJsonResponse> {
+        self.check_unlocked(idx)?;
         self.session
-            .delete(idx, true)
+            .delete_with_opts(idx, opts)
             .context("error deleting torrent with files")?;
         Ok(Default::default())
     }
@@ -147,18 +790,44 @@ impl Api {
             .context("line_rx wasn't set")?)
     }
 
+    // Mirror paths added over HTTP have to be in SessionOptions::mirror_dir, relative ones
+    // are taken from it.
+    pub(crate) fn resolve_api_mirror_paths(&self, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        if paths.is_empty() {
+            return Ok(paths);
+        }
+        let dir = self
+            .session
+            .mirror_dir()
+            .context("mirrors are disabled, set mirror_dir to allow them")
+            .with_error_status_code(StatusCode::FORBIDDEN)?;
+        paths
+            .into_iter()
+            .map(|path| {
+                let path = dir.join(path);
+                check_path_within(dir, &path)?;
+                Ok(path)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_error_status_code(StatusCode::BAD_REQUEST)
+    }
+
     pub async fn api_add_torrent(
         &self,
         add: AddTorrent<'_>,
         opts: Option<AddTorrentOptions>,
     ) -> Result<ApiAddTorrentResponse> {
-        let response = match self
-            .session
-            .add_torrent(add, opts)
-            .await
-            .context("error adding torrent")
-            .with_error_status_code(StatusCode::BAD_REQUEST)?
-        {
+        let added = self.session.add_torrent(add, opts).await.map_err(|e| {
+            match e.downcast::<ApiError>() {
+                // E.g. SessionOptions::max_torrents, which has its own status.
+                Ok(e) => e,
+                Err(e) => ApiError::new_from_anyhow(
+                    StatusCode::BAD_REQUEST,
+                    e.context("error adding torrent"),
+                ),
+            }
+        })?;
+        let response = match added {
             AddTorrentResponse::AlreadyManaged(id, managed) => {
                 return Err(anyhow::anyhow!(
                     "{:?} is already managed, id={}, downloaded to {:?}",
@@ -182,12 +851,13 @@ impl Api {
                     .context("error making torrent details")?,
             },
             AddTorrentResponse::Added(id, handle) => {
-                let details = make_torrent_details(
+                let mut details = make_torrent_details(
                     &handle.info_hash(),
                     &handle.info().info,
                     handle.only_files().as_deref(),
                 )
                 .context("error making torrent details")?;
+                details.user_metadata = handle.user_metadata();
                 ApiAddTorrentResponse {
                     id: Some(id),
                     details,
@@ -199,6 +869,108 @@ impl Api {
         Ok(response)
     }
 
+    pub fn api_session_stats(&self) -> SessionStats {
+        self.session.stats()
+    }
+
+    pub fn api_listen_status(&self) -> ListenStatus {
+        self.session.listen_status()
+    }
+
+    /// See [Session::upnp_status].
+    pub fn api_upnp_status(&self) -> UpnpStatus {
+        self.session.upnp_status()
+    }
+
+    /// See [Session::upnp_remap].
+    pub fn api_upnp_remap(&self) -> Result<EmptyJsonResponse> {
+        self.session
+            .upnp_remap()
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// See [Session::storage_stats].
+    pub async fn api_storage_stats(&self) -> Result<Vec<StorageRootStats>> {
+        Ok(self.session.storage_stats().await?)
+    }
+
+    /// See [Session::queue].
+    pub fn api_queue(&self) -> QueueResponse {
+        QueueResponse {
+            queue: self.session.queue(),
+        }
+    }
+
+    /// Reorder the download queue, see [Session::sort_queue].
+    pub fn api_queue_sort(&self, request: QueueSortRequest) -> QueueResponse {
+        QueueResponse {
+            queue: self.session.sort_queue(request.key, request.reverse),
+        }
+    }
+
+    /// Take a torrent out of the download queue, see [Session::dequeue].
+    pub fn api_queue_dequeue(&self, idx: TorrentId) -> Result<QueueResponse> {
+        self.session
+            .dequeue(idx)
+            .with_error_status_code(StatusCode::NOT_FOUND)?;
+        Ok(QueueResponse {
+            queue: self.session.queue(),
+        })
+    }
+
+    pub fn api_set_announce_enabled(&self, enabled: bool) -> Result<EmptyJsonResponse> {
+        self.session.set_announce_enabled(enabled);
+        Ok(Default::default())
+    }
+
+    pub fn api_set_seeding_announce_interval(
+        &self,
+        request: SeedingAnnounceIntervalRequest,
+    ) -> Result<EmptyJsonResponse> {
+        self.session
+            .set_seeding_announce_interval(request.interval_secs.map(Duration::from_secs))
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    pub fn api_startup_connect_rate(&self) -> StartupConnectRateResponse {
+        let rate = self.session.startup_connect_rate();
+        StartupConnectRateResponse {
+            initial_per_sec: rate.map(|r| r.initial_per_sec),
+            ramp_secs: rate.and_then(|r| r.ramp).map(|d| d.as_secs()),
+            stats: self.session.stats().connect_rate,
+        }
+    }
+
+    pub fn api_set_startup_connect_rate(
+        &self,
+        request: StartupConnectRateRequest,
+    ) -> Result<EmptyJsonResponse> {
+        let rate = request
+            .initial_per_sec
+            .map(|initial_per_sec| StartupConnectRate {
+                initial_per_sec,
+                ramp: request.ramp_secs.map(Duration::from_secs),
+            });
+        self.session
+            .set_startup_connect_rate(rate)
+            .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        Ok(Default::default())
+    }
+
+    /// The session's network, tracker and limit settings, see [Session::export_profile].
+    pub fn api_profile_export(&self, name: Option<String>) -> SessionProfile {
+        self.session.export_profile(name.unwrap_or_default())
+    }
+
+    /// See [Session::import_profile].
+    pub fn api_profile_import(&self, profile: SessionProfile) -> Result<ProfileImportResult> {
+        self.session
+            .import_profile(&profile)
+            .with_error_status_code(StatusCode::BAD_REQUEST)
+    }
+
     pub fn api_dht_stats(&self) -> Result<DhtStats> {
         self.session
             .get_dht()
@@ -207,11 +979,51 @@ impl Api {
             .ok_or(ApiError::dht_disabled())
     }
 
+    pub fn api_dht_set_read_only(&self, read_only: bool) -> Result<EmptyJsonResponse> {
+        self.session
+            .get_dht()
+            .ok_or(ApiError::dht_disabled())?
+            .set_read_only(read_only);
+        Ok(Default::default())
+    }
+
+    /// Fill the DHT routing table before adding magnet links, see [dht::DhtState::warmup].
+    /// Returns once enough nodes are known or the timeout passes; GET /dht/stats shows the
+    /// progress meanwhile. Only one runs at a time, another one fails with CONFLICT.
+    pub async fn api_dht_warmup(&self, request: DhtWarmupRequest) -> Result<DhtWarmupProgress> {
+        let dht = self.session.get_dht().ok_or(ApiError::dht_disabled())?;
+        let min_good_nodes = request.min_good_nodes.unwrap_or(DEFAULT_DHT_WARMUP_NODES);
+        if min_good_nodes == 0 {
+            return Err(anyhow::anyhow!("min_good_nodes must be more than 0"))
+                .with_error_status_code(StatusCode::BAD_REQUEST);
+        }
+        let timeout = Duration::from_secs(
+            request
+                .timeout_secs
+                .unwrap_or(DEFAULT_DHT_WARMUP_TIMEOUT_SECS)
+                .min(MAX_DHT_WARMUP_TIMEOUT_SECS),
+        );
+        dht.warmup(min_good_nodes, timeout)
+            .await
+            .with_error_status_code(StatusCode::CONFLICT)
+    }
+
     pub fn api_dht_table(&self) -> Result<impl Serialize> {
         let dht = self.session.get_dht().ok_or(ApiError::dht_disabled())?;
         Ok(dht.with_routing_table(|r| r.clone()))
     }
 
+    pub fn api_resolving_magnets(&self) -> Vec<ResolvingMagnet> {
+        self.session.resolving_magnets()
+    }
+
+    pub fn api_dht_active_lookups(&self) -> Result<Vec<DhtLookupStats>> {
+        self.session
+            .get_dht()
+            .map(|d| d.active_lookups())
+            .ok_or(ApiError::dht_disabled())
+    }
+
     pub fn api_stats_v0(&self, idx: TorrentId) -> Result<LiveStats> {
         let mgr = self.mgr_handle(idx)?;
         let live = mgr.live().context("torrent not live")?;
@@ -223,6 +1035,53 @@ impl Api {
         Ok(mgr.stats())
     }
 
+    /// Move the session and/or DHT persistence files to new locations. The current state
+    /// is written to the new location right away, and all future writes go there. The new
+    /// files must not exist yet, see [Session::relocate_persistence].
+    pub fn api_relocate_state_files(
+        &self,
+        request: RelocateStateFilesRequest,
+    ) -> Result<EmptyJsonResponse> {
+        if let Some(filename) = request.session_persistence_filename {
+            self.session
+                .relocate_persistence(filename)
+                .context("error relocating session persistence file")
+                .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        }
+        if let Some(filename) = request.dht_persistence_filename {
+            check_state_file_target(&filename).with_error_status_code(StatusCode::BAD_REQUEST)?;
+            self.session
+                .get_dht()
+                .ok_or(ApiError::dht_disabled())?
+                .relocate_persistence(filename)
+                .context("error relocating DHT persistence file")
+                .with_error_status_code(StatusCode::BAD_REQUEST)?;
+        }
+        Ok(Default::default())
+    }
+
+    /// Compare the file lists of two torrents, e.g. for cross-seeding or dedup.
+    ///
+    /// Files are paired up by length, preferring the same relative path. With
+    /// `compare_piece_hashes`, pairs are also checked against the piece hashes from
+    /// the metadata, which is cheap as nothing is read from disk. This only works when
+    /// both torrents have the same piece length and the file starts on a piece boundary
+    /// in both.
+    pub fn api_compare_torrents(
+        &self,
+        idx_a: TorrentId,
+        idx_b: TorrentId,
+        opts: CompareTorrentsOptions,
+    ) -> Result<CompareTorrentsResponse> {
+        let a = self.mgr_handle(idx_a)?;
+        let b = self.mgr_handle(idx_b)?;
+        compare_torrents(
+            (&a.info().info, a.info().out_dir.as_path()),
+            (&b.info().info, b.info().out_dir.as_path()),
+            opts.compare_piece_hashes,
+        )
+    }
+
     pub fn api_dump_haves(&self, idx: usize) -> Result<String> {
         let mgr = self.mgr_handle(idx)?;
         Ok(mgr.with_chunk_tracker(|chunks| format!("{:?}", chunks.get_have_pieces()))?)
@@ -233,6 +1092,87 @@ impl Api {
 pub struct TorrentListResponseItem {
     pub id: usize,
     pub info_hash: String,
+    pub labels: Vec<String>,
+}
+
+impl TorrentListResponseItem {
+    fn new(id: TorrentId, mgr: &ManagedTorrentHandle) -> Self {
+        Self {
+            id,
+            info_hash: mgr.info().info_hash.as_string(),
+            labels: mgr.labels(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TorrentStatsExportRow {
+    pub id: TorrentId,
+    pub name: Option<String>,
+    pub info_hash: String,
+    pub total_bytes: u64,
+    pub progress_bytes: u64,
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub ratio: Option<f64>,
+    /// Unix timestamp, seconds.
+    pub added_at: u64,
+    pub state: &'static str,
+}
+
+impl TorrentStatsExportRow {
+    const CSV_HEADER: &'static str = "id,name,info_hash,total_bytes,progress_bytes,uploaded_bytes,\
+        downloaded_bytes,ratio,added_at,state\n";
+
+    fn new(id: TorrentId, handle: &ManagedTorrentHandle) -> Self {
+        let stats = handle.stats();
+        Self {
+            id,
+            name: handle
+                .info()
+                .info
+                .name
+                .as_ref()
+                .map(|n| String::from_utf8_lossy(n).into_owned()),
+            info_hash: handle.info_hash().as_string(),
+            total_bytes: stats.total_bytes,
+            progress_bytes: stats.progress_bytes,
+            uploaded_bytes: stats.lifetime.uploaded_bytes,
+            downloaded_bytes: stats.lifetime.downloaded_bytes,
+            ratio: stats.lifetime.ratio(),
+            added_at: handle.added_at_secs(),
+            state: stats.state,
+        }
+    }
+
+    fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            self.id,
+            csv_quote(self.name.as_deref().unwrap_or_default()),
+            self.info_hash,
+            self.total_bytes,
+            self.progress_bytes,
+            self.uploaded_bytes,
+            self.downloaded_bytes,
+            self.ratio.map(|r| format!("{r:.3}")).unwrap_or_default(),
+            self.added_at,
+            self.state,
+        )
+    }
+}
+
+// Names may contain commas, quotes or newlines.
+fn csv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
 }
 
 #[derive(Serialize)]
@@ -251,11 +1191,170 @@ pub struct TorrentDetailsResponseFile {
 #[derive(Default, Serialize)]
 pub struct EmptyJsonResponse {}
 
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct ConfigReloadResponse {
+    /// Settings that changed and were applied in place.
+    pub applied: Vec<String>,
+    /// Settings that changed and needed the session to be restarted.
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelocateStateFilesRequest {
+    pub session_persistence_filename: Option<PathBuf>,
+    pub dht_persistence_filename: Option<PathBuf>,
+}
+
+/// A partial update of torrent options. Fields that aren't set are left as is.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TorrentOptionsPatch {
+    pub labels: Option<Vec<String>>,
+    pub sequential: Option<bool>,
+    /// In seconds. 0 removes the torrent's own interval, so that the session's is used.
+    pub seeding_announce_interval_secs: Option<u64>,
+}
+
+impl TorrentOptionsPatch {
+    fn apply(&self, handle: &ManagedTorrentHandle) -> anyhow::Result<()> {
+        // Nothing is changed on a locked torrent.
+        handle.check_unlocked()?;
+        // The only other one that can fail, so it goes first, and nothing is changed if it
+        // does.
+        if let Some(secs) = self.seeding_announce_interval_secs {
+            handle.set_seeding_announce_interval(match secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            })?;
+        }
+        if let Some(labels) = self.labels.as_ref() {
+            handle.set_labels(labels.clone())?;
+        }
+        if let Some(sequential) = self.sequential {
+            handle.set_sequential(sequential)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set or, with null, remove the session-wide seeding announce interval.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeedingAnnounceIntervalRequest {
+    pub interval_secs: Option<u64>,
+}
+
+/// Set or, with a null rate, remove [crate::SessionOptions::startup_connect_rate]. The ramp
+/// starts over.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConnectRateRequest {
+    pub initial_per_sec: Option<u32>,
+    /// Defaults to 3 minutes.
+    pub ramp_secs: Option<u64>,
+}
+
+/// See [Api::api_dht_warmup].
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DhtWarmupRequest {
+    /// Defaults to 32.
+    pub min_good_nodes: Option<usize>,
+    /// Defaults to 30 seconds, at most 5 minutes.
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StartupConnectRateResponse {
+    /// None if dials aren't limited at startup.
+    pub initial_per_sec: Option<u32>,
+    pub ramp_secs: Option<u64>,
+    #[serde(flatten)]
+    pub stats: ConnectRateStats,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueueSortRequest {
+    pub key: QueueSortKey,
+    /// E.g. largest or newest first.
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+/// Queued torrent ids, first to start first.
+#[derive(Serialize, Deserialize)]
+pub struct QueueResponse {
+    pub queue: Vec<TorrentId>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TorrentsBulkUpdateRequest {
+    pub ids: Vec<TorrentId>,
+    pub changes: TorrentOptionsPatch,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TorrentBulkUpdateResult {
+    pub id: TorrentId,
+    /// Set if this torrent couldn't be updated.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TorrentsBulkUpdateResponse {
+    pub results: Vec<TorrentBulkUpdateResult>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompareTorrentsOptions {
+    pub compare_piece_hashes: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PieceHashComparison {
+    NotChecked,
+    /// The file isn't aligned the same way in both torrents, or is smaller than one piece.
+    NotComparable,
+    Match,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompareTorrentsFileMatch {
+    pub file_a: usize,
+    pub file_b: usize,
+    pub name_a: String,
+    pub name_b: String,
+    pub length: u64,
+    pub piece_hashes: PieceHashComparison,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompareTorrentsHardlinkCandidate {
+    pub file_a: usize,
+    pub file_b: usize,
+    pub path_a: PathBuf,
+    pub path_b: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompareTorrentsResponse {
+    pub identical: Vec<CompareTorrentsFileMatch>,
+    pub only_in_a: Vec<usize>,
+    pub only_in_b: Vec<usize>,
+    /// Identical files whose piece hashes matched and which live at different paths.
+    pub hardlink_candidates: Vec<CompareTorrentsHardlinkCandidate>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TorrentDetailsResponse {
     pub info_hash: String,
     pub name: Option<String>,
     pub files: Vec<TorrentDetailsResponseFile>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub user_metadata: serde_json::Value,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -266,6 +1365,155 @@ pub struct ApiAddTorrentResponse {
     pub seen_peers: Option<Vec<SocketAddr>>,
 }
 
+struct CompareFile {
+    name: String,
+    relative_path: Option<PathBuf>,
+    offset: u64,
+    length: u64,
+}
+
+fn compare_files(info: &TorrentMetaV1Info<ByteString>) -> Result<Vec<CompareFile>> {
+    let mut offset = 0;
+    let mut files = Vec::new();
+    for (filename_it, length) in info
+        .iter_filenames_and_lengths()
+        .context("error iterating filenames and lengths")?
+    {
+        files.push(CompareFile {
+            name: filename_it
+                .to_string()
+                .unwrap_or_else(|_| "<INVALID NAME>".to_string()),
+            relative_path: filename_it.to_pathbuf().ok(),
+            offset,
+            length,
+        });
+        offset += length;
+    }
+    Ok(files)
+}
+
+fn compare_piece_hashes(
+    a: &TorrentMetaV1Info<ByteString>,
+    a_file: &CompareFile,
+    b: &TorrentMetaV1Info<ByteString>,
+    b_file: &CompareFile,
+) -> Option<bool> {
+    let piece_length = a.piece_length as u64;
+    if piece_length != b.piece_length as u64
+        || a_file.offset % piece_length != 0
+        || b_file.offset % piece_length != 0
+    {
+        return None;
+    }
+    // The last partial piece also covers the next file, so only full pieces are compared.
+    let full_pieces = a_file.length / piece_length;
+    if full_pieces == 0 {
+        return None;
+    }
+    let a_start = (a_file.offset / piece_length) as u32;
+    let b_start = (b_file.offset / piece_length) as u32;
+    for i in 0..full_pieces as u32 {
+        if a.get_hash(a_start + i)? != b.get_hash(b_start + i)? {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+fn compare_torrents(
+    (a, a_out_dir): (&TorrentMetaV1Info<ByteString>, &Path),
+    (b, b_out_dir): (&TorrentMetaV1Info<ByteString>, &Path),
+    check_hashes: bool,
+) -> Result<CompareTorrentsResponse> {
+    let a_files = compare_files(a)?;
+    let b_files = compare_files(b)?;
+    let mut b_used = vec![false; b_files.len()];
+
+    let mut identical = Vec::new();
+    let mut only_in_a = Vec::new();
+    let mut hardlink_candidates = Vec::new();
+
+    for (a_idx, a_file) in a_files.iter().enumerate() {
+        let mut candidates = b_files
+            .iter()
+            .enumerate()
+            .filter(|(b_idx, b_file)| !b_used[*b_idx] && b_file.length == a_file.length)
+            .collect::<Vec<_>>();
+        // Same relative path first.
+        candidates.sort_by_key(|(_, b_file)| b_file.relative_path != a_file.relative_path);
+
+        let found = candidates.into_iter().find_map(|(b_idx, b_file)| {
+            let piece_hashes = if !check_hashes {
+                PieceHashComparison::NotChecked
+            } else {
+                match compare_piece_hashes(a, a_file, b, b_file) {
+                    Some(true) => PieceHashComparison::Match,
+                    Some(false) => return None,
+                    None => PieceHashComparison::NotComparable,
+                }
+            };
+            Some((b_idx, b_file, piece_hashes))
+        });
+
+        let (b_idx, b_file, piece_hashes) = match found {
+            Some(found) => found,
+            None => {
+                only_in_a.push(a_idx);
+                continue;
+            }
+        };
+        b_used[b_idx] = true;
+
+        if piece_hashes == PieceHashComparison::Match {
+            if let (Some(a_rel), Some(b_rel)) = (&a_file.relative_path, &b_file.relative_path) {
+                let path_a = a_out_dir.join(a_rel);
+                let path_b = b_out_dir.join(b_rel);
+                if path_a != path_b {
+                    hardlink_candidates.push(CompareTorrentsHardlinkCandidate {
+                        file_a: a_idx,
+                        file_b: b_idx,
+                        path_a,
+                        path_b,
+                    });
+                }
+            }
+        }
+
+        identical.push(CompareTorrentsFileMatch {
+            file_a: a_idx,
+            file_b: b_idx,
+            name_a: a_file.name.clone(),
+            name_b: b_file.name.clone(),
+            length: a_file.length,
+            piece_hashes,
+        });
+    }
+
+    let only_in_b = b_used
+        .iter()
+        .enumerate()
+        .filter(|(_, used)| !**used)
+        .map(|(idx, _)| idx)
+        .collect();
+
+    Ok(CompareTorrentsResponse {
+        identical,
+        only_in_a,
+        only_in_b,
+        hardlink_candidates,
+    })
+}
+
+// Handles are cloned first, so that the session isn't locked while the stats are computed.
+fn all_torrent_stats(session: &Session) -> HashMap<TorrentId, TorrentStats> {
+    let torrents: Vec<(TorrentId, ManagedTorrentHandle)> =
+        session.with_torrents(|torrents| torrents.map(|(id, t)| (id, t.clone())).collect());
+    torrents
+        .into_iter()
+        .map(|(id, t)| (id, t.stats()))
+        .collect()
+}
+
 fn make_torrent_details(
     info_hash: &Id20,
     info: &TorrentMetaV1Info<ByteString>,
@@ -297,5 +1545,231 @@ fn make_torrent_details(
         info_hash: info_hash.as_string(),
         name: info.name.as_ref().map(|b| b.to_string()),
         files,
+        user_metadata: serde_json::Value::Null,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use http::StatusCode;
+
+    use super::{
+        compare_torrents, Api, PieceHashComparison, TorrentOptionsPatch, TorrentStatsExportRow,
+    };
+    use crate::{
+        api_error::ApiError,
+        session::{AddTorrentOptions, SessionOptions},
+        test_util::{
+            add_test_torrent, test_data, test_dir, test_session, test_torrent_info,
+            TEST_PIECE_LENGTH,
+        },
+    };
+
+    fn paused() -> AddTorrentOptions {
+        AddTorrentOptions {
+            paused: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_csv_line_quotes_name() {
+        let row = TorrentStatsExportRow {
+            id: 3,
+            name: Some("a \"b\", c".to_owned()),
+            info_hash: "00".repeat(20),
+            total_bytes: 100,
+            progress_bytes: 50,
+            uploaded_bytes: 30,
+            downloaded_bytes: 20,
+            ratio: Some(1.5),
+            added_at: 1700000000,
+            state: "live",
+        };
+        assert_eq!(
+            row.to_csv_line(),
+            format!(
+                "3,\"a \"\"b\"\", c\",{},100,50,30,20,1.500,1700000000,live\n",
+                "00".repeat(20)
+            )
+        );
+        assert_eq!(
+            TorrentStatsExportRow::CSV_HEADER.split(',').count(),
+            row.to_csv_line().split(',').count() - 1
+        );
+    }
+
+    #[test]
+    fn test_compare_torrents() {
+        let big = test_data(2 * TEST_PIECE_LENGTH as usize, 0);
+        let small = test_data(100, 1);
+        let other = test_data(50, 2);
+        let a = test_torrent_info(&[("x/big.bin", &big), ("x/small.txt", &small)]);
+        let b = test_torrent_info(&[("y/big.bin", &big), ("y/other.txt", &other)]);
+
+        let r = compare_torrents((&a, Path::new("/a")), (&b, Path::new("/b")), true).unwrap();
+        assert_eq!(r.identical.len(), 1);
+        assert_eq!((r.identical[0].file_a, r.identical[0].file_b), (0, 0));
+        assert_eq!(r.identical[0].piece_hashes, PieceHashComparison::Match);
+        assert_eq!(r.only_in_a, vec![1]);
+        assert_eq!(r.only_in_b, vec![1]);
+        assert_eq!(r.hardlink_candidates.len(), 1);
+        assert_eq!(r.hardlink_candidates[0].path_a, Path::new("/a/x/big.bin"));
+        assert_eq!(r.hardlink_candidates[0].path_b, Path::new("/b/y/big.bin"));
+
+        // Same file in the same place: nothing to hardlink.
+        let same = compare_torrents((&a, Path::new("/a")), (&a, Path::new("/a")), true).unwrap();
+        assert_eq!(same.identical.len(), 2);
+        assert!(same.hardlink_candidates.is_empty());
+    }
+
+    #[test]
+    fn test_compare_torrents_piece_hashes() {
+        let big = test_data(2 * TEST_PIECE_LENGTH as usize, 0);
+        let changed = test_data(2 * TEST_PIECE_LENGTH as usize, 7);
+        let a = test_torrent_info(&[("big.bin", &big)]);
+        let c = test_torrent_info(&[("big.bin", &changed)]);
+
+        // Only the lengths are compared unless asked to check hashes.
+        let r = compare_torrents((&a, Path::new("/a")), (&c, Path::new("/c")), false).unwrap();
+        assert_eq!(r.identical[0].piece_hashes, PieceHashComparison::NotChecked);
+        assert!(r.hardlink_candidates.is_empty());
+        let r = compare_torrents((&a, Path::new("/a")), (&c, Path::new("/c")), true).unwrap();
+        assert!(r.identical.is_empty());
+        assert_eq!((r.only_in_a, r.only_in_b), (vec![0], vec![0]));
+
+        // Not at a piece boundary in "d", so there's no piece to compare.
+        let pad = test_data(10, 3);
+        let d = test_torrent_info(&[("pad", &pad), ("big.bin", &big)]);
+        let r = compare_torrents((&a, Path::new("/a")), (&d, Path::new("/d")), true).unwrap();
+        assert_eq!(r.identical.len(), 1);
+        assert_eq!(r.identical[0].file_b, 1);
+        assert_eq!(
+            r.identical[0].piece_hashes,
+            PieceHashComparison::NotComparable
+        );
+        assert!(r.hardlink_candidates.is_empty());
+        assert_eq!(r.only_in_b, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn test_torrents_bulk_update() {
+        let dir = test_dir("bulk-update");
+        let data = test_data(100, 0);
+        let session = test_session(&dir, Default::default()).await;
+        let (a, a_handle) = add_test_torrent(&session, "a", &[("f", &data)], paused()).await;
+        let (b, b_handle) = add_test_torrent(&session, "b", &[("f", &data)], paused()).await;
+        b_handle.set_locked(true);
+        let api = Api::new(session.clone(), None, None);
+
+        let response = api.api_torrents_bulk_update(
+            vec![a, b, 1000],
+            TorrentOptionsPatch {
+                labels: Some(vec!["tv".to_owned()]),
+                sequential: Some(true),
+                ..Default::default()
+            },
+        );
+        let results: Vec<_> = response
+            .results
+            .iter()
+            .map(|r| (r.id, r.error.is_some()))
+            .collect();
+        // Each one on its own: the locked and the unknown ones fail, the other one doesn't.
+        assert_eq!(results, vec![(a, false), (b, true), (1000, true)]);
+        assert_eq!(a_handle.labels(), vec!["tv".to_owned()]);
+        assert!(a_handle.is_sequential());
+        assert!(b_handle.labels().is_empty());
+        assert!(!b_handle.is_sequential());
+
+        // Unset fields are left alone.
+        api.api_torrents_bulk_update(
+            vec![a],
+            TorrentOptionsPatch {
+                sequential: Some(false),
+                ..Default::default()
+            },
+        );
+        assert_eq!(a_handle.labels(), vec!["tv".to_owned()]);
+        assert!(!a_handle.is_sequential());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_stays_in_its_dir() {
+        let dir = test_dir("file-sink-dir");
+        let status = |e: ApiError| e.status();
+        let data = test_data(100, 0);
+
+        let session = test_session(&dir, Default::default()).await;
+        let (id, _) = add_test_torrent(&session, "t", &[("f", &data)], paused()).await;
+        let api = Api::new(session.clone(), None, None);
+        let err = api
+            .api_torrent_file_sink(id, 0, dir.join("sink"), false)
+            .unwrap_err();
+        assert_eq!(status(err), StatusCode::FORBIDDEN);
+        session.stop().await;
+
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                file_sink_dir: Some(dir.join("sinks")),
+                ..Default::default()
+            },
+        )
+        .await;
+        let (id, _) = add_test_torrent(&session, "t", &[("f", &data)], paused()).await;
+        let api = Api::new(session.clone(), None, None);
+        for path in ["../sink", "/etc/sink"] {
+            let err = api
+                .api_torrent_file_sink(id, 0, PathBuf::from(path), false)
+                .unwrap_err();
+            assert_eq!(status(err), StatusCode::BAD_REQUEST, "{path}");
+        }
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_api_mirrors_stay_in_mirror_dir() {
+        let dir = test_dir("api-mirror-dir");
+        let status = |e: ApiError| e.status();
+
+        let session = test_session(&dir, Default::default()).await;
+        let api = Api::new(session.clone(), None, None);
+        assert!(api.resolve_api_mirror_paths(Vec::new()).unwrap().is_empty());
+        let err = api
+            .resolve_api_mirror_paths(vec![dir.join("mirror")])
+            .unwrap_err();
+        assert_eq!(status(err), StatusCode::FORBIDDEN);
+        session.stop().await;
+
+        let mirrors = dir.join("mirrors");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                mirror_dir: Some(mirrors.clone()),
+                ..Default::default()
+            },
+        )
+        .await;
+        let api = Api::new(session.clone(), None, None);
+        assert_eq!(
+            api.resolve_api_mirror_paths(vec![PathBuf::from("a"), mirrors.join("b")])
+                .unwrap(),
+            vec![mirrors.join("a"), mirrors.join("b")]
+        );
+        for path in ["../mirror", "/etc/mirror"] {
+            let err = api
+                .resolve_api_mirror_paths(vec![PathBuf::from("a"), PathBuf::from(path)])
+                .unwrap_err();
+            assert_eq!(status(err), StatusCode::BAD_REQUEST, "{path}");
+        }
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
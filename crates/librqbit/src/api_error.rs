@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 use serde::{Serialize, Serializer};
@@ -44,6 +46,22 @@ impl ApiError {
         }
     }
 
+    pub const fn max_torrents_reached(max: usize) -> Self {
+        Self {
+            status: Some(StatusCode::INSUFFICIENT_STORAGE),
+            kind: ApiErrorKind::MaxTorrentsReached(max),
+            plaintext: false,
+        }
+    }
+
+    pub fn path_conflict(path: PathBuf, torrent_id: usize) -> Self {
+        Self {
+            status: Some(StatusCode::CONFLICT),
+            kind: ApiErrorKind::PathConflict(path, torrent_id),
+            plaintext: false,
+        }
+    }
+
     pub const fn dht_disabled() -> Self {
         Self {
             status: Some(StatusCode::NOT_FOUND),
@@ -76,6 +94,8 @@ impl ApiError {
 #[derive(Debug)]
 enum ApiErrorKind {
     TorrentNotFound(usize),
+    MaxTorrentsReached(usize),
+    PathConflict(PathBuf, usize),
     DhtDisabled,
     Text(&'static str),
     Other(anyhow::Error),
@@ -98,6 +118,8 @@ impl Serialize for ApiError {
         let mut serr: SerializedError = SerializedError {
             error_kind: match self.kind {
                 ApiErrorKind::TorrentNotFound(_) => "torrent_not_found",
+                ApiErrorKind::MaxTorrentsReached(_) => "max_torrents_reached",
+                ApiErrorKind::PathConflict(..) => "path_conflict",
                 ApiErrorKind::DhtDisabled => "dht_disabled",
                 ApiErrorKind::Other(_) => "internal_error",
                 ApiErrorKind::Text(_) => "internal_error",
@@ -107,8 +129,11 @@ impl Serialize for ApiError {
             status_text: self.status().to_string(),
             ..Default::default()
         };
-        if let ApiErrorKind::TorrentNotFound(id) = &self.kind {
-            serr.id = Some(*id)
+        match &self.kind {
+            ApiErrorKind::TorrentNotFound(id) | ApiErrorKind::PathConflict(_, id) => {
+                serr.id = Some(*id)
+            }
+            _ => {}
         }
         serr.serialize(serializer)
     }
@@ -138,6 +163,12 @@ impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.kind {
             ApiErrorKind::TorrentNotFound(idx) => write!(f, "torrent {idx} not found"),
+            ApiErrorKind::MaxTorrentsReached(max) => {
+                write!(f, "the session already holds the maximum of {max} torrents")
+            }
+            ApiErrorKind::PathConflict(path, id) => {
+                write!(f, "torrent {id} already writes to {path:?}")
+            }
             ApiErrorKind::Other(err) => write!(f, "{err:?}"),
             ApiErrorKind::DhtDisabled => write!(f, "DHT is disabled"),
             ApiErrorKind::Text(t) => write!(f, "{t}"),
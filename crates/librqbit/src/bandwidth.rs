@@ -0,0 +1,285 @@
+// Capping the session's download and upload rates so that part of the link stays free for
+// other traffic, see SessionOptions::reserved_headroom_bps. The caps follow the link's
+// capacity in each direction: the configured one, or else the highest rate the session has
+// reached, as the link can do at least that. Nothing can be measured above a cap, so measured
+// directions are uncapped for PROBE_DURATION at the start and then every PROBE_INTERVAL, to
+// find out what the link can do now.
+//
+// Each direction is a token bucket holding up to a second's worth of bytes. Takers go into
+// debt and wait for it to be paid back, so concurrent peers share the rate in turn.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+// How often the caps are recomputed from the measured rates.
+pub(crate) const BANDWIDTH_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+// A headroom bigger than the capacity doesn't stop the session altogether.
+const MIN_LIMIT_BPS: u64 = 16 * 1024;
+const PROBE_DURATION: Duration = Duration::from_secs(30);
+const PROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// The link's capacity, see [crate::SessionOptions::link_capacity]. Directions that aren't
+/// set are measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkCapacity {
+    /// In bytes per second.
+    pub download_bps: Option<u64>,
+    /// In bytes per second.
+    pub upload_bps: Option<u64>,
+}
+
+/// Shown in [crate::SessionStats] when [crate::SessionOptions::reserved_headroom_bps] is
+/// set. All rates are in bytes per second.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BandwidthLimitStats {
+    pub reserved_headroom_bps: u64,
+    /// Configured, or the highest rate measured so far. None until something was measured.
+    pub download_capacity_bps: Option<u64>,
+    pub upload_capacity_bps: Option<u64>,
+    /// The caps in effect: the capacity minus the headroom. None while uncapped.
+    pub download_limit_bps: Option<u64>,
+    pub upload_limit_bps: Option<u64>,
+    /// Directions without a configured capacity are uncapped for 30 seconds every 10
+    /// minutes, to measure the link again.
+    pub probing: bool,
+}
+
+pub(crate) fn effective_limit(capacity: Option<u64>, headroom: u64) -> Option<u64> {
+    Some(capacity?.saturating_sub(headroom).max(MIN_LIMIT_BPS))
+}
+
+#[derive(Default)]
+struct Bucket {
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl Bucket {
+    // Takes "bytes" at "rate" bytes per second, going into debt if there aren't enough.
+    // Returns how long until the debt is paid back.
+    fn take(&mut self, bytes: u64, rate: u64, now: Instant) -> Duration {
+        let rate = rate as f64;
+        self.tokens = match self.last {
+            Some(last) => (self.tokens + now.duration_since(last).as_secs_f64() * rate).min(rate),
+            None => rate,
+        };
+        self.last = Some(now);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0. {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / rate)
+    }
+}
+
+// One direction. Uncapped while the limit is 0. Public as PeerConnectionHandler returns it.
+#[derive(Default)]
+pub struct RateLimiter {
+    limit_bps: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn limit(&self) -> Option<u64> {
+        Some(self.limit_bps.load(Ordering::Relaxed)).filter(|l| *l > 0)
+    }
+
+    fn set_limit(&self, limit: Option<u64>) {
+        self.limit_bps.store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    // Wait until "bytes" may be transferred.
+    pub async fn acquire(&self, bytes: u32) {
+        let rate = match self.limit() {
+            Some(rate) => rate,
+            None => return,
+        };
+        let wait = self.bucket.lock().take(bytes as u64, rate, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// The capacity of one direction, and the cap to apply. Measured capacities are only updated
+// while probing, as that's when the rate isn't capped.
+fn direction_limit(
+    configured: Option<u64>,
+    measured: Option<u64>,
+    rate_bps: u64,
+    probing: bool,
+    headroom: u64,
+) -> (Option<u64>, Option<u64>) {
+    if configured.is_some() {
+        return (configured, effective_limit(configured, headroom));
+    }
+    if probing {
+        let capacity = Some(measured.unwrap_or(0).max(rate_bps)).filter(|c| *c > 0);
+        return (capacity, None);
+    }
+    (measured, effective_limit(measured, headroom))
+}
+
+// Shared by all torrents of a session. Never caps anything without a headroom.
+pub(crate) struct BandwidthLimiter {
+    pub download: RateLimiter,
+    pub upload: RateLimiter,
+    headroom_bps: Option<u64>,
+    capacity: LinkCapacity,
+    started: Instant,
+    stats: RwLock<BandwidthLimitStats>,
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        Self::new(None, LinkCapacity::default())
+    }
+}
+
+impl BandwidthLimiter {
+    pub fn new(headroom_bps: Option<u64>, capacity: LinkCapacity) -> Self {
+        let started = Instant::now();
+        let limiter = Self {
+            download: Default::default(),
+            upload: Default::default(),
+            headroom_bps,
+            capacity,
+            started,
+            stats: Default::default(),
+        };
+        limiter.update(0, 0, started);
+        limiter
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.headroom_bps.is_some()
+    }
+
+    pub fn headroom_bps(&self) -> Option<u64> {
+        self.headroom_bps
+    }
+
+    pub fn capacity(&self) -> LinkCapacity {
+        self.capacity
+    }
+
+    // Recomputes the caps with the session-wide rates measured at "now".
+    pub fn update(&self, download_bps: u64, upload_bps: u64, now: Instant) {
+        let headroom = match self.headroom_bps {
+            Some(headroom) => headroom,
+            None => return,
+        };
+        let since_start = now.saturating_duration_since(self.started);
+        let probing = since_start.as_secs() % PROBE_INTERVAL.as_secs() < PROBE_DURATION.as_secs();
+        let mut stats = self.stats.write();
+        stats.reserved_headroom_bps = headroom;
+        stats.probing =
+            probing && (self.capacity.download_bps.is_none() || self.capacity.upload_bps.is_none());
+        let (capacity, limit) = direction_limit(
+            self.capacity.download_bps,
+            stats.download_capacity_bps,
+            download_bps,
+            probing,
+            headroom,
+        );
+        stats.download_capacity_bps = capacity;
+        stats.download_limit_bps = limit;
+        self.download.set_limit(limit);
+        let (capacity, limit) = direction_limit(
+            self.capacity.upload_bps,
+            stats.upload_capacity_bps,
+            upload_bps,
+            probing,
+            headroom,
+        );
+        stats.upload_capacity_bps = capacity;
+        stats.upload_limit_bps = limit;
+        self.upload.set_limit(limit);
+    }
+
+    pub fn stats(&self) -> Option<BandwidthLimitStats> {
+        if !self.is_enabled() {
+            return None;
+        }
+        Some(self.stats.read().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{BandwidthLimiter, Bucket, LinkCapacity, MIN_LIMIT_BPS};
+
+    #[test]
+    fn test_bucket() {
+        let mut bucket = Bucket::default();
+        let t0 = Instant::now();
+        // A second's worth is there to begin with.
+        assert_eq!(bucket.take(1000, 1000, t0), Duration::ZERO);
+        assert_eq!(bucket.take(500, 1000, t0), Duration::from_millis(500));
+        // The debt is paid back at the rate.
+        assert_eq!(
+            bucket.take(500, 1000, t0 + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        // Idle time doesn't add up past a second's worth.
+        assert_eq!(
+            bucket.take(1000, 1000, t0 + Duration::from_secs(10)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_bandwidth_limiter() {
+        let disabled = BandwidthLimiter::new(None, LinkCapacity::default());
+        disabled.update(1_000_000, 1_000_000, Instant::now());
+        assert_eq!(disabled.stats(), None);
+        assert_eq!(disabled.download.limit(), None);
+
+        let limiter = BandwidthLimiter::new(
+            Some(100_000),
+            LinkCapacity {
+                download_bps: None,
+                upload_bps: Some(1_000_000),
+            },
+        );
+        let at = |secs| limiter.started + Duration::from_secs(secs);
+        // Probing the download at first, the configured upload is capped right away.
+        assert_eq!(limiter.download.limit(), None);
+        assert_eq!(limiter.upload.limit(), Some(900_000));
+        limiter.update(3_000_000, 2_000_000, at(5));
+        limiter.update(5_000_000, 2_000_000, at(10));
+        assert_eq!(limiter.download.limit(), None);
+        assert!(limiter.stats().unwrap().probing);
+
+        // Capped at the highest rate seen while probing.
+        limiter.update(4_900_000, 900_000, at(30));
+        let stats = limiter.stats().unwrap();
+        assert!(!stats.probing);
+        assert_eq!(stats.download_capacity_bps, Some(5_000_000));
+        assert_eq!(limiter.download.limit(), Some(4_900_000));
+        assert_eq!(limiter.upload.limit(), Some(900_000));
+
+        // Probing again, the capacity only goes up.
+        limiter.update(4_000_000, 0, at(600));
+        assert_eq!(limiter.download.limit(), None);
+        limiter.update(8_000_000, 0, at(610));
+        limiter.update(1_000_000, 0, at(640));
+        assert_eq!(limiter.download.limit(), Some(7_900_000));
+
+        let tiny = BandwidthLimiter::new(
+            Some(100_000),
+            LinkCapacity {
+                download_bps: Some(50_000),
+                upload_bps: None,
+            },
+        );
+        assert_eq!(tiny.download.limit(), Some(MIN_LIMIT_BPS));
+    }
+}
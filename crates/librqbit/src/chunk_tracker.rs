@@ -126,6 +126,28 @@ impl ChunkTracker {
             .sum()
     }
 
+    // How much of the piece is left to download, going by the chunks received so far.
+    pub fn bytes_left(&self, index: ValidPieceIndex) -> u64 {
+        let status = match self.chunk_status.get(self.lengths.chunk_range(index)) {
+            Some(status) => status,
+            None => return 0,
+        };
+        self.lengths
+            .iter_chunk_infos(index)
+            .filter(|c| {
+                !status
+                    .get(c.chunk_index as usize)
+                    .map(|v| *v)
+                    .unwrap_or(false)
+            })
+            .map(|c| c.size as u64)
+            .sum()
+    }
+
+    pub fn is_piece_needed(&self, index: usize) -> bool {
+        self.needed_pieces.get(index).map(|v| *v).unwrap_or(false)
+    }
+
     pub fn iter_needed_pieces(&self) -> impl Iterator<Item = usize> + '_ {
         self.priority_piece_ids
             .iter()
@@ -173,6 +195,13 @@ impl ChunkTracker {
         }
     }
 
+    // For pieces fetched whole rather than chunk by chunk, e.g. from an HTTP seed.
+    pub fn mark_all_chunks_downloaded(&mut self, index: ValidPieceIndex) {
+        if let Some(s) = self.chunk_status.get_mut(self.lengths.chunk_range(index)) {
+            s.fill(true);
+        }
+    }
+
     pub fn mark_piece_downloaded(&mut self, idx: ValidPieceIndex) {
         self.have.set(idx.get() as usize, true);
     }
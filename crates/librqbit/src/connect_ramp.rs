@@ -0,0 +1,208 @@
+// Spreading out the outgoing peer connections right after the session starts, see
+// crate::SessionOptions::startup_connect_rate. A session restoring many torrents would
+// otherwise dial all their peers within a few seconds, which some ISPs and home routers
+// treat as a port scan. The limit starts low, doubles every RAMP_STEP, and goes away once
+// the ramp is over.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_RAMP: Duration = Duration::from_secs(180);
+const RAMP_STEP: Duration = Duration::from_secs(30);
+// The dial rate in the stats is averaged over this long.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Limit how fast peers are dialed after startup, see
+/// [crate::SessionOptions::startup_connect_rate].
+#[derive(Debug, Clone, Copy)]
+pub struct StartupConnectRate {
+    /// Connection attempts per second across all torrents right after startup. The limit
+    /// doubles every 30 seconds.
+    pub initial_per_sec: u32,
+    /// How long dials are limited for. Defaults to 3 minutes.
+    pub ramp: Option<Duration>,
+}
+
+/// Shown in [crate::SessionStats].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConnectRateStats {
+    /// Outgoing peer connection attempts per second, over the last 10 seconds.
+    pub dials_per_sec: f64,
+    /// The current limit while ramping up, None once the ramp is over or if there's none.
+    pub limit_per_sec: Option<f64>,
+    /// Seconds until dials aren't limited anymore.
+    pub ramp_remaining_secs: Option<u64>,
+}
+
+// The limit "elapsed" into the ramp, None once it's over.
+fn limit_at(rate: &StartupConnectRate, elapsed: Duration) -> Option<f64> {
+    if elapsed >= rate.ramp.unwrap_or(DEFAULT_RAMP) {
+        return None;
+    }
+    let steps = (elapsed.as_secs() / RAMP_STEP.as_secs()).min(16) as i32;
+    Some(rate.initial_per_sec.max(1) as f64 * 2f64.powi(steps))
+}
+
+struct ConnectRampState {
+    rate: Option<StartupConnectRate>,
+    started: Instant,
+    // The earliest time the next dial may go out.
+    next_dial: Instant,
+    // When the recent dials went out, for the stats.
+    recent: VecDeque<Instant>,
+}
+
+impl ConnectRampState {
+    // Returns when the dial may go out, and books that slot.
+    fn reserve(&mut self, now: Instant) -> Instant {
+        let limit = self
+            .rate
+            .as_ref()
+            .and_then(|r| limit_at(r, now.saturating_duration_since(self.started)));
+        let at = match limit {
+            Some(limit) => {
+                let at = self.next_dial.max(now);
+                self.next_dial = at + Duration::from_secs_f64(1. / limit);
+                at
+            }
+            None => now,
+        };
+        self.recent.push_back(at);
+        self.prune(now);
+        at
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some(t) = self.recent.front() {
+            if now.saturating_duration_since(*t) <= RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn stats(&mut self, now: Instant) -> ConnectRateStats {
+        self.prune(now);
+        let elapsed = now.saturating_duration_since(self.started);
+        let limit_per_sec = self.rate.as_ref().and_then(|r| limit_at(r, elapsed));
+        ConnectRateStats {
+            // Dials booked for later don't count yet.
+            dials_per_sec: self.recent.iter().filter(|t| **t <= now).count() as f64
+                / RATE_WINDOW.as_secs_f64(),
+            limit_per_sec,
+            ramp_remaining_secs: limit_per_sec.and(self.rate.as_ref()).map(|r| {
+                r.ramp
+                    .unwrap_or(DEFAULT_RAMP)
+                    .saturating_sub(elapsed)
+                    .as_secs()
+            }),
+        }
+    }
+}
+
+// Shared by all torrents of a session. With no rate set, dials never wait.
+pub(crate) struct ConnectRamp {
+    state: Mutex<ConnectRampState>,
+}
+
+impl Default for ConnectRamp {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl ConnectRamp {
+    pub fn new(rate: Option<StartupConnectRate>) -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(ConnectRampState {
+                rate,
+                started: now,
+                next_dial: now,
+                recent: VecDeque::new(),
+            }),
+        }
+    }
+
+    // Waits for the turn of one outgoing connection attempt.
+    pub async fn wait(&self) {
+        let now = Instant::now();
+        let at = self.state.lock().reserve(now);
+        if at > now {
+            tokio::time::sleep_until(at.into()).await;
+        }
+    }
+
+    pub fn rate(&self) -> Option<StartupConnectRate> {
+        self.state.lock().rate
+    }
+
+    // Replaces the rate and starts the ramp over from now, e.g. before adding many torrents
+    // at once. None stops limiting dials.
+    pub fn set_rate(&self, rate: Option<StartupConnectRate>) {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        state.rate = rate;
+        state.started = now;
+        state.next_dial = now;
+    }
+
+    pub fn stats(&self) -> ConnectRateStats {
+        self.state.lock().stats(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{limit_at, ConnectRamp, StartupConnectRate};
+
+    #[test]
+    fn test_limit_at() {
+        let rate = StartupConnectRate {
+            initial_per_sec: 5,
+            ramp: Some(Duration::from_secs(120)),
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(limit_at(&rate, secs(0)), Some(5.));
+        assert_eq!(limit_at(&rate, secs(29)), Some(5.));
+        assert_eq!(limit_at(&rate, secs(30)), Some(10.));
+        assert_eq!(limit_at(&rate, secs(119)), Some(40.));
+        assert_eq!(limit_at(&rate, secs(120)), None);
+    }
+
+    #[test]
+    fn test_connect_ramp_spaces_dials() {
+        let ramp = ConnectRamp::new(Some(StartupConnectRate {
+            initial_per_sec: 2,
+            ramp: None,
+        }));
+        let mut state = ramp.state.lock();
+        let now = Instant::now();
+        state.started = now;
+        state.next_dial = now;
+
+        assert_eq!(state.reserve(now), now);
+        assert_eq!(state.reserve(now), now + Duration::from_millis(500));
+        assert_eq!(state.reserve(now), now + Duration::from_secs(1));
+        let stats = state.stats(now);
+        assert_eq!(stats.dials_per_sec, 0.1);
+        assert_eq!(stats.limit_per_sec, Some(2.));
+        assert_eq!(stats.ramp_remaining_secs, Some(180));
+
+        // Once the ramp is over, dials go out right away.
+        let later = now + Duration::from_secs(180);
+        assert_eq!(state.reserve(later), later);
+        assert_eq!(state.reserve(later), later);
+        let stats = state.stats(later);
+        assert_eq!(stats.dials_per_sec, 0.2);
+        assert_eq!(stats.limit_per_sec, None);
+        assert_eq!(stats.ramp_remaining_secs, None);
+    }
+}
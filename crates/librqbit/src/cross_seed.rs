@@ -0,0 +1,228 @@
+// Reusing the data of torrents already in the session for a torrent added with
+// AddTorrentOptions::cross_seed, e.g. the same release from another tracker, made with a
+// different piece length. The pieces of the two torrents don't line up, so they can't share
+// pieces. Instead, complete files with the same name and length are copied to where the new
+// torrent expects them by its first initial check, which then checks them against its own
+// piece hashes. The existing files are only ever read: a wrong match costs a download, not
+// the other torrent's data.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    file_permissions::FilePermissions,
+    torrent_state::{
+        utils::{check_path_within, is_file_complete, with_incomplete_suffix},
+        ManagedTorrent,
+    },
+};
+
+// Copies are written here first, so that an interrupted copy isn't taken for a file.
+const COPY_SUFFIX: &str = ".cross-seed";
+
+// A complete file of a torrent in the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CrossSeedSource {
+    pub path: PathBuf,
+    // Relative to the torrent's output folder.
+    pub relative_path: PathBuf,
+    pub len: u64,
+}
+
+// For each of the new torrent's "files" (relative path and length), the index of the source
+// to copy it from. A source matches if it has the same length and file name, and one at the
+// same relative path is preferred. Each source is used once, and empty files are skipped as
+// there's nothing to reuse.
+pub(crate) fn match_files(
+    files: &[(PathBuf, u64)],
+    sources: &[CrossSeedSource],
+) -> Vec<Option<usize>> {
+    let mut used = HashSet::new();
+    let mut matches = vec![None; files.len()];
+    // Exact paths go first, so that they aren't taken by a file that only has the same name.
+    for exact in [true, false] {
+        for (file, (path, len)) in files.iter().enumerate() {
+            if *len == 0 || matches[file].is_some() {
+                continue;
+            }
+            let found = (0..sources.len()).find(|idx| {
+                let s = &sources[*idx];
+                !used.contains(idx)
+                    && s.len == *len
+                    && if exact {
+                        s.relative_path == *path
+                    } else {
+                        s.path.file_name().is_some() && s.path.file_name() == path.file_name()
+                    }
+            });
+            if let Some(idx) = found {
+                used.insert(idx);
+                matches[file] = Some(idx);
+            }
+        }
+    }
+    matches
+}
+
+impl ManagedTorrent {
+    // The torrent's complete files that are on disk with the right length. Empty if it's not
+    // paused or live, as only then is it known what's complete.
+    pub(crate) fn cross_seed_sources(&self) -> Vec<CrossSeedSource> {
+        let info = self.info();
+        let files = || -> anyhow::Result<Vec<CrossSeedSource>> {
+            let paths = info.file_paths()?;
+            let lengths: Vec<u64> = info.info.iter_file_lengths()?.collect();
+            let complete = self.with_chunk_tracker(|ct| {
+                let mut offset = 0;
+                lengths
+                    .iter()
+                    .map(|len| {
+                        let complete =
+                            is_file_complete(&info.lengths, ct.get_have_pieces(), offset, *len);
+                        offset += len;
+                        complete
+                    })
+                    .collect::<Vec<bool>>()
+            })?;
+            let mut sources = Vec::new();
+            for ((path, len), complete) in paths.into_iter().zip(lengths).zip(complete) {
+                if !complete {
+                    continue;
+                }
+                // Complete files may still be at their incomplete path.
+                let on_disk = std::iter::once(path.clone())
+                    .chain(info.incomplete_file_path(&path))
+                    .find(|p| std::fs::metadata(p).map(|m| m.len()).ok() == Some(len));
+                if let (Some(on_disk), Ok(relative_path)) =
+                    (on_disk, path.strip_prefix(&info.out_dir))
+                {
+                    sources.push(CrossSeedSource {
+                        path: on_disk,
+                        relative_path: relative_path.to_owned(),
+                        len,
+                    });
+                }
+            }
+            Ok(sources)
+        };
+        match files() {
+            Ok(sources) => sources,
+            Err(e) => {
+                debug!(info_hash = ?info.info_hash, "not using torrent for cross-seeding: {e:#}");
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn copy_file(from: &Path, to: &Path, perms: FilePermissions) -> anyhow::Result<u64> {
+    if let Some(parent) = to.parent() {
+        perms.create_dir_all(parent)?;
+    }
+    let tmp = with_incomplete_suffix(to, COPY_SUFFIX);
+    let copied = std::fs::copy(from, &tmp)?;
+    if let Err(e) = perms
+        .apply_to_file(&tmp)
+        .and_then(|_| Ok(std::fs::rename(&tmp, to)?))
+    {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(copied)
+}
+
+// Copies each source to its target inside "root", skipping targets that already exist. Failed
+// copies are logged and skipped, the torrent downloads those files instead. Returns the files
+// and bytes copied.
+pub(crate) fn copy_files(
+    root: &Path,
+    copies: &[(PathBuf, PathBuf)],
+    perms: FilePermissions,
+) -> (usize, u64) {
+    let mut files = 0;
+    let mut bytes = 0;
+    for (from, to) in copies {
+        if to.exists() {
+            continue;
+        }
+        match check_path_within(root, to).and_then(|_| copy_file(from, to, perms)) {
+            Ok(copied) => {
+                files += 1;
+                bytes += copied;
+            }
+            Err(e) => warn!(?from, ?to, "error copying file for cross-seeding: {e:#}"),
+        }
+    }
+    (files, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{copy_files, match_files, CrossSeedSource};
+
+    fn source(path: &str, relative_path: &str, len: u64) -> CrossSeedSource {
+        CrossSeedSource {
+            path: PathBuf::from(path),
+            relative_path: PathBuf::from(relative_path),
+            len,
+        }
+    }
+
+    #[test]
+    fn test_match_files() {
+        let sources = [
+            source("/dl/a/movie.mkv", "movie.mkv", 1000),
+            source("/dl/a/sample.mkv", "sample.mkv", 100),
+            source("/dl/b/Extras/sample.mkv", "Extras/sample.mkv", 100),
+            source("/dl/b/info.nfo", "info.nfo", 10),
+        ];
+        let files = [
+            (PathBuf::from("Movie (2020)/movie.mkv"), 1000),
+            (PathBuf::from("Extras/sample.mkv"), 100),
+            (PathBuf::from("sample.mkv"), 100),
+            // Same name, different length.
+            (PathBuf::from("info.nfo"), 11),
+            (PathBuf::from("empty"), 0),
+        ];
+        assert_eq!(
+            match_files(&files, &sources),
+            vec![Some(0), Some(2), Some(1), None, None]
+        );
+        // A source is only used once.
+        let files = [
+            (PathBuf::from("movie.mkv"), 1000),
+            (PathBuf::from("copy/movie.mkv"), 1000),
+        ];
+        assert_eq!(match_files(&files, &sources), vec![Some(0), None]);
+    }
+
+    #[test]
+    fn test_copy_files() {
+        let dir =
+            std::env::temp_dir().join(format!("librqbit-cross-seed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("a.bin");
+        std::fs::write(&from, b"hello").unwrap();
+        let new = dir.join("new/sub/a.bin");
+        let existing = dir.join("existing.bin");
+        std::fs::write(&existing, b"keep").unwrap();
+
+        let copies = [
+            (from.clone(), new.clone()),
+            (from.clone(), existing.clone()),
+            (from.clone(), dir.join("../outside.bin")),
+        ];
+        assert_eq!(copy_files(&dir, &copies, Default::default()), (1, 5));
+        assert_eq!(std::fs::read(&new).unwrap(), b"hello");
+        assert_eq!(std::fs::read(&existing).unwrap(), b"keep");
+        // The source is left alone.
+        assert_eq!(std::fs::read(&from).unwrap(), b"hello");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
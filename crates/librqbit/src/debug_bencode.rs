@@ -0,0 +1,118 @@
+// Dumping the raw bencode tree of a torrent file as JSON, see Api::api_debug_bencode.
+// Strings that are valid UTF-8 become JSON strings, anything else, e.g. "pieces", becomes
+// {"base64": "..."}. Dict keys that aren't UTF-8 are written as "base64:...".
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose, Engine as _};
+use bencode::BencodeValue;
+use buffers::ByteBuf;
+use serde_json::Value;
+
+// The parser recurses on every list and dict.
+const MAX_DEPTH: usize = 64;
+
+fn base64(b: &[u8]) -> String {
+    general_purpose::STANDARD.encode(b)
+}
+
+// Refuses nesting the parser might overflow the stack on. Anything malformed is left for
+// the parser to report.
+fn check_depth(buf: &[u8]) -> anyhow::Result<()> {
+    let mut depth = 0usize;
+    let mut pos = 0;
+    while pos < buf.len() {
+        match buf[pos] {
+            b'l' | b'd' => {
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    bail!("bencode is nested deeper than {MAX_DEPTH} levels");
+                }
+                pos += 1;
+            }
+            b'e' => {
+                depth = depth.saturating_sub(1);
+                pos += 1;
+            }
+            b'i' => match buf[pos..].iter().position(|c| *c == b'e') {
+                Some(end) => pos += end + 1,
+                None => return Ok(()),
+            },
+            b'0'..=b'9' => {
+                let colon = match buf[pos..].iter().position(|c| *c == b':') {
+                    Some(colon) => pos + colon,
+                    None => return Ok(()),
+                };
+                let len: usize = match std::str::from_utf8(&buf[pos..colon])
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                {
+                    Some(len) => len,
+                    None => return Ok(()),
+                };
+                pos = (colon + 1).saturating_add(len);
+            }
+            _ => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn to_json(value: &BencodeValue<ByteBuf>) -> Value {
+    match value {
+        BencodeValue::Bytes(b) => match std::str::from_utf8(b.as_ref()) {
+            Ok(s) => Value::String(s.to_owned()),
+            Err(_) => serde_json::json!({ "base64": base64(b.as_ref()) }),
+        },
+        BencodeValue::Integer(i) => Value::from(*i),
+        BencodeValue::List(l) => Value::Array(l.iter().map(to_json).collect()),
+        BencodeValue::Dict(d) => Value::Object(
+            d.iter()
+                .map(|(k, v)| {
+                    let key = match std::str::from_utf8(k.as_ref()) {
+                        Ok(s) => s.to_owned(),
+                        Err(_) => format!("base64:{}", base64(k.as_ref())),
+                    };
+                    (key, to_json(v))
+                })
+                .collect(),
+        ),
+    }
+}
+
+pub(crate) fn debug_bencode(buf: &[u8]) -> anyhow::Result<Value> {
+    check_depth(buf)?;
+    let value = bencode::dyn_from_bytes::<ByteBuf>(buf).context("error parsing bencode")?;
+    Ok(to_json(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::debug_bencode;
+
+    #[test]
+    fn test_debug_bencode() {
+        let v = debug_bencode(b"d4:infod6:lengthi5e6:pieces2:\xff\x00e5:listsl1:ai-1eee").unwrap();
+        assert_eq!(
+            v,
+            json!({
+                "info": {"length": 5, "pieces": {"base64": "/wA="}},
+                "lists": ["a", -1],
+            })
+        );
+        assert!(debug_bencode(b"d4:info").is_err());
+    }
+
+    #[test]
+    fn test_debug_bencode_refuses_deep_nesting() {
+        let mut deep = vec![b'l'; 100];
+        deep.extend(vec![b'e'; 100]);
+        let e = debug_bencode(&deep).unwrap_err();
+        assert!(format!("{e:#}").contains("nested deeper than"));
+        // Brackets inside strings don't count.
+        let mut s = b"100:".to_vec();
+        s.extend(vec![b'l'; 100]);
+        assert_eq!(debug_bencode(&s).unwrap(), json!("l".repeat(100)));
+    }
+}
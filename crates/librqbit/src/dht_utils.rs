@@ -1,9 +1,14 @@
-use std::{collections::HashSet, net::SocketAddr};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use anyhow::Context;
 use buffers::ByteString;
 use futures::{stream::FuturesUnordered, Stream, StreamExt};
 use librqbit_core::torrent_metainfo::TorrentMetaV1Info;
+use tokio::sync::Semaphore;
 use tracing::debug;
 
 use crate::{
@@ -15,6 +20,8 @@ use librqbit_core::hash_id::Id20;
 pub enum ReadMetainfoResult<Rx> {
     Found {
         info: TorrentMetaV1Info<ByteString>,
+        // The info dictionary exactly as received, see [crate::ManagedTorrent::raw_info].
+        info_bytes: ByteString,
         rx: Rx,
         seen: HashSet<SocketAddr>,
     },
@@ -23,41 +30,58 @@ pub enum ReadMetainfoResult<Rx> {
     },
 }
 
-pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unpin>(
+/// How many peers a metadata fetch has found, and what it's doing with them.
+#[derive(Default, Debug)]
+pub(crate) struct MetadataFetchCounters {
+    pub peers_seen: AtomicU64,
+    pub peers_connecting: AtomicU64,
+    pub peers_failed: AtomicU64,
+}
+
+/// The default limit of peers contacted at a time for one magnet's metadata.
+pub(crate) const DEFAULT_MAX_METADATA_PEERS: usize = 128;
+
+// Peers are only contacted while holding a permit from "semaphore", which may be shared
+// between several fetches.
+pub(crate) async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unpin>(
     peer_id: Id20,
     info_hash: Id20,
     initial_addrs: Vec<SocketAddr>,
     addrs_stream: A,
     peer_connection_options: Option<PeerConnectionOptions>,
+    semaphore: &Semaphore,
+    counters: &MetadataFetchCounters,
 ) -> ReadMetainfoResult<A> {
     let mut seen = HashSet::<SocketAddr>::new();
     let mut addrs = addrs_stream;
 
-    let semaphore = tokio::sync::Semaphore::new(128);
-
-    let read_info_guarded = |addr| {
-        let semaphore = &semaphore;
-        async move {
-            let token = semaphore.acquire().await?;
-            let ret = peer_info_reader::read_metainfo_from_peer(
-                addr,
-                peer_id,
-                info_hash,
-                peer_connection_options,
-                BlockingSpawner::new(true),
-            )
-            .await
-            .with_context(|| format!("error reading metainfo from {addr}"));
-            drop(token);
-            ret
+    let read_info_guarded = |addr| async move {
+        let token = semaphore.acquire().await?;
+        counters.peers_connecting.fetch_add(1, Ordering::Relaxed);
+        let ret = peer_info_reader::read_metainfo_from_peer(
+            addr,
+            peer_id,
+            info_hash,
+            peer_connection_options,
+            BlockingSpawner::new(true),
+        )
+        .await
+        .with_context(|| format!("error reading metainfo from {addr}"));
+        counters.peers_connecting.fetch_sub(1, Ordering::Relaxed);
+        if ret.is_err() {
+            counters.peers_failed.fetch_add(1, Ordering::Relaxed);
         }
+        drop(token);
+        ret
     };
 
     let mut unordered = FuturesUnordered::new();
 
     for a in initial_addrs {
-        seen.insert(a);
-        unordered.push(read_info_guarded(a));
+        if seen.insert(a) {
+            counters.peers_seen.fetch_add(1, Ordering::Relaxed);
+            unordered.push(read_info_guarded(a));
+        }
     }
 
     loop {
@@ -66,6 +90,7 @@ pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unp
                 match next_addr {
                     Some(addr) => {
                         if seen.insert(addr) {
+                            counters.peers_seen.fetch_add(1, Ordering::Relaxed);
                             unordered.push(read_info_guarded(addr));
                         }
                     },
@@ -74,7 +99,12 @@ pub async fn read_metainfo_from_peer_receiver<A: Stream<Item = SocketAddr> + Unp
             },
             done = unordered.next(), if !unordered.is_empty() => {
                 match done {
-                    Some(Ok(info)) => return ReadMetainfoResult::Found { info, seen, rx: addrs },
+                    Some(Ok(received)) => return ReadMetainfoResult::Found {
+                        info: received.info,
+                        info_bytes: received.info_bytes,
+                        seen,
+                        rx: addrs,
+                    },
                     Some(Err(e)) => {
                         debug!("{:#}", e);
                     },
@@ -112,7 +142,17 @@ mod tests {
 
         let peer_rx = dht.get_peers(info_hash, None).unwrap();
         let peer_id = generate_peer_id();
-        match read_metainfo_from_peer_receiver(peer_id, info_hash, Vec::new(), peer_rx, None).await
+        let semaphore = Semaphore::new(DEFAULT_MAX_METADATA_PEERS);
+        match read_metainfo_from_peer_receiver(
+            peer_id,
+            info_hash,
+            Vec::new(),
+            peer_rx,
+            None,
+            &semaphore,
+            &Default::default(),
+        )
+        .await
         {
             ReadMetainfoResult::Found { info, .. } => dbg!(info),
             ReadMetainfoResult::ChannelClosed { .. } => todo!("should not have happened"),
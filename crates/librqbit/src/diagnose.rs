@@ -0,0 +1,175 @@
+// Telling why a torrent isn't downloading, see crate::api::Api::api_torrent_diagnose. The
+// torrent's state decides most of it. A live torrent that isn't complete is looked at in
+// order of what to fix first: nobody to download from, then peers that don't let us download,
+// then peers that don't have what's missing.
+
+use serde::Serialize;
+
+/// Why a torrent is or isn't downloading, see [crate::Session::diagnose_torrent]. Serialized
+/// with the kind in "reason", e.g. `{"reason": "all_peers_choking", "peers": 5}`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum TorrentDiagnosis {
+    /// Nothing is holding it back.
+    Downloading { peers: u32 },
+    /// All selected files are downloaded.
+    Complete,
+    /// Checking the files on disk, e.g. after adding or a recheck.
+    Checking {
+        progress_bytes: u64,
+        total_bytes: u64,
+    },
+    /// Paused.
+    Paused,
+    /// Frozen with [crate::ManagedTorrent::set_locked].
+    Locked,
+    /// Waiting for a download slot, see [crate::SessionOptions::max_active_downloads].
+    /// Position 1 starts next.
+    Queued { position: usize },
+    /// A magnet link still waiting for its metadata, see [crate::Session::resolving_magnets].
+    MetadataNotResolved {
+        peers_seen: u64,
+        peers_connecting: u64,
+        peers_failed: u64,
+    },
+    /// Stopped as reading or writing its files failed, e.g. a full disk or a read-only
+    /// filesystem.
+    DiskError { error: String },
+    /// Stopped with another error.
+    Error { error: String },
+    /// No peers connected.
+    NoPeers {
+        /// Peers known but not connected yet, i.e. queued or connecting.
+        known_peers: usize,
+        /// False while [crate::Session::set_announce_enabled] has turned announces off, so
+        /// trackers and DHT find no new peers.
+        announce_enabled: bool,
+    },
+    /// Peers are connected but none of them let us download.
+    AllPeersChoking { peers: u32 },
+    /// Peers let us download, but none of them have the missing pieces, see
+    /// [crate::TorrentStats::unavailable_pieces].
+    PiecesUnavailable { peers: u32, unavailable_pieces: u32 },
+}
+
+impl TorrentDiagnosis {
+    pub(crate) fn from_error(e: &anyhow::Error) -> Self {
+        let error = format!("{e:#}");
+        let is_disk_error = e
+            .chain()
+            .any(|e| e.downcast_ref::<std::io::Error>().is_some());
+        if is_disk_error {
+            TorrentDiagnosis::DiskError { error }
+        } else {
+            TorrentDiagnosis::Error { error }
+        }
+    }
+}
+
+// What a live torrent that isn't complete looks like.
+#[derive(Debug, Default)]
+pub(crate) struct LiveDiagnosisInput {
+    pub connected_peers: u32,
+    // Connected peers that choke us.
+    pub choking_peers: u32,
+    pub known_peers: usize,
+    pub announce_enabled: bool,
+    // Set once stalled, see TorrentStateLive::stalled_unavailable_pieces.
+    pub unavailable_pieces: Option<u32>,
+}
+
+pub(crate) fn diagnose_live(input: &LiveDiagnosisInput) -> TorrentDiagnosis {
+    if input.connected_peers == 0 {
+        return TorrentDiagnosis::NoPeers {
+            known_peers: input.known_peers,
+            announce_enabled: input.announce_enabled,
+        };
+    }
+    if input.choking_peers >= input.connected_peers {
+        return TorrentDiagnosis::AllPeersChoking {
+            peers: input.connected_peers,
+        };
+    }
+    match input.unavailable_pieces {
+        Some(unavailable_pieces) if unavailable_pieces > 0 => TorrentDiagnosis::PiecesUnavailable {
+            peers: input.connected_peers,
+            unavailable_pieces,
+        },
+        _ => TorrentDiagnosis::Downloading {
+            peers: input.connected_peers,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::{diagnose_live, LiveDiagnosisInput, TorrentDiagnosis};
+
+    #[test]
+    fn test_diagnose_live() {
+        let no_peers = LiveDiagnosisInput {
+            known_peers: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            diagnose_live(&no_peers),
+            TorrentDiagnosis::NoPeers {
+                known_peers: 3,
+                announce_enabled: false
+            }
+        );
+
+        let choked = LiveDiagnosisInput {
+            connected_peers: 4,
+            choking_peers: 4,
+            unavailable_pieces: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            diagnose_live(&choked),
+            TorrentDiagnosis::AllPeersChoking { peers: 4 }
+        );
+
+        let unavailable = LiveDiagnosisInput {
+            choking_peers: 3,
+            ..choked
+        };
+        assert_eq!(
+            diagnose_live(&unavailable),
+            TorrentDiagnosis::PiecesUnavailable {
+                peers: 4,
+                unavailable_pieces: 10
+            }
+        );
+
+        let downloading = LiveDiagnosisInput {
+            unavailable_pieces: None,
+            ..unavailable
+        };
+        assert_eq!(
+            diagnose_live(&downloading),
+            TorrentDiagnosis::Downloading { peers: 4 }
+        );
+    }
+
+    #[test]
+    fn test_diagnose_error() {
+        let disk: anyhow::Result<()> = Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "no space left on device",
+        ))
+        .context("error writing");
+        assert!(matches!(
+            TorrentDiagnosis::from_error(&disk.unwrap_err()),
+            TorrentDiagnosis::DiskError { .. }
+        ));
+        assert_eq!(
+            TorrentDiagnosis::from_error(&anyhow::anyhow!("bad metadata")),
+            TorrentDiagnosis::Error {
+                error: "bad metadata".to_owned()
+            }
+        );
+    }
+}
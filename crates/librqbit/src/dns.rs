@@ -0,0 +1,426 @@
+// Resolving tracker and web seed hostnames with something other than the system resolver,
+// see SessionOptions::dns. Only A and AAAA queries are sent, encoded by hand as that's all
+// that's needed. A truncated UDP answer is asked for again over TCP. Answers are cached for their TTL and failures for a little while, per host,
+// so a broken hostname doesn't hold up announces to the others.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+use tracing::debug;
+use url::Url;
+
+/// Where hostnames of trackers, web seeds and torrent URLs are resolved, see
+/// [crate::SessionOptions::dns].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsConfig {
+    /// The operating system's resolver.
+    #[default]
+    System,
+    /// Plain DNS over UDP to these servers, tried in order, e.g. a resolver inside a VPN.
+    Servers(Vec<SocketAddr>),
+    /// DNS over HTTPS (RFC 8484) to this URL, e.g. "https://1.1.1.1/dns-query". If the URL
+    /// has a hostname, that one is looked up with the system resolver.
+    DnsOverHttps(Url),
+}
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+// Bounds for the TTLs servers send, so that a TTL of 0 doesn't mean a lookup per request.
+const MIN_TTL: Duration = Duration::from_secs(30);
+const MAX_TTL: Duration = Duration::from_secs(24 * 3600);
+// How long a failed lookup is remembered before the host is tried again.
+const FAILURE_TTL: Duration = Duration::from_secs(30);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+struct CacheEntry {
+    result: Result<Vec<IpAddr>, String>,
+    expires: Instant,
+}
+
+pub(crate) struct DnsResolver {
+    config: DnsConfig,
+    doh_client: reqwest::Client,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl DnsResolver {
+    /// None for [DnsConfig::System], which needs nothing from us.
+    pub fn new(config: DnsConfig) -> anyhow::Result<Option<Arc<Self>>> {
+        match &config {
+            DnsConfig::System => return Ok(None),
+            DnsConfig::Servers(servers) if servers.is_empty() => {
+                bail!("at least one DNS server is needed")
+            }
+            DnsConfig::DnsOverHttps(url) if url.scheme() != "https" => {
+                bail!("DNS over HTTPS needs an https:// URL, got {url}")
+            }
+            _ => {}
+        }
+        Ok(Some(Arc::new(Self {
+            config,
+            doh_client: reqwest::Client::builder().timeout(QUERY_TIMEOUT).build()?,
+            cache: Default::default(),
+        })))
+    }
+
+    pub async fn lookup(&self, host: &str) -> anyhow::Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(e) = self.cache.lock().get(&host) {
+            if e.expires > Instant::now() {
+                return e.result.clone().map_err(anyhow::Error::msg);
+            }
+        }
+        let (result, ttl) = match self.lookup_uncached(&host).await {
+            Ok((addrs, ttl)) => (Ok(addrs), ttl.clamp(MIN_TTL, MAX_TTL)),
+            Err(e) => (Err(format!("error resolving {host}: {e:#}")), FAILURE_TTL),
+        };
+        debug!(%host, ?result, ?ttl, "resolved");
+        self.cache.lock().insert(
+            host,
+            CacheEntry {
+                result: result.clone(),
+                expires: Instant::now() + ttl,
+            },
+        );
+        result.map_err(anyhow::Error::msg)
+    }
+
+    async fn lookup_uncached(&self, host: &str) -> anyhow::Result<(Vec<IpAddr>, Duration)> {
+        let (a, aaaa) = tokio::join!(self.query(host, TYPE_A), self.query(host, TYPE_AAAA));
+        let (addrs, ttl) = match (a, aaaa) {
+            (Err(e), Err(_)) => return Err(e),
+            (a, aaaa) => {
+                a.into_iter()
+                    .chain(aaaa)
+                    .fold((Vec::new(), None), |(mut addrs, ttl), answer| {
+                        addrs.extend(answer.addrs);
+                        let ttl = match (ttl, answer.ttl) {
+                            (Some(a), Some(b)) => Some(a.min(b)),
+                            (a, b) => a.or(b),
+                        };
+                        (addrs, ttl)
+                    })
+            }
+        };
+        if addrs.is_empty() {
+            bail!("no addresses");
+        }
+        Ok((addrs, Duration::from_secs(ttl.unwrap_or_default().into())))
+    }
+
+    async fn query(&self, host: &str, qtype: u16) -> anyhow::Result<Answer> {
+        let id = rand::random();
+        let query = encode_query(id, host, qtype)?;
+        let response = match &self.config {
+            DnsConfig::System => bail!("bug: the system resolver is used directly"),
+            DnsConfig::Servers(servers) => {
+                let mut last_error = None;
+                let mut response = None;
+                for server in servers {
+                    match tokio::time::timeout(QUERY_TIMEOUT, query_server(*server, &query)).await {
+                        Ok(Ok(r)) => {
+                            response = Some(r);
+                            break;
+                        }
+                        Ok(Err(e)) => last_error = Some(e.context(format!("server {server}"))),
+                        Err(_) => last_error = Some(anyhow::anyhow!("server {server} timed out")),
+                    }
+                }
+                match (response, last_error) {
+                    (Some(r), _) => r,
+                    (None, Some(e)) => return Err(e),
+                    (None, None) => bail!("no DNS servers"),
+                }
+            }
+            DnsConfig::DnsOverHttps(url) => self
+                .doh_client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/dns-message")
+                .header(reqwest::header::ACCEPT, "application/dns-message")
+                .body(query)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .context("DNS over HTTPS request failed")?
+                .bytes()
+                .await?
+                .to_vec(),
+        };
+        decode_response(id, &response)
+    }
+}
+
+// The TC bit, set when the answer didn't fit in a UDP datagram.
+fn is_truncated(response: &[u8]) -> bool {
+    response.get(2).map(|b| b & 0x02 != 0).unwrap_or(false)
+}
+
+// Over UDP, then over TCP if the answer was truncated.
+async fn query_server(server: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let response = query_udp(server, query).await?;
+    if !is_truncated(&response) {
+        return Ok(response);
+    }
+    debug!(%server, "DNS answer truncated, asking again over TCP");
+    query_tcp(server, query)
+        .await
+        .context("error querying over TCP")
+}
+
+// Messages over TCP are prefixed by their length, see RFC 1035 section 4.2.2.
+async fn query_tcp(server: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+    let mut msg = Vec::with_capacity(2 + query.len());
+    msg.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    msg.extend_from_slice(query);
+    stream.write_all(&msg).await?;
+    let len = stream.read_u16().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn query_udp(server: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+    let mut buf = vec![0u8; 4096];
+    let len = socket.recv(&mut buf).await?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+// Plugs DnsResolver into reqwest, for trackers, web seeds and torrent URLs.
+struct ReqwestResolver(Arc<DnsResolver>);
+
+impl reqwest::dns::Resolve for ReqwestResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(resolve_for_reqwest(self.0.clone(), name))
+    }
+}
+
+async fn resolve_for_reqwest(
+    resolver: Arc<DnsResolver>,
+    name: reqwest::dns::Name,
+) -> Result<reqwest::dns::Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let addrs = resolver.lookup(name.as_str()).await?;
+    // reqwest fills in the port.
+    Ok(Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0))))
+}
+
+/// An HTTP client builder that resolves hostnames with "dns", or with the system resolver
+/// if it's None.
+pub(crate) fn client_builder(dns: Option<&Arc<DnsResolver>>) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    match dns {
+        Some(dns) => builder.dns_resolver(Arc::new(ReqwestResolver(dns.clone()))),
+        None => builder,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Answer {
+    addrs: Vec<IpAddr>,
+    // The lowest TTL of the addresses, in seconds.
+    ttl: Option<u32>,
+}
+
+fn encode_query(id: u16, host: &str, qtype: u16) -> anyhow::Result<Vec<u8>> {
+    if host.is_empty() || host.len() > 253 {
+        bail!("invalid hostname {host:?}");
+    }
+    let mut msg = Vec::with_capacity(17 + host.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid hostname {host:?}");
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(buf.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+// Returns the position after the name at "pos".
+fn skip_name(buf: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *buf.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            // A compression pointer ends the name.
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l => pos += 1 + l,
+        }
+    }
+}
+
+fn decode_response(id: u16, buf: &[u8]) -> anyhow::Result<Answer> {
+    let malformed = || anyhow::anyhow!("malformed DNS response");
+    if read_u16(buf, 0).ok_or_else(malformed)? != id {
+        bail!("DNS response id doesn't match the query");
+    }
+    let flags = read_u16(buf, 2).ok_or_else(malformed)?;
+    if flags & 0x8000 == 0 {
+        bail!("expected a DNS response, got a query");
+    }
+    if flags & 0x0200 != 0 {
+        bail!("DNS response was truncated");
+    }
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NXDOMAIN => bail!("no such host"),
+        rcode => bail!("DNS server returned error code {rcode}"),
+    }
+    let questions = read_u16(buf, 4).ok_or_else(malformed)?;
+    let answers = read_u16(buf, 6).ok_or_else(malformed)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(buf, pos).ok_or_else(malformed)? + 4;
+    }
+    let mut answer = Answer {
+        addrs: Vec::new(),
+        ttl: None,
+    };
+    for _ in 0..answers {
+        pos = skip_name(buf, pos).ok_or_else(malformed)?;
+        let rtype = read_u16(buf, pos).ok_or_else(malformed)?;
+        let ttl = buf
+            .get(pos + 4..pos + 8)
+            .and_then(|b| b.try_into().ok())
+            .map(u32::from_be_bytes)
+            .ok_or_else(malformed)?;
+        let len = read_u16(buf, pos + 8).ok_or_else(malformed)? as usize;
+        let data = buf.get(pos + 10..pos + 10 + len).ok_or_else(malformed)?;
+        pos += 10 + len;
+        // Anything else, e.g. the CNAMEs leading to the addresses, is skipped.
+        let addr = match (rtype, data.len()) {
+            (TYPE_A, 4) => IpAddr::from(<[u8; 4]>::try_from(data)?),
+            (TYPE_AAAA, 16) => IpAddr::from(<[u8; 16]>::try_from(data)?),
+            _ => continue,
+        };
+        answer.addrs.push(addr);
+        answer.ttl = Some(answer.ttl.map_or(ttl, |t| t.min(ttl)));
+    }
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, UdpSocket},
+    };
+
+    use super::{decode_response, encode_query, query_server, Answer, TYPE_A};
+
+    // A response to the query, with the answer records appended.
+    fn response(query: &[u8], rcode: u8, answers: &[&[u8]]) -> Vec<u8> {
+        let mut r = query.to_vec();
+        r[2] = 0x81;
+        r[3] = 0x80 | rcode;
+        r[7] = answers.len() as u8;
+        for a in answers {
+            r.extend_from_slice(a);
+        }
+        r
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let q = encode_query(0x1234, "tracker.example", TYPE_A).unwrap();
+        assert_eq!(
+            q,
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+              \x07tracker\x07example\x00\x00\x01\x00\x01"
+        );
+        assert!(encode_query(1, "a..example", TYPE_A).is_err());
+        assert!(encode_query(1, &"a".repeat(64), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let q = encode_query(7, "tracker.example", TYPE_A).unwrap();
+        // "tracker.example" is a CNAME for "t.example", then comes the address of that. The
+        // owner names are compression pointers to the earlier names.
+        let cname: &[u8] = b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x0e\x10\x00\x0b\
+                             \x01t\x07example\x00";
+        let a: &[u8] = b"\xc0\x2d\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x0a\x00\x00\x01";
+        assert_eq!(
+            decode_response(7, &response(&q, 0, &[cname, a])).unwrap(),
+            Answer {
+                addrs: vec![IpAddr::from([10, 0, 0, 1])],
+                ttl: Some(300),
+            }
+        );
+
+        let e = decode_response(7, &response(&q, 3, &[])).unwrap_err();
+        assert_eq!(e.to_string(), "no such host");
+        assert!(decode_response(8, &response(&q, 0, &[a])).is_err());
+        // The answer is cut short.
+        assert!(decode_response(7, &response(&q, 0, &[&a[..14]])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_answer_is_asked_again_over_tcp() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server: SocketAddr = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(server).await.unwrap();
+        let a: &[u8] = b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x01\x2c\x00\x04\x0a\x00\x00\x01";
+        tokio::spawn(async move {
+            // Over UDP, only the question with the TC bit.
+            let mut buf = [0u8; 512];
+            let (len, from) = udp.recv_from(&mut buf).await.unwrap();
+            let mut truncated = response(&buf[..len], 0, &[]);
+            truncated[2] |= 0x02;
+            udp.send_to(&truncated, from).await.unwrap();
+
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap() as usize;
+            let mut query = vec![0u8; len];
+            stream.read_exact(&mut query).await.unwrap();
+            let full = response(&query, 0, &[a]);
+            stream.write_u16(full.len() as u16).await.unwrap();
+            stream.write_all(&full).await.unwrap();
+        });
+
+        let q = encode_query(9, "tracker.example", TYPE_A).unwrap();
+        let r = query_server(server, &q).await.unwrap();
+        assert_eq!(
+            decode_response(9, &r).unwrap().addrs,
+            vec![IpAddr::from([10, 0, 0, 1])]
+        );
+    }
+}
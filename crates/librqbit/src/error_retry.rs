@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::session::TorrentId;
+
+// How often the session looks for torrents in error.
+pub(crate) const ERROR_RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Restart torrents that fail to initialize, until the retries are used up, see
+/// [crate::SessionOptions::error_retry].
+#[derive(Debug, Clone, Default)]
+pub struct ErrorRetryOptions {
+    /// How many times to restart a torrent that failed to initialize before giving up on it.
+    /// With 0, it's given up on as soon as it fails.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubling with each one after, up to an hour.
+    /// Defaults to 30 seconds.
+    pub backoff: Option<Duration>,
+}
+
+// The wait before retry "attempt", counting from 0.
+fn retry_delay(backoff: Duration, attempt: u32) -> Duration {
+    backoff
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ErrorRetryAction {
+    // Restart it, this being the "attempt"th retry.
+    Retry { attempt: u32 },
+    // Give up after "attempts" retries. Only returned once, the torrent is left in error
+    // after.
+    GiveUp { attempts: u32 },
+}
+
+#[derive(Default)]
+struct RetryState {
+    attempts: u32,
+    // When the torrent was last seen going into error, None while a retry is in progress.
+    errored_since: Option<Instant>,
+    gave_up: bool,
+}
+
+// Counts the retries of each torrent. A torrent's count is only reset once it goes live, as
+// restarting a torrent that errors while initializing would otherwise never give up.
+pub(crate) struct ErrorRetryTracker {
+    retries: u32,
+    backoff: Duration,
+    torrents: HashMap<TorrentId, RetryState>,
+}
+
+impl ErrorRetryTracker {
+    pub fn new(opts: &ErrorRetryOptions) -> Self {
+        Self {
+            retries: opts.retries,
+            backoff: opts.backoff.unwrap_or(DEFAULT_BACKOFF),
+            torrents: HashMap::new(),
+        }
+    }
+
+    // Called for every torrent in error on each check.
+    pub fn on_errored(&mut self, id: TorrentId, now: Instant) -> Option<ErrorRetryAction> {
+        let state = self.torrents.entry(id).or_default();
+        if state.gave_up {
+            return None;
+        }
+        if state.attempts >= self.retries {
+            state.gave_up = true;
+            return Some(ErrorRetryAction::GiveUp {
+                attempts: state.attempts,
+            });
+        }
+        let since = *state.errored_since.get_or_insert(now);
+        if now.saturating_duration_since(since) < retry_delay(self.backoff, state.attempts) {
+            return None;
+        }
+        state.attempts += 1;
+        state.errored_since = None;
+        Some(ErrorRetryAction::Retry {
+            attempt: state.attempts,
+        })
+    }
+
+    pub fn on_live(&mut self, id: TorrentId) {
+        self.torrents.remove(&id);
+    }
+
+    // Drops torrents that aren't in the session anymore.
+    pub fn retain(&mut self, exists: impl Fn(TorrentId) -> bool) {
+        self.torrents.retain(|id, _| exists(*id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{retry_delay, ErrorRetryAction, ErrorRetryOptions, ErrorRetryTracker};
+
+    #[test]
+    fn test_retry_delay() {
+        let backoff = Duration::from_secs(30);
+        assert_eq!(retry_delay(backoff, 0), Duration::from_secs(30));
+        assert_eq!(retry_delay(backoff, 2), Duration::from_secs(120));
+        assert_eq!(retry_delay(backoff, 100), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_error_retry_tracker() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let mut tracker = ErrorRetryTracker::new(&ErrorRetryOptions {
+            retries: 2,
+            backoff: Some(Duration::from_secs(10)),
+        });
+
+        assert_eq!(tracker.on_errored(0, secs(0)), None);
+        assert_eq!(
+            tracker.on_errored(0, secs(10)),
+            Some(ErrorRetryAction::Retry { attempt: 1 })
+        );
+        // Errored again, the wait doubles.
+        assert_eq!(tracker.on_errored(0, secs(15)), None);
+        assert_eq!(tracker.on_errored(0, secs(34)), None);
+        assert_eq!(
+            tracker.on_errored(0, secs(35)),
+            Some(ErrorRetryAction::Retry { attempt: 2 })
+        );
+        assert_eq!(
+            tracker.on_errored(0, secs(36)),
+            Some(ErrorRetryAction::GiveUp { attempts: 2 })
+        );
+        // Left in error from then on.
+        assert_eq!(tracker.on_errored(0, secs(10000)), None);
+
+        // Going live resets the count.
+        assert_eq!(tracker.on_errored(1, secs(0)), None);
+        tracker.on_errored(1, secs(10));
+        tracker.on_live(1);
+        assert_eq!(tracker.on_errored(1, secs(20)), None);
+        assert_eq!(
+            tracker.on_errored(1, secs(30)),
+            Some(ErrorRetryAction::Retry { attempt: 1 })
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::bail;
+use librqbit_core::torrent_metainfo::TorrentMetaV1Info;
+use serde::{Deserialize, Serialize};
+
+/// What a torrent's metadata has to look like for it to be added, see
+/// [crate::AddTorrentOptions::expect]. Unset fields aren't checked.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExpectedMetadata {
+    /// The total size of all files, in bytes, is at least this.
+    pub min_total_bytes: Option<u64>,
+    /// The total size of all files, in bytes, is at most this.
+    pub max_total_bytes: Option<u64>,
+    /// The torrent has exactly this many files.
+    pub file_count: Option<usize>,
+}
+
+impl ExpectedMetadata {
+    pub(crate) fn check<B: AsRef<[u8]>>(&self, info: &TorrentMetaV1Info<B>) -> anyhow::Result<()> {
+        let (file_count, total_bytes) = info
+            .iter_file_lengths()?
+            .fold((0usize, 0u64), |(count, total), len| {
+                (count + 1, total + len)
+            });
+        if let Some(expected) = self.file_count {
+            if file_count != expected {
+                bail!("torrent has {file_count} files, expected {expected}");
+            }
+        }
+        if let Some(min) = self.min_total_bytes {
+            if total_bytes < min {
+                bail!("torrent is {total_bytes} bytes, expected at least {min}");
+            }
+        }
+        if let Some(max) = self.max_total_bytes {
+            if total_bytes > max {
+                bail!("torrent is {total_bytes} bytes, expected at most {max}");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffers::ByteString;
+    use librqbit_core::torrent_metainfo::{TorrentMetaV1File, TorrentMetaV1Info};
+
+    use super::ExpectedMetadata;
+
+    fn info(lengths: &[u64]) -> TorrentMetaV1Info<ByteString> {
+        TorrentMetaV1Info {
+            name: Some(b"t".to_vec().into()),
+            pieces: Vec::<u8>::new().into(),
+            piece_length: 16384,
+            length: None,
+            md5sum: None,
+            files: Some(
+                lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, len)| TorrentMetaV1File {
+                        length: *len,
+                        path: vec![format!("{i}").into_bytes().into()],
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_check_expected_metadata() {
+        let info = info(&[100, 200]);
+        let expect = |min, max, count| ExpectedMetadata {
+            min_total_bytes: min,
+            max_total_bytes: max,
+            file_count: count,
+        };
+        assert!(expect(None, None, None).check(&info).is_ok());
+        assert!(expect(Some(300), Some(300), Some(2)).check(&info).is_ok());
+        let e = expect(None, None, Some(3)).check(&info).unwrap_err();
+        assert_eq!(e.to_string(), "torrent has 2 files, expected 3");
+        let e = expect(Some(1000), None, None).check(&info).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "torrent is 300 bytes, expected at least 1000"
+        );
+        assert!(expect(None, Some(299), None).check(&info).is_err());
+    }
+}
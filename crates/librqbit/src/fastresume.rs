@@ -0,0 +1,254 @@
+// libtorrent ".fastresume" files, as written by qBittorrent, Deluge and other libtorrent
+// based clients. They let a torrent move between clients without re-hashing all its data.
+//
+// Only a small part of the format is supported:
+// - Written: the info hash, "pieces" (one byte per piece, 1 if we have it), "file_priority"
+//   (0 for skipped files, 4 for the rest), "save_path", the transfer totals and "paused".
+// - Read: the info hash, "pieces" and "file_priority". Everything else, e.g. "unfinished"
+//   partial pieces, peers, trackers, renamed files, and client-specific keys like qBittorrent's
+//   "qBt-*" ones, is ignored. The download location comes from the add options as usual.
+// - Only the lowest bit of each "pieces" byte, i.e. "have", is looked at.
+// - File priorities only decide whether a file is downloaded, all non-zero ones are the same.
+
+use anyhow::{bail, Context};
+use buffers::ByteString;
+use librqbit_core::{hash_id::Id20, lengths::Lengths, torrent_metainfo::TorrentMetaV1Info};
+use serde::{Deserialize, Serialize};
+
+use crate::{torrent_state::ManagedTorrent, type_aliases::BF};
+
+const FILE_FORMAT: &[u8] = b"libtorrent resume file";
+const FILE_VERSION: i64 = 1;
+// libtorrent's priority for files that are downloaded normally.
+const DEFAULT_FILE_PRIORITY: i64 = 4;
+
+#[derive(Serialize, Deserialize)]
+struct FastResumeFile {
+    #[serde(rename = "file-format")]
+    file_format: ByteString,
+    #[serde(rename = "file-version")]
+    file_version: i64,
+    #[serde(default)]
+    file_priority: Vec<i64>,
+    #[serde(rename = "info-hash")]
+    info_hash: ByteString,
+    #[serde(default)]
+    paused: i64,
+    pieces: ByteString,
+    #[serde(default)]
+    save_path: ByteString,
+    #[serde(default)]
+    total_downloaded: u64,
+    #[serde(default)]
+    total_uploaded: u64,
+}
+
+// What we take from a fastresume file when adding a torrent.
+#[derive(Debug)]
+pub(crate) struct FastResume {
+    // Pieces to take as downloaded without hashing them.
+    pub have_pieces: BF,
+    // Files with a non-zero priority, None if that's all of them.
+    pub only_files: Option<Vec<usize>>,
+}
+
+impl FastResume {
+    pub fn parse(
+        data: &[u8],
+        info_hash: Id20,
+        info: &TorrentMetaV1Info<ByteString>,
+    ) -> anyhow::Result<Self> {
+        let file: FastResumeFile =
+            bencode::from_bytes(data).context("error deserializing fastresume file")?;
+        if file.file_format.as_ref() != FILE_FORMAT {
+            bail!("not a libtorrent resume file");
+        }
+        if file.info_hash.as_ref() != info_hash.0.as_slice() {
+            bail!("fastresume file is for a different torrent");
+        }
+
+        let lengths = Lengths::from_torrent(info)?;
+        let total_pieces = lengths.total_pieces() as usize;
+        if file.pieces.as_ref().len() != total_pieces {
+            bail!(
+                "fastresume file has {} pieces, the torrent has {}",
+                file.pieces.as_ref().len(),
+                total_pieces
+            );
+        }
+        let mut have_pieces = BF::from_vec(vec![0u8; lengths.piece_bitfield_bytes()]);
+        for (idx, piece) in file.pieces.as_ref().iter().enumerate() {
+            have_pieces.set(idx, piece & 1 != 0);
+        }
+
+        let total_files = info.iter_file_lengths()?.count();
+        if file.file_priority.len() > total_files {
+            bail!(
+                "fastresume file has {} file priorities, the torrent has {} files",
+                file.file_priority.len(),
+                total_files
+            );
+        }
+        // Files missing from the list have the default priority.
+        let only_files = if file.file_priority.contains(&0) {
+            Some(
+                (0..total_files)
+                    .filter(|idx| file.file_priority.get(*idx) != Some(&0))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            have_pieces,
+            only_files,
+        })
+    }
+}
+
+impl ManagedTorrent {
+    /// Export the torrent's progress as a libtorrent ".fastresume" file, so that other clients
+    /// can pick it up without re-checking the data. Only works when the torrent is paused or
+    /// live. Only the piece bitfield, file priorities, save path, transfer totals and paused
+    /// flag are written, not partial pieces, peers or trackers.
+    pub fn export_fastresume(&self) -> anyhow::Result<Vec<u8>> {
+        let have = self.with_chunk_tracker(|ct| ct.get_have_pieces().clone())?;
+        let info = self.info();
+        let total_pieces = info.lengths.total_pieces() as usize;
+        let only_files = self.only_files();
+        let file_priority = (0..info.info.iter_file_lengths()?.count())
+            .map(|idx| match &only_files {
+                Some(only_files) if !only_files.contains(&idx) => 0,
+                _ => DEFAULT_FILE_PRIORITY,
+            })
+            .collect();
+        let transfer = self.lifetime_transfer();
+        let paused = self.with_state(|s| s.is_paused());
+
+        let file = FastResumeFile {
+            file_format: FILE_FORMAT.to_vec().into(),
+            file_version: FILE_VERSION,
+            file_priority,
+            info_hash: info.info_hash.0.to_vec().into(),
+            paused: paused as i64,
+            pieces: (0..total_pieces)
+                .map(|idx| have[idx] as u8)
+                .collect::<Vec<u8>>()
+                .into(),
+            save_path: save_path(
+                info.info.name.as_ref(),
+                &info.out_dir,
+                info.info.files.is_some(),
+            )
+            .into_bytes()
+            .into(),
+            total_downloaded: transfer.downloaded_bytes,
+            total_uploaded: transfer.uploaded_bytes,
+        };
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&file, &mut buf)
+            .context("error serializing fastresume file")?;
+        Ok(buf)
+    }
+}
+
+// libtorrent puts multi-file torrents into a folder named after the torrent inside
+// "save_path", which is how the default output folder is picked too.
+fn save_path(name: Option<&ByteString>, out_dir: &std::path::Path, multi_file: bool) -> String {
+    let parent = match (name, out_dir.file_name(), out_dir.parent()) {
+        (Some(name), Some(dir), Some(parent))
+            if multi_file && dir.to_str().map(str::as_bytes) == Some(name.as_ref()) =>
+        {
+            parent
+        }
+        _ => out_dir,
+    };
+    parent.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use buffers::ByteString;
+
+    use crate::{
+        session::AddTorrentOptions,
+        test_util::{add_test_torrent, test_data, test_dir, test_session, TEST_PIECE_LENGTH},
+    };
+
+    use super::{save_path, FastResume, FastResumeFile};
+
+    #[tokio::test]
+    async fn test_export_parse_roundtrip() {
+        let dir = test_dir("fastresume");
+        let session = test_session(&dir, Default::default()).await;
+        let a = test_data(TEST_PIECE_LENGTH as usize, 0);
+        let b = test_data(TEST_PIECE_LENGTH as usize, 1);
+        std::fs::create_dir_all(dir.join("t")).unwrap();
+        std::fs::write(dir.join("t/a"), &a).unwrap();
+        let (_, handle) = add_test_torrent(
+            &session,
+            "t",
+            &[("a", &a), ("b", &b)],
+            AddTorrentOptions {
+                only_files: Some(vec![0]),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let exported = handle.export_fastresume().unwrap();
+        let info = handle.info();
+        let parsed = FastResume::parse(&exported, info.info_hash, &info.info).unwrap();
+        assert!(parsed.have_pieces[0] && !parsed.have_pieces[1]);
+        assert_eq!(parsed.only_files, Some(vec![0]));
+
+        // Not for another torrent.
+        let mut other = info.info_hash;
+        other.0[0] ^= 1;
+        assert!(FastResume::parse(&exported, other, &info.info).is_err());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_serialized_keys_are_sorted() {
+        let file = FastResumeFile {
+            file_format: b"libtorrent resume file".to_vec().into(),
+            file_version: 1,
+            file_priority: vec![4, 0],
+            info_hash: vec![0x11; 20].into(),
+            paused: 0,
+            pieces: vec![1, 0, 1].into(),
+            save_path: b"/tmp".to_vec().into(),
+            total_downloaded: 2,
+            total_uploaded: 3,
+        };
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&file, &mut buf).unwrap();
+        let expected = [
+            b"d11:file-format22:libtorrent resume file12:file-versioni1e".as_slice(),
+            b"13:file_priorityli4ei0ee9:info-hash20:",
+            &[0x11; 20],
+            b"6:pausedi0e6:pieces3:\x01\x00\x019:save_path4:/tmp",
+            b"16:total_downloadedi2e14:total_uploadedi3ee",
+        ]
+        .concat();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_save_path() {
+        let name = ByteString::from(b"name".to_vec());
+        let out_dir = Path::new("/downloads/name");
+        assert_eq!(save_path(Some(&name), out_dir, true), "/downloads");
+        assert_eq!(save_path(Some(&name), out_dir, false), "/downloads/name");
+        assert_eq!(
+            save_path(Some(&name), Path::new("/elsewhere"), true),
+            "/elsewhere"
+        );
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
+    collections::BTreeSet,
     fs::File,
     io::{Read, Seek, SeekFrom, Write},
     marker::PhantomData,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use anyhow::Context;
@@ -21,6 +24,51 @@ use tracing::{debug, trace, warn};
 
 use crate::type_aliases::{PeerHandle, BF};
 
+/// Controls when downloaded data is fsynced to disk.
+///
+/// On startup every torrent goes through the initial check, which re-hashes all
+/// data on disk, so losing unsynced data on a crash never results in corrupt
+/// pieces being treated as valid. The only cost of syncing less often is having to
+/// re-download whatever didn't make it to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Never fsync explicitly, leave it to the OS. This is the fastest option, but on
+    /// a crash or power loss everything the OS hadn't written back yet is lost.
+    #[default]
+    Never,
+    /// Fsync the files a piece touches as soon as the piece is verified. The safest
+    /// option, but it issues an fsync per piece, which is slow on spinning disks.
+    OnPieceComplete,
+    /// Fsync all files once the torrent finishes downloading. A crash while
+    /// downloading may lose recent pieces, but completed torrents are durable.
+    OnTorrentComplete,
+    /// Fsync all files of live torrents periodically. A crash loses at most
+    /// roughly one interval worth of downloaded data.
+    Interval(Duration),
+}
+
+impl FsyncPolicy {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if *self == FsyncPolicy::Interval(Duration::ZERO) {
+            anyhow::bail!("the fsync interval must be longer than 0");
+        }
+        Ok(())
+    }
+}
+
+// Calls "sync" every "interval" until it returns false, see FsyncPolicy::Interval.
+pub(crate) async fn sync_every(
+    interval: Duration,
+    mut sync: impl FnMut() -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::time::sleep(interval).await;
+        if !sync()? {
+            return Ok(());
+        }
+    }
+}
+
 pub(crate) struct InitialCheckResults {
     // The pieces that we need to download.
     pub needed_pieces: BF,
@@ -54,10 +102,80 @@ pub fn update_hash_from_file<Sha1: ISha1>(
     Ok(())
 }
 
+/// Bytes read from and written to disk for torrent data, not counting the initial check.
+#[derive(Default, Debug)]
+pub(crate) struct DiskIoCounters {
+    pub read_bytes: AtomicU64,
+    pub written_bytes: AtomicU64,
+}
+
+struct FileRange<'a> {
+    file_idx: usize,
+    name: FileIteratorName<'a, ByteString>,
+    // Offset within the file.
+    offset: u64,
+    len: u64,
+}
+
+#[cfg(unix)]
+type ReadJob = Box<dyn FnOnce() + Send>;
+
+// The threads check_piece_parallel() reads on, shared by all torrents and kept between
+// pieces instead of started for each one. There are as many as the most parallel reads asked
+// for.
+#[cfg(unix)]
+struct ReadPool {
+    jobs: Mutex<std::sync::mpsc::Sender<ReadJob>>,
+    queue: Arc<Mutex<std::sync::mpsc::Receiver<ReadJob>>>,
+    threads: Mutex<usize>,
+}
+
+#[cfg(unix)]
+impl ReadPool {
+    fn get() -> &'static ReadPool {
+        static POOL: std::sync::OnceLock<ReadPool> = std::sync::OnceLock::new();
+        POOL.get_or_init(|| {
+            let (jobs, queue) = std::sync::mpsc::channel();
+            ReadPool {
+                jobs: Mutex::new(jobs),
+                queue: Arc::new(Mutex::new(queue)),
+                threads: Mutex::new(0),
+            }
+        })
+    }
+
+    fn run(&self, threads: usize, jobs: Vec<ReadJob>) -> anyhow::Result<()> {
+        {
+            let mut started = self.threads.lock();
+            while *started < threads {
+                let queue = self.queue.clone();
+                std::thread::Builder::new()
+                    .name("piece-read".to_owned())
+                    .spawn(move || loop {
+                        let job = match queue.lock().recv() {
+                            Ok(job) => job,
+                            Err(_) => return,
+                        };
+                        // A panicking read only loses its result, not the thread.
+                        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+                    })
+                    .context("error starting a piece read thread")?;
+                *started += 1;
+            }
+        }
+        let tx = self.jobs.lock();
+        for job in jobs {
+            tx.send(job).context("bug: piece read threads are gone")?;
+        }
+        Ok(())
+    }
+}
+
 pub(crate) struct FileOps<'a, Sha1> {
     torrent: &'a TorrentMetaV1Info<ByteString>,
     files: &'a [Arc<Mutex<File>>],
     lengths: &'a Lengths,
+    io_counters: Option<&'a DiskIoCounters>,
     phantom_data: PhantomData<Sha1>,
 }
 
@@ -71,14 +189,35 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
             torrent,
             files,
             lengths,
+            io_counters: None,
             phantom_data: PhantomData,
         }
     }
 
+    pub fn with_io_counters(mut self, io_counters: &'a DiskIoCounters) -> Self {
+        self.io_counters = Some(io_counters);
+        self
+    }
+
+    fn count_read(&self, bytes: u64) {
+        if let Some(c) = self.io_counters {
+            c.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn count_written(&self, bytes: u64) {
+        if let Some(c) = self.io_counters {
+            c.written_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Hash all the pieces on disk. Once "cancel" is set, the pieces not hashed yet are taken
+    /// as not downloaded, so what's returned only has the pieces verified until then.
     pub fn initial_check(
         &self,
         only_files: Option<&[usize]>,
         progress: &AtomicU64,
+        cancel: &AtomicBool,
     ) -> anyhow::Result<InitialCheckResults> {
         let mut needed_pieces = BF::from_vec(vec![0u8; self.lengths.piece_bitfield_bytes()]);
         let mut have_pieces = BF::from_vec(vec![0u8; self.lengths.piece_bitfield_bytes()]);
@@ -138,7 +277,10 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
             let mut piece_remaining = piece_info.len as usize;
             let mut some_files_broken = false;
             let mut at_least_one_file_required = current_file.full_file_required;
-            progress.fetch_add(piece_info.len as u64, Ordering::Relaxed);
+            let cancelled = cancel.load(Ordering::Relaxed);
+            if !cancelled {
+                progress.fetch_add(piece_info.len as u64, Ordering::Relaxed);
+            }
 
             while piece_remaining > 0 {
                 let mut to_read_in_file =
@@ -160,7 +302,7 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
                 piece_remaining -= to_read_in_file;
                 current_file.mark_processed_bytes(to_read_in_file as u64);
 
-                if current_file.is_broken {
+                if current_file.is_broken || cancelled {
                     // no need to read.
                     continue;
                 }
@@ -199,6 +341,14 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
                 continue;
             }
 
+            if cancelled {
+                if at_least_one_file_required {
+                    needed_bytes += piece_info.len as u64;
+                    needed_pieces.set(piece_info.piece_index.get() as usize, true);
+                }
+                continue;
+            }
+
             if self
                 .torrent
                 .compare_hash(piece_info.piece_index.get(), computed_hash.finish())
@@ -234,60 +384,168 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
         })
     }
 
+    /// Like initial_check(), but takes the "trusted_have" pieces as downloaded instead of
+    /// hashing them, e.g. when they come from a fastresume file. Pieces that don't fit into
+    /// the files as they are on disk are needed anyway.
+    pub fn trusted_check(
+        &self,
+        only_files: Option<&[usize]>,
+        trusted_have: &BF,
+        progress: &AtomicU64,
+    ) -> anyhow::Result<InitialCheckResults> {
+        let mut needed_pieces = BF::from_vec(vec![0u8; self.lengths.piece_bitfield_bytes()]);
+        let mut have_pieces = BF::from_vec(vec![0u8; self.lengths.piece_bitfield_bytes()]);
+
+        let mut have_bytes = 0u64;
+        let mut needed_bytes = 0u64;
+        let mut total_selected_bytes = 0u64;
+
+        let file_lengths = self
+            .files
+            .iter()
+            .map(|f| Ok(f.lock().metadata()?.len()))
+            .collect::<anyhow::Result<Vec<u64>>>()?;
+
+        for piece_info in self.lengths.iter_piece_infos() {
+            let idx = piece_info.piece_index.get() as usize;
+            let len = piece_info.len as u64;
+            progress.fetch_add(len, Ordering::Relaxed);
+
+            let mut required = false;
+            let mut on_disk = true;
+            for r in self.file_ranges(self.lengths.piece_offset(piece_info.piece_index), len)? {
+                required |= only_files.map(|o| o.contains(&r.file_idx)).unwrap_or(true);
+                on_disk &= file_lengths[r.file_idx] >= r.offset + r.len;
+            }
+
+            if required {
+                total_selected_bytes += len;
+            }
+            if on_disk && trusted_have.get(idx).map(|b| *b).unwrap_or(false) {
+                have_bytes += len;
+                have_pieces.set(idx, true);
+            } else if required {
+                trace!(
+                    "piece {} is not in the trusted data, marking as needed",
+                    idx
+                );
+                needed_bytes += len;
+                needed_pieces.set(idx, true);
+            }
+        }
+
+        Ok(InitialCheckResults {
+            needed_pieces,
+            have_pieces,
+            have_bytes,
+            needed_bytes,
+            total_selected_bytes,
+        })
+    }
+
+    /// Map a byte range of the whole torrent to the byte ranges of the files it covers.
+    ///
+    /// A piece or chunk may straddle several files. Files it has no bytes in, including
+    /// zero-length files at the boundary, are skipped, so nothing is read from or written to
+    /// them. They still exist: the initial check creates every file up front.
+    fn file_ranges(
+        &self,
+        absolute_offset: u64,
+        len: u64,
+    ) -> anyhow::Result<impl Iterator<Item = FileRange<'a>> + 'a> {
+        let end = absolute_offset + len;
+        let mut file_start = 0u64;
+        Ok(self
+            .torrent
+            .iter_filenames_and_lengths()?
+            .enumerate()
+            .map(move |(file_idx, (name, file_len))| {
+                let start = file_start;
+                file_start += file_len;
+                (file_idx, name, start, file_len)
+            })
+            .take_while(move |(_, _, start, _)| *start < end)
+            .filter_map(move |(file_idx, name, start, file_len)| {
+                let from = std::cmp::max(absolute_offset, start);
+                let to = std::cmp::min(end, start + file_len);
+                if from >= to {
+                    return None;
+                }
+                Some(FileRange {
+                    file_idx,
+                    name,
+                    offset: from - start,
+                    len: to - from,
+                })
+            }))
+    }
+
     pub fn check_piece(
         &self,
         who_sent: PeerHandle,
         piece_index: ValidPieceIndex,
         last_received_chunk: &ChunkInfo,
     ) -> anyhow::Result<bool> {
+        trace!(
+            "piece={}, handle={}, checking. Last received chunk: {:?}",
+            piece_index,
+            who_sent,
+            &last_received_chunk
+        );
+        let hash = self.hash_piece(piece_index)?;
+        self.compare_piece_hash(piece_index, hash)
+    }
+
+    /// Whether the piece in these files is right, without logging a mismatch, e.g. to find
+    /// what a mirror is missing, see [crate::AddTorrentOptions::mirror_paths].
+    pub fn piece_matches(&self, piece_index: ValidPieceIndex) -> anyhow::Result<bool> {
+        let hash = self.hash_piece(piece_index)?;
+        Ok(self.torrent.compare_hash(piece_index.get(), hash) == Some(true))
+    }
+
+    fn hash_piece(&self, piece_index: ValidPieceIndex) -> anyhow::Result<[u8; 20]> {
         let mut h = Sha1Impl::new();
         let piece_length = self.lengths.piece_length(piece_index);
-        let mut absolute_offset = self.lengths.piece_offset(piece_index);
+        let absolute_offset = self.lengths.piece_offset(piece_index);
         let mut buf = vec![0u8; std::cmp::min(65536, piece_length as usize)];
 
-        let mut piece_remaining_bytes = piece_length as usize;
-
-        for (file_idx, (name, file_len)) in self.torrent.iter_filenames_and_lengths()?.enumerate() {
-            if absolute_offset > file_len {
-                absolute_offset -= file_len;
-                continue;
-            }
-            let file_remaining_len = file_len - absolute_offset;
+        let mut piece_remaining_bytes = piece_length as u64;
 
-            let to_read_in_file =
-                std::cmp::min(file_remaining_len, piece_remaining_bytes as u64) as usize;
-            let mut file_g = self.files[file_idx].lock();
-            trace!(
-                "piece={}, handle={}, file_idx={}, seeking to {}. Last received chunk: {:?}",
-                piece_index,
-                who_sent,
+        for range in self.file_ranges(absolute_offset, piece_length as u64)? {
+            let FileRange {
                 file_idx,
-                absolute_offset,
-                &last_received_chunk
-            );
+                name,
+                offset,
+                len,
+            } = range;
+            let mut file_g = self.files[file_idx].lock();
             file_g
-                .seek(SeekFrom::Start(absolute_offset))
-                .with_context(|| {
-                    format!("error seeking to {absolute_offset}, file id: {file_idx}")
-                })?;
-            update_hash_from_file(&mut file_g, &mut h, &mut buf, to_read_in_file).with_context(
-                || {
-                    format!(
-                        "error reading {to_read_in_file} bytes, file_id: {file_idx} (\"{name:?}\")"
-                    )
-                },
+                .seek(SeekFrom::Start(offset))
+                .with_context(|| format!("error seeking to {offset}, file id: {file_idx}"))?;
+            update_hash_from_file(&mut file_g, &mut h, &mut buf, len as usize).with_context(
+                || format!("error reading {len} bytes, file_id: {file_idx} (\"{name:?}\")"),
             )?;
+            self.count_read(len);
+            piece_remaining_bytes -= len;
+        }
 
-            piece_remaining_bytes -= to_read_in_file;
-
-            if piece_remaining_bytes == 0 {
-                return Ok(true);
-            }
-
-            absolute_offset = 0;
+        if piece_remaining_bytes > 0 {
+            anyhow::bail!(
+                "bug: piece={} extends {} bytes past the last file",
+                piece_index,
+                piece_remaining_bytes
+            );
         }
 
-        match self.torrent.compare_hash(piece_index.get(), h.finish()) {
+        Ok(h.finish())
+    }
+
+    fn compare_piece_hash(
+        &self,
+        piece_index: ValidPieceIndex,
+        hash: [u8; 20],
+    ) -> anyhow::Result<bool> {
+        match self.torrent.compare_hash(piece_index.get(), hash) {
             Some(true) => {
                 trace!("piece={} hash matches", piece_index);
                 Ok(true)
@@ -304,6 +562,109 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
         }
     }
 
+    /// Like check_piece(), but the piece is read into memory with up to "parallel_reads"
+    /// reads in flight, e.g. for network filesystems where each read mostly waits. The
+    /// reads are done on handles of their own, positioned with pread, so they don't move
+    /// the shared file position. They run on threads kept for the next pieces. Pieces with
+    /// less than a chunk per read are checked like check_piece() does.
+    #[cfg(unix)]
+    pub fn check_piece_parallel(
+        &self,
+        who_sent: PeerHandle,
+        piece_index: ValidPieceIndex,
+        last_received_chunk: &ChunkInfo,
+        parallel_reads: usize,
+    ) -> anyhow::Result<bool> {
+        use librqbit_core::{constants::CHUNK_SIZE, lengths::ceil_div_u64};
+        use std::os::unix::fs::FileExt;
+
+        let piece_length = self.lengths.piece_length(piece_index) as usize;
+        let absolute_offset = self.lengths.piece_offset(piece_index);
+        let chunks = ceil_div_u64(piece_length as u64, CHUNK_SIZE as u64);
+        if parallel_reads <= 1 || chunks < parallel_reads as u64 {
+            return self.check_piece(who_sent, piece_index, last_received_chunk);
+        }
+        // Each read covers a run of whole chunks, up to the end of its file.
+        let read_len = (ceil_div_u64(chunks, parallel_reads as u64) * CHUNK_SIZE as u64) as usize;
+        let (results_tx, results_rx) = std::sync::mpsc::channel();
+        let mut reads: Vec<ReadJob> = Vec::new();
+        let mut start = 0;
+        for range in self.file_ranges(absolute_offset, piece_length as u64)? {
+            let file = self.files[range.file_idx]
+                .lock()
+                .try_clone()
+                .with_context(|| format!("error opening file {}", range.file_idx))?;
+            let file = Arc::new(file);
+            let file_idx = range.file_idx;
+            let mut offset = range.offset;
+            let mut len = range.len as usize;
+            while len > 0 {
+                let this_len = std::cmp::min(len, read_len);
+                let (file, results_tx) = (file.clone(), results_tx.clone());
+                reads.push(Box::new(move || {
+                    let mut buf = vec![0u8; this_len];
+                    let res = file.read_exact_at(&mut buf, offset).with_context(|| {
+                        format!("error reading {this_len} bytes at {offset}, file_id: {file_idx}")
+                    });
+                    let _ = results_tx.send((start, res.map(|_| buf)));
+                }));
+                start += this_len;
+                offset += this_len as u64;
+                len -= this_len;
+            }
+        }
+        drop(results_tx);
+        if start < piece_length {
+            anyhow::bail!(
+                "bug: piece={} extends {} bytes past the last file",
+                piece_index,
+                piece_length - start
+            );
+        }
+
+        let count = reads.len();
+        ReadPool::get().run(parallel_reads, reads)?;
+        let mut data = vec![0u8; piece_length];
+        for _ in 0..count {
+            let (start, buf) = results_rx.recv().context("bug: a piece read was dropped")?;
+            let buf = buf?;
+            data[start..start + buf.len()].copy_from_slice(&buf);
+        }
+        self.count_read(piece_length as u64);
+        self.check_piece_data(piece_index, &data)
+    }
+
+    #[cfg(not(unix))]
+    pub fn check_piece_parallel(
+        &self,
+        who_sent: PeerHandle,
+        piece_index: ValidPieceIndex,
+        last_received_chunk: &ChunkInfo,
+        _parallel_reads: usize,
+    ) -> anyhow::Result<bool> {
+        self.check_piece(who_sent, piece_index, last_received_chunk)
+    }
+
+    /// Like check_piece(), but hashes the given data instead of reading the piece from disk.
+    pub fn check_piece_data(
+        &self,
+        piece_index: ValidPieceIndex,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        let piece_length = self.lengths.piece_length(piece_index) as usize;
+        if data.len() != piece_length {
+            anyhow::bail!(
+                "bug: got {} bytes for piece={} of length {}",
+                data.len(),
+                piece_index,
+                piece_length
+            );
+        }
+        let mut h = Sha1Impl::new();
+        h.update(data);
+        self.compare_piece_hash(piece_index, h.finish())
+    }
+
     pub fn read_chunk(
         &self,
         who_sent: PeerHandle,
@@ -313,46 +674,60 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
         if result_buf.len() < chunk_info.size as usize {
             anyhow::bail!("read_chunk(): not enough capacity in the provided buffer")
         }
-        let mut absolute_offset = self.lengths.chunk_absolute_offset(chunk_info);
-        let mut buf = result_buf;
-
-        for (file_idx, file_len) in self.torrent.iter_file_lengths()?.enumerate() {
-            if absolute_offset > file_len {
-                absolute_offset -= file_len;
-                continue;
-            }
-            let file_remaining_len = file_len - absolute_offset;
-            let to_read_in_file = std::cmp::min(file_remaining_len, buf.len() as u64) as usize;
+        let absolute_offset = self.lengths.chunk_absolute_offset(chunk_info);
+        let mut buf = &mut result_buf[..chunk_info.size as usize];
 
+        for range in self.file_ranges(absolute_offset, chunk_info.size as u64)? {
+            let FileRange {
+                file_idx,
+                offset,
+                len,
+                ..
+            } = range;
             let mut file_g = self.files[file_idx].lock();
             trace!(
                 "piece={}, handle={}, file_idx={}, seeking to {}. To read chunk: {:?}",
                 chunk_info.piece_index,
                 who_sent,
                 file_idx,
-                absolute_offset,
+                offset,
                 &chunk_info
             );
             file_g
-                .seek(SeekFrom::Start(absolute_offset))
-                .with_context(|| {
-                    format!("error seeking to {absolute_offset}, file id: {file_idx}")
-                })?;
+                .seek(SeekFrom::Start(offset))
+                .with_context(|| format!("error seeking to {offset}, file id: {file_idx}"))?;
+            let (to_read, rest) = buf.split_at_mut(len as usize);
             file_g
-                .read_exact(&mut buf[..to_read_in_file])
-                .with_context(|| {
-                    format!("error reading {file_idx} bytes, file_id: {to_read_in_file}")
-                })?;
+                .read_exact(to_read)
+                .with_context(|| format!("error reading {len} bytes, file_id: {file_idx}"))?;
+            self.count_read(len);
+            buf = rest;
+        }
 
-            buf = &mut buf[to_read_in_file..];
+        Ok(())
+    }
 
-            if buf.is_empty() {
-                break;
-            }
+    /// Fsync all files that the given piece touches.
+    pub fn sync_piece(&self, piece_index: ValidPieceIndex) -> anyhow::Result<()> {
+        let absolute_offset = self.lengths.piece_offset(piece_index);
+        let piece_length = self.lengths.piece_length(piece_index) as u64;
 
-            absolute_offset = 0;
+        for FileRange { file_idx, name, .. } in self.file_ranges(absolute_offset, piece_length)? {
+            self.files[file_idx]
+                .lock()
+                .sync_data()
+                .with_context(|| format!("error syncing file {file_idx} (\"{name:?}\")"))?;
         }
+        Ok(())
+    }
 
+    /// Fsync all files of the torrent.
+    pub fn sync_all(&self) -> anyhow::Result<()> {
+        for (file_idx, file) in self.files.iter().enumerate() {
+            file.lock()
+                .sync_data()
+                .with_context(|| format!("error syncing file {file_idx}"))?;
+        }
         Ok(())
     }
 
@@ -365,45 +740,660 @@ impl<'a, Sha1Impl: ISha1> FileOps<'a, Sha1Impl> {
     where
         ByteBuf: AsRef<[u8]>,
     {
-        let mut buf = data.block.as_ref();
-        let mut absolute_offset = self.lengths.chunk_absolute_offset(chunk_info);
+        trace!(
+            "piece={}, chunk={:?}, handle={}, begin={}, writing {} bytes",
+            chunk_info.piece_index,
+            chunk_info,
+            who_sent,
+            chunk_info.offset,
+            data.block.as_ref().len()
+        );
+        let absolute_offset = self.lengths.chunk_absolute_offset(chunk_info);
+        self.write_at(absolute_offset, data.block.as_ref())
+    }
 
-        for (file_idx, (name, file_len)) in self.torrent.iter_filenames_and_lengths()?.enumerate() {
-            if absolute_offset > file_len {
-                absolute_offset -= file_len;
-                continue;
-            }
+    /// Write a whole piece at once.
+    pub fn write_piece(
+        &self,
+        who_sent: PeerHandle,
+        piece_index: ValidPieceIndex,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        trace!(
+            "piece={}, handle={}, writing {} bytes",
+            piece_index,
+            who_sent,
+            data.len()
+        );
+        self.write_at(self.lengths.piece_offset(piece_index), data)
+    }
 
-            let remaining_len = file_len - absolute_offset;
-            let to_write = std::cmp::min(buf.len(), remaining_len as usize);
+    /// Copy a piece to the same place in another set of files laid out like these, e.g. a
+    /// mirror of the torrent, see [crate::AddTorrentOptions::mirror_paths].
+    pub fn copy_piece_to(
+        &self,
+        piece_index: ValidPieceIndex,
+        dst_files: &[Arc<Mutex<File>>],
+    ) -> anyhow::Result<()> {
+        let absolute_offset = self.lengths.piece_offset(piece_index);
+        let piece_length = self.lengths.piece_length(piece_index) as u64;
+        let mut buf = vec![0u8; std::cmp::min(65536, piece_length as usize)];
 
-            let mut file_g = self.files[file_idx].lock();
-            trace!(
-                "piece={}, chunk={:?}, handle={}, begin={}, file={}, writing {} bytes at {}",
-                chunk_info.piece_index,
-                chunk_info,
-                who_sent,
-                chunk_info.offset,
+        for range in self.file_ranges(absolute_offset, piece_length)? {
+            let FileRange {
                 file_idx,
-                to_write,
-                absolute_offset
-            );
-            file_g
-                .seek(SeekFrom::Start(absolute_offset))
-                .with_context(|| {
-                    format!("error seeking to {absolute_offset} in file {file_idx} (\"{name:?}\")")
+                name,
+                offset,
+                len,
+            } = range;
+            let dst = dst_files
+                .get(file_idx)
+                .with_context(|| format!("bug: no file {file_idx} to copy to"))?;
+            let mut src_g = self.files[file_idx].lock();
+            let mut dst_g = dst.lock();
+            src_g
+                .seek(SeekFrom::Start(offset))
+                .with_context(|| format!("error seeking to {offset}, file id: {file_idx}"))?;
+            dst_g.seek(SeekFrom::Start(offset)).with_context(|| {
+                format!("error seeking to {offset} in the copy of file {file_idx}")
+            })?;
+            let mut remaining = len as usize;
+            while remaining > 0 {
+                let chunk = std::cmp::min(buf.len(), remaining);
+                src_g
+                    .read_exact(&mut buf[..chunk])
+                    .with_context(|| format!("error reading file {file_idx} (\"{name:?}\")"))?;
+                dst_g.write_all(&buf[..chunk]).with_context(|| {
+                    format!("error writing the copy of file {file_idx} (\"{name:?}\")")
                 })?;
+                remaining -= chunk;
+            }
+            self.count_read(len);
+            self.count_written(len);
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, absolute_offset: u64, mut buf: &[u8]) -> anyhow::Result<()> {
+        for range in self.file_ranges(absolute_offset, buf.len() as u64)? {
+            let FileRange {
+                file_idx,
+                name,
+                offset,
+                len,
+            } = range;
+            let mut file_g = self.files[file_idx].lock();
+            trace!("file={}, writing {} bytes at {}", file_idx, len, offset);
+            file_g.seek(SeekFrom::Start(offset)).with_context(|| {
+                format!("error seeking to {offset} in file {file_idx} (\"{name:?}\")")
+            })?;
+            let (to_write, rest) = buf.split_at(len as usize);
             file_g
-                .write_all(&buf[..to_write])
+                .write_all(to_write)
                 .with_context(|| format!("error writing to file {file_idx} (\"{name:?}\")"))?;
-            buf = &buf[to_write..];
-            if buf.is_empty() {
-                break;
+            self.count_written(len);
+            buf = rest;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn is_read_only_fs(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(rustix::io::Errno::ROFS.raw_os_error())
+}
+
+#[cfg(not(unix))]
+fn is_read_only_fs(_e: &std::io::Error) -> bool {
+    false
+}
+
+/// Whether "e" came from writing to a filesystem mounted read-only (EROFS), e.g. one the
+/// kernel remounted after errors on a failing drive. Retrying won't help there.
+pub(crate) fn is_read_only_fs_error(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(is_read_only_fs)
+}
+
+// Makes the error stopping a torrent say it's the read-only filesystem, and counts it.
+pub(crate) fn tag_read_only_fs_error(e: anyhow::Error, counter: &AtomicU64) -> anyhow::Error {
+    if !is_read_only_fs_error(&e) {
+        return e;
+    }
+    counter.fetch_add(1, Ordering::Relaxed);
+    e.context("read-only filesystem, resume the torrent once the disk is writable again")
+}
+
+/// Remove the directories of "files" below "root" that are empty once the files are gone,
+/// deepest first. "root" itself is kept, and so is any directory with something else left in
+/// it, e.g. files of another torrent sharing the output folder.
+pub(crate) fn remove_empty_dirs(root: &Path, files: &[PathBuf]) {
+    let dirs: BTreeSet<&Path> = files
+        .iter()
+        .flat_map(|f| {
+            f.ancestors()
+                .skip(1)
+                .take_while(|d| *d != root && d.starts_with(root))
+        })
+        .collect();
+    let mut dirs: Vec<&Path> = dirs.into_iter().collect();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    for dir in dirs {
+        // This fails on directories that aren't empty, so nothing but empty ones go away.
+        match std::fs::remove_dir(dir) {
+            Ok(()) => debug!(?dir, "removed empty directory"),
+            Err(e) => debug!(?dir, error=?e, "not removing directory"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{Read, Seek, SeekFrom, Write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc,
+        },
+    };
+
+    use buffers::ByteString;
+    use librqbit_core::{
+        lengths::Lengths,
+        torrent_metainfo::{TorrentMetaV1File, TorrentMetaV1Info},
+    };
+    use parking_lot::Mutex;
+    use peer_binary_protocol::Piece;
+    use sha1w::{ISha1, Sha1};
+
+    use super::{sync_every, tag_read_only_fs_error, DiskIoCounters, FileOps, FsyncPolicy};
+    use crate::type_aliases::BF;
+
+    const PIECE_LENGTH: u32 = 16384;
+    // The piece length doesn't divide any of these, and there's an empty file right at
+    // a piece boundary: piece 1 covers the end of "a", all of "b", and the start of "d".
+    const FILE_LENGTHS: [u64; 4] = [20000, 10000, 0, 7000];
+
+    struct TestTorrent {
+        dir: PathBuf,
+        info: TorrentMetaV1Info<ByteString>,
+        lengths: Lengths,
+        files: Vec<Arc<Mutex<File>>>,
+        data: Vec<u8>,
+    }
+
+    impl TestTorrent {
+        fn new() -> Self {
+            Self::with_layout(&std::env::temp_dir(), PIECE_LENGTH, &FILE_LENGTHS)
+        }
+
+        // Files are named "a", "b" and so on.
+        fn with_layout(parent: &std::path::Path, piece_length: u32, file_lengths: &[u64]) -> Self {
+            let total: u64 = file_lengths.iter().sum();
+            let data = (0..total).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+            let pieces = data
+                .chunks(piece_length as usize)
+                .flat_map(|piece| {
+                    let mut h = Sha1::new();
+                    h.update(piece);
+                    h.finish()
+                })
+                .collect::<Vec<u8>>();
+            let names: Vec<String> = (0..file_lengths.len())
+                .map(|i| ((b'a' + i as u8) as char).to_string())
+                .collect();
+            let info = TorrentMetaV1Info {
+                name: Some(ByteString::from(b"test".as_slice())),
+                pieces: ByteString(pieces),
+                piece_length,
+                length: None,
+                md5sum: None,
+                files: Some(
+                    names
+                        .iter()
+                        .zip(file_lengths.iter().copied())
+                        .map(|(name, length)| TorrentMetaV1File {
+                            length,
+                            path: vec![ByteString::from(name.as_bytes())],
+                        })
+                        .collect(),
+                ),
+            };
+            let lengths = Lengths::from_torrent(&info).unwrap();
+
+            let dir = parent.join(format!("librqbit-file-ops-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let files = names
+                .iter()
+                .map(|name| {
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .read(true)
+                        .write(true)
+                        .open(dir.join(name))
+                        .unwrap();
+                    Arc::new(Mutex::new(file))
+                })
+                .collect();
+            Self {
+                dir,
+                info,
+                lengths,
+                files,
+                data,
             }
+        }
 
-            absolute_offset = 0;
+        fn file_ops(&self) -> FileOps<'_, Sha1> {
+            FileOps::new(&self.info, &self.files, &self.lengths)
         }
 
-        Ok(())
+        fn write_all_pieces(&self) {
+            let addr = "127.0.0.1:1".parse().unwrap();
+            // Write out of order on purpose.
+            for piece in self
+                .lengths
+                .iter_piece_infos()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+            {
+                for chunk in self.lengths.iter_chunk_infos(piece.piece_index) {
+                    let offset = self.lengths.chunk_absolute_offset(&chunk) as usize;
+                    let block = &self.data[offset..offset + chunk.size as usize];
+                    let data = Piece {
+                        index: chunk.piece_index.get(),
+                        begin: chunk.offset,
+                        block,
+                    };
+                    self.file_ops().write_chunk(addr, &data, &chunk).unwrap();
+                }
+            }
+        }
+
+        fn file_contents(&self, name: &str) -> Vec<u8> {
+            let mut buf = Vec::new();
+            File::open(self.dir.join(name))
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            buf
+        }
+    }
+
+    impl Drop for TestTorrent {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn test_straddling_pieces_write_into_all_files() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+
+        let mut offset = 0;
+        for (name, len) in ["a", "b", "c", "d"].iter().zip(FILE_LENGTHS) {
+            let expected = &t.data[offset..offset + len as usize];
+            assert_eq!(t.file_contents(name), expected, "file {name}");
+            offset += len as usize;
+        }
+    }
+
+    #[test]
+    fn test_copy_piece_to_mirror() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+
+        let mirror_dir = t.dir.join("mirror");
+        std::fs::create_dir_all(&mirror_dir).unwrap();
+        let names = ["a", "b", "c", "d"];
+        let mirror = names
+            .iter()
+            .map(|name| {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .read(true)
+                    .write(true)
+                    .open(mirror_dir.join(name))
+                    .unwrap();
+                Arc::new(Mutex::new(file))
+            })
+            .collect::<Vec<_>>();
+        for piece in t.lengths.iter_piece_infos() {
+            t.file_ops()
+                .copy_piece_to(piece.piece_index, &mirror)
+                .unwrap();
+        }
+
+        let mut buf = Vec::new();
+        for name in names {
+            buf.clear();
+            File::open(mirror_dir.join(name))
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            assert_eq!(buf, t.file_contents(name), "file {name}");
+        }
+    }
+
+    #[test]
+    fn test_straddling_pieces_verify_and_read_back() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let ops = t.file_ops();
+        for piece in t.lengths.iter_piece_infos() {
+            let last_chunk = t
+                .lengths
+                .iter_chunk_infos(piece.piece_index)
+                .last()
+                .unwrap();
+            assert!(ops
+                .check_piece(addr, piece.piece_index, &last_chunk)
+                .unwrap());
+
+            for chunk in t.lengths.iter_chunk_infos(piece.piece_index) {
+                let offset = t.lengths.chunk_absolute_offset(&chunk) as usize;
+                let mut buf = vec![0u8; chunk.size as usize];
+                ops.read_chunk(addr, &chunk, &mut buf).unwrap();
+                assert_eq!(buf, &t.data[offset..offset + chunk.size as usize]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_straddling_piece_corrupted_in_second_file_fails_check() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+
+        // The first byte of "d" belongs to piece 1, which starts in "a".
+        {
+            let mut f = t.files[3].lock();
+            f.seek(SeekFrom::Start(0)).unwrap();
+            f.write_all(&[t.data[30000].wrapping_add(1)]).unwrap();
+        }
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let piece = t.lengths.validate_piece_index(1).unwrap();
+        let last_chunk = t.lengths.iter_chunk_infos(piece).last().unwrap();
+        assert!(!t.file_ops().check_piece(addr, piece, &last_chunk).unwrap());
+        assert!(!t
+            .file_ops()
+            .check_piece_parallel(addr, piece, &last_chunk, 4)
+            .unwrap());
+    }
+
+    // Compares the ways of verifying pieces, see SessionOptions::verify_from_memory:
+    //   cargo test --release -p librqbit bench_verify -- --ignored --nocapture
+    // The data is written to RQBIT_BENCH_DIR, or the temp dir. Locally, reads come from the
+    // page cache, so the read-back numbers are a best case. Point it at a network mount with
+    // client caching off, e.g. CIFS with "cache=none", for what read-back costs there.
+    #[test]
+    #[ignore]
+    fn bench_verify_read_back_vs_memory() {
+        // Reported through the test harness's output capture, hence --nocapture above.
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let parent = std::env::var_os("RQBIT_BENCH_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let t = TestTorrent::with_layout(&parent, 4 * 1024 * 1024, &[256 * 1024 * 1024]);
+        t.write_all_pieces();
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let ops = t.file_ops();
+
+        let time = |name: &str, check: &dyn Fn(librqbit_core::lengths::ValidPieceIndex) -> bool| {
+            let started = std::time::Instant::now();
+            for piece in t.lengths.iter_piece_infos() {
+                assert!(check(piece.piece_index));
+            }
+            let elapsed = started.elapsed();
+            let mib_per_sec = t.lengths.total_length() as f64 / 1048576. / elapsed.as_secs_f64();
+            tracing::info!(name, ?elapsed, "{mib_per_sec:.0} MiB/s");
+        };
+        let last_chunk = |piece| t.lengths.iter_chunk_infos(piece).last().unwrap();
+        time("read back", &|piece| {
+            ops.check_piece(addr, piece, &last_chunk(piece)).unwrap()
+        });
+        for parallel_reads in [4, 16] {
+            time(
+                &format!("read back, {parallel_reads} parallel reads"),
+                &|piece| {
+                    ops.check_piece_parallel(addr, piece, &last_chunk(piece), parallel_reads)
+                        .unwrap()
+                },
+            );
+        }
+        time("from memory", &|piece| {
+            let offset = t.lengths.piece_offset(piece) as usize;
+            let len = t.lengths.piece_length(piece) as usize;
+            ops.check_piece_data(piece, &t.data[offset..offset + len])
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_check_piece_parallel() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let ops = t.file_ops();
+        for piece in t.lengths.iter_piece_infos() {
+            let last_chunk = t
+                .lengths
+                .iter_chunk_infos(piece.piece_index)
+                .last()
+                .unwrap();
+            for parallel_reads in [1, 2, 8] {
+                assert!(ops
+                    .check_piece_parallel(addr, piece.piece_index, &last_chunk, parallel_reads)
+                    .unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_io_counters() {
+        let t = TestTorrent::new();
+        let counters = DiskIoCounters::default();
+        let ops = t.file_ops().with_io_counters(&counters);
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let read = || counters.read_bytes.load(Ordering::Relaxed);
+        let written = || counters.written_bytes.load(Ordering::Relaxed);
+
+        for piece in t.lengths.iter_piece_infos() {
+            for chunk in t.lengths.iter_chunk_infos(piece.piece_index) {
+                let offset = t.lengths.chunk_absolute_offset(&chunk) as usize;
+                let data = Piece {
+                    index: chunk.piece_index.get(),
+                    begin: chunk.offset,
+                    block: &t.data[offset..offset + chunk.size as usize],
+                };
+                ops.write_chunk(addr, &data, &chunk).unwrap();
+            }
+        }
+        assert_eq!(written(), t.lengths.total_length());
+        assert_eq!(read(), 0);
+
+        // Piece 1 is read from three files, and counted once.
+        let piece = t.lengths.validate_piece_index(1).unwrap();
+        let last_chunk = t.lengths.iter_chunk_infos(piece).last().unwrap();
+        assert!(ops.check_piece(addr, piece, &last_chunk).unwrap());
+        assert_eq!(read(), t.lengths.piece_length(piece) as u64);
+
+        let mut buf = vec![0u8; last_chunk.size as usize];
+        ops.read_chunk(addr, &last_chunk, &mut buf).unwrap();
+        assert_eq!(read(), 2 * t.lengths.piece_length(piece) as u64);
+        assert_eq!(written(), t.lengths.total_length());
+
+        // Without counters, nothing is counted.
+        t.file_ops().check_piece(addr, piece, &last_chunk).unwrap();
+        assert_eq!(read(), 2 * t.lengths.piece_length(piece) as u64);
+    }
+
+    #[test]
+    fn test_check_piece_parallel_with_several_chunks_per_read() {
+        // 4 chunks per piece, so that 2 and 4 reads go to the read threads. Piece 0 covers
+        // all of "a" and the start of "b".
+        let t = TestTorrent::with_layout(&std::env::temp_dir(), 4 * 16384, &[50000, 30000]);
+        t.write_all_pieces();
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let ops = t.file_ops();
+        let piece = t.lengths.validate_piece_index(0).unwrap();
+        let last_chunk = t.lengths.iter_chunk_infos(piece).last().unwrap();
+        for parallel_reads in [2, 4] {
+            assert!(ops
+                .check_piece_parallel(addr, piece, &last_chunk, parallel_reads)
+                .unwrap());
+        }
+
+        {
+            let mut f = t.files[1].lock();
+            f.seek(SeekFrom::Start(100)).unwrap();
+            f.write_all(&[t.data[50100].wrapping_add(1)]).unwrap();
+        }
+        for parallel_reads in [2, 4] {
+            assert!(!ops
+                .check_piece_parallel(addr, piece, &last_chunk, parallel_reads)
+                .unwrap());
+        }
+    }
+
+    #[test]
+    fn test_trusted_check_skips_pieces_past_end_of_file() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+        // Piece 1 ends at byte 2768 of "d", piece 2 is all in "d".
+        t.files[3].lock().set_len(100).unwrap();
+
+        let mut trusted = BF::from_vec(vec![0u8; t.lengths.piece_bitfield_bytes()]);
+        trusted[..3].fill(true);
+        let progress = AtomicU64::new(0);
+        let results = t
+            .file_ops()
+            .trusted_check(None, &trusted, &progress)
+            .unwrap();
+        let bits = |bf: &BF| bf.iter().by_vals().take(3).collect::<Vec<bool>>();
+        assert_eq!(bits(&results.have_pieces), [true, false, false]);
+        assert_eq!(bits(&results.needed_pieces), [false, true, true]);
+        assert_eq!(results.have_bytes, PIECE_LENGTH as u64);
+        assert_eq!(progress.load(Ordering::Relaxed), t.lengths.total_length());
+    }
+
+    #[test]
+    fn test_cancelled_initial_check_keeps_unchecked_pieces_needed() {
+        let t = TestTorrent::new();
+        t.write_all_pieces();
+        let progress = AtomicU64::new(0);
+
+        let results = t
+            .file_ops()
+            .initial_check(None, &progress, &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(results.have_bytes, t.lengths.total_length());
+
+        // Nothing was hashed, so nothing is taken as downloaded even though it's all there.
+        let progress = AtomicU64::new(0);
+        let results = t
+            .file_ops()
+            .initial_check(None, &progress, &AtomicBool::new(true))
+            .unwrap();
+        assert_eq!(results.have_pieces.count_ones(), 0);
+        assert_eq!(
+            results.needed_pieces.count_ones(),
+            t.lengths.total_pieces() as usize
+        );
+        assert_eq!(results.needed_bytes, t.lengths.total_length());
+        assert_eq!(results.total_selected_bytes, t.lengths.total_length());
+        assert_eq!(progress.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tag_read_only_fs_error() {
+        use anyhow::Context;
+
+        let counter = AtomicU64::new(0);
+        let erofs = std::io::Error::from_raw_os_error(rustix::io::Errno::ROFS.raw_os_error());
+        let e = Err::<(), _>(erofs)
+            .context("error writing to file 0")
+            .unwrap_err();
+        let e = tag_read_only_fs_error(e, &counter);
+        assert!(format!("{e}").starts_with("read-only filesystem"));
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+
+        // Disk full is something else.
+        let enospc = std::io::Error::from_raw_os_error(rustix::io::Errno::NOSPC.raw_os_error());
+        let e = tag_read_only_fs_error(enospc.into(), &counter);
+        assert!(!format!("{e}").contains("read-only"));
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_remove_empty_dirs_keeps_shared_dirs() {
+        let root = std::env::temp_dir().join(format!("librqbit-file-ops-{}", uuid::Uuid::new_v4()));
+        // Two torrents in a shared output folder. Torrent "a" has a file next to one of "b".
+        let ours = vec![
+            root.join("shared/a.bin"),
+            root.join("shared/only_a/deep/x.bin"),
+        ];
+        let theirs = root.join("shared/b.bin");
+        for f in ours.iter().chain(std::iter::once(&theirs)) {
+            std::fs::create_dir_all(f.parent().unwrap()).unwrap();
+            File::create(f).unwrap();
+        }
+
+        for f in ours.iter() {
+            std::fs::remove_file(f).unwrap();
+        }
+        super::remove_empty_dirs(&root, &ours);
+
+        assert!(!root.join("shared/only_a").exists());
+        assert!(theirs.exists());
+        assert!(root.exists());
+
+        // Once the other torrent is gone too, the shared folder goes, but not the root.
+        std::fs::remove_file(&theirs).unwrap();
+        super::remove_empty_dirs(&root, &[theirs]);
+        assert!(!root.join("shared").exists());
+        assert!(root.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fsync_interval_validation() {
+        use std::time::Duration;
+        assert!(FsyncPolicy::Interval(Duration::ZERO).validate().is_err());
+        assert!(FsyncPolicy::Interval(Duration::from_secs(5))
+            .validate()
+            .is_ok());
+        assert!(FsyncPolicy::Never.validate().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sync_every_stops() {
+        use std::time::Duration;
+        let started = tokio::time::Instant::now();
+        let mut syncs = 0;
+        sync_every(Duration::from_secs(10), || {
+            syncs += 1;
+            // E.g. the torrent finished, and its files were reopened read-only.
+            Ok(syncs < 3)
+        })
+        .await
+        .unwrap();
+        assert_eq!(syncs, 3);
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+
+        let failed = sync_every(Duration::from_secs(10), || anyhow::bail!("read-only")).await;
+        assert!(failed.is_err());
     }
 }
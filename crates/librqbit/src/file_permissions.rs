@@ -0,0 +1,125 @@
+// The mode and group of the files and directories a torrent creates, see
+// SessionOptions::file_mode and SessionOptions::file_group. Every place that creates paths
+// for a torrent goes through here: the initial check, moving files out of incomplete_dir,
+// mirrors, file sinks and cross-seed copies. Paths that existed already are left alone.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FilePermissions {
+    pub mode: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FilePermissions {
+    pub fn is_default(&self) -> bool {
+        self.mode.is_none() && self.gid.is_none()
+    }
+
+    // Like std::fs::create_dir_all(), setting the mode and group on the directories it
+    // created.
+    pub fn create_dir_all(&self, dir: &Path) -> anyhow::Result<()> {
+        let missing = if self.is_default() {
+            Vec::new()
+        } else {
+            dir.ancestors()
+                .take_while(|p| !p.exists())
+                .map(|p| p.to_owned())
+                .collect::<Vec<PathBuf>>()
+        };
+        std::fs::create_dir_all(dir).with_context(|| format!("error creating {dir:?}"))?;
+        let mode = self.mode.map(dir_mode_from_file_mode);
+        for created in missing.iter().rev() {
+            set_permissions(created, mode, self.gid)?;
+        }
+        Ok(())
+    }
+
+    // Sets the mode and group on a file that was just created.
+    pub fn apply_to_file(&self, path: &Path) -> anyhow::Result<()> {
+        if self.is_default() {
+            return Ok(());
+        }
+        set_permissions(path, self.mode, self.gid)
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: Option<u32>, gid: Option<u32>) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("error setting mode {mode:o} on {path:?}"))?;
+    }
+    if let Some(gid) = gid {
+        std::os::unix::fs::chown(path, None, Some(gid))
+            .with_context(|| format!("error setting group {gid} on {path:?}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: Option<u32>, _gid: Option<u32>) -> anyhow::Result<()> {
+    Ok(())
+}
+
+// Directories need "x" to be traversable, so add it wherever "r" is set.
+fn dir_mode_from_file_mode(mode: u32) -> u32 {
+    mode | ((mode & 0o444) >> 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dir_mode_from_file_mode;
+
+    #[test]
+    fn test_dir_mode_from_file_mode() {
+        assert_eq!(dir_mode_from_file_mode(0o640), 0o750);
+        assert_eq!(dir_mode_from_file_mode(0o644), 0o755);
+        assert_eq!(dir_mode_from_file_mode(0o600), 0o700);
+        assert_eq!(dir_mode_from_file_mode(0o200), 0o200);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_created_paths_get_mode_and_group() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        use super::FilePermissions;
+        use crate::test_util::test_dir;
+
+        let dir = test_dir("file-permissions");
+        let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        let dir_mode = mode(&dir);
+        // Our own group, which we're allowed to set without being root.
+        let gid = std::fs::metadata(&dir).unwrap().gid();
+        let perms = FilePermissions {
+            mode: Some(0o640),
+            gid: Some(gid),
+        };
+
+        perms.create_dir_all(&dir.join("a/b")).unwrap();
+        for created in [dir.join("a"), dir.join("a/b")] {
+            assert_eq!(mode(&created), 0o750, "{created:?}");
+            assert_eq!(std::fs::metadata(&created).unwrap().gid(), gid);
+        }
+        // It existed already.
+        assert_eq!(mode(&dir), dir_mode);
+
+        let file = dir.join("a/b/f");
+        std::fs::write(&file, b"x").unwrap();
+        perms.apply_to_file(&file).unwrap();
+        assert_eq!(mode(&file), 0o640);
+        assert_eq!(std::fs::metadata(&file).unwrap().gid(), gid);
+
+        // Nothing set, nothing changed.
+        let other = dir.join("other");
+        std::fs::write(&other, b"x").unwrap();
+        let before = mode(&other);
+        FilePermissions::default().apply_to_file(&other).unwrap();
+        assert_eq!(mode(&other), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+// Writing a file of a torrent to a pipe or socket while it downloads, e.g. straight into a
+// transcoder, without waiting for the file to complete. The bytes go out in order, a piece
+// at a time as pieces verify, so the consumer waits whenever the next piece isn't there
+// yet, woken on each verified piece. What gets downloaded first isn't changed here: sequential mode or piece deadlines
+// bring the pieces in the order they're written.
+
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use librqbit_core::lengths::Lengths;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, error_span, info};
+
+use crate::{
+    file_permissions::FilePermissions,
+    torrent_state::{utils::file_pieces, ManagedTorrent, ManagedTorrentState},
+    type_aliases::BF,
+};
+
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+// How many bytes from the start of the file at "offset" are in pieces we have.
+fn file_bytes_ready(lengths: &Lengths, have_pieces: &BF, offset: u64, len: u64) -> u64 {
+    let piece_length = lengths.default_piece_length() as u64;
+    for piece in file_pieces(lengths, offset, len) {
+        if have_pieces.get(piece as usize).map(|v| *v) != Some(true) {
+            return (piece as u64 * piece_length).saturating_sub(offset);
+        }
+    }
+    len
+}
+
+// The file is written to its incomplete path, if there is one, until the torrent moves it
+// to the final one. A file opened before it's moved can still be read after.
+async fn open_torrent_file(
+    final_path: &Path,
+    incomplete_path: Option<PathBuf>,
+) -> anyhow::Result<tokio::fs::File> {
+    if let Some(path) = incomplete_path {
+        match tokio::fs::File::open(&path).await {
+            Ok(f) => return Ok(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("error opening {path:?}")),
+        }
+    }
+    tokio::fs::File::open(final_path)
+        .await
+        .with_context(|| format!("error opening {final_path:?}"))
+}
+
+type Sink = Box<dyn AsyncWrite + Send + Unpin>;
+
+// A unix socket is connected to, anything else is opened for writing, e.g. a FIFO, which
+// waits for its reader to show up. Regular files are created or truncated, getting the
+// torrent's file_mode and file_group if they're new.
+async fn open_sink(path: &Path, perms: FilePermissions) -> anyhow::Result<Sink> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let is_socket = tokio::fs::metadata(path)
+            .await
+            .map(|m| m.file_type().is_socket())
+            .unwrap_or(false);
+        if is_socket {
+            let stream = tokio::net::UnixStream::connect(path)
+                .await
+                .with_context(|| format!("error connecting to {path:?}"))?;
+            return Ok(Box::new(stream));
+        }
+    }
+    let existed = tokio::fs::try_exists(path).await.unwrap_or(true);
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await
+        .with_context(|| format!("error opening {path:?} for writing"))?;
+    if !existed {
+        perms.apply_to_file(path)?;
+    }
+    Ok(Box::new(file))
+}
+
+// Whether the error came from the consumer hanging up, which isn't worth more than a
+// debug line.
+fn is_consumer_gone(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset
+            )
+        })
+}
+
+impl ManagedTorrent {
+    // Waits until more than "written" bytes of the file are ready, returns how many are.
+    // Fails once no more are coming, i.e. the torrent is paused, in error or deleted.
+    async fn wait_for_file_bytes(
+        &self,
+        offset: u64,
+        len: u64,
+        written: u64,
+    ) -> anyhow::Result<u64> {
+        loop {
+            // Before checking, so that a piece verified in between isn't missed.
+            let changed = self.info().pieces_changed.notified();
+            // Whether it's live, None while initializing.
+            let live = self.with_state(|s| match s {
+                ManagedTorrentState::Live(_) => Ok(Some(true)),
+                ManagedTorrentState::Paused(_) => Ok(Some(false)),
+                ManagedTorrentState::Initializing(_) => Ok(None),
+                ManagedTorrentState::Lazy(_) => {
+                    bail!("torrent isn't loaded, start or load it first")
+                }
+                ManagedTorrentState::Error(e) => bail!("torrent is in error: {:#}", e),
+                ManagedTorrentState::None => bail!("torrent was deleted"),
+            })?;
+            if let Some(live) = live {
+                let bytes = self.with_chunk_tracker(|ct| {
+                    file_bytes_ready(&self.info().lengths, ct.get_have_pieces(), offset, len)
+                })?;
+                if bytes > written {
+                    return Ok(bytes);
+                }
+                if !live {
+                    bail!("torrent is paused, the rest of the file won't come");
+                }
+            }
+            changed.await;
+        }
+    }
+
+    /// Write file "file_idx" to "writer" while it downloads, from the start and in order,
+    /// waiting for each next piece to verify. Returns the bytes written once the whole file
+    /// is. Fails if the file isn't selected for download, as it would never complete, if the
+    /// torrent is or gets paused, deleted or stopped by an error before the file is
+    /// complete, or when writing fails, e.g. because the consumer closed the pipe. The torrent carries on
+    /// either way. Use [ManagedTorrent::set_sequential] so that the pieces come in the
+    /// order they're written.
+    pub async fn stream_file_to<W: AsyncWrite + Unpin>(
+        &self,
+        file_idx: usize,
+        mut writer: W,
+    ) -> anyhow::Result<u64> {
+        let info = self.info();
+        let mut offset = 0u64;
+        let mut file_len = None;
+        for (idx, len) in info.info.iter_file_lengths()?.enumerate() {
+            if idx == file_idx {
+                file_len = Some(len);
+                break;
+            }
+            offset += len;
+        }
+        let len = file_len.with_context(|| format!("torrent has no file {file_idx}"))?;
+        let skipped = self
+            .only_files()
+            .map(|only| !only.contains(&file_idx))
+            .unwrap_or(false);
+        if skipped {
+            bail!(
+                "file {} is not selected for download, it won't complete",
+                file_idx
+            );
+        }
+        let final_path = info.file_paths()?.swap_remove(file_idx);
+
+        let mut file = None;
+        let mut written = 0u64;
+        let mut buf = vec![0u8; READ_BUF_SIZE];
+        while written < len {
+            let ready = self.wait_for_file_bytes(offset, len, written).await?;
+            if file.is_none() {
+                let incomplete_path = info.incomplete_file_path(&final_path);
+                file = Some(open_torrent_file(&final_path, incomplete_path).await?);
+            }
+            let f = file.as_mut().context("bug: file not opened")?;
+            f.seek(SeekFrom::Start(written))
+                .await
+                .with_context(|| format!("error seeking in {final_path:?}"))?;
+            while written < ready {
+                let n = (ready - written).min(buf.len() as u64) as usize;
+                f.read_exact(&mut buf[..n])
+                    .await
+                    .with_context(|| format!("error reading {final_path:?}"))?;
+                writer
+                    .write_all(&buf[..n])
+                    .await
+                    .context("error writing to the sink")?;
+                written += n as u64;
+            }
+            writer.flush().await.context("error writing to the sink")?;
+        }
+        writer.shutdown().await.context("error closing the sink")?;
+        Ok(written)
+    }
+
+    // Streams the file to the unix socket, FIFO or file at "path" in the background, see
+    // stream_file_to.
+    pub(crate) fn spawn_file_sink(self: &Arc<Self>, file_idx: usize, path: PathBuf) {
+        let torrent = self.clone();
+        let perms = self.info().file_permissions();
+        crate::spawn_utils::spawn(
+            "file_sink",
+            error_span!(parent: self.info().span.clone(), "file_sink", file_idx, ?path),
+            async move {
+                let sink = open_sink(&path, perms).await?;
+                match torrent.stream_file_to(file_idx, sink).await {
+                    Ok(bytes) => {
+                        info!(bytes, "streamed the whole file");
+                        Ok(())
+                    }
+                    Err(e) if is_consumer_gone(&e) => {
+                        debug!("consumer went away: {:#}", e);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use librqbit_core::lengths::Lengths;
+
+    use crate::{
+        session::AddTorrentOptions,
+        test_util::{add_test_torrent, test_data, test_dir, test_session},
+        type_aliases::BF,
+    };
+
+    use super::file_bytes_ready;
+
+    #[test]
+    fn test_file_bytes_ready() {
+        let lengths = Lengths::new(100, 10, Some(10)).unwrap();
+        let mut have = BF::repeat(false, 10);
+        // The file spans pieces 1 to 4.
+        let (offset, len) = (15, 30);
+        assert_eq!(file_bytes_ready(&lengths, &have, offset, len), 0);
+
+        // Pieces past a missing one don't count yet.
+        have.set(1, true);
+        have.set(3, true);
+        assert_eq!(file_bytes_ready(&lengths, &have, offset, len), 5);
+        have.set(2, true);
+        assert_eq!(file_bytes_ready(&lengths, &have, offset, len), 25);
+        have.set(4, true);
+        assert_eq!(file_bytes_ready(&lengths, &have, offset, len), 30);
+    }
+
+    #[tokio::test]
+    async fn test_sink_stops_when_paused() {
+        let dir = test_dir("file-sink-paused");
+        let session = test_session(&dir, Default::default()).await;
+        let data = test_data(100, 0);
+        let opts = AddTorrentOptions {
+            paused: true,
+            ..Default::default()
+        };
+        // Nothing on disk, so nothing is ever ready.
+        let (_, handle) = add_test_torrent(&session, "t", &[("f", &data)], opts).await;
+        let res = tokio::time::timeout(
+            Duration::from_secs(5),
+            handle.stream_file_to(0, tokio::io::sink()),
+        )
+        .await
+        .expect("the sink kept waiting");
+        assert!(format!("{:#}", res.unwrap_err()).contains("paused"));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+// Picking the first piece to download from a newly connected peer, see
+// SessionOptions::first_piece_strategy. Only the first piece is picked this way, later ones
+// are taken in order as usual.
+
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+
+/// Which piece to request first from a newly connected peer.
+///
+/// Until its first piece is done, there's no measure of how fast a peer is, and nothing it
+/// sent can be verified. Pieces with a playback deadline still go first, and in sequential
+/// mode or with contiguous storage the first piece is always taken in order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstPieceStrategy {
+    /// The default. The first needed piece the peer has, same as for every later piece.
+    #[default]
+    InOrder,
+    /// A random needed piece the peer has, so that peers connecting at the same time don't
+    /// all start on the same few pieces.
+    Random,
+    /// The needed piece the fewest connected peers have, so that it doesn't get lost if
+    /// they go away. See [crate::SessionOptions::rarest_first_randomness] for picking
+    /// among the rarest ones randomly.
+    Rarest,
+    /// The needed piece with the fewest bytes left, e.g. the shorter last piece. All of a
+    /// piece's chunks are requested, including ones received before, so this only gets the
+    /// first measure of the peer's throughput sooner if the piece itself is shorter. Taken
+    /// in order when there's no such piece.
+    QuickStart,
+}
+
+// Picks the next piece to reserve from the needed pieces the peer has, in the order they'd be
+// downloaded in. "in_order" takes the first of them, wherever it is, so a peer that only has
+// later pieces still gets one. Otherwise "first_piece" is the strategy if it's the peer's first.
+pub(crate) fn pick_next_piece(
+    mut candidates: impl Iterator<Item = usize>,
+    in_order: bool,
+    first_piece: Option<FirstPieceStrategy>,
+    bytes_left: impl Fn(usize) -> u64,
+    availability: &[u32],
+    rarest_randomness: Option<u32>,
+) -> Option<usize> {
+    match first_piece {
+        Some(strategy) if !in_order => pick_first_piece(
+            strategy,
+            candidates,
+            bytes_left,
+            availability,
+            rarest_randomness,
+        ),
+        _ => candidates.next(),
+    }
+}
+
+// Picks from the needed pieces the peer has, in the order they'd be downloaded in.
+// "availability" is how many connected peers have each piece, only used for Rarest, and so is
+// "rarest_randomness", see SessionOptions::rarest_first_randomness.
+fn pick_first_piece(
+    strategy: FirstPieceStrategy,
+    mut candidates: impl Iterator<Item = usize>,
+    bytes_left: impl Fn(usize) -> u64,
+    availability: &[u32],
+    rarest_randomness: Option<u32>,
+) -> Option<usize> {
+    match strategy {
+        FirstPieceStrategy::InOrder => candidates.next(),
+        FirstPieceStrategy::Random => candidates.choose(&mut rand::thread_rng()),
+        FirstPieceStrategy::Rarest => pick_rarest(candidates, availability, rarest_randomness),
+        FirstPieceStrategy::QuickStart => candidates.min_by_key(|p| bytes_left(*p)),
+    }
+}
+
+fn pick_rarest(
+    candidates: impl Iterator<Item = usize>,
+    availability: &[u32],
+    randomness: Option<u32>,
+) -> Option<usize> {
+    let peers_with = |p: usize| availability.get(p).copied().unwrap_or_default();
+    let randomness = match randomness {
+        Some(randomness) => randomness,
+        None => return candidates.min_by_key(|p| peers_with(*p)),
+    };
+    let candidates: Vec<usize> = candidates.collect();
+    let rarest = candidates.iter().map(|p| peers_with(*p)).min()?;
+    candidates
+        .into_iter()
+        .filter(|p| peers_with(*p) <= rarest.saturating_add(randomness))
+        .choose(&mut rand::thread_rng())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{pick_first_piece, pick_next_piece, pick_rarest, FirstPieceStrategy};
+
+    #[test]
+    fn test_pick_first_piece() {
+        let candidates = [4, 1, 2, 3];
+        let bytes_left = |p| if p == 2 { 100 } else { 16384 };
+        let availability = [5, 3, 3, 1, 6];
+        let pick = |strategy| {
+            pick_first_piece(
+                strategy,
+                candidates.iter().copied(),
+                bytes_left,
+                &availability,
+                None,
+            )
+        };
+
+        assert_eq!(pick(FirstPieceStrategy::InOrder), Some(4));
+        assert_eq!(pick(FirstPieceStrategy::Rarest), Some(3));
+        assert_eq!(pick(FirstPieceStrategy::QuickStart), Some(2));
+        assert!(candidates.contains(&pick(FirstPieceStrategy::Random).unwrap()));
+
+        // Ties go to whichever piece comes first.
+        let pick = pick_first_piece(
+            FirstPieceStrategy::QuickStart,
+            candidates.iter().copied(),
+            |_| 16384,
+            &[],
+            None,
+        );
+        assert_eq!(pick, Some(4));
+        assert_eq!(
+            pick_first_piece(
+                FirstPieceStrategy::Random,
+                std::iter::empty(),
+                |_| 0,
+                &[],
+                None
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pick_rarest_randomness() {
+        let candidates = [4, 1, 2, 3];
+        let availability = [5, 3, 3, 1, 6];
+        let picks = |randomness| {
+            (0..200)
+                .filter_map(|_| pick_rarest(candidates.iter().copied(), &availability, randomness))
+                .collect::<HashSet<usize>>()
+        };
+
+        assert_eq!(picks(None), HashSet::from([3]));
+        assert_eq!(picks(Some(0)), HashSet::from([3]));
+        // Pieces 1 and 2 are only two peers more common than piece 3, piece 4 is outside.
+        assert_eq!(picks(Some(2)), HashSet::from([1, 2, 3]));
+        // Equally rare pieces are picked from randomly.
+        let picks = (0..200)
+            .filter_map(|_| pick_rarest([1, 2].into_iter(), &availability, Some(0)))
+            .collect::<HashSet<usize>>();
+        assert_eq!(picks, HashSet::from([1, 2]));
+        assert_eq!(
+            pick_rarest(std::iter::empty(), &availability, Some(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pick_next_piece_in_order() {
+        // The peer only has pieces far behind the first needed ones, e.g. 0..20.
+        let peer_has = 40..50;
+        let bytes_left = |p| if p == 45 { 100 } else { 16384 };
+        let pick = |in_order, first_piece| {
+            pick_next_piece(
+                (0..100).filter(|p| peer_has.contains(p)),
+                in_order,
+                first_piece,
+                bytes_left,
+                &[],
+                None,
+            )
+        };
+
+        assert_eq!(pick(true, None), Some(40));
+        assert_eq!(pick(true, Some(FirstPieceStrategy::QuickStart)), Some(40));
+        assert_eq!(pick(false, None), Some(40));
+        assert_eq!(pick(false, Some(FirstPieceStrategy::QuickStart)), Some(45));
+        assert_eq!(
+            pick_next_piece(std::iter::empty(), true, None, bytes_left, &[], None),
+            None
+        );
+    }
+}
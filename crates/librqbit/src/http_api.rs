@@ -3,7 +3,7 @@ use axum::body::Bytes;
 use axum::extract::{Path, Query, State};
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
 use itertools::Itertools;
 
 use serde::{Deserialize, Serialize};
@@ -14,15 +14,28 @@ use tracing::{debug, info};
 
 use axum::Router;
 
-use crate::api::Api;
+use crate::api::{
+    Api, CompareTorrentsOptions, DhtWarmupRequest, QueueSortRequest, RelocateStateFilesRequest,
+    SeedingAnnounceIntervalRequest, StartupConnectRateRequest, StatsExportFormat,
+    TorrentsBulkUpdateRequest,
+};
+use crate::expected_metadata::ExpectedMetadata;
+use crate::peer_budget::PriorityTier;
 use crate::peer_connection::PeerConnectionOptions;
-use crate::session::{AddTorrent, AddTorrentOptions, SUPPORTED_SCHEMES};
+use crate::profile::SessionProfile;
+use crate::session::{AddTorrent, AddTorrentOptions, DeleteOptions, SUPPORTED_SCHEMES};
+use crate::torrent_state::deadlines::PieceDeadline;
 use crate::torrent_state::peer::stats::snapshot::PeerStatsFilter;
+use crate::torrent_state::ExistingDataPolicy;
 
 type ApiState = Api;
 
 use crate::api::Result;
 
+// See GET /torrents/stats_stream.
+const DEFAULT_STATS_STREAM_INTERVAL_MS: u64 = 1000;
+const MIN_STATS_STREAM_INTERVAL_MS: u64 = 100;
+
 /// An HTTP server for the API.
 pub struct HttpApi {
     inner: ApiState,
@@ -32,6 +45,9 @@ pub struct HttpApi {
 #[derive(Debug, Default)]
 pub struct HttpApiOptions {
     pub read_only: bool,
+    /// Serve the web UI at "/" to browsers, in addition to "/web/". Requests that
+    /// don't accept HTML still get the list of APIs. Needs the "webui" feature.
+    pub serve_webui_at_root: bool,
 }
 
 impl HttpApi {
@@ -46,50 +62,220 @@ impl HttpApi {
     /// If read_only is passed, no state-modifying methods will be exposed.
     pub async fn make_http_api_and_run(self, addr: SocketAddr) -> anyhow::Result<()> {
         let state = self.inner;
+        let read_only = self.opts.read_only;
 
-        async fn api_root() -> impl IntoResponse {
+        async fn api_root(read_only: bool) -> impl IntoResponse {
             axum::Json(serde_json::json!({
                 "apis": {
                     "GET /": "list all available APIs",
+                    "GET /stats": "Session stats",
+                    "GET /listen": "Whether the listen port is bound and inbound peers have connected, i.e. the port is reachable",
+                    "GET /connect_rate": "How fast peers are being dialed, and the startup ramp limiting it",
+                    "POST /connect_rate": "Set the startup dial rate limit and start its ramp over. JSON body: {\"initial_per_sec\": N or null, \"ramp_secs\": N}",
+                    "GET /profile": "The network, tracker and limit settings as a profile to import into another instance. Query: ?name=...",
+                    "POST /profile": "Import a profile from GET /profile. Applies what can change at runtime, and lists the settings that need a restart",
+                    "GET /upnp": "UPnP port mappings: external address, lease remaining and the last error",
+                    "POST /upnp/remap": "Redo the UPnP port mappings now",
+                    "GET /storage": "Total, free and used bytes of each filesystem torrents are stored on",
+                    "GET /queue": "Ids of the torrents waiting for a download slot, first to start first",
+                    "GET /collections": "Collections by name, with the ids of their torrents",
+                    "GET /collections/{name}/stats": "The stats of the collection's torrents added up",
                     "GET /dht/stats": "DHT stats",
                     "GET /dht/table": "DHT routing table",
+                    "GET /dht/lookups": "Running DHT peer lookups",
                     "GET /torrents": "List torrents (default torrent is 0)",
+                    "GET /torrents/resolving": "Magnet links still waiting for metadata, with metadata peer counts",
+                    "GET /torrents/resolving/{info_hash}/diagnose": "How far a magnet link that is being added got with its metadata",
+                    "GET /torrents/by_path": "Torrents with their output or incomplete folder under ?prefix=PATH, e.g. all torrents on one disk",
+                    "GET /torrents/export_stats": "A report of all torrents with sizes, progress, transfer totals and ratios. Add ?format=csv for CSV",
+                    "GET /torrents/stats_stream": "Server-sent events with the stats of all torrents: the full stats first, then only the fields that changed, with their new value. Add ?interval_ms=N, default 1000",
                     "GET /torrents/{index}": "Torrent details",
                     "GET /torrents/{index}/haves": "The bitfield of have pieces",
+                    "GET /torrents/{index}/raw_info": "The bencoded info dictionary, byte for byte as it hashes to the info hash",
+                    "GET /torrents/{index}/magnet": "A magnet link with the current trackers. Add ?include_peers=true to add connected peers as x.pe",
+                    "GET /torrents/{index}/fastresume": "A libtorrent .fastresume file with the have pieces and file priorities, for other clients",
+                    "GET /torrents/{index}/zip": "Stream the complete files as an uncompressed ZIP. Add ?files=0,2 to pick files, ?wait=true to wait for incomplete ones instead of leaving them out",
                     "GET /torrents/{index}/stats/v1": "Torrent stats",
                     "GET /torrents/{index}/peer_stats": "Per peer stats",
+                    "GET /torrents/{index}/poisoned_pieces": "Pieces that failed verification from several distinct peers",
+                    "GET /torrents/{index}/peer_events": "Recent peer disconnects and their reasons",
+                    "GET /torrents/{index}/piece_latency": "Percentiles and a histogram of how long recent pieces took from first request to verification",
+                    "GET /torrents/{index}/verification_failures": "Recent pieces that failed verification, with the peers that sent each chunk. Only recorded with --verification-mode=log-only",
+                    "GET /torrents/{index}/trackers": "Announce state of each tracker, with the effective announce interval and whether it's dead",
+                    "GET /torrents/{index}/announce_history": "The last announces to the trackers, with the uploaded and downloaded bytes reported and the peers returned",
+                    "GET /torrents/{index}/swarm_health": "Estimated number of full copies in the swarm, and the rarest piece's availability",
+                    "GET /torrents/{index}/diagnose": "Why the torrent isn't downloading, e.g. no peers, all peers choking, queued, paused or a disk error",
+                    "GET /torrents/{index}/compare/{other}": "Compare file lists of two torrents. Add ?compare_piece_hashes=true to check piece hashes",
                     "POST /torrents/{index}/pause": "Pause torrent",
                     "POST /torrents/{index}/start": "Resume torrent",
+                    "POST /torrents/{index}/load": "Open and check the files of a torrent restored lazily, leaving it paused",
+                    "POST /torrents/{index}/recheck": "Hash all the data on disk again, e.g. after files went missing",
+                    "POST /torrents/{index}/cancel_verify": "Stop checking the data, keeping the pieces verified so far. Leaves the torrent paused",
+                    "POST /torrents/{index}/solo": "Pause all other live torrents, start this one if paused",
+                    "POST /solo/clear": "Resume the torrents paused by solo mode",
+                    "POST /collections/{name}": "Create an empty collection of torrents",
+                    "POST /collections/{name}/delete": "Remove the collection, keep its torrents",
+                    "POST /collections/{name}/add/{index}": "Put the torrent in the collection",
+                    "POST /collections/{name}/remove/{index}": "Take the torrent out of the collection",
+                    "POST /collections/{name}/pause": "Pause the collection's live torrents",
+                    "POST /collections/{name}/start": "Start the collection's paused torrents",
+                    "POST /collections/{name}/torrents/forget": "Forget about the collection's torrents, keep the files",
+                    "POST /collections/{name}/torrents/delete": "Forget about the collection's torrents, remove the files",
+                    "POST /torrents/{index}/lock": "Freeze the torrent: no pausing, starting, deleting, uploading or downloading until unlocked",
+                    "POST /torrents/{index}/unlock": "Undo /lock",
+                    "POST /torrents/{index}/trackers/revive": "Announce again to a tracker that was given up on as dead. JSON body: {\"url\": \"http://tracker/announce\"}",
+                    "POST /torrents/{index}/priority_tier": "Weight the torrent's share of the session's peer connection budget. JSON body: {\"tier\": \"low\" | \"normal\" | \"high\"}",
+                    "POST /torrents/{index}/upload_slots": "How many peers to upload to at a time, null for the session's default. JSON body: {\"slots\": N}",
+                    "POST /torrents/{index}/files/{file_index}/sink": "Write the file to a unix socket, FIFO or file in the server's file sink directory while it downloads, in order. JSON body: {\"path\": \"fifo\", \"sequential\": true}",
+                    "POST /torrents/{index}/peer_opts": "Peer timeouts in seconds for new connections, missing ones are the session's. JSON body: {\"connect_timeout\": N, \"read_write_timeout\": N, \"handshake_timeout\": N, \"choke_timeout\": N}",
+                    "POST /torrents/{index}/boost": "Give the torrent priority for a while, then put it back as it was. JSON body: {\"duration_secs\": N}",
                     "POST /torrents/{index}/forget": "Forget about the torrent, keep the files",
-                    "POST /torrents/{index}/delete": "Forget about the torrent, remove the files",
-                    "POST /torrents": "Add a torrent here. magnet: or http:// or a local file.",
+                    "POST /torrents/{index}/delete": "Forget about the torrent, remove the files. Add ?remove_empty_dirs=true to also remove its directories once empty",
+                    "POST /torrents/{index}/metadata": "Replace the torrent's user metadata with the JSON body, at most 64 KiB",
+                    "POST /torrents/{index}/deadlines": "Replace the playback deadlines of pieces, for streaming. JSON body: [{\"piece\": N, \"deadline_ms\": M}, ...], empty to clear",
+                    "POST /torrents": "Add a torrent here. magnet: or http:// or a local file. Add ?expect_min_bytes=N, ?expect_max_bytes=N or ?expect_file_count=N to refuse torrents that don't match. Add ?sample_files=0,2 to only download these files and pause once they're complete. Add ?cross_seed=true to reuse matching files of torrents already in the session",
+                    "POST /torrents/pause_by_path": "Pause all torrents under ?prefix=PATH, e.g. before unmounting a disk",
+                    "POST /torrents/bulk_update": "Change labels, sequential mode or seeding announce interval of several torrents. JSON body: {\"ids\": [...], \"changes\": {...}}",
+                    "POST /config/reload": "Re-read the config file and apply it, if the embedder has one",
                     "POST /rust_log": "Set RUST_LOG to this post launch (for debugging)",
+                    "POST /dht/read_only/enable": "Stop answering DHT queries (BEP 43 read-only node), peer lookups keep working",
+                    "POST /dht/read_only/disable": "Answer DHT queries again",
+                    "POST /dht/warmup": "Fill the DHT routing table now, e.g. before adding magnet links. Returns once enough nodes respond or after the timeout, 409 if one is already running. JSON body: {\"min_good_nodes\": N, \"timeout_secs\": N}",
+                    "POST /announce/enable": "Resume tracker announces and DHT activity",
+                    "POST /announce/disable": "Pause tracker announces and DHT activity, torrents keep running",
+                    "POST /announce/seeding_interval": "Set how often complete torrents announce. JSON body: {\"interval_secs\": N or null}",
+                    "POST /queue/sort": "Reorder the download queue. JSON body: {\"key\": \"size\" | \"added_at\" | \"progress\" | \"name\", \"reverse\": bool}",
+                    "POST /torrents/{index}/dequeue": "Take the torrent out of the download queue, it stays paused",
+                    "POST /relocate_state": "Move session and/or DHT persistence files to new paths",
                     "GET /web/": "Web UI",
                 },
                 "server": "rqbit",
                 "version": env!("CARGO_PKG_VERSION"),
+                // POST APIs are not available when this is true.
+                "read_only": read_only,
             }))
         }
 
+        async fn session_stats(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_session_stats())
+        }
+
+        async fn listen_status(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_listen_status())
+        }
+
+        async fn connect_rate(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_startup_connect_rate())
+        }
+
+        async fn set_connect_rate(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<StartupConnectRateRequest>,
+        ) -> Result<impl IntoResponse> {
+            state.api_set_startup_connect_rate(request).map(axum::Json)
+        }
+
+        async fn profile_export(
+            State(state): State<ApiState>,
+            Query(params): Query<ProfileExportParams>,
+        ) -> impl IntoResponse {
+            axum::Json(state.api_profile_export(params.name))
+        }
+
+        async fn profile_import(
+            State(state): State<ApiState>,
+            axum::Json(profile): axum::Json<SessionProfile>,
+        ) -> Result<impl IntoResponse> {
+            state.api_profile_import(profile).map(axum::Json)
+        }
+
+        async fn upnp_status(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_upnp_status())
+        }
+
+        async fn upnp_remap(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_upnp_remap().map(axum::Json)
+        }
+
+        async fn storage_stats(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_storage_stats().await.map(axum::Json)
+        }
+
+        async fn queue(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_queue())
+        }
+
+        async fn queue_sort(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<QueueSortRequest>,
+        ) -> impl IntoResponse {
+            axum::Json(state.api_queue_sort(request))
+        }
+
+        async fn queue_dequeue(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_queue_dequeue(idx).map(axum::Json)
+        }
+
         async fn dht_stats(State(state): State<ApiState>) -> Result<impl IntoResponse> {
             state.api_dht_stats().map(axum::Json)
         }
 
+        async fn dht_read_only_enable(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_dht_set_read_only(true).map(axum::Json)
+        }
+
+        async fn dht_read_only_disable(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_dht_set_read_only(false).map(axum::Json)
+        }
+
+        async fn dht_warmup(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<DhtWarmupRequest>,
+        ) -> Result<impl IntoResponse> {
+            state.api_dht_warmup(request).await.map(axum::Json)
+        }
+
         async fn dht_table(State(state): State<ApiState>) -> Result<impl IntoResponse> {
             state.api_dht_table().map(axum::Json)
         }
 
+        async fn dht_lookups(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_dht_active_lookups().map(axum::Json)
+        }
+
         async fn torrents_list(State(state): State<ApiState>) -> impl IntoResponse {
             axum::Json(state.api_torrent_list())
         }
 
+        async fn torrents_by_path(
+            State(state): State<ApiState>,
+            Query(params): Query<PathPrefixParams>,
+        ) -> impl IntoResponse {
+            axum::Json(state.api_torrents_by_path(&params.prefix))
+        }
+
+        async fn torrents_pause_by_path(
+            State(state): State<ApiState>,
+            Query(params): Query<PathPrefixParams>,
+        ) -> impl IntoResponse {
+            axum::Json(state.api_torrents_pause_by_path(&params.prefix))
+        }
+
+        async fn torrents_resolving(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_resolving_magnets())
+        }
+
         async fn torrents_post(
             State(state): State<ApiState>,
             Query(params): Query<TorrentAddQueryParams>,
             data: Bytes,
         ) -> Result<impl IntoResponse> {
             let is_url = params.is_url;
-            let opts = params.into_add_torrent_options();
+            let mut opts = params.into_add_torrent_options();
+            opts.mirror_paths = state.resolve_api_mirror_paths(opts.mirror_paths)?;
             let data = data.to_vec();
             let add = match is_url {
                 Some(true) => AddTorrent::Url(
@@ -129,6 +315,74 @@ impl HttpApi {
             state.api_dump_haves(idx)
         }
 
+        async fn torrent_raw_info(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            let bytes = state.api_torrent_raw_info(idx)?;
+            Ok((
+                [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+                bytes,
+            ))
+        }
+
+        async fn torrent_magnet(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            Query(params): Query<MagnetParams>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_magnet(idx, params.include_peers)
+        }
+
+        async fn torrent_fastresume(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            let bytes = state.api_torrent_fastresume(idx)?;
+            Ok((
+                [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+                bytes,
+            ))
+        }
+
+        async fn torrent_zip(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            Query(params): Query<ZipQueryParams>,
+        ) -> Result<impl IntoResponse> {
+            let name = state
+                .api_torrent_details(idx)?
+                .name
+                .unwrap_or_else(|| "torrent".to_owned());
+            let stream = state.api_torrent_zip(
+                idx,
+                params.files.map(|f| f.0),
+                params.wait.unwrap_or(false),
+            )?;
+            // Header values have to be ASCII.
+            let filename = name
+                .chars()
+                .map(|c| match c {
+                    '"' | '\\' => '_',
+                    c if c.is_ascii() && !c.is_ascii_control() => c,
+                    _ => '_',
+                })
+                .collect::<String>();
+            Ok((
+                [
+                    (
+                        axum::http::header::CONTENT_TYPE,
+                        "application/zip".to_owned(),
+                    ),
+                    (
+                        axum::http::header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{filename}.zip\""),
+                    ),
+                ],
+                axum::body::Body::from_stream(stream),
+            ))
+        }
+
         async fn torrent_stats_v0(
             State(state): State<ApiState>,
             Path(idx): Path<usize>,
@@ -136,6 +390,44 @@ impl HttpApi {
             state.api_stats_v0(idx).map(axum::Json)
         }
 
+        async fn torrents_export_stats(
+            State(state): State<ApiState>,
+            Query(params): Query<ExportStatsParams>,
+        ) -> impl IntoResponse {
+            let format = params.format.unwrap_or_default();
+            let content_type = match format {
+                StatsExportFormat::Json => "application/json",
+                StatsExportFormat::Csv => "text/csv",
+            };
+            (
+                [(axum::http::header::CONTENT_TYPE, content_type)],
+                axum::body::Body::from_stream(state.api_export_stats(format)),
+            )
+        }
+
+        async fn torrents_stats_stream(
+            State(state): State<ApiState>,
+            Query(params): Query<StatsStreamParams>,
+        ) -> impl IntoResponse {
+            let interval = Duration::from_millis(
+                params
+                    .interval_ms
+                    .unwrap_or(DEFAULT_STATS_STREAM_INTERVAL_MS)
+                    .max(MIN_STATS_STREAM_INTERVAL_MS),
+            );
+            let events = state.api_stats_delta_stream(interval).map(|delta| {
+                let json = serde_json::to_string(&delta?)?;
+                Ok::<_, anyhow::Error>(Bytes::from(format!("data: {json}\n\n")))
+            });
+            (
+                [
+                    (axum::http::header::CONTENT_TYPE, "text/event-stream"),
+                    (axum::http::header::CACHE_CONTROL, "no-cache"),
+                ],
+                axum::body::Body::from_stream(events),
+            )
+        }
+
         async fn torrent_stats_v1(
             State(state): State<ApiState>,
             Path(idx): Path<usize>,
@@ -151,6 +443,126 @@ impl HttpApi {
             state.api_peer_stats(idx, filter).map(axum::Json)
         }
 
+        async fn torrent_poisoned_pieces(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_poisoned_pieces(idx).map(axum::Json)
+        }
+
+        async fn torrent_peer_events(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_recent_peer_events(idx).map(axum::Json)
+        }
+
+        async fn torrent_piece_latency(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_piece_latency(idx).map(axum::Json)
+        }
+
+        async fn torrent_verification_failures(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_verification_failures(idx).map(axum::Json)
+        }
+
+        async fn torrent_set_metadata(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(metadata): axum::Json<serde_json::Value>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_set_metadata(idx, metadata)
+                .map(axum::Json)
+        }
+
+        async fn torrent_set_deadlines(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(deadlines): axum::Json<Vec<PieceDeadline>>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_set_deadlines(idx, deadlines)
+                .map(axum::Json)
+        }
+
+        async fn torrent_trackers(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_tracker_stats(idx).map(axum::Json)
+        }
+
+        async fn torrent_announce_history(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_announce_history(idx).map(axum::Json)
+        }
+
+        async fn torrent_swarm_health(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_swarm_health(idx).map(axum::Json)
+        }
+
+        async fn torrent_diagnose(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_diagnose(idx).map(axum::Json)
+        }
+
+        async fn magnet_diagnose(
+            State(state): State<ApiState>,
+            Path(info_hash): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_magnet_diagnose(&info_hash).map(axum::Json)
+        }
+
+        #[cfg(feature = "debug-api")]
+        async fn torrent_picker_plan(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            Query(params): Query<PickerPlanParams>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_picker_plan(idx, params.n.unwrap_or(DEFAULT_PICKER_PLAN_PIECES))
+                .map(axum::Json)
+        }
+
+        #[cfg(feature = "debug-api")]
+        async fn torrent_debug_dump(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_debug_dump(idx).map(axum::Json)
+        }
+
+        #[cfg(feature = "debug-api")]
+        async fn debug_bencode(
+            State(state): State<ApiState>,
+            data: Bytes,
+        ) -> Result<impl IntoResponse> {
+            state.api_debug_bencode(&data).map(axum::Json)
+        }
+
+        async fn torrent_compare(
+            State(state): State<ApiState>,
+            Path((idx_a, idx_b)): Path<(usize, usize)>,
+            Query(opts): Query<CompareTorrentsOptions>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_compare_torrents(idx_a, idx_b, opts)
+                .map(axum::Json)
+        }
+
         async fn torrent_action_pause(
             State(state): State<ApiState>,
             Path(idx): Path<usize>,
@@ -165,6 +577,181 @@ impl HttpApi {
             state.api_torrent_action_start(idx).map(axum::Json)
         }
 
+        async fn torrent_action_load(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_action_load(idx).map(axum::Json)
+        }
+
+        async fn torrent_action_recheck(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_action_recheck(idx).map(axum::Json)
+        }
+
+        async fn torrent_cancel_verify(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_cancel_verify(idx).map(axum::Json)
+        }
+
+        async fn torrent_action_solo(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_solo(idx).map(axum::Json)
+        }
+
+        async fn solo_clear(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_clear_solo().map(axum::Json)
+        }
+
+        async fn collections_list(State(state): State<ApiState>) -> impl IntoResponse {
+            axum::Json(state.api_collections())
+        }
+
+        async fn collection_stats(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_stats(&name).map(axum::Json)
+        }
+
+        async fn collection_create(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_create(&name).map(axum::Json)
+        }
+
+        async fn collection_delete(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_delete(&name).map(axum::Json)
+        }
+
+        async fn collection_add(
+            State(state): State<ApiState>,
+            Path((name, idx)): Path<(String, usize)>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_add(&name, idx).map(axum::Json)
+        }
+
+        async fn collection_remove(
+            State(state): State<ApiState>,
+            Path((name, idx)): Path<(String, usize)>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_remove(&name, idx).map(axum::Json)
+        }
+
+        async fn collection_pause(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_pause(&name).map(axum::Json)
+        }
+
+        async fn collection_start(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state.api_collection_start(&name).map(axum::Json)
+        }
+
+        async fn collection_forget_torrents(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_collection_delete_torrents(&name, false)
+                .map(axum::Json)
+        }
+
+        async fn collection_delete_torrents(
+            State(state): State<ApiState>,
+            Path(name): Path<String>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_collection_delete_torrents(&name, true)
+                .map(axum::Json)
+        }
+
+        async fn torrent_lock(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_set_locked(idx, true).map(axum::Json)
+        }
+
+        async fn torrent_unlock(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_set_locked(idx, false).map(axum::Json)
+        }
+
+        async fn torrent_boost(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(request): axum::Json<TorrentBoostRequest>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_boost(idx, Duration::from_secs(request.duration_secs))
+                .map(axum::Json)
+        }
+
+        async fn torrent_set_priority_tier(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(request): axum::Json<PriorityTierRequest>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_set_priority_tier(idx, request.tier)
+                .map(axum::Json)
+        }
+
+        async fn torrent_set_upload_slots(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(request): axum::Json<UploadSlotsRequest>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_set_upload_slots(idx, request.slots)
+                .map(axum::Json)
+        }
+
+        async fn torrent_revive_tracker(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(request): axum::Json<ReviveTrackerRequest>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_revive_tracker(idx, &request.url)
+                .map(axum::Json)
+        }
+
+        async fn torrent_file_sink(
+            State(state): State<ApiState>,
+            Path((idx, file_idx)): Path<(usize, usize)>,
+            axum::Json(request): axum::Json<FileSinkRequest>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_file_sink(idx, file_idx, request.path, request.sequential)
+                .map(axum::Json)
+        }
+
+        async fn torrent_set_peer_opts(
+            State(state): State<ApiState>,
+            Path(idx): Path<usize>,
+            axum::Json(opts): axum::Json<PeerConnectionOptions>,
+        ) -> Result<impl IntoResponse> {
+            state.api_torrent_set_peer_opts(idx, opts).map(axum::Json)
+        }
+
         async fn torrent_action_forget(
             State(state): State<ApiState>,
             Path(idx): Path<usize>,
@@ -175,8 +762,45 @@ impl HttpApi {
         async fn torrent_action_delete(
             State(state): State<ApiState>,
             Path(idx): Path<usize>,
+            Query(params): Query<DeleteQueryParams>,
+        ) -> Result<impl IntoResponse> {
+            state
+                .api_torrent_action_delete_with_opts(
+                    idx,
+                    DeleteOptions {
+                        delete_files: true,
+                        remove_empty_dirs: params.remove_empty_dirs.unwrap_or(false),
+                    },
+                )
+                .map(axum::Json)
+        }
+
+        async fn reload_config(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_reload_config().await.map(axum::Json)
+        }
+
+        async fn torrents_bulk_update(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<TorrentsBulkUpdateRequest>,
+        ) -> impl IntoResponse {
+            axum::Json(state.api_torrents_bulk_update(request.ids, request.changes))
+        }
+
+        async fn announce_enable(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_set_announce_enabled(true).map(axum::Json)
+        }
+
+        async fn announce_disable(State(state): State<ApiState>) -> Result<impl IntoResponse> {
+            state.api_set_announce_enabled(false).map(axum::Json)
+        }
+
+        async fn announce_seeding_interval(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<SeedingAnnounceIntervalRequest>,
         ) -> Result<impl IntoResponse> {
-            state.api_torrent_action_delete(idx).map(axum::Json)
+            state
+                .api_set_seeding_announce_interval(request)
+                .map(axum::Json)
         }
 
         async fn set_rust_log(
@@ -186,6 +810,13 @@ impl HttpApi {
             state.api_set_rust_log(new_value).map(axum::Json)
         }
 
+        async fn relocate_state(
+            State(state): State<ApiState>,
+            axum::Json(request): axum::Json<RelocateStateFilesRequest>,
+        ) -> Result<impl IntoResponse> {
+            state.api_relocate_state_files(request).map(axum::Json)
+        }
+
         async fn stream_logs(State(state): State<ApiState>) -> Result<impl IntoResponse> {
             let s = state.api_log_lines_stream()?.map_err(|e| {
                 debug!(error=%e, "stream_logs");
@@ -195,39 +826,137 @@ impl HttpApi {
         }
 
         let mut app = Router::new()
-            .route("/", get(api_root))
             .route("/stream_logs", get(stream_logs))
             .route("/rust_log", post(set_rust_log))
+            .route("/stats", get(session_stats))
+            .route("/listen", get(listen_status))
+            .route("/connect_rate", get(connect_rate))
+            .route("/profile", get(profile_export))
+            .route("/upnp", get(upnp_status))
+            .route("/storage", get(storage_stats))
+            .route("/queue", get(queue))
+            .route("/collections", get(collections_list))
+            .route("/collections/:name/stats", get(collection_stats))
             .route("/dht/stats", get(dht_stats))
             .route("/dht/table", get(dht_table))
+            .route("/dht/lookups", get(dht_lookups))
             .route("/torrents", get(torrents_list))
+            .route("/torrents/resolving", get(torrents_resolving))
+            .route(
+                "/torrents/resolving/:info_hash/diagnose",
+                get(magnet_diagnose),
+            )
+            .route("/torrents/by_path", get(torrents_by_path))
+            .route("/torrents/export_stats", get(torrents_export_stats))
+            .route("/torrents/stats_stream", get(torrents_stats_stream))
             .route("/torrents/:id", get(torrent_details))
             .route("/torrents/:id/haves", get(torrent_haves))
+            .route("/torrents/:id/raw_info", get(torrent_raw_info))
+            .route("/torrents/:id/magnet", get(torrent_magnet))
+            .route("/torrents/:id/fastresume", get(torrent_fastresume))
+            .route("/torrents/:id/zip", get(torrent_zip))
             .route("/torrents/:id/stats", get(torrent_stats_v0))
             .route("/torrents/:id/stats/v1", get(torrent_stats_v1))
-            .route("/torrents/:id/peer_stats", get(peer_stats));
+            .route("/torrents/:id/peer_stats", get(peer_stats))
+            .route(
+                "/torrents/:id/poisoned_pieces",
+                get(torrent_poisoned_pieces),
+            )
+            .route("/torrents/:id/peer_events", get(torrent_peer_events))
+            .route("/torrents/:id/piece_latency", get(torrent_piece_latency))
+            .route(
+                "/torrents/:id/verification_failures",
+                get(torrent_verification_failures),
+            )
+            .route("/torrents/:id/trackers", get(torrent_trackers))
+            .route(
+                "/torrents/:id/announce_history",
+                get(torrent_announce_history),
+            )
+            .route("/torrents/:id/swarm_health", get(torrent_swarm_health))
+            .route("/torrents/:id/diagnose", get(torrent_diagnose))
+            .route("/torrents/:id/compare/:other", get(torrent_compare));
+
+        #[cfg(feature = "debug-api")]
+        {
+            app = app
+                .route("/torrents/:id/picker_plan", get(torrent_picker_plan))
+                .route("/torrents/:id/debug_dump", get(torrent_debug_dump))
+                .route("/debug/bencode", post(debug_bencode));
+        }
 
         if !self.opts.read_only {
             app = app
                 .route("/torrents", post(torrents_post))
+                .route("/torrents/bulk_update", post(torrents_bulk_update))
+                .route("/torrents/pause_by_path", post(torrents_pause_by_path))
                 .route("/torrents/:id/pause", post(torrent_action_pause))
                 .route("/torrents/:id/start", post(torrent_action_start))
+                .route("/torrents/:id/load", post(torrent_action_load))
+                .route("/torrents/:id/recheck", post(torrent_action_recheck))
+                .route("/torrents/:id/cancel_verify", post(torrent_cancel_verify))
+                .route("/torrents/:id/solo", post(torrent_action_solo))
+                .route("/solo/clear", post(solo_clear))
+                .route("/collections/:name", post(collection_create))
+                .route("/collections/:name/delete", post(collection_delete))
+                .route("/collections/:name/add/:id", post(collection_add))
+                .route("/collections/:name/remove/:id", post(collection_remove))
+                .route("/collections/:name/pause", post(collection_pause))
+                .route("/collections/:name/start", post(collection_start))
+                .route(
+                    "/collections/:name/torrents/forget",
+                    post(collection_forget_torrents),
+                )
+                .route(
+                    "/collections/:name/torrents/delete",
+                    post(collection_delete_torrents),
+                )
+                .route("/torrents/:id/lock", post(torrent_lock))
+                .route("/torrents/:id/unlock", post(torrent_unlock))
+                .route("/torrents/:id/boost", post(torrent_boost))
+                .route(
+                    "/torrents/:id/priority_tier",
+                    post(torrent_set_priority_tier),
+                )
+                .route("/torrents/:id/upload_slots", post(torrent_set_upload_slots))
+                .route(
+                    "/torrents/:id/trackers/revive",
+                    post(torrent_revive_tracker),
+                )
+                .route("/torrents/:id/peer_opts", post(torrent_set_peer_opts))
+                .route("/torrents/:id/files/:file_id/sink", post(torrent_file_sink))
                 .route("/torrents/:id/forget", post(torrent_action_forget))
-                .route("/torrents/:id/delete", post(torrent_action_delete));
+                .route("/torrents/:id/delete", post(torrent_action_delete))
+                .route("/torrents/:id/metadata", post(torrent_set_metadata))
+                .route("/torrents/:id/deadlines", post(torrent_set_deadlines))
+                .route("/queue/sort", post(queue_sort))
+                .route("/torrents/:id/dequeue", post(queue_dequeue))
+                .route("/relocate_state", post(relocate_state))
+                .route("/config/reload", post(reload_config))
+                .route("/dht/read_only/enable", post(dht_read_only_enable))
+                .route("/dht/read_only/disable", post(dht_read_only_disable))
+                .route("/dht/warmup", post(dht_warmup))
+                .route("/upnp/remap", post(upnp_remap))
+                .route("/connect_rate", post(set_connect_rate))
+                .route("/profile", post(profile_import))
+                .route("/announce/enable", post(announce_enable))
+                .route("/announce/disable", post(announce_disable))
+                .route(
+                    "/announce/seeding_interval",
+                    post(announce_seeding_interval),
+                );
         }
 
         #[cfg(feature = "webui")]
         {
-            let webui_router = Router::new()
-                .route(
-                    "/",
-                    get(|| async {
-                        (
-                            [("Content-Type", "text/html")],
-                            include_str!("../webui/dist/index.html"),
-                        )
-                    }),
+            fn webui_index() -> impl IntoResponse {
+                (
+                    [("Content-Type", "text/html")],
+                    include_str!("../webui/dist/index.html"),
                 )
+            }
+
+            let webui_assets = Router::new()
                 .route(
                     "/assets/index.js",
                     get(|| async {
@@ -256,7 +985,40 @@ impl HttpApi {
                     }),
                 );
 
+            let webui_router = Router::new()
+                .route("/", get(|| async { webui_index() }))
+                .merge(webui_assets.clone());
+
             app = app.nest("/web/", webui_router);
+
+            if self.opts.serve_webui_at_root {
+                // Browsers get the UI, API clients (including the UI itself) still get the
+                // list of APIs.
+                app = app
+                    .route(
+                        "/",
+                        get(move |headers: axum::http::HeaderMap| async move {
+                            if accepts_html(&headers) {
+                                webui_index().into_response()
+                            } else {
+                                api_root(read_only).await.into_response()
+                            }
+                        }),
+                    )
+                    .merge(webui_assets);
+            } else {
+                app = app.route("/", get(move || api_root(read_only)));
+            }
+        }
+
+        #[cfg(not(feature = "webui"))]
+        {
+            if self.opts.serve_webui_at_root {
+                tracing::warn!(
+                    "serve_webui_at_root is set, but rqbit was built without the webui feature"
+                );
+            }
+            app = app.route("/", get(move || api_root(read_only)));
         }
 
         let cors_layer = {
@@ -296,22 +1058,115 @@ impl HttpApi {
     }
 }
 
-pub(crate) struct OnlyFiles(Vec<usize>);
+#[derive(Deserialize)]
+struct DeleteQueryParams {
+    remove_empty_dirs: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct TorrentBoostRequest {
+    duration_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct PriorityTierRequest {
+    tier: PriorityTier,
+}
+
+#[derive(Deserialize)]
+struct UploadSlotsRequest {
+    slots: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ReviveTrackerRequest {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct FileSinkRequest {
+    path: std::path::PathBuf,
+    #[serde(default)]
+    sequential: bool,
+}
+
+#[derive(Deserialize)]
+struct PathPrefixParams {
+    prefix: std::path::PathBuf,
+}
+
+#[derive(Deserialize)]
+struct ZipQueryParams {
+    files: Option<OnlyFiles>,
+    wait: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ExportStatsParams {
+    format: Option<StatsExportFormat>,
+}
+
+#[derive(Deserialize)]
+struct StatsStreamParams {
+    interval_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ProfileExportParams {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MagnetParams {
+    #[serde(default)]
+    include_peers: bool,
+}
+
+#[cfg(feature = "debug-api")]
+const DEFAULT_PICKER_PLAN_PIECES: usize = 16;
+
+#[cfg(feature = "debug-api")]
+#[derive(Deserialize)]
+struct PickerPlanParams {
+    n: Option<usize>,
+}
+
+pub(crate) struct OnlyFiles(pub Vec<usize>);
 pub(crate) struct InitialPeers(pub Vec<SocketAddr>);
 
 #[derive(Serialize, Deserialize, Default)]
 pub(crate) struct TorrentAddQueryParams {
     pub overwrite: Option<bool>,
+    pub existing_data_policy: Option<ExistingDataPolicy>,
     pub output_folder: Option<String>,
     pub sub_folder: Option<String>,
     pub only_files_regex: Option<String>,
     pub only_files: Option<OnlyFiles>,
     pub peer_connect_timeout: Option<u64>,
     pub peer_read_write_timeout: Option<u64>,
+    pub peer_handshake_timeout: Option<u64>,
     pub initial_peers: Option<InitialPeers>,
     // Will force interpreting the content as a URL.
     pub is_url: Option<bool>,
     pub list_only: Option<bool>,
+    pub no_persist: Option<bool>,
+    pub contiguous_storage: Option<bool>,
+    // Comma-separated list of subnets to upload to, e.g. "10.0.0.0/8,192.168.1.0/24".
+    pub upload_subnets: Option<String>,
+    // Comma-separated list of directories to mirror the data to, see
+    // AddTorrentOptions::mirror_paths. They have to be in SessionOptions::mirror_dir.
+    pub mirror_paths: Option<String>,
+    // In seconds.
+    pub seeding_announce_interval: Option<u64>,
+    // See ExpectedMetadata.
+    pub expect_min_bytes: Option<u64>,
+    pub expect_max_bytes: Option<u64>,
+    pub expect_file_count: Option<usize>,
+    pub pause_at_percent: Option<f64>,
+    pub sequential_after_percent: Option<f64>,
+    // Comma-separated file ids, see AddTorrentOptions::sample_files.
+    pub sample_files: Option<OnlyFiles>,
+    pub cross_seed: Option<bool>,
 }
 
 impl Serialize for OnlyFiles {
@@ -385,18 +1240,134 @@ impl TorrentAddQueryParams {
     pub fn into_add_torrent_options(self) -> AddTorrentOptions {
         AddTorrentOptions {
             overwrite: self.overwrite.unwrap_or(false),
+            existing_data_policy: self.existing_data_policy,
             only_files_regex: self.only_files_regex,
             only_files: self.only_files.map(|o| o.0),
             output_folder: self.output_folder,
             sub_folder: self.sub_folder,
             list_only: self.list_only.unwrap_or(false),
+            no_persist: self.no_persist.unwrap_or(false),
+            contiguous_storage: self.contiguous_storage.unwrap_or(false),
             initial_peers: self.initial_peers.map(|i| i.0),
+            upload_subnets: self.upload_subnets.map(|s| {
+                s.split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_owned())
+                    .collect()
+            }),
+            mirror_paths: self
+                .mirror_paths
+                .map(|s| {
+                    s.split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(std::path::PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            seeding_announce_interval: self.seeding_announce_interval.map(Duration::from_secs),
+            pause_at_percent: self.pause_at_percent,
+            sequential_after_percent: self.sequential_after_percent,
+            sample_files: self.sample_files.map(|f| f.0),
+            cross_seed: self.cross_seed.unwrap_or(false),
+            expect: match (
+                self.expect_min_bytes,
+                self.expect_max_bytes,
+                self.expect_file_count,
+            ) {
+                (None, None, None) => None,
+                (min_total_bytes, max_total_bytes, file_count) => Some(ExpectedMetadata {
+                    min_total_bytes,
+                    max_total_bytes,
+                    file_count,
+                }),
+            },
             peer_opts: Some(PeerConnectionOptions {
                 connect_timeout: self.peer_connect_timeout.map(Duration::from_secs),
                 read_write_timeout: self.peer_read_write_timeout.map(Duration::from_secs),
+                handshake_timeout: self.peer_handshake_timeout.map(Duration::from_secs),
                 ..Default::default()
             }),
             ..Default::default()
         }
     }
 }
+
+#[cfg(feature = "webui")]
+fn accepts_html(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{HttpApi, HttpApiOptions};
+    use crate::{
+        api::Api,
+        test_util::{test_dir, test_session},
+    };
+
+    #[tokio::test]
+    async fn test_root_with_webui_at_root() {
+        let dir = test_dir("http-api-root");
+        let session = test_session(&dir, Default::default()).await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let api = HttpApi::new(
+            Api::new(session.clone(), None, None),
+            Some(HttpApiOptions {
+                read_only: true,
+                serve_webui_at_root: true,
+            }),
+        );
+        let server = tokio::spawn(api.make_http_api_and_run(([127, 0, 0, 1], port).into()));
+        let url = format!("http://127.0.0.1:{port}/");
+        let client = reqwest::Client::new();
+        let get = |accept: &'static str| client.get(&url).header("Accept", accept).send();
+        let mut response = get("application/json").await;
+        for _ in 0..500 {
+            if response.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            response = get("application/json").await;
+        }
+
+        // API clients get the list of APIs, telling them what they can't do.
+        let root: serde_json::Value = response.unwrap().json().await.unwrap();
+        assert_eq!(root["read_only"], true);
+        // Browsers get the UI, if it's built in.
+        let html = get("text/html,*/*").await.unwrap();
+        let content_type = html.headers()["content-type"].to_str().unwrap().to_owned();
+        assert_eq!(
+            content_type.starts_with("text/html"),
+            cfg!(feature = "webui")
+        );
+
+        server.abort();
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "webui")]
+    #[test]
+    fn test_accepts_html() {
+        let headers = |accept: &str| {
+            let mut headers = axum::http::HeaderMap::new();
+            headers.insert(axum::http::header::ACCEPT, accept.parse().unwrap());
+            headers
+        };
+        assert!(super::accepts_html(&headers(
+            "text/html,application/xhtml+xml,*/*;q=0.8"
+        )));
+        assert!(!super::accepts_html(&headers("application/json")));
+        assert!(!super::accepts_html(&Default::default()));
+    }
+}
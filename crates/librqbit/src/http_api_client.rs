@@ -3,7 +3,7 @@ use serde::Deserialize;
 
 use crate::{
     api::ApiAddTorrentResponse,
-    http_api::TorrentAddQueryParams,
+    http_api::{OnlyFiles, TorrentAddQueryParams},
     session::{AddTorrent, AddTorrentOptions},
 };
 
@@ -87,23 +87,101 @@ impl HttpApiClient {
         let opts = opts.unwrap_or_default();
         let params = TorrentAddQueryParams {
             overwrite: Some(opts.overwrite),
+            existing_data_policy: opts.existing_data_policy,
             only_files_regex: opts.only_files_regex,
             only_files: None,
             output_folder: opts.output_folder,
             sub_folder: opts.sub_folder,
             list_only: Some(opts.list_only),
+            no_persist: Some(opts.no_persist),
+            contiguous_storage: Some(opts.contiguous_storage),
+            upload_subnets: opts.upload_subnets.map(|s| s.join(",")),
+            mirror_paths: Some(opts.mirror_paths).filter(|p| !p.is_empty()).map(|p| {
+                p.iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            seeding_announce_interval: opts.seeding_announce_interval.map(|d| d.as_secs()),
+            expect_min_bytes: opts.expect.as_ref().and_then(|e| e.min_total_bytes),
+            expect_max_bytes: opts.expect.as_ref().and_then(|e| e.max_total_bytes),
+            expect_file_count: opts.expect.as_ref().and_then(|e| e.file_count),
+            pause_at_percent: opts.pause_at_percent,
+            sequential_after_percent: opts.sequential_after_percent,
+            sample_files: opts.sample_files.map(OnlyFiles),
+            cross_seed: Some(opts.cross_seed),
+            peer_connect_timeout: opts
+                .peer_opts
+                .and_then(|o| o.connect_timeout)
+                .map(|d| d.as_secs()),
+            peer_read_write_timeout: opts
+                .peer_opts
+                .and_then(|o| o.read_write_timeout)
+                .map(|d| d.as_secs()),
+            peer_handshake_timeout: opts
+                .peer_opts
+                .and_then(|o| o.handshake_timeout)
+                .map(|d| d.as_secs()),
             ..Default::default()
         };
         let qs = serde_urlencoded::to_string(&params).unwrap();
         let url = format!("{}torrents?{}", &self.base_url, qs);
-        let response = check_response(
-            self.client
-                .post(&url)
-                .body(torrent.into_bytes())
-                .send()
-                .await?,
-        )
-        .await?;
+        let body = match torrent {
+            AddTorrent::TorrentFilePath(path) => tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("error reading {path:?}"))?,
+            AddTorrent::TorrentInfo(_) => {
+                anyhow::bail!("can't send parsed torrent info over HTTP, pass the .torrent file")
+            }
+            torrent => torrent.into_bytes(),
+        };
+        let response = check_response(self.client.post(&url).body(body).send().await?).await?;
         json_response(response).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::{
+        session::AddTorrent,
+        test_util::{http_response, request_header, serve_http, test_dir},
+    };
+
+    use super::HttpApiClient;
+
+    #[tokio::test]
+    async fn test_add_torrent_from_path() {
+        let dir = test_dir("http-api-client");
+        let path = dir.join("test.torrent");
+        std::fs::write(&path, b"d4:infod4:name4:testee").unwrap();
+
+        let content_length = Arc::new(Mutex::new(None));
+        let addr = serve_http({
+            let content_length = content_length.clone();
+            move |request| {
+                *content_length.lock() = request_header(request, "content-length");
+                let body = br#"{"id":1,"details":{"info_hash":"00","name":"test","files":[]},"output_folder":"/tmp","seen_peers":null}"#;
+                http_response("200 OK", &[("Content-Type", "application/json")], body)
+            }
+        })
+        .await;
+        let client = HttpApiClient::new(&format!("http://{addr}/")).unwrap();
+
+        let response = client
+            .add_torrent(AddTorrent::from_path(&path), None)
+            .await
+            .unwrap();
+        assert_eq!(response.id, Some(1));
+        assert_eq!(content_length.lock().as_deref(), Some("22"));
+
+        let missing = client
+            .add_torrent(AddTorrent::from_path(dir.join("missing.torrent")), None)
+            .await;
+        assert!(missing.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use reqwest::{header, StatusCode};
+use tracing::debug;
+
+use crate::dns::{client_builder, DnsResolver};
+
+/// Torrent files are rarely more than a few megabytes, anything much larger is refused.
+pub(crate) const MAX_TORRENT_FILE_SIZE: usize = 32 * 1024 * 1024;
+
+const MAX_ATTEMPTS: u32 = 5;
+// Doubled after each interrupted attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+pub(crate) enum ReadBodyError {
+    TooLarge,
+    Interrupted(reqwest::Error),
+}
+
+/// Append the body of the response to "buf", refusing to grow it past "max_size" bytes.
+pub(crate) async fn read_capped_body(
+    response: &mut reqwest::Response,
+    max_size: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), ReadBodyError> {
+    if let Some(len) = response.content_length() {
+        if buf.len() as u64 + len > max_size as u64 {
+            return Err(ReadBodyError::TooLarge);
+        }
+    }
+    while let Some(chunk) = response.chunk().await.map_err(ReadBodyError::Interrupted)? {
+        if buf.len() + chunk.len() > max_size {
+            return Err(ReadBodyError::TooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(())
+}
+
+/// Download the URL into memory. If the connection drops midway, the download continues
+/// where it left off with a Range request, or starts over if the server doesn't support
+/// ranges.
+pub(crate) async fn download_resumable(
+    url: &str,
+    max_size: usize,
+    dns: Option<&Arc<DnsResolver>>,
+) -> anyhow::Result<Vec<u8>> {
+    let client = client_builder(dns).build()?;
+    let mut buf = Vec::new();
+    for attempt in 1..=MAX_ATTEMPTS {
+        if download_once(&client, url, max_size, &mut buf).await? {
+            return Ok(buf);
+        }
+        if attempt < MAX_ATTEMPTS {
+            let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+            debug!(
+                attempt,
+                downloaded = buf.len(),
+                "download of {url} interrupted, retrying in {delay:?}"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+    bail!("error downloading {url}: interrupted {MAX_ATTEMPTS} times")
+}
+
+// Returns false if the download was interrupted and should be retried. Bytes received so
+// far are left in "buf".
+async fn download_once(
+    client: &reqwest::Client,
+    url: &str,
+    max_size: usize,
+    buf: &mut Vec<u8>,
+) -> anyhow::Result<bool> {
+    let mut request = client.get(url);
+    if !buf.is_empty() {
+        request = request.header(header::RANGE, format!("bytes={}-", buf.len()));
+    }
+    let mut response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            debug!("error sending request to {url}: {e:#}");
+            return Ok(false);
+        }
+    };
+
+    let status = response.status();
+    if !status.is_success() {
+        bail!("GET {} returned {}", url, status)
+    }
+    if !buf.is_empty() {
+        let resumed_at = if status == StatusCode::PARTIAL_CONTENT {
+            content_range_start(&response)
+        } else {
+            None
+        };
+        if resumed_at != Some(buf.len() as u64) {
+            debug!("{url} can't be resumed at {}, starting over", buf.len());
+            buf.clear();
+        }
+    }
+
+    match read_capped_body(&mut response, max_size, buf).await {
+        Ok(()) => Ok(true),
+        Err(ReadBodyError::TooLarge) => bail!("{url} is larger than {max_size} bytes"),
+        Err(ReadBodyError::Interrupted(e)) => {
+            debug!("error reading response body from {url}: {e:#}");
+            Ok(false)
+        }
+    }
+}
+
+// The first byte offset in a "Content-Range: bytes <start>-<end>/<total>" header.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let value = response
+        .headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (start, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+    start.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::test_util::{http_response, request_header, serve_http};
+
+    use super::{download_resumable, MAX_TORRENT_FILE_SIZE};
+
+    const BODY: &[u8] = b"d8:announce35:http://tracker.example.com/announcee";
+
+    // Serves BODY, but cuts the connection after "cut_at" bytes of the first response.
+    // Returns the URL and the Range headers of all requests so far.
+    async fn serve(
+        cut_at: usize,
+        support_ranges: bool,
+    ) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let requests = ranges.clone();
+        let addr = serve_http(move |request| {
+            let range = request_header(request, "range")
+                .and_then(|r| r.strip_prefix("bytes=").map(|r| r.to_owned()));
+            let mut requests = requests.lock();
+            requests.push(range.clone());
+
+            let start = match range {
+                Some(r) if support_ranges => r.trim_end_matches('-').parse::<usize>().unwrap(),
+                _ => 0,
+            };
+            let mut response = if start > 0 {
+                let content_range = format!("bytes {}-{}/{}", start, BODY.len() - 1, BODY.len());
+                http_response(
+                    "206 Partial Content",
+                    &[("Content-Range", &content_range)],
+                    &BODY[start..],
+                )
+            } else {
+                http_response("200 OK", &[], BODY)
+            };
+            if requests.len() == 1 {
+                response.truncate(response.len() - BODY.len() + cut_at);
+            }
+            response
+        })
+        .await;
+        (format!("http://{addr}/t.torrent"), ranges)
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_with_range_request() {
+        let (url, ranges) = serve(10, true).await;
+        let body = download_resumable(&url, MAX_TORRENT_FILE_SIZE, None)
+            .await
+            .unwrap();
+        assert_eq!(body, BODY);
+        assert_eq!(*ranges.lock(), vec![None, Some("10-".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn test_download_starts_over_without_range_support() {
+        let (url, ranges) = serve(10, false).await;
+        let body = download_resumable(&url, MAX_TORRENT_FILE_SIZE, None)
+            .await
+            .unwrap();
+        assert_eq!(body, BODY);
+        assert_eq!(*ranges.lock(), vec![None, Some("10-".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn test_download_size_limit() {
+        let (url, _ranges) = serve(BODY.len(), true).await;
+        assert!(download_resumable(&url, 10, None).await.is_err());
+    }
+}
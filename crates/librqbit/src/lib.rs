@@ -24,37 +24,107 @@
 
 pub mod api;
 mod api_error;
+mod bandwidth;
 mod chunk_tracker;
+mod connect_ramp;
+mod cross_seed;
+#[cfg(feature = "debug-api")]
+mod debug_bencode;
 mod dht_utils;
+mod diagnose;
+mod dns;
+mod error_retry;
+mod expected_metadata;
+mod fastresume;
 mod file_ops;
+mod file_permissions;
+mod file_sink;
+mod first_piece;
 pub mod http_api;
 pub mod http_api_client;
+mod http_download;
+mod magnet_link;
+mod path_conflict;
+mod peer_budget;
 mod peer_connection;
 mod peer_info_reader;
+mod power_profile;
+mod profile;
+mod queue;
 mod read_buf;
+mod request_limiter;
 mod session;
+mod sleep_detector;
 mod spawn_utils;
+mod stats_delta;
+mod storage;
+#[cfg(test)]
+mod test_util;
+mod torrent_collections;
 mod torrent_state;
 pub mod tracing_subscriber_config_utils;
 mod tracker_comms;
+mod tracker_schedule;
 mod type_aliases;
+mod upload_saturation;
+mod verify_from_memory;
+mod zip_stream;
 
 pub use api::Api;
 pub use api_error::ApiError;
+pub use bandwidth::{BandwidthLimitStats, LinkCapacity};
 pub use dht;
+pub use diagnose::TorrentDiagnosis;
+pub use dns::DnsConfig;
+pub use expected_metadata::ExpectedMetadata;
+pub use file_ops::FsyncPolicy;
+pub use first_piece::FirstPieceStrategy;
+pub use path_conflict::PathConflictPolicy;
 pub use peer_connection::PeerConnectionOptions;
+pub use power_profile::PowerProfile;
+pub use profile::{
+    ProfileImportResult, ProfileRuntimeSettings, ProfileStartupSettings, SessionProfile,
+    SESSION_PROFILE_VERSION,
+};
+pub use queue::QueueSortKey;
 pub use session::{
-    AddTorrent, AddTorrentOptions, AddTorrentResponse, ListOnlyResponse, Session, SessionOptions,
-    SUPPORTED_SCHEMES,
+    AddTorrent, AddTorrentOptions, AddTorrentResponse, DeleteOptions, IdleShutdownHook,
+    ListOnlyResponse, ListenStatus, ResolvingMagnet, Session, SessionEvent, SessionOptions,
+    SessionStats, SoloMode, UpnpStatus, SUPPORTED_SCHEMES,
 };
 pub use spawn_utils::spawn as librqbit_spawn;
-pub use torrent_state::{ManagedTorrent, ManagedTorrentState};
+pub use stats_delta::{StatsDelta, StatsDeltaTracker};
+pub use storage::StorageRootStats;
+pub use torrent_collections::CollectionStats;
+pub use torrent_state::deadlines::PieceDeadline;
+pub use torrent_state::events::{PoisonedPiece, TorrentEvent};
+pub use torrent_state::peer_events::{PeerDisconnectReason, PeerEvent};
+pub use torrent_state::peer_source::PeerSource;
+pub use torrent_state::piece_latency::{PieceLatencyBucket, PieceLatencyStats};
+pub use torrent_state::progress_thresholds::{ProgressAction, ProgressThreshold};
+pub use torrent_state::swarm_health::SwarmHealth;
+pub use torrent_state::tracker_stats::{AnnounceEvent, AnnounceRecord, TrackerStats};
+pub use torrent_state::verification::{
+    ChunkSource, FailedPieceFileRange, VerificationFailure, VerificationMode,
+};
+pub use torrent_state::{
+    ExistingDataPolicy, ManagedTorrent, ManagedTorrentState, MAX_USER_METADATA_SIZE,
+};
+pub use connect_ramp::{ConnectRateStats, StartupConnectRate};
+pub use error_retry::ErrorRetryOptions;
+pub use peer_budget::PriorityTier;
+pub use tracker_schedule::TrackerHostSchedule;
+pub use upload_saturation::{
+    UploadSaturationAction, UploadSaturationOptions, UploadSaturationStats,
+};
+pub use verify_from_memory::{VerifyFromMemoryOptions, VerifyFromMemoryStats};
 
 pub use buffers::*;
 pub use clone_to_owned::CloneToOwned;
 pub use librqbit_core::magnet::*;
 pub use librqbit_core::peer_id::*;
 pub use librqbit_core::torrent_metainfo::*;
+pub use librqbit_upnp::PortMappingStatus;
 
 /// The cargo version of librqbit.
 pub fn version() -> &'static str {
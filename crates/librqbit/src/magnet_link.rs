@@ -0,0 +1,102 @@
+// Magnet links for sharing torrents that are already in the session, with what's known about
+// them now: the trackers the torrent uses, and optionally the peers it's connected to, as
+// "x.pe" (BEP 9).
+
+use std::net::SocketAddr;
+
+use librqbit_core::hash_id::Id20;
+
+use crate::torrent_state::ManagedTorrent;
+
+// How many peers to put in a magnet link at most, keeping it short enough to paste.
+const MAX_MAGNET_PEERS: usize = 10;
+
+fn make_magnet_link(
+    info_hash: Id20,
+    name: Option<&str>,
+    trackers: &[String],
+    peers: &[SocketAddr],
+) -> String {
+    let mut magnet = format!("magnet:?xt=urn:btih:{}", info_hash.as_string());
+    if let Some(name) = name {
+        magnet.push_str("&dn=");
+        magnet.push_str(&urlencoding::encode(name));
+    }
+    for tracker in trackers {
+        magnet.push_str("&tr=");
+        magnet.push_str(&urlencoding::encode(tracker));
+    }
+    for peer in peers {
+        magnet.push_str("&x.pe=");
+        magnet.push_str(&urlencoding::encode(&peer.to_string()));
+    }
+    magnet
+}
+
+impl ManagedTorrent {
+    /// A magnet link for the torrent with the name and the trackers it uses now. With
+    /// "include_peers", up to 10 of the best connected peers are added as "x.pe", so that
+    /// the link also works without trackers or the DHT while this torrent is live.
+    pub fn magnet_link(&self, include_peers: bool) -> String {
+        let info = self.info();
+        let name = info
+            .info
+            .name
+            .as_ref()
+            .map(|n| String::from_utf8_lossy(n.as_slice()).into_owned());
+        let mut trackers: Vec<String> = info.trackers.iter().map(|t| t.to_string()).collect();
+        trackers.sort();
+        let peers = match self.live() {
+            Some(live) if include_peers => {
+                let mut peers = live.peers_to_cache();
+                peers.truncate(MAX_MAGNET_PEERS);
+                peers
+            }
+            _ => Vec::new(),
+        };
+        make_magnet_link(info.info_hash, name.as_deref(), &trackers, &peers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use librqbit_core::{hash_id::Id20, magnet::Magnet};
+
+    use super::make_magnet_link;
+
+    #[test]
+    fn test_make_magnet_link() {
+        let info_hash = Id20::from_str("a621779b5e3d486e127c3efbca9b6f8d135f52e5").unwrap();
+        let trackers = [
+            "udp://tracker.example.org:2710/announce".to_owned(),
+            "http://example.com/announce?passkey=a&b".to_owned(),
+        ];
+        let peers: [SocketAddr; 2] = [
+            "10.0.0.1:6881".parse().unwrap(),
+            "[::1]:51413".parse().unwrap(),
+        ];
+
+        let magnet = make_magnet_link(info_hash, Some("The Name & more"), &trackers, &peers);
+        assert_eq!(
+            magnet,
+            "magnet:?xt=urn:btih:a621779b5e3d486e127c3efbca9b6f8d135f52e5\
+             &dn=The%20Name%20%26%20more\
+             &tr=udp%3A%2F%2Ftracker.example.org%3A2710%2Fannounce\
+             &tr=http%3A%2F%2Fexample.com%2Fannounce%3Fpasskey%3Da%26b\
+             &x.pe=10.0.0.1%3A6881\
+             &x.pe=%5B%3A%3A1%5D%3A51413"
+        );
+
+        // Parses back, with the trackers intact.
+        let parsed = Magnet::parse(&magnet).unwrap();
+        assert_eq!(parsed.as_id20(), Some(info_hash));
+        assert_eq!(parsed.trackers, trackers);
+
+        assert_eq!(
+            make_magnet_link(info_hash, None, &[], &[]),
+            "magnet:?xt=urn:btih:a621779b5e3d486e127c3efbca9b6f8d135f52e5"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+// Keeping torrents from writing to each other's files, see SessionOptions::on_path_conflict.
+// Two torrents conflict when a file of one is also a file of the other, or a directory the
+// other needs to put its files in.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::TorrentId;
+
+/// What to do when a torrent being added would write to a path a torrent already in the
+/// session writes to. Torrents writing to the same files overwrite each other's data, and
+/// then fail their piece checks over and over.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathConflictPolicy {
+    /// Refuse to add the torrent, with [crate::ApiError::path_conflict].
+    Reject,
+    /// Put the torrent's files in a subfolder of its output folder named after its info
+    /// hash. The torrent is rejected if even that conflicts.
+    Subfolder,
+    /// The default. Add the torrent anyway, logging a warning.
+    #[default]
+    Warn,
+}
+
+// Returns the first of "paths" that conflicts with the files of "existing" torrents, and the
+// torrent it conflicts with.
+pub(crate) fn find_path_conflict(
+    existing: &[(TorrentId, Vec<PathBuf>)],
+    paths: &[PathBuf],
+) -> Option<(TorrentId, PathBuf)> {
+    let mut files: HashMap<&Path, TorrentId> = HashMap::new();
+    let mut dirs: HashMap<&Path, TorrentId> = HashMap::new();
+    for (id, paths) in existing {
+        for path in paths {
+            files.insert(path, *id);
+            for dir in path.ancestors().skip(1) {
+                if dirs.insert(dir, *id).is_some() {
+                    break;
+                }
+            }
+        }
+    }
+    paths.iter().find_map(|path| {
+        let id = files
+            .get(path.as_path())
+            .or_else(|| dirs.get(path.as_path()))
+            .or_else(|| path.ancestors().skip(1).find_map(|dir| files.get(dir)))?;
+        Some((*id, path.clone()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::find_path_conflict;
+
+    #[test]
+    fn test_find_path_conflict() {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let existing = [
+            (0, paths(&["/dl/a/1.mkv", "/dl/a/2.mkv"])),
+            (1, paths(&["/dl/b.iso"])),
+        ];
+
+        // Two torrents mapping to the same file.
+        assert_eq!(
+            find_path_conflict(&existing, &paths(&["/dl/c/1.mkv", "/dl/b.iso"])),
+            Some((1, PathBuf::from("/dl/b.iso")))
+        );
+        // A file where a torrent needs a directory, and the other way around.
+        assert_eq!(
+            find_path_conflict(&existing, &paths(&["/dl/a"])),
+            Some((0, PathBuf::from("/dl/a")))
+        );
+        assert_eq!(
+            find_path_conflict(&existing, &paths(&["/dl/b.iso/1.mkv"])),
+            Some((1, PathBuf::from("/dl/b.iso/1.mkv")))
+        );
+        // Sharing a directory is fine.
+        assert_eq!(
+            find_path_conflict(&existing, &paths(&["/dl/a/3.mkv", "/dl/c.iso"])),
+            None
+        );
+    }
+}
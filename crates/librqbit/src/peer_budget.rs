@@ -0,0 +1,109 @@
+// Sharing a session-wide budget of peer connections between the live torrents, see
+// crate::SessionOptions::peer_connection_budget. Each torrent gets a share weighted by its
+// PriorityTier. A torrent can't use more than MAX_LIVE_PEERS, so what a capped torrent
+// can't use goes to the others. Torrents over their share keep their peers, they just don't
+// connect to new ones until they're under it again.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::torrent_state::live::MAX_LIVE_PEERS;
+
+// How often the shares are recomputed, e.g. as torrents start and stop.
+pub(crate) const PEER_BUDGET_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How much of the session's peer connection budget a torrent gets, see
+/// [crate::SessionOptions::peer_connection_budget]. Unrelated to the download queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityTier {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl PriorityTier {
+    // Shares are proportional to this.
+    fn weight(self) -> usize {
+        match self {
+            PriorityTier::Low => 1,
+            PriorityTier::Normal => 2,
+            PriorityTier::High => 4,
+        }
+    }
+}
+
+// The peer limit of each torrent, in the order of "tiers". Every torrent gets at least one
+// peer, even if that's over the budget.
+pub(crate) fn allocate_peer_budget(budget: usize, tiers: &[PriorityTier]) -> Vec<usize> {
+    let mut limits = vec![0; tiers.len()];
+    let mut open: Vec<usize> = (0..tiers.len()).collect();
+    let mut left = budget;
+    // Torrents whose share is over the cap get the cap, and the rest is shared again.
+    loop {
+        let weights: usize = open.iter().map(|i| tiers[*i].weight()).sum();
+        if weights == 0 {
+            break;
+        }
+        let capped: Vec<usize> = open
+            .iter()
+            .copied()
+            .filter(|i| left * tiers[*i].weight() / weights >= MAX_LIVE_PEERS)
+            .collect();
+        if capped.is_empty() {
+            for i in open.iter() {
+                limits[*i] = left * tiers[*i].weight() / weights;
+            }
+            // What rounding left over goes to the heavier torrents first.
+            let mut rest = left - open.iter().map(|i| limits[*i]).sum::<usize>();
+            open.sort_by_key(|i| std::cmp::Reverse(tiers[*i].weight()));
+            for i in open.iter() {
+                if rest == 0 {
+                    break;
+                }
+                limits[*i] += 1;
+                rest -= 1;
+            }
+            break;
+        }
+        for i in capped.iter() {
+            limits[*i] = MAX_LIVE_PEERS;
+            left -= MAX_LIVE_PEERS;
+        }
+        open.retain(|i| !capped.contains(i));
+    }
+    limits.into_iter().map(|l| l.max(1)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::torrent_state::live::MAX_LIVE_PEERS;
+
+    use super::{allocate_peer_budget, PriorityTier::*};
+
+    #[test]
+    fn test_allocate_peer_budget() {
+        assert_eq!(
+            allocate_peer_budget(70, &[Low, Normal, High]),
+            vec![10, 20, 40]
+        );
+        // The remainder goes to the higher tiers.
+        assert_eq!(
+            allocate_peer_budget(10, &[Normal, High, Normal]),
+            vec![2, 6, 2]
+        );
+        assert!(allocate_peer_budget(100, &[]).is_empty());
+        // Every torrent can connect to someone.
+        assert_eq!(allocate_peer_budget(2, &[Low, Low, High]), vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn test_allocate_peer_budget_redistributes_capped() {
+        let limits = allocate_peer_budget(200, &[High, Low]);
+        assert_eq!(limits, vec![MAX_LIVE_PEERS, 200 - MAX_LIVE_PEERS]);
+        let limits = allocate_peer_budget(1000, &[High, Low]);
+        assert_eq!(limits, vec![MAX_LIVE_PEERS, MAX_LIVE_PEERS]);
+    }
+}
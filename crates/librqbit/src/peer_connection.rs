@@ -17,12 +17,23 @@ use serde_with::serde_as;
 use tokio::time::timeout;
 use tracing::trace;
 
-use crate::{read_buf::ReadBuf, spawn_utils::BlockingSpawner};
+use crate::{
+    bandwidth::RateLimiter,
+    read_buf::ReadBuf,
+    spawn_utils::BlockingSpawner,
+    torrent_state::peer_events::{PeerErrorContext, PeerTimeout},
+};
 
 pub trait PeerConnectionHandler {
     fn on_connected(&self, _connection_time: Duration) {}
     fn get_have_bytes(&self) -> u64;
+    // With the fast extension, "have all" is sent instead of the bitfield if this is true.
+    fn has_all_pieces(&self) -> bool {
+        false
+    }
     fn serialize_bitfield_message_to_buf(&self, buf: &mut Vec<u8>) -> anyhow::Result<usize>;
+    // Called before any messages are exchanged if both sides support the fast extension.
+    fn on_fast_extension(&self) {}
     fn on_handshake<B>(&self, handshake: Handshake<B>) -> anyhow::Result<()>;
     fn on_extended_handshake(
         &self,
@@ -30,6 +41,10 @@ pub trait PeerConnectionHandler {
     ) -> anyhow::Result<()>;
     fn on_received_message(&self, msg: Message<ByteBuf<'_>>) -> anyhow::Result<()>;
     fn on_uploaded_bytes(&self, bytes: u32);
+    // Waited on before each chunk is uploaded.
+    fn upload_limiter(&self) -> Option<&RateLimiter> {
+        None
+    }
     fn read_chunk(&self, chunk: &ChunkInfo, buf: &mut [u8]) -> anyhow::Result<()>;
 }
 
@@ -41,7 +56,7 @@ pub enum WriterRequest {
 }
 
 #[serde_as]
-#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PeerConnectionOptions {
     #[serde_as(as = "Option<serde_with::DurationSeconds>")]
     pub connect_timeout: Option<Duration>,
@@ -49,8 +64,34 @@ pub struct PeerConnectionOptions {
     #[serde_as(as = "Option<serde_with::DurationSeconds>")]
     pub read_write_timeout: Option<Duration>,
 
+    // For exchanging handshakes once connected. Falls back to read_write_timeout.
+    #[serde_as(as = "Option<serde_with::DurationSeconds>")]
+    pub handshake_timeout: Option<Duration>,
+
     #[serde_as(as = "Option<serde_with::DurationSeconds>")]
     pub keep_alive_interval: Option<Duration>,
+
+    // Disconnect peers that keep us choked for this long at a time while we're interested,
+    // so their slot goes to another peer. They aren't reconnected to. None keeps them.
+    #[serde_as(as = "Option<serde_with::DurationSeconds>")]
+    pub choke_timeout: Option<Duration>,
+}
+
+impl PeerConnectionOptions {
+    // Zero would time out every connection right away, or send keep alives in a loop.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let timeouts = [
+            self.connect_timeout,
+            self.read_write_timeout,
+            self.handshake_timeout,
+            self.keep_alive_interval,
+            self.choke_timeout,
+        ];
+        if timeouts.contains(&Some(Duration::ZERO)) {
+            bail!("peer timeouts must be more than 0");
+        }
+        Ok(())
+    }
 }
 
 pub(crate) struct PeerConnection<H> {
@@ -71,7 +112,7 @@ where
 {
     match timeout(timeout_value, fut).await {
         Ok(v) => v.map_err(Into::into),
-        Err(_) => anyhow::bail!("timeout at {timeout_value:?}"),
+        Err(_) => Err(PeerTimeout(timeout_value).into()),
     }
 }
 
@@ -109,6 +150,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
             .options
             .read_write_timeout
             .unwrap_or_else(|| Duration::from_secs(10));
+        let handshake_timeout = self.options.handshake_timeout.unwrap_or(rwtimeout);
 
         if handshake.info_hash != self.info_hash.0 {
             anyhow::bail!("wrong info hash");
@@ -122,11 +164,12 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
             "incoming connection: id={:?}",
             try_decode_peer_id(Id20::new(handshake.peer_id))
         );
+        let h_supports_fast = handshake.supports_fast();
 
         let mut write_buf = Vec::<u8>::with_capacity(PIECE_MESSAGE_DEFAULT_LEN);
         let handshake = Handshake::new(self.info_hash, self.peer_id);
         handshake.serialize(&mut write_buf);
-        with_timeout(rwtimeout, conn.write_all(&write_buf))
+        with_timeout(handshake_timeout, conn.write_all(&write_buf))
             .await
             .context("error writing handshake")?;
         write_buf.clear();
@@ -137,6 +180,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
 
         self.manage_peer(
             h_supports_extended,
+            h_supports_fast,
             read_buf,
             write_buf,
             conn,
@@ -155,6 +199,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
             .options
             .read_write_timeout
             .unwrap_or_else(|| Duration::from_secs(10));
+        let handshake_timeout = self.options.handshake_timeout.unwrap_or(rwtimeout);
 
         let connect_timeout = self
             .options
@@ -164,23 +209,24 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         let now = Instant::now();
         let mut conn = with_timeout(connect_timeout, tokio::net::TcpStream::connect(self.addr))
             .await
-            .context("error connecting")?;
+            .context(PeerErrorContext::Connecting)?;
         self.handler.on_connected(now.elapsed());
 
         let mut write_buf = Vec::<u8>::with_capacity(PIECE_MESSAGE_DEFAULT_LEN);
         let handshake = Handshake::new(self.info_hash, self.peer_id);
         handshake.serialize(&mut write_buf);
-        with_timeout(rwtimeout, conn.write_all(&write_buf))
+        with_timeout(handshake_timeout, conn.write_all(&write_buf))
             .await
             .context("error writing handshake")?;
         write_buf.clear();
 
         let mut read_buf = ReadBuf::new();
         let h = read_buf
-            .read_handshake(&mut conn, rwtimeout)
+            .read_handshake(&mut conn, handshake_timeout)
             .await
             .context("error reading handshake")?;
         let h_supports_extended = h.supports_extended();
+        let h_supports_fast = h.supports_fast();
         trace!("connected: id={:?}", try_decode_peer_id(Id20::new(h.peer_id)));
         if h.info_hash != self.info_hash.0 {
             anyhow::bail!("info hash does not match");
@@ -194,6 +240,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
 
         self.manage_peer(
             h_supports_extended,
+            h_supports_fast,
             read_buf,
             write_buf,
             conn,
@@ -205,6 +252,7 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
     async fn manage_peer(
         &self,
         handshake_supports_extended: bool,
+        handshake_supports_fast: bool,
         mut read_buf: ReadBuf,
         mut write_buf: Vec<u8>,
         mut conn: tokio::net::TcpStream,
@@ -220,6 +268,11 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
         let extended_handshake: RwLock<Option<ExtendedHandshake<ByteString>>> = RwLock::new(None);
         let extended_handshake_ref = &extended_handshake;
         let supports_extended = handshake_supports_extended;
+        // We always set the fast bit, so it's enough if the peer does too.
+        let supports_fast = handshake_supports_fast;
+        if supports_fast {
+            self.handler.on_fast_extension();
+        }
 
         if supports_extended {
             let my_extended =
@@ -240,14 +293,31 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
                 .keep_alive_interval
                 .unwrap_or_else(|| Duration::from_secs(120));
 
-            if self.handler.get_have_bytes() > 0 {
-                let len = self
+            let fast_message = if !supports_fast {
+                None
+            } else if self.handler.has_all_pieces() {
+                Some(MessageOwned::HaveAll)
+            } else if self.handler.get_have_bytes() == 0 {
+                Some(MessageOwned::HaveNone)
+            } else {
+                None
+            };
+            let len = match &fast_message {
+                Some(msg) => msg.serialize(&mut write_buf, &|| None)?,
+                None if self.handler.get_have_bytes() > 0 => self
                     .handler
-                    .serialize_bitfield_message_to_buf(&mut write_buf)?;
+                    .serialize_bitfield_message_to_buf(&mut write_buf)?,
+                // Without the fast extension, having nothing is said by sending nothing.
+                None => 0,
+            };
+            if len > 0 {
                 with_timeout(rwtimeout, write_half.write_all(&write_buf[..len]))
                     .await
                     .context("error writing bitfield to peer")?;
-                trace!("sent bitfield");
+                match &fast_message {
+                    Some(msg) => trace!("sent {:?}", msg),
+                    None => trace!("sent bitfield"),
+                }
             }
 
             loop {
@@ -269,6 +339,9 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
                             .and_then(|e| e.ut_metadata())
                     })?,
                     WriterRequest::ReadChunkRequest(chunk) => {
+                        if let Some(limiter) = self.handler.upload_limiter() {
+                            limiter.acquire(chunk.size).await;
+                        }
                         // this whole section is an optimization
                         write_buf.resize(PIECE_MESSAGE_DEFAULT_LEN, 0);
                         let preamble_len = serialize_piece_preamble(chunk, &mut write_buf);
@@ -320,12 +393,12 @@ impl<H: PeerConnectionHandler> PeerConnection<H> {
                         } else {
                             self.handler
                                 .on_received_message(message)
-                                .context("error in handler.on_received_message()")?;
+                                .context(PeerErrorContext::HandlingMessage)?;
                         }
                         Ok(())
                     })
                     .await
-                    .context("error reading message")?;
+                    .context(PeerErrorContext::ReadingMessage)?;
             }
 
             // For type inference.
@@ -24,15 +24,21 @@ use crate::{
     spawn_utils::BlockingSpawner,
 };
 
+/// The info dictionary received from a peer, both parsed and as the exact bytes that hash to
+/// the info hash.
+pub(crate) struct ReceivedInfo {
+    pub info: TorrentMetaV1Info<ByteString>,
+    pub info_bytes: ByteString,
+}
+
 pub(crate) async fn read_metainfo_from_peer(
     addr: SocketAddr,
     peer_id: Id20,
     info_hash: Id20,
     peer_connection_options: Option<PeerConnectionOptions>,
     spawner: BlockingSpawner,
-) -> anyhow::Result<TorrentMetaV1Info<ByteString>> {
-    let (result_tx, result_rx) =
-        tokio::sync::oneshot::channel::<anyhow::Result<TorrentMetaV1Info<ByteString>>>();
+) -> anyhow::Result<ReceivedInfo> {
+    let (result_tx, result_rx) = tokio::sync::oneshot::channel::<anyhow::Result<ReceivedInfo>>();
     let (writer_tx, writer_rx) = tokio::sync::mpsc::unbounded_channel::<WriterRequest>();
     let handler = Handler {
         addr,
@@ -131,8 +137,7 @@ struct Handler {
     addr: SocketAddr,
     info_hash: Id20,
     writer_tx: UnboundedSender<WriterRequest>,
-    result_tx:
-        Mutex<Option<tokio::sync::oneshot::Sender<anyhow::Result<TorrentMetaV1Info<ByteString>>>>>,
+    result_tx: Mutex<Option<tokio::sync::oneshot::Sender<anyhow::Result<ReceivedInfo>>>>,
     locked: RwLock<Option<HandlerLocked>>,
 }
 
@@ -169,7 +174,11 @@ impl PeerConnectionHandler for Handler {
                     .record_piece(piece, &data, self.info_hash)?;
             if piece_ready {
                 let buf = self.locked.write().take().unwrap().buffer;
-                let info = from_bytes::<TorrentMetaV1Info<ByteString>>(&buf);
+                let info =
+                    from_bytes::<TorrentMetaV1Info<ByteString>>(&buf).map(|info| ReceivedInfo {
+                        info,
+                        info_bytes: ByteString(buf),
+                    });
                 self.result_tx
                     .lock()
                     .take()
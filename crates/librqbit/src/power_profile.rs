@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::FsyncPolicy;
+
+/// How eagerly the session runs its periodic timers.
+///
+/// Every timer tick wakes the CPU up. On a laptop or phone running from battery that matters
+/// more than a few seconds of latency, so the coarser profiles trade responsiveness for fewer
+/// wakeups:
+/// - speed / ETA estimates are sampled less often, so they react slower to changes;
+/// - session state and the DHT routing table are dumped to disk less often, so more of it can
+///   be lost on a crash;
+/// - [FsyncPolicy::Interval] is stretched, batching more data per flush;
+/// - trackers are announced to less often, so new peers are discovered slower;
+/// - dead peers are retried with a longer backoff, so a swarm with flaky peers will ramp up
+///   slower.
+///
+/// Transfer rates of already connected peers are not throttled, but expect slower starts and
+/// less peers on swarms with few seeders with [PowerProfile::LowPower].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerProfile {
+    /// The default, no coarsening.
+    #[default]
+    Performance,
+    /// Coarsen timers somewhat.
+    Balanced,
+    /// Coarsen timers as much as reasonable, for running on battery.
+    LowPower,
+}
+
+impl PowerProfile {
+    pub(crate) fn stats_sample_interval(&self) -> Duration {
+        match self {
+            PowerProfile::Performance => Duration::from_secs(1),
+            PowerProfile::Balanced => Duration::from_secs(2),
+            PowerProfile::LowPower => Duration::from_secs(5),
+        }
+    }
+
+    pub(crate) fn session_dump_interval(&self) -> Duration {
+        match self {
+            PowerProfile::Performance => Duration::from_secs(10),
+            PowerProfile::Balanced => Duration::from_secs(30),
+            PowerProfile::LowPower => Duration::from_secs(60),
+        }
+    }
+
+    /// The "configured" one if set. None means leave it to the DHT default.
+    pub(crate) fn dht_dump_interval(&self, configured: Option<Duration>) -> Option<Duration> {
+        configured.or(match self {
+            PowerProfile::Performance => None,
+            PowerProfile::Balanced => Some(Duration::from_secs(30)),
+            PowerProfile::LowPower => Some(Duration::from_secs(120)),
+        })
+    }
+
+    /// Applied to the interval the tracker asked for. A forced tracker interval is used as is.
+    pub(crate) fn announce_interval(&self, interval: Duration) -> Duration {
+        match self {
+            PowerProfile::Performance => interval,
+            PowerProfile::Balanced => interval.mul_f32(1.5),
+            PowerProfile::LowPower => interval * 3,
+        }
+    }
+
+    /// Applied to the backoff before reconnecting to a dead peer.
+    pub(crate) fn reconnect_backoff(&self, backoff: Duration) -> Duration {
+        match self {
+            PowerProfile::Performance => backoff,
+            PowerProfile::Balanced => backoff * 2,
+            PowerProfile::LowPower => backoff * 4,
+        }
+    }
+
+    /// Stretches [FsyncPolicy::Interval] so that flushes get batched. Other policies are
+    /// about durability, not timers, and are left alone.
+    pub(crate) fn fsync_policy(&self, policy: FsyncPolicy) -> FsyncPolicy {
+        let min = match self {
+            PowerProfile::Performance => return policy,
+            PowerProfile::Balanced => Duration::from_secs(30),
+            PowerProfile::LowPower => Duration::from_secs(60),
+        };
+        match policy {
+            FsyncPolicy::Interval(interval) => FsyncPolicy::Interval(interval.max(min)),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::FsyncPolicy;
+
+    use super::PowerProfile;
+
+    #[test]
+    fn test_performance_keeps_defaults() {
+        let p = PowerProfile::Performance;
+        assert_eq!(p.stats_sample_interval(), Duration::from_secs(1));
+        assert_eq!(p.session_dump_interval(), Duration::from_secs(10));
+        assert_eq!(p.dht_dump_interval(None), None);
+        assert_eq!(
+            p.announce_interval(Duration::from_secs(1800)),
+            Duration::from_secs(1800)
+        );
+        assert_eq!(
+            p.fsync_policy(FsyncPolicy::Interval(Duration::from_secs(5))),
+            FsyncPolicy::Interval(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_dht_dump_interval() {
+        let balanced = PowerProfile::Balanced.dht_dump_interval(None).unwrap();
+        let low_power = PowerProfile::LowPower.dht_dump_interval(None).unwrap();
+        // The DHT dumps every 3 seconds by default.
+        assert!(balanced > Duration::from_secs(3));
+        assert!(low_power > balanced);
+
+        // What's configured wins, whatever the profile.
+        for p in [
+            PowerProfile::Performance,
+            PowerProfile::Balanced,
+            PowerProfile::LowPower,
+        ] {
+            assert_eq!(
+                p.dht_dump_interval(Some(Duration::from_secs(7))),
+                Some(Duration::from_secs(7))
+            );
+        }
+    }
+
+    #[test]
+    fn test_low_power_fsync_only_stretches_interval() {
+        let p = PowerProfile::LowPower;
+        assert_eq!(
+            p.fsync_policy(FsyncPolicy::Interval(Duration::from_secs(5))),
+            FsyncPolicy::Interval(Duration::from_secs(60))
+        );
+        assert_eq!(
+            p.fsync_policy(FsyncPolicy::Interval(Duration::from_secs(600))),
+            FsyncPolicy::Interval(Duration::from_secs(600))
+        );
+        assert_eq!(
+            p.fsync_policy(FsyncPolicy::OnPieceComplete),
+            FsyncPolicy::OnPieceComplete
+        );
+    }
+}
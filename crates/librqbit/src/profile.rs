@@ -0,0 +1,233 @@
+// Exporting the session's network, tracker and limit settings as a named profile, to set up
+// other instances the same way, see crate::api::Api::api_profile_export. Unlike the session
+// state, a profile has no torrents. Most of these settings are fixed when the session is
+// created, so importing a profile applies the ones that can change live and lists the others,
+// which take effect once the session is created with them, see
+// ProfileStartupSettings::apply_to. The CLI's "--profile" and the desktop app's "profile"
+// setting do both: apply_to before creating the session, and an import after.
+//
+// The format is versioned. Within a version, missing fields take their defaults and unknown
+// ones are ignored, so profiles from older and newer librqbit releases load. Profiles of a
+// newer version are refused, as their fields may mean something else.
+
+use std::{fs::File, io::BufReader, net::IpAddr, path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bandwidth::LinkCapacity, peer_connection::PeerConnectionOptions, session::SessionOptions,
+    torrent_state::peer_source::PeerSource,
+};
+
+/// The version of [SessionProfile] written by this release, and the newest one it reads.
+pub const SESSION_PROFILE_VERSION: u32 = 1;
+
+/// A session's configuration, see [crate::Session::export_profile].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionProfile {
+    /// See [SESSION_PROFILE_VERSION].
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub runtime: ProfileRuntimeSettings,
+    #[serde(default)]
+    pub startup: ProfileStartupSettings,
+}
+
+impl SessionProfile {
+    /// Read a profile saved from [crate::Session::export_profile], e.g. the output of
+    /// GET /profile. Profiles of an unsupported version are refused.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("error opening {path:?}"))?;
+        let profile: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("error parsing {path:?}"))?;
+        profile.check_version()?;
+        Ok(profile)
+    }
+
+    pub(crate) fn check_version(&self) -> anyhow::Result<()> {
+        if self.version == 0 || self.version > SESSION_PROFILE_VERSION {
+            bail!(
+                "unsupported profile version {}, expected 1 to {}",
+                self.version,
+                SESSION_PROFILE_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Settings that importing a profile applies right away.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProfileRuntimeSettings {
+    /// See [crate::Session::set_announce_enabled].
+    pub announce_enabled: bool,
+    /// See [crate::Session::set_seeding_announce_interval].
+    pub seeding_announce_interval_secs: Option<u64>,
+    /// See [crate::Session::set_startup_connect_rate]. None doesn't limit dials.
+    pub startup_connect_rate_per_sec: Option<u32>,
+    pub startup_connect_ramp_secs: Option<u64>,
+    /// See [dht::Dht::set_read_only]. Ignored without a DHT.
+    pub dht_read_only: bool,
+}
+
+impl Default for ProfileRuntimeSettings {
+    fn default() -> Self {
+        Self {
+            announce_enabled: true,
+            seeding_announce_interval_secs: None,
+            startup_connect_rate_per_sec: None,
+            startup_connect_ramp_secs: None,
+            dht_read_only: false,
+        }
+    }
+}
+
+/// Settings of [SessionOptions] that only take effect when the session is created. Each has
+/// the meaning of the [SessionOptions] field of the same name.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ProfileStartupSettings {
+    pub peer_opts: PeerConnectionOptions,
+    pub announce_ip: Option<IpAddr>,
+    pub enable_upnp_port_forwarding: bool,
+    pub peer_source_priority: Vec<PeerSource>,
+    pub upload_slots: Option<usize>,
+    pub max_torrents: Option<usize>,
+    pub max_active_downloads: Option<usize>,
+    pub peer_connection_budget: Option<usize>,
+    pub reserved_headroom_bps: Option<u64>,
+    pub link_capacity: LinkCapacity,
+    pub max_tracker_response_size: Option<usize>,
+    pub max_tracker_redirects: Option<usize>,
+    pub dead_tracker_timeout_secs: Option<u64>,
+}
+
+impl ProfileStartupSettings {
+    /// Set these settings on "opts", e.g. from a profile imported earlier, before creating
+    /// the session.
+    pub fn apply_to(&self, opts: &mut SessionOptions) {
+        opts.peer_opts = Some(self.peer_opts);
+        opts.announce_ip = self.announce_ip;
+        opts.enable_upnp_port_forwarding = self.enable_upnp_port_forwarding;
+        opts.peer_source_priority = self.peer_source_priority.clone();
+        opts.upload_slots = self.upload_slots;
+        opts.max_torrents = self.max_torrents;
+        opts.max_active_downloads = self.max_active_downloads;
+        opts.peer_connection_budget = self.peer_connection_budget;
+        opts.reserved_headroom_bps = self.reserved_headroom_bps;
+        opts.link_capacity = self.link_capacity;
+        opts.max_tracker_response_size = self.max_tracker_response_size;
+        opts.max_tracker_redirects = self.max_tracker_redirects;
+        opts.dead_tracker_timeout = self.dead_tracker_timeout_secs.map(Duration::from_secs);
+    }
+
+    // The names of the settings that differ between the two, in order.
+    pub(crate) fn changed_fields(&self, other: &ProfileStartupSettings) -> Vec<String> {
+        let (ours, theirs) = match (serde_json::to_value(self), serde_json::to_value(other)) {
+            (Ok(serde_json::Value::Object(ours)), Ok(serde_json::Value::Object(theirs))) => {
+                (ours, theirs)
+            }
+            _ => return Vec::new(),
+        };
+        let mut changed: Vec<String> = ours
+            .into_iter()
+            .filter(|(key, value)| theirs.get(key) != Some(value))
+            .map(|(key, _)| key)
+            .collect();
+        changed.sort();
+        changed
+    }
+}
+
+/// The result of [crate::Session::import_profile].
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileImportResult {
+    pub name: String,
+    /// The startup settings that differ from this session's. They take effect when the
+    /// session is created with them, see [ProfileStartupSettings::apply_to].
+    pub needs_restart: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{session::SessionOptions, test_util::test_dir};
+
+    use super::{ProfileStartupSettings, SessionProfile, SESSION_PROFILE_VERSION};
+
+    #[test]
+    fn test_profile_versions() {
+        // Unknown fields are ignored, missing ones take their defaults.
+        let profile: SessionProfile = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "name": "seedbox",
+            "runtime": {"announce_enabled": false, "from_the_future": 1},
+        }))
+        .unwrap();
+        assert!(profile.check_version().is_ok());
+        assert!(!profile.runtime.announce_enabled);
+        assert_eq!(profile.startup, ProfileStartupSettings::default());
+
+        let newer = SessionProfile {
+            version: SESSION_PROFILE_VERSION + 1,
+            ..profile.clone()
+        };
+        assert!(newer.check_version().is_err());
+        // The version can't be left out.
+        assert!(serde_json::from_value::<SessionProfile>(serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_startup_settings() {
+        let settings = ProfileStartupSettings {
+            upload_slots: Some(4),
+            max_tracker_redirects: Some(2),
+            dead_tracker_timeout_secs: Some(3600),
+            ..Default::default()
+        };
+        assert!(settings.changed_fields(&settings).is_empty());
+        assert_eq!(
+            settings.changed_fields(&ProfileStartupSettings::default()),
+            vec![
+                "dead_tracker_timeout_secs",
+                "max_tracker_redirects",
+                "upload_slots"
+            ]
+        );
+
+        let mut opts = SessionOptions::default();
+        settings.apply_to(&mut opts);
+        assert_eq!(opts.upload_slots, Some(4));
+        assert_eq!(opts.dead_tracker_timeout, Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_profile_from_file() {
+        let dir = test_dir("profile-from-file");
+        let path = dir.join("profile.json");
+        let profile = SessionProfile {
+            version: SESSION_PROFILE_VERSION,
+            name: "seedbox".to_owned(),
+            runtime: Default::default(),
+            startup: ProfileStartupSettings {
+                upload_slots: Some(4),
+                ..Default::default()
+            },
+        };
+        std::fs::write(&path, serde_json::to_vec(&profile).unwrap()).unwrap();
+        assert_eq!(SessionProfile::from_file(&path).unwrap(), profile);
+
+        let newer = SessionProfile {
+            version: SESSION_PROFILE_VERSION + 1,
+            ..profile
+        };
+        std::fs::write(&path, serde_json::to_vec(&newer).unwrap()).unwrap();
+        assert!(SessionProfile::from_file(&path).is_err());
+        assert!(SessionProfile::from_file(&dir.join("missing.json")).is_err());
+    }
+}
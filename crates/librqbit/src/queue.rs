@@ -0,0 +1,97 @@
+// The download queue. With SessionOptions::max_active_downloads set, torrents added while
+// that many are downloading start paused and wait in line, and are started from the front
+// as downloads finish. Starting a queued torrent by hand takes it out of the line.
+
+use std::{cmp::Ordering, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::TorrentId;
+
+/// How to reorder the download queue, see [crate::Session::sort_queue].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueSortKey {
+    /// Smallest first.
+    Size,
+    /// Oldest first.
+    AddedAt,
+    /// Least downloaded first, as a fraction of the size.
+    Progress,
+    /// By name, case-insensitive.
+    Name,
+}
+
+// What the queue is sorted by, for one queued torrent.
+pub(crate) struct QueueEntry {
+    pub id: TorrentId,
+    pub total_bytes: u64,
+    pub progress_bytes: u64,
+    pub added_at: SystemTime,
+    pub name: String,
+}
+
+fn progress_cmp(a: &QueueEntry, b: &QueueEntry) -> Ordering {
+    // a.progress / a.total vs b.progress / b.total, without floats.
+    let lhs = a.progress_bytes as u128 * b.total_bytes.max(1) as u128;
+    let rhs = b.progress_bytes as u128 * a.total_bytes.max(1) as u128;
+    lhs.cmp(&rhs)
+}
+
+// Stable, so torrents that compare equal keep their place relative to each other.
+pub(crate) fn sort_entries(entries: &mut [QueueEntry], key: QueueSortKey, reverse: bool) {
+    entries.sort_by(|a, b| {
+        let ord = match key {
+            QueueSortKey::Size => a.total_bytes.cmp(&b.total_bytes),
+            QueueSortKey::AddedAt => a.added_at.cmp(&b.added_at),
+            QueueSortKey::Progress => progress_cmp(a, b),
+            QueueSortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        };
+        if reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{sort_entries, QueueEntry, QueueSortKey};
+
+    fn entries() -> Vec<QueueEntry> {
+        [
+            (1, 300, 150, 20, "b"),
+            (2, 100, 90, 10, "C"),
+            (3, 200, 0, 30, "a"),
+        ]
+        .into_iter()
+        .map(
+            |(id, total_bytes, progress_bytes, added_at, name)| QueueEntry {
+                id,
+                total_bytes,
+                progress_bytes,
+                added_at: SystemTime::UNIX_EPOCH + Duration::from_secs(added_at),
+                name: name.to_owned(),
+            },
+        )
+        .collect()
+    }
+
+    fn sorted(key: QueueSortKey, reverse: bool) -> Vec<usize> {
+        let mut entries = entries();
+        sort_entries(&mut entries, key, reverse);
+        entries.into_iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        assert_eq!(sorted(QueueSortKey::Size, false), vec![2, 3, 1]);
+        assert_eq!(sorted(QueueSortKey::Size, true), vec![1, 3, 2]);
+        assert_eq!(sorted(QueueSortKey::AddedAt, true), vec![3, 1, 2]);
+        assert_eq!(sorted(QueueSortKey::Progress, false), vec![3, 1, 2]);
+        assert_eq!(sorted(QueueSortKey::Name, false), vec![3, 1, 2]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::Semaphore;
+
+/// Caps the number of chunk requests in flight across all torrents and peers of a session.
+///
+/// The semaphore is fair (FIFO), so when the budget is exhausted, peers waiting for it get
+/// it in turn, which spreads it across torrents and peers. Boosted torrents don't wait,
+/// and go over the budget if it's exhausted, see [RequestLimiter::acquire_now].
+#[derive(Default)]
+pub(crate) struct RequestLimiter {
+    semaphore: Option<Semaphore>,
+    max: Option<usize>,
+    outstanding: AtomicU64,
+    // Requests taken over the budget, paid back before permits are returned.
+    borrowed: AtomicU64,
+}
+
+impl RequestLimiter {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            semaphore: max.map(Semaphore::new),
+            max,
+            outstanding: AtomicU64::new(0),
+            borrowed: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait until there's room for one more request. Every successful call must be
+    /// paired with a release() once the request is answered or cancelled.
+    pub async fn acquire(&self) -> anyhow::Result<()> {
+        if let Some(sem) = self.semaphore.as_ref() {
+            sem.acquire().await?.forget();
+        }
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Like acquire(), but doesn't wait: if there's no room, the request goes over the
+    /// budget, and the next release() pays it back instead of returning permits.
+    pub fn acquire_now(&self) {
+        if let Some(sem) = self.semaphore.as_ref() {
+            match sem.try_acquire() {
+                Ok(permit) => permit.forget(),
+                Err(_) => {
+                    self.borrowed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        if let Some(sem) = self.semaphore.as_ref() {
+            let count = count as u64;
+            let borrowed = self
+                .borrowed
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                    Some(b.saturating_sub(count))
+                })
+                .unwrap_or_default();
+            sem.add_permits((count - borrowed.min(count)) as usize);
+        }
+        self.outstanding.fetch_sub(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn outstanding(&self) -> u64 {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestLimiter;
+
+    #[tokio::test]
+    async fn test_borrowed_requests_are_paid_back() {
+        let limiter = RequestLimiter::new(Some(1));
+        limiter.acquire().await.unwrap();
+        // Over the budget.
+        limiter.acquire_now();
+        assert_eq!(limiter.outstanding(), 2);
+
+        // The first release pays back the borrowed request, so there's still no room.
+        limiter.release(1);
+        assert!(limiter.semaphore.as_ref().unwrap().try_acquire().is_err());
+        limiter.release(1);
+        assert_eq!(limiter.outstanding(), 0);
+        assert_eq!(limiter.semaphore.as_ref().unwrap().available_permits(), 1);
+    }
+}
@@ -1,24 +1,29 @@
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     io::{BufReader, BufWriter, Read},
-    net::SocketAddr,
-    path::PathBuf,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{bail, Context};
 use bencode::{bencode_serialize_to_writer, BencodeDeserializer};
 use buffers::{ByteBuf, ByteBufT, ByteString};
 use clone_to_owned::CloneToOwned;
+use dashmap::DashMap;
 use dht::{
     Dht, DhtBuilder, DhtConfig, Id20, PersistentDht, PersistentDhtConfig, RequestPeersStream,
 };
 use futures::{stream::FuturesUnordered, StreamExt, TryFutureExt};
 use librqbit_core::{
     directories::get_configuration_directory,
+    lengths::Lengths,
     magnet::Magnet,
     peer_id::generate_peer_id,
     spawn_utils::spawn_with_cancel,
@@ -31,19 +36,69 @@ use peer_binary_protocol::Handshake;
 use reqwest::Url;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::serde_as;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, watch, Notify, Semaphore},
+};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, error_span, info, trace, warn, Instrument};
 
 use crate::{
-    dht_utils::{read_metainfo_from_peer_receiver, ReadMetainfoResult},
+    api_error::ApiError,
+    bandwidth::{BandwidthLimitStats, BandwidthLimiter, LinkCapacity, BANDWIDTH_UPDATE_INTERVAL},
+    connect_ramp::{ConnectRamp, ConnectRateStats, StartupConnectRate},
+    cross_seed::{match_files, CrossSeedSource},
+    dht_utils::{
+        read_metainfo_from_peer_receiver, MetadataFetchCounters, ReadMetainfoResult,
+        DEFAULT_MAX_METADATA_PEERS,
+    },
+    diagnose::TorrentDiagnosis,
+    dns::{DnsConfig, DnsResolver},
+    expected_metadata::ExpectedMetadata,
+    fastresume::FastResume,
+    file_ops::{is_read_only_fs_error, remove_empty_dirs, FsyncPolicy},
+    first_piece::FirstPieceStrategy,
+    http_download::{download_resumable, MAX_TORRENT_FILE_SIZE},
+    path_conflict::{find_path_conflict, PathConflictPolicy},
+    peer_budget::{allocate_peer_budget, PriorityTier, PEER_BUDGET_INTERVAL},
     peer_connection::PeerConnectionOptions,
+    error_retry::{
+        ErrorRetryAction, ErrorRetryOptions, ErrorRetryTracker, ERROR_RETRY_CHECK_INTERVAL,
+    },
+    power_profile::PowerProfile,
+    profile::{
+        ProfileImportResult, ProfileRuntimeSettings, ProfileStartupSettings, SessionProfile,
+        SESSION_PROFILE_VERSION,
+    },
+    queue::{sort_entries, QueueEntry, QueueSortKey},
     read_buf::ReadBuf,
+    request_limiter::RequestLimiter,
+    sleep_detector::{SleepDetector, SLEEP_CHECK_INTERVAL},
     spawn_utils::BlockingSpawner,
+    storage::{self, StorageRootStats},
+    torrent_collections::TorrentCollections,
     torrent_state::{
-        ManagedTorrentBuilder, ManagedTorrentHandle, ManagedTorrentState, TorrentStateLive,
+        events::TorrentEvent, peer_source::PeerSource, stats::LifetimeTransfer,
+        lazy::PersistedProgress, utils::parse_subnets, verification::VerificationMode,
+        ExistingDataPolicy, ManagedTorrentBuilder, ManagedTorrentHandle, ManagedTorrentState,
+        TorrentStateLive,
+    },
+    tracker_comms::tracker_client,
+    tracker_schedule::TrackerHostScheduler,
+    upload_saturation::{
+        UploadSaturationAction, UploadSaturationDetector, UploadSaturationOptions,
+        UploadSaturationStats,
     },
+    verify_from_memory::{PieceMemoryBudget, VerifyFromMemoryOptions, VerifyFromMemoryStats},
+};
+
+use crate::torrent_state::progress_thresholds::{
+    sample_files, threshold_percent, ProgressAction, ProgressThreshold,
 };
+use crate::torrent_state::tracker_stats::{
+    check_seeding_announce_interval, MIN_SEEDING_ANNOUNCE_INTERVAL,
+};
+use crate::torrent_state::utils::with_incomplete_suffix;
 
 pub const SUPPORTED_SCHEMES: [&str; 3] = ["http:", "https:", "magnet:"];
 
@@ -61,6 +116,29 @@ fn torrent_from_bytes(bytes: &[u8]) -> anyhow::Result<TorrentMetaV1Owned> {
 pub struct SessionDatabase {
     next_id: TorrentId,
     torrents: HashMap<TorrentId, ManagedTorrentHandle>,
+    solo: Option<SoloMode>,
+    // Torrents waiting for a download slot, in the order they'll be started.
+    queue: Vec<TorrentId>,
+    // Boosted torrents, see Session::boost.
+    boosts: HashMap<TorrentId, Boost>,
+    // See Session::create_collection.
+    collections: TorrentCollections,
+}
+
+// How a boosted torrent was before the boost, to put it back that way after.
+struct Boost {
+    until: std::time::Instant,
+    // Where it was in the download queue, if it was queued.
+    queue_position: Option<usize>,
+    was_paused: bool,
+}
+
+/// One torrent has all the bandwidth, see [Session::solo].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SoloMode {
+    pub id: TorrentId,
+    /// The torrents paused to make room, resumed by [Session::clear_solo].
+    pub paused: Vec<TorrentId>,
 }
 
 impl SessionDatabase {
@@ -86,12 +164,54 @@ impl SessionDatabase {
         idx
     }
 
-    fn serialize(&self) -> SerializedSessionDatabase {
+    // What the other torrents than "info_hash" write to, see find_path_conflict().
+    fn written_paths(&self, info_hash: Id20) -> anyhow::Result<Vec<(TorrentId, Vec<PathBuf>)>> {
+        self.torrents
+            .iter()
+            .filter(|(_, t)| t.info_hash() != info_hash)
+            .map(|(id, t)| Ok((*id, t.info().written_paths()?)))
+            .collect()
+    }
+
+    // The queue as it will be once the boosts end.
+    fn queue_after_boosts(&self) -> Vec<TorrentId> {
+        let mut queue = self.queue.clone();
+        let mut boosted: Vec<(usize, TorrentId)> = self
+            .boosts
+            .iter()
+            .filter_map(|(id, b)| Some((b.queue_position?, *id)))
+            .collect();
+        boosted.sort_unstable();
+        for (pos, id) in boosted {
+            queue.insert(pos.min(queue.len()), id);
+        }
+        queue
+    }
+
+    // Torrents taking up a download slot, see SessionOptions::max_active_downloads.
+    fn active_downloads(&self) -> usize {
+        self.torrents
+            .iter()
+            .filter(|(id, t)| {
+                !self.queue.contains(id)
+                    && t.with_state(|s| match s {
+                        ManagedTorrentState::Initializing(_) => true,
+                        ManagedTorrentState::Live(l) => !l.is_finished(),
+                        _ => false,
+                    })
+            })
+            .count()
+    }
+
+    fn serialize(&self, persist_peers: bool) -> SerializedSessionDatabase {
         SerializedSessionDatabase {
             torrents: self
                 .torrents
                 .iter()
+                .filter(|(_, torrent)| !torrent.info().options.no_persist)
                 .map(|(id, torrent)| {
+                    let lifetime = torrent.lifetime_transfer();
+                    let progress = torrent.persisted_progress();
                     (
                         *id,
                         SerializedTorrent {
@@ -101,16 +221,67 @@ impl SessionDatabase {
                                 .iter()
                                 .map(|u| u.to_string())
                                 .collect(),
+                            http_seeds: torrent
+                                .info()
+                                .http_seeds
+                                .iter()
+                                .map(|u| u.to_string())
+                                .collect(),
                             info_hash: torrent.info_hash().as_string(),
                             info: torrent.info().info.clone(),
                             only_files: torrent.only_files.clone(),
-                            is_paused: torrent
-                                .with_state(|s| matches!(s, ManagedTorrentState::Paused(_))),
+                            // Boosts aren't persisted, the torrent comes back as it was
+                            // before.
+                            is_paused: torrent.with_state(|s| s.is_paused())
+                                || self.boosts.get(id).map(|b| b.was_paused).unwrap_or(false),
                             output_folder: torrent.info().out_dir.clone(),
+                            labels: torrent.labels(),
+                            user_metadata: torrent.user_metadata(),
+                            sequential: torrent.is_sequential(),
+                            seeding_announce_interval_secs: torrent
+                                .seeding_announce_interval()
+                                .map(|d| d.as_secs()),
+                            upload_subnets: torrent
+                                .info()
+                                .options
+                                .upload_subnets
+                                .as_ref()
+                                .map(|nets| nets.iter().map(|n| n.to_string()).collect()),
+                            added_at: Some(torrent.added_at_secs()),
+                            uploaded_bytes: lifetime.uploaded_bytes,
+                            downloaded_bytes: lifetime.downloaded_bytes,
+                            web_seed_bytes: lifetime.web_seed_bytes,
+                            tracker_redirects: torrent
+                                .tracker_redirects()
+                                .into_iter()
+                                .map(|(from, to)| (from.to_string(), to.to_string()))
+                                .collect(),
+                            dead_trackers: torrent
+                                .dead_trackers()
+                                .iter()
+                                .map(|url| url.to_string())
+                                .collect(),
+                            locked: torrent.is_locked(),
+                            peers: if persist_peers {
+                                torrent.peer_cache()
+                            } else {
+                                Vec::new()
+                            },
+                            upload_slots: torrent.upload_slots(),
+                            priority_tier: torrent.priority_tier(),
+                            progress_thresholds: torrent.progress_thresholds(),
+                            peer_opts: Some(torrent.peer_opts())
+                                .filter(|o| *o != PeerConnectionOptions::default()),
+                            mirror_paths: torrent.info().options.mirror_paths.clone(),
+                            progress_bytes: progress.map(|p| p.progress_bytes),
+                            selected_bytes: progress.map(|p| p.total_bytes),
                         },
                     )
                 })
                 .collect(),
+            solo: self.solo.clone(),
+            queue: self.queue_after_boosts(),
+            collections: self.collections.clone(),
         }
     }
 }
@@ -124,9 +295,58 @@ struct SerializedTorrent {
     )]
     info: TorrentMetaV1Info<ByteString>,
     trackers: HashSet<String>,
+    #[serde(default)]
+    http_seeds: Vec<String>,
     output_folder: PathBuf,
     only_files: Option<Vec<usize>>,
     is_paused: bool,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    user_metadata: serde_json::Value,
+    #[serde(default)]
+    sequential: bool,
+    #[serde(default)]
+    seeding_announce_interval_secs: Option<u64>,
+    #[serde(default)]
+    upload_subnets: Option<Vec<String>>,
+    // Unix timestamp. Not set in session files written by older versions.
+    #[serde(default)]
+    added_at: Option<u64>,
+    #[serde(default)]
+    uploaded_bytes: u64,
+    #[serde(default)]
+    downloaded_bytes: u64,
+    #[serde(default)]
+    web_seed_bytes: u64,
+    // Announce URL -> where it redirected to.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    tracker_redirects: HashMap<String, String>,
+    // See SessionOptions::dead_tracker_timeout.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dead_trackers: Vec<String>,
+    #[serde(default)]
+    locked: bool,
+    // See SessionOptions::persist_peers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    peers: Vec<SocketAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    upload_slots: Option<usize>,
+    #[serde(default)]
+    priority_tier: PriorityTier,
+    // The ones not reached yet. With sample files, they are the only files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    progress_thresholds: Vec<ProgressThreshold>,
+    // The torrent's own peer timeouts, if it has any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer_opts: Option<PeerConnectionOptions>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mirror_paths: Vec<PathBuf>,
+    // Shown while the torrent is lazy, see SessionOptions::lazy_load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    progress_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    selected_bytes: Option<u64>,
 }
 
 fn serialize_torrent<S>(t: &TorrentMetaV1Info<ByteString>, serializer: S) -> Result<S::Ok, S::Error>
@@ -158,12 +378,21 @@ where
 #[derive(Serialize, Deserialize)]
 struct SerializedSessionDatabase {
     torrents: HashMap<usize, SerializedTorrent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    solo: Option<SoloMode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    queue: Vec<TorrentId>,
+    #[serde(default, skip_serializing_if = "TorrentCollections::is_empty")]
+    collections: TorrentCollections,
 }
 
 pub struct Session {
     peer_id: Id20,
     dht: Option<Dht>,
-    persistence_filename: PathBuf,
+    persistence: bool,
+    persistence_filename: RwLock<PathBuf>,
+    persist_peers: bool,
+    lazy_load: bool,
     peer_opts: PeerConnectionOptions,
     spawner: BlockingSpawner,
     db: RwLock<SessionDatabase>,
@@ -171,23 +400,253 @@ pub struct Session {
 
     tcp_listen_port: Option<u16>,
 
+    file_mode: Option<u32>,
+    file_group: Option<u32>,
+    fsync_policy: FsyncPolicy,
+    power_profile: PowerProfile,
+    first_piece_strategy: FirstPieceStrategy,
+    rarest_first_randomness: Option<u32>,
+    on_path_conflict: PathConflictPolicy,
+    announce_ip: Option<IpAddr>,
+    request_limiter: Arc<RequestLimiter>,
+    connect_ramp: Arc<ConnectRamp>,
+    // See SessionStats::read_only_fs_errors.
+    read_only_fs_errors: Arc<AtomicU64>,
+    dns: Option<Arc<DnsResolver>>,
+    tracker_client: reqwest::Client,
+    poisoned_piece_threshold: Option<usize>,
+    pause_on_poisoned_piece: bool,
+    verification_mode: VerificationMode,
+    prefer_good_peers: bool,
+    slow_peer_piece_limit: Option<usize>,
+    upload_slots: Option<usize>,
+    endgame_max_duplicates: Option<usize>,
+    stall_timeout: Option<Duration>,
+    missing_files_check_interval: Option<Duration>,
+    incomplete_suffix: Option<String>,
+    incomplete_dir: Option<PathBuf>,
+    file_sink_dir: Option<PathBuf>,
+    mirror_dir: Option<PathBuf>,
+    verify_before_write: bool,
+    verify_from_memory: Option<Arc<PieceMemoryBudget>>,
+    max_tracker_response_size: Option<usize>,
+    max_tracker_redirects: Option<usize>,
+    dead_tracker_timeout: Option<Duration>,
+    announce_enabled: watch::Sender<bool>,
+    seeding_announce_interval: watch::Sender<Option<Duration>>,
+    peer_source_priority: Vec<PeerSource>,
+    tracker_host_scheduler: Option<Arc<TrackerHostScheduler>>,
+    // Bumped on every network change.
+    network_changed: watch::Sender<u64>,
+    upnp_port_forwarding: bool,
+    upnp_lease_duration: Option<Duration>,
+    upnp_refresh_interval: Option<Duration>,
+    // The mappings of the running port forwarder. Replaced whenever it starts over.
+    upnp_status: RwLock<Option<librqbit_upnp::UpnpStatus>>,
+    upnp_remap: Notify,
+    // Bounds add_torrent() calls in progress if max_concurrent_adds is set.
+    add_semaphore: Option<Semaphore>,
+    max_torrents: Option<usize>,
+    queued_adds: AtomicUsize,
+    max_active_downloads: Option<usize>,
+    // Woken when a download slot may have freed up, see task_download_queue().
+    download_queue_changed: Arc<Notify>,
+    peer_connection_budget: Option<usize>,
+    // Reset on network change, see ListenStatus::incoming_peers.
+    incoming_peers: AtomicU64,
+    // Present if SessionOptions::upload_saturation is set.
+    upload_saturation: Option<RwLock<UploadSaturationStats>>,
+    // Uncapped unless SessionOptions::reserved_headroom_bps is set.
+    bandwidth: Arc<BandwidthLimiter>,
+    // Shared by all magnet metadata fetches if max_metadata_peers is set.
+    metadata_peer_semaphore: Option<Semaphore>,
+    resolving_magnets: DashMap<Id20, Arc<MetadataFetchCounters>>,
+    events: broadcast::Sender<SessionEvent>,
+
     cancellation_token: CancellationToken,
 }
 
-async fn torrent_from_url(url: &str) -> anyhow::Result<TorrentMetaV1Owned> {
-    let response = reqwest::get(url)
-        .await
-        .context("error downloading torrent metadata")?;
-    if !response.status().is_success() {
-        anyhow::bail!("GET {} returned {}", url, response.status())
+/// A magnet link that is being added, but its metadata isn't known yet.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResolvingMagnet {
+    pub info_hash: String,
+    /// Distinct peers learned about so far, from DHT and the magnet link itself.
+    pub peers_seen: u64,
+    /// Peers that metadata is being requested from right now.
+    pub peers_connecting: u64,
+    /// Peers that failed to provide metadata.
+    pub peers_failed: u64,
+}
+
+// Removes a magnet from Session::resolving_magnets when its metadata fetch is over,
+// including when add_torrent() is cancelled. Only its own entry: if the same magnet is added
+// again meanwhile, the newer fetch replaces the entry, and keeps it until it's over.
+struct ResolvingMagnetGuard<'a> {
+    magnets: &'a DashMap<Id20, Arc<MetadataFetchCounters>>,
+    info_hash: Id20,
+    counters: Arc<MetadataFetchCounters>,
+}
+
+impl<'a> ResolvingMagnetGuard<'a> {
+    fn new(magnets: &'a DashMap<Id20, Arc<MetadataFetchCounters>>, info_hash: Id20) -> Self {
+        let counters = Arc::new(MetadataFetchCounters::default());
+        magnets.insert(info_hash, counters.clone());
+        Self {
+            magnets,
+            info_hash,
+            counters,
+        }
+    }
+}
+
+impl<'a> Drop for ResolvingMagnetGuard<'a> {
+    fn drop(&mut self) {
+        self.magnets
+            .remove_if(&self.info_hash, |_, c| Arc::ptr_eq(c, &self.counters));
+    }
+}
+
+// What a torrent restored from the session file brings along, see add_torrent_queued().
+#[derive(Default)]
+struct RestoredTorrent {
+    // The peers saved with the torrent.
+    cached_peers: Vec<SocketAddr>,
+    // Its progress, if it's restored lazily.
+    lazy: Option<PersistedProgress>,
+    tracker_redirects: HashMap<Url, Url>,
+}
+
+// Counts add_torrent() calls waiting for a free slot, including when they are cancelled.
+struct QueuedAddGuard<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedAddGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl<'a> Drop for QueuedAddGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
     }
-    let b = response
-        .bytes()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionStats {
+    /// Chunk requests sent to peers and not yet answered, across all torrents.
+    pub outstanding_requests: u64,
+    pub max_outstanding_requests: Option<usize>,
+    /// False while tracker announces and DHT requests are paused session-wide.
+    pub announce_enabled: bool,
+    /// Torrent additions waiting for one of the max_concurrent_adds slots.
+    pub queued_adds: usize,
+    /// See [SessionOptions::max_torrents].
+    pub max_torrents: Option<usize>,
+    /// The session-wide seeding announce interval, in seconds.
+    pub seeding_announce_interval_secs: Option<u64>,
+    /// The torrent in solo mode, see [Session::solo].
+    pub solo_torrent_id: Option<TorrentId>,
+    /// Set if [SessionOptions::upload_saturation] is.
+    pub upload_saturation: Option<UploadSaturationStats>,
+    /// Set if [SessionOptions::reserved_headroom_bps] is.
+    pub bandwidth_limits: Option<BandwidthLimitStats>,
+    /// Set if [SessionOptions::verify_from_memory] is.
+    pub verify_from_memory: Option<VerifyFromMemoryStats>,
+    pub listen: ListenStatus,
+    /// Torrents stopped with an error because their disk was mounted read-only, since the
+    /// session started. They stay stopped until resumed by hand.
+    pub read_only_fs_errors: u64,
+    /// Outgoing peer connection attempts, see [SessionOptions::startup_connect_rate].
+    pub connect_rate: ConnectRateStats,
+}
+
+/// Whether peers can reach us, e.g. to check that a port forward works.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListenStatus {
+    /// Whether the TCP listener is bound. False if listening is disabled, or no port in
+    /// [SessionOptions::listen_port_range] was free.
+    pub bound: bool,
+    pub port: Option<u16>,
+    /// Inbound peer connections handed over to a torrent since the session started, or
+    /// since the last network change.
+    pub incoming_peers: u64,
+    /// True once an inbound peer connected. False doesn't necessarily mean the port is
+    /// closed: nobody may have tried yet, e.g. when no torrent has leechers.
+    pub port_open: bool,
+}
+
+/// See [Session::upnp_status].
+#[derive(Serialize)]
+pub struct UpnpStatus {
+    /// False if [SessionOptions::enable_upnp_port_forwarding] is off or there's no TCP
+    /// listener.
+    pub enabled: bool,
+    /// One per router found and port, including the ones the router refused.
+    pub mappings: Vec<librqbit_upnp::PortMappingStatus>,
+}
+
+async fn torrent_from_url(
+    url: &str,
+    dns: Option<&Arc<DnsResolver>>,
+) -> anyhow::Result<TorrentMetaV1Owned> {
+    let b = download_resumable(url, MAX_TORRENT_FILE_SIZE, dns)
         .await
-        .with_context(|| format!("error reading response body from {url}"))?;
+        .context("error downloading torrent metadata")?;
     torrent_from_bytes(&b).context("error decoding torrent")
 }
 
+// SessionOptions::upnp_lease_duration and upnp_refresh_interval over the forwarder's defaults.
+fn upnp_forwarder_options(
+    lease: Option<Duration>,
+    refresh: Option<Duration>,
+) -> librqbit_upnp::UpnpPortForwarderOptions {
+    let mut opts = librqbit_upnp::UpnpPortForwarderOptions {
+        refresh_interval: refresh,
+        ..Default::default()
+    };
+    if let Some(lease) = lease {
+        opts.lease_duration = lease;
+    }
+    opts
+}
+
+fn torrent_from_path(path: &Path) -> anyhow::Result<TorrentMetaV1Owned> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("error reading torrent file {path:?}"))?;
+    if !metadata.is_file() {
+        bail!("{path:?} is not a file");
+    }
+    if metadata.len() > MAX_TORRENT_FILE_SIZE as u64 {
+        bail!("{path:?} is larger than {MAX_TORRENT_FILE_SIZE} bytes, not a torrent file");
+    }
+    let bytes =
+        std::fs::read(path).with_context(|| format!("error reading torrent file {path:?}"))?;
+    torrent_from_bytes(&bytes).with_context(|| format!("error decoding torrent file {path:?}"))
+}
+
+// Where state files may be moved to, see Session::relocate_persistence. These paths can come
+// over the HTTP API, so nothing that is already there gets overwritten.
+pub(crate) fn check_state_file_target(path: &Path) -> anyhow::Result<()> {
+    if !path.is_absolute() {
+        bail!("{path:?} is not an absolute path");
+    }
+    if path
+        .components()
+        .any(|c| c == std::path::Component::ParentDir)
+    {
+        bail!("{path:?} can't contain \"..\"");
+    }
+    if path.file_name().is_none() {
+        bail!("{path:?} has no file name");
+    }
+    // Dangling symlinks count as existing too.
+    if std::fs::symlink_metadata(path).is_ok() {
+        bail!("{path:?} already exists");
+    }
+    Ok(())
+}
+
 fn compute_only_files<ByteBuf: AsRef<[u8]>>(
     torrent: &TorrentMetaV1Info<ByteBuf>,
     filename_re: &str,
@@ -208,6 +667,18 @@ fn compute_only_files<ByteBuf: AsRef<[u8]>>(
     Ok(only_files)
 }
 
+/// Options for [Session::delete_with_opts].
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeleteOptions {
+    /// Remove the torrent's files from disk.
+    pub delete_files: bool,
+    /// Together with "delete_files", also remove the torrent's directories that end up
+    /// empty. Directories with anything else in them are kept, and so is the output folder.
+    /// Off by default, as other processes may be watching these directories.
+    pub remove_empty_dirs: bool,
+}
+
 /// Options for adding new torrents to the session.
 #[serde_as]
 #[derive(Default, Clone, Serialize, Deserialize)]
@@ -222,7 +693,11 @@ pub struct AddTorrentOptions {
     pub only_files: Option<Vec<usize>>,
     /// Allow writing on top of existing files, including when resuming a torrent.
     /// You probably want to set it, however for safety it's not default.
+    /// Shorthand for [ExistingDataPolicy::Verify], ignored if "existing_data_policy" is set.
     pub overwrite: bool,
+    /// What to do if some of the torrent's files already exist. If not set, this is
+    /// [ExistingDataPolicy::Verify] with "overwrite", and [ExistingDataPolicy::Error] without.
+    pub existing_data_policy: Option<ExistingDataPolicy>,
     /// Only list the files in the torrent without starting it.
     pub list_only: bool,
     /// The output folder for the torrent. If not set, the session's default one will be used.
@@ -230,7 +705,8 @@ pub struct AddTorrentOptions {
     /// Sub-folder within session's default output folder. Will error if "output_folder" if also set.
     /// By default, multi-torrent files are downloaded to a sub-folder.
     pub sub_folder: Option<String>,
-    /// Peer connection options, timeouts etc. If not set, session's defaults will be used.
+    /// Peer connection options, timeouts etc. The ones not set are the session's defaults.
+    /// Can be changed later with [crate::ManagedTorrent::set_peer_opts].
     pub peer_opts: Option<PeerConnectionOptions>,
 
     /// Force a refresh interval for polling trackers.
@@ -242,6 +718,90 @@ pub struct AddTorrentOptions {
     /// Initial peers to start of with.
     pub initial_peers: Option<Vec<SocketAddr>>,
 
+    /// Only upload to peers within these subnets, e.g. "10.0.0.0/8". Peers outside of them
+    /// are never unchoked, but may still be downloaded from. Unrestricted if not set.
+    pub upload_subnets: Option<Vec<String>>,
+
+    /// Don't send the session's "announce_ip" to trackers for this torrent.
+    pub disable_announce_ip: bool,
+
+    /// Keep the torrent in memory only, never writing it to the session persistence file.
+    /// It will be gone after restart. Useful for temporary or streaming torrents.
+    pub no_persist: bool,
+
+    /// Storage mode for filesystems that dislike sparse files and random writes, e.g. some
+    /// FUSE or network mounts. All files are fully preallocated by writing zeroes, and
+    /// pieces are requested close to each other so that writes stay mostly sequential.
+    /// Preallocation may take a while for large torrents.
+    pub contiguous_storage: bool,
+
+    /// Request pieces in order. Can be changed later, see [crate::ManagedTorrent::set_sequential].
+    pub sequential: bool,
+
+    /// More directories to keep a full copy of the data in, e.g. on another disk, laid out
+    /// like the output folder. Each piece is copied to all of them once it's verified, so
+    /// every mirror costs as many disk writes as the torrent's size, plus the reads of the
+    /// pieces back from the output folder. Nothing more is downloaded. Pieces already
+    /// downloaded when the torrent goes live, e.g. after a restart, are copied in the
+    /// background, skipping the ones a mirror already has right. Checking only ever reads
+    /// the output folder. Uploads fall back to a mirror when reading a piece fails there,
+    /// but only for pieces all mirrors are known to have, so that data a mirror may not
+    /// have is never sent. Over the HTTP API, these have to be in
+    /// [SessionOptions::mirror_dir].
+    pub mirror_paths: Vec<PathBuf>,
+
+    /// Overrides [SessionOptions::seeding_announce_interval] for this torrent. Can be
+    /// changed later, see [crate::ManagedTorrent::set_seeding_announce_interval].
+    #[serde_as(as = "Option<serde_with::DurationSeconds>")]
+    pub seeding_announce_interval: Option<Duration>,
+
+    /// Free-form labels to group torrents with.
+    pub labels: Vec<String>,
+
+    /// Arbitrary JSON to keep with the torrent, see [crate::ManagedTorrent::set_user_metadata].
+    pub user_metadata: Option<serde_json::Value>,
+
+    /// Pause the torrent once this percent of it is downloaded and verified, e.g. 5 to get
+    /// enough to preview it. With "only_files" it's the percent of the selected files. Only
+    /// triggers once: resuming the torrent downloads the rest. A
+    /// [crate::TorrentEvent::ProgressThresholdReached] is sent when it triggers.
+    pub pause_at_percent: Option<f64>,
+
+    /// Switch the torrent to sequential once this percent of it is downloaded and verified,
+    /// e.g. 90 to get the rest in order when only the end is missing. Counted like
+    /// "pause_at_percent", and only triggers once. Does nothing if "sequential" is set. A
+    /// [crate::TorrentEvent::ProgressThresholdReached] is sent when it triggers.
+    pub sequential_after_percent: Option<f64>,
+
+    /// Download only these files, then pause, e.g. to check what a torrent contains by its
+    /// first file. Unlike "only_files", the torrent pauses once they're complete, and a
+    /// [crate::TorrentEvent::ProgressThresholdReached] is sent. Can't be combined with "only_files" or
+    /// "only_files_regex".
+    pub sample_files: Option<Vec<usize>>,
+
+    /// Reuse the files of torrents already in the session that look like this torrent's,
+    /// e.g. the same release from another tracker made with a different piece length.
+    /// Complete files with the same name and length are copied into the output folder while
+    /// the torrent initializes, after the add returns, and then checked against this
+    /// torrent's pieces, so that only what doesn't match is downloaded. The other torrents' files are only read. Implies
+    /// [ExistingDataPolicy::Verify] unless "existing_data_policy" is set, and nothing is
+    /// copied with any other policy.
+    pub cross_seed: bool,
+
+    /// A libtorrent ".fastresume" file from another client, e.g. qBittorrent, with the
+    /// pieces it already downloaded. They are taken as they are instead of re-checking the
+    /// data, and its file priorities are used unless "only_files" is set. Only the piece and
+    /// priority info is read, and it's only trusted once: files that are shorter than the
+    /// pieces need are downloaded again, and later checks hash everything as usual.
+    /// Implies [ExistingDataPolicy::Verify] unless "existing_data_policy" is set.
+    #[serde(skip)]
+    pub fastresume: Option<Vec<u8>>,
+
+    /// Refuse to add the torrent if its metadata doesn't look like this, e.g. when adding
+    /// magnets from an untrusted source. For magnets this is checked once the metadata is
+    /// resolved, and on a mismatch the torrent is dropped and never added.
+    pub expect: Option<ExpectedMetadata>,
+
     /// This is used to restore the session from serialized state.
     #[serde(skip)]
     pub preferred_id: Option<usize>,
@@ -290,6 +850,8 @@ pub fn read_local_file_including_stdin(filename: &str) -> anyhow::Result<Vec<u8>
 pub enum AddTorrent<'a> {
     Url(Cow<'a, str>),
     TorrentFileBytes(Cow<'a, [u8]>),
+    /// A .torrent file on the local filesystem, read when the torrent is added.
+    TorrentFilePath(PathBuf),
     TorrentInfo(Box<TorrentMetaV1Owned>),
 }
 
@@ -310,6 +872,10 @@ impl<'a> AddTorrent<'a> {
         Self::TorrentFileBytes(bytes.into())
     }
 
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        Self::TorrentFilePath(path.into())
+    }
+
     // Don't call this from HTTP API.
     pub fn from_local_filename(filename: &str) -> anyhow::Result<Self> {
         let file = read_local_file_including_stdin(filename)
@@ -317,15 +883,30 @@ impl<'a> AddTorrent<'a> {
         Ok(Self::TorrentFileBytes(Cow::Owned(file)))
     }
 
+    /// The URL or the .torrent file's contents. Panics for [AddTorrent::TorrentFilePath] and
+    /// [AddTorrent::TorrentInfo].
     pub fn into_bytes(self) -> Vec<u8> {
         match self {
             Self::Url(s) => s.into_owned().into_bytes(),
             Self::TorrentFileBytes(b) => b.into_owned(),
-            Self::TorrentInfo(_) => unimplemented!(),
+            Self::TorrentFilePath(_) | Self::TorrentInfo(_) => unimplemented!(),
         }
     }
 }
 
+/// Called right before the session shuts down because of [SessionOptions::idle_shutdown].
+/// Return false to veto the shutdown, in which case the idle timer starts over.
+pub type IdleShutdownHook = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// Notable things happening to the session, see [Session::subscribe_events].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// The session was idle for [SessionOptions::idle_shutdown] and is stopping now. Sent
+    /// once the [IdleShutdownHook], if any, didn't veto it.
+    IdleShutdown { idle_for: Duration },
+}
+
 #[derive(Default)]
 pub struct SessionOptions {
     /// Turn on to disable DHT.
@@ -342,6 +923,15 @@ pub struct SessionOptions {
     pub persistence: bool,
     /// The filename for persistence. By default uses an OS-specific folder.
     pub persistence_filename: Option<PathBuf>,
+    /// With persistence on, also save the best connected peers of each torrent, up to 50, and
+    /// dial them as soon as it starts on the next run, before trackers and the DHT answer.
+    pub persist_peers: bool,
+    /// With persistence on, restore paused torrents without opening or checking their files.
+    /// They're listed with the progress they had when the session was last saved, see
+    /// [crate::TorrentStats::lazy], and loaded once started, or with
+    /// [crate::ManagedTorrent::load]. Cuts startup time and memory with large libraries.
+    /// Torrents that were running start as usual.
+    pub lazy_load: bool,
 
     /// The peer ID to use. If not specified, a random one will be generated.
     pub peer_id: Option<Id20>,
@@ -350,6 +940,277 @@ pub struct SessionOptions {
 
     pub listen_port_range: Option<std::ops::Range<u16>>,
     pub enable_upnp_port_forwarding: bool,
+    /// How long the router should keep the UPnP port mapping. Defaults to 60 seconds.
+    pub upnp_lease_duration: Option<Duration>,
+    /// How often to renew the UPnP port mapping. Defaults to half the lease duration. For
+    /// routers that drop leases early, renew more often than that.
+    pub upnp_refresh_interval: Option<Duration>,
+
+    /// Unix permission bits (e.g. 0o640) to set on files created for torrent data.
+    /// Directories created for torrent data get the same bits, plus "x" wherever "r" is set.
+    /// If not set, the process umask applies. Ignored on non-unix platforms.
+    pub file_mode: Option<u32>,
+    /// Unix group ID to assign to files and directories created for torrent data.
+    /// Ignored on non-unix platforms.
+    pub file_group: Option<u32>,
+
+    /// When to fsync downloaded data to disk. See [FsyncPolicy] for the
+    /// durability implications of each option.
+    pub fsync_policy: FsyncPolicy,
+
+    /// How eagerly to run periodic timers (stats sampling, state dumps, announces, peer
+    /// reconnects). [PowerProfile::LowPower] saves battery at the cost of slower peer discovery
+    /// and ramp up; see [PowerProfile] for the details.
+    pub power_profile: PowerProfile,
+
+    /// Which piece to request first from each newly connected peer. The default,
+    /// [FirstPieceStrategy::InOrder], picks it like every later piece; see
+    /// [FirstPieceStrategy] for the others.
+    pub first_piece_strategy: FirstPieceStrategy,
+
+    /// With [FirstPieceStrategy::Rarest], pick the first piece from each peer randomly among
+    /// the pieces at most this many peers more common than the rarest one, instead of always
+    /// the first rarest one. In small swarms, leechers picking the same way otherwise all
+    /// request the same piece at the same time. 0 only randomizes between equally rare
+    /// pieces. Rarer pieces still win over more common ones outside the window. Later pieces
+    /// are picked as usual, and setting this with any other strategy is an error.
+    pub rarest_first_randomness: Option<u32>,
+
+    /// What to do when a torrent being added would write to a file another torrent in the
+    /// session writes to, e.g. two torrents with the same name in a shared download folder.
+    /// By default it's added anyway with a warning; see [PathConflictPolicy]. Torrents
+    /// restored from the session file aren't checked.
+    pub on_path_conflict: PathConflictPolicy,
+
+    /// The IP to send as the "ip" parameter in tracker announces, for setups where the
+    /// tracker sees the wrong address (e.g. behind a proxy or VPN). If not set, the
+    /// parameter isn't sent, and trackers use the address the request came from.
+    ///
+    /// This is only a hint: most public trackers (e.g. opentracker in its default
+    /// configuration) ignore it unless the request comes from a trusted proxy, while some
+    /// private trackers do respect it. It's only sent to HTTP(S) trackers, as announces
+    /// to UDP trackers aren't supported.
+    pub announce_ip: Option<IpAddr>,
+
+    /// How to resolve the hostnames of trackers, web seeds and torrent URLs, e.g. to keep
+    /// the lookups away from the ISP's resolver when using a VPN. The system resolver by
+    /// default. DHT bootstrap nodes are always looked up with the system resolver.
+    pub dns: DnsConfig,
+
+    /// Stop the session once it has been idle for this long. The session is idle when
+    /// there are no torrents, or all of them are paused with their selected files complete.
+    /// Live torrents, seeding ones included, and torrents in error keep it running. Before
+    /// stopping, [SessionEvent::IdleShutdown] is sent. Once stopped,
+    /// [Session::cancellation_token] is cancelled, and it's up to the embedder to exit.
+    pub idle_shutdown: Option<Duration>,
+    /// See [IdleShutdownHook].
+    pub on_idle_shutdown: Option<IdleShutdownHook>,
+
+    /// Limit the total number of chunk requests in flight across all torrents and peers,
+    /// on top of the per-peer limit. Bounds memory use on fast links. Unlimited by default.
+    pub max_outstanding_requests_global: Option<usize>,
+
+    /// After how many failed hash checks from distinct peers a piece is considered poisoned.
+    /// Defaults to 3.
+    pub poisoned_piece_threshold: Option<usize>,
+    /// Stop torrents with an error once they have a poisoned piece, instead of retrying it.
+    pub pause_on_poisoned_piece: bool,
+    /// A debugging aid: with [VerificationMode::LogOnly], pieces that fail verification are
+    /// recorded with which peers sent which of their bytes instead of counting towards
+    /// poisoned piece detection. Defaults to [VerificationMode::Strict].
+    pub verification_mode: VerificationMode,
+
+    /// Bias piece requests toward peers that recently delivered most of what they were
+    /// asked for, quickly. Peers scoring well below the average only get a new piece once
+    /// they deliver their in-flight requests, apart from occasional probes so that they
+    /// can recover. Scores are shown in the per-peer stats.
+    pub prefer_good_peers: bool,
+
+    /// A slow peer, i.e. one scoring well below the average as for
+    /// [SessionOptions::prefer_good_peers], downloads at most this many pieces at a time.
+    /// Pieces it holds beyond that are taken over by faster peers. Off by default. The
+    /// per-peer stats show how many pieces each peer holds.
+    pub slow_peer_piece_limit: Option<usize>,
+
+    /// How many peers each torrent uploads to at a time. Only peers interested in our pieces
+    /// get a slot, and keep it until they disconnect. By default every peer is uploaded to.
+    /// Can be overridden per torrent, see [crate::ManagedTorrent::set_upload_slots]. The
+    /// slots in use show up in the torrent stats.
+    pub upload_slots: Option<usize>,
+
+    /// Towards the end of a download, pieces in flight from slow peers are requested again
+    /// from faster ones. This limits how many peers a piece is requested from at once, at
+    /// the cost of bandwidth for the duplicates. Unlimited by default, 1 disables it, 0 is
+    /// an error. How much was requested twice shows up in the torrent stats.
+    pub endgame_max_duplicates: Option<usize>,
+
+    /// Report a live torrent as stalled in its stats if it has peers, but hasn't verified a
+    /// piece for this long, e.g. when the last pieces have no seeders. Defaults to 10 minutes.
+    pub stall_timeout: Option<Duration>,
+
+    /// How often live torrents check that their files are still on disk. If some were
+    /// deleted, e.g. while seeding, the torrent stops with a "files missing" error, see
+    /// [Session::force_recheck]. Failed reads are checked for this right away.
+    /// Defaults to 1 minute, zero turns the periodic check off.
+    pub missing_files_check_interval: Option<Duration>,
+
+    /// Once a torrent is complete, announce to its trackers this often instead of what
+    /// they ask for. Can be longer or shorter than the tracker's interval, but never
+    /// shorter than its "min interval", or than a minute. Torrents can override it, and it
+    /// can be changed later, see [Session::set_seeding_announce_interval].
+    pub seeding_announce_interval: Option<Duration>,
+
+    /// When more peers are known than can be connected to, dial them in this order of
+    /// where they came from, e.g. DHT first for well seeded public torrents. Sources that
+    /// aren't listed go last. HTTP seeds ranked below a peer source start a few seconds
+    /// late. Empty, the default, dials peers in the order they were found. Each peer's
+    /// source is shown in the per-peer stats.
+    pub peer_source_priority: Vec<PeerSource>,
+
+    /// Write files with this suffix appended (e.g. ".part"), and rename them to their final
+    /// name once all their pieces are verified, so that e.g. media scanners skip files
+    /// still downloading. Files that are already there when the torrent is added keep
+    /// their name.
+    pub incomplete_suffix: Option<String>,
+
+    /// Write files under this directory, at the same relative path, and move each to the
+    /// output folder once all its pieces are verified. Can be on another filesystem, the
+    /// file is copied then. Combines with [Self::incomplete_suffix].
+    pub incomplete_dir: Option<PathBuf>,
+
+    /// The only directory [crate::api::Api::api_torrent_file_sink] writes to, e.g. where
+    /// transcoders create their FIFOs. Relative sink paths are taken from it, and absolute
+    /// ones have to be inside it. Without it, file sinks over the API are refused, so that
+    /// HTTP clients can't create or truncate files anywhere else.
+    /// [crate::ManagedTorrent::stream_file_to] isn't limited.
+    pub file_sink_dir: Option<PathBuf>,
+
+    /// The only directory [AddTorrentOptions::mirror_paths] added over the HTTP API may be
+    /// in. Relative mirror paths are taken from it. Without it, mirrors over the HTTP API
+    /// are refused, so that HTTP clients can't create directories and files anywhere else.
+    pub mirror_dir: Option<PathBuf>,
+
+    /// Poll the local network interfaces this often, and on a change (e.g. switching from
+    /// WiFi to ethernet, or a VPN reconnecting), redo UPnP port forwarding, bootstrap DHT
+    /// again and re-announce all torrents. Off by default. Embedders with their own
+    /// detection can call [Session::on_network_change] instead.
+    pub network_change_poll_interval: Option<Duration>,
+
+    /// Recover quickly after the host wakes from sleep, which is detected as the wall clock
+    /// jumping ahead by more than this between two checks a few seconds apart. On waking,
+    /// all torrents re-announce, DHT is queried again and peers waiting to be reconnected
+    /// are retried right away, see [Session::on_wake_from_sleep]. Off by default.
+    pub wake_recovery_gap: Option<Duration>,
+
+    /// Restart torrents that fail to initialize after being added, e.g. when their files
+    /// couldn't be opened, with a backoff. Each retry is logged, and once the retries are
+    /// used up [crate::TorrentEvent::RetriesExhausted] is sent and the torrent is left in
+    /// error. Torrents are never removed. Torrents that fail while live, on a read-only
+    /// filesystem (see [crate::SessionStats::read_only_fs_errors]) or locked are left alone,
+    /// and so are adds that fail before the torrent is in the session, e.g. a magnet whose
+    /// metadata couldn't be found: those return the error to the caller. Off by default,
+    /// torrents in error wait to be restarted by hand.
+    pub error_retry: Option<ErrorRetryOptions>,
+
+    /// Leave at least this much time between two announces to the same tracker host, from
+    /// any torrents, so that e.g. 50 torrents on one private tracker don't all announce at
+    /// once and get rate-limited. Each tracker's schedule is in
+    /// [crate::TrackerStats::host_schedule]. Off by default.
+    pub tracker_host_min_gap: Option<Duration>,
+
+    /// Watch the session-wide upload rate, and once it stays close to the link's capacity
+    /// for a while, e.g. starving interactive traffic on a home connection without router
+    /// QoS, report it in [SessionStats] and optionally pause seeding torrents. Off by
+    /// default.
+    pub upload_saturation: Option<UploadSaturationOptions>,
+
+    /// Cap the session's download and upload rates to the link's capacity minus this many
+    /// bytes per second, leaving room for other traffic, e.g. video calls on a home
+    /// connection. Unlike a fixed limit, the caps follow the capacity: the one in
+    /// "link_capacity", or else the highest rate measured. Measured directions are uncapped
+    /// for 30 seconds every 10 minutes to measure the link again. Boosted torrents aren't
+    /// capped on download. The caps are in [SessionStats::bandwidth_limits]. Off by default.
+    pub reserved_headroom_bps: Option<u64>,
+
+    /// The link's capacity in each direction, for "reserved_headroom_bps". Directions that
+    /// aren't set are measured.
+    pub link_capacity: LinkCapacity,
+
+    /// Limit how many peers are contacted at a time for magnet link metadata, across all
+    /// magnets being added. Once a torrent's metadata is known, the usual peer limits
+    /// apply. If not set, each magnet may contact up to 128 peers at a time.
+    pub max_metadata_peers: Option<usize>,
+
+    /// Limit how fast peers are dialed, across all torrents, for the first minutes after
+    /// the session starts, so that restoring many torrents doesn't open hundreds of
+    /// connections at once and trip ISP or router connection-rate limits. The limit
+    /// doubles every 30 seconds until the ramp is over, then only the per-torrent peer
+    /// limits apply. Incoming peers aren't limited. Can be changed later, which starts the
+    /// ramp over, see [Session::set_startup_connect_rate]. Off by default.
+    pub startup_connect_rate: Option<StartupConnectRate>,
+
+    /// Limit how many torrents are being added at a time, i.e. resolving magnets,
+    /// downloading .torrent files and setting up storage. The rest wait in line, which
+    /// smooths out DHT, tracker and metadata traffic when lots of torrents are added at
+    /// once, including when restoring the session on startup. Unlimited if not set.
+    pub max_concurrent_adds: Option<usize>,
+
+    /// Refuse to add torrents once the session holds this many, in any state, with
+    /// [crate::ApiError::max_torrents_reached] (HTTP 507), e.g. so that one user can't fill
+    /// up a shared instance. Torrents restored from the session file are not limited, even
+    /// if there are more of them. Unlimited if not set.
+    pub max_torrents: Option<usize>,
+
+    /// Download at most this many torrents at once. Torrents added beyond that start
+    /// paused and wait in the download queue, see [Session::queue], and are started in
+    /// order as downloads finish. Torrents being checked count as downloading, seeding ones
+    /// don't. Torrents added paused aren't queued, and [Session::dequeue] takes one out.
+    /// Nothing starts from the queue in solo mode, see [Session::solo]. Unlimited if not set.
+    pub max_active_downloads: Option<usize>,
+
+    /// Live peer connections across all torrents. Each live torrent gets a share weighted by
+    /// its [PriorityTier], see [Session::set_priority_tier], so a high priority torrent gets
+    /// more peers than a low priority one. A torrent never has more than 128 peers, what it
+    /// can't use goes to the others. Torrents over their share keep their peers but don't
+    /// connect to more. Without a budget (the default), the tiers change nothing.
+    pub peer_connection_budget: Option<usize>,
+
+    /// Buffer each piece in memory until it's complete and passes the hash check, and
+    /// only then write it to disk, so that corrupt data is never written. Pieces that fail
+    /// the check are discarded and downloaded again.
+    ///
+    /// This costs a buffer of the piece length for every piece being downloaded. There's
+    /// about one such piece per live peer, so e.g. 100 peers on a torrent with 4 MiB pieces
+    /// may use up to 400 MiB. Buffered pieces are lost on pause.
+    ///
+    /// When off, which is the default and what memory-limited devices want, no piece is
+    /// buffered: each received block is written straight to its final position, and the
+    /// piece is verified by reading it back from disk, 64 KiB at a time, once complete.
+    /// Pieces that fail the check are downloaded again block by block.
+    pub verify_before_write: bool,
+
+    /// Like "verify_before_write", but with the memory capped across the session, for
+    /// download folders on slow network storage, e.g. a NAS or a cloud mount, where reading
+    /// each piece back slows down downloads. Pieces hash from memory while there's room,
+    /// and the others are read back with several reads in parallel. How many pieces went
+    /// either way is in [SessionStats::verify_from_memory]. Ignored with
+    /// "verify_before_write", which buffers every piece. Off by default.
+    pub verify_from_memory: Option<VerifyFromMemoryOptions>,
+
+    /// Ignore HTTP tracker responses with a body larger than this many bytes, so that a
+    /// malicious tracker can't exhaust memory. Defaults to 1 MiB.
+    pub max_tracker_response_size: Option<usize>,
+
+    /// How many HTTP redirects to follow per tracker announce. Where a tracker redirected to
+    /// is remembered, also across restarts, and used for later announces. Only http(s)
+    /// redirects are followed. Defaults to 5, 0 doesn't follow any.
+    pub max_tracker_redirects: Option<usize>,
+
+    /// Stop announcing to a tracker once every announce to it failed for this long, e.g.
+    /// for trackers that shut down years ago. Dead trackers stay listed in the tracker stats,
+    /// are remembered across restarts, and can be announced to again with
+    /// [crate::api::Api::api_torrent_revive_tracker]. None (the default) never gives up.
+    pub dead_tracker_timeout: Option<Duration>,
 }
 
 async fn create_tcp_listener(
@@ -388,6 +1249,11 @@ impl Session {
         &self.cancellation_token
     }
 
+    /// Subscribe to notable things happening to the session, e.g. an idle shutdown.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
     /// Create a new session with options.
     pub async fn new_with_opts(
         output_folder: PathBuf,
@@ -396,6 +1262,64 @@ impl Session {
         let peer_id = opts.peer_id.unwrap_or_else(generate_peer_id);
         let token = CancellationToken::new();
 
+        if let Some(ip) = opts.announce_ip {
+            if ip.is_unspecified() || ip.is_multicast() {
+                bail!("{ip} can't be used as the IP to announce to trackers");
+            }
+        }
+        opts.fsync_policy
+            .validate()
+            .context("invalid fsync_policy")?;
+        if let Some(upload_saturation) = &opts.upload_saturation {
+            upload_saturation
+                .validate()
+                .context("invalid upload_saturation")?;
+        }
+        if opts.max_outstanding_requests_global == Some(0) {
+            bail!("max_outstanding_requests_global must be at least 1");
+        }
+        let dns = DnsResolver::new(opts.dns.clone()).context("invalid dns option")?;
+        let tracker_client = tracker_client(dns.as_ref())?;
+        if opts.slow_peer_piece_limit == Some(0) {
+            bail!("slow_peer_piece_limit must be at least 1");
+        }
+        if opts.upload_slots == Some(0) {
+            bail!("upload_slots must be at least 1");
+        }
+        if opts.endgame_max_duplicates == Some(0) {
+            bail!("endgame_max_duplicates must be at least 1");
+        }
+        check_seeding_announce_interval(opts.seeding_announce_interval)?;
+        if opts.max_concurrent_adds == Some(0) {
+            bail!("max_concurrent_adds must be at least 1");
+        }
+        if opts.startup_connect_rate.map(|r| r.initial_per_sec) == Some(0) {
+            bail!("startup_connect_rate must be at least 1 per second");
+        }
+        if opts.max_metadata_peers == Some(0) {
+            bail!("max_metadata_peers must be at least 1");
+        }
+        if opts.max_tracker_response_size == Some(0) {
+            bail!("max_tracker_response_size must be at least 1");
+        }
+        if opts.idle_shutdown == Some(Duration::ZERO) {
+            bail!("idle_shutdown must be above 0");
+        }
+        if opts.network_change_poll_interval == Some(Duration::ZERO) {
+            bail!("network_change_poll_interval must be above 0");
+        }
+        if opts.rarest_first_randomness.is_some()
+            && opts.first_piece_strategy != FirstPieceStrategy::Rarest
+        {
+            bail!("rarest_first_randomness only applies to the \"rarest\" first_piece_strategy");
+        }
+        if let Some(peer_opts) = &opts.peer_opts {
+            peer_opts.validate().context("invalid peer_opts")?;
+        }
+        upnp_forwarder_options(opts.upnp_lease_duration, opts.upnp_refresh_interval)
+            .validate()
+            .context("invalid upnp_lease_duration or upnp_refresh_interval")?;
+
         let (tcp_listener, tcp_listen_port) = if let Some(port_range) = opts.listen_port_range {
             let (l, p) = create_tcp_listener(port_range)
                 .await
@@ -412,12 +1336,21 @@ impl Session {
             let dht = if opts.disable_dht_persistence {
                 DhtBuilder::with_config(DhtConfig {
                     cancellation_token: Some(token.child_token()),
+                    read_only: opts
+                        .dht_config
+                        .as_ref()
+                        .map(|c| c.read_only)
+                        .unwrap_or(false),
+                    max_query_rate: opts.dht_config.as_ref().and_then(|c| c.max_query_rate),
                     ..Default::default()
                 })
                 .await
                 .context("error initializing DHT")?
             } else {
-                let pdht_config = opts.dht_config.take().unwrap_or_default();
+                let mut pdht_config = opts.dht_config.take().unwrap_or_default();
+                pdht_config.dump_interval = opts
+                    .power_profile
+                    .dht_dump_interval(pdht_config.dump_interval);
                 PersistentDht::create(Some(pdht_config), Some(token.clone()))
                     .await
                     .context("error initializing persistent DHT")?
@@ -433,7 +1366,10 @@ impl Session {
         let spawner = BlockingSpawner::default();
 
         let session = Arc::new(Self {
-            persistence_filename,
+            persistence: opts.persistence,
+            persistence_filename: RwLock::new(persistence_filename),
+            persist_peers: opts.persist_peers,
+            lazy_load: opts.lazy_load,
             peer_id,
             dht,
             peer_opts,
@@ -442,6 +1378,66 @@ impl Session {
             db: RwLock::new(Default::default()),
             cancellation_token: token,
             tcp_listen_port,
+            file_mode: opts.file_mode,
+            file_group: opts.file_group,
+            fsync_policy: opts.fsync_policy,
+            power_profile: opts.power_profile,
+            first_piece_strategy: opts.first_piece_strategy,
+            rarest_first_randomness: opts.rarest_first_randomness,
+            on_path_conflict: opts.on_path_conflict,
+            announce_ip: opts.announce_ip,
+            request_limiter: Arc::new(RequestLimiter::new(opts.max_outstanding_requests_global)),
+            connect_ramp: Arc::new(ConnectRamp::new(opts.startup_connect_rate)),
+            read_only_fs_errors: Default::default(),
+            dns,
+            tracker_client,
+            poisoned_piece_threshold: opts.poisoned_piece_threshold,
+            pause_on_poisoned_piece: opts.pause_on_poisoned_piece,
+            verification_mode: opts.verification_mode,
+            prefer_good_peers: opts.prefer_good_peers,
+            slow_peer_piece_limit: opts.slow_peer_piece_limit,
+            upload_slots: opts.upload_slots,
+            endgame_max_duplicates: opts.endgame_max_duplicates,
+            stall_timeout: opts.stall_timeout,
+            missing_files_check_interval: opts.missing_files_check_interval,
+            incomplete_suffix: opts.incomplete_suffix,
+            incomplete_dir: opts.incomplete_dir,
+            file_sink_dir: opts.file_sink_dir,
+            mirror_dir: opts.mirror_dir,
+            verify_before_write: opts.verify_before_write,
+            verify_from_memory: opts
+                .verify_from_memory
+                .map(|o| Arc::new(PieceMemoryBudget::new(o))),
+            max_tracker_response_size: opts.max_tracker_response_size,
+            max_tracker_redirects: opts.max_tracker_redirects,
+            dead_tracker_timeout: opts.dead_tracker_timeout,
+            announce_enabled: watch::channel(true).0,
+            seeding_announce_interval: watch::channel(opts.seeding_announce_interval).0,
+            peer_source_priority: opts.peer_source_priority,
+            tracker_host_scheduler: opts
+                .tracker_host_min_gap
+                .map(|gap| Arc::new(TrackerHostScheduler::new(gap))),
+            network_changed: watch::channel(0).0,
+            upnp_port_forwarding: opts.enable_upnp_port_forwarding && tcp_listen_port.is_some(),
+            upnp_lease_duration: opts.upnp_lease_duration,
+            upnp_refresh_interval: opts.upnp_refresh_interval,
+            upnp_status: Default::default(),
+            upnp_remap: Notify::new(),
+            add_semaphore: opts.max_concurrent_adds.map(Semaphore::new),
+            max_torrents: opts.max_torrents,
+            queued_adds: AtomicUsize::new(0),
+            download_queue_changed: Default::default(),
+            max_active_downloads: opts.max_active_downloads,
+            peer_connection_budget: opts.peer_connection_budget,
+            incoming_peers: AtomicU64::new(0),
+            upload_saturation: opts.upload_saturation.as_ref().map(|_| Default::default()),
+            bandwidth: Arc::new(BandwidthLimiter::new(
+                opts.reserved_headroom_bps,
+                opts.link_capacity,
+            )),
+            metadata_peer_semaphore: opts.max_metadata_peers.map(Semaphore::new),
+            resolving_magnets: Default::default(),
+            events: broadcast::channel(16).0,
         });
 
         if let Some(tcp_listener) = tcp_listener {
@@ -460,12 +1456,75 @@ impl Session {
             }
         }
 
+        if let Some(poll_interval) = opts.network_change_poll_interval {
+            session.spawn(
+                error_span!("network_change_watcher"),
+                session
+                    .clone()
+                    .task_network_change_watcher(poll_interval, librqbit_upnp::list_local_ips),
+            );
+        }
+
+        if let Some(gap) = opts.wake_recovery_gap {
+            session.spawn(
+                error_span!("wake_watcher"),
+                session.clone().task_wake_watcher(gap),
+            );
+        }
+
+        if let Some(error_retry) = opts.error_retry.take() {
+            session.spawn(
+                error_span!("error_retry"),
+                session.clone().task_error_retry(error_retry),
+            );
+        }
+
+        if let Some(upload_saturation) = opts.upload_saturation.take() {
+            session.spawn(
+                error_span!("upload_saturation_watcher"),
+                session
+                    .clone()
+                    .task_upload_saturation_watcher(upload_saturation),
+            );
+        }
+
+        if session.bandwidth.is_enabled() {
+            session.spawn(
+                error_span!("bandwidth_limits"),
+                session.clone().task_bandwidth_limits(),
+            );
+        }
+
+        if let Some(budget) = opts.peer_connection_budget {
+            session.spawn(
+                error_span!("peer_budget"),
+                session.clone().task_peer_budget(budget),
+            );
+        }
+
+        if opts.max_active_downloads.is_some() {
+            session.spawn(
+                error_span!("download_queue"),
+                session.clone().task_download_queue(),
+            );
+        }
+
+        if let Some(idle_timeout) = opts.idle_shutdown {
+            session.spawn(
+                error_span!("idle_shutdown"),
+                session
+                    .clone()
+                    .task_idle_shutdown(idle_timeout, opts.on_idle_shutdown.take()),
+            );
+        }
+
         if opts.persistence {
+            let persistence_filename = session.persistence_filename();
             info!(
                 "will use {:?} for session persistence",
-                session.persistence_filename
+                persistence_filename
             );
-            if let Some(parent) = session.persistence_filename.parent() {
+            if let Some(parent) = persistence_filename.parent() {
                 std::fs::create_dir_all(parent).with_context(|| {
                     format!("couldn't create directory {:?} for session storage", parent)
                 })?;
@@ -483,11 +1542,12 @@ impl Session {
             error!("could not populate session from stored file: {:?}", e);
         }
 
+        let dump_interval = self.power_profile.session_dump_interval();
         let session = Arc::downgrade(&self);
         drop(self);
 
         loop {
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            tokio::time::sleep(dump_interval).await;
             let session = match session.upgrade() {
                 Some(s) => s,
                 None => break,
@@ -500,59 +1560,346 @@ impl Session {
         Ok(())
     }
 
-    async fn check_incoming_connection(
-        &self,
-        addr: SocketAddr,
-        mut stream: TcpStream,
-    ) -> anyhow::Result<(Arc<TorrentStateLive>, CheckedIncomingConnection)> {
-        // TODO: move buffer handling to peer_connection
-
-        let rwtimeout = self
-            .peer_opts
-            .read_write_timeout
-            .unwrap_or_else(|| Duration::from_secs(10));
-
-        let mut read_buf = ReadBuf::new();
-        let h = read_buf
-            .read_handshake(&mut stream, rwtimeout)
-            .await
-            .context("error reading handshake")?;
-        trace!("received handshake from {addr}: {:?}", h);
+    // No torrents, or all of them complete and paused, see SessionOptions::idle_shutdown.
+    fn is_idle(&self) -> bool {
+        self.db.read().torrents.values().all(|t| {
+            t.with_state(|s| match s {
+                ManagedTorrentState::Paused(p) => p.needed_bytes == 0,
+                ManagedTorrentState::Lazy(l) => l.is_finished(),
+                ManagedTorrentState::Live(_)
+                | ManagedTorrentState::Error(_)
+                | ManagedTorrentState::Initializing(_)
+                | ManagedTorrentState::None => false,
+            })
+        })
+    }
 
-        if h.peer_id == self.peer_id.0 {
-            bail!("seems like we are connecting to ourselves, ignoring");
+    async fn task_idle_shutdown(
+        self: Arc<Self>,
+        idle_timeout: Duration,
+        hook: Option<IdleShutdownHook>,
+    ) -> anyhow::Result<()> {
+        let check_interval = idle_timeout.min(Duration::from_secs(5));
+        let mut idle_since: Option<std::time::Instant> = None;
+        loop {
+            tokio::time::sleep(check_interval).await;
+            if !self.is_idle() {
+                idle_since = None;
+                continue;
+            }
+            let since = *idle_since.get_or_insert_with(std::time::Instant::now);
+            if since.elapsed() < idle_timeout {
+                continue;
+            }
+            if let Some(hook) = hook.as_ref() {
+                if !hook() {
+                    debug!("idle shutdown vetoed");
+                    idle_since = None;
+                    continue;
+                }
+            }
+            info!("session was idle for {:?}, shutting down", idle_timeout);
+            let _ = self.events.send(SessionEvent::IdleShutdown {
+                idle_for: since.elapsed(),
+            });
+            self.stop().await;
+            return Ok(());
         }
+    }
 
-        for (id, torrent) in self.db.read().torrents.iter() {
-            if torrent.info_hash().0 != h.info_hash {
+    async fn task_upload_saturation_watcher(
+        self: Arc<Self>,
+        opts: UploadSaturationOptions,
+    ) -> anyhow::Result<()> {
+        let mut detector = UploadSaturationDetector::new(&opts);
+        let check_interval = self.power_profile.stats_sample_interval();
+        loop {
+            tokio::time::sleep(check_interval).await;
+            let upload_bps = self.with_torrents(|torrents| {
+                torrents
+                    .filter_map(|(_, t)| t.live())
+                    .map(|live| live.up_speed_estimator().bps())
+                    .sum::<u64>()
+            });
+            let triggered = detector.update(upload_bps, std::time::Instant::now());
+            if let Some(stats) = self.upload_saturation.as_ref() {
+                stats.write().saturated = detector.is_saturated();
+            }
+            if !triggered {
                 continue;
             }
-
-            let live = match torrent.live() {
-                Some(live) => live,
-                None => {
-                    bail!("torrent {id} is not live, ignoring connection");
-                }
+            let paused = match opts.action {
+                UploadSaturationAction::Report => Vec::new(),
+                UploadSaturationAction::PauseSeeding => self.pause_seeding_torrents(),
             };
-
-            let handshake = h.clone_to_owned();
-
-            return Ok((
-                live,
-                CheckedIncomingConnection {
-                    addr,
-                    stream,
-                    handshake,
-                    read_buf,
-                },
-            ));
+            warn!(upload_bps, paused = paused.len(), "upload stayed saturated");
+            if let Some(stats) = self.upload_saturation.as_ref() {
+                stats.write().record_trigger(paused);
+            }
         }
-
-        bail!("didn't find a matching torrent for {:?}", Id20::new(h.info_hash))
     }
 
-    async fn task_tcp_listener(self: Arc<Self>, l: TcpListener) -> anyhow::Result<()> {
-        let mut futs = FuturesUnordered::new();
+    async fn task_bandwidth_limits(self: Arc<Self>) -> anyhow::Result<()> {
+        loop {
+            tokio::time::sleep(BANDWIDTH_UPDATE_INTERVAL).await;
+            let (download_bps, upload_bps) = self.with_torrents(|torrents| {
+                torrents
+                    .filter_map(|(_, t)| t.live())
+                    .map(|live| {
+                        (
+                            live.down_speed_estimator().bps(),
+                            live.up_speed_estimator().bps(),
+                        )
+                    })
+                    .fold((0, 0), |(d, u), (ld, lu)| (d + ld, u + lu))
+            });
+            self.bandwidth
+                .update(download_bps, upload_bps, std::time::Instant::now());
+        }
+    }
+
+    async fn task_peer_budget(self: Arc<Self>, budget: usize) -> anyhow::Result<()> {
+        loop {
+            self.share_peer_budget(budget);
+            tokio::time::sleep(PEER_BUDGET_INTERVAL).await;
+        }
+    }
+
+    // Gives each live torrent its share of the peer connection budget. Torrents that went
+    // live since the last time get theirs on the next run.
+    fn share_peer_budget(&self, budget: usize) {
+        let live: Vec<ManagedTorrentHandle> = self.with_torrents(|torrents| {
+            torrents
+                .filter(|(_, t)| t.live().is_some())
+                .map(|(_, t)| t.clone())
+                .collect()
+        });
+        let tiers: Vec<PriorityTier> = live.iter().map(|t| t.priority_tier()).collect();
+        for (torrent, limit) in live.iter().zip(allocate_peer_budget(budget, &tiers)) {
+            torrent.set_peer_limit(Some(limit));
+        }
+    }
+
+    /// Weight the torrent's share of [SessionOptions::peer_connection_budget]. The shares
+    /// are recomputed right away.
+    pub fn set_priority_tier(&self, id: TorrentId, tier: PriorityTier) -> anyhow::Result<()> {
+        let handle = self
+            .get(id)
+            .with_context(|| format!("torrent with id {} did not exist", id))?;
+        handle.check_unlocked()?;
+        handle.set_priority_tier(tier);
+        if let Some(budget) = self.peer_connection_budget {
+            self.share_peer_budget(budget);
+        }
+        Ok(())
+    }
+
+    async fn task_error_retry(self: Arc<Self>, opts: ErrorRetryOptions) -> anyhow::Result<()> {
+        let mut tracker = ErrorRetryTracker::new(&opts);
+        let session = Arc::downgrade(&self);
+        drop(self);
+        loop {
+            tokio::time::sleep(ERROR_RETRY_CHECK_INTERVAL).await;
+            let session = match session.upgrade() {
+                Some(s) => s,
+                None => return Ok(()),
+            };
+            let torrents = session.with_torrents(|torrents| {
+                torrents
+                    .map(|(id, t)| (id, t.clone()))
+                    .collect::<Vec<_>>()
+            });
+            tracker.retain(|id| torrents.iter().any(|(t, _)| *t == id));
+            let now = std::time::Instant::now();
+            for (id, handle) in torrents {
+                if handle.live().is_some() {
+                    tracker.on_live(id);
+                    continue;
+                }
+                if handle.is_locked() || !handle.info().failed_initializing.load(Ordering::Relaxed)
+                {
+                    continue;
+                }
+                // Those wait for a manual resume once the disk is writable again.
+                let error = match handle.with_state(|s| match s {
+                    ManagedTorrentState::Error(e) if !is_read_only_fs_error(e) => {
+                        Some(format!("{e:#}"))
+                    }
+                    _ => None,
+                }) {
+                    Some(error) => error,
+                    None => continue,
+                };
+                match tracker.on_errored(id, now) {
+                    None => {}
+                    Some(ErrorRetryAction::Retry { attempt }) => {
+                        info!(
+                            id,
+                            attempt,
+                            retries = opts.retries,
+                            %error,
+                            "retrying torrent in error"
+                        );
+                        if let Err(e) = session.unpause(&handle) {
+                            warn!(id, "error retrying torrent: {:#}", e);
+                        }
+                    }
+                    Some(ErrorRetryAction::GiveUp { attempts }) => {
+                        warn!(id, attempts, %error, "still failing to initialize, giving up");
+                        let _ = handle
+                            .info()
+                            .events
+                            .send(TorrentEvent::RetriesExhausted { attempts, error });
+                    }
+                }
+            }
+        }
+    }
+
+    // Torrents wake it when they stop taking up a download slot, see
+    // ManagedTorrentInfo::notify_state_changed(), and so does the session when one is removed
+    // or solo mode ends. Woken while running, it runs once more right after.
+    async fn task_download_queue(self: Arc<Self>) -> anyhow::Result<()> {
+        let changed = self.download_queue_changed.clone();
+        let session = Arc::downgrade(&self);
+        drop(self);
+        loop {
+            changed.notified().await;
+            let session = match session.upgrade() {
+                Some(s) => s,
+                None => return Ok(()),
+            };
+            session.start_queued();
+        }
+    }
+
+    // Starts queued torrents from the front while there are free download slots. Nothing
+    // starts in solo mode, the slots stay free for the solo torrent.
+    fn start_queued(&self) {
+        let max = match self.max_active_downloads {
+            Some(max) => max,
+            None => return,
+        };
+        let to_start = {
+            let mut g = self.db.write();
+            let g = &mut *g;
+            if g.solo.is_some() {
+                return;
+            }
+            // Drop the ones that were removed or started by hand. Queued torrents still
+            // being checked stay in line.
+            let torrents = &g.torrents;
+            g.queue.retain(|id| {
+                torrents
+                    .get(id)
+                    .map(|t| {
+                        t.with_state(|s| {
+                            matches!(
+                                s,
+                                ManagedTorrentState::Paused(_)
+                                    | ManagedTorrentState::Lazy(_)
+                                    | ManagedTorrentState::Initializing(_)
+                            )
+                        })
+                    })
+                    .unwrap_or(false)
+            });
+            let mut free = max.saturating_sub(g.active_downloads());
+            let mut to_start = Vec::new();
+            g.queue.retain(|id| {
+                if free == 0 {
+                    return true;
+                }
+                let handle = &torrents[id];
+                if handle.is_locked() || !handle.with_state(|s| s.is_paused()) {
+                    return true;
+                }
+                free -= 1;
+                to_start.push((*id, handle.clone()));
+                false
+            });
+            to_start
+        };
+        for (id, handle) in to_start {
+            info!(id, "starting queued torrent");
+            if let Err(e) = self.unpause(&handle) {
+                warn!(id, "error starting queued torrent: {:#}", e);
+            }
+        }
+    }
+
+    // Pauses the live torrents that have nothing left to download, returns their ids.
+    fn pause_seeding_torrents(&self) -> Vec<TorrentId> {
+        let seeding = self.with_torrents(|torrents| {
+            torrents
+                .filter(|(_, t)| !t.is_locked())
+                .filter(|(_, t)| t.live().map(|l| l.is_finished()).unwrap_or(false))
+                .map(|(id, t)| (id, t.clone()))
+                .collect::<Vec<_>>()
+        });
+        let mut paused = Vec::new();
+        for (id, handle) in seeding {
+            match handle.pause() {
+                Ok(()) => paused.push(id),
+                Err(e) => warn!(id, "error pausing seeding torrent: {:#}", e),
+            }
+        }
+        paused
+    }
+
+    async fn check_incoming_connection(
+        &self,
+        addr: SocketAddr,
+        mut stream: TcpStream,
+    ) -> anyhow::Result<(Arc<TorrentStateLive>, CheckedIncomingConnection)> {
+        // TODO: move buffer handling to peer_connection
+
+        let rwtimeout = self
+            .peer_opts
+            .read_write_timeout
+            .unwrap_or_else(|| Duration::from_secs(10));
+
+        let mut read_buf = ReadBuf::new();
+        let h = read_buf
+            .read_handshake(&mut stream, rwtimeout)
+            .await
+            .context("error reading handshake")?;
+        trace!("received handshake from {addr}: {:?}", h);
+
+        if h.peer_id == self.peer_id.0 {
+            bail!("seems like we are connecting to ourselves, ignoring");
+        }
+
+        for (id, torrent) in self.db.read().torrents.iter() {
+            if torrent.info_hash().0 != h.info_hash {
+                continue;
+            }
+
+            let live = match torrent.live() {
+                Some(live) => live,
+                None => {
+                    bail!("torrent {id} is not live, ignoring connection");
+                }
+            };
+
+            let handshake = h.clone_to_owned();
+
+            return Ok((
+                live,
+                CheckedIncomingConnection {
+                    addr,
+                    stream,
+                    handshake,
+                    read_buf,
+                },
+            ));
+        }
+
+        bail!("didn't find a matching torrent for {:?}", Id20::new(h.info_hash))
+    }
+
+    async fn task_tcp_listener(self: Arc<Self>, l: TcpListener) -> anyhow::Result<()> {
+        let mut futs = FuturesUnordered::new();
 
         loop {
             tokio::select! {
@@ -576,8 +1923,11 @@ impl Session {
                     }
                 },
                 Some(Ok((live, checked))) = futs.next(), if !futs.is_empty() => {
-                    if let Err(e) = live.add_incoming_peer(checked) {
-                        warn!("error handing over incoming connection: {e:#}");
+                    match live.add_incoming_peer(checked) {
+                        Ok(()) => {
+                            self.incoming_peers.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => warn!("error handing over incoming connection: {e:#}"),
                     }
                 },
             }
@@ -585,14 +1935,700 @@ impl Session {
     }
 
     async fn task_upnp_port_forwarder(self: Arc<Self>, port: u16) -> anyhow::Result<()> {
-        let pf = librqbit_upnp::UpnpPortForwarder::new(vec![port], None)?;
-        pf.run_forever().await
+        let mut network_changed = self.network_changed.subscribe();
+        loop {
+            // The gateway and our local IP may both be different after a network change,
+            // so start from scratch.
+            let pf_opts =
+                upnp_forwarder_options(self.upnp_lease_duration, self.upnp_refresh_interval);
+            let pf = librqbit_upnp::UpnpPortForwarder::new(vec![port], Some(pf_opts))?;
+            *self.upnp_status.write() = Some(pf.status());
+            tokio::select! {
+                _ = pf.run_forever() => {},
+                r = network_changed.changed() => {
+                    r?;
+                    info!("network changed, redoing port forwarding");
+                }
+                _ = self.upnp_remap.notified() => {
+                    info!("redoing port forwarding on request");
+                }
+            }
+        }
+    }
+
+    // "list_ips" is librqbit_upnp::list_local_ips outside of tests.
+    async fn task_network_change_watcher<T: PartialEq + std::fmt::Debug + Send>(
+        self: Arc<Self>,
+        poll_interval: Duration,
+        mut list_ips: impl FnMut() -> anyhow::Result<T> + Send,
+    ) -> anyhow::Result<()> {
+        let mut last = list_ips()?;
+        loop {
+            tokio::time::sleep(poll_interval).await;
+            let current = match list_ips() {
+                Ok(ips) => ips,
+                Err(e) => {
+                    debug!("error listing local IPs: {e:#}");
+                    continue;
+                }
+            };
+            if current != last {
+                info!(old=?last, new=?current, "local IP addresses changed");
+                last = current;
+                self.on_network_change();
+            }
+        }
+    }
+
+    async fn task_wake_watcher(self: Arc<Self>, gap: Duration) -> anyhow::Result<()> {
+        let mut detector = SleepDetector::new(SystemTime::now(), SLEEP_CHECK_INTERVAL, gap);
+        loop {
+            tokio::time::sleep(SLEEP_CHECK_INTERVAL).await;
+            if let Some(slept) = detector.tick(SystemTime::now()) {
+                info!(?slept, "woke from sleep");
+                self.on_wake_from_sleep();
+            }
+        }
+    }
+
+    /// Get torrents going again quickly after the host was asleep, and all connections died
+    /// at once: everything [Session::on_network_change] does, and retrying peers right away
+    /// instead of after their reconnect backoff. Embedders that are told about waking by
+    /// the OS can call this instead of setting [SessionOptions::wake_recovery_gap].
+    pub fn on_wake_from_sleep(&self) {
+        self.on_network_change();
+        let live = self.with_torrents(|torrents| {
+            torrents
+                .filter_map(|(id, t)| t.live().map(|l| (id, l)))
+                .collect::<Vec<_>>()
+        });
+        for (id, live) in live {
+            match live.reconnect_dead_peers() {
+                Ok(requeued) => debug!(id, requeued, "retrying peers after sleep"),
+                Err(e) => warn!(id, "error retrying peers after sleep: {:#}", e),
+            }
+        }
+    }
+
+    /// Redo UPnP port forwarding, bootstrap DHT again and re-announce all torrents to
+    /// trackers and DHT. Call this when the network the host is on changes.
+    ///
+    /// The TCP listener and the DHT socket are bound to the unspecified address, so they
+    /// keep working across network changes and aren't re-bound.
+    pub fn on_network_change(&self) {
+        // A port forward that worked before may not anymore.
+        self.incoming_peers.store(0, Ordering::Relaxed);
+        self.network_changed
+            .send_modify(|generation| *generation += 1);
+        if let Some(dht) = self.dht.as_ref() {
+            dht.on_network_change();
+        }
     }
 
     pub fn get_dht(&self) -> Option<&Dht> {
         self.dht.as_ref()
     }
 
+    /// Magnet links that are being added, and are still waiting for metadata.
+    pub fn resolving_magnets(&self) -> Vec<ResolvingMagnet> {
+        use std::sync::atomic::Ordering::Relaxed;
+        self.resolving_magnets
+            .iter()
+            .map(|e| ResolvingMagnet {
+                info_hash: e.key().as_string(),
+                peers_seen: e.value().peers_seen.load(Relaxed),
+                peers_connecting: e.value().peers_connecting.load(Relaxed),
+                peers_failed: e.value().peers_failed.load(Relaxed),
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        SessionStats {
+            outstanding_requests: self.request_limiter.outstanding(),
+            max_outstanding_requests: self.request_limiter.max(),
+            announce_enabled: *self.announce_enabled.borrow(),
+            queued_adds: self.queued_adds.load(Ordering::Relaxed),
+            max_torrents: self.max_torrents,
+            seeding_announce_interval_secs: self
+                .seeding_announce_interval
+                .borrow()
+                .map(|d| d.as_secs()),
+            solo_torrent_id: self.db.read().solo.as_ref().map(|s| s.id),
+            upload_saturation: self.upload_saturation.as_ref().map(|s| s.read().clone()),
+            bandwidth_limits: self.bandwidth.stats(),
+            verify_from_memory: self.verify_from_memory.as_ref().map(|b| b.stats()),
+            listen: self.listen_status(),
+            read_only_fs_errors: self.read_only_fs_errors.load(Ordering::Relaxed),
+            connect_rate: self.connect_ramp.stats(),
+        }
+    }
+
+    /// Whether the TCP listener is bound, and inbound peers have connected to it.
+    pub fn listen_status(&self) -> ListenStatus {
+        let incoming_peers = self.incoming_peers.load(Ordering::Relaxed);
+        ListenStatus {
+            bound: self.tcp_listen_port.is_some(),
+            port: self.tcp_listen_port,
+            incoming_peers,
+            port_open: incoming_peers > 0,
+        }
+    }
+
+    /// The UPnP port mappings, with their external address, lease and the last error.
+    pub fn upnp_status(&self) -> UpnpStatus {
+        UpnpStatus {
+            enabled: self.upnp_port_forwarding,
+            mappings: self
+                .upnp_status
+                .read()
+                .as_ref()
+                .map(|s| s.mappings())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Discover the routers again and redo all UPnP port mappings now, instead of waiting
+    /// for the next renewal.
+    pub fn upnp_remap(&self) -> anyhow::Result<()> {
+        if !self.upnp_port_forwarding {
+            bail!("UPnP port forwarding is disabled");
+        }
+        self.upnp_remap.notify_one();
+        Ok(())
+    }
+
+    /// Disk space on each filesystem in use: the default output folder, the incomplete
+    /// folder if set, and the output folders of all torrents. Inaccessible paths are
+    /// reported with an error instead of sizes.
+    pub async fn storage_stats(&self) -> anyhow::Result<Vec<StorageRootStats>> {
+        let mut paths = vec![(None, self.output_folder.clone())];
+        paths.extend(self.incomplete_dir.clone().map(|dir| (None, dir)));
+        self.with_torrents(|torrents| {
+            paths.extend(torrents.map(|(id, t)| (Some(id), t.info().out_dir.clone())))
+        });
+        // A slow or hung mount blocks in the filesystem calls.
+        tokio::task::spawn_blocking(move || storage::storage_stats(paths))
+            .await
+            .context("error querying storage")
+    }
+
+    /// Torrents with their output folder, or their incomplete folder, under "prefix", e.g.
+    /// all torrents on a disk about to be unmounted. Paths are compared component by
+    /// component as given, without resolving symlinks or "..".
+    pub fn torrents_by_path(&self, prefix: &Path) -> Vec<TorrentId> {
+        self.with_torrents(|torrents| {
+            torrents
+                .filter(|(_, t)| {
+                    let info = t.info();
+                    info.out_dir.starts_with(prefix)
+                        || info
+                            .options
+                            .incomplete_dir
+                            .as_ref()
+                            .map(|dir| dir.starts_with(prefix))
+                            .unwrap_or(false)
+                })
+                .map(|(id, _)| id)
+                .collect()
+        })
+    }
+
+    /// The current [SessionOptions::startup_connect_rate].
+    pub fn startup_connect_rate(&self) -> Option<StartupConnectRate> {
+        self.connect_ramp.rate()
+    }
+
+    /// Change [SessionOptions::startup_connect_rate]. The ramp starts over from now, e.g. to
+    /// spread out the connections of a large batch of torrents about to be added. None
+    /// stops limiting dials.
+    pub fn set_startup_connect_rate(&self, rate: Option<StartupConnectRate>) -> anyhow::Result<()> {
+        if rate.map(|r| r.initial_per_sec) == Some(0) {
+            bail!("startup_connect_rate must be at least 1 per second");
+        }
+        self.connect_ramp.set_rate(rate);
+        Ok(())
+    }
+
+    /// Change [SessionOptions::seeding_announce_interval]. Takes effect on each tracker's
+    /// next announce. Torrents with their own interval keep it.
+    pub fn set_seeding_announce_interval(&self, interval: Option<Duration>) -> anyhow::Result<()> {
+        check_seeding_announce_interval(interval)?;
+        self.seeding_announce_interval.send_replace(interval);
+        info!(?interval, "changed seeding announce interval");
+        Ok(())
+    }
+
+    /// Pause or resume all tracker announces and DHT requests, for all torrents.
+    /// Torrents keep running, and established peer connections are left intact.
+    pub fn set_announce_enabled(&self, enabled: bool) {
+        self.announce_enabled.send_replace(enabled);
+        if let Some(dht) = self.dht.as_ref() {
+            dht.set_paused(!enabled);
+        }
+        info!(enabled, "changed announce state");
+    }
+
+    /// The session's network, tracker and limit settings, to set up another instance the
+    /// same way with [Session::import_profile].
+    pub fn export_profile(&self, name: String) -> SessionProfile {
+        let rate = self.startup_connect_rate();
+        SessionProfile {
+            version: SESSION_PROFILE_VERSION,
+            name,
+            runtime: ProfileRuntimeSettings {
+                announce_enabled: *self.announce_enabled.borrow(),
+                seeding_announce_interval_secs: self
+                    .seeding_announce_interval
+                    .borrow()
+                    .map(|d| d.as_secs()),
+                startup_connect_rate_per_sec: rate.map(|r| r.initial_per_sec),
+                startup_connect_ramp_secs: rate.and_then(|r| r.ramp).map(|d| d.as_secs()),
+                dht_read_only: self.dht.as_ref().map(|d| d.is_read_only()).unwrap_or(false),
+            },
+            startup: ProfileStartupSettings {
+                peer_opts: self.peer_opts,
+                announce_ip: self.announce_ip,
+                enable_upnp_port_forwarding: self.upnp_port_forwarding,
+                peer_source_priority: self.peer_source_priority.clone(),
+                upload_slots: self.upload_slots,
+                max_torrents: self.max_torrents,
+                max_active_downloads: self.max_active_downloads,
+                peer_connection_budget: self.peer_connection_budget,
+                reserved_headroom_bps: self.bandwidth.headroom_bps(),
+                link_capacity: self.bandwidth.capacity(),
+                max_tracker_response_size: self.max_tracker_response_size,
+                max_tracker_redirects: self.max_tracker_redirects,
+                dead_tracker_timeout_secs: self.dead_tracker_timeout.map(|d| d.as_secs()),
+            },
+        }
+    }
+
+    /// Apply a profile from [Session::export_profile], e.g. one exported by another
+    /// instance. The runtime settings change right away. The startup settings can't change
+    /// while the session runs: the ones that differ are returned, see
+    /// [ProfileStartupSettings::apply_to].
+    pub fn import_profile(&self, profile: &SessionProfile) -> anyhow::Result<ProfileImportResult> {
+        profile.check_version()?;
+        let runtime = &profile.runtime;
+        let interval = runtime
+            .seeding_announce_interval_secs
+            .map(Duration::from_secs);
+        // Checked before anything is changed.
+        check_seeding_announce_interval(interval)?;
+        let rate = runtime
+            .startup_connect_rate_per_sec
+            .map(|initial_per_sec| StartupConnectRate {
+                initial_per_sec,
+                ramp: runtime.startup_connect_ramp_secs.map(Duration::from_secs),
+            });
+        let current_rate = self.startup_connect_rate();
+        // Setting it starts the ramp over, so it's left alone if it's the same.
+        if rate.map(|r| (r.initial_per_sec, r.ramp))
+            != current_rate.map(|r| (r.initial_per_sec, r.ramp))
+        {
+            self.set_startup_connect_rate(rate)?;
+        }
+        if runtime.announce_enabled != *self.announce_enabled.borrow() {
+            self.set_announce_enabled(runtime.announce_enabled);
+        }
+        if interval != *self.seeding_announce_interval.borrow() {
+            self.set_seeding_announce_interval(interval)?;
+        }
+        if let Some(dht) = self.dht.as_ref() {
+            if dht.is_read_only() != runtime.dht_read_only {
+                dht.set_read_only(runtime.dht_read_only);
+            }
+        }
+        let needs_restart = self
+            .export_profile(String::new())
+            .startup
+            .changed_fields(&profile.startup);
+        info!(profile = %profile.name, ?needs_restart, "imported profile");
+        Ok(ProfileImportResult {
+            name: profile.name.clone(),
+            needs_restart,
+        })
+    }
+
+    /// Give one torrent all the bandwidth: start it if it's paused, and pause every other
+    /// live torrent. [Session::clear_solo] resumes only the torrents paused here, so ones
+    /// that were paused already stay paused. Calling this again with another torrent
+    /// switches to it.
+    pub fn solo(&self, id: TorrentId) -> anyhow::Result<()> {
+        let handle = self
+            .get(id)
+            .with_context(|| format!("torrent with id {} did not exist", id))?;
+        if handle.with_state(|s| s.is_paused()) {
+            self.unpause(&handle).context("error starting torrent")?;
+        }
+
+        let mut paused = self
+            .db
+            .read()
+            .solo
+            .as_ref()
+            .map(|solo| solo.paused.clone())
+            .unwrap_or_default();
+        paused.retain(|p| *p != id);
+        // Set before pausing the others, so that the download queue doesn't take the slots
+        // they free up.
+        self.db.write().solo = Some(SoloMode {
+            id,
+            paused: paused.clone(),
+        });
+        let others = self.with_torrents(|torrents| {
+            torrents
+                .filter(|(other_id, t)| *other_id != id && t.live().is_some() && !t.is_locked())
+                .map(|(other_id, t)| (other_id, t.clone()))
+                .collect::<Vec<_>>()
+        });
+        for (other_id, other) in others {
+            match other.pause() {
+                Ok(()) => paused.push(other_id),
+                Err(e) => warn!(id = other_id, "error pausing torrent for solo mode: {:#}", e),
+            }
+        }
+        info!(id, paused = paused.len(), "entered solo mode");
+        self.db.write().solo = Some(SoloMode { id, paused });
+        Ok(())
+    }
+
+    /// Leave solo mode, resuming the torrents [Session::solo] paused. Ones that were
+    /// resumed or removed in the meantime are skipped.
+    pub fn clear_solo(&self) -> anyhow::Result<()> {
+        let solo = match self.db.write().solo.take() {
+            Some(solo) => solo,
+            None => return Ok(()),
+        };
+        for id in solo.paused {
+            let handle = match self.get(id) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            if !handle.with_state(|s| s.is_paused()) {
+                continue;
+            }
+            if let Err(e) = self.unpause(&handle) {
+                warn!(id, "error resuming torrent after solo mode: {:#}", e);
+            }
+        }
+        info!(id = solo.id, "left solo mode");
+        self.download_queue_changed.notify_one();
+        Ok(())
+    }
+
+    /// The current solo mode, if any.
+    pub fn solo_mode(&self) -> Option<SoloMode> {
+        self.db.read().solo.clone()
+    }
+
+    /// Give the torrent priority for "duration": start it if it's paused or waiting in the
+    /// download queue, and let its chunk requests past
+    /// [SessionOptions::max_outstanding_requests_global] instead of waiting their turn.
+    /// Afterwards it's put back how it was: paused again, or back in the queue at its old
+    /// position unless it finished meanwhile. Boosting it again restarts the countdown.
+    /// Boosts aren't persisted, after a restart the torrent is as it was before the boost.
+    pub fn boost(self: &Arc<Self>, id: TorrentId, duration: Duration) -> anyhow::Result<()> {
+        if duration.is_zero() {
+            bail!("boost duration must be above 0");
+        }
+        let handle = self
+            .get(id)
+            .with_context(|| format!("torrent with id {} did not exist", id))?;
+        handle.check_unlocked()?;
+        let until = std::time::Instant::now() + duration;
+        let is_paused = handle.with_state(|s| s.is_paused());
+        {
+            let mut g = self.db.write();
+            let g = &mut *g;
+            match g.boosts.get_mut(&id) {
+                Some(boost) => boost.until = until,
+                None => {
+                    let queue_position = g.queue.iter().position(|q| *q == id);
+                    g.queue.retain(|q| *q != id);
+                    let boost = Boost {
+                        until,
+                        queue_position,
+                        was_paused: is_paused,
+                    };
+                    g.boosts.insert(id, boost);
+                }
+            }
+        }
+        handle.set_boosted_until(Some(until));
+        if is_paused {
+            if let Err(e) = self.unpause(&handle) {
+                self.end_boost(id, until);
+                return Err(e.context("error starting torrent"));
+            }
+        }
+
+        let session = Arc::downgrade(self);
+        self.spawn(error_span!("boost", id), async move {
+            tokio::time::sleep_until(until.into()).await;
+            if let Some(session) = session.upgrade() {
+                session.end_boost(id, until);
+            }
+            Ok(())
+        });
+        info!(id, ?duration, "boosted torrent");
+        Ok(())
+    }
+
+    // Puts the torrent back how it was before the boost ending at "until". Does nothing if
+    // the boost was restarted since.
+    fn end_boost(&self, id: TorrentId, until: std::time::Instant) {
+        let boost = {
+            let mut g = self.db.write();
+            if g.boosts.get(&id).map(|b| b.until) != Some(until) {
+                return;
+            }
+            match g.boosts.remove(&id) {
+                Some(boost) => boost,
+                None => return,
+            }
+        };
+        let handle = match self.get(id) {
+            Some(handle) => handle,
+            None => return,
+        };
+        handle.set_boosted_until(None);
+        let finished = handle.live().map(|l| l.is_finished()).unwrap_or(false);
+        let requeue_at = boost.queue_position.filter(|_| !finished);
+        let pause = requeue_at.is_some() || (boost.was_paused && boost.queue_position.is_none());
+        if pause && handle.live().is_some() && !handle.is_locked() {
+            if let Err(e) = handle.pause() {
+                warn!(id, "error pausing torrent after boost: {:#}", e);
+            }
+        }
+        if let Some(pos) = requeue_at {
+            let mut g = self.db.write();
+            if !g.queue.contains(&id) {
+                let pos = pos.min(g.queue.len());
+                g.queue.insert(pos, id);
+            }
+        }
+        info!(id, "boost ended");
+    }
+
+    /// Torrents waiting for a download slot, in the order they'll be started. Empty unless
+    /// [SessionOptions::max_active_downloads] is set.
+    pub fn queue(&self) -> Vec<TorrentId> {
+        self.db.read().queue.clone()
+    }
+
+    /// Why a torrent is or isn't downloading, e.g. no peers, or waiting for a download slot.
+    /// None if there's no such torrent.
+    pub fn diagnose_torrent(&self, id: TorrentId) -> Option<TorrentDiagnosis> {
+        let (torrent, queue_position) = {
+            let g = self.db.read();
+            let torrent = g.torrents.get(&id)?.clone();
+            (torrent, g.queue.iter().position(|q| *q == id))
+        };
+        Some(torrent.diagnose(queue_position, *self.announce_enabled.borrow()))
+    }
+
+    /// How far a magnet link that is being added got with its metadata, see
+    /// [Self::resolving_magnets]. None if it isn't being resolved.
+    pub fn diagnose_magnet(&self, info_hash: Id20) -> Option<TorrentDiagnosis> {
+        use std::sync::atomic::Ordering::Relaxed;
+        let counters = self.resolving_magnets.get(&info_hash)?;
+        Some(TorrentDiagnosis::MetadataNotResolved {
+            peers_seen: counters.peers_seen.load(Relaxed),
+            peers_connecting: counters.peers_connecting.load(Relaxed),
+            peers_failed: counters.peers_failed.load(Relaxed),
+        })
+    }
+
+    /// Take the torrent out of the download queue. It stays paused until started by hand.
+    /// False if it wasn't queued.
+    pub fn dequeue(&self, id: TorrentId) -> anyhow::Result<bool> {
+        let mut g = self.db.write();
+        if !g.torrents.contains_key(&id) {
+            bail!("torrent with id {} did not exist", id);
+        }
+        let queued = g.queue.contains(&id);
+        g.queue.retain(|q| *q != id);
+        if queued {
+            info!(id, "removed torrent from the download queue");
+        }
+        Ok(queued)
+    }
+
+    /// Reorder the download queue, e.g. smallest first. Only queued torrents move, the ones
+    /// downloading already are not affected. Torrents that compare equal keep their order.
+    pub fn sort_queue(&self, key: QueueSortKey, reverse: bool) -> Vec<TorrentId> {
+        let mut g = self.db.write();
+        let mut entries = g
+            .queue
+            .iter()
+            .filter_map(|id| g.torrents.get(id).map(|t| (*id, t)))
+            .map(|(id, t)| QueueEntry {
+                id,
+                total_bytes: t.get_total_bytes(),
+                progress_bytes: t.stats().progress_bytes,
+                added_at: t.added_at(),
+                name: t
+                    .info()
+                    .info
+                    .name
+                    .as_ref()
+                    .map(|n| n.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        sort_entries(&mut entries, key, reverse);
+        g.queue = entries.into_iter().map(|e| e.id).collect();
+        info!(?key, reverse, "sorted download queue");
+        g.queue.clone()
+    }
+
+    /// Start a new, empty collection: a named group of torrents, e.g. for a project, to
+    /// pause, start or delete together, see [crate::api::Api::api_collection_stats] for
+    /// their stats. A torrent can be in several collections. Fails if the name is taken.
+    pub fn create_collection(&self, name: &str) -> anyhow::Result<()> {
+        self.db.write().collections.create(name)?;
+        info!(name, "created collection");
+        Ok(())
+    }
+
+    /// Remove the collection. Its torrents stay in the session.
+    pub fn delete_collection(&self, name: &str) -> anyhow::Result<()> {
+        self.db.write().collections.delete(name)?;
+        info!(name, "deleted collection");
+        Ok(())
+    }
+
+    /// Put the torrent in the collection. Torrents leave their collections when they're
+    /// removed from the session.
+    pub fn add_to_collection(&self, name: &str, id: TorrentId) -> anyhow::Result<()> {
+        let mut g = self.db.write();
+        if !g.torrents.contains_key(&id) {
+            bail!("torrent with id {} did not exist", id);
+        }
+        g.collections.add(name, id)
+    }
+
+    pub fn remove_from_collection(&self, name: &str, id: TorrentId) -> anyhow::Result<()> {
+        self.db.write().collections.remove(name, id)
+    }
+
+    /// All collections by name, with the ids of their torrents.
+    pub fn collections(&self) -> BTreeMap<String, Vec<TorrentId>> {
+        self.db.read().collections.all()
+    }
+
+    /// The ids of the torrents in the collection.
+    pub fn collection(&self, name: &str) -> anyhow::Result<Vec<TorrentId>> {
+        self.db.read().collections.members(name)
+    }
+
+    // The (from, to) files of other torrents to copy into "output_folder", see
+    // AddTorrentOptions::cross_seed. The copying itself happens while the torrent
+    // initializes.
+    fn cross_seed_copies(
+        &self,
+        info: &TorrentMetaV1Info<ByteString>,
+        info_hash: Id20,
+        output_folder: &Path,
+        only_files: Option<&[usize]>,
+    ) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+        let torrents: Vec<ManagedTorrentHandle> = self.with_torrents(|torrents| {
+            torrents
+                .filter(|(_, t)| t.info_hash() != info_hash)
+                .map(|(_, t)| t.clone())
+                .collect()
+        });
+        let sources: Vec<CrossSeedSource> = torrents
+            .iter()
+            .flat_map(|t| t.cross_seed_sources())
+            .collect();
+        let files = info
+            .iter_filenames_and_lengths()?
+            .map(|(f, l)| Ok((f.to_pathbuf()?, l)))
+            .collect::<anyhow::Result<Vec<(PathBuf, u64)>>>()?;
+        let copies: Vec<(PathBuf, PathBuf)> = match_files(&files, &sources)
+            .into_iter()
+            .zip(files.iter())
+            .enumerate()
+            .filter(|(idx, _)| only_files.map(|o| o.contains(idx)).unwrap_or(true))
+            .filter_map(|(_, (source, (path, _)))| {
+                Some((sources[source?].path.clone(), output_folder.join(path)))
+            })
+            .collect();
+        if copies.is_empty() {
+            debug!("cross-seeding: no matching files in the session");
+        }
+        Ok(copies)
+    }
+
+    // The paths a torrent with "info" added to "output_folder" would write to, like
+    // ManagedTorrentInfo::written_paths().
+    fn written_paths(
+        &self,
+        info: &TorrentMetaV1Info<ByteString>,
+        output_folder: &Path,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        let mut incomplete_paths = Vec::new();
+        for (f, _) in info.iter_filenames_and_lengths()? {
+            let relative = f.to_pathbuf()?;
+            let path = output_folder.join(&relative);
+            let incomplete = match &self.incomplete_dir {
+                Some(dir) => dir.join(&relative),
+                None => path.clone(),
+            };
+            match &self.incomplete_suffix {
+                Some(suffix) => incomplete_paths.push(with_incomplete_suffix(&incomplete, suffix)),
+                None if self.incomplete_dir.is_some() => incomplete_paths.push(incomplete),
+                None => {}
+            }
+            paths.push(path);
+        }
+        paths.extend(incomplete_paths);
+        Ok(paths)
+    }
+
+    // Applies SessionOptions::on_path_conflict to a torrent about to be added to
+    // "output_folder", returning the folder to add it to. Checked again when the torrent is
+    // inserted, as another one may get added meanwhile.
+    fn resolve_path_conflict(
+        &self,
+        info: &TorrentMetaV1Info<ByteString>,
+        info_hash: Id20,
+        output_folder: PathBuf,
+    ) -> anyhow::Result<PathBuf> {
+        let existing = self.db.read().written_paths(info_hash)?;
+        let paths_in = |dir: &Path| self.written_paths(info, dir);
+        let (id, path) = match find_path_conflict(&existing, &paths_in(&output_folder)?) {
+            Some(conflict) => conflict,
+            None => return Ok(output_folder),
+        };
+        match self.on_path_conflict {
+            PathConflictPolicy::Reject => Err(ApiError::path_conflict(path, id).into()),
+            PathConflictPolicy::Warn => {
+                warn!(
+                    ?path,
+                    "torrent {id} already writes to this path, adding anyway"
+                );
+                Ok(output_folder)
+            }
+            PathConflictPolicy::Subfolder => {
+                let subfolder = output_folder.join(info_hash.as_string());
+                if let Some((id, path)) = find_path_conflict(&existing, &paths_in(&subfolder)?) {
+                    return Err(ApiError::path_conflict(path, id).into());
+                }
+                info!(
+                    ?path,
+                    ?subfolder,
+                    "torrent {id} already writes to this path, adding to a subfolder"
+                );
+                Ok(subfolder)
+            }
+        }
+    }
+
     fn merge_peer_opts(&self, other: Option<PeerConnectionOptions>) -> PeerConnectionOptions {
         let other = match other {
             Some(o) => o,
@@ -603,9 +2639,13 @@ impl Session {
             read_write_timeout: other
                 .read_write_timeout
                 .or(self.peer_opts.read_write_timeout),
+            handshake_timeout: other
+                .handshake_timeout
+                .or(self.peer_opts.handshake_timeout),
             keep_alive_interval: other
                 .keep_alive_interval
                 .or(self.peer_opts.keep_alive_interval),
+            choke_timeout: other.choke_timeout.or(self.peer_opts.choke_timeout),
         }
     }
 
@@ -638,18 +2678,23 @@ impl Session {
     }
 
     async fn populate_from_stored(self: &Arc<Self>) -> anyhow::Result<()> {
-        let mut rdr = match std::fs::File::open(&self.persistence_filename) {
+        let persistence_filename = self.persistence_filename();
+        let mut rdr = match std::fs::File::open(&persistence_filename) {
             Ok(f) => BufReader::new(f),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
             Err(e) => {
                 return Err(e).context(format!(
                     "error opening session file {:?}",
-                    self.persistence_filename
+                    persistence_filename
                 ))
             }
         };
         let db: SerializedSessionDatabase =
             serde_json::from_reader(&mut rdr).context("error deserializing session database")?;
+        self.db.write().solo = db.solo;
+        // Set once the torrents are back, so that the queue isn't pruned before.
+        let queue = db.queue;
+        let mut collections = db.collections;
         let mut futures = Vec::new();
         for (id, storrent) in db.torrents.into_iter() {
             let trackers: Vec<ByteString> = storrent
@@ -663,6 +2708,11 @@ impl Session {
                     .cloned()
                     .unwrap_or_else(|| ByteString(b"http://retracker.local/announce".to_vec())),
                 announce_list: vec![trackers],
+                httpseeds: storrent
+                    .http_seeds
+                    .into_iter()
+                    .map(|s| ByteString(s.into_bytes()))
+                    .collect(),
                 info: storrent.info,
                 comment: None,
                 created_by: None,
@@ -675,8 +2725,42 @@ impl Session {
             futures.push({
                 let session = self.clone();
                 async move {
-                    session
-                        .add_torrent(
+                    let added_at = storrent.added_at;
+                    let thresholds = &storrent.progress_thresholds;
+                    let sample_files = sample_files(thresholds);
+                    let pause_at_percent = threshold_percent(thresholds, &ProgressAction::Pause);
+                    let sequential_after_percent =
+                        threshold_percent(thresholds, &ProgressAction::SwitchToSequential);
+                    let tracker_redirects = storrent
+                        .tracker_redirects
+                        .iter()
+                        .filter_map(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+                        .collect::<HashMap<Url, Url>>();
+                    let dead_trackers = storrent
+                        .dead_trackers
+                        .iter()
+                        .filter_map(|url| url.parse().ok())
+                        .collect::<HashSet<Url>>();
+                    let lifetime = LifetimeTransfer {
+                        uploaded_bytes: storrent.uploaded_bytes,
+                        downloaded_bytes: storrent.downloaded_bytes,
+                        web_seed_bytes: storrent.web_seed_bytes,
+                    };
+                    // Session files from older versions don't have the progress, these
+                    // torrents are loaded once the usual way.
+                    let lazy = match (storrent.progress_bytes, storrent.selected_bytes) {
+                        (Some(progress_bytes), Some(total_bytes))
+                            if session.lazy_load && storrent.is_paused =>
+                        {
+                            Some(PersistedProgress {
+                                progress_bytes,
+                                total_bytes,
+                            })
+                        }
+                        _ => None,
+                    };
+                    let response = session
+                        .add_torrent_queued(
                             AddTorrent::TorrentInfo(Box::new(info)),
                             Some(AddTorrentOptions {
                                 paused: storrent.is_paused,
@@ -687,26 +2771,83 @@ impl Session {
                                         .context("broken path")?
                                         .to_owned(),
                                 ),
-                                only_files: storrent.only_files,
+                                only_files: storrent.only_files.filter(|_| sample_files.is_none()),
+                                sample_files,
+                                labels: storrent.labels,
+                                user_metadata: Some(storrent.user_metadata),
+                                sequential: storrent.sequential,
+                                // Older versions accepted any interval.
+                                seeding_announce_interval: storrent
+                                    .seeding_announce_interval_secs
+                                    .map(|secs| {
+                                        Duration::from_secs(secs).max(MIN_SEEDING_ANNOUNCE_INTERVAL)
+                                    }),
+                                upload_subnets: storrent.upload_subnets,
+                                pause_at_percent,
+                                sequential_after_percent,
+                                // Older versions accepted any timeouts.
+                                peer_opts: storrent.peer_opts.filter(|o| match o.validate() {
+                                    Ok(()) => true,
+                                    Err(e) => {
+                                        warn!(id, "ignoring stored peer_opts: {:#}", e);
+                                        false
+                                    }
+                                }),
+                                mirror_paths: storrent.mirror_paths,
                                 overwrite: true,
                                 preferred_id: Some(id),
                                 ..Default::default()
                             }),
+                            false,
+                            Some(RestoredTorrent {
+                                cached_peers: storrent.peers,
+                                lazy,
+                                tracker_redirects,
+                            }),
                         )
                         .await
                         .map_err(|e| {
                             error!("error adding torrent from stored session: {:?}", e);
                             e
-                        })
+                        })?;
+                    if let AddTorrentResponse::Added(_, handle) = &response {
+                        handle.restore_lifetime(added_at, lifetime);
+                        handle.restore_dead_trackers(dead_trackers);
+                        handle.set_priority_tier(storrent.priority_tier);
+                        if let Err(e) = handle.set_upload_slots(storrent.upload_slots) {
+                            warn!(id, "ignoring stored upload slots: {:#}", e);
+                        }
+                        // Last, as it refuses changes.
+                        handle.set_locked(storrent.locked);
+                    }
+                    Ok::<_, anyhow::Error>(response)
                 }
             });
         }
         futures::future::join_all(futures).await;
+        let mut g = self.db.write();
+        g.queue = queue;
+        // Torrents that failed to come back are dropped from their collections.
+        collections.retain_torrents(|id| g.torrents.contains_key(&id));
+        g.collections = collections;
+        drop(g);
+        self.download_queue_changed.notify_one();
         Ok(())
     }
 
     fn dump_to_disk(&self) -> anyhow::Result<()> {
-        let tmp_filename = format!("{}.tmp", self.persistence_filename.to_str().unwrap());
+        // Held while writing, so that relocate_persistence() can't switch files mid-dump.
+        let filename = self.persistence_filename.write();
+        self.dump_to_disk_at(&filename)
+    }
+
+    fn dump_to_disk_at(&self, persistence_filename: &Path) -> anyhow::Result<()> {
+        let tmp_filename = format!(
+            "{}.tmp",
+            persistence_filename
+                .to_str()
+                .context("persistence filename is not valid UTF-8")?
+        );
         let mut tmp = BufWriter::new(
             std::fs::OpenOptions::new()
                 .create(true)
@@ -715,13 +2856,51 @@ impl Session {
                 .open(&tmp_filename)
                 .with_context(|| format!("error opening {:?}", tmp_filename))?,
         );
-        let serialized = self.db.read().serialize();
+        let serialized = self.db.read().serialize(self.persist_peers);
         serde_json::to_writer(&mut tmp, &serialized).context("error serializing")?;
         drop(tmp);
 
-        std::fs::rename(&tmp_filename, &self.persistence_filename)
+        std::fs::rename(&tmp_filename, persistence_filename)
             .context("error renaming persistence file")?;
-        trace!(filename=?self.persistence_filename, "wrote persistence");
+        trace!(filename=?persistence_filename, "wrote persistence");
+        Ok(())
+    }
+
+    /// The file the session state is periodically dumped to.
+    pub fn persistence_filename(&self) -> PathBuf {
+        self.persistence_filename.read().clone()
+    }
+
+    // See SessionOptions::file_sink_dir.
+    pub(crate) fn file_sink_dir(&self) -> Option<&Path> {
+        self.file_sink_dir.as_deref()
+    }
+
+    // See SessionOptions::mirror_dir.
+    pub(crate) fn mirror_dir(&self) -> Option<&Path> {
+        self.mirror_dir.as_deref()
+    }
+
+    /// Dump the session state to a new file, and switch all future dumps to it.
+    /// The old file is left as is. The new file must not exist yet, and its path must be
+    /// absolute, without "..".
+    pub fn relocate_persistence(&self, filename: PathBuf) -> anyhow::Result<()> {
+        if !self.persistence {
+            bail!("session persistence is disabled");
+        }
+        check_state_file_target(&filename)?;
+        // Periodic dumps wait until the switch is done.
+        let mut current = self.persistence_filename.write();
+        if let Some(parent) = filename.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("couldn't create directory {:?} for session storage", parent)
+            })?;
+        }
+        // This also checks that the new location is writable.
+        self.dump_to_disk_at(&filename)
+            .with_context(|| format!("error writing session state to {:?}", filename))?;
+        info!(?filename, "relocated session persistence");
+        *current = filename;
         Ok(())
     }
 
@@ -733,17 +2912,71 @@ impl Session {
         callback(&mut self.db.read().torrents.iter().map(|(id, t)| (*id, t)))
     }
 
-    /// Add a torrent to the session.
+    /// Add a torrent to the session. With [SessionOptions::max_concurrent_adds] set, this
+    /// waits for a free slot first.
     pub async fn add_torrent(
         &self,
         add: AddTorrent<'_>,
         opts: Option<AddTorrentOptions>,
+    ) -> anyhow::Result<AddTorrentResponse> {
+        self.add_torrent_queued(add, opts, true, None).await
+    }
+
+    // Restoring the session passes enforce_max_torrents=false, so that lowering
+    // SessionOptions::max_torrents doesn't drop torrents from the session file, and what was
+    // saved with the torrent as "restored".
+    async fn add_torrent_queued(
+        &self,
+        add: AddTorrent<'_>,
+        opts: Option<AddTorrentOptions>,
+        enforce_max_torrents: bool,
+        restored: Option<RestoredTorrent>,
+    ) -> anyhow::Result<AddTorrentResponse> {
+        let _permit = match self.add_semaphore.as_ref() {
+            Some(sem) => {
+                let _queued = QueuedAddGuard::new(&self.queued_adds);
+                Some(sem.acquire().await.context("bug: add semaphore closed")?)
+            }
+            None => None,
+        };
+        self.add_torrent_now(add, opts, enforce_max_torrents, restored)
+            .await
+    }
+
+    async fn add_torrent_now(
+        &self,
+        add: AddTorrent<'_>,
+        opts: Option<AddTorrentOptions>,
+        enforce_max_torrents: bool,
+        restored: Option<RestoredTorrent>,
     ) -> anyhow::Result<AddTorrentResponse> {
         // Magnet links are different in that we first need to discover the metadata.
         let span = error_span!("add_torrent");
         let _ = span.enter();
 
         let opts = opts.unwrap_or_default();
+        check_seeding_announce_interval(opts.seeding_announce_interval)?;
+        if let Some(percent) = opts.pause_at_percent {
+            if percent.is_nan() || percent <= 0. || percent > 100. {
+                bail!("pause_at_percent must be more than 0 and at most 100");
+            }
+        }
+        if let Some(percent) = opts.sequential_after_percent {
+            if percent.is_nan() || !(0. ..=100.).contains(&percent) {
+                bail!("sequential_after_percent must be between 0 and 100");
+            }
+        }
+        if let Some(sample_files) = &opts.sample_files {
+            if opts.only_files.is_some() || opts.only_files_regex.is_some() {
+                bail!("sample_files can't be combined with only_files or only_files_regex");
+            }
+            if sample_files.is_empty() {
+                bail!("sample_files is empty, use list_only to only get the metadata");
+            }
+        }
+        if let Some(peer_opts) = &opts.peer_opts {
+            peer_opts.validate().context("invalid peer_opts")?;
+        }
 
         let announce_port = if opts.list_only {
             None
@@ -751,7 +2984,7 @@ impl Session {
             self.tcp_listen_port
         };
 
-        let (info_hash, info, dht_rx, trackers, initial_peers) = match add {
+        let (info_hash, info, info_bytes, dht_rx, trackers, http_seeds, initial_peers) = match add {
             AddTorrent::Url(magnet) if magnet.starts_with("magnet:") => {
                 let magnet = Magnet::parse(&magnet).context("provided path is not a valid magnet URL")?;
                 let info_hash = magnet.as_id20().context("magnet link didn't contain a BTv1 infohash")?;
@@ -774,30 +3007,50 @@ impl Session {
                     .collect();
 
                 debug!(?info_hash, "querying DHT");
-                let (info, dht_rx, initial_peers) = match read_metainfo_from_peer_receiver(
-                    self.peer_id,
-                    info_hash,
-                    opts.initial_peers.clone().unwrap_or_default(),
-                    dht_rx,
-                    Some(self.merge_peer_opts(opts.peer_opts)),
-                )
-                .await
-                {
-                    ReadMetainfoResult::Found { info, rx, seen } => (info, rx, seen),
-                    ReadMetainfoResult::ChannelClosed { .. } => {
-                        anyhow::bail!("DHT died, no way to discover torrent metainfo")
+                let resolving_guard = ResolvingMagnetGuard::new(&self.resolving_magnets, info_hash);
+                let counters = &resolving_guard.counters;
+                let per_magnet_semaphore;
+                let semaphore = match self.metadata_peer_semaphore.as_ref() {
+                    Some(s) => s,
+                    None => {
+                        per_magnet_semaphore = Semaphore::new(DEFAULT_MAX_METADATA_PEERS);
+                        &per_magnet_semaphore
                     }
                 };
+                let (info, info_bytes, dht_rx, initial_peers) =
+                    match read_metainfo_from_peer_receiver(
+                        self.peer_id,
+                        info_hash,
+                        opts.initial_peers.clone().unwrap_or_default(),
+                        dht_rx,
+                        Some(self.merge_peer_opts(opts.peer_opts)),
+                        semaphore,
+                        counters,
+                    )
+                    .await
+                    {
+                        ReadMetainfoResult::Found {
+                            info,
+                            info_bytes,
+                            rx,
+                            seen,
+                        } => (info, Some(info_bytes), rx, seen),
+                        ReadMetainfoResult::ChannelClosed { .. } => {
+                            anyhow::bail!("DHT died, no way to discover torrent metainfo")
+                        }
+                    };
                 debug!(?info, "received result from DHT");
                 (
                     info_hash,
                     info,
+                    info_bytes,
                     if opts.paused || opts.list_only {
                         None
                     } else {
                         Some(dht_rx)
                     },
                     trackers,
+                    Vec::new(),
                     initial_peers,
                 )
             }
@@ -806,7 +3059,7 @@ impl Session {
                     AddTorrent::Url(url)
                         if url.starts_with("http://") || url.starts_with("https://") =>
                     {
-                        torrent_from_url(&url).await?
+                        torrent_from_url(&url, self.dns.as_ref()).await?
                     }
                     AddTorrent::Url(url) => {
                         bail!(
@@ -817,6 +3070,7 @@ impl Session {
                     AddTorrent::TorrentFileBytes(bytes) => {
                         torrent_from_bytes(&bytes).context("error decoding torrent")?
                     }
+                    AddTorrent::TorrentFilePath(path) => torrent_from_path(&path)?,
                     AddTorrent::TorrentInfo(t) => *t,
                 };
 
@@ -846,11 +3100,27 @@ impl Session {
                         }
                     })
                     .collect::<Vec<_>>();
+                let http_seeds = torrent
+                    .httpseeds
+                    .iter()
+                    .filter_map(|seed| {
+                        let url = std::str::from_utf8(seed.as_ref()).ok()?;
+                        match Url::parse(url) {
+                            Ok(url) => Some(url),
+                            Err(e) => {
+                                warn!("cannot parse HTTP seed URL {}: {}", url, e);
+                                None
+                            }
+                        }
+                    })
+                    .collect::<Vec<_>>();
                 (
                     torrent.info_hash,
                     torrent.info,
+                    None,
                     dht_rx,
                     trackers,
+                    http_seeds,
                     opts.initial_peers
                         .clone()
                         .unwrap_or_default()
@@ -863,10 +3133,14 @@ impl Session {
         self.main_torrent_info(
             info_hash,
             info,
+            info_bytes,
             dht_rx,
             initial_peers.into_iter().collect(),
             trackers,
+            http_seeds,
             opts,
+            enforce_max_torrents,
+            restored,
         )
         .await
     }
@@ -876,13 +3150,33 @@ impl Session {
         &self,
         info_hash: Id20,
         info: TorrentMetaV1Info<ByteString>,
+        info_bytes: Option<ByteString>,
         dht_peer_rx: Option<RequestPeersStream>,
         initial_peers: Vec<SocketAddr>,
         trackers: Vec<reqwest::Url>,
+        http_seeds: Vec<reqwest::Url>,
         opts: AddTorrentOptions,
+        enforce_max_torrents: bool,
+        restored: Option<RestoredTorrent>,
     ) -> anyhow::Result<AddTorrentResponse> {
         debug!("Torrent info: {:#?}", &info);
 
+        // Also done when the torrent is built, but malformed torrents shouldn't get listed.
+        Lengths::from_torrent(&info)?;
+
+        if let Some(expect) = &opts.expect {
+            expect
+                .check(&info)
+                .context("torrent metadata doesn't match what was expected")?;
+        }
+
+        let upload_subnets = opts
+            .upload_subnets
+            .as_deref()
+            .map(parse_subnets)
+            .transpose()
+            .context("invalid upload_subnets")?;
+
         let get_only_files =
             |only_files: Option<Vec<usize>>, only_files_regex: Option<String>, list_only: bool| {
                 match (only_files, only_files_regex) {
@@ -914,7 +3208,23 @@ impl Session {
                 }
             };
 
-        let only_files = get_only_files(opts.only_files, opts.only_files_regex, opts.list_only)?;
+        let sample_files = opts.sample_files;
+        let only_files = get_only_files(
+            opts.only_files.or_else(|| sample_files.clone()),
+            opts.only_files_regex,
+            opts.list_only,
+        )?;
+
+        let fastresume = opts
+            .fastresume
+            .as_deref()
+            .map(|data| FastResume::parse(data, info_hash, &info))
+            .transpose()
+            .context("invalid fastresume file")?;
+        let only_files = match (only_files, &fastresume) {
+            (None, Some(fastresume)) => fastresume.only_files.clone(),
+            (only_files, _) => only_files,
+        };
 
         let get_default_subfolder = || {
             let files = info
@@ -959,52 +3269,237 @@ impl Session {
             }));
         }
 
+        // Restored torrents were checked when they were first added.
+        let restoring = restored.is_some();
+        let output_folder = if restoring {
+            output_folder
+        } else {
+            self.resolve_path_conflict(&info, info_hash, output_folder)?
+        };
+        let written_paths = match self.on_path_conflict {
+            PathConflictPolicy::Reject | PathConflictPolicy::Subfolder if !restoring => {
+                Some(self.written_paths(&info, &output_folder)?)
+            }
+            _ => None,
+        };
+
+        if let Some(mirror) = opts.mirror_paths.iter().find(|m| **m == output_folder) {
+            bail!("mirror path {:?} is the output folder", mirror);
+        }
+
+        let existing_data_policy = match opts.existing_data_policy {
+            Some(policy) => policy,
+            None if opts.overwrite || fastresume.is_some() || opts.cross_seed => {
+                ExistingDataPolicy::Verify
+            }
+            None => ExistingDataPolicy::Error,
+        };
+
+        let cross_seed_copies = if opts.cross_seed
+            && !restoring
+            && existing_data_policy == ExistingDataPolicy::Verify
+        {
+            self.cross_seed_copies(&info, info_hash, &output_folder, only_files.as_deref())?
+        } else {
+            Vec::new()
+        };
+
         let mut builder = ManagedTorrentBuilder::new(info, info_hash, output_folder.clone());
+        builder.cross_seed_copies(cross_seed_copies);
         builder
-            .overwrite(opts.overwrite)
+            .existing_data_policy(existing_data_policy)
             .spawner(self.spawner)
             .cancellation_token(self.cancellation_token.child_token())
-            .peer_id(self.peer_id);
+            .peer_id(self.peer_id)
+            .fsync_policy(self.power_profile.fsync_policy(self.fsync_policy))
+            .power_profile(self.power_profile)
+            .first_piece_strategy(self.first_piece_strategy)
+            .rarest_first_randomness(self.rarest_first_randomness)
+            .no_persist(opts.no_persist)
+            .contiguous_storage(opts.contiguous_storage)
+            .sequential(opts.sequential)
+            .mirror_paths(opts.mirror_paths)
+            .info_bytes(info_bytes)
+            .upload_subnets(upload_subnets)
+            .labels(opts.labels)
+            .user_metadata(opts.user_metadata.unwrap_or_default())
+            .request_limiter(self.request_limiter.clone())
+            .bandwidth(self.bandwidth.clone())
+            .connect_ramp(self.connect_ramp.clone())
+            .read_only_fs_errors(self.read_only_fs_errors.clone())
+            .state_changed(self.download_queue_changed.clone())
+            .dns_resolver(self.dns.clone())
+            .tracker_client(self.tracker_client.clone())
+            .pause_on_poisoned_piece(self.pause_on_poisoned_piece)
+            .verification_mode(self.verification_mode)
+            .prefer_good_peers(self.prefer_good_peers)
+            .verify_before_write(self.verify_before_write)
+            .verify_from_memory(self.verify_from_memory.clone())
+            .announce_enabled(self.announce_enabled.subscribe())
+            .global_seeding_announce_interval(self.seeding_announce_interval.subscribe())
+            .peer_source_priority(self.peer_source_priority.clone())
+            .network_changed(self.network_changed.subscribe());
+
+        if let Some(ip) = self.announce_ip {
+            if !opts.disable_announce_ip {
+                builder.announce_ip(ip);
+            }
+        }
+        if let Some(threshold) = self.poisoned_piece_threshold {
+            builder.poisoned_piece_threshold(threshold);
+        }
+        if let Some(max_duplicates) = self.endgame_max_duplicates {
+            builder.endgame_max_duplicates(max_duplicates);
+        }
+        if let Some(timeout) = self.stall_timeout {
+            builder.stall_timeout(timeout);
+        }
+        if let Some(interval) = self.missing_files_check_interval {
+            builder.missing_files_check_interval(interval);
+        }
+        if let Some(interval) = opts.seeding_announce_interval {
+            builder.seeding_announce_interval(interval);
+        }
+        if let Some(suffix) = &self.incomplete_suffix {
+            builder.incomplete_suffix(suffix.clone());
+        }
+        if let Some(dir) = &self.incomplete_dir {
+            builder.incomplete_dir(dir.clone());
+        }
+        if let Some(max_size) = self.max_tracker_response_size {
+            builder.max_tracker_response_size(max_size);
+        }
+        if let Some(limit) = self.slow_peer_piece_limit {
+            builder.slow_peer_piece_limit(limit);
+        }
+        if let Some(slots) = self.upload_slots {
+            builder.upload_slots(slots);
+        }
+        if let Some(percent) = opts.pause_at_percent {
+            builder.pause_at_percent(percent);
+        }
+        if let Some(percent) = opts.sequential_after_percent {
+            builder.sequential_after_percent(percent);
+        }
+        if let Some(max_redirects) = self.max_tracker_redirects {
+            builder.max_tracker_redirects(max_redirects);
+        }
+        builder.dead_tracker_timeout(self.dead_tracker_timeout);
+        if let Some(scheduler) = &self.tracker_host_scheduler {
+            builder.tracker_host_scheduler(scheduler.clone());
+        }
+        if let Some(mode) = self.file_mode {
+            builder.file_mode(mode);
+        }
+        if let Some(gid) = self.file_group {
+            builder.file_group(gid);
+        }
 
         if opts.disable_trackers {
             builder.trackers(trackers);
         }
+        builder.http_seeds(http_seeds);
 
         if let Some(only_files) = only_files {
             builder.only_files(only_files);
         }
+        if let Some(sample_files) = sample_files {
+            builder.sample_files(sample_files);
+        }
+        if let Some(fastresume) = fastresume {
+            builder.trusted_have_pieces(fastresume.have_pieces);
+        }
         if let Some(interval) = opts.force_tracker_interval {
             builder.force_tracker_interval(interval);
         }
 
-        let peer_opts = self.merge_peer_opts(opts.peer_opts);
-
-        if let Some(t) = peer_opts.connect_timeout {
+        if let Some(t) = self.peer_opts.connect_timeout {
             builder.peer_connect_timeout(t);
         }
 
-        if let Some(t) = peer_opts.read_write_timeout {
+        if let Some(t) = self.peer_opts.read_write_timeout {
             builder.peer_read_write_timeout(t);
         }
 
-        let (managed_torrent, id) = {
+        if let Some(t) = self.peer_opts.handshake_timeout {
+            builder.peer_handshake_timeout(t);
+        }
+
+        if let Some(t) = self.peer_opts.choke_timeout {
+            builder.peer_choke_timeout(t);
+        }
+
+        if let Some(peer_opts) = opts.peer_opts {
+            builder.peer_opts(peer_opts);
+        }
+
+        if let Some(restored) = restored {
+            builder
+                .peer_cache(restored.cached_peers)
+                .tracker_redirects(restored.tracker_redirects);
+            if let Some(progress) = restored.lazy {
+                builder.lazy(progress);
+            }
+        }
+
+        let (managed_torrent, id, queued) = {
             let mut g = self.db.write();
             if let Some((id, handle)) = g.torrents.iter().find(|(_, t)| t.info_hash() == info_hash)
             {
                 return Ok(AddTorrentResponse::AlreadyManaged(*id, handle.clone()));
             }
+            match self.max_torrents {
+                Some(max) if enforce_max_torrents && g.torrents.len() >= max => {
+                    return Err(ApiError::max_torrents_reached(max).into());
+                }
+                _ => {}
+            }
+            if let Some(paths) = &written_paths {
+                if let Some((id, path)) = find_path_conflict(&g.written_paths(info_hash)?, paths) {
+                    return Err(ApiError::path_conflict(path, id).into());
+                }
+            }
             let next_id = g.torrents.len();
             let managed_torrent =
                 builder.build(error_span!(parent: None, "torrent", id = next_id))?;
+            // Restored torrents come with their place in the queue already.
+            let queued = match self.max_active_downloads {
+                Some(max) if !restoring && !opts.paused && !opts.list_only => {
+                    g.active_downloads() >= max
+                }
+                _ => false,
+            };
             let id = g.add_torrent(managed_torrent.clone(), opts.preferred_id);
-            (managed_torrent, id)
+            if queued {
+                info!(id, "download slots full, queueing torrent");
+                g.queue.push(id);
+            }
+            (managed_torrent, id, queued)
         };
 
-        {
+        // For magnets, the peers seen while resolving come from the DHT.
+        let manual_peers: HashSet<SocketAddr> =
+            opts.initial_peers.iter().flatten().copied().collect();
+        let initial_peers = initial_peers
+            .into_iter()
+            .map(|addr| {
+                let source = if manual_peers.contains(&addr) {
+                    PeerSource::Manual
+                } else {
+                    PeerSource::Dht
+                };
+                (addr, source)
+            })
+            .collect();
+
+        // Lazy torrents are initialized once started.
+        if !managed_torrent.is_lazy() {
             let span = managed_torrent.info.span.clone();
             let _ = span.enter();
+            // Queued torrents ask the DHT for peers again once started.
+            let dht_peer_rx = if queued { None } else { dht_peer_rx };
             managed_torrent
-                .start(initial_peers, dht_peer_rx, opts.paused)
+                .start(initial_peers, dht_peer_rx, opts.paused || queued)
                 .context("error starting torrent")?;
         }
 
@@ -1016,44 +3511,81 @@ impl Session {
     }
 
     pub fn delete(&self, id: TorrentId, delete_files: bool) -> anyhow::Result<()> {
-        let removed = self
-            .db
-            .write()
-            .torrents
-            .remove(&id)
-            .with_context(|| format!("torrent with id {} did not exist", id))?;
+        self.delete_with_opts(
+            id,
+            DeleteOptions {
+                delete_files,
+                ..Default::default()
+            },
+        )
+    }
 
-        let paused = removed
+    /// Fails if the torrent is locked, see [crate::ManagedTorrent::set_locked].
+    pub fn delete_with_opts(&self, id: TorrentId, opts: DeleteOptions) -> anyhow::Result<()> {
+        let removed = {
+            let mut db = self.db.write();
+            db.torrents
+                .get(&id)
+                .with_context(|| format!("torrent with id {} did not exist", id))?
+                .check_unlocked()?;
+            db.collections.retain_torrents(|other| other != id);
+            db.torrents.remove(&id).unwrap()
+        };
+        self.download_queue_changed.notify_one();
+
+        // The files are closed once the paused state is dropped.
+        let filenames = removed
             .with_state_mut(|s| {
                 let paused = match s.take() {
                     ManagedTorrentState::Paused(p) => p,
                     ManagedTorrentState::Live(l) => l.pause()?,
+                    ManagedTorrentState::Lazy(l) => {
+                        return Ok(Some(l.existing_files(removed.info())?))
+                    }
                     _ => return Ok(None),
                 };
-                Ok::<_, anyhow::Error>(Some(paused))
+                Ok::<_, anyhow::Error>(Some(paused.filenames))
             })
             .context("error pausing torrent");
+        // For the file sinks still waiting for its pieces.
+        removed.info().pieces_changed.notify_waiters();
 
-        match (paused, delete_files) {
+        match (filenames, opts.delete_files) {
             (Err(e), true) => Err(e).context("torrent deleted, but could not delete files"),
             (Err(e), false) => {
                 warn!(error=?e, "could not delete torrent files");
                 Ok(())
             }
-            (Ok(Some(paused)), true) => {
-                drop(paused.files);
-                for file in paused.filenames {
-                    if let Err(e) = std::fs::remove_file(&file) {
+            (Ok(Some(filenames)), true) => {
+                for file in filenames.iter() {
+                    if let Err(e) = std::fs::remove_file(file) {
                         warn!(?file, error=?e, "could not delete file");
                     }
                 }
+                if opts.remove_empty_dirs {
+                    // Files still in incomplete_dir are cleaned up to there, not the output
+                    // folder.
+                    let info = removed.info();
+                    let mut by_root: BTreeMap<&Path, Vec<PathBuf>> = BTreeMap::new();
+                    for file in filenames.iter() {
+                        by_root
+                            .entry(info.root_of(file))
+                            .or_default()
+                            .push(file.clone());
+                    }
+                    for (root, files) in by_root {
+                        remove_empty_dirs(root, &files);
+                    }
+                }
                 Ok(())
             }
             _ => Ok(()),
         }
     }
 
+    /// Start a paused torrent. Fails if it's locked, see [crate::ManagedTorrent::set_locked].
     pub fn unpause(&self, handle: &ManagedTorrentHandle) -> anyhow::Result<()> {
+        handle.check_unlocked()?;
         let peer_rx = self
             .dht
             .as_ref()
@@ -1062,4 +3594,982 @@ impl Session {
         handle.start(Default::default(), peer_rx, false)?;
         Ok(())
     }
+
+    /// Hash all the torrent's data on disk again, e.g. after its files went missing or were
+    /// replaced from outside. A live torrent or one in error starts again afterwards, a paused
+    /// one stays paused.
+    pub fn force_recheck(&self, handle: &ManagedTorrentHandle) -> anyhow::Result<()> {
+        let paused = handle.reset_for_recheck()?;
+        let peer_rx = if paused {
+            None
+        } else {
+            self.dht
+                .as_ref()
+                .map(|dht| dht.get_peers(handle.info_hash(), self.tcp_listen_port))
+                .transpose()?
+        };
+        handle.start(Default::default(), peer_rx, paused)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use dashmap::DashMap;
+    use dht::Id20;
+    use http::StatusCode;
+
+    use super::{
+        AddTorrent, AddTorrentOptions, AddTorrentResponse, ApiError, DeleteOptions,
+        ManagedTorrentHandle, PathConflictPolicy, PeerConnectionOptions, ResolvingMagnetGuard,
+        Session, SessionEvent, SessionOptions, TorrentId, UploadSaturationOptions,
+    };
+    use crate::test_util::{
+        add_test_torrent, http_response, request_path, serve_http, test_data, test_dir,
+        test_session, test_torrent, test_torrent_with_announce, wait_until_live, RawPeer,
+        TEST_PIECE_LENGTH,
+    };
+
+    #[tokio::test]
+    async fn test_invalid_options() {
+        let dir = test_dir("invalid-options");
+        let invalid = [
+            SessionOptions {
+                endgame_max_duplicates: Some(0),
+                ..Default::default()
+            },
+            SessionOptions {
+                seeding_announce_interval: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            // Checked against the default lease of 60 seconds.
+            SessionOptions {
+                upnp_refresh_interval: Some(Duration::from_secs(120)),
+                ..Default::default()
+            },
+            SessionOptions {
+                upnp_lease_duration: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            SessionOptions {
+                upload_saturation: Some(UploadSaturationOptions {
+                    capacity_bytes_per_sec: 0,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            // Would drop every peer as soon as it chokes us.
+            SessionOptions {
+                peer_opts: Some(PeerConnectionOptions {
+                    choke_timeout: Some(Duration::ZERO),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            // Would check for idleness in a busy loop.
+            SessionOptions {
+                idle_shutdown: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            // Would list the network interfaces in a busy loop.
+            SessionOptions {
+                network_change_poll_interval: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            // Only used with FirstPieceStrategy::Rarest.
+            SessionOptions {
+                rarest_first_randomness: Some(2),
+                ..Default::default()
+            },
+            SessionOptions {
+                announce_ip: Some("0.0.0.0".parse().unwrap()),
+                ..Default::default()
+            },
+        ];
+        for opts in invalid {
+            let opts = SessionOptions {
+                disable_dht: true,
+                persistence: false,
+                ..opts
+            };
+            assert!(Session::new_with_opts(dir.clone(), opts).await.is_err());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_network_change_reannounces() {
+        let dir = test_dir("network-change");
+        let announces = Arc::new(AtomicUsize::new(0));
+        let addr = serve_http({
+            let announces = announces.clone();
+            move |request| {
+                if request_path(request) == "/announce" {
+                    announces.fetch_add(1, Ordering::SeqCst);
+                }
+                http_response("200 OK", &[], b"d8:intervali3600e5:peers0:e")
+            }
+        })
+        .await;
+        let wait_for_announces = |n: usize| {
+            let announces = announces.clone();
+            async move {
+                for _ in 0..500 {
+                    if announces.load(Ordering::SeqCst) >= n {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                panic!("expected {n} announces, got {announces:?}");
+            }
+        };
+        let session = test_session(&dir, Default::default()).await;
+        let announce = format!("http://{addr}/announce");
+        let torrent =
+            test_torrent_with_announce("t", &[("f", &test_data(100, 0))], Some(&announce));
+        session
+            .add_torrent(AddTorrent::from_bytes(torrent), None)
+            .await
+            .unwrap();
+        wait_for_announces(1).await;
+
+        // The addresses change on the third listing, the first one is what they were at start.
+        let mut listings = 0;
+        let list_ips = move || -> anyhow::Result<Vec<IpAddr>> {
+            listings += 1;
+            let ip = if listings < 3 {
+                "192.168.1.2"
+            } else {
+                "10.0.0.2"
+            };
+            Ok(vec![ip.parse()?])
+        };
+        session.spawn(
+            tracing::Span::none(),
+            session
+                .clone()
+                .task_network_change_watcher(Duration::from_millis(20), list_ips),
+        );
+        wait_for_announces(2).await;
+        // Once, as the addresses stay the same after that, and the interval is an hour.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(announces.load(Ordering::SeqCst), 2);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listen_status() {
+        let dir = test_dir("listen-status");
+        let session = test_session(&dir, Default::default()).await;
+        let status = session.listen_status();
+        assert!(!status.bound && status.port.is_none() && !status.port_open);
+        session.stop().await;
+
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27500..27600),
+                ..Default::default()
+            },
+        )
+        .await;
+        let status = session.stats().listen;
+        assert!(status.bound);
+        assert_eq!(status.incoming_peers, 0);
+        assert!(!status.port_open);
+        let port = status.port.unwrap();
+        assert!((27500..27600).contains(&port));
+
+        let (_, handle) = add_test_torrent(
+            &session,
+            "t",
+            &[("f", &test_data(100, 0))],
+            Default::default(),
+        )
+        .await;
+        let _peer = RawPeer::connect(port, handle.info_hash()).await;
+        for _ in 0..500 {
+            if session.listen_status().incoming_peers > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let status = session.listen_status();
+        assert_eq!(status.incoming_peers, 1);
+        assert!(status.port_open);
+
+        // The port forward has to prove itself again on the new network.
+        session.on_network_change();
+        let status = session.listen_status();
+        assert!(status.bound);
+        assert_eq!(status.incoming_peers, 0);
+        assert!(!status.port_open);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_announce_ip() {
+        let dir = test_dir("announce-ip");
+        // The path and the whole request target of each announce.
+        let announces = Arc::new(parking_lot::Mutex::new(Vec::<(String, String)>::new()));
+        let addr = serve_http({
+            let announces = announces.clone();
+            move |request| {
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                announces
+                    .lock()
+                    .push((request_path(request).to_owned(), target.to_owned()));
+                http_response("200 OK", &[], b"d8:intervali3600e5:peers0:e")
+            }
+        })
+        .await;
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                announce_ip: Some("203.0.113.7".parse().unwrap()),
+                ..Default::default()
+            },
+        )
+        .await;
+        let data = test_data(100, 0);
+        for (name, disable_announce_ip) in [("with-ip", false), ("without-ip", true)] {
+            let announce = format!("http://{addr}/{name}");
+            let torrent = test_torrent_with_announce(name, &[("f", &data)], Some(&announce));
+            let opts = AddTorrentOptions {
+                disable_announce_ip,
+                ..Default::default()
+            };
+            session
+                .add_torrent(AddTorrent::from_bytes(torrent), Some(opts))
+                .await
+                .unwrap();
+        }
+        let announced = |path: &str| {
+            announces
+                .lock()
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, target)| target.clone())
+        };
+        for _ in 0..500 {
+            if announced("/with-ip").is_some() && announced("/without-ip").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(announced("/with-ip").unwrap().contains("&ip=203.0.113.7"));
+        assert!(!announced("/without-ip").unwrap().contains("&ip="));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_announce_enabled() {
+        let dir = test_dir("announce-enabled");
+        let announces = Arc::new(AtomicUsize::new(0));
+        let addr = serve_http({
+            let announces = announces.clone();
+            move |_| {
+                announces.fetch_add(1, Ordering::SeqCst);
+                http_response("200 OK", &[], b"d8:intervali3600e5:peers0:e")
+            }
+        })
+        .await;
+        let session = test_session(&dir, Default::default()).await;
+        session.set_announce_enabled(false);
+        assert!(!session.stats().announce_enabled);
+
+        let announce = format!("http://{addr}/announce");
+        let torrent =
+            test_torrent_with_announce("t", &[("f", &test_data(100, 0))], Some(&announce));
+        let handle = match session
+            .add_torrent(AddTorrent::from_bytes(torrent), None)
+            .await
+            .unwrap()
+        {
+            AddTorrentResponse::Added(_, handle) => handle,
+            _ => panic!("expected the torrent to be added"),
+        };
+        wait_until_live(&handle).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(announces.load(Ordering::SeqCst), 0);
+        // The torrent itself keeps running.
+        assert!(handle.live().is_some());
+
+        session.set_announce_enabled(true);
+        assert!(session.stats().announce_enabled);
+        for _ in 0..500 {
+            if announces.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(announces.load(Ordering::SeqCst), 1);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_shutdown() {
+        let dir = test_dir("idle-shutdown");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                idle_shutdown: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        )
+        .await;
+        let mut events = session.subscribe_events();
+        let data = test_data(TEST_PIECE_LENGTH as usize, 0);
+        std::fs::create_dir_all(dir.join("complete")).unwrap();
+        std::fs::write(dir.join("complete/f"), &data).unwrap();
+        let (_, complete) = add_test_torrent(
+            &session,
+            "complete",
+            &[("f", &data)],
+            AddTorrentOptions {
+                overwrite: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        let (incomplete_id, incomplete) = add_test_torrent(
+            &session,
+            "incomplete",
+            &[("f", &test_data(100, 1))],
+            AddTorrentOptions {
+                paused: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        let wait_paused = |handle: ManagedTorrentHandle| async move {
+            while !handle.with_state(|s| s.is_paused()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        wait_paused(incomplete.clone()).await;
+
+        // Seeding keeps the session running.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!session.cancellation_token().is_cancelled());
+
+        // So does a paused torrent that isn't complete.
+        complete.pause().unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!session.cancellation_token().is_cancelled());
+
+        // Only complete and paused torrents left.
+        session.delete(incomplete_id, false).unwrap();
+        match tokio::time::timeout(Duration::from_secs(5), events.recv()).await {
+            Ok(Ok(SessionEvent::IdleShutdown { idle_for })) => {
+                assert!(idle_for >= Duration::from_millis(100))
+            }
+            other => panic!("expected an idle shutdown event, got {other:?}"),
+        }
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            session.cancellation_token().cancelled(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_solo() {
+        let dir = test_dir("solo");
+        let session = test_session(&dir, Default::default()).await;
+        let data = test_data(100, 0);
+        let mut torrents = Vec::new();
+        for name in ["a", "b", "c"] {
+            torrents
+                .push(add_test_torrent(&session, name, &[("f", &data)], Default::default()).await);
+        }
+        let (a, b, c) = (&torrents[0], &torrents[1], &torrents[2]);
+        let is_paused = |t: &(TorrentId, ManagedTorrentHandle)| t.1.with_state(|s| s.is_paused());
+
+        // Paused by hand before, so it stays paused after.
+        c.1.pause().unwrap();
+        session.solo(a.0).unwrap();
+        assert!(!is_paused(a) && is_paused(b) && is_paused(c));
+        assert_eq!(session.solo_mode().unwrap().paused, vec![b.0]);
+
+        // Switching to another torrent pauses the previous one, and resumes only what solo
+        // mode paused when it's cleared.
+        session.solo(b.0).unwrap();
+        wait_until_live(&b.1).await;
+        assert!(is_paused(a) && is_paused(c));
+        assert_eq!(session.solo_mode().unwrap().paused, vec![a.0]);
+        session.clear_solo().unwrap();
+        assert!(session.solo_mode().is_none());
+        wait_until_live(&a.1).await;
+        assert!(!is_paused(b) && is_paused(c));
+
+        // Nothing to clear.
+        session.clear_solo().unwrap();
+
+        // A torrent solo mode paused, but resumed and removed since, is skipped.
+        session.solo(c.0).unwrap();
+        wait_until_live(&c.1).await;
+        session.delete(a.0, false).unwrap();
+        session.unpause(&b.1).unwrap();
+        session.clear_solo().unwrap();
+        wait_until_live(&b.1).await;
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_queue() {
+        let dir = test_dir("download-queue");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                max_active_downloads: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        let data = test_data(100, 0);
+        let (a_id, a) = add_test_torrent(&session, "a", &[("f", &data)], Default::default()).await;
+        let mut queued = Vec::new();
+        for name in ["b", "c"] {
+            let add = AddTorrent::from_bytes(test_torrent(name, &[("f", &data)]));
+            match session.add_torrent(add, None).await.unwrap() {
+                AddTorrentResponse::Added(id, handle) => queued.push((id, handle)),
+                _ => panic!("expected {name:?} to be added"),
+            }
+        }
+        let (b, c) = (&queued[0], &queued[1]);
+        assert_eq!(session.queue(), vec![b.0, c.0]);
+
+        // The slot "a" frees is kept for it in solo mode.
+        session.solo(a_id).unwrap();
+        a.pause().unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(session.queue(), vec![b.0, c.0]);
+
+        // Once solo mode ends, the next one starts without waiting for a poll.
+        session.clear_solo().unwrap();
+        for _ in 0..100 {
+            if b.1.live().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(b.1.live().is_some());
+        assert_eq!(session.queue(), vec![c.0]);
+
+        // Taken out of the queue, it stays paused when a slot frees up.
+        assert!(session.dequeue(c.0).unwrap());
+        assert!(!session.dequeue(c.0).unwrap());
+        assert!(session.dequeue(1000).is_err());
+        assert!(session.queue().is_empty());
+        b.1.pause().unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(c.1.with_state(|s| s.is_paused()));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_torrents_by_path() {
+        let root = test_dir("torrents-by-path");
+        let session = Session::new_with_opts(
+            root.join("downloads"),
+            SessionOptions {
+                disable_dht: true,
+                persistence: false,
+                incomplete_dir: Some(root.join("incomplete")),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let data = test_data(100, 0);
+        let (a, _) = add_test_torrent(&session, "a", &[("f", &data)], Default::default()).await;
+        let (b, _) = add_test_torrent(
+            &session,
+            "b",
+            &[("f", &data)],
+            AddTorrentOptions {
+                output_folder: Some(root.join("archive").to_str().unwrap().to_owned()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let by_path = |path: PathBuf| {
+            let mut ids = session.torrents_by_path(&path);
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(by_path(root.join("downloads")), vec![a]);
+        assert_eq!(by_path(root.join("archive")), vec![b]);
+        // Both write there until they're complete.
+        assert_eq!(by_path(root.join("incomplete")), vec![a, b]);
+        assert_eq!(by_path(root.clone()), vec![a, b]);
+        // Whole components only.
+        assert!(by_path(root.join("down")).is_empty());
+        assert!(by_path(root.join("elsewhere")).is_empty());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_path_conflicts_are_rejected() {
+        let root = test_dir("path-conflicts");
+        let session = Session::new_with_opts(
+            root.join("downloads"),
+            SessionOptions {
+                disable_dht: true,
+                persistence: false,
+                incomplete_dir: Some(root.join("incomplete")),
+                on_path_conflict: PathConflictPolicy::Reject,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let (session, dir) = (&session, &root);
+        let add = move |name: &str, file: &str, folder: &str, preferred_id| {
+            let torrent = test_torrent(name, &[(file, &test_data(100, 0))]);
+            let opts = AddTorrentOptions {
+                output_folder: Some(dir.join(folder).to_str().unwrap().to_owned()),
+                paused: true,
+                preferred_id,
+                ..Default::default()
+            };
+            session.add_torrent(AddTorrent::from_bytes(torrent), Some(opts))
+        };
+        let is_conflict = |res: &anyhow::Result<AddTorrentResponse>| match res {
+            Err(e) => e.downcast_ref::<ApiError>().unwrap().status() == StatusCode::CONFLICT,
+            Ok(_) => false,
+        };
+
+        assert!(matches!(
+            add("a", "f", "a", None).await,
+            Ok(AddTorrentResponse::Added(..))
+        ));
+        // Only the incomplete files conflict.
+        assert!(is_conflict(&add("b", "f", "b", None).await));
+        // Not a way around the check.
+        assert!(is_conflict(&add("c", "f", "a", Some(100)).await));
+
+        // Only one of two torrents added at once gets the path.
+        let (d, e) = tokio::join!(add("d", "g", "d", None), add("e", "g", "d", None));
+        let added = [&d, &e]
+            .into_iter()
+            .filter(|res| matches!(res, Ok(AddTorrentResponse::Added(..))))
+            .count();
+        assert_eq!(added, 1);
+        assert!(is_conflict(&d) || is_conflict(&e));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_empty_incomplete_dirs() {
+        let root = test_dir("delete-incomplete-dirs");
+        let incomplete = root.join("incomplete");
+        let session = Session::new_with_opts(
+            root.join("downloads"),
+            SessionOptions {
+                disable_dht: true,
+                persistence: false,
+                incomplete_dir: Some(incomplete.clone()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Nothing is downloaded, so the files stay in incomplete_dir.
+        let data = test_data(100, 0);
+        let (id, _) = add_test_torrent(
+            &session,
+            "t",
+            &[("sub/a", &data), ("sub/deeper/b", &data)],
+            Default::default(),
+        )
+        .await;
+        let is_empty = |dir: &PathBuf| std::fs::read_dir(dir).unwrap().next().is_none();
+        assert!(!is_empty(&incomplete));
+
+        session
+            .delete_with_opts(
+                id,
+                DeleteOptions {
+                    delete_files: true,
+                    remove_empty_dirs: true,
+                },
+            )
+            .unwrap();
+        // incomplete_dir itself is kept.
+        assert!(is_empty(&incomplete));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_peer_opts_are_rejected_on_add() {
+        let dir = test_dir("add-peer-opts");
+        let session = test_session(&dir, Default::default()).await;
+        let add = |name: &str, choke_timeout| {
+            let torrent = test_torrent(name, &[("f", &test_data(100, 0))]);
+            let opts = AddTorrentOptions {
+                paused: true,
+                peer_opts: Some(PeerConnectionOptions {
+                    choke_timeout,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            session.add_torrent(AddTorrent::from_bytes(torrent), Some(opts))
+        };
+
+        assert!(add("a", Some(Duration::ZERO)).await.is_err());
+        assert!(session.with_torrents(|t| t.count()) == 0);
+        match add("b", Some(Duration::from_secs(5))).await.unwrap() {
+            AddTorrentResponse::Added(_, handle) => assert_eq!(
+                handle.peer_opts().choke_timeout,
+                Some(Duration::from_secs(5))
+            ),
+            _ => panic!("expected the torrent to be added"),
+        }
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_torrents() {
+        let dir = test_dir("max-torrents");
+        let opts = |max_torrents| SessionOptions {
+            persistence: true,
+            max_torrents: Some(max_torrents),
+            ..Default::default()
+        };
+        let data = test_data(100, 0);
+        let add = |session: Arc<Session>, name: &'static str| {
+            let torrent = test_torrent(name, &[("f", &data)]);
+            async move {
+                let opts = AddTorrentOptions {
+                    paused: true,
+                    ..Default::default()
+                };
+                session
+                    .add_torrent(AddTorrent::from_bytes(torrent), Some(opts))
+                    .await
+            }
+        };
+
+        let session = test_session(&dir, opts(2)).await;
+        let a = match add(session.clone(), "a").await.unwrap() {
+            AddTorrentResponse::Added(id, _) => id,
+            _ => panic!("expected the torrent to be added"),
+        };
+        add(session.clone(), "b").await.unwrap();
+        let err = add(session.clone(), "c").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>().unwrap().status(),
+            StatusCode::INSUFFICIENT_STORAGE
+        );
+        // Torrents already there are still found.
+        assert!(matches!(
+            add(session.clone(), "a").await.unwrap(),
+            AddTorrentResponse::AlreadyManaged(id, _) if id == a
+        ));
+        session.delete(a, false).unwrap();
+        add(session.clone(), "c").await.unwrap();
+
+        // Lowering the limit doesn't drop torrents from the session file.
+        session.dump_to_disk().unwrap();
+        session.stop().await;
+        let session = test_session(&dir, opts(1)).await;
+        for _ in 0..1000 {
+            if session.with_torrents(|t| t.count()) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(session.with_torrents(|t| t.count()), 2);
+        assert!(add(session.clone(), "d").await.is_err());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_adds() {
+        let dir = test_dir("max-concurrent-adds");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                max_concurrent_adds: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        let add = |name: &str| {
+            let session = session.clone();
+            let torrent = test_torrent(name, &[("f", &test_data(100, 0))]);
+            tokio::spawn(async move {
+                let opts = AddTorrentOptions {
+                    paused: true,
+                    ..Default::default()
+                };
+                session
+                    .add_torrent(AddTorrent::from_bytes(torrent), Some(opts))
+                    .await
+                    .map(|_| ())
+            })
+        };
+        let wait_for_queued = |n: usize| {
+            let session = session.clone();
+            async move {
+                for _ in 0..500 {
+                    if session.stats().queued_adds == n {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                assert_eq!(session.stats().queued_adds, n);
+            }
+        };
+
+        // Take the only slot, as if an add was in progress.
+        let permit = session
+            .add_semaphore
+            .as_ref()
+            .unwrap()
+            .acquire()
+            .await
+            .unwrap();
+        let a = add("a");
+        let b = add("b");
+        wait_for_queued(2).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!a.is_finished() && !b.is_finished());
+        assert_eq!(session.with_torrents(|t| t.count()), 0);
+
+        // Giving up while waiting leaves the queue.
+        b.abort();
+        wait_for_queued(1).await;
+
+        drop(permit);
+        a.await.unwrap().unwrap();
+        assert_eq!(session.stats().queued_adds, 0);
+        assert_eq!(session.with_torrents(|t| t.count()), 1);
+        add("c").await.unwrap().unwrap();
+        assert_eq!(session.with_torrents(|t| t.count()), 2);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_persist_torrents_are_not_restored() {
+        let dir = test_dir("no-persist");
+        let opts = || SessionOptions {
+            persistence: true,
+            ..Default::default()
+        };
+        let data = test_data(100, 0);
+        let session = test_session(&dir, opts()).await;
+        let (_, kept) = add_test_torrent(
+            &session,
+            "kept",
+            &[("f", &data)],
+            AddTorrentOptions {
+                paused: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        let (temporary, _) = add_test_torrent(
+            &session,
+            "temporary",
+            &[("f", &data)],
+            AddTorrentOptions {
+                paused: true,
+                no_persist: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        session.dump_to_disk().unwrap();
+        // Removing it doesn't take anything else out of the session file.
+        session.delete(temporary, false).unwrap();
+        session.dump_to_disk().unwrap();
+        session.stop().await;
+
+        let session = test_session(&dir, opts()).await;
+        for _ in 0..1000 {
+            if session.with_torrents(|t| t.count()) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let restored: Vec<Id20> =
+            session.with_torrents(|t| t.map(|(_, t)| t.info_hash()).collect());
+        assert_eq!(restored, vec![kept.info_hash()]);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_progress_thresholds_survive_restart() {
+        let dir = test_dir("progress-thresholds");
+        let opts = || SessionOptions {
+            persistence: true,
+            ..Default::default()
+        };
+        let data = test_data(100, 0);
+        let session = test_session(&dir, opts()).await;
+        let (id, _) = add_test_torrent(
+            &session,
+            "t",
+            &[("a", &data), ("b", &data)],
+            AddTorrentOptions {
+                paused: true,
+                pause_at_percent: Some(40.),
+                sequential_after_percent: Some(80.),
+                sample_files: Some(vec![1]),
+                ..Default::default()
+            },
+        )
+        .await;
+        session.dump_to_disk().unwrap();
+        session.stop().await;
+
+        let session = test_session(&dir, opts()).await;
+        let mut restored = None;
+        for _ in 0..1000 {
+            restored = session.get(id);
+            if restored.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let restored = restored.expect("torrent wasn't restored");
+        assert_eq!(restored.pause_at_percent(), Some(40.));
+        assert_eq!(restored.sequential_after_percent(), Some(80.));
+        assert_eq!(restored.sample_files(), Some(vec![1]));
+        assert_eq!(restored.only_files(), Some(vec![1]));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolving_magnet_guard_keeps_newer_entry() {
+        let magnets = DashMap::new();
+        let info_hash = Id20::new([1; 20]);
+
+        let first = ResolvingMagnetGuard::new(&magnets, info_hash);
+        let second = ResolvingMagnetGuard::new(&magnets, info_hash);
+        second.counters.peers_seen.store(3, Ordering::Relaxed);
+        drop(first);
+        // The same magnet added again is still being resolved.
+        let entry = magnets
+            .get(&info_hash)
+            .map(|c| c.peers_seen.load(Ordering::Relaxed));
+        assert_eq!(entry, Some(3));
+        drop(second);
+        assert!(magnets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_relocate_persistence() {
+        let dir = test_dir("relocate-persistence");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                persistence: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let target = dir.join("moved/session.json");
+        session.relocate_persistence(target.clone()).unwrap();
+        assert_eq!(session.persistence_filename(), target);
+        let state: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&target).unwrap()).unwrap();
+        assert!(state.is_object());
+
+        // Nothing that's already there is overwritten.
+        let other = dir.join("other.txt");
+        std::fs::write(&other, b"keep").unwrap();
+        assert!(session.relocate_persistence(other.clone()).is_err());
+        assert!(session.relocate_persistence(target.clone()).is_err());
+        assert_eq!(std::fs::read(&other).unwrap(), b"keep");
+        assert!(session
+            .relocate_persistence(PathBuf::from("relative.json"))
+            .is_err());
+        assert!(session
+            .relocate_persistence(dir.join("moved/../up.json"))
+            .is_err());
+        assert_eq!(session.persistence_filename(), target);
+
+        // Dumps racing with relocations always go to a whole file, the current one.
+        let dumper = {
+            let session = session.clone();
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    session.dump_to_disk().unwrap();
+                }
+            })
+        };
+        for i in 0..20 {
+            session
+                .relocate_persistence(dir.join(format!("state-{i}.json")))
+                .unwrap();
+        }
+        dumper.join().unwrap();
+        let last = dir.join("state-19.json");
+        assert_eq!(session.persistence_filename(), last);
+        assert!(
+            serde_json::from_slice::<serde_json::Value>(&std::fs::read(&last).unwrap()).is_ok()
+        );
+        let leftovers = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,60 @@
+// Noticing that the host was asleep, see SessionOptions::wake_recovery_gap. Timers are
+// monotonic and don't count the time spent suspended, so a timer firing late doesn't tell
+// anything. The wall clock does keep going, so it jumps ahead between two timer ticks.
+
+use std::time::{Duration, SystemTime};
+
+// How often the wall clock is checked.
+pub(crate) const SLEEP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) struct SleepDetector {
+    last: SystemTime,
+    expected: Duration,
+    gap: Duration,
+}
+
+impl SleepDetector {
+    pub fn new(now: SystemTime, expected: Duration, gap: Duration) -> Self {
+        Self {
+            last: now,
+            expected,
+            gap,
+        }
+    }
+
+    // Call once per tick. Returns how long the host was probably asleep since the last
+    // tick, if it was for longer than the gap.
+    pub fn tick(&mut self, now: SystemTime) -> Option<Duration> {
+        let last = std::mem::replace(&mut self.last, now);
+        // The clock going back, e.g. NTP fixing it, isn't sleep.
+        let elapsed = now.duration_since(last).ok()?;
+        let slept = elapsed.saturating_sub(self.expected);
+        if slept > self.gap {
+            Some(slept)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::SleepDetector;
+
+    #[test]
+    fn test_sleep_detector() {
+        let secs = Duration::from_secs;
+        let start = SystemTime::UNIX_EPOCH + secs(1000);
+        let mut d = SleepDetector::new(start, secs(5), secs(30));
+
+        assert_eq!(d.tick(start + secs(5)), None);
+        // Late, but within the gap.
+        assert_eq!(d.tick(start + secs(30)), None);
+        assert_eq!(d.tick(start + secs(635)), Some(secs(600)));
+        // The clock was set back.
+        assert_eq!(d.tick(start), None);
+        assert_eq!(d.tick(start + secs(5)), None);
+    }
+}
@@ -0,0 +1,291 @@
+// Sending torrent stats as changes instead of full snapshots, see
+// crate::api::Api::api_stats_delta. The first delta has the stats of every torrent, later ones
+// only what changed since the previous one. The changes are computed from the typed stats, so
+// only new torrents and changed fields are serialized. For a torrent that was already sent,
+// only the fields whose value changed are listed, with their new value. "live" is diffed the
+// same way while the torrent stays live, every other value, arrays and "live.snapshot"
+// included, replaces the old one. Stats always have every field, so null is a value, e.g.
+// "error" was cleared or "live" is gone because the torrent paused, not a removal.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    session::TorrentId,
+    torrent_state::stats::{LiveStats, TorrentStats},
+};
+
+/// What changed in the torrents' stats, see [crate::api::Api::api_stats_delta].
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct StatsDelta {
+    /// Set on the first delta, and after [StatsDeltaTracker::reset]. Then "torrents" has the
+    /// full stats of every torrent, and anything the client had should be dropped.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub snapshot: bool,
+    /// Full stats for new torrents, only the fields that changed since the previous delta
+    /// otherwise. Torrents that didn't change aren't listed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub torrents: BTreeMap<TorrentId, Value>,
+    /// Torrents gone since the previous delta.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<TorrentId>,
+}
+
+impl StatsDelta {
+    pub fn is_empty(&self) -> bool {
+        !self.snapshot && self.torrents.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The stats last sent to a client, to compute the next [StatsDelta] from. One per client.
+#[derive(Debug, Default)]
+pub struct StatsDeltaTracker {
+    last: Option<HashMap<TorrentId, TorrentStats>>,
+}
+
+impl StatsDeltaTracker {
+    /// Make the next delta a full snapshot, e.g. when the client lost its state.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    pub(crate) fn delta(
+        &mut self,
+        current: HashMap<TorrentId, TorrentStats>,
+    ) -> anyhow::Result<StatsDelta> {
+        let mut delta = StatsDelta::default();
+        match &self.last {
+            None => {
+                delta.snapshot = true;
+                for (id, stats) in current.iter() {
+                    delta.torrents.insert(*id, serde_json::to_value(stats)?);
+                }
+            }
+            Some(last) => {
+                for (id, stats) in current.iter() {
+                    let patch = match last.get(id) {
+                        Some(old) => torrent_stats_patch(old, stats)?.map(Value::Object),
+                        None => Some(serde_json::to_value(stats)?),
+                    };
+                    if let Some(patch) = patch {
+                        delta.torrents.insert(*id, patch);
+                    }
+                }
+                delta.removed = last
+                    .keys()
+                    .filter(|id| !current.contains_key(id))
+                    .copied()
+                    .collect();
+                delta.removed.sort_unstable();
+            }
+        }
+        self.last = Some(current);
+        Ok(delta)
+    }
+}
+
+// Inserts the fields that differ between "old" and "new" into "patch", with their new value.
+// The fields are the bindings of destructuring "new", so that a field added to the struct
+// can't be forgotten here.
+macro_rules! insert_changed {
+    ($patch:ident, $old:ident, $($field:ident),+ $(,)?) => {
+        $(
+            if &$old.$field != $field {
+                $patch.insert(stringify!($field).to_owned(), serde_json::to_value($field)?);
+            }
+        )+
+    };
+}
+
+// The fields of "new" that changed since "old", None if none did.
+fn torrent_stats_patch(
+    old: &TorrentStats,
+    new: &TorrentStats,
+) -> serde_json::Result<Option<Map<String, Value>>> {
+    let TorrentStats {
+        state,
+        error,
+        progress_bytes,
+        total_bytes,
+        finished,
+        lifetime,
+        stalled,
+        unavailable_pieces,
+        locked,
+        boost_remaining_ms,
+        lazy,
+        priority_tier,
+        sequential,
+        sequential_after_percent,
+        live,
+    } = new;
+    let mut patch = Map::new();
+    insert_changed!(
+        patch,
+        old,
+        state,
+        error,
+        progress_bytes,
+        total_bytes,
+        finished,
+        lifetime,
+        stalled,
+        unavailable_pieces,
+        locked,
+        boost_remaining_ms,
+        lazy,
+        priority_tier,
+        sequential,
+        sequential_after_percent,
+    );
+    match (&old.live, live) {
+        (Some(old_live), Some(new_live)) => {
+            if let Some(live_patch) = live_stats_patch(old_live, new_live)? {
+                patch.insert("live".to_owned(), Value::Object(live_patch));
+            }
+        }
+        (old_live, new_live) => {
+            if old_live != new_live {
+                patch.insert("live".to_owned(), serde_json::to_value(new_live)?);
+            }
+        }
+    }
+    Ok(if patch.is_empty() { None } else { Some(patch) })
+}
+
+fn live_stats_patch(
+    old: &LiveStats,
+    new: &LiveStats,
+) -> serde_json::Result<Option<Map<String, Value>>> {
+    let LiveStats {
+        snapshot,
+        average_piece_download_time,
+        download_speed,
+        upload_speed,
+        disk_read_speed,
+        disk_write_speed,
+        time_remaining,
+        upload_slots_used,
+        upload_slots,
+        peer_opts,
+        peer_limit,
+    } = new;
+    let mut patch = Map::new();
+    insert_changed!(
+        patch,
+        old,
+        snapshot,
+        average_piece_download_time,
+        download_speed,
+        upload_speed,
+        disk_read_speed,
+        disk_write_speed,
+        time_remaining,
+        upload_slots_used,
+        upload_slots,
+        peer_opts,
+        peer_limit,
+    );
+    Ok(if patch.is_empty() { None } else { Some(patch) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use crate::torrent_state::stats::{LiveStats, TorrentStats};
+
+    use super::{torrent_stats_patch, StatsDeltaTracker};
+
+    fn stats(progress_bytes: u64) -> TorrentStats {
+        TorrentStats {
+            state: "live",
+            progress_bytes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_torrent_stats_patch() {
+        let old = TorrentStats {
+            error: Some("oops".to_owned()),
+            live: Some(LiveStats {
+                upload_slots_used: 1,
+                ..Default::default()
+            }),
+            ..stats(10)
+        };
+        assert_eq!(torrent_stats_patch(&old, &old).unwrap(), None);
+
+        let new = TorrentStats {
+            live: Some(LiveStats {
+                upload_slots_used: 2,
+                ..Default::default()
+            }),
+            ..stats(20)
+        };
+        // A field going to null is sent as null.
+        assert_eq!(
+            torrent_stats_patch(&old, &new).unwrap().map(Value::Object),
+            Some(json!({
+                "error": null,
+                "progress_bytes": 20,
+                "live": {"upload_slots_used": 2},
+            }))
+        );
+
+        // Paused: the live stats are gone.
+        let paused = TorrentStats {
+            state: "paused",
+            ..stats(20)
+        };
+        assert_eq!(
+            torrent_stats_patch(&new, &paused)
+                .unwrap()
+                .map(Value::Object),
+            Some(json!({"state": "paused", "live": null}))
+        );
+        let resumed = torrent_stats_patch(&paused, &new).unwrap().unwrap();
+        assert_eq!(resumed["live"], serde_json::to_value(&new.live).unwrap());
+    }
+
+    #[test]
+    fn test_stats_delta_tracker() {
+        let mut tracker = StatsDeltaTracker::default();
+        let all = |items: &[(usize, u64)]| -> HashMap<_, _> {
+            items.iter().map(|(id, p)| (*id, stats(*p))).collect()
+        };
+
+        let first = tracker.delta(all(&[(0, 1), (1, 2)])).unwrap();
+        assert!(first.snapshot);
+        assert_eq!(first.torrents.len(), 2);
+        assert_eq!(
+            first.torrents.get(&0),
+            Some(&serde_json::to_value(stats(1)).unwrap())
+        );
+
+        let unchanged = tracker.delta(all(&[(0, 1), (1, 2)])).unwrap();
+        assert!(unchanged.is_empty());
+
+        let delta = tracker.delta(all(&[(0, 5), (2, 3)])).unwrap();
+        assert!(!delta.snapshot);
+        assert_eq!(delta.torrents.get(&0), Some(&json!({"progress_bytes": 5})));
+        assert_eq!(
+            delta.torrents.get(&2),
+            Some(&serde_json::to_value(stats(3)).unwrap())
+        );
+        assert_eq!(delta.removed, vec![1]);
+
+        tracker.reset();
+        let snapshot = tracker.delta(all(&[(0, 5)])).unwrap();
+        assert!(snapshot.snapshot);
+        assert_eq!(
+            snapshot.torrents.get(&0),
+            Some(&serde_json::to_value(stats(5)).unwrap())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+// Disk space at the places torrents are stored. Paths on the same filesystem are grouped
+// into one storage root, so that a disk shows up once however many folders are used on it.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::session::TorrentId;
+
+/// A filesystem holding torrent data, see [crate::Session::storage_stats].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageRootStats {
+    /// The deepest folder that all the paths stored on this filesystem have in common.
+    pub path: PathBuf,
+    pub total_bytes: Option<u64>,
+    /// Bytes available for new data, not counting space reserved for the superuser.
+    pub free_bytes: Option<u64>,
+    pub used_bytes: Option<u64>,
+    /// Why the sizes above are missing, e.g. the path is inaccessible.
+    pub error: Option<String>,
+    /// Torrents with their output folder on this filesystem.
+    pub torrents: Vec<TorrentId>,
+}
+
+struct Space {
+    total: u64,
+    free: u64,
+    used: u64,
+}
+
+#[cfg(unix)]
+fn filesystem_space(path: &Path) -> anyhow::Result<Space> {
+    let s = rustix::fs::statvfs(path)?;
+    let total = s.f_blocks * s.f_frsize;
+    Ok(Space {
+        total,
+        free: s.f_bavail * s.f_frsize,
+        used: total.saturating_sub(s.f_bfree * s.f_frsize),
+    })
+}
+
+#[cfg(not(unix))]
+fn filesystem_space(_path: &Path) -> anyhow::Result<Space> {
+    anyhow::bail!("querying disk space is not supported on this platform")
+}
+
+#[cfg(unix)]
+fn filesystem_id(path: &Path) -> anyhow::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(std::fs::metadata(path)?.dev())
+}
+
+#[cfg(not(unix))]
+fn filesystem_id(path: &Path) -> anyhow::Result<u64> {
+    std::fs::metadata(path)?;
+    anyhow::bail!("can't tell filesystems apart on this platform")
+}
+
+// Folders that aren't created yet take their space from the nearest one that is.
+fn nearest_existing(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect()
+}
+
+#[derive(PartialEq, Eq)]
+enum RootKey {
+    Filesystem(u64),
+    // When the filesystem can't be told, every path is its own root.
+    Path(PathBuf),
+}
+
+/// Group the paths by filesystem and query the space on each. Paths with no torrent are
+/// included too, e.g. the default output folder.
+pub(crate) fn storage_stats(
+    paths: impl IntoIterator<Item = (Option<TorrentId>, PathBuf)>,
+) -> Vec<StorageRootStats> {
+    let mut roots: Vec<(RootKey, StorageRootStats)> = Vec::new();
+    for (id, path) in paths {
+        let existing = nearest_existing(&path);
+        let key = match existing.map(filesystem_id) {
+            Some(Ok(fs)) => RootKey::Filesystem(fs),
+            _ => RootKey::Path(path.clone()),
+        };
+        let idx = match roots.iter().position(|(k, _)| *k == key) {
+            Some(idx) => {
+                let root = &mut roots[idx].1;
+                root.path = common_ancestor(&root.path, &path);
+                idx
+            }
+            None => {
+                let (space, error) = match existing.map(filesystem_space) {
+                    Some(Ok(space)) => (Some(space), None),
+                    Some(Err(e)) => (None, Some(format!("{e:#}"))),
+                    None => (None, Some(format!("{path:?} is inaccessible"))),
+                };
+                roots.push((
+                    key,
+                    StorageRootStats {
+                        path: path.clone(),
+                        total_bytes: space.as_ref().map(|s| s.total),
+                        free_bytes: space.as_ref().map(|s| s.free),
+                        used_bytes: space.as_ref().map(|s| s.used),
+                        error,
+                        torrents: Vec::new(),
+                    },
+                ));
+                roots.len() - 1
+            }
+        };
+        if let Some(id) = id {
+            roots[idx].1.torrents.push(id);
+        }
+    }
+    roots.into_iter().map(|(_, root)| root).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{common_ancestor, storage_stats};
+
+    #[test]
+    fn test_common_ancestor() {
+        assert_eq!(
+            common_ancestor(Path::new("/data/movies/a"), Path::new("/data/music")),
+            PathBuf::from("/data")
+        );
+        assert_eq!(
+            common_ancestor(Path::new("/data"), Path::new("/data/music")),
+            PathBuf::from("/data")
+        );
+        assert_eq!(
+            common_ancestor(Path::new("/data"), Path::new("/other")),
+            PathBuf::from("/")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_storage_stats_groups_by_filesystem() {
+        let dir = std::env::temp_dir().join(format!("librqbit-storage-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+
+        let stats = storage_stats([
+            (None, dir.clone()),
+            (Some(1), dir.join("a")),
+            // Not created yet, takes the space of "dir".
+            (Some(2), dir.join("b").join("c")),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].path, dir);
+        assert_eq!(stats[0].torrents, vec![1, 2]);
+        assert!(stats[0].error.is_none());
+        assert!(stats[0].total_bytes.unwrap() >= stats[0].free_bytes.unwrap());
+    }
+}
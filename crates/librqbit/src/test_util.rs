@@ -0,0 +1,285 @@
+// Helpers shared by the tests of several modules.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use buffers::ByteString;
+use clone_to_owned::CloneToOwned;
+use librqbit_core::{hash_id::Id20, torrent_metainfo::TorrentMetaV1Info};
+use peer_binary_protocol::{
+    Handshake, Message, MessageBorrowed, MessageDeserializeError, MessageOwned,
+};
+use sha1w::{ISha1, Sha1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    session::{
+        AddTorrent, AddTorrentOptions, AddTorrentResponse, Session, SessionOptions, TorrentId,
+    },
+    torrent_state::{ManagedTorrentHandle, ManagedTorrentState},
+};
+
+pub const TEST_PIECE_LENGTH: u32 = 16384;
+
+// An empty directory that nothing else uses.
+pub fn test_dir(prefix: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("librqbit-{prefix}-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Deterministic file contents, different for each "seed".
+pub fn test_data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| ((i * 31 + i / 251) as u8).wrapping_add(seed))
+        .collect()
+}
+
+fn bencode_bytes(out: &mut Vec<u8>, b: &[u8]) {
+    out.extend_from_slice(format!("{}:", b.len()).as_bytes());
+    out.extend_from_slice(b);
+}
+
+// A multi-file .torrent of the files, by "/" separated path, without trackers.
+pub fn test_torrent(name: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+    test_torrent_with_announce(name, files, None)
+}
+
+// Like test_torrent(), announcing to "announce" if set.
+pub fn test_torrent_with_announce(
+    name: &str,
+    files: &[(&str, &[u8])],
+    announce: Option<&str>,
+) -> Vec<u8> {
+    let data: Vec<u8> = files.iter().flat_map(|(_, d)| d.iter().copied()).collect();
+    let mut pieces = Vec::new();
+    for piece in data.chunks(TEST_PIECE_LENGTH as usize) {
+        let mut hash = Sha1::new();
+        hash.update(piece);
+        pieces.extend_from_slice(&hash.finish());
+    }
+
+    let mut info = b"d5:filesl".to_vec();
+    for (path, d) in files {
+        info.extend_from_slice(format!("d6:lengthi{}e4:pathl", d.len()).as_bytes());
+        for component in path.split('/') {
+            bencode_bytes(&mut info, component.as_bytes());
+        }
+        info.extend_from_slice(b"ee");
+    }
+    info.extend_from_slice(b"e4:name");
+    bencode_bytes(&mut info, name.as_bytes());
+    info.extend_from_slice(format!("12:piece lengthi{TEST_PIECE_LENGTH}e6:pieces").as_bytes());
+    bencode_bytes(&mut info, &pieces);
+    info.push(b'e');
+
+    let mut torrent = b"d".to_vec();
+    if let Some(announce) = announce {
+        bencode_bytes(&mut torrent, b"announce");
+        bencode_bytes(&mut torrent, announce.as_bytes());
+    }
+    torrent.extend_from_slice(b"4:info");
+    torrent.extend_from_slice(&info);
+    torrent.push(b'e');
+    torrent
+}
+
+pub fn test_torrent_info(files: &[(&str, &[u8])]) -> TorrentMetaV1Info<ByteString> {
+    let torrent = test_torrent("test", files);
+    librqbit_core::torrent_metainfo::torrent_from_bytes::<ByteString>(&torrent)
+        .unwrap()
+        .info
+}
+
+// A session downloading to "dir" without DHT, listening, UPnP, or persistence unless "opts"
+// turn it on. The persistence file goes to "dir" too.
+pub async fn test_session(dir: &Path, mut opts: SessionOptions) -> Arc<Session> {
+    opts.disable_dht = true;
+    opts.disable_dht_persistence = true;
+    if opts.persistence_filename.is_none() {
+        opts.persistence_filename = Some(dir.join("session.json"));
+    }
+    Session::new_with_opts(dir.to_owned(), opts).await.unwrap()
+}
+
+// Adds test_torrent(name, files), waiting until it's live unless "opts" pause it. Its files go
+// to the session's output folder joined with "name".
+pub async fn add_test_torrent(
+    session: &Session,
+    name: &str,
+    files: &[(&str, &[u8])],
+    opts: AddTorrentOptions,
+) -> (TorrentId, ManagedTorrentHandle) {
+    let paused = opts.paused;
+    let (id, handle) = match session
+        .add_torrent(
+            AddTorrent::from_bytes(test_torrent(name, files)),
+            Some(opts),
+        )
+        .await
+        .unwrap()
+    {
+        AddTorrentResponse::Added(id, handle) => (id, handle),
+        _ => panic!("expected {name:?} to be added"),
+    };
+    if !paused {
+        wait_until_live(&handle).await;
+    }
+    (id, handle)
+}
+
+// Waits for the torrent to finish initializing. Panics if it fails, or takes over 10 seconds.
+pub async fn wait_until_live(handle: &ManagedTorrentHandle) {
+    for _ in 0..1000 {
+        if handle.live().is_some() {
+            return;
+        }
+        if let Some(e) = handle.with_state(|s| match s {
+            ManagedTorrentState::Error(e) => Some(format!("{e:#}")),
+            _ => None,
+        }) {
+            panic!("torrent failed: {e}");
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("torrent didn't become live");
+}
+
+// An HTTP server answering each request with "respond(request)", where "request" is the
+// request line and headers. The response is sent as is, then the connection is closed.
+pub async fn serve_http(respond: impl Fn(&str) -> Vec<u8> + Send + Sync + 'static) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let respond = Arc::new(respond);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = respond(&String::from_utf8_lossy(&request));
+                // The client may hang up early, e.g. on a body that's too large.
+                let _ = stream.write_all(&response).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+// The path of a request passed to serve_http(), without the query.
+pub fn request_path(request: &str) -> &str {
+    let target = request.split(' ').nth(1).unwrap_or_default();
+    target.split('?').next().unwrap_or_default()
+}
+
+// The value of a request header passed to serve_http(), by lowercase name.
+pub fn request_header(request: &str, name: &str) -> Option<String> {
+    request.lines().find_map(|l| {
+        let (n, v) = l.split_once(':')?;
+        (n.trim().to_ascii_lowercase() == name).then(|| v.trim().to_owned())
+    })
+}
+
+// A complete HTTP response for serve_http(), with a Content-Length.
+pub fn http_response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\n", body.len());
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("Connection: close\r\n\r\n");
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+// A peer speaking the wire protocol by hand to a session's listener, with the fast
+// extension.
+pub struct RawPeer {
+    pub stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl RawPeer {
+    pub async fn connect(port: u16, info_hash: Id20) -> Self {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut handshake = Vec::new();
+        Handshake::new(info_hash, Id20::new([7; 20])).serialize(&mut handshake);
+        stream.write_all(&handshake).await.unwrap();
+        let mut peer = Self {
+            stream,
+            buf: Vec::new(),
+        };
+        loop {
+            let len = match Handshake::deserialize(&peer.buf) {
+                Ok((h, len)) => {
+                    assert!(h.supports_fast());
+                    Some(len)
+                }
+                Err(_) => None,
+            };
+            match len {
+                Some(len) => {
+                    peer.buf.drain(..len);
+                    return peer;
+                }
+                None => peer.fill().await,
+            }
+        }
+    }
+
+    async fn fill(&mut self) {
+        let mut buf = [0u8; 4096];
+        let n = tokio::time::timeout(Duration::from_secs(5), self.stream.read(&mut buf))
+            .await
+            .expect("timeout reading from the torrent")
+            .unwrap();
+        assert!(n > 0, "the torrent disconnected");
+        self.buf.extend_from_slice(&buf[..n]);
+    }
+
+    // The next message, skipping keep alives and extended messages.
+    pub async fn read(&mut self) -> MessageOwned {
+        loop {
+            let parsed = match MessageBorrowed::deserialize(&self.buf) {
+                Ok((msg, len)) => Some((msg.clone_to_owned(), len)),
+                Err(MessageDeserializeError::NotEnoughData(..)) => None,
+                Err(e) => panic!("error reading message: {e}"),
+            };
+            let (msg, len) = match parsed {
+                Some(parsed) => parsed,
+                None => {
+                    self.fill().await;
+                    continue;
+                }
+            };
+            self.buf.drain(..len);
+            if !matches!(msg, Message::KeepAlive | Message::Extended(_)) {
+                return msg;
+            }
+        }
+    }
+
+    pub async fn send(&mut self, msg: MessageOwned) {
+        let mut buf = Vec::new();
+        let len = msg.serialize(&mut buf, &|| None).unwrap();
+        self.stream.write_all(&buf[..len]).await.unwrap();
+    }
+}
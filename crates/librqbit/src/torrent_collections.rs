@@ -0,0 +1,149 @@
+// Named groups of torrents, e.g. one per project, to pause, start or delete together and to
+// see their stats added up, see Session::create_collection. A torrent can be in several
+// collections, and leaves them all when it's removed from the session. Membership is
+// persisted with the session.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    session::TorrentId,
+    torrent_state::stats::{Speed, TorrentStats},
+};
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub(crate) struct TorrentCollections(BTreeMap<String, Vec<TorrentId>>);
+
+impl TorrentCollections {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn all(&self) -> BTreeMap<String, Vec<TorrentId>> {
+        self.0.clone()
+    }
+
+    pub fn members(&self, name: &str) -> anyhow::Result<Vec<TorrentId>> {
+        self.0
+            .get(name)
+            .cloned()
+            .with_context(|| format!("no collection named {name:?}"))
+    }
+
+    pub fn create(&mut self, name: &str) -> anyhow::Result<()> {
+        if name.is_empty() {
+            bail!("collection name can't be empty");
+        }
+        if self.0.contains_key(name) {
+            bail!("collection {:?} already exists", name);
+        }
+        self.0.insert(name.to_owned(), Vec::new());
+        Ok(())
+    }
+
+    // Returns the torrents it had, which stay in the session.
+    pub fn delete(&mut self, name: &str) -> anyhow::Result<Vec<TorrentId>> {
+        self.0
+            .remove(name)
+            .with_context(|| format!("no collection named {name:?}"))
+    }
+
+    // Adding a torrent that's in the collection already does nothing.
+    pub fn add(&mut self, name: &str, id: TorrentId) -> anyhow::Result<()> {
+        let members = self
+            .0
+            .get_mut(name)
+            .with_context(|| format!("no collection named {name:?}"))?;
+        if !members.contains(&id) {
+            members.push(id);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str, id: TorrentId) -> anyhow::Result<()> {
+        self.0
+            .get_mut(name)
+            .with_context(|| format!("no collection named {name:?}"))?
+            .retain(|m| *m != id);
+        Ok(())
+    }
+
+    // Drops torrents from every collection, e.g. once they're removed from the session.
+    pub fn retain_torrents(&mut self, keep: impl Fn(TorrentId) -> bool) {
+        for members in self.0.values_mut() {
+            members.retain(|id| keep(*id));
+        }
+    }
+}
+
+/// The stats of a collection's torrents added up, see
+/// [crate::api::Api::api_collection_stats].
+#[derive(Serialize, Default, Debug)]
+pub struct CollectionStats {
+    pub torrents: usize,
+    pub live: usize,
+    pub finished: usize,
+    /// Torrents in error.
+    pub errors: usize,
+    pub progress_bytes: u64,
+    pub total_bytes: u64,
+    /// Totals since the torrents were added.
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub download_speed: Speed,
+    pub upload_speed: Speed,
+}
+
+impl CollectionStats {
+    pub(crate) fn add(&mut self, stats: &TorrentStats) {
+        self.torrents += 1;
+        if stats.finished {
+            self.finished += 1;
+        }
+        if stats.error.is_some() {
+            self.errors += 1;
+        }
+        self.progress_bytes += stats.progress_bytes;
+        self.total_bytes += stats.total_bytes;
+        self.uploaded_bytes += stats.lifetime.uploaded_bytes;
+        self.downloaded_bytes += stats.lifetime.downloaded_bytes;
+        if let Some(live) = stats.live.as_ref() {
+            self.live += 1;
+            self.download_speed = Speed::from(self.download_speed.mbps + live.download_speed.mbps);
+            self.upload_speed = Speed::from(self.upload_speed.mbps + live.upload_speed.mbps);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TorrentCollections;
+
+    #[test]
+    fn test_torrent_collections() {
+        let mut c = TorrentCollections::default();
+        c.create("project").unwrap();
+        assert!(c.create("project").is_err());
+        assert!(c.create("").is_err());
+        assert!(c.add("other", 1).is_err());
+
+        c.add("project", 1).unwrap();
+        c.add("project", 2).unwrap();
+        c.add("project", 1).unwrap();
+        assert_eq!(c.members("project").unwrap(), vec![1, 2]);
+
+        c.create("archive").unwrap();
+        c.add("archive", 2).unwrap();
+        // Torrent 2 was removed from the session.
+        c.retain_torrents(|id| id != 2);
+        assert_eq!(c.members("project").unwrap(), vec![1]);
+        assert_eq!(c.members("archive").unwrap(), Vec::<usize>::new());
+
+        c.remove("project", 1).unwrap();
+        assert_eq!(c.delete("project").unwrap(), Vec::<usize>::new());
+        assert!(c.members("project").is_err());
+    }
+}
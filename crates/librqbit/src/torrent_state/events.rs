@@ -0,0 +1,33 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use super::progress_thresholds::ProgressAction;
+
+/// A piece that failed hash verification after being downloaded from several distinct peers.
+/// This usually means someone is poisoning the swarm, or the torrent itself is broken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoisonedPiece {
+    pub piece: u32,
+    /// The peers that sent the last chunk of each failed attempt.
+    pub peers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TorrentEvent {
+    PiecePoisoned(PoisonedPiece),
+    /// The torrent reached one of its thresholds, and did "action", see
+    /// [crate::ProgressThreshold].
+    ProgressThresholdReached {
+        percent: f64,
+        progress: f64,
+        action: ProgressAction,
+    },
+    /// The torrent still failed to initialize after all its retries, see
+    /// [crate::SessionOptions::error_retry]. It stays in error until restarted by hand.
+    RetriesExhausted {
+        attempts: u32,
+        error: String,
+    },
+}
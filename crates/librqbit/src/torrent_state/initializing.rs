@@ -1,77 +1,237 @@
 use std::{
     fs::{File, OpenOptions},
-    sync::{atomic::AtomicU64, Arc},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     time::Instant,
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 
 use parking_lot::Mutex;
 
+use serde::{Deserialize, Serialize};
 use sha1w::Sha1;
 use size_format::SizeFormatterBinary as SF;
 use tracing::{debug, info, warn};
 
-use crate::{chunk_tracker::ChunkTracker, file_ops::FileOps};
+use crate::{
+    chunk_tracker::ChunkTracker,
+    cross_seed::copy_files,
+    file_ops::{FileOps, InitialCheckResults},
+    type_aliases::BF,
+};
 
-use super::{paused::TorrentStatePaused, ManagedTorrentInfo};
+use super::{
+    live::{move_open_file, open_mirror_files},
+    paused::TorrentStatePaused,
+    utils::{check_path_within, is_file_complete},
+    ManagedTorrentInfo,
+};
+
+/// What to do when some of the files of a torrent being added already exist on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExistingDataPolicy {
+    /// Keep the existing files and check them against the piece hashes. Only the pieces
+    /// that don't match are downloaded.
+    Verify,
+    /// Truncate the existing files and download everything from scratch.
+    Overwrite,
+    /// Refuse to add the torrent if any of its files exist. Nothing is created in that case.
+    #[default]
+    Error,
+}
+
+// Run before creating any files, so that a refused torrent leaves nothing behind.
+fn check_existing_files(paths: &[PathBuf], policy: ExistingDataPolicy) -> anyhow::Result<()> {
+    if policy != ExistingDataPolicy::Error {
+        return Ok(());
+    }
+    if let Some(path) = paths.iter().find(|p| p.exists()) {
+        bail!("{path:?} already exists, set a different existing data policy to use it")
+    }
+    Ok(())
+}
+
+fn open_file(path: &Path, policy: ExistingDataPolicy) -> anyhow::Result<File> {
+    match policy {
+        ExistingDataPolicy::Verify | ExistingDataPolicy::Overwrite => OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(policy == ExistingDataPolicy::Overwrite)
+            .open(path)
+            .with_context(|| format!("error opening {path:?} in read/write mode")),
+        ExistingDataPolicy::Error => {
+            // TODO: create_new does not seem to work with read(true), so calling this twice.
+            OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(path)
+                .with_context(|| format!("error creating {path:?}"))?;
+            Ok(OpenOptions::new().read(true).write(true).open(path)?)
+        }
+    }
+}
 
 fn ensure_file_length(file: &File, length: u64) -> anyhow::Result<()> {
     Ok(file.set_len(length)?)
 }
 
+// Unlike set_len(), this doesn't leave sparse holes: the missing tail is written out with zeroes.
+fn preallocate_file(file: &mut File, length: u64) -> anyhow::Result<()> {
+    let current = file.metadata()?.len();
+    if current >= length {
+        return ensure_file_length(file, length);
+    }
+    file.seek(SeekFrom::Start(current))?;
+    let zeroes = vec![0u8; 1024 * 1024];
+    let mut remaining = length - current;
+    while remaining > 0 {
+        let len = remaining.min(zeroes.len() as u64) as usize;
+        file.write_all(&zeroes[..len])?;
+        remaining -= len as u64;
+    }
+    Ok(())
+}
+
 pub struct TorrentStateInitializing {
     pub(crate) meta: Arc<ManagedTorrentInfo>,
     pub(crate) only_files: Option<Vec<usize>>,
     pub(crate) checked_bytes: AtomicU64,
+    // Pieces to take as downloaded instead of hashing them, e.g. from a fastresume file.
+    // Only used by the first check, later ones (e.g. after an error) hash everything.
+    trusted_have_pieces: Mutex<Option<BF>>,
+    // (from, to) files of other torrents to copy before the first check, see
+    // AddTorrentOptions::cross_seed.
+    cross_seed_copies: Mutex<Vec<(PathBuf, PathBuf)>>,
+    existing_data_policy: ExistingDataPolicy,
+    cancelled: AtomicBool,
 }
 
 impl TorrentStateInitializing {
-    pub fn new(meta: Arc<ManagedTorrentInfo>, only_files: Option<Vec<usize>>) -> Self {
+    pub fn new(
+        meta: Arc<ManagedTorrentInfo>,
+        only_files: Option<Vec<usize>>,
+        trusted_have_pieces: Option<BF>,
+        cross_seed_copies: Vec<(PathBuf, PathBuf)>,
+    ) -> Self {
         Self {
-            meta,
             only_files,
             checked_bytes: AtomicU64::new(0),
+            trusted_have_pieces: Mutex::new(trusted_have_pieces),
+            cross_seed_copies: Mutex::new(cross_seed_copies),
+            existing_data_policy: meta.options.existing_data_policy,
+            cancelled: AtomicBool::new(false),
+            meta,
+        }
+    }
+
+    /// Check the data of a torrent that was already added, e.g. after an error. The files on
+    /// disk are its own by now, so they are always verified, never truncated or refused.
+    pub fn for_recheck(meta: Arc<ManagedTorrentInfo>, only_files: Option<Vec<usize>>) -> Self {
+        Self {
+            existing_data_policy: ExistingDataPolicy::Verify,
+            ..Self::new(meta, only_files, None, Vec::new())
         }
     }
 
     pub fn get_checked_bytes(&self) -> u64 {
-        self.checked_bytes
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.checked_bytes.load(Ordering::Relaxed)
+    }
+
+    // Stop hashing the data. check() still finishes, with the pieces not hashed by then
+    // taken as not downloaded, and the torrent ends up paused.
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Files still at their incomplete path that turned out complete, e.g. empty ones or the
+    // ones finished right before a restart, are moved to the final one right away.
+    fn move_complete_files(
+        &self,
+        files: &[Arc<Mutex<File>>],
+        mut filenames: Vec<PathBuf>,
+        check: &InitialCheckResults,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.meta.has_incomplete_paths() {
+            return Ok(filenames);
+        }
+        let final_filenames = self.meta.file_paths()?;
+        let mut offset = 0u64;
+        for (idx, len) in self.meta.info.iter_file_lengths()?.enumerate() {
+            let file_offset = offset;
+            offset += len;
+            if filenames[idx] == final_filenames[idx]
+                || !is_file_complete(&self.meta.lengths, &check.have_pieces, file_offset, len)
+            {
+                continue;
+            }
+            move_open_file(
+                &files[idx],
+                &filenames[idx],
+                &final_filenames[idx],
+                self.meta.file_permissions(),
+            )?;
+            debug!("{:?} is complete, moved", final_filenames[idx]);
+            filenames[idx] = final_filenames[idx].clone();
+        }
+        Ok(filenames)
     }
 
     pub async fn check(&self) -> anyhow::Result<TorrentStatePaused> {
+        let copies = std::mem::take(&mut *self.cross_seed_copies.lock());
+        if !copies.is_empty() {
+            let out_dir = &self.meta.out_dir;
+            let (files, bytes) = self.meta.spawner.spawn_block_in_place(|| {
+                copy_files(out_dir, &copies, self.meta.file_permissions())
+            });
+            info!(
+                ?out_dir,
+                "cross-seeding: copied {files} files ({bytes} bytes) from other torrents"
+            );
+        }
+
         let (files, filenames) = {
             let mut files =
                 Vec::<Arc<Mutex<File>>>::with_capacity(self.meta.info.iter_file_lengths()?.count());
-            let mut filenames = Vec::new();
-            for (path_bits, _) in self.meta.info.iter_filenames_and_lengths()? {
-                let mut full_path = self.meta.out_dir.clone();
-                let relative_path = path_bits
-                    .to_pathbuf()
-                    .context("error converting file to path")?;
-                full_path.push(relative_path);
-
-                std::fs::create_dir_all(full_path.parent().unwrap())?;
-                let file = if self.meta.options.overwrite {
-                    OpenOptions::new()
-                        .create(true)
-                        .read(true)
-                        .write(true)
-                        .open(&full_path)
-                        .with_context(|| {
-                            format!("error opening {full_path:?} in read/write mode")
-                        })?
-                } else {
-                    // TODO: create_new does not seem to work with read(true), so calling this twice.
-                    OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(&full_path)
-                        .with_context(|| format!("error creating {:?}", &full_path))?;
-                    OpenOptions::new().read(true).write(true).open(&full_path)?
+            // Files that are already there stay, the rest is created at the incomplete path,
+            // if any.
+            let filenames: Vec<PathBuf> = self
+                .meta
+                .file_paths()?
+                .into_iter()
+                .map(|path| match self.meta.incomplete_file_path(&path) {
+                    Some(incomplete) if !path.exists() => incomplete,
+                    _ => path,
+                })
+                .collect();
+
+            let policy = self.existing_data_policy;
+            check_existing_files(&filenames, policy)?;
+            for full_path in filenames.iter() {
+                let root = match &self.meta.options.incomplete_dir {
+                    Some(dir) if full_path.starts_with(dir) => dir,
+                    _ => &self.meta.out_dir,
                 };
-                filenames.push(full_path);
+                check_path_within(root, full_path)?;
+            }
+            let perms = self.meta.file_permissions();
+            for full_path in filenames.iter() {
+                perms.create_dir_all(full_path.parent().unwrap())?;
+                let existed = full_path.exists();
+                let file = open_file(full_path, policy)?;
+                if !existed {
+                    perms.apply_to_file(full_path)?;
+                }
                 files.push(Arc::new(Mutex::new(file)))
             }
             (files, filenames)
@@ -79,10 +239,23 @@ impl TorrentStateInitializing {
 
         debug!("computed lengths: {:?}", &self.meta.lengths);
 
-        info!("Doing initial checksum validation, this might take a while...");
+        let trusted_have_pieces = self.trusted_have_pieces.lock().take();
         let initial_check_results = self.meta.spawner.spawn_block_in_place(|| {
-            FileOps::<Sha1>::new(&self.meta.info, &files, &self.meta.lengths)
-                .initial_check(self.only_files.as_deref(), &self.checked_bytes)
+            let file_ops = FileOps::<Sha1>::new(&self.meta.info, &files, &self.meta.lengths);
+            match &trusted_have_pieces {
+                Some(have) => {
+                    info!("Using the fastresume data instead of the initial checksum validation");
+                    file_ops.trusted_check(self.only_files.as_deref(), have, &self.checked_bytes)
+                }
+                None => {
+                    info!("Doing initial checksum validation, this might take a while...");
+                    file_ops.initial_check(
+                        self.only_files.as_deref(),
+                        &self.checked_bytes,
+                        &self.cancelled,
+                    )
+                }
+            }
         })?;
 
         info!(
@@ -107,7 +280,12 @@ impl TorrentStateInitializing {
                     continue;
                 }
                 let now = Instant::now();
-                if let Err(err) = ensure_file_length(&file.lock(), length) {
+                let res = if self.meta.options.contiguous_storage {
+                    preallocate_file(&mut file.lock(), length)
+                } else {
+                    ensure_file_length(&file.lock(), length)
+                };
+                if let Err(err) = res {
                     warn!(
                         "Error setting length for file {:?} to {}: {:#?}",
                         name, length, err
@@ -123,6 +301,9 @@ impl TorrentStateInitializing {
             }
         });
 
+        let filenames = self.move_complete_files(&files, filenames, &initial_check_results)?;
+        let mirror_files = open_mirror_files(&self.meta)?;
+
         let chunk_tracker = ChunkTracker::new(
             initial_check_results.needed_pieces,
             initial_check_results.have_pieces,
@@ -134,6 +315,7 @@ impl TorrentStateInitializing {
             info: self.meta.clone(),
             files,
             filenames,
+            mirror_files,
             chunk_tracker,
             have_bytes: initial_check_results.have_bytes,
             needed_bytes: initial_check_results.needed_bytes,
@@ -141,3 +323,80 @@ impl TorrentStateInitializing {
         Ok(paused)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        path::PathBuf,
+    };
+
+    use super::{check_existing_files, open_file, ExistingDataPolicy};
+
+    const EXISTING_DATA: &[u8] = b"downloaded elsewhere";
+
+    // A torrent with two files, where only "b" is already on disk.
+    struct PartialDownload {
+        dir: PathBuf,
+        paths: Vec<PathBuf>,
+    }
+
+    impl PartialDownload {
+        fn new() -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("librqbit-initializing-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let paths = vec![dir.join("a"), dir.join("b")];
+            File::create(&paths[1])
+                .unwrap()
+                .write_all(EXISTING_DATA)
+                .unwrap();
+            Self { dir, paths }
+        }
+
+        fn open(&self, policy: ExistingDataPolicy) -> anyhow::Result<Vec<File>> {
+            check_existing_files(&self.paths, policy)?;
+            self.paths.iter().map(|p| open_file(p, policy)).collect()
+        }
+
+        fn contents(&self, idx: usize) -> Vec<u8> {
+            let mut buf = Vec::new();
+            File::open(&self.paths[idx])
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            buf
+        }
+    }
+
+    impl Drop for PartialDownload {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn test_verify_keeps_existing_files() {
+        let t = PartialDownload::new();
+        t.open(ExistingDataPolicy::Verify).unwrap();
+        assert_eq!(t.contents(0), b"");
+        assert_eq!(t.contents(1), EXISTING_DATA);
+    }
+
+    #[test]
+    fn test_overwrite_truncates_existing_files() {
+        let t = PartialDownload::new();
+        t.open(ExistingDataPolicy::Overwrite).unwrap();
+        assert_eq!(t.contents(0), b"");
+        assert_eq!(t.contents(1), b"");
+    }
+
+    #[test]
+    fn test_error_creates_nothing() {
+        let t = PartialDownload::new();
+        assert!(t.open(ExistingDataPolicy::Error).is_err());
+        assert!(!t.paths[0].exists());
+        assert_eq!(t.contents(1), EXISTING_DATA);
+    }
+}
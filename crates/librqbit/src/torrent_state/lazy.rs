@@ -0,0 +1,51 @@
+// Torrents restored with crate::SessionOptions::lazy_load. They are listed with what was
+// persisted about them, and their files aren't opened or checked until they're started or
+// loaded, see ManagedTorrent::load.
+
+use std::{path::PathBuf, sync::Arc};
+
+use super::{initializing::TorrentStateInitializing, ManagedTorrentInfo};
+
+// As persisted when the session was last saved.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PersistedProgress {
+    pub progress_bytes: u64,
+    // The bytes of the selected files.
+    pub total_bytes: u64,
+}
+
+pub struct TorrentStateLazy {
+    // Run when the torrent is started or loaded.
+    pub(crate) initializing: Arc<TorrentStateInitializing>,
+    pub(crate) progress: PersistedProgress,
+}
+
+impl TorrentStateLazy {
+    pub fn progress_bytes(&self) -> u64 {
+        self.progress.progress_bytes
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.progress.total_bytes
+    }
+
+    /// Whether all the selected files were complete, as [Self::total_bytes] only counts
+    /// those.
+    pub fn is_finished(&self) -> bool {
+        self.progress.progress_bytes == self.progress.total_bytes
+    }
+
+    // The files that may be on disk, final or incomplete, e.g. to delete them. The torrent
+    // never opened them, so which one is in use isn't known.
+    pub(crate) fn existing_files(&self, info: &ManagedTorrentInfo) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for path in info.file_paths()? {
+            if let Some(incomplete) = info.incomplete_file_path(&path) {
+                paths.push(incomplete);
+            }
+            paths.push(path);
+        }
+        paths.retain(|p| p.exists());
+        Ok(paths)
+    }
+}
@@ -0,0 +1,313 @@
+// Playback deadlines for streaming. Pieces with a deadline are picked before any other,
+// earliest first, and once a deadline is close, a piece still in flight gets requested
+// from another peer too. A piece that misses its deadline is useless to the player, so it
+// loses the deadline and goes back to the normal order. If it's in flight, its requests are
+// cancelled, so that the bandwidth goes to the pieces that can still make it.
+
+use std::time::{Duration, Instant};
+
+use librqbit_core::lengths::ValidPieceIndex;
+use peer_binary_protocol::{MessageOwned, Request};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::peer_connection::WriterRequest;
+
+use super::{peer::InflightRequest, TorrentStateLive, TorrentStateLocked};
+
+// How long before its deadline a piece in flight may be requested from another peer.
+pub(crate) const DEADLINE_ESCALATION: Duration = Duration::from_secs(3);
+
+/// A piece, and how soon it's needed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PieceDeadline {
+    pub piece: u32,
+    /// Milliseconds from now.
+    pub deadline_ms: u64,
+}
+
+impl TorrentStateLocked {
+    // Forgets deadlines that have passed, returns the pieces that missed theirs.
+    pub(crate) fn expire_deadlines(&mut self, now: Instant) -> Vec<ValidPieceIndex> {
+        let mut missed = Vec::new();
+        self.deadlines.retain(|piece, deadline| {
+            if *deadline > now {
+                return true;
+            }
+            missed.push(*piece);
+            false
+        });
+        missed
+    }
+
+    // The needed piece with the earliest deadline among those "has" accepts.
+    pub(crate) fn next_deadline_piece(
+        &self,
+        has: impl Fn(usize) -> bool,
+    ) -> anyhow::Result<Option<ValidPieceIndex>> {
+        let chunks = self.get_chunks()?;
+        Ok(self
+            .deadlines
+            .iter()
+            .filter(|(piece, _)| chunks.is_piece_needed(piece.get() as usize))
+            .filter(|(piece, _)| has(piece.get() as usize))
+            .min_by_key(|(_, deadline)| **deadline)
+            .map(|(piece, _)| *piece))
+    }
+
+    // The needed pieces with a deadline, earliest first.
+    pub(crate) fn needed_deadline_pieces(&self) -> anyhow::Result<Vec<ValidPieceIndex>> {
+        let chunks = self.get_chunks()?;
+        let mut pieces: Vec<(ValidPieceIndex, Instant)> = self
+            .deadlines
+            .iter()
+            .filter(|(piece, _)| chunks.is_piece_needed(piece.get() as usize))
+            .map(|(piece, deadline)| (*piece, *deadline))
+            .collect();
+        pieces.sort_by_key(|(piece, deadline)| (*deadline, piece.get()));
+        Ok(pieces.into_iter().map(|(piece, _)| piece).collect())
+    }
+
+    // Whether the piece's deadline is within DEADLINE_ESCALATION.
+    pub(crate) fn is_deadline_urgent(&self, piece: ValidPieceIndex, now: Instant) -> bool {
+        self.deadlines
+            .get(&piece)
+            .map(|deadline| *deadline <= now + DEADLINE_ESCALATION)
+            .unwrap_or(false)
+    }
+}
+
+impl TorrentStateLive {
+    /// Replace the playback deadlines of the torrent, e.g. for the next few pieces a media
+    /// player is about to read. An empty list clears them. Pieces already downloaded are
+    /// ignored. Missed deadlines are counted in the torrent stats.
+    pub fn set_piece_deadlines(&self, deadlines: &[PieceDeadline]) -> anyhow::Result<()> {
+        let pieces = deadlines
+            .iter()
+            .map(|d| {
+                self.lengths
+                    .validate_piece_index(d.piece)
+                    .ok_or_else(|| anyhow::anyhow!("invalid piece {}", d.piece))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        self.expire_deadlines()?;
+
+        let now = Instant::now();
+        let mut g = self.lock_write("set_piece_deadlines");
+        let chunks = g.get_chunks()?;
+        let new = pieces
+            .into_iter()
+            .zip(deadlines)
+            .filter(|(piece, _)| !chunks.get_have_pieces()[piece.get() as usize])
+            .map(|(piece, d)| (piece, now + Duration::from_millis(d.deadline_ms)))
+            .collect();
+        g.deadlines = new;
+        Ok(())
+    }
+
+    // Forgets deadlines that have passed, and stops downloading the pieces that missed them.
+    pub(crate) fn expire_deadlines(&self) -> anyhow::Result<()> {
+        let released = {
+            let mut g = self.lock_write("expire_deadlines");
+            let missed = g.expire_deadlines(Instant::now());
+            if missed.is_empty() {
+                return Ok(());
+            }
+            self.stats
+                .deadline_misses
+                .fetch_add(missed.len() as u64, std::sync::atomic::Ordering::Relaxed);
+            let mut released = Vec::new();
+            for piece in missed {
+                if g.inflight_pieces.remove(&piece).is_none() {
+                    continue;
+                }
+                // The chunk doesn't matter, the whole piece is needed again.
+                g.get_chunks_mut()?.mark_chunk_request_cancelled(piece, 0);
+                self.release_piece_buffer(&mut g, piece)?;
+                released.push(piece);
+            }
+            released
+        };
+        // Peers are locked before the torrent elsewhere, so not while it's locked.
+        self.cancel_requests(&released);
+        Ok(())
+    }
+
+    // Cancels the requests for the pieces to all peers. Chunks that arrive anyway are dropped.
+    fn cancel_requests(&self, pieces: &[ValidPieceIndex]) {
+        if pieces.is_empty() {
+            return;
+        }
+        let mut cancelled = 0;
+        for mut peer in self.peers.states.iter_mut() {
+            let live = match peer.value_mut().state.get_live_mut() {
+                Some(live) => live,
+                None => continue,
+            };
+            // Without the fast extension, a peer may drop a cancelled request silently, and
+            // the slot it took in the peer's request pipeline would never come back.
+            if !live.fast_extension {
+                continue;
+            }
+            for chunk in pieces
+                .iter()
+                .flat_map(|piece| self.lengths.iter_chunk_infos(*piece))
+            {
+                let request = InflightRequest::from(&chunk);
+                if !live.inflight_requests.remove(&request) {
+                    continue;
+                }
+                live.cancelled_requests.insert(request);
+                cancelled += 1;
+                let _ = live
+                    .tx
+                    .send(WriterRequest::Message(MessageOwned::Cancel(Request {
+                        index: chunk.piece_index.get(),
+                        begin: chunk.offset,
+                        length: chunk.size,
+                    })));
+            }
+        }
+        debug!(
+            ?pieces,
+            cancelled, "cancelled requests of pieces that missed their deadline"
+        );
+        self.meta.options.request_limiter.release(cancelled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use librqbit_core::lengths::Lengths;
+    use peer_binary_protocol::Message;
+
+    use crate::{
+        chunk_tracker::ChunkTracker,
+        session::SessionOptions,
+        test_util::{
+            add_test_torrent, test_data, test_dir, test_session, RawPeer, TEST_PIECE_LENGTH,
+        },
+        type_aliases::BF,
+    };
+
+    use super::{super::TorrentStateLocked, PieceDeadline};
+
+    #[test]
+    fn test_deadline_order_and_expiry() {
+        let lengths = Lengths::new(16384 * 4, 16384, None).unwrap();
+        let piece = |n| lengths.validate_piece_index(n).unwrap();
+        let mut needed = BF::from_vec(vec![0]);
+        let mut have = BF::from_vec(vec![0]);
+        needed[..3].fill(true);
+        have.set(3, true);
+        let mut g = TorrentStateLocked {
+            chunks: Some(ChunkTracker::new(needed, have, lengths, 16384 * 4)),
+            inflight_pieces: Default::default(),
+            fatal_errors_tx: None,
+            failed_pieces: Default::default(),
+            poisoned_pieces: Default::default(),
+            deadlines: Default::default(),
+        };
+        let now = Instant::now();
+        g.deadlines.insert(piece(0), now + Duration::from_secs(10));
+        g.deadlines.insert(piece(1), now + Duration::from_secs(1));
+        g.deadlines.insert(piece(2), now - Duration::from_secs(1));
+        // Downloaded already.
+        g.deadlines.insert(piece(3), now + Duration::from_secs(2));
+
+        assert_eq!(
+            g.needed_deadline_pieces().unwrap(),
+            vec![piece(2), piece(1), piece(0)]
+        );
+        assert_eq!(g.next_deadline_piece(|n| n != 2).unwrap(), Some(piece(1)));
+        assert_eq!(g.next_deadline_piece(|_| false).unwrap(), None);
+        assert!(g.is_deadline_urgent(piece(1), now));
+        assert!(!g.is_deadline_urgent(piece(0), now));
+
+        assert_eq!(g.expire_deadlines(now), vec![piece(2)]);
+        assert!(g.expire_deadlines(now).is_empty());
+        assert_eq!(
+            g.needed_deadline_pieces().unwrap(),
+            vec![piece(1), piece(0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_piece_deadlines() {
+        let dir = test_dir("piece-deadlines");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27100..27200),
+                ..Default::default()
+            },
+        )
+        .await;
+        let data = test_data(TEST_PIECE_LENGTH as usize * 4, 0);
+        let (_, handle) =
+            add_test_torrent(&session, "t", &[("f", &data)], Default::default()).await;
+        let live = handle.live().unwrap();
+        let deadline = |piece, deadline_ms| PieceDeadline { piece, deadline_ms };
+
+        live.set_piece_deadlines(&[deadline(2, 60_000), deadline(1, 60_000)])
+            .unwrap();
+        // An invalid piece leaves the deadlines as they were.
+        assert!(live
+            .set_piece_deadlines(&[deadline(0, 60_000), deadline(4, 60_000)])
+            .is_err());
+        let pieces = |live: &super::TorrentStateLive| -> Vec<u32> {
+            live.lock_read("test")
+                .needed_deadline_pieces()
+                .unwrap()
+                .into_iter()
+                .map(|p| p.get())
+                .collect()
+        };
+        assert_eq!(pieces(&live), vec![1, 2]);
+
+        // A piece in flight that misses its deadline is cancelled.
+        let port = session.listen_status().port.unwrap();
+        let mut peer = RawPeer::connect(port, handle.info_hash()).await;
+        peer.send(Message::HaveAll).await;
+        peer.send(Message::Unchoke).await;
+        let request = loop {
+            if let Message::Request(r) = peer.read().await {
+                break r;
+            }
+        };
+        live.set_piece_deadlines(&[deadline(request.index, 0)])
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        live.set_piece_deadlines(&[]).unwrap();
+        assert_eq!(live.stats_snapshot().deadline_misses, 1);
+        let cancelled = loop {
+            if let Message::Cancel(r) = peer.read().await {
+                break r;
+            }
+        };
+        assert_eq!(cancelled.index, request.index);
+
+        // Answering it anyway is fine.
+        peer.send(Message::RejectRequest(cancelled)).await;
+        let addr = peer.stream.local_addr().unwrap();
+        for _ in 0..500 {
+            if live
+                .peers
+                .with_live(addr, |l| l.cancelled_requests.is_empty())
+                .unwrap()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(live
+            .peers
+            .with_live(addr, |l| l.cancelled_requests.is_empty())
+            .unwrap());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+// BEP 17 HTTP seeding. The seed serves whole pieces at "<url>?info_hash=..&piece=N", so
+// each seed task reserves one needed piece at a time, the same way a peer would, and lets
+// peers steal it back if the seed is slower than them.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use librqbit_core::{hash_id::Id20, lengths::ValidPieceIndex};
+use reqwest::StatusCode;
+use tracing::{debug, error, warn};
+use url::Url;
+
+use crate::{dns::client_builder, type_aliases::PeerHandle};
+
+use super::{InflightPiece, TorrentStateLive};
+
+// How long to leave a seed alone after it failed.
+const HTTP_SEED_ERROR_BACKOFF: Duration = Duration::from_secs(60);
+// How often to look for work when all needed pieces are already being downloaded.
+const HTTP_SEED_IDLE_INTERVAL: Duration = Duration::from_secs(5);
+// Upper bound for the wait a busy seed asks for.
+const HTTP_SEED_MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+// A seed that doesn't send the whole piece by then counts as failed, so that a stalled
+// connection doesn't hold on to the piece forever.
+const HTTP_SEED_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The piece bookkeeping identifies whoever downloads a piece by address. HTTP seeds get
+// 0.0.0.0:<index + 1>, which no real peer can have.
+pub(crate) fn http_seed_handle(idx: usize) -> PeerHandle {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, (idx % u16::MAX as usize) as u16 + 1))
+}
+
+fn piece_url(seed: &Url, info_hash: &Id20, piece: u32) -> String {
+    let separator = if seed.query().is_some() { '&' } else { '?' };
+    format!(
+        "{seed}{separator}info_hash={}&piece={piece}",
+        urlencoding::encode_binary(&info_hash.0)
+    )
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SeedResponse {
+    Piece(Vec<u8>),
+    // The seed is busy and asked to come back later.
+    RetryAfter(Duration),
+}
+
+async fn fetch_piece(
+    client: &reqwest::Client,
+    url: &str,
+    piece_len: usize,
+) -> anyhow::Result<SeedResponse> {
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("error fetching {url}"))?;
+    let status = response.status();
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        // The body is the number of seconds to wait before retrying.
+        let wait = response
+            .text()
+            .await
+            .ok()
+            .and_then(|body| body.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(HTTP_SEED_ERROR_BACKOFF);
+        return Ok(SeedResponse::RetryAfter(
+            wait.min(HTTP_SEED_MAX_RETRY_AFTER),
+        ));
+    }
+    if !status.is_success() {
+        bail!("GET {} returned {}", url, status)
+    }
+    let mut buf = Vec::with_capacity(piece_len);
+    while let Some(chunk) = response.chunk().await? {
+        if buf.len() + chunk.len() > piece_len {
+            bail!("{url} sent more than the piece length of {piece_len} bytes");
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    if buf.len() != piece_len {
+        bail!(
+            "{url} sent {} bytes, expected the piece length of {piece_len}",
+            buf.len()
+        );
+    }
+    Ok(SeedResponse::Piece(buf))
+}
+
+impl TorrentStateLive {
+    pub(crate) async fn task_http_seed(
+        self: Arc<Self>,
+        idx: usize,
+        seed: Url,
+    ) -> anyhow::Result<()> {
+        if let Some(delay) = self.web_seed_head_start() {
+            debug!("letting peers go first for {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+        let handle = http_seed_handle(idx);
+        let client = client_builder(self.meta.options.dns_resolver.as_ref())
+            .timeout(HTTP_SEED_REQUEST_TIMEOUT)
+            .build()?;
+        loop {
+            if self.is_finished() {
+                debug!("torrent finished, stopping");
+                return Ok(());
+            }
+            let piece = match self.reserve_piece_for_http_seed(handle)? {
+                Some(piece) => piece,
+                None => {
+                    tokio::time::sleep(HTTP_SEED_IDLE_INTERVAL).await;
+                    continue;
+                }
+            };
+            let wait = match self
+                .download_from_http_seed(&client, &seed, handle, piece)
+                .await
+            {
+                Ok(None) => continue,
+                Ok(Some(wait)) => {
+                    debug!("seed is busy, retrying in {:?}", wait);
+                    wait
+                }
+                Err(e) => {
+                    warn!("error downloading piece={} from HTTP seed: {:#}", piece, e);
+                    HTTP_SEED_ERROR_BACKOFF
+                }
+            };
+            self.release_http_seed_piece(handle, piece)?;
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Returns how long to wait if the seed is busy, None if the piece was taken care of.
+    async fn download_from_http_seed(
+        &self,
+        client: &reqwest::Client,
+        seed: &Url,
+        handle: PeerHandle,
+        piece: ValidPieceIndex,
+    ) -> anyhow::Result<Option<Duration>> {
+        let url = piece_url(seed, &self.info_hash(), piece.get());
+        let piece_len = self.lengths.piece_length(piece) as usize;
+        let data = match fetch_piece(client, &url, piece_len).await? {
+            SeedResponse::Piece(data) => data,
+            SeedResponse::RetryAfter(wait) => return Ok(Some(wait)),
+        };
+        self.stats
+            .fetched_bytes
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+
+        self.meta.spawner.spawn_block_in_place(|| {
+            let verified = self
+                .file_ops()
+                .check_piece_data(piece, &data)
+                .with_context(|| format!("error checking piece={piece}"))?;
+            if !verified {
+                self.on_piece_failed_verification(piece, handle)?;
+                bail!("checksum for piece={} did not validate", piece);
+            }
+
+            let download_time = match self.claim_http_seed_piece(handle, piece)? {
+                Some(t) => t,
+                None => {
+                    debug!("piece={} was stolen by a peer, dropping it", piece);
+                    return Ok(None);
+                }
+            };
+            if let Err(e) = self.file_ops().write_piece(handle, piece, &data) {
+                error!("FATAL: error writing piece to disk: {:?}", e);
+                return self.on_fatal_error(e).map(|_| None);
+            }
+            self.on_piece_verified(piece, download_time)?;
+            self.stats
+                .web_seed_bytes
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+            self.stats.web_seed_pieces.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
+        })
+    }
+
+    // Seeds have every piece, so just take the first needed one.
+    fn reserve_piece_for_http_seed(
+        &self,
+        handle: PeerHandle,
+    ) -> anyhow::Result<Option<ValidPieceIndex>> {
+        let mut g = self.lock_write("reserve_piece_for_http_seed");
+        let n = match g.get_chunks()?.iter_needed_pieces().next() {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        let n = self
+            .lengths
+            .validate_piece_index(n as u32)
+            .context("bug: invalid piece")?;
+        g.inflight_pieces.insert(
+            n,
+            InflightPiece {
+                peer: handle,
+                started: Instant::now(),
+                requested_from: 1,
+            },
+        );
+        g.get_chunks_mut()?.reserve_needed_piece(n);
+        Ok(Some(n))
+    }
+
+    // Takes the piece out of flight so that peers can't steal it anymore. Returns how long
+    // it took, or None if a peer has stolen it already.
+    fn claim_http_seed_piece(
+        &self,
+        handle: PeerHandle,
+        piece: ValidPieceIndex,
+    ) -> anyhow::Result<Option<Duration>> {
+        let mut g = self.lock_write("claim_http_seed_piece");
+        match g.inflight_pieces.get(&piece) {
+            Some(InflightPiece { peer, .. }) if *peer == handle => {}
+            _ => return Ok(None),
+        }
+        let elapsed = g
+            .inflight_pieces
+            .remove(&piece)
+            .map(|p| p.started.elapsed());
+        g.get_chunks_mut()?.mark_all_chunks_downloaded(piece);
+        Ok(elapsed)
+    }
+
+    // Gives the piece back to the peers, unless one of them has stolen it already.
+    fn release_http_seed_piece(
+        &self,
+        handle: PeerHandle,
+        piece: ValidPieceIndex,
+    ) -> anyhow::Result<()> {
+        let mut g = self.lock_write("release_http_seed_piece");
+        match g.inflight_pieces.get(&piece) {
+            Some(InflightPiece { peer, .. }) if *peer == handle => {}
+            _ => return Ok(()),
+        }
+        g.inflight_pieces.remove(&piece);
+        g.get_chunks_mut()?.mark_piece_broken_if_not_have(piece);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use librqbit_core::hash_id::Id20;
+    use url::Url;
+
+    use crate::{
+        session::{AddTorrent, AddTorrentResponse},
+        test_util::{
+            http_response, serve_http, test_data, test_dir, test_session, test_torrent,
+            TEST_PIECE_LENGTH,
+        },
+    };
+
+    use super::{fetch_piece, http_seed_handle, piece_url, SeedResponse};
+
+    // Answers requests with the given status line and body, returns the URL.
+    async fn serve(status: &'static str, body: &'static [u8]) -> String {
+        let addr = serve_http(move |_| http_response(status, &[], body)).await;
+        format!("http://{addr}/seed")
+    }
+
+    #[test]
+    fn test_piece_url() {
+        let info_hash = Id20::new([0x11; 20]);
+        let plain = Url::parse("http://seed.example/seed.php").unwrap();
+        assert_eq!(
+            piece_url(&plain, &info_hash, 7),
+            format!(
+                "http://seed.example/seed.php?info_hash={}&piece=7",
+                "%11".repeat(20)
+            )
+        );
+        let with_query = Url::parse("http://seed.example/seed.php?key=1").unwrap();
+        assert!(piece_url(&with_query, &info_hash, 7)
+            .starts_with("http://seed.example/seed.php?key=1&info_hash="));
+    }
+
+    #[test]
+    fn test_http_seed_handles_are_distinct() {
+        assert_ne!(http_seed_handle(0), http_seed_handle(1));
+        assert_eq!(http_seed_handle(0).port(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_piece() {
+        let client = reqwest::Client::new();
+
+        let url = serve("200 OK", b"piece").await;
+        assert_eq!(
+            fetch_piece(&client, &url, 5).await.unwrap(),
+            SeedResponse::Piece(b"piece".to_vec())
+        );
+
+        let url = serve("503 Service Unavailable", b"30").await;
+        assert_eq!(
+            fetch_piece(&client, &url, 5).await.unwrap(),
+            SeedResponse::RetryAfter(Duration::from_secs(30))
+        );
+
+        let url = serve("200 OK", b"short").await;
+        assert!(fetch_piece(&client, &url, 6).await.is_err());
+
+        let url = serve("404 Not Found", b"").await;
+        assert!(fetch_piece(&client, &url, 5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_web_seed_bytes_are_counted() {
+        let dir = test_dir("web-seed-stats");
+        let piece_len = TEST_PIECE_LENGTH as usize;
+        let data = test_data(piece_len * 2 + 100, 0);
+        let addr = serve_http({
+            let data = data.clone();
+            move |request| {
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let piece: usize = target
+                    .split("piece=")
+                    .nth(1)
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(usize::MAX);
+                let start = piece.saturating_mul(piece_len).min(data.len());
+                let end = (start + piece_len).min(data.len());
+                http_response("200 OK", &[], &data[start..end])
+            }
+        })
+        .await;
+        // The same torrent, with the seed.
+        let mut torrent = test_torrent("t", &[("f", &data)]);
+        let seeds = format!(
+            "9:httpseedsl{}:http://{addr}/seede",
+            12 + addr.to_string().len()
+        );
+        torrent.splice(1..1, seeds.into_bytes());
+
+        let session = test_session(&dir, Default::default()).await;
+        let handle = match session
+            .add_torrent(AddTorrent::from_bytes(torrent), None)
+            .await
+            .unwrap()
+        {
+            AddTorrentResponse::Added(_, handle) => handle,
+            _ => panic!("expected the torrent to be added"),
+        };
+        tokio::time::timeout(Duration::from_secs(10), handle.wait_until_completed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let stats = handle.stats();
+        let snapshot = stats.live.unwrap().snapshot;
+        assert_eq!(snapshot.web_seed_pieces, 3);
+        assert_eq!(snapshot.web_seed_bytes, data.len() as u64);
+        assert_eq!(snapshot.peer_pieces, 0);
+        assert_eq!(snapshot.peer_bytes, 0);
+        assert_eq!(stats.lifetime.web_seed_bytes, data.len() as u64);
+        assert_eq!(std::fs::read(dir.join("t/f")).unwrap(), data);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
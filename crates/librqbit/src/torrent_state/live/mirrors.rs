@@ -0,0 +1,253 @@
+// Keeping full copies of the data in other directories, see
+// crate::AddTorrentOptions::mirror_paths. Pieces are written to the output folder as they
+// come in, and copied to each mirror from there once verified, so a mirror only ever gets
+// good data. The pieces that were already there when the torrent goes live are back-filled
+// in the background, skipping the ones a mirror already has right. Mirrors always use the
+// final file names, incomplete paths are for the output folder only.
+
+use std::{
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use librqbit_core::lengths::{ChunkInfo, ValidPieceIndex};
+use parking_lot::Mutex;
+use sha1w::Sha1;
+use tracing::{debug, warn};
+
+use crate::{file_ops::FileOps, torrent_state::ManagedTorrentInfo, type_aliases::PeerHandle};
+
+use super::TorrentStateLive;
+
+pub(crate) type MirrorFiles = Vec<Vec<Arc<Mutex<File>>>>;
+
+// Opens (creating if needed) every file of the torrent under each mirror path. Files are
+// left as they are: a mirror that was used before keeps its data.
+pub(crate) fn open_mirror_files(meta: &ManagedTorrentInfo) -> anyhow::Result<MirrorFiles> {
+    if meta.options.mirror_paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let relative_paths = meta
+        .file_paths()?
+        .into_iter()
+        .map(|p| {
+            p.strip_prefix(&meta.out_dir)
+                .map(|p| p.to_owned())
+                .with_context(|| format!("bug: {p:?} is not in the output folder"))
+        })
+        .collect::<anyhow::Result<Vec<PathBuf>>>()?;
+    let perms = meta.file_permissions();
+    meta.options
+        .mirror_paths
+        .iter()
+        .map(|mirror| {
+            relative_paths
+                .iter()
+                .map(|relative| {
+                    let path = mirror.join(relative);
+                    if let Some(parent) = path.parent() {
+                        perms.create_dir_all(parent)?;
+                    }
+                    let existed = path.exists();
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .truncate(false)
+                        .read(true)
+                        .write(true)
+                        .open(&path)
+                        .with_context(|| format!("error opening mirror file {path:?}"))?;
+                    if !existed {
+                        perms.apply_to_file(&path)?;
+                    }
+                    Ok(Arc::new(Mutex::new(file)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl TorrentStateLive {
+    fn mirror_file_ops<'a>(&'a self, files: &'a [Arc<Mutex<File>>]) -> FileOps<'a, Sha1> {
+        FileOps::new(&self.meta.info, files, &self.lengths).with_io_counters(&self.stats.disk_io)
+    }
+
+    fn is_mirrored(&self, piece: ValidPieceIndex) -> bool {
+        self.mirrored_pieces
+            .lock()
+            .get(piece.get() as usize)
+            .map(|v| *v)
+            .unwrap_or(false)
+    }
+
+    // Called for every verified piece. A mirror failing doesn't stop the torrent, the
+    // output folder still has the data.
+    pub(crate) fn copy_piece_to_mirrors(&self, piece: ValidPieceIndex) {
+        self.mirror_piece(piece, false)
+    }
+
+    // With "check_first", mirrors that already have the piece right are only read.
+    fn mirror_piece(&self, piece: ValidPieceIndex, check_first: bool) {
+        if self.mirror_files.is_empty() {
+            return;
+        }
+        let mut copied = true;
+        for (mirror, files) in self
+            .meta
+            .options
+            .mirror_paths
+            .iter()
+            .zip(self.mirror_files.iter())
+        {
+            let there = check_first
+                && self
+                    .mirror_file_ops(files)
+                    .piece_matches(piece)
+                    .unwrap_or(false);
+            if there {
+                continue;
+            }
+            if let Err(e) = self.file_ops().copy_piece_to(piece, files) {
+                warn!(?mirror, "error copying piece={} to mirror: {:#}", piece, e);
+                copied = false;
+            }
+        }
+        if copied {
+            self.mirrored_pieces.lock().set(piece.get() as usize, true);
+        }
+    }
+
+    // Copies the pieces the torrent had when it went live, e.g. after a restart or a
+    // mirror being added to a torrent that was already downloaded.
+    pub(crate) async fn task_backfill_mirrors(self: Arc<Self>) -> anyhow::Result<()> {
+        let have = self
+            .lock_read("mirror_backfill")
+            .get_chunks()?
+            .get_have_pieces()
+            .clone();
+        let state = Arc::downgrade(&self);
+        drop(self);
+        for piece in have.iter_ones() {
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return Ok(()),
+            };
+            let piece = state
+                .lengths
+                .validate_piece_index(piece as u32)
+                .context("bug: invalid piece")?;
+            // Pieces verified since were copied already.
+            if !state.is_mirrored(piece) {
+                state
+                    .meta
+                    .spawner
+                    .spawn_block_in_place(|| state.mirror_piece(piece, true));
+            }
+            drop(state);
+            tokio::task::yield_now().await;
+        }
+        debug!("mirrors are up to date");
+        Ok(())
+    }
+
+    // Reads the chunk from the first mirror that has it, after reading it from the output
+    // folder failed.
+    pub(crate) fn read_chunk_from_mirrors(
+        &self,
+        who_sent: PeerHandle,
+        chunk: &ChunkInfo,
+        buf: &mut [u8],
+    ) -> anyhow::Result<()> {
+        if !self.is_mirrored(chunk.piece_index) {
+            anyhow::bail!("piece={} is not in the mirrors", chunk.piece_index);
+        }
+        let mut last_error = None;
+        for (mirror, files) in self
+            .meta
+            .options
+            .mirror_paths
+            .iter()
+            .zip(self.mirror_files.iter())
+        {
+            match self.mirror_file_ops(files).read_chunk(who_sent, chunk, buf) {
+                Ok(()) => {
+                    debug!(?mirror, "read piece={} from mirror", chunk.piece_index);
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no mirrors")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use crate::{
+        session::AddTorrentOptions,
+        test_util::{add_test_torrent, test_data, test_dir, test_session, TEST_PIECE_LENGTH},
+    };
+
+    async fn wait_for_file(path: &Path, data: &[u8]) {
+        for _ in 0..500 {
+            if std::fs::read(path).ok().as_deref() == Some(data) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{path:?} never got the data");
+    }
+
+    #[tokio::test]
+    async fn test_backfills_pieces_already_downloaded() {
+        let dir = test_dir("mirror-backfill");
+        let session = test_session(&dir, Default::default()).await;
+        let a = test_data(TEST_PIECE_LENGTH as usize * 2 + 10, 0);
+        let b = test_data(TEST_PIECE_LENGTH as usize, 1);
+        std::fs::create_dir_all(dir.join("t")).unwrap();
+        std::fs::write(dir.join("t/a"), &a).unwrap();
+        std::fs::write(dir.join("t/b"), &b).unwrap();
+        // This mirror already has "b", only "a" gets written.
+        let mirror = dir.join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        std::fs::write(mirror.join("b"), &b).unwrap();
+
+        let (_, handle) = add_test_torrent(
+            &session,
+            "t",
+            &[("a", &a), ("b", &b)],
+            AddTorrentOptions {
+                mirror_paths: vec![mirror.clone()],
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(handle.stats().finished);
+        wait_for_file(&mirror.join("a"), &a).await;
+        assert_eq!(std::fs::read(mirror.join("b")).unwrap(), b);
+
+        let live = handle.live().unwrap();
+        let total_pieces = live.lengths.total_pieces();
+        for _ in 0..500 {
+            if live.mirrored_pieces.lock().count_ones() == total_pieces as usize {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            live.mirrored_pieces.lock().count_ones(),
+            total_pieces as usize
+        );
+        // The piece straddling "a" and "b" is copied whole, the last one, all in "b", isn't.
+        assert_eq!(
+            live.stats_snapshot().disk_written_bytes,
+            TEST_PIECE_LENGTH as u64 * 3
+        );
+        drop(live);
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
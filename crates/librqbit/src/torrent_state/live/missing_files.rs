@@ -0,0 +1,184 @@
+// Files deleted from under a live torrent. On unix an open file stays readable after it's
+// deleted, so failed reads alone don't catch it, and the files are also checked
+// periodically. Once one is gone, the torrent stops with an error, which also stops
+// announcing it. Starting it again, or a forced recheck, hashes whatever data is left.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tracing::warn;
+
+use super::TorrentStateLive;
+
+// How many of the missing paths to name in the error.
+const MAX_PATHS_IN_ERROR: usize = 5;
+
+fn missing_paths<'a>(filenames: impl IntoIterator<Item = &'a Path>) -> Vec<PathBuf> {
+    filenames
+        .into_iter()
+        .filter(|p| !p.exists())
+        .map(|p| p.to_owned())
+        .collect()
+}
+
+fn files_missing_error(missing: &[PathBuf]) -> anyhow::Error {
+    let mut names = missing
+        .iter()
+        .take(MAX_PATHS_IN_ERROR)
+        .map(|p| format!("{p:?}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if missing.len() > MAX_PATHS_IN_ERROR {
+        names += &format!(" and {} more", missing.len() - MAX_PATHS_IN_ERROR);
+    }
+    anyhow::anyhow!(
+        "files missing from disk: {names}. Force a recheck to find out what's left of the data"
+    )
+}
+
+impl TorrentStateLive {
+    // Stops the torrent with an error if any of its files are gone. Returns whether they were.
+    pub(crate) fn check_missing_files(&self) -> bool {
+        let missing = missing_paths(self.filenames.read().iter().map(|p| p.as_path()));
+        if missing.is_empty() {
+            return false;
+        }
+        let e = files_missing_error(&missing);
+        warn!("{:#}", e);
+        // This always returns the error back.
+        let _ = self.on_fatal_error(e);
+        true
+    }
+
+    pub(crate) async fn task_missing_files_watcher(
+        self: Arc<Self>,
+        interval: std::time::Duration,
+    ) -> anyhow::Result<()> {
+        let state = Arc::downgrade(&self);
+        drop(self);
+        loop {
+            tokio::time::sleep(interval).await;
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return Ok(()),
+            };
+            if state.check_missing_files() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Seek, SeekFrom, Write},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use crate::{
+        session::SessionOptions,
+        test_util::{
+            add_test_torrent, test_data, test_dir, test_session, wait_until_live, TEST_PIECE_LENGTH,
+        },
+    };
+
+    use super::{files_missing_error, missing_paths};
+
+    // Windows doesn't let open files be deleted.
+    #[cfg(unix)]
+    #[test]
+    fn test_file_removed_while_seeding() {
+        let dir =
+            std::env::temp_dir().join(format!("librqbit-missing-files-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = vec![dir.join("a"), dir.join("b")];
+        let mut files = paths
+            .iter()
+            .map(|p| {
+                let mut f = File::options()
+                    .create(true)
+                    .read(true)
+                    .write(true)
+                    .open(p)
+                    .unwrap();
+                f.write_all(b"data").unwrap();
+                f
+            })
+            .collect::<Vec<_>>();
+        assert!(missing_paths(paths.iter().map(|p| p.as_path())).is_empty());
+
+        std::fs::remove_file(&paths[1]).unwrap();
+        // The open file can still be read, only the path tells it's gone.
+        let mut buf = Vec::new();
+        files[1].seek(SeekFrom::Start(0)).unwrap();
+        files[1].read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"data");
+        files.clear();
+        assert_eq!(
+            missing_paths(paths.iter().map(|p| p.as_path())),
+            vec![paths[1].clone()]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_session_file_removed_while_seeding() {
+        let dir = test_dir("missing-files-session");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                missing_files_check_interval: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        )
+        .await;
+        let a = test_data(TEST_PIECE_LENGTH as usize * 2, 0);
+        let b = test_data(TEST_PIECE_LENGTH as usize, 1);
+        std::fs::create_dir_all(dir.join("t")).unwrap();
+        std::fs::write(dir.join("t/a"), &a).unwrap();
+        std::fs::write(dir.join("t/b"), &b).unwrap();
+        let (_, handle) =
+            add_test_torrent(&session, "t", &[("a", &a), ("b", &b)], Default::default()).await;
+        assert!(handle.stats().finished);
+
+        std::fs::remove_file(dir.join("t/b")).unwrap();
+        let mut error = None;
+        for _ in 0..500 {
+            let stats = handle.stats();
+            if stats.error.is_some() {
+                error = stats.error;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let error = error.expect("the removed file wasn't noticed");
+        assert!(error.starts_with("files missing from disk"), "{error}");
+        assert!(error.contains("t/b"), "{error}");
+        assert!(handle.live().is_none());
+
+        // A recheck finds what's left, i.e. all of "a" and none of "b".
+        session.force_recheck(&handle).unwrap();
+        wait_until_live(&handle).await;
+        let stats = handle.stats();
+        assert!(!stats.finished);
+        assert_eq!(stats.progress_bytes, a.len() as u64);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_missing_error_is_bounded() {
+        let missing = (0..7)
+            .map(|i| PathBuf::from(format!("/data/{i}")))
+            .collect::<Vec<_>>();
+        let e = format!("{:#}", files_missing_error(&missing));
+        assert!(e.starts_with("files missing from disk: \"/data/0\""));
+        assert!(e.contains("\"/data/4\" and 2 more"));
+        assert!(!e.contains("/data/5"));
+    }
+}
@@ -39,15 +39,36 @@
 // > so don't lock them both at the same time at all, or at the worst lock them in the
 // > same order (peers one first, then the global one).
 
+pub mod deadlines;
+#[cfg(feature = "debug-api")]
+mod debug_dump;
+mod http_seed;
+mod mirrors;
+mod missing_files;
 pub mod peer;
+mod peer_cache;
+pub mod peer_events;
+pub mod peer_source;
 pub mod peers;
+#[cfg(feature = "debug-api")]
+pub mod picker_plan;
+mod piece_buffers;
+pub mod piece_latency;
+pub mod progress_thresholds;
+mod slow_peers;
 pub mod stats;
+pub mod swarm_health;
+pub mod tracker_stats;
+mod upload_slots;
+pub mod verification;
+
+pub(crate) use mirrors::{open_mirror_files, MirrorFiles};
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fs::File,
     net::SocketAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -64,6 +85,7 @@ use futures::{stream::FuturesUnordered, StreamExt};
 use itertools::Itertools;
 use librqbit_core::{
     hash_id::Id20,
+    jitter::{jitter, DEFAULT_JITTER},
     lengths::{ChunkInfo, Lengths, ValidPieceIndex},
     spawn_utils::spawn_with_cancel,
     speed_estimator::SpeedEstimator,
@@ -86,14 +108,18 @@ use tracing::{debug, error, error_span, info, trace, warn};
 use url::Url;
 
 use crate::{
+    bandwidth::RateLimiter,
     chunk_tracker::{ChunkMarkingResult, ChunkTracker},
-    file_ops::FileOps,
-    peer_connection::{
-        PeerConnection, PeerConnectionHandler, PeerConnectionOptions, WriterRequest,
-    },
+    file_ops::{is_read_only_fs_error, remove_empty_dirs, sync_every, FileOps, FsyncPolicy},
+    file_permissions::FilePermissions,
+    first_piece::{pick_next_piece, FirstPieceStrategy},
+    peer_connection::{PeerConnection, PeerConnectionHandler, WriterRequest},
     session::CheckedIncomingConnection,
     torrent_state::{peer::Peer, utils::atomic_inc},
-    tracker_comms::{TrackerError, TrackerRequest, TrackerRequestEvent, TrackerResponse},
+    tracker_comms::{
+        read_tracker_response, tracker_get, TrackerError, TrackerRequest, TrackerRequestEvent,
+        TrackerResponse,
+    },
     type_aliases::{PeerHandle, BF},
 };
 
@@ -101,23 +127,57 @@ use self::{
     peer::{
         stats::{
             atomic::PeerCountersAtomic as AtomicPeerCounters,
-            snapshot::{PeerStatsFilter, PeerStatsSnapshot},
+            snapshot::{PeerStats, PeerStatsFilter, PeerStatsSnapshot},
         },
         InflightRequest, PeerRx, PeerState, PeerTx,
     },
+    peer_events::{PeerDisconnectReason, PeerEvent},
+    peer_source::{PeerDialQueue, PeerSource},
     peers::PeerStates,
+    piece_buffers::PieceBuffers,
+    piece_latency::{PieceLatencyHistogram, PieceLatencyStats},
+    slow_peers::PeerRanking,
     stats::{atomic::AtomicStats, snapshot::StatsSnapshot},
+    tracker_stats::{initial_tracker_stats, AnnounceRecord, TrackerStats},
+    verification::{ChunkSource, VerificationFailure},
 };
 
 use super::{
+    events::{PoisonedPiece, TorrentEvent},
     paused::TorrentStatePaused,
-    utils::{timeit, TimedExistence},
+    utils::{ip_in_subnets, is_file_complete, timeit, with_incomplete_suffix, TimedExistence},
     ManagedTorrentInfo,
 };
 
+// Live peer connections per torrent.
+pub(crate) const MAX_LIVE_PEERS: usize = 128;
+
+// With prefer_good_peers, a peer scoring below this fraction of the average score of live
+// peers waits for its in-flight requests before reserving another piece...
+const OUTSCORED_PEER_FRACTION: f64 = 0.5;
+// ...except for this share of pieces, so that it can still prove itself.
+const OUTSCORED_PEER_PROBE_PROBABILITY: f64 = 0.1;
+// ...and for at most this long each time.
+const YIELD_TO_BETTER_PEERS_MAX_WAIT: Duration = Duration::from_secs(5);
+
 struct InflightPiece {
     peer: PeerHandle,
     started: Instant,
+    // How many peers this piece was requested from since it was reserved, counting steals.
+    requested_from: usize,
+}
+
+// What a successful announce returned, apart from the peers.
+struct TrackerAnnounce {
+    interval: u64,
+    min_interval: Option<u64>,
+    tracker_id: Option<String>,
+    // Where the request ended up after redirects.
+    final_url: Url,
+    seeders: u64,
+    leechers: u64,
+    // How many peers came back.
+    peers: usize,
 }
 
 fn dummy_file() -> anyhow::Result<std::fs::File> {
@@ -132,6 +192,55 @@ fn dummy_file() -> anyhow::Result<std::fs::File> {
         .with_context(|| format!("error opening {}", DEVNULL))
 }
 
+// Moves an open torrent file. The handle is closed around the move so that it works on
+// Windows too, and the lock keeps any IO on the file out until it's reopened.
+pub(crate) fn move_open_file(
+    file: &Mutex<File>,
+    from: &Path,
+    to: &Path,
+    perms: FilePermissions,
+) -> anyhow::Result<()> {
+    let mut g = file.lock();
+    *g = dummy_file()?;
+    let moved = move_file(from, to, perms);
+    // If the move failed, keep using the file where it was.
+    let path = if moved.is_ok() { to } else { from };
+    *g = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("error re-opening {path:?}"))?;
+    moved
+}
+
+fn move_file(from: &Path, to: &Path, perms: FilePermissions) -> anyhow::Result<()> {
+    move_file_with_rename(from, to, perms, |from, to| std::fs::rename(from, to))
+}
+
+// "rename" is std::fs::rename outside of tests. Directories it creates and a copy made
+// across filesystems get the torrent's file_mode and file_group.
+fn move_file_with_rename(
+    from: &Path,
+    to: &Path,
+    perms: FilePermissions,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> anyhow::Result<()> {
+    if let Some(dir) = to.parent() {
+        perms.create_dir_all(dir)?;
+    }
+    if let Err(e) = rename(from, to) {
+        // Most likely a different filesystem. Copy next to the target first, so that the
+        // final name only ever shows up complete.
+        debug!(?from, ?to, error=?e, "rename failed, copying");
+        let tmp = with_incomplete_suffix(to, ".moving");
+        std::fs::copy(from, &tmp).with_context(|| format!("error copying {from:?} to {tmp:?}"))?;
+        perms.apply_to_file(&tmp)?;
+        std::fs::rename(&tmp, to).with_context(|| format!("error renaming {tmp:?} to {to:?}"))?;
+        std::fs::remove_file(from).with_context(|| format!("error removing {from:?}"))?;
+    }
+    Ok(())
+}
+
 pub(crate) struct TorrentStateLocked {
     // What chunks we have and need.
     // If this is None, the torrent was paused, and this live state is useless, and needs to be dropped.
@@ -143,6 +252,13 @@ pub(crate) struct TorrentStateLocked {
 
     // If this is None, then it was already used
     fatal_errors_tx: Option<tokio::sync::oneshot::Sender<anyhow::Error>>,
+
+    // Pieces that failed verification, and the peers that sent them.
+    failed_pieces: HashMap<ValidPieceIndex, HashSet<SocketAddr>>,
+    poisoned_pieces: Vec<PoisonedPiece>,
+
+    // Playback deadlines of pieces, see deadlines.rs.
+    deadlines: HashMap<ValidPieceIndex, Instant>,
 }
 
 impl TorrentStateLocked {
@@ -157,6 +273,26 @@ impl TorrentStateLocked {
             .as_mut()
             .context("chunk tracker empty, torrent was paused")
     }
+
+    // Returns the piece if it just became poisoned.
+    fn record_failed_piece(
+        &mut self,
+        piece: ValidPieceIndex,
+        peer: SocketAddr,
+        threshold: usize,
+    ) -> Option<PoisonedPiece> {
+        let peers = self.failed_pieces.entry(piece).or_default();
+        peers.insert(peer);
+        if peers.len() < threshold || self.poisoned_pieces.iter().any(|p| p.piece == piece.get()) {
+            return None;
+        }
+        let poisoned = PoisonedPiece {
+            piece: piece.get(),
+            peers: peers.iter().copied().collect(),
+        };
+        self.poisoned_pieces.push(poisoned.clone());
+        Some(poisoned)
+    }
 }
 
 #[derive(Default)]
@@ -171,7 +307,12 @@ pub struct TorrentStateLive {
     locked: RwLock<TorrentStateLocked>,
 
     files: Vec<Arc<Mutex<File>>>,
-    filenames: Vec<PathBuf>,
+    // Where the files are on disk. Can change from the incomplete name to the final one.
+    filenames: RwLock<Vec<PathBuf>>,
+    // One set of files per mirror path, see mirrors.rs.
+    mirror_files: MirrorFiles,
+    // The pieces all mirrors are known to have.
+    mirrored_pieces: Mutex<BF>,
 
     initially_needed_bytes: u64,
     total_selected_bytes: u64,
@@ -186,9 +327,32 @@ pub struct TorrentStateLive {
     peer_queue_tx: UnboundedSender<SocketAddr>,
 
     finished_notify: Notify,
+    // See progress_thresholds.rs.
+    pause_requested: Notify,
 
     down_speed_estimator: SpeedEstimator,
     up_speed_estimator: SpeedEstimator,
+    disk_read_speed_estimator: SpeedEstimator,
+    disk_write_speed_estimator: SpeedEstimator,
+
+    // Pieces being downloaded with verify_before_write or verify_from_memory, see
+    // piece_buffers.rs.
+    piece_buffers: PieceBuffers,
+    // See slow_peers.rs.
+    peer_ranking: Mutex<Option<Arc<PeerRanking>>>,
+    // When the torrent went live or last verified a piece, for detecting stalls.
+    last_piece_verified_at: Mutex<Instant>,
+    // The last PEER_EVENTS_HISTORY peer disconnects.
+    peer_events: Mutex<VecDeque<PeerEvent>>,
+    // See verification.rs. Only used with VerificationMode::LogOnly.
+    chunk_sources: Mutex<HashMap<ValidPieceIndex, Vec<ChunkSource>>>,
+    verification_failures: Mutex<VecDeque<VerificationFailure>>,
+    piece_latency: Mutex<PieceLatencyHistogram>,
+    // Keyed by tracker URL.
+    tracker_stats: Mutex<BTreeMap<String, TrackerStats>>,
+    announce_history: Mutex<VecDeque<AnnounceRecord>>,
+    // The peers we sent an unchoke to, and the ones waiting for it, see upload_slots.rs.
+    upload_slots: Mutex<upload_slots::UploadSlots>,
     cancellation_token: CancellationToken,
 }
 
@@ -202,6 +366,8 @@ impl TorrentStateLive {
 
         let down_speed_estimator = SpeedEstimator::new(5);
         let up_speed_estimator = SpeedEstimator::new(5);
+        let disk_read_speed_estimator = SpeedEstimator::new(5);
+        let disk_write_speed_estimator = SpeedEstimator::new(5);
 
         let have_bytes = paused.have_bytes;
         let needed_bytes = paused.needed_bytes;
@@ -215,9 +381,14 @@ impl TorrentStateLive {
                 chunks: Some(paused.chunk_tracker),
                 inflight_pieces: Default::default(),
                 fatal_errors_tx: Some(fatal_errors_tx),
+                failed_pieces: Default::default(),
+                poisoned_pieces: Default::default(),
+                deadlines: Default::default(),
             }),
             files: paused.files,
-            filenames: paused.filenames,
+            filenames: RwLock::new(paused.filenames),
+            mirror_files: paused.mirror_files,
+            mirrored_pieces: Mutex::new(BF::repeat(false, lengths.total_pieces() as usize)),
             stats: AtomicStats {
                 have_bytes: AtomicU64::new(have_bytes),
                 ..Default::default()
@@ -225,11 +396,24 @@ impl TorrentStateLive {
             initially_needed_bytes: needed_bytes,
             lengths,
             total_selected_bytes,
-            peer_semaphore: Arc::new(Semaphore::new(128)),
+            peer_semaphore: Arc::new(Semaphore::new(MAX_LIVE_PEERS)),
             peer_queue_tx,
             finished_notify: Notify::new(),
+            pause_requested: Notify::new(),
             down_speed_estimator,
             up_speed_estimator,
+            disk_read_speed_estimator,
+            disk_write_speed_estimator,
+            piece_buffers: Default::default(),
+            peer_ranking: Default::default(),
+            last_piece_verified_at: Mutex::new(Instant::now()),
+            peer_events: Default::default(),
+            chunk_sources: Default::default(),
+            verification_failures: Default::default(),
+            piece_latency: Default::default(),
+            tracker_stats: Mutex::new(initial_tracker_stats(paused.info.trackers.iter())),
+            announce_history: Default::default(),
+            upload_slots: Default::default(),
             cancellation_token,
         });
 
@@ -240,9 +424,17 @@ impl TorrentStateLive {
             );
         }
 
+        for (idx, seed) in state.meta.http_seeds.iter().enumerate() {
+            state.spawn(
+                error_span!(parent: state.meta.span.clone(), "http_seed", url = seed.to_string()),
+                state.clone().task_http_seed(idx, seed.clone()),
+            );
+        }
+
         state.spawn(
             error_span!(parent: state.meta.span.clone(), "speed_estimator_updater"),
             {
+                let sample_interval = state.meta.options.power_profile.stats_sample_interval();
                 let state = Arc::downgrade(&state);
                 async move {
                     loop {
@@ -264,16 +456,78 @@ impl TorrentStateLive {
                         state
                             .up_speed_estimator
                             .add_snapshot(stats.uploaded_bytes, None, now);
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        state.disk_read_speed_estimator.add_snapshot(
+                            stats.disk_read_bytes,
+                            None,
+                            now,
+                        );
+                        state.disk_write_speed_estimator.add_snapshot(
+                            stats.disk_written_bytes,
+                            None,
+                            now,
+                        );
+                        tokio::time::sleep(sample_interval).await;
                     }
                 }
             },
         );
 
+        if !state.mirror_files.is_empty() {
+            state.spawn(
+                error_span!(parent: state.meta.span.clone(), "mirror_backfill"),
+                state.clone().task_backfill_mirrors(),
+            );
+        }
+
+        state.spawn(
+            error_span!(parent: state.meta.span.clone(), "upload_slot_rotation"),
+            state.clone().task_rotate_upload_slots(),
+        );
+
         state.spawn(
             error_span!(parent: state.meta.span.clone(), "peer_adder"),
             state.clone().task_peer_adder(peer_queue_rx),
         );
+
+        let missing_files_interval = state.meta.options.missing_files_check_interval;
+        if !missing_files_interval.is_zero() {
+            state.spawn(
+                error_span!(parent: state.meta.span.clone(), "missing_files_watcher"),
+                state
+                    .clone()
+                    .task_missing_files_watcher(missing_files_interval),
+            );
+        }
+
+        if let FsyncPolicy::Interval(interval) = state.meta.options.fsync_policy {
+            // Finished torrents are reopened read-only, nothing gets written to them anymore.
+            if !state.is_finished() {
+                let weak = Arc::downgrade(&state);
+                state.spawn(
+                    error_span!(parent: state.meta.span.clone(), "periodic_fsync"),
+                    sync_every(interval, move || {
+                        let state = match weak.upgrade() {
+                            Some(state) => state,
+                            None => return Ok(false),
+                        };
+                        // Sync once more after finishing, for the last pieces.
+                        let finished = state.is_finished();
+                        let sync_result = state
+                            .meta
+                            .spawner
+                            .spawn_block_in_place(|| state.file_ops().sync_all());
+                        match sync_result {
+                            Ok(()) => {}
+                            Err(e) if is_read_only_fs_error(&e) => {
+                                return state.on_fatal_error(e).map(|()| false);
+                            }
+                            Err(e) => warn!("error syncing torrent files: {:#}", e),
+                        }
+                        Ok(!finished)
+                    }),
+                );
+            }
+        }
         state
     }
 
@@ -293,12 +547,28 @@ impl TorrentStateLive {
         &self.up_speed_estimator
     }
 
-    async fn tracker_one_request(&self, tracker_url: Url) -> anyhow::Result<u64> {
-        let response: reqwest::Response = reqwest::get(tracker_url).await?;
+    pub fn disk_read_speed_estimator(&self) -> &SpeedEstimator {
+        &self.disk_read_speed_estimator
+    }
+
+    pub fn disk_write_speed_estimator(&self) -> &SpeedEstimator {
+        &self.disk_write_speed_estimator
+    }
+
+    // Returns the announce interval and min interval, the tracker id if the tracker sent
+    // one, and the URL the response came from after redirects.
+    async fn tracker_one_request(&self, tracker_url: Url) -> anyhow::Result<TrackerAnnounce> {
+        let (response, final_url) = tracker_get(
+            &self.meta.options.tracker_client,
+            tracker_url,
+            self.meta.options.max_tracker_redirects,
+        )
+        .await?;
         if !response.status().is_success() {
             anyhow::bail!("tracker responded with {:?}", response.status());
         }
-        let bytes = response.bytes().await?;
+        let bytes =
+            read_tracker_response(response, self.meta.options.max_tracker_response_size).await?;
         if let Ok(error) = from_bytes::<TrackerError>(&bytes) {
             anyhow::bail!(
                 "tracker returned failure. Failure reason: {}",
@@ -307,18 +577,52 @@ impl TorrentStateLive {
         };
         let response = from_bytes::<TrackerResponse>(&bytes)?;
 
+        let mut peers = 0;
         for peer in response.peers.iter_sockaddrs() {
-            self.add_peer_if_not_seen(peer)?;
+            peers += 1;
+            self.add_peer_if_not_seen(peer, PeerSource::Tracker)?;
         }
-        Ok(response.interval)
+        Ok(TrackerAnnounce {
+            interval: response.interval,
+            min_interval: response.min_interval,
+            tracker_id: response.tracker_id_string(),
+            final_url,
+            seeders: response.complete,
+            leechers: response.incomplete,
+            peers,
+        })
     }
 
-    async fn task_single_tracker_monitor(
-        self: Arc<Self>,
-        mut tracker_url: Url,
-    ) -> anyhow::Result<()> {
+    async fn task_single_tracker_monitor(self: Arc<Self>, announce_url: Url) -> anyhow::Result<()> {
+        let stats_key = announce_url.to_string();
         let mut event = Some(TrackerRequestEvent::Started);
+        // Trackers may send a "tracker id", which they expect to be echoed back in
+        // subsequent announces.
+        let mut trackerid: Option<String> = None;
         loop {
+            self.wait_while_tracker_dead(&announce_url).await;
+            if let Some(announce_enabled) = self.meta.options.announce_enabled.as_ref() {
+                announce_enabled
+                    .clone()
+                    .wait_for(|enabled| *enabled)
+                    .await
+                    .context("announce_enabled sender dropped")?;
+            }
+
+            // Where the tracker redirected to before, if it did.
+            let redirected = self
+                .meta
+                .tracker_redirects
+                .read()
+                .get(&announce_url)
+                .cloned();
+            let mut tracker_url = redirected.clone().unwrap_or_else(|| announce_url.clone());
+            if let Some(scheduler) = self.meta.options.tracker_host_scheduler.as_ref() {
+                scheduler
+                    .wait_turn(tracker_url.host_str().unwrap_or_default())
+                    .await;
+            }
+
             let request = TrackerRequest {
                 info_hash: self.info_hash(),
                 peer_id: self.peer_id(),
@@ -329,38 +633,82 @@ impl TorrentStateLive {
                 compact: true,
                 no_peer_id: false,
                 event,
-                ip: None,
+                ip: self.meta.options.announce_ip,
                 numwant: None,
                 key: None,
-                trackerid: None,
+                trackerid: trackerid.clone(),
             };
 
             let request_query = request.as_querystring();
             tracker_url.set_query(Some(&request_query));
 
             match self.tracker_one_request(tracker_url.clone()).await {
-                Ok(interval) => {
+                Ok(announce) => {
+                    let mut final_url = announce.final_url.clone();
+                    final_url.set_query(None);
+                    let mut requested_url = tracker_url.clone();
+                    requested_url.set_query(None);
+                    if final_url != requested_url {
+                        info!(
+                            from = %announce_url,
+                            to = %final_url,
+                            "tracker redirected, announcing there from now on"
+                        );
+                        self.meta
+                            .tracker_redirects
+                            .write()
+                            .insert(announce_url.clone(), final_url);
+                    }
                     event = None;
-                    let interval = self
-                        .meta
-                        .options
-                        .force_tracker_interval
-                        .unwrap_or_else(|| Duration::from_secs(interval));
+                    if announce.tracker_id.is_some() {
+                        trackerid = announce.tracker_id.clone();
+                    }
+                    let interval =
+                        self.next_announce_interval(announce.interval, announce.min_interval);
+                    self.record_announce(&stats_key, &request, Ok((&announce, interval)));
                     debug!(
                         "sleeping for {:?} after calling tracker {}",
                         interval,
                         tracker_url.host().unwrap()
                     );
-                    tokio::time::sleep(interval).await;
+                    self.sleep_until_next_announce(&stats_key, interval).await;
                 }
                 Err(e) => {
                     debug!("error calling the tracker {}: {:#}", tracker_url, e);
-                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    if redirected.is_some() {
+                        // The tracker may have moved back.
+                        self.meta.tracker_redirects.write().remove(&announce_url);
+                    }
+                    self.record_announce(&stats_key, &request, Err(&e));
+                    if self.check_tracker_dead(&announce_url) {
+                        continue;
+                    }
+                    self.sleep_until_next_announce(&stats_key, Duration::from_secs(60))
+                        .await;
                 }
             };
         }
     }
 
+    // Sleeps for the (jittered) interval, or until the network changes, in which case
+    // the tracker needs to learn our new address right away. The deadline is recorded for
+    // TrackerStats::next_announce_in.
+    async fn sleep_until_next_announce(&self, stats_key: &str, interval: Duration) {
+        let deadline = self.schedule_next_announce(stats_key, jitter(interval, DEFAULT_JITTER));
+        let sleep = tokio::time::sleep_until(deadline.into());
+        let mut network_changed = match self.meta.options.network_changed.clone() {
+            Some(rx) => rx,
+            None => return sleep.await,
+        };
+        network_changed.borrow_and_update();
+        tokio::select! {
+            _ = sleep => {},
+            Ok(()) = network_changed.changed() => {
+                debug!("network changed, re-announcing");
+            }
+        }
+    }
+
     pub(crate) fn add_incoming_peer(
         self: &Arc<Self>,
         checked_peer: CheckedIncomingConnection,
@@ -368,12 +716,18 @@ impl TorrentStateLive {
         use dashmap::mapref::entry::Entry;
         let (tx, rx) = unbounded_channel();
         let permit = match self.peer_semaphore.clone().try_acquire_owned() {
-            Ok(permit) => permit,
-            Err(_) => {
+            Ok(permit) if !self.peer_limit_reached() => permit,
+            _ => {
                 warn!("limit of live peers reached, dropping incoming peer");
                 self.peers.with_peer(checked_peer.addr, |p| {
                     atomic_inc(&p.stats.counters.incoming_connections);
                 });
+                self.record_peer_disconnect(
+                    checked_peer.addr,
+                    None,
+                    false,
+                    Some(PeerDisconnectReason::PeerLimit),
+                );
                 return Ok(());
             }
         };
@@ -429,20 +783,19 @@ impl TorrentStateLive {
             addr: checked_peer.addr,
             on_bitfield_notify: Default::default(),
             unchoke_notify: Default::default(),
+            inflight_drained_notify: Default::default(),
             locked: RwLock::new(PeerHandlerLocked {
                 i_am_choked: true,
                 previously_requested_pieces: BF::new(),
+                reserved_first_piece: false,
+                disconnect_reason: None,
             }),
             requests_sem: Semaphore::new(0),
             state: self.clone(),
             tx,
             counters,
         };
-        let options = PeerConnectionOptions {
-            connect_timeout: self.meta.options.peer_connect_timeout,
-            read_write_timeout: self.meta.options.peer_read_write_timeout,
-            ..Default::default()
-        };
+        let options = self.meta.peer_connection_options();
         let peer_connection = PeerConnection::new(
             checked_peer.addr,
             self.meta.info_hash,
@@ -493,20 +846,19 @@ impl TorrentStateLive {
             addr,
             on_bitfield_notify: Default::default(),
             unchoke_notify: Default::default(),
+            inflight_drained_notify: Default::default(),
             locked: RwLock::new(PeerHandlerLocked {
                 i_am_choked: true,
                 previously_requested_pieces: BF::new(),
+                reserved_first_piece: false,
+                disconnect_reason: None,
             }),
             requests_sem: Semaphore::new(0),
             state: state.clone(),
             tx,
             counters,
         };
-        let options = PeerConnectionOptions {
-            connect_timeout: state.meta.options.peer_connect_timeout,
-            read_write_timeout: state.meta.options.peer_read_write_timeout,
-            ..Default::default()
-        };
+        let options = state.meta.peer_connection_options();
         let peer_connection = PeerConnection::new(
             addr,
             state.meta.info_hash,
@@ -545,15 +897,37 @@ impl TorrentStateLive {
         mut peer_queue_rx: UnboundedReceiver<SocketAddr>,
     ) -> anyhow::Result<()> {
         let state = self;
+        let mut queue = PeerDialQueue::new(&state.meta.options.peer_source_priority);
+        let enqueue = |queue: &mut PeerDialQueue, addr: SocketAddr| {
+            let source = state.peers.with_peer(addr, |p| p.source);
+            queue.push(addr, source.unwrap_or(PeerSource::Manual));
+        };
         loop {
-            let addr = peer_queue_rx.recv().await.context("torrent closed")?;
+            if queue.is_empty() {
+                let addr = peer_queue_rx.recv().await.context("torrent closed")?;
+                enqueue(&mut queue, addr);
+            }
+
+            loop {
+                let changed = state.meta.peer_slots_changed.notified();
+                if !state.peer_limit_reached() {
+                    break;
+                }
+                changed.await;
+            }
+            let permit = state.peer_semaphore.clone().acquire_owned().await?;
+            state.meta.options.connect_ramp.wait().await;
+            // Peers that came in while waiting for a slot compete for it by source.
+            while let Ok(addr) = peer_queue_rx.try_recv() {
+                enqueue(&mut queue, addr);
+            }
+            let addr = queue.pop().context("bug: empty peer dial queue")?;
             if state.is_finished() {
                 debug!("ignoring peer {} as we are finished", addr);
                 state.peers.mark_peer_not_needed(addr);
                 continue;
             }
 
-            let permit = state.peer_semaphore.clone().acquire_owned().await?;
             state.spawn(
                 error_span!(parent: state.meta.span.clone(), "manage_peer", peer = addr.to_string()),
                 state.clone().task_manage_outgoing_peer(addr, permit),
@@ -576,6 +950,7 @@ impl TorrentStateLive {
     }
     pub(crate) fn file_ops(&self) -> FileOps<'_, Sha1> {
         FileOps::new(&self.meta.info, &self.files, &self.lengths)
+            .with_io_counters(&self.stats.disk_io)
     }
     pub fn initially_needed(&self) -> u64 {
         self.initially_needed_bytes
@@ -608,6 +983,9 @@ impl TorrentStateLive {
     pub fn get_uploaded_bytes(&self) -> u64 {
         self.stats.uploaded_bytes.load(Ordering::Relaxed)
     }
+    pub fn get_web_seed_bytes(&self) -> u64 {
+        self.stats.web_seed_bytes.load(Ordering::Relaxed)
+    }
     pub fn get_downloaded_bytes(&self) -> u64 {
         self.stats
             .downloaded_and_checked_bytes
@@ -683,8 +1061,24 @@ impl TorrentStateLive {
         );
     }
 
-    pub(crate) fn add_peer_if_not_seen(&self, addr: SocketAddr) -> anyhow::Result<bool> {
-        match self.peers.add_if_not_seen(addr) {
+    // Nothing is uploaded while the torrent is locked. With upload_subnets set, peers
+    // outside of them are never unchoked.
+    fn may_upload_to(&self, addr: SocketAddr) -> bool {
+        if self.meta.locked.load(Ordering::Relaxed) {
+            return false;
+        }
+        match self.meta.options.upload_subnets.as_deref() {
+            Some(subnets) => ip_in_subnets(addr.ip(), subnets),
+            None => true,
+        }
+    }
+
+    pub(crate) fn add_peer_if_not_seen(
+        &self,
+        addr: SocketAddr,
+        source: PeerSource,
+    ) -> anyhow::Result<bool> {
+        match self.peers.add_if_not_seen(addr, source) {
             Some(handle) => handle,
             None => return Ok(false),
         };
@@ -693,31 +1087,323 @@ impl TorrentStateLive {
         Ok(true)
     }
 
+    // Retry the peers that are waiting out their reconnect backoff right away, e.g. after
+    // the host woke from sleep and all connections died at once.
+    pub(crate) fn reconnect_dead_peers(&self) -> anyhow::Result<usize> {
+        let requeued = self.peers.requeue_dead_peers();
+        for handle in requeued.iter() {
+            self.peer_queue_tx.send(*handle)?;
+        }
+        Ok(requeued.len())
+    }
+
+    /// How long the recently verified pieces took, from their first request.
+    pub fn piece_latency(&self) -> PieceLatencyStats {
+        self.piece_latency.lock().stats()
+    }
+
     pub fn stats_snapshot(&self) -> StatsSnapshot {
         use Ordering::*;
         let downloaded_bytes = self.stats.downloaded_and_checked_bytes.load(Relaxed);
+        let downloaded_pieces = self.stats.downloaded_and_checked_pieces.load(Relaxed);
+        let web_seed_bytes = self.stats.web_seed_bytes.load(Relaxed);
+        let web_seed_pieces = self.stats.web_seed_pieces.load(Relaxed);
         StatsSnapshot {
             downloaded_and_checked_bytes: downloaded_bytes,
-            downloaded_and_checked_pieces: self.stats.downloaded_and_checked_pieces.load(Relaxed),
+            downloaded_and_checked_pieces: downloaded_pieces,
             fetched_bytes: self.stats.fetched_bytes.load(Relaxed),
             uploaded_bytes: self.stats.uploaded_bytes.load(Relaxed),
             total_piece_download_ms: self.stats.total_piece_download_ms.load(Relaxed),
+            disk_read_bytes: self.stats.disk_io.read_bytes.load(Relaxed),
+            disk_written_bytes: self.stats.disk_io.written_bytes.load(Relaxed),
+            endgame_duplicate_requests: self.stats.endgame_duplicate_requests.load(Relaxed),
+            endgame_duplicate_bytes: self.stats.endgame_duplicate_bytes.load(Relaxed),
+            deadline_misses: self.stats.deadline_misses.load(Relaxed),
+            web_seed_bytes,
+            web_seed_pieces,
+            peer_bytes: downloaded_bytes.saturating_sub(web_seed_bytes),
+            peer_pieces: downloaded_pieces.saturating_sub(web_seed_pieces),
             peer_stats: self.peers.stats(),
         }
     }
 
     pub fn per_peer_stats_snapshot(&self, filter: PeerStatsFilter) -> PeerStatsSnapshot {
-        PeerStatsSnapshot {
-            peers: self
-                .peers
+        let held = self
+            .lock_read("per_peer_stats_snapshot")
+            .held_pieces_by_peer();
+        PeerStatsSnapshot::new(
+            self.peers
                 .states
                 .iter()
-                .filter(|e| filter.state.matches(e.value().state.get()))
-                .map(|e| (e.key().to_string(), e.value().into()))
-                .collect(),
+                .filter(|e| filter.matches(e.value()))
+                .map(|e| {
+                    let mut stats = PeerStats::from(e.value());
+                    stats.held_pieces = held.get(e.key()).copied().unwrap_or_default();
+                    (e.key().to_string(), stats)
+                }),
+        )
+    }
+
+    /// Connected peers, i.e. the ones holding a connection slot.
+    pub fn live_peer_connections(&self) -> usize {
+        MAX_LIVE_PEERS.saturating_sub(self.peer_semaphore.available_permits())
+    }
+
+    // (live peers, live peers choking us).
+    pub(crate) fn choking_peers(&self) -> (u32, u32) {
+        let mut live_peers = 0;
+        let mut choking = 0;
+        for peer in self.peers.states.iter() {
+            let live = match peer.value().state.get_live() {
+                Some(live) => live,
+                None => continue,
+            };
+            live_peers += 1;
+            if live.peer_choking {
+                choking += 1;
+            }
+        }
+        (live_peers, choking)
+    }
+
+    // See crate::SessionOptions::peer_connection_budget.
+    fn peer_limit_reached(&self) -> bool {
+        self.meta
+            .peer_limit()
+            .map(|limit| self.live_peer_connections() >= limit)
+            .unwrap_or(false)
+    }
+
+    /// If the torrent has live peers but hasn't verified a piece within the stall timeout,
+    /// returns how many of the needed pieces none of the live peers have. Unlike paused or
+    /// errored torrents, a stalled one keeps running and recovers if a seeder shows up.
+    pub fn stalled_unavailable_pieces(&self) -> Option<u32> {
+        if self.is_finished()
+            || self.last_piece_verified_at.lock().elapsed() < self.meta.options.stall_timeout
+        {
+            return None;
+        }
+
+        // Release the lock before looking at peers, same as picker_plan().
+        let needed: Vec<usize> = {
+            let g = self.lock_read("stalled_unavailable_pieces");
+            g.get_chunks().ok()?.iter_needed_pieces().collect()
+        };
+
+        let mut available = vec![false; needed.len()];
+        let mut live_peers = 0;
+        for peer in self.peers.states.iter() {
+            let live = match peer.value().state.get_live() {
+                Some(live) => live,
+                None => continue,
+            };
+            live_peers += 1;
+            for (has, piece) in available.iter_mut().zip(needed.iter()) {
+                if !*has && live.bitfield.get(*piece).map(|v| *v) == Some(true) {
+                    *has = true;
+                }
+            }
+        }
+        if live_peers == 0 {
+            // Nobody to download from is a different problem, the peer count shows that.
+            return None;
+        }
+
+        Some(available.iter().filter(|a| !**a).count() as u32)
+    }
+
+    // Moves the files completed by this piece from their incomplete path to the final one.
+    fn move_completed_files(&self, piece: ValidPieceIndex) -> anyhow::Result<()> {
+        if !self.meta.has_incomplete_paths() {
+            return Ok(());
+        }
+        let piece_start = self.lengths.piece_offset(piece);
+        let piece_end = piece_start + self.lengths.piece_length(piece) as u64;
+        let final_filenames = self.meta.file_paths()?;
+
+        let mut file_start = 0u64;
+        for (idx, len) in self.meta.info.iter_file_lengths()?.enumerate() {
+            let offset = file_start;
+            file_start += len;
+            if offset + len <= piece_start || offset >= piece_end {
+                continue;
+            }
+            let current = self.filenames.read()[idx].clone();
+            let target = &final_filenames[idx];
+            if current == *target {
+                continue;
+            }
+            let complete = {
+                let g = self.lock_read("move_completed_files");
+                is_file_complete(
+                    &self.lengths,
+                    g.get_chunks()?.get_have_pieces(),
+                    offset,
+                    len,
+                )
+            };
+            if !complete {
+                continue;
+            }
+            // Holding the lock while moving, so that the file never looks missing.
+            let mut filenames = self.filenames.write();
+            move_open_file(
+                &self.files[idx],
+                &current,
+                target,
+                self.meta.file_permissions(),
+            )?;
+            filenames[idx] = target.clone();
+            drop(filenames);
+            debug!("file {:?} complete, moved from {:?}", target, current);
+            if let Some(dir) = &self.meta.options.incomplete_dir {
+                remove_empty_dirs(dir, &[current]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Bookkeeping once a downloaded piece was checked and written, whoever it came from.
+    fn on_piece_verified(
+        &self,
+        piece: ValidPieceIndex,
+        download_time: Duration,
+    ) -> anyhow::Result<()> {
+        {
+            let mut g = self.lock_write("mark_piece_downloaded");
+            g.get_chunks_mut()?.mark_piece_downloaded(piece);
+            g.deadlines.remove(&piece);
+        }
+        self.forget_chunk_sources(piece);
+        self.piece_latency.lock().record(download_time);
+
+        // Global piece counters.
+        let piece_len = self.lengths.piece_length(piece) as u64;
+        self.stats
+            .downloaded_and_checked_bytes
+            // This counter is used to compute "is_finished", so using
+            // stronger ordering.
+            .fetch_add(piece_len, Ordering::Release);
+        self.stats
+            .downloaded_and_checked_pieces
+            // This counter is used to compute "is_finished", so using
+            // stronger ordering.
+            .fetch_add(1, Ordering::Release);
+        self.stats
+            .have_bytes
+            .fetch_add(piece_len, Ordering::Relaxed);
+        self.stats
+            .total_piece_download_ms
+            .fetch_add(download_time.as_millis() as u64, Ordering::Relaxed);
+        *self.last_piece_verified_at.lock() = Instant::now();
+
+        // The moves and syncs below failing is worked around, unless the filesystem went
+        // read-only: then the piece just written is likely not on disk either.
+        if let Err(e) = self.move_completed_files(piece) {
+            if is_read_only_fs_error(&e) {
+                return self.on_fatal_error(e);
+            }
+            warn!("error moving completed files: {:#}", e);
+        }
+
+        debug!("piece={} successfully downloaded and verified", piece);
+        self.copy_piece_to_mirrors(piece);
+
+        let fsync_policy = self.meta.options.fsync_policy;
+        if fsync_policy == FsyncPolicy::OnPieceComplete {
+            if let Err(e) = self.file_ops().sync_piece(piece) {
+                if is_read_only_fs_error(&e) {
+                    return self.on_fatal_error(e);
+                }
+                warn!("error syncing piece={} to disk: {:#}", piece, e);
+            }
+        }
+
+        if self.is_finished() {
+            info!("torrent finished downloading");
+            if fsync_policy == FsyncPolicy::OnTorrentComplete {
+                if let Err(e) = self.file_ops().sync_all() {
+                    if is_read_only_fs_error(&e) {
+                        return self.on_fatal_error(e);
+                    }
+                    warn!("error syncing torrent files to disk: {:#}", e);
+                }
+            }
+            self.finished_notify.notify_waiters();
+            self.meta.notify_state_changed();
+            self.disconnect_all_peers_that_have_full_torrent();
+            self.reopen_read_only()?;
+        }
+
+        self.maybe_transmit_haves(piece);
+        self.meta.pieces_changed.notify_waiters();
+        self.check_progress_thresholds();
+        Ok(())
+    }
+
+    fn disconnect_all_peers_that_have_full_torrent(&self) {
+        for mut pe in self.peers.states.iter_mut() {
+            if let PeerState::Live(l) = pe.value().state.get() {
+                if l.has_full_torrent(self.lengths.total_pieces() as usize) {
+                    let prev = pe.value_mut().state.set_not_needed(&self.peers.stats);
+                    let _ = prev
+                        .take_live_no_counters()
+                        .unwrap()
+                        .tx
+                        .send(WriterRequest::Disconnect);
+                }
+            }
         }
     }
 
+    fn reopen_read_only(&self) -> anyhow::Result<()> {
+        // Lock exclusive just in case to ensure in-flight operations finish.??
+        let _guard = self.lock_write("reopen_read_only");
+
+        let filenames = self.filenames.read();
+        for (file, filename) in self.files.iter().zip(filenames.iter()) {
+            let mut g = file.lock();
+            // this should close the original file
+            // putting in a block just in case to guarantee drop.
+            {
+                *g = dummy_file()?;
+            }
+            *g = std::fs::OpenOptions::new()
+                .read(true)
+                .open(filename)
+                .with_context(|| format!("error re-opening {:?} readonly", filename))?;
+            debug!("reopened {:?} read-only", filename);
+        }
+        info!("reopened all torrent files in read-only mode");
+        Ok(())
+    }
+
+    /// Pieces that failed verification from too many distinct peers.
+    pub fn poisoned_pieces(&self) -> Vec<PoisonedPiece> {
+        self.locked.read().poisoned_pieces.clone()
+    }
+
+    fn on_piece_poisoned(&self, poisoned: PoisonedPiece) -> anyhow::Result<()> {
+        warn!(
+            "piece={} failed verification from {} distinct peers, it might be poisoned: {:?}",
+            poisoned.piece,
+            poisoned.peers.len(),
+            poisoned.peers
+        );
+        let _ = self
+            .meta
+            .events
+            .send(TorrentEvent::PiecePoisoned(poisoned.clone()));
+        if self.meta.options.pause_on_poisoned_piece {
+            return self.on_fatal_error(anyhow::anyhow!(
+                "piece {} failed verification from peers {:?}",
+                poisoned.piece,
+                poisoned.peers
+            ));
+        }
+        Ok(())
+    }
+
     pub async fn wait_until_completed(&self) {
         if self.is_finished() {
             return;
@@ -730,18 +1416,25 @@ impl TorrentStateLive {
 
         let mut g = self.locked.write();
 
-        let files = self
-            .files
+        let take_files = |files: &[Arc<Mutex<File>>]| {
+            files
+                .iter()
+                .map(|f| {
+                    let mut f = f.lock();
+                    let dummy = dummy_file()?;
+                    let f = std::mem::replace(&mut *f, dummy);
+                    Ok::<_, anyhow::Error>(Arc::new(Mutex::new(f)))
+                })
+                .try_collect()
+        };
+        let files = take_files(&self.files)?;
+        let mirror_files = self
+            .mirror_files
             .iter()
-            .map(|f| {
-                let mut f = f.lock();
-                let dummy = dummy_file()?;
-                let f = std::mem::replace(&mut *f, dummy);
-                Ok::<_, anyhow::Error>(Arc::new(Mutex::new(f)))
-            })
+            .map(|files| take_files(files))
             .try_collect()?;
 
-        let filenames = self.filenames.clone();
+        let filenames = self.filenames.read().clone();
 
         let mut chunk_tracker = g
             .chunks
@@ -758,6 +1451,7 @@ impl TorrentStateLive {
             info: self.meta.clone(),
             files,
             filenames,
+            mirror_files,
             chunk_tracker,
             have_bytes,
             needed_bytes,
@@ -784,6 +1478,12 @@ struct PeerHandlerLocked {
     // This is used to only request a piece from a peer once when stealing from others.
     // So that you don't steal then re-steal the same piece in a loop.
     pub previously_requested_pieces: BF,
+
+    // Whether a piece was reserved for this peer yet, see SessionOptions::first_piece_strategy.
+    pub reserved_first_piece: bool,
+
+    // Why we disconnected the peer ourselves, unless it's just not needed.
+    pub disconnect_reason: Option<PeerDisconnectReason>,
 }
 
 // All peer state that would never be used by other actors should pe put here.
@@ -805,6 +1505,9 @@ struct PeerHandler {
     // This is used to unpause after we were choked.
     unchoke_notify: Notify,
 
+    // Notified when the peer has delivered everything we asked it for.
+    inflight_drained_notify: Notify,
+
     // This is used to limit the number of chunk requests we send to a peer at a time.
     requests_sem: Semaphore,
 
@@ -839,12 +1542,18 @@ impl<'a> PeerConnectionHandler for &'a PeerHandler {
                 trace!("keepalive received");
             }
             Message::Have(h) => self.on_have(h),
-            Message::NotInterested => {
-                trace!("received \"not interested\", but we don't process it yet")
-            }
+            Message::NotInterested => self.on_peer_not_interested(),
             Message::Cancel(_) => {
                 trace!("received \"cancel\", but we don't process it yet")
             }
+            Message::HaveAll => self.on_have_all_or_none(true).context("on_have_all")?,
+            Message::HaveNone => self.on_have_all_or_none(false).context("on_have_none")?,
+            Message::RejectRequest(request) => self
+                .on_request_rejected(request)
+                .context("on_request_rejected")?,
+            Message::SuggestPiece(_) | Message::AllowedFast(_) => {
+                trace!("received {:?}, but we don't process it yet", message)
+            }
             message => {
                 warn!("received unsupported message {:?}, ignoring", message);
             }
@@ -862,8 +1571,13 @@ impl<'a> PeerConnectionHandler for &'a PeerHandler {
 
     fn on_handshake<B>(&self, handshake: Handshake<B>) -> anyhow::Result<()> {
         self.state.set_peer_live(self.addr, handshake);
-        self.tx
-            .send(WriterRequest::Message(MessageOwned::Unchoke))?;
+        // With a limit, only interested peers get a slot.
+        if self.state.meta.upload_slots().is_none() {
+            self.state.want_upload_slot(self.addr);
+        }
+        if !self.state.holds_upload_slot(self.addr) {
+            debug!("not unchoking, torrent is locked, peer is outside of upload subnets or not interested yet");
+        }
         Ok(())
     }
 
@@ -874,21 +1588,72 @@ impl<'a> PeerConnectionHandler for &'a PeerHandler {
             .fetch_add(bytes as u64, Ordering::Relaxed);
     }
 
+    fn upload_limiter(&self) -> Option<&RateLimiter> {
+        Some(&self.state.meta.options.bandwidth.upload)
+    }
+
     fn read_chunk(&self, chunk: &ChunkInfo, buf: &mut [u8]) -> anyhow::Result<()> {
-        self.state.file_ops().read_chunk(self.addr, chunk, buf)
+        let result = self.state.file_ops().read_chunk(self.addr, chunk, buf);
+        if let Err(e) = &result {
+            match self.state.read_chunk_from_mirrors(self.addr, chunk, buf) {
+                Ok(()) => {
+                    warn!("error reading chunk, sent it from a mirror: {:#}", e);
+                    return Ok(());
+                }
+                Err(mirror_error) => debug!("chunk not read from mirrors: {:#}", mirror_error),
+            }
+            self.state.check_missing_files();
+        }
+        result
     }
 
-    fn on_extended_handshake(&self, _: &ExtendedHandshake<ByteBuf>) -> anyhow::Result<()> {
+    fn on_extended_handshake(&self, h: &ExtendedHandshake<ByteBuf>) -> anyhow::Result<()> {
+        let extensions =
+            h.m.iter()
+                .map(|(name, id)| (String::from_utf8_lossy(name.as_ref()).into_owned(), *id))
+                .collect();
+        self.state
+            .peers
+            .with_live_mut(self.addr, "on_extended_handshake", |live| {
+                live.extensions = extensions;
+            });
         Ok(())
     }
 
     fn get_have_bytes(&self) -> u64 {
         self.state.get_approx_have_bytes()
     }
+
+    fn has_all_pieces(&self) -> bool {
+        let total = self.state.lengths.total_pieces() as usize;
+        self.state
+            .lock_read("has_all_pieces")
+            .get_chunks()
+            .map(|c| c.get_have_pieces()[..total].all())
+            .unwrap_or(false)
+    }
+
+    fn on_fast_extension(&self) {
+        self.state
+            .peers
+            .with_live_mut(self.addr, "on_fast_extension", |live| {
+                live.fast_extension = true
+            });
+    }
 }
 
 impl PeerHandler {
     fn on_peer_died(self, error: Option<anyhow::Error>) -> anyhow::Result<()> {
+        let state = self.state.clone();
+        let addr = self.addr;
+        let res = self.forget_live_peer(error);
+        // The peer isn't live anymore, so its upload slot can go to someone else.
+        state.leave_upload_slot(addr, true);
+        res
+    }
+
+    // Moves the peer out of the live state, re-queueing it if it died of an error.
+    fn forget_live_peer(self, error: Option<anyhow::Error>) -> anyhow::Result<()> {
         let peers = &self.state.peers;
         let pstats = &peers.stats;
         let handle = self.addr;
@@ -901,10 +1666,26 @@ impl PeerHandler {
         };
         let prev = pe.value_mut().state.take(pstats);
 
+        if matches!(prev, PeerState::Connecting(_) | PeerState::Live(_)) {
+            let was_live = matches!(prev, PeerState::Live(_));
+            let reason = self.locked.read().disconnect_reason;
+            self.state
+                .record_peer_disconnect(handle, error.as_ref(), was_live, reason);
+            if was_live {
+                self.state.meta.peer_slots_changed.notify_waiters();
+            }
+        }
+
         match prev {
             PeerState::Connecting(_) => {}
             PeerState::Live(live) => {
+                self.state
+                    .meta
+                    .options
+                    .request_limiter
+                    .release(live.inflight_requests.len());
                 let mut g = self.state.lock_write("mark_chunk_requests_canceled");
+                let mut pieces = HashSet::new();
                 for req in live.inflight_requests {
                     debug!(
                         "peer dead, marking chunk request cancelled, index={}, chunk={}",
@@ -913,6 +1694,15 @@ impl PeerHandler {
                     );
                     g.get_chunks_mut()?
                         .mark_chunk_request_cancelled(req.piece, req.chunk);
+                    pieces.insert(req.piece);
+                }
+                // Unless stolen, nobody downloads these anymore.
+                for piece in pieces {
+                    match g.inflight_pieces.get(&piece) {
+                        Some(InflightPiece { peer, .. }) if *peer == handle => {}
+                        _ => continue,
+                    }
+                    self.state.release_piece_buffer(&mut g, piece)?;
                 }
             }
             PeerState::NotNeeded => {
@@ -948,7 +1738,12 @@ impl PeerHandler {
 
         pe.value_mut().state.set(PeerState::Dead, pstats);
 
-        let backoff = pe.value_mut().stats.backoff.next_backoff();
+        let backoff = pe
+            .value_mut()
+            .stats
+            .backoff
+            .next_backoff()
+            .map(|dur| self.state.meta.options.power_profile.reconnect_backoff(dur));
 
         // Prevent deadlocks.
         drop(pe);
@@ -963,21 +1758,29 @@ impl PeerHandler {
                 ),
                 async move {
                     tokio::time::sleep(dur).await;
-                    self.state
+                    let requeue = self
+                        .state
                         .peers
                         .with_peer_mut(handle, "dead_to_queued", |peer| {
                             match peer.state.get() {
                                 PeerState::Dead => {
-                                    peer.state.set(PeerState::Queued, &self.state.peers.stats)
+                                    peer.state.set(PeerState::Queued, &self.state.peers.stats);
+                                    Ok(true)
                                 }
+                                // Already retried, see reconnect_dead_peers().
+                                PeerState::Queued
+                                | PeerState::Connecting(_)
+                                | PeerState::Live(_) => Ok(false),
                                 other => bail!(
                                     "peer is in unexpected state: {}. Expected dead",
                                     other.name()
                                 ),
-                            };
-                            Ok(())
+                            }
                         })
                         .context("bug: peer disappeared")??;
+                    if !requeue {
+                        return Ok(());
+                    }
                     self.state.peer_queue_tx.send(handle)?;
                     Ok::<_, anyhow::Error>(())
                 },
@@ -990,6 +1793,18 @@ impl PeerHandler {
     }
 
     fn reserve_next_needed_piece(&self) -> anyhow::Result<Option<ValidPieceIndex>> {
+        let first_piece_strategy = if self.locked.read().reserved_first_piece {
+            None
+        } else {
+            Some(self.state.meta.options.first_piece_strategy)
+        };
+        // Goes through all peers, so can't be done inside with_live_mut().
+        let availability = match first_piece_strategy {
+            Some(FirstPieceStrategy::Rarest) => self.state.piece_availability().0,
+            _ => Vec::new(),
+        };
+        // Goes through all peers too.
+        self.state.expire_deadlines()?;
         // TODO: locking one inside the other in different order results in deadlocks.
         self.state
             .peers
@@ -1001,13 +1816,34 @@ impl PeerHandler {
                 let mut g = self.state.lock_write("reserve_next_needed_piece");
 
                 let n = {
-                    let mut n_opt = None;
                     let bf = &live.bitfield;
-                    for n in g.get_chunks()?.iter_needed_pieces() {
-                        if bf.get(n).map(|v| *v) == Some(true) {
-                            n_opt = Some(n);
-                            break;
-                        }
+                    // Pieces with a playback deadline go first, wherever they are.
+                    let mut n_opt = g
+                        .next_deadline_piece(|n| bf.get(n).map(|v| *v) == Some(true))?
+                        .map(|n| n.get() as usize);
+                    // With contiguous storage, writes stay close together instead of spreading
+                    // over the file. Sequential mode does the same, so that data arrives mostly
+                    // in order.
+                    let in_order = self.state.meta.options.contiguous_storage
+                        || self.state.meta.sequential.load(Ordering::Relaxed);
+                    if n_opt.is_none() {
+                        let chunks = g.get_chunks()?;
+                        n_opt = pick_next_piece(
+                            chunks
+                                .iter_needed_pieces()
+                                .filter(|n| bf.get(*n).map(|v| *v) == Some(true)),
+                            in_order,
+                            first_piece_strategy,
+                            |n| {
+                                self.state
+                                    .lengths
+                                    .validate_piece_index(n as u32)
+                                    .map(|n| chunks.bytes_left(n))
+                                    .unwrap_or_default()
+                            },
+                            &availability,
+                            self.state.meta.options.rarest_first_randomness,
+                        );
                     }
 
                     let n_opt = match n_opt {
@@ -1025,15 +1861,57 @@ impl PeerHandler {
                     InflightPiece {
                         peer: self.addr,
                         started: Instant::now(),
+                        requested_from: 1,
                     },
                 );
                 g.get_chunks_mut()?.reserve_needed_piece(n);
+                self.locked.write().reserved_first_piece = true;
                 Ok(Some(n))
             })
             .transpose()
             .map(|r| r.flatten())
     }
 
+    // Take over an in-flight piece whose deadline is close, if we have it too. Unlike
+    // try_steal_old_slow_piece() this doesn't care how fast the other peer is, and allows
+    // one more duplicate than endgame does.
+    fn try_steal_urgent_piece(&self) -> Option<ValidPieceIndex> {
+        let max_duplicates = self
+            .state
+            .meta
+            .options
+            .endgame_max_duplicates
+            .saturating_add(1);
+        self.state
+            .peers
+            .with_live(self.addr, |live| {
+                let now = Instant::now();
+                let mut g = self.state.lock_write("try_steal_urgent_piece");
+                let urgent: Vec<ValidPieceIndex> = g
+                    .inflight_pieces
+                    .iter()
+                    .filter(|(_, r)| r.peer != self.addr)
+                    .filter(|(_, r)| r.requested_from < max_duplicates)
+                    .filter(|(p, _)| live.bitfield.get(p.get() as usize).map(|v| *v) == Some(true))
+                    .filter(|(p, _)| g.is_deadline_urgent(**p, now))
+                    .map(|(p, _)| *p)
+                    .collect();
+                let idx = urgent
+                    .into_iter()
+                    .min_by_key(|p| g.deadlines.get(p).copied())?;
+                let piece_req = g.inflight_pieces.get_mut(&idx)?;
+                debug!(
+                    "will steal piece {} from {}: its deadline is close",
+                    idx, piece_req.peer
+                );
+                piece_req.peer = self.addr;
+                piece_req.started = Instant::now();
+                piece_req.requested_from += 1;
+                Some(idx)
+            })
+            .flatten()
+    }
+
     /// Try to steal a piece from a slower peer. Threshold is
     /// "how many times is my average download speed faster to be able to steal".
     ///
@@ -1044,12 +1922,14 @@ impl PeerHandler {
             None => return None,
         };
 
+        let max_duplicates = self.state.meta.options.endgame_max_duplicates;
         let mut g = self.state.lock_write("try_steal_old_slow_piece");
         let (idx, elapsed, piece_req) = g
             .inflight_pieces
             .iter_mut()
             // don't steal from myself
             .filter(|(_, r)| r.peer != self.addr)
+            .filter(|(_, r)| r.requested_from < max_duplicates)
             .map(|(p, r)| (p, r.started.elapsed(), r))
             .max_by_key(|(_, e, _)| *e)?;
 
@@ -1061,12 +1941,26 @@ impl PeerHandler {
             );
             piece_req.peer = self.addr;
             piece_req.started = Instant::now();
+            piece_req.requested_from += 1;
             return Some(*idx);
         }
         None
     }
 
     fn on_download_request(&self, request: Request) -> anyhow::Result<()> {
+        let has_slot =
+            self.state.meta.upload_slots().is_none() || self.state.holds_upload_slot(self.addr);
+        if !self.state.may_upload_to(self.addr) || !has_slot {
+            debug!(
+                "ignoring {:?}, torrent is locked, peer is outside of upload subnets or has no upload slot",
+                request
+            );
+            if self.fast_extension() {
+                self.tx
+                    .send(WriterRequest::Message(MessageOwned::RejectRequest(request)))?;
+            }
+            return Ok(());
+        }
         let piece_index = match self.state.lengths.validate_piece_index(request.index) {
             Some(p) => p,
             None => {
@@ -1132,6 +2026,69 @@ impl PeerHandler {
             });
     }
 
+    fn fast_extension(&self) -> bool {
+        self.state
+            .peers
+            .with_live(self.addr, |live| live.fast_extension)
+            .unwrap_or(false)
+    }
+
+    // BEP 6 shorthands for a full or an empty bitfield.
+    fn on_have_all_or_none(&self, have_all: bool) -> anyhow::Result<()> {
+        if !self.fast_extension() {
+            anyhow::bail!("peer sent have all/have none without negotiating the fast extension");
+        }
+        let mut bf = BF::from_vec(vec![0; self.state.lengths.piece_bitfield_bytes()]);
+        bf[..self.state.lengths.total_pieces() as usize].fill(have_all);
+        self.on_bitfield(ByteString(bf.into_vec()))
+    }
+
+    // The peer won't send the chunk, so give it back to be requested again.
+    fn on_request_rejected(&self, request: Request) -> anyhow::Result<()> {
+        let piece_index = self
+            .state
+            .lengths
+            .validate_piece_index(request.index)
+            .with_context(|| format!("peer rejected an invalid request {:?}", request))?;
+        let chunk_info = self
+            .state
+            .lengths
+            .chunk_info_from_received_data(piece_index, request.begin, request.length)
+            .with_context(|| format!("peer rejected an invalid request {:?}", request))?;
+
+        let (removed, cancelled) = self
+            .state
+            .peers
+            .with_live_mut(self.addr, "inflight_requests.remove", |h| {
+                let request = InflightRequest::from(&chunk_info);
+                let removed = h.inflight_requests.remove(&request);
+                if removed && h.inflight_requests.is_empty() {
+                    self.inflight_drained_notify.notify_waiters();
+                }
+                (removed, h.cancelled_requests.remove(&request))
+            })
+            .context("peer not found")?;
+        if cancelled {
+            // Cancelled as its piece missed the deadline, the rest was done then.
+            self.requests_sem.add_permits(1);
+            return Ok(());
+        }
+        if !removed {
+            debug!(
+                "peer rejected {:?} that we did not ask for, ignoring",
+                request
+            );
+            return Ok(());
+        }
+        self.requests_sem.add_permits(1);
+        self.state.meta.options.request_limiter.release(1);
+        self.state
+            .lock_write("mark_chunk_request_cancelled")
+            .get_chunks_mut()?
+            .mark_chunk_request_cancelled(chunk_info.piece_index, chunk_info.chunk_index);
+        Ok(())
+    }
+
     fn on_bitfield(&self, bitfield: ByteString) -> anyhow::Result<()> {
         if bitfield.len() != self.state.lengths.piece_bitfield_bytes() {
             anyhow::bail!(
@@ -1173,6 +2130,52 @@ impl PeerHandler {
             .await;
     }
 
+    // Returns false if the peer kept us choked for longer than
+    // PeerConnectionOptions::choke_timeout.
+    async fn wait_for_unchoke_or_choke_timeout(&self) -> bool {
+        let choke_timeout = match self.state.meta.peer_connection_options().choke_timeout {
+            // Once we're done, we aren't interested anymore, so it's fine to be choked.
+            Some(t) if !self.state.is_finished() => t,
+            _ => {
+                self.wait_for_unchoke().await;
+                return true;
+            }
+        };
+        tokio::time::timeout(choke_timeout, self.wait_for_unchoke())
+            .await
+            .is_ok()
+    }
+
+    fn is_outscored(&self) -> bool {
+        let my_score = match self.counters.score() {
+            Some(score) => score,
+            None => return false,
+        };
+        match self.state.peer_ranking().average_score {
+            Some(avg) => my_score < avg * OUTSCORED_PEER_FRACTION,
+            None => false,
+        }
+    }
+
+    // Lets better peers get the next pieces, see SessionOptions::prefer_good_peers.
+    async fn yield_to_better_peers(&self) {
+        if rand::random::<f64>() < OUTSCORED_PEER_PROBE_PROBABILITY || !self.is_outscored() {
+            return;
+        }
+        trace!("outscored by other peers, waiting for in-flight requests");
+        // Requests aren't answered while we are choked, so don't wait for them forever.
+        let _ = tokio::time::timeout(
+            YIELD_TO_BETTER_PEERS_MAX_WAIT,
+            self.wait_for_any_notify(&self.inflight_drained_notify, || {
+                self.state
+                    .peers
+                    .with_live(self.addr, |live| live.inflight_requests.is_empty())
+                    .unwrap_or(true)
+            }),
+        )
+        .await;
+    }
+
     async fn task_peer_chunk_requester(&self) -> anyhow::Result<()> {
         let handle = self.addr;
         self.wait_for_bitfield().await;
@@ -1203,7 +2206,12 @@ impl PeerHandler {
         }
 
         loop {
-            self.wait_for_unchoke().await;
+            if !self.wait_for_unchoke_or_choke_timeout().await {
+                debug!("peer kept us choked for too long, disconnecting");
+                atomic_inc(&self.state.peers.stats.choke_rotated);
+                self.locked.write().disconnect_reason = Some(PeerDisconnectReason::ChokeTimeout);
+                return Ok(());
+            }
 
             if self.state.is_finished() {
                 debug!("nothing left to download, looping forever until manage_peer quits");
@@ -1212,14 +2220,40 @@ impl PeerHandler {
                 }
             }
 
+            if self.state.meta.locked.load(Ordering::Relaxed) {
+                debug!("torrent is locked, not requesting pieces");
+                self.wait_for_any_notify(&self.state.meta.unlocked, || {
+                    !self.state.meta.locked.load(Ordering::Relaxed)
+                })
+                .await;
+                continue;
+            }
+
+            if self.state.meta.options.prefer_good_peers {
+                self.yield_to_better_peers().await;
+            }
+            if let Some(limit) = self.state.meta.options.slow_peer_piece_limit {
+                self.wait_while_holding_too_many_pieces(limit).await;
+            }
+
+            // Pieces about to miss their playback deadline come before anything else, then
+            // ones slow peers hold beyond slow_peer_piece_limit.
             // Try steal a pice from a very slow peer first. Otherwise we might wait too long
             // to download early pieces.
             // Then try get the next one in queue.
             // Afterwards means we are close to completion, try stealing more aggressively.
-            let next = match self
-                .try_steal_old_slow_piece(10.)
-                .map_or_else(|| self.reserve_next_needed_piece(), |v| Ok(Some(v)))?
-                .or_else(|| self.try_steal_old_slow_piece(3.))
+            let (next, stolen) = match self
+                .try_steal_urgent_piece()
+                .or_else(|| self.try_steal_excess_piece())
+                .or_else(|| self.try_steal_old_slow_piece(10.))
+                .map_or_else(
+                    || {
+                        self.reserve_next_needed_piece()
+                            .map(|p| p.map(|p| (p, false)))
+                    },
+                    |v| Ok(Some((v, true))),
+                )?
+                .or_else(|| self.try_steal_old_slow_piece(3.).map(|p| (p, true)))
             {
                 Some(next) => next,
                 None => {
@@ -1241,6 +2275,23 @@ impl PeerHandler {
                     length: chunk.size,
                 };
 
+                // Take from the session-wide budget before the request is recorded as
+                // in-flight, so that on_peer_died() can return exactly what was taken.
+                let request_limiter = &self.state.meta.options.request_limiter;
+                if self.state.meta.boost_remaining().is_some() {
+                    request_limiter.acquire_now();
+                } else {
+                    // Requesting at the download cap keeps the download under it.
+                    self.state
+                        .meta
+                        .options
+                        .bandwidth
+                        .download
+                        .acquire(chunk.size)
+                        .await;
+                    request_limiter.acquire().await?;
+                }
+
                 match self
                     .state
                     .peers
@@ -1249,6 +2300,7 @@ impl PeerHandler {
                     }) {
                     Some(true) => {}
                     Some(false) => {
+                        request_limiter.release(1);
                         // This request was already in-flight for this peer for this chunk.
                         // This might happen in theory, but not very likely.
                         //
@@ -1259,7 +2311,10 @@ impl PeerHandler {
                         continue;
                     }
                     // peer died
-                    None => return Ok(()),
+                    None => {
+                        request_limiter.release(1);
+                        return Ok(());
+                    }
                 };
 
                 loop {
@@ -1269,6 +2324,18 @@ impl PeerHandler {
                     };
                 }
 
+                self.counters
+                    .requested_bytes
+                    .fetch_add(chunk.size as u64, Ordering::Relaxed);
+                if stolen {
+                    let stats = &self.state.stats;
+                    stats
+                        .endgame_duplicate_requests
+                        .fetch_add(1, Ordering::Relaxed);
+                    stats
+                        .endgame_duplicate_bytes
+                        .fetch_add(chunk.size as u64, Ordering::Relaxed);
+                }
                 if self
                     .tx
                     .send(WriterRequest::Message(MessageOwned::Request(request)))
@@ -1282,37 +2349,30 @@ impl PeerHandler {
 
     fn on_i_am_choked(&self) {
         self.locked.write().i_am_choked = true;
+        self.state.peers.mark_peer_choking(self.addr, true);
     }
 
     fn on_peer_interested(&self) {
         trace!("peer is interested");
         self.state.peers.mark_peer_interested(self.addr, true);
+        if self.state.meta.upload_slots().is_some() {
+            self.state.want_upload_slot(self.addr);
+        }
     }
 
-    fn reopen_read_only(&self) -> anyhow::Result<()> {
-        // Lock exclusive just in case to ensure in-flight operations finish.??
-        let _guard = self.state.lock_write("reopen_read_only");
-
-        for (file, filename) in self.state.files.iter().zip(self.state.filenames.iter()) {
-            let mut g = file.lock();
-            // this should close the original file
-            // putting in a block just in case to guarantee drop.
-            {
-                *g = dummy_file()?;
-            }
-            *g = std::fs::OpenOptions::new()
-                .read(true)
-                .open(filename)
-                .with_context(|| format!("error re-opening {:?} readonly", filename))?;
-            debug!("reopened {:?} read-only", filename);
+    fn on_peer_not_interested(&self) {
+        trace!("peer is not interested");
+        self.state.peers.mark_peer_interested(self.addr, false);
+        // Without a limit, peers keep their slot whether they are interested or not.
+        if self.state.meta.upload_slots().is_some() {
+            self.state.leave_upload_slot(self.addr, false);
         }
-        info!("reopened all torrent files in read-only mode");
-        Ok(())
     }
 
     fn on_i_am_unchoked(&self) {
         trace!("we are unchoked");
         self.locked.write().i_am_choked = false;
+        self.state.peers.mark_peer_choking(self.addr, false);
         self.unchoke_notify.notify_waiters();
         self.requests_sem.add_permits(16);
     }
@@ -1343,22 +2403,32 @@ impl PeerHandler {
             .fetched_bytes
             .fetch_add(piece.block.len() as u64, Ordering::Relaxed);
 
-        self.state
+        let cancelled = self
+            .state
             .peers
             .with_live_mut(self.addr, "inflight_requests.remove", |h| {
-                if !h
-                    .inflight_requests
-                    .remove(&InflightRequest::from(&chunk_info))
-                {
+                let request = InflightRequest::from(&chunk_info);
+                if h.cancelled_requests.remove(&request) {
+                    return Ok(true);
+                }
+                if !h.inflight_requests.remove(&request) {
                     anyhow::bail!(
                         "peer sent us a piece we did not ask. Requested pieces: {:?}. Got: {:?}",
                         &h.inflight_requests,
                         &piece,
                     );
                 }
-                Ok(())
+                if h.inflight_requests.is_empty() {
+                    self.inflight_drained_notify.notify_waiters();
+                }
+                Ok(false)
             })
             .context("peer not found")??;
+        if cancelled {
+            debug!("received {:?} after cancelling it, ignoring", chunk_info);
+            return Ok(());
+        }
+        self.state.meta.options.request_limiter.release(1);
 
         let full_piece_download_time = {
             let mut g = self.state.lock_write("mark_chunk_downloaded");
@@ -1408,6 +2478,7 @@ impl PeerHandler {
 
         // By this time we reach here, no other peer can for this piece. All others, even if they steal pieces would
         // have fallen off above in one of the defensive checks.
+        self.state.record_chunk_source(self.addr, &chunk_info);
 
         self.state
             .meta
@@ -1419,15 +2490,18 @@ impl PeerHandler {
                 // should we really do? If we unmark it, it will get requested forever...
                 //
                 // So let's just unwrap and abort.
-                match self
-                    .state
-                    .file_ops()
-                    .write_chunk(self.addr, &piece, &chunk_info)
-                {
-                    Ok(()) => {}
-                    Err(e) => {
-                        error!("FATAL: error writing chunk to disk: {:?}", e);
-                        return self.state.on_fatal_error(e);
+                let options = &self.state.meta.options;
+                if !self.state.buffer_chunk(&chunk_info, piece.block.as_ref()) {
+                    match self
+                        .state
+                        .file_ops()
+                        .write_chunk(self.addr, &piece, &chunk_info)
+                    {
+                        Ok(()) => {}
+                        Err(e) => {
+                            error!("FATAL: error writing chunk to disk: {:?}", e);
+                            return self.state.on_fatal_error(e);
+                        }
                     }
                 }
 
@@ -1436,58 +2510,59 @@ impl PeerHandler {
                     None => return Ok(()),
                 };
 
-                match self
-                    .state
-                    .file_ops()
-                    .check_piece(self.addr, chunk_info.piece_index, &chunk_info)
-                    .with_context(|| format!("error checking piece={index}"))?
-                {
-                    true => {
-                        {
-                            let mut g = self.state.lock_write("mark_piece_downloaded");
-                            g.get_chunks_mut()?
-                                .mark_piece_downloaded(chunk_info.piece_index);
+                let buffered = self.state.take_piece_buffer(chunk_info.piece_index);
+                if let Some(budget) = options.verify_from_memory.as_ref() {
+                    budget.on_piece_checked(buffered.is_some());
+                }
+                let verified = match buffered {
+                    Some(buffered) => {
+                        let verified = self
+                            .state
+                            .file_ops()
+                            .check_piece_data(chunk_info.piece_index, &buffered.data)
+                            .with_context(|| format!("error checking piece={index}"))?;
+                        if verified {
+                            if let Err(e) = self.state.file_ops().write_piece(
+                                self.addr,
+                                chunk_info.piece_index,
+                                &buffered.data,
+                            ) {
+                                error!("FATAL: error writing piece to disk: {:?}", e);
+                                return self.state.on_fatal_error(e);
+                            }
                         }
+                        verified
+                    }
+                    None if options.verify_before_write => {
+                        bail!("bug: piece={index} has no buffer")
+                    }
+                    None => match options.verify_from_memory.as_ref() {
+                        Some(budget) => self.state.file_ops().check_piece_parallel(
+                            self.addr,
+                            chunk_info.piece_index,
+                            &chunk_info,
+                            budget.parallel_reads,
+                        ),
+                        None => self.state.file_ops().check_piece(
+                            self.addr,
+                            chunk_info.piece_index,
+                            &chunk_info,
+                        ),
+                    }
+                    .with_context(|| format!("error checking piece={index}"))?,
+                };
 
-                        // Global piece counters.
+                match verified {
+                    true => {
+                        // Per-peer piece counters.
                         let piece_len =
                             self.state.lengths.piece_length(chunk_info.piece_index) as u64;
-                        self.state
-                            .stats
-                            .downloaded_and_checked_bytes
-                            // This counter is used to compute "is_finished", so using
-                            // stronger ordering.
-                            .fetch_add(piece_len, Ordering::Release);
-                        self.state
-                            .stats
-                            .downloaded_and_checked_pieces
-                            // This counter is used to compute "is_finished", so using
-                            // stronger ordering.
-                            .fetch_add(1, Ordering::Release);
-                        self.state
-                            .stats
-                            .have_bytes
-                            .fetch_add(piece_len, Ordering::Relaxed);
-                        self.state.stats.total_piece_download_ms.fetch_add(
-                            full_piece_download_time.as_millis() as u64,
-                            Ordering::Relaxed,
-                        );
-
-                        // Per-peer piece counters.
                         self.counters
                             .on_piece_downloaded(piece_len, full_piece_download_time);
                         self.state.peers.reset_peer_backoff(self.addr);
 
-                        debug!("piece={} successfully downloaded and verified", index);
-
-                        if self.state.is_finished() {
-                            info!("torrent finished downloading");
-                            self.state.finished_notify.notify_waiters();
-                            self.disconnect_all_peers_that_have_full_torrent();
-                            self.reopen_read_only()?;
-                        }
-
-                        self.state.maybe_transmit_haves(chunk_info.piece_index);
+                        self.state
+                            .on_piece_verified(chunk_info.piece_index, full_piece_download_time)?;
                     }
                     false => {
                         warn!("checksum for piece={} did not validate", index,);
@@ -1495,6 +2570,8 @@ impl PeerHandler {
                             .lock_write("mark_piece_broken")
                             .get_chunks_mut()?
                             .mark_piece_broken_if_not_have(chunk_info.piece_index);
+                        self.state
+                            .on_piece_failed_verification(chunk_info.piece_index, self.addr)?;
                     }
                 };
                 Ok::<_, anyhow::Error>(())
@@ -1502,19 +2579,299 @@ impl PeerHandler {
             .with_context(|| format!("error processing received chunk {chunk_info:?}"))?;
         Ok(())
     }
+}
 
-    fn disconnect_all_peers_that_have_full_torrent(&self) {
-        for mut pe in self.state.peers.states.iter_mut() {
-            if let PeerState::Live(l) = pe.value().state.get() {
-                if l.has_full_torrent(self.state.lengths.total_pieces() as usize) {
-                    let prev = pe.value_mut().state.set_not_needed(&self.state.peers.stats);
-                    let _ = prev
-                        .take_live_no_counters()
-                        .unwrap()
-                        .tx
-                        .send(WriterRequest::Disconnect);
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::atomic::AtomicBool, time::Duration};
+
+    use librqbit_core::lengths::Lengths;
+    use peer_binary_protocol::{Message, Request};
+
+    use crate::{
+        peer_connection::PeerConnectionOptions,
+        session::SessionOptions,
+        test_util::{
+            add_test_torrent, test_data, test_dir, test_session, RawPeer, TEST_PIECE_LENGTH,
+        },
+    };
+
+    use super::{
+        move_file_with_rename, peer_events::PeerDisconnectReason, InflightRequest,
+        TorrentStateLocked,
+    };
+
+    #[tokio::test]
+    async fn test_fast_extension_interop() {
+        let dir = test_dir("fast-extension");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27000..27100),
+                ..Default::default()
+            },
+        )
+        .await;
+        let port = session.listen_status().port.unwrap();
+        let data = test_data(TEST_PIECE_LENGTH as usize * 2, 0);
+        std::fs::create_dir_all(dir.join("seed")).unwrap();
+        std::fs::write(dir.join("seed/f"), &data).unwrap();
+        let (_, seed) =
+            add_test_torrent(&session, "seed", &[("f", &data)], Default::default()).await;
+        let (_, leech) =
+            add_test_torrent(&session, "leech", &[("f", &data[1..])], Default::default()).await;
+
+        // A complete torrent sends "have all" instead of the bitfield, and rejects the
+        // requests it won't upload.
+        let mut peer = RawPeer::connect(port, seed.info_hash()).await;
+        assert!(matches!(peer.read().await, Message::HaveAll));
+        seed.set_locked(true);
+        peer.send(Message::Request(Request::new(0, 0, TEST_PIECE_LENGTH)))
+            .await;
+        loop {
+            match peer.read().await {
+                Message::RejectRequest(r) => {
+                    assert_eq!((r.index, r.begin, r.length), (0, 0, TEST_PIECE_LENGTH));
+                    break;
                 }
+                Message::Piece(_) => panic!("a locked torrent uploaded"),
+                _ => {}
+            }
+        }
+
+        // An empty one sends "have none". "have all" from the peer makes it interested, and a
+        // request the peer rejects isn't in flight anymore.
+        let mut peer = RawPeer::connect(port, leech.info_hash()).await;
+        assert!(matches!(peer.read().await, Message::HaveNone));
+        peer.send(Message::HaveAll).await;
+        while !matches!(peer.read().await, Message::Interested) {}
+        peer.send(Message::Unchoke).await;
+        let request = loop {
+            if let Message::Request(r) = peer.read().await {
+                break r;
+            }
+        };
+        let live = leech.live().unwrap();
+        let addr = peer.stream.local_addr().unwrap();
+        let in_flight = || {
+            let piece = live.lengths.validate_piece_index(request.index).unwrap();
+            let chunk = live
+                .lengths
+                .chunk_info_from_received_data(piece, request.begin, request.length)
+                .unwrap();
+            live.peers
+                .with_live(addr, |l| {
+                    l.inflight_requests.contains(&InflightRequest::from(&chunk))
+                })
+                .unwrap()
+        };
+        assert!(in_flight());
+        peer.send(Message::RejectRequest(request)).await;
+        for _ in 0..500 {
+            if !in_flight() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!in_flight());
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_keeping_us_choked_is_rotated_out() {
+        let dir = test_dir("choke-timeout");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27200..27300),
+                peer_opts: Some(PeerConnectionOptions {
+                    choke_timeout: Some(Duration::from_millis(200)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .await;
+        let port = session.listen_status().port.unwrap();
+        let data = test_data(TEST_PIECE_LENGTH as usize * 2, 0);
+        let (_, leech) =
+            add_test_torrent(&session, "leech", &[("f", &data)], Default::default()).await;
+        let live = leech.live().unwrap();
+
+        // It has everything we want, but never unchokes us.
+        let mut peer = RawPeer::connect(port, leech.info_hash()).await;
+        let addr = peer.stream.local_addr().unwrap();
+        assert!(matches!(peer.read().await, Message::HaveNone));
+        peer.send(Message::HaveAll).await;
+        while !matches!(peer.read().await, Message::Interested) {}
+
+        let rotated = || live.stats_snapshot().peer_stats.choke_rotated;
+        for _ in 0..500 {
+            if rotated() > 0 {
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        assert_eq!(rotated(), 1);
+        let mut event = None;
+        for _ in 0..500 {
+            event = live
+                .recent_peer_events()
+                .into_iter()
+                .find(|e| e.peer == addr);
+            if event.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let event = event.expect("the disconnect wasn't recorded");
+        assert_eq!(event.reason, PeerDisconnectReason::ChokeTimeout);
+        assert!(event.was_live);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stalled_with_unavailable_pieces() {
+        let dir = test_dir("stalled");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27400..27500),
+                stall_timeout: Some(Duration::from_millis(500)),
+                ..Default::default()
+            },
+        )
+        .await;
+        let port = session.listen_status().port.unwrap();
+        let data = test_data(TEST_PIECE_LENGTH as usize * 2, 0);
+        let (_, handle) =
+            add_test_torrent(&session, "t", &[("f", &data)], Default::default()).await;
+        // Without peers it's not stalled, whatever the time.
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(!handle.stats().stalled);
+
+        // It has only the first piece, and never unchokes us.
+        let mut peer = RawPeer::connect(port, handle.info_hash()).await;
+        peer.send(Message::HaveNone).await;
+        peer.send(Message::Have(0)).await;
+        for _ in 0..500 {
+            if handle.stats().stalled {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stats = handle.stats();
+        assert!(stats.stalled);
+        assert_eq!(stats.unavailable_pieces, Some(1));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_file_across_devices() {
+        let dir = test_dir("move-file");
+        let from = dir.join("from/file");
+        let to = dir.join("to/sub/file");
+        std::fs::create_dir_all(from.parent().unwrap()).unwrap();
+        std::fs::write(&from, b"contents").unwrap();
+
+        // The first rename is the one across devices.
+        let failed = AtomicBool::new(false);
+        move_file_with_rename(&from, &to, Default::default(), |a, b| {
+            if !failed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "Invalid cross-device link",
+                ));
+            }
+            std::fs::rename(a, b)
+        })
+        .unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"contents");
+        // Nothing is left from the copy.
+        let left: Vec<_> = std::fs::read_dir(to.parent().unwrap())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec![std::ffi::OsString::from("file")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_file_across_devices_keeps_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        use crate::file_permissions::FilePermissions;
+
+        let dir = test_dir("move-file-permissions");
+        let from = dir.join("from/file");
+        let to = dir.join("to/sub/file");
+        std::fs::create_dir_all(from.parent().unwrap()).unwrap();
+        std::fs::write(&from, b"contents").unwrap();
+        let gid = std::fs::metadata(&dir).unwrap().gid();
+        let perms = FilePermissions {
+            mode: Some(0o640),
+            gid: Some(gid),
+        };
+
+        // The first rename fails, so the file is copied.
+        let failed = AtomicBool::new(false);
+        move_file_with_rename(&from, &to, perms, |a, b| {
+            if !failed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "Invalid cross-device link",
+                ));
+            }
+            std::fs::rename(a, b)
+        })
+        .unwrap();
+
+        let mode = |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&to), 0o640);
+        assert_eq!(std::fs::metadata(&to).unwrap().gid(), gid);
+        for created in [dir.join("to"), dir.join("to/sub")] {
+            assert_eq!(mode(&created), 0o750, "{created:?}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_failed_piece() {
+        let mut g = TorrentStateLocked {
+            chunks: None,
+            inflight_pieces: Default::default(),
+            fatal_errors_tx: None,
+            failed_pieces: Default::default(),
+            poisoned_pieces: Default::default(),
+            deadlines: Default::default(),
+        };
+        let lengths = Lengths::new(16384 * 4, 16384, None).unwrap();
+        let piece = lengths.validate_piece_index(1).unwrap();
+        let other = lengths.validate_piece_index(2).unwrap();
+        let peer = |n: u8| SocketAddr::from(([10, 0, 0, n], 6881));
+
+        assert!(g.record_failed_piece(piece, peer(1), 2).is_none());
+        // The same peer failing again doesn't count twice.
+        assert!(g.record_failed_piece(piece, peer(1), 2).is_none());
+        assert!(g.record_failed_piece(other, peer(2), 2).is_none());
+
+        let mut poisoned = g.record_failed_piece(piece, peer(2), 2).unwrap();
+        poisoned.peers.sort();
+        assert_eq!(poisoned.piece, 1);
+        assert_eq!(poisoned.peers, vec![peer(1), peer(2)]);
+        assert_eq!(g.poisoned_pieces.len(), 1);
+
+        // Reported once only.
+        assert!(g.record_failed_piece(piece, peer(3), 2).is_none());
+        assert_eq!(g.poisoned_pieces.len(), 1);
     }
 }
@@ -1,6 +1,6 @@
 pub mod stats;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 use librqbit_core::hash_id::Id20;
 use librqbit_core::lengths::{ChunkInfo, ValidPieceIndex};
@@ -10,6 +10,7 @@ use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use crate::peer_connection::WriterRequest;
 use crate::type_aliases::BF;
 
+use super::peer_source::PeerSource;
 use super::peers::stats::atomic::AggregatePeerStatsAtomic;
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -30,13 +31,22 @@ impl From<&ChunkInfo> for InflightRequest {
 pub(crate) type PeerRx = UnboundedReceiver<WriterRequest>;
 pub(crate) type PeerTx = UnboundedSender<WriterRequest>;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct Peer {
     pub state: PeerStateNoMut,
     pub stats: stats::atomic::PeerStats,
+    pub source: PeerSource,
 }
 
 impl Peer {
+    pub fn new_queued(source: PeerSource) -> Self {
+        Self {
+            state: Default::default(),
+            stats: Default::default(),
+            source,
+        }
+    }
+
     pub fn new_live_for_incoming_connection(
         peer_id: Id20,
         tx: PeerTx,
@@ -47,6 +57,7 @@ impl Peer {
         Self {
             state,
             stats: Default::default(),
+            source: PeerSource::Incoming,
         }
     }
 }
@@ -182,6 +193,8 @@ pub(crate) struct LivePeerState {
     peer_id: Id20,
 
     pub peer_interested: bool,
+    // Whether the peer chokes us, i.e. won't serve our requests. Peers start out choking.
+    pub peer_choking: bool,
 
     // This is used to track the pieces the peer has.
     pub bitfield: BF,
@@ -191,6 +204,16 @@ pub(crate) struct LivePeerState {
 
     // The main channel to send requests to peer.
     pub tx: PeerTx,
+
+    // Extension name to message id, from the peer's last extended handshake.
+    pub extensions: BTreeMap<String, u8>,
+
+    // Whether both sides support the BEP 6 fast extension.
+    pub fast_extension: bool,
+
+    // Requests cancelled as their piece missed its playback deadline. With the fast
+    // extension, the peer answers each with the chunk anyway or a reject.
+    pub cancelled_requests: HashSet<InflightRequest>,
 }
 
 impl LivePeerState {
@@ -198,9 +221,13 @@ impl LivePeerState {
         LivePeerState {
             peer_id,
             peer_interested: false,
+            peer_choking: true,
             bitfield: BF::new(),
             inflight_requests: Default::default(),
             tx,
+            extensions: Default::default(),
+            fast_extension: false,
+            cancelled_requests: Default::default(),
         }
     }
 
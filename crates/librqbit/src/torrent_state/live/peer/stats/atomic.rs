@@ -20,8 +20,14 @@ pub(crate) struct PeerCountersAtomic {
     pub downloaded_and_checked_pieces: AtomicU32,
     pub downloaded_and_checked_bytes: AtomicU64,
     pub total_piece_download_ms: AtomicU64,
+    pub requested_bytes: AtomicU64,
+    // A rolling average of the piece download rate in bytes/s, stored as f64 bits.
+    pub recent_piece_rate: AtomicU64,
 }
 
+// How much the latest piece moves the rolling piece download rate.
+const PIECE_RATE_WEIGHT: f64 = 0.3;
+
 impl PeerCountersAtomic {
     pub(crate) fn on_piece_downloaded(&self, piece_len: u64, elapsed: Duration) {
         let elapsed = elapsed.as_millis() as u64;
@@ -31,6 +37,35 @@ impl PeerCountersAtomic {
             .fetch_add(1, Ordering::Release);
         self.downloaded_and_checked_bytes
             .fetch_add(piece_len, Ordering::Relaxed);
+
+        // Only the peer's own tasks download pieces from it, so this doesn't race.
+        let rate = piece_len as f64 / elapsed.max(1) as f64 * 1000.;
+        let prev = f64::from_bits(self.recent_piece_rate.load(Ordering::Relaxed));
+        let rate = if prev == 0. {
+            rate
+        } else {
+            prev * (1. - PIECE_RATE_WEIGHT) + rate * PIECE_RATE_WEIGHT
+        };
+        self.recent_piece_rate
+            .store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    /// How well the peer has been doing recently, higher is better: the rolling piece
+    /// download rate, which is low for both slow and high latency peers, scaled by the
+    /// share of requested bytes the peer delivered. None until it delivers a full piece.
+    pub(crate) fn score(&self) -> Option<f64> {
+        let rate = f64::from_bits(self.recent_piece_rate.load(Ordering::Relaxed));
+        if rate == 0. {
+            return None;
+        }
+        let requested = self.requested_bytes.load(Ordering::Relaxed);
+        let fetched = self.fetched_bytes.load(Ordering::Relaxed);
+        let delivered = if requested == 0 {
+            1.
+        } else {
+            (fetched as f64 / requested as f64).min(1.)
+        };
+        Some(rate * delivered)
     }
 
     pub(crate) fn average_piece_download_time(&self) -> Option<Duration> {
@@ -64,3 +99,41 @@ impl Default for PeerStats {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use super::PeerCountersAtomic;
+
+    fn peer(requested: u64, fetched: u64, piece_times_ms: &[u64]) -> PeerCountersAtomic {
+        let p = PeerCountersAtomic::default();
+        p.requested_bytes.store(requested, Ordering::Relaxed);
+        p.fetched_bytes.store(fetched, Ordering::Relaxed);
+        for ms in piece_times_ms {
+            p.on_piece_downloaded(16384, Duration::from_millis(*ms));
+        }
+        p
+    }
+
+    #[test]
+    fn test_score_unknown_until_first_piece() {
+        assert_eq!(peer(16384, 0, &[]).score(), None);
+    }
+
+    #[test]
+    fn test_score_prefers_fast_peers_that_deliver() {
+        let fast = peer(16384, 16384, &[100]).score().unwrap();
+        let slow = peer(16384, 16384, &[1000]).score().unwrap();
+        let unreliable = peer(4 * 16384, 16384, &[100]).score().unwrap();
+        assert!(fast > slow);
+        assert!(fast > unreliable);
+    }
+
+    #[test]
+    fn test_score_follows_recent_pieces() {
+        let got_slower = peer(0, 0, &[100, 100, 1000, 1000, 1000]).score().unwrap();
+        let got_faster = peer(0, 0, &[1000, 1000, 100, 100, 100]).score().unwrap();
+        assert!(got_faster > got_slower);
+    }
+}
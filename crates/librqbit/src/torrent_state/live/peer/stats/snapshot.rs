@@ -1,8 +1,14 @@
-use std::{collections::HashMap, sync::atomic::Ordering};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::Ordering,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::torrent_state::live::peer::{Peer, PeerState};
+use crate::torrent_state::live::{
+    peer::{Peer, PeerState},
+    peer_source::PeerSource,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct PeerCounters {
@@ -15,12 +21,28 @@ pub struct PeerCounters {
     pub fetched_chunks: u32,
     pub downloaded_and_checked_pieces: u32,
     pub total_piece_download_ms: u64,
+    pub requested_bytes: u64,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct PeerStats {
     pub counters: PeerCounters,
     pub state: &'static str,
+    /// Where the peer was found. Kept while the peer is reconnected to, i.e. until it's
+    /// forgotten after too many failed attempts.
+    pub source: PeerSource,
+    /// Higher is better, see [crate::SessionOptions::prefer_good_peers]. Not set until the
+    /// peer delivers a full piece.
+    pub score: Option<f64>,
+    /// In-flight pieces being downloaded from the peer, see
+    /// [crate::SessionOptions::slow_peer_piece_limit].
+    #[serde(default)]
+    pub held_pieces: u32,
+    /// The extension protocol messages (BEP 10) the peer advertised, e.g. "ut_metadata" or
+    /// "ut_pex", with the message ids it wants them sent with. An id of 0 means the peer
+    /// turned the extension off. Empty until the peer sends its extended handshake.
+    #[serde(default)]
+    pub extensions: BTreeMap<String, u8>,
 }
 
 impl From<&super::atomic::PeerCountersAtomic> for PeerCounters {
@@ -39,6 +61,7 @@ impl From<&super::atomic::PeerCountersAtomic> for PeerCounters {
                 .downloaded_and_checked_pieces
                 .load(Ordering::Relaxed),
             total_piece_download_ms: counters.total_piece_download_ms.load(Ordering::Relaxed),
+            requested_bytes: counters.requested_bytes.load(Ordering::Relaxed),
         }
     }
 }
@@ -48,13 +71,48 @@ impl From<&Peer> for PeerStats {
         Self {
             counters: peer.stats.counters.as_ref().into(),
             state: peer.state.get().name(),
+            source: peer.source,
+            score: peer.stats.counters.score(),
+            held_pieces: 0,
+            extensions: peer
+                .state
+                .get_live()
+                .map(|l| l.extensions.clone())
+                .unwrap_or_default(),
         }
     }
 }
 
+/// Totals for the peers found through one source.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct PeerSourceStats {
+    pub peers: usize,
+    pub live: usize,
+    pub fetched_bytes: u64,
+}
+
 #[derive(Serialize)]
 pub struct PeerStatsSnapshot {
     pub peers: HashMap<String, PeerStats>,
+    /// The peers above, summed up by source, to see which discovery mechanisms find
+    /// useful peers.
+    pub by_source: HashMap<PeerSource, PeerSourceStats>,
+}
+
+impl PeerStatsSnapshot {
+    pub(crate) fn new(peers: impl Iterator<Item = (String, PeerStats)>) -> Self {
+        let peers: HashMap<String, PeerStats> = peers.collect();
+        let mut by_source = HashMap::<PeerSource, PeerSourceStats>::new();
+        for stats in peers.values() {
+            let totals = by_source.entry(stats.source).or_default();
+            totals.peers += 1;
+            if stats.state == "live" {
+                totals.live += 1;
+            }
+            totals.fetched_bytes += stats.counters.fetched_bytes;
+        }
+        Self { peers, by_source }
+    }
 }
 
 #[derive(Clone, Copy, Default, Deserialize)]
@@ -73,4 +131,83 @@ impl PeerStatsFilterState {
 #[derive(Default, Deserialize)]
 pub struct PeerStatsFilter {
     pub state: PeerStatsFilterState,
+    /// Only peers found through this source.
+    #[serde(default)]
+    pub source: Option<PeerSource>,
+}
+
+impl PeerStatsFilter {
+    pub(crate) fn matches(&self, peer: &Peer) -> bool {
+        self.state.matches(peer.state.get()) && self.source.map_or(true, |s| s == peer.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::torrent_state::live::{
+        peer::{Peer, PeerState},
+        peer_source::PeerSource,
+        peers::stats::atomic::AggregatePeerStatsAtomic,
+    };
+
+    use super::{
+        PeerSourceStats, PeerStats, PeerStatsFilter, PeerStatsFilterState, PeerStatsSnapshot,
+    };
+
+    #[test]
+    fn test_by_source() {
+        let tracker = Peer::new_queued(PeerSource::Tracker);
+        tracker
+            .stats
+            .counters
+            .fetched_bytes
+            .store(100, Ordering::Relaxed);
+        let mut dht = Peer::new_queued(PeerSource::Dht);
+        dht.state
+            .set(PeerState::Dead, &AggregatePeerStatsAtomic::default());
+        let peers = [tracker, dht, Peer::new_queued(PeerSource::Tracker)];
+
+        let snapshot = PeerStatsSnapshot::new(
+            peers
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (i.to_string(), p.into())),
+        );
+        assert_eq!(
+            snapshot.by_source[&PeerSource::Tracker],
+            PeerSourceStats {
+                peers: 2,
+                live: 0,
+                fetched_bytes: 100
+            }
+        );
+        assert_eq!(snapshot.by_source[&PeerSource::Dht].peers, 1);
+
+        let filter = PeerStatsFilter {
+            state: PeerStatsFilterState::All,
+            source: Some(PeerSource::Dht),
+        };
+        assert_eq!(peers.iter().filter(|p| filter.matches(p)).count(), 1);
+    }
+
+    #[test]
+    fn test_extensions() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let counters = AggregatePeerStatsAtomic::default();
+        let mut peer = Peer::new_live_for_incoming_connection(Default::default(), tx, &counters);
+        assert!(PeerStats::from(&peer).extensions.is_empty());
+
+        let live = peer.state.get_live_mut().unwrap();
+        live.extensions.insert("ut_metadata".to_owned(), 3);
+        live.extensions.insert("ut_pex".to_owned(), 0);
+        let stats = PeerStats::from(&peer);
+        assert_eq!(stats.extensions["ut_metadata"], 3);
+        assert_eq!(stats.extensions["ut_pex"], 0);
+
+        // Only live peers have a handshake to read it from.
+        peer.state.set(PeerState::Dead, &counters);
+        assert!(PeerStats::from(&peer).extensions.is_empty());
+    }
 }
@@ -0,0 +1,61 @@
+// Remembering the peers a torrent was connected to, so that after a restart it can dial
+// them right away instead of waiting for trackers and the DHT, see
+// SessionOptions::persist_peers.
+
+use std::net::SocketAddr;
+
+use super::TorrentStateLive;
+
+// How many peers are remembered per torrent.
+pub(crate) const MAX_CACHED_PEERS: usize = 50;
+
+// The best scoring peers first, then the ones not scored yet.
+fn best_peers(
+    peers: impl Iterator<Item = (SocketAddr, Option<f64>)>,
+    limit: usize,
+) -> Vec<SocketAddr> {
+    let mut peers: Vec<(SocketAddr, Option<f64>)> = peers.collect();
+    peers.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    peers
+        .into_iter()
+        .take(limit)
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+impl TorrentStateLive {
+    // The connected peers worth dialing again, best first.
+    pub(crate) fn peers_to_cache(&self) -> Vec<SocketAddr> {
+        best_peers(
+            self.peers
+                .states
+                .iter()
+                .filter(|e| e.value().state.get_live().is_some())
+                .map(|e| (*e.key(), e.value().stats.counters.score())),
+            MAX_CACHED_PEERS,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::best_peers;
+
+    #[test]
+    fn test_best_peers() {
+        let addr = |p: u16| SocketAddr::from(([127, 0, 0, 1], p));
+        let peers = [
+            (addr(1), None),
+            (addr(2), Some(10.)),
+            (addr(3), Some(30.)),
+            (addr(4), Some(20.)),
+        ];
+        assert_eq!(
+            best_peers(peers.into_iter(), 10),
+            vec![addr(3), addr(4), addr(2), addr(1)]
+        );
+        assert_eq!(best_peers(peers.into_iter(), 2), vec![addr(3), addr(4)]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::TorrentStateLive;
+
+// How many disconnects to remember per torrent.
+pub(crate) const PEER_EVENTS_HISTORY: usize = 256;
+
+/// Why a peer connection ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerDisconnectReason {
+    /// We closed it ourselves, e.g. because the torrent finished.
+    NotNeeded,
+    /// Couldn't connect to the peer.
+    ConnectFailed,
+    /// Connected, but the handshake failed, e.g. wrong info hash.
+    HandshakeFailed,
+    /// The peer went silent for longer than the timeout.
+    Timeout,
+    /// The peer closed the connection.
+    ClosedByPeer,
+    /// The peer sent something invalid, or something we couldn't handle.
+    BadData,
+    /// We closed it as the peer kept us choked, see
+    /// [crate::PeerConnectionOptions::choke_timeout].
+    ChokeTimeout,
+    /// We turned down an incoming connection as the torrent had as many live peers as it
+    /// may, see [crate::SessionOptions::peer_connection_budget].
+    PeerLimit,
+    /// Anything else, see the error.
+    Other,
+}
+
+// The error of a peer operation that timed out, see peer_connection::with_timeout().
+#[derive(Debug)]
+pub(crate) struct PeerTimeout(pub Duration);
+
+impl std::fmt::Display for PeerTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timeout at {:?}", self.0)
+    }
+}
+
+impl std::error::Error for PeerTimeout {}
+
+// What a peer connection was doing when it failed, as context of its error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeerErrorContext {
+    Connecting,
+    ReadingMessage,
+    HandlingMessage,
+}
+
+impl std::fmt::Display for PeerErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PeerErrorContext::Connecting => "error connecting",
+            PeerErrorContext::ReadingMessage => "error reading message",
+            PeerErrorContext::HandlingMessage => "error in handler.on_received_message()",
+        })
+    }
+}
+
+impl PeerDisconnectReason {
+    pub(crate) fn classify(error: Option<&anyhow::Error>, was_live: bool) -> Self {
+        let error = match error {
+            Some(e) => e,
+            None => return PeerDisconnectReason::NotNeeded,
+        };
+        let io_kind = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind());
+        // The outermost one, e.g. reading a message that the handler failed on.
+        let context = error.downcast_ref::<PeerErrorContext>().copied();
+
+        if io_kind == Some(ErrorKind::TimedOut) || error.chain().any(|e| e.is::<PeerTimeout>()) {
+            return PeerDisconnectReason::Timeout;
+        }
+        if context == Some(PeerErrorContext::Connecting) {
+            return PeerDisconnectReason::ConnectFailed;
+        }
+        if !was_live {
+            return PeerDisconnectReason::HandshakeFailed;
+        }
+        match io_kind {
+            Some(
+                ErrorKind::UnexpectedEof
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe,
+            ) => PeerDisconnectReason::ClosedByPeer,
+            Some(_) => PeerDisconnectReason::Other,
+            None if context.is_some() => PeerDisconnectReason::BadData,
+            None => PeerDisconnectReason::Other,
+        }
+    }
+}
+
+/// A peer connection that ended.
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerEvent {
+    pub peer: SocketAddr,
+    /// Unix timestamp, in seconds.
+    pub time: u64,
+    pub reason: PeerDisconnectReason,
+    /// Whether the handshake went through before the disconnect.
+    pub was_live: bool,
+    pub error: Option<String>,
+}
+
+impl TorrentStateLive {
+    // "reason" is for peers we disconnected ourselves, otherwise it's told from the error.
+    pub(crate) fn record_peer_disconnect(
+        &self,
+        peer: SocketAddr,
+        error: Option<&anyhow::Error>,
+        was_live: bool,
+        reason: Option<PeerDisconnectReason>,
+    ) {
+        let event = PeerEvent {
+            peer,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            reason: reason.unwrap_or_else(|| PeerDisconnectReason::classify(error, was_live)),
+            was_live,
+            error: error.map(|e| format!("{e:#}")),
+        };
+        push_capped(&mut self.peer_events.lock(), event, PEER_EVENTS_HISTORY);
+    }
+
+    /// The last few peer disconnects, oldest first. Covers peers that never got live, which
+    /// the peer stats don't show.
+    pub fn recent_peer_events(&self) -> Vec<PeerEvent> {
+        self.peer_events.lock().iter().cloned().collect()
+    }
+}
+
+pub(super) fn push_capped<T>(q: &mut VecDeque<T>, value: T, cap: usize) {
+    if q.len() >= cap {
+        q.pop_front();
+    }
+    q.push_back(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use std::time::Duration;
+
+    use super::{push_capped, PeerDisconnectReason, PeerErrorContext, PeerTimeout};
+
+    #[test]
+    fn test_classify_disconnects() {
+        let classify =
+            |e: anyhow::Error, was_live| PeerDisconnectReason::classify(Some(&e), was_live);
+        let io = |kind: std::io::ErrorKind| anyhow::Error::from(std::io::Error::from(kind));
+
+        assert_eq!(
+            PeerDisconnectReason::classify(None, true),
+            PeerDisconnectReason::NotNeeded
+        );
+        assert_eq!(
+            classify(
+                anyhow::Error::new(PeerTimeout(Duration::from_secs(10)))
+                    .context(PeerErrorContext::Connecting),
+                false
+            ),
+            PeerDisconnectReason::Timeout
+        );
+        // The message alone doesn't make it one.
+        assert_eq!(
+            classify(anyhow::anyhow!("timeout at 10s"), true),
+            PeerDisconnectReason::Other
+        );
+        assert_eq!(
+            classify(
+                io(std::io::ErrorKind::ConnectionRefused).context(PeerErrorContext::Connecting),
+                false
+            ),
+            PeerDisconnectReason::ConnectFailed
+        );
+        assert_eq!(
+            classify(anyhow::anyhow!("info hash does not match"), false),
+            PeerDisconnectReason::HandshakeFailed
+        );
+        assert_eq!(
+            classify(
+                io(std::io::ErrorKind::UnexpectedEof).context(PeerErrorContext::ReadingMessage),
+                true
+            ),
+            PeerDisconnectReason::ClosedByPeer
+        );
+        assert_eq!(
+            classify(
+                anyhow::anyhow!("peer sent us an invalid piece")
+                    .context(PeerErrorContext::HandlingMessage)
+                    .context(PeerErrorContext::ReadingMessage),
+                true
+            ),
+            PeerDisconnectReason::BadData
+        );
+        assert_eq!(
+            classify(
+                anyhow::anyhow!("peer sent us an invalid piece").context("something else"),
+                true
+            ),
+            PeerDisconnectReason::Other
+        );
+    }
+
+    #[test]
+    fn test_push_capped() {
+        let mut q = VecDeque::new();
+        for i in 0..5 {
+            push_capped(&mut q, i, 3);
+        }
+        assert_eq!(q, [2, 3, 4]);
+    }
+}
@@ -0,0 +1,113 @@
+// Where peers come from, and in which order to dial them when more are queued than there
+// are connection slots, see SessionOptions::peer_source_priority.
+
+use std::{collections::VecDeque, net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::TorrentStateLive;
+
+// How long HTTP seeds wait before starting when they are ranked below a peer source.
+const WEB_SEED_HEAD_START: Duration = Duration::from_secs(10);
+
+/// How we learned about a peer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerSource {
+    Tracker,
+    Dht,
+    /// Peer exchange (BEP 11). Not implemented yet, so no peers come from it.
+    Pex,
+    /// Local peer discovery (BEP 14). Not implemented yet, so no peers come from it.
+    Lpd,
+    /// HTTP seeds (BEP 17). They aren't peers and never show up in the peer stats, but
+    /// ranking them delays when they start downloading.
+    WebSeed,
+    /// Passed in when adding the torrent, see [crate::AddTorrentOptions::initial_peers].
+    Manual,
+    /// The peer connected to us.
+    Incoming,
+    /// Connected to in an earlier session, see [crate::SessionOptions::persist_peers].
+    Cache,
+}
+
+// Position of the source in the priority list. Unlisted sources share the last place.
+fn rank(priority: &[PeerSource], source: PeerSource) -> usize {
+    priority
+        .iter()
+        .position(|s| *s == source)
+        .unwrap_or(priority.len())
+}
+
+// Peers waiting for a connection slot, one FIFO per rank. With an empty priority list
+// there's a single FIFO, i.e. peers are dialed in the order they were found.
+pub(crate) struct PeerDialQueue {
+    priority: Vec<PeerSource>,
+    queues: Vec<VecDeque<SocketAddr>>,
+}
+
+impl PeerDialQueue {
+    pub fn new(priority: &[PeerSource]) -> Self {
+        Self {
+            priority: priority.to_vec(),
+            queues: (0..=priority.len()).map(|_| VecDeque::new()).collect(),
+        }
+    }
+
+    pub fn push(&mut self, addr: SocketAddr, source: PeerSource) {
+        self.queues[rank(&self.priority, source)].push_back(addr);
+    }
+
+    pub fn pop(&mut self) -> Option<SocketAddr> {
+        self.queues.iter_mut().find_map(|q| q.pop_front())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|q| q.is_empty())
+    }
+}
+
+impl TorrentStateLive {
+    // HTTP seeds ranked below some other source give the peers a head start.
+    pub(crate) fn web_seed_head_start(&self) -> Option<Duration> {
+        let priority = &self.meta.options.peer_source_priority;
+        if priority.is_empty() || rank(priority, PeerSource::WebSeed) == 0 {
+            return None;
+        }
+        Some(WEB_SEED_HEAD_START)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{PeerDialQueue, PeerSource};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_dial_queue_order() {
+        let mut q = PeerDialQueue::new(&[PeerSource::Dht, PeerSource::Tracker]);
+        q.push(addr(1), PeerSource::Tracker);
+        q.push(addr(2), PeerSource::Manual);
+        q.push(addr(3), PeerSource::Dht);
+        q.push(addr(4), PeerSource::Tracker);
+        q.push(addr(5), PeerSource::Dht);
+        let order: Vec<_> = std::iter::from_fn(|| q.pop()).collect();
+        assert_eq!(order, [addr(3), addr(5), addr(1), addr(4), addr(2)]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_dial_queue_without_priority_is_fifo() {
+        let mut q = PeerDialQueue::new(&[]);
+        q.push(addr(1), PeerSource::Tracker);
+        q.push(addr(2), PeerSource::Dht);
+        q.push(addr(3), PeerSource::Tracker);
+        let order: Vec<_> = std::iter::from_fn(|| q.pop()).collect();
+        assert_eq!(order, [addr(1), addr(2), addr(3)]);
+    }
+}
@@ -11,7 +11,10 @@ use crate::{
 
 use self::stats::{atomic::AggregatePeerStatsAtomic, snapshot::AggregatePeerStats};
 
-use super::peer::{LivePeerState, Peer, PeerRx, PeerState, PeerTx};
+use super::{
+    peer::{LivePeerState, Peer, PeerRx, PeerState, PeerTx},
+    peer_source::PeerSource,
+};
 
 pub mod stats;
 
@@ -26,12 +29,12 @@ impl PeerStates {
         AggregatePeerStats::from(&self.stats)
     }
 
-    pub fn add_if_not_seen(&self, addr: SocketAddr) -> Option<PeerHandle> {
+    pub fn add_if_not_seen(&self, addr: SocketAddr, source: PeerSource) -> Option<PeerHandle> {
         use dashmap::mapref::entry::Entry;
         match self.states.entry(addr) {
             Entry::Occupied(_) => None,
             Entry::Vacant(vac) => {
-                vac.insert(Default::default());
+                vac.insert(Peer::new_queued(source));
                 atomic_inc(&self.stats.queued);
                 atomic_inc(&self.stats.seen);
                 Some(addr)
@@ -81,6 +84,11 @@ impl PeerStates {
             prev
         })
     }
+    pub fn mark_peer_choking(&self, handle: PeerHandle, is_choking: bool) -> Option<()> {
+        self.with_live_mut(handle, "mark_peer_choking", |live| {
+            live.peer_choking = is_choking;
+        })
+    }
     pub fn update_bitfield_from_vec(&self, handle: PeerHandle, bitfield: Vec<u8>) -> Option<()> {
         self.with_live_mut(handle, "update_bitfield_from_vec", |live| {
             live.bitfield = BF::from_vec(bitfield);
@@ -103,6 +111,22 @@ impl PeerStates {
         });
     }
 
+    // Resets all reconnect backoffs, and queues the peers that were waiting on theirs.
+    // Returns those, to be sent to the peer queue.
+    pub fn requeue_dead_peers(&self) -> Vec<PeerHandle> {
+        let mut requeued = Vec::new();
+        for mut peer in self.states.iter_mut() {
+            let handle = *peer.key();
+            let peer = peer.value_mut();
+            peer.stats.backoff.reset();
+            if matches!(peer.state.get(), PeerState::Dead) {
+                peer.state.set(PeerState::Queued, &self.stats);
+                requeued.push(handle);
+            }
+        }
+        requeued
+    }
+
     pub fn mark_peer_not_needed(&self, handle: PeerHandle) -> Option<PeerState> {
         let prev = self.with_peer_mut(handle, "mark_peer_not_needed", |peer| {
             peer.state.set_not_needed(&self.stats)
@@ -15,6 +15,8 @@ pub(crate) struct AggregatePeerStatsAtomic {
     pub seen: AtomicU32,
     pub dead: AtomicU32,
     pub not_needed: AtomicU32,
+    // Not a state, peers disconnected by PeerConnectionOptions::choke_timeout.
+    pub choke_rotated: AtomicU32,
 }
 
 impl AggregatePeerStatsAtomic {
@@ -12,6 +12,9 @@ pub struct AggregatePeerStats {
     pub seen: usize,
     pub dead: usize,
     pub not_needed: usize,
+    /// Peers disconnected for keeping us choked, see
+    /// [crate::PeerConnectionOptions::choke_timeout].
+    pub choke_rotated: usize,
 }
 
 impl<'a> From<&'a AggregatePeerStatsAtomic> for AggregatePeerStats {
@@ -24,6 +27,7 @@ impl<'a> From<&'a AggregatePeerStatsAtomic> for AggregatePeerStats {
             seen: s.seen.load(ordering) as usize,
             dead: s.dead.load(ordering) as usize,
             not_needed: s.not_needed.load(ordering) as usize,
+            choke_rotated: s.choke_rotated.load(ordering) as usize,
         }
     }
 }
@@ -0,0 +1,111 @@
+use serde::Serialize;
+
+use crate::first_piece::FirstPieceStrategy;
+
+use super::TorrentStateLive;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct PlannedPiece {
+    pub piece: u32,
+    /// Live peers that have the piece, i.e. the ones it could be requested from.
+    pub peers: Vec<String>,
+    /// How many live peers have the piece. Lower is rarer.
+    pub availability: usize,
+    /// Whether it has a playback deadline, see [TorrentStateLive::set_piece_deadlines].
+    pub deadline: bool,
+}
+
+/// What the piece picker would request next, in order.
+#[derive(Serialize, Debug)]
+pub struct PickerPlan {
+    pub live_peers: usize,
+    pub pieces: Vec<PlannedPiece>,
+    /// Picks the first piece of a newly connected peer instead of the order above. Unused
+    /// in sequential mode and with contiguous storage.
+    pub first_piece_strategy: FirstPieceStrategy,
+    /// See [crate::SessionOptions::rarest_first_randomness].
+    pub rarest_first_randomness: Option<u32>,
+}
+
+// The order reserve_next_needed_piece() goes through the needed pieces in: those with a
+// playback deadline, earliest first, then the others in order.
+fn plan_order(
+    deadline_pieces: &[usize],
+    needed: impl Iterator<Item = usize>,
+    n: usize,
+) -> Vec<usize> {
+    deadline_pieces
+        .iter()
+        .copied()
+        .chain(needed.filter(|p| !deadline_pieces.contains(p)))
+        .take(n)
+        .collect()
+}
+
+impl TorrentStateLive {
+    /// Snapshot the next "n" pieces the picker would reserve. Nothing gets reserved.
+    ///
+    /// Each peer picks the first of these it has, so a piece with no peers is skipped until
+    /// one shows up.
+    pub fn picker_plan(&self, n: usize) -> anyhow::Result<PickerPlan> {
+        // Release the lock before looking at peers, reserve_next_needed_piece() takes them
+        // in the opposite order.
+        let (deadline_pieces, planned): (Vec<usize>, Vec<usize>) = {
+            let g = self.lock_read("picker_plan");
+            let deadline_pieces: Vec<usize> = g
+                .needed_deadline_pieces()?
+                .into_iter()
+                .map(|p| p.get() as usize)
+                .collect();
+            let needed = g.get_chunks()?.iter_needed_pieces();
+            let planned = plan_order(&deadline_pieces, needed, n);
+            (deadline_pieces, planned)
+        };
+
+        let mut plan = PickerPlan {
+            live_peers: 0,
+            pieces: planned
+                .iter()
+                .map(|piece| PlannedPiece {
+                    piece: *piece as u32,
+                    peers: Vec::new(),
+                    availability: 0,
+                    deadline: deadline_pieces.contains(piece),
+                })
+                .collect(),
+            first_piece_strategy: self.meta.options.first_piece_strategy,
+            rarest_first_randomness: self.meta.options.rarest_first_randomness,
+        };
+
+        for peer in self.peers.states.iter() {
+            let live = match peer.value().state.get_live() {
+                Some(live) => live,
+                None => continue,
+            };
+            plan.live_peers += 1;
+            for (planned, piece) in plan.pieces.iter_mut().zip(planned.iter()) {
+                if live.bitfield.get(*piece).map(|v| *v) == Some(true) {
+                    planned.peers.push(peer.key().to_string());
+                    planned.availability += 1;
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plan_order;
+
+    #[test]
+    fn test_plan_order() {
+        let needed = || [1, 2, 3, 5, 8].into_iter();
+        assert_eq!(plan_order(&[], needed(), 3), vec![1, 2, 3]);
+        // Deadlines go first, and aren't listed again.
+        assert_eq!(plan_order(&[8, 3], needed(), 4), vec![8, 3, 1, 2]);
+        assert_eq!(plan_order(&[8, 3], needed(), 10), vec![8, 3, 1, 2, 5]);
+        assert_eq!(plan_order(&[8, 3], needed(), 1), vec![8]);
+    }
+}
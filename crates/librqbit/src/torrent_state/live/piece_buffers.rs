@@ -0,0 +1,166 @@
+// Where the chunks of the pieces being downloaded go, see SessionOptions::verify_before_write
+// and SessionOptions::verify_from_memory. Whether a piece is kept in memory is decided when its
+// first chunk arrives, and holds until the piece is complete, so that a piece is never half in
+// memory and half on disk. A piece nobody downloads anymore, e.g. as its peer died, gives its
+// memory back right away, and is downloaded again from scratch.
+
+use std::collections::HashMap;
+
+use librqbit_core::lengths::{ChunkInfo, ValidPieceIndex};
+use parking_lot::Mutex;
+
+use crate::verify_from_memory::PieceMemoryReservation;
+
+use super::{TorrentStateLive, TorrentStateLocked};
+
+pub(crate) struct BufferedPiece {
+    pub data: Vec<u8>,
+    // None with verify_before_write, which isn't capped.
+    _reservation: Option<PieceMemoryReservation>,
+}
+
+pub(crate) enum PieceBuffer {
+    Memory(BufferedPiece),
+    // Written chunk by chunk, and read back to verify.
+    Disk,
+}
+
+#[derive(Default)]
+pub(crate) struct PieceBuffers(Mutex<HashMap<ValidPieceIndex, PieceBuffer>>);
+
+impl PieceBuffers {
+    // Copies the chunk into its piece's buffer, creating it with "new" for the first chunk.
+    // False if the piece goes to disk instead.
+    fn buffer_chunk(
+        &self,
+        chunk_info: &ChunkInfo,
+        block: &[u8],
+        new: impl FnOnce() -> PieceBuffer,
+    ) -> bool {
+        let mut buffers = self.0.lock();
+        match buffers.entry(chunk_info.piece_index).or_insert_with(new) {
+            PieceBuffer::Memory(piece) => {
+                let offset = chunk_info.offset as usize;
+                piece.data[offset..offset + chunk_info.size as usize].copy_from_slice(block);
+                true
+            }
+            PieceBuffer::Disk => false,
+        }
+    }
+
+    fn take(&self, piece: ValidPieceIndex) -> Option<BufferedPiece> {
+        match self.0.lock().remove(&piece)? {
+            PieceBuffer::Memory(piece) => Some(piece),
+            PieceBuffer::Disk => None,
+        }
+    }
+
+    // Drops the piece's buffer. True if the chunks received so far were only in memory, so
+    // they are gone.
+    fn release(&self, piece: ValidPieceIndex) -> bool {
+        self.take(piece).is_some()
+    }
+}
+
+impl TorrentStateLive {
+    fn new_piece_buffer(&self, piece: ValidPieceIndex) -> PieceBuffer {
+        let len = self.lengths.piece_length(piece);
+        let reservation = if self.meta.options.verify_before_write {
+            None
+        } else {
+            match self
+                .meta
+                .options
+                .verify_from_memory
+                .as_ref()
+                .and_then(|budget| budget.try_reserve(len as u64))
+            {
+                Some(reservation) => Some(reservation),
+                None => return PieceBuffer::Disk,
+            }
+        };
+        PieceBuffer::Memory(BufferedPiece {
+            data: vec![0u8; len as usize],
+            _reservation: reservation,
+        })
+    }
+
+    // Copies the chunk into its piece's buffer. False if the piece goes to disk instead.
+    pub(crate) fn buffer_chunk(&self, chunk_info: &ChunkInfo, block: &[u8]) -> bool {
+        let options = &self.meta.options;
+        if !options.verify_before_write && options.verify_from_memory.is_none() {
+            return false;
+        }
+        self.piece_buffers.buffer_chunk(chunk_info, block, || {
+            self.new_piece_buffer(chunk_info.piece_index)
+        })
+    }
+
+    // The complete piece, if it was kept in memory. Forgets about the piece either way, so
+    // that it's decided again if it has to be downloaded again.
+    pub(crate) fn take_piece_buffer(&self, piece: ValidPieceIndex) -> Option<BufferedPiece> {
+        self.piece_buffers.take(piece)
+    }
+
+    // For a piece given back to the other peers before it was complete. If its chunks were
+    // only in memory, they all have to be downloaded again.
+    pub(crate) fn release_piece_buffer(
+        &self,
+        g: &mut TorrentStateLocked,
+        piece: ValidPieceIndex,
+    ) -> anyhow::Result<()> {
+        if self.piece_buffers.release(piece) {
+            g.get_chunks_mut()?.mark_piece_broken_if_not_have(piece);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use librqbit_core::lengths::Lengths;
+
+    use crate::verify_from_memory::{PieceMemoryBudget, VerifyFromMemoryOptions};
+
+    use super::{BufferedPiece, PieceBuffer, PieceBuffers};
+
+    #[test]
+    fn test_released_piece_gives_memory_back() {
+        let lengths = Lengths::new(16384 * 4, 16384 * 2, Some(16384)).unwrap();
+        let budget = Arc::new(PieceMemoryBudget::new(VerifyFromMemoryOptions {
+            max_buffered_bytes: Some(16384 * 2),
+            parallel_reads: None,
+        }));
+        let buffers = PieceBuffers::default();
+        let new = || match budget.try_reserve(16384 * 2) {
+            Some(reservation) => PieceBuffer::Memory(BufferedPiece {
+                data: vec![0u8; 16384 * 2],
+                _reservation: Some(reservation),
+            }),
+            None => PieceBuffer::Disk,
+        };
+        let piece = |n| lengths.validate_piece_index(n).unwrap();
+        let chunk = |p, c| lengths.chunk_info_from_received_data(piece(p), c * 16384, 16384);
+        let block = vec![1u8; 16384];
+
+        assert!(buffers.buffer_chunk(&chunk(0, 0).unwrap(), &block, new));
+        // The budget is used up by piece 0.
+        assert!(!buffers.buffer_chunk(&chunk(1, 0).unwrap(), &block, new));
+        assert_eq!(budget.stats().buffered_bytes, 16384 * 2);
+
+        // e.g. the peer downloading piece 0 died.
+        assert!(buffers.release(piece(0)));
+        assert_eq!(budget.stats().buffered_bytes, 0);
+        // Piece 1 was going to disk, nothing to download again.
+        assert!(!buffers.release(piece(1)));
+        assert!(buffers.0.lock().is_empty());
+
+        // Downloaded again, it fits in memory now.
+        assert!(buffers.buffer_chunk(&chunk(0, 1).unwrap(), &block, new));
+        let taken = buffers.take(piece(0)).unwrap();
+        assert_eq!(&taken.data[..16384], &[0u8; 16384][..]);
+        assert_eq!(&taken.data[16384..], &block[..]);
+    }
+}
@@ -0,0 +1,160 @@
+// How long pieces take from their first request to verification, see
+// crate::api::Api::api_piece_latency. Latencies go into log-scale buckets, four per doubling,
+// so percentiles are accurate to within a bucket (about 19%). Only the last
+// PIECE_LATENCY_WINDOW pieces count, older ones are taken out of their buckets again.
+
+use std::{collections::VecDeque, time::Duration};
+
+use serde::Serialize;
+
+const PIECE_LATENCY_WINDOW: usize = 4096;
+const BUCKETS_PER_DOUBLING: f64 = 4.;
+// The last bucket also holds everything above 2^24ms, i.e. about 4.5 hours.
+const BUCKETS: usize = 24 * BUCKETS_PER_DOUBLING as usize + 1;
+
+fn bucket_of(latency_ms: u64) -> usize {
+    if latency_ms <= 1 {
+        return 0;
+    }
+    let bucket = ((latency_ms as f64).log2() * BUCKETS_PER_DOUBLING).ceil() as usize;
+    bucket.min(BUCKETS - 1)
+}
+
+// The highest latency that goes into the bucket.
+fn bucket_upper_ms(bucket: usize) -> u64 {
+    2f64.powf(bucket as f64 / BUCKETS_PER_DOUBLING).floor() as u64
+}
+
+/// One bucket of [PieceLatencyStats::buckets].
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PieceLatencyBucket {
+    /// The pieces that took at most this long, and longer than the previous bucket's.
+    pub le_ms: u64,
+    pub pieces: u32,
+}
+
+/// The download times of a torrent's recently verified pieces.
+#[derive(Serialize, Debug, Default)]
+pub struct PieceLatencyStats {
+    /// How many pieces the numbers are about, at most the last 4096.
+    pub pieces: usize,
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+    /// Only the ones with pieces in them.
+    pub buckets: Vec<PieceLatencyBucket>,
+}
+
+pub(crate) struct PieceLatencyHistogram {
+    counts: [u32; BUCKETS],
+    // The bucket of each piece in the window, oldest first.
+    recent: VecDeque<u8>,
+}
+
+impl Default for PieceLatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            recent: VecDeque::with_capacity(PIECE_LATENCY_WINDOW),
+        }
+    }
+}
+
+impl PieceLatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        if self.recent.len() >= PIECE_LATENCY_WINDOW {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.counts[oldest as usize] -= 1;
+            }
+        }
+        let bucket = bucket_of(latency.as_millis() as u64);
+        self.counts[bucket] += 1;
+        self.recent.push_back(bucket as u8);
+    }
+
+    // The upper bound of the bucket the "p"th fraction of the pieces is in.
+    fn percentile(&self, p: f64) -> Option<u64> {
+        let total = self.recent.len();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((p * total as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += *count as usize;
+            if seen >= rank {
+                return Some(bucket_upper_ms(bucket));
+            }
+        }
+        None
+    }
+
+    pub fn stats(&self) -> PieceLatencyStats {
+        PieceLatencyStats {
+            pieces: self.recent.len(),
+            p50_ms: self.percentile(0.5),
+            p90_ms: self.percentile(0.9),
+            p99_ms: self.percentile(0.99),
+            max_ms: self.percentile(1.),
+            buckets: self
+                .counts
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(bucket, count)| PieceLatencyBucket {
+                    le_ms: bucket_upper_ms(bucket),
+                    pieces: *count,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bucket_of, bucket_upper_ms, PieceLatencyHistogram, PIECE_LATENCY_WINDOW};
+
+    #[test]
+    fn test_buckets() {
+        for ms in [0, 1, 2, 3, 100, 1000, 65_432, 10_000_000] {
+            let bucket = bucket_of(ms);
+            assert!(ms <= bucket_upper_ms(bucket).max(1), "{ms}");
+            if bucket > 0 {
+                assert!(ms > bucket_upper_ms(bucket - 1), "{ms}");
+            }
+        }
+        assert_eq!(bucket_upper_ms(bucket_of(1024)), 1024);
+    }
+
+    #[test]
+    fn test_piece_latency_histogram() {
+        let mut h = PieceLatencyHistogram::default();
+        assert_eq!(h.stats().p50_ms, None);
+
+        for _ in 0..90 {
+            h.record(Duration::from_millis(100));
+        }
+        for _ in 0..10 {
+            h.record(Duration::from_secs(4));
+        }
+        let stats = h.stats();
+        assert_eq!(stats.pieces, 100);
+        assert_eq!(stats.p50_ms, Some(bucket_upper_ms(bucket_of(100))));
+        assert_eq!(stats.p90_ms, Some(bucket_upper_ms(bucket_of(100))));
+        assert_eq!(stats.p99_ms, Some(4096));
+        assert_eq!(stats.max_ms, Some(4096));
+        assert_eq!(stats.buckets.len(), 2);
+
+        // Old pieces fall out of the window.
+        for _ in 0..PIECE_LATENCY_WINDOW {
+            h.record(Duration::from_millis(100));
+        }
+        let stats = h.stats();
+        assert_eq!(stats.pieces, PIECE_LATENCY_WINDOW);
+        assert_eq!(stats.max_ms, Some(bucket_upper_ms(bucket_of(100))));
+        assert_eq!(stats.buckets.len(), 1);
+    }
+}
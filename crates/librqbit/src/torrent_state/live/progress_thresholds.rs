@@ -0,0 +1,327 @@
+// What a torrent does once enough of it is downloaded, see
+// crate::AddTorrentOptions::pause_at_percent, sequential_after_percent and sample_files. Only
+// verified pieces count, and only the selected files. Each threshold triggers once and is then
+// forgotten, so that e.g. a torrent paused at 10% can be resumed to download the rest, or set
+// back to rarest-first by hand. The ones not reached yet are kept in the session file. The live
+// state can't pause itself, so it asks ManagedTorrent to, the same way fatal errors go.
+
+use std::sync::atomic::Ordering;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::torrent_state::events::TorrentEvent;
+
+use super::TorrentStateLive;
+
+/// What happens once a torrent reaches a [ProgressThreshold].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProgressAction {
+    /// The torrent pauses, see [crate::AddTorrentOptions::pause_at_percent].
+    Pause,
+    /// The torrent switches to sequential, unless it is already, see
+    /// [crate::AddTorrentOptions::sequential_after_percent].
+    SwitchToSequential,
+    /// The sample files are complete and the torrent pauses, see
+    /// [crate::AddTorrentOptions::sample_files]. They are the only selected files, so it's
+    /// always at 100%.
+    SampleComplete { files: Vec<usize> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressThreshold {
+    /// Of the selected bytes, downloaded and verified.
+    pub percent: f64,
+    pub action: ProgressAction,
+}
+
+// Percent of the selected bytes that are downloaded and verified.
+fn selected_progress_percent(total_selected_bytes: u64, left_bytes: u64) -> f64 {
+    if total_selected_bytes == 0 {
+        return 100.;
+    }
+    let have = total_selected_bytes.saturating_sub(left_bytes);
+    have as f64 * 100. / total_selected_bytes as f64
+}
+
+// Removes the thresholds "progress" reached from "thresholds", and returns them in order.
+fn take_reached(thresholds: &mut Vec<ProgressThreshold>, progress: f64) -> Vec<ProgressThreshold> {
+    let (reached, left): (Vec<_>, Vec<_>) = std::mem::take(thresholds)
+        .into_iter()
+        .partition(|t| progress >= t.percent);
+    *thresholds = left;
+    reached
+}
+
+// The percent of the first threshold with an action like "action".
+pub(crate) fn threshold_percent(
+    thresholds: &[ProgressThreshold],
+    action: &ProgressAction,
+) -> Option<f64> {
+    thresholds
+        .iter()
+        .find(|t| std::mem::discriminant(&t.action) == std::mem::discriminant(action))
+        .map(|t| t.percent)
+}
+
+pub(crate) fn sample_files(thresholds: &[ProgressThreshold]) -> Option<Vec<usize>> {
+    thresholds.iter().find_map(|t| match &t.action {
+        ProgressAction::SampleComplete { files } => Some(files.clone()),
+        _ => None,
+    })
+}
+
+impl TorrentStateLive {
+    // Called when the torrent goes live and on every verified piece.
+    pub(crate) fn check_progress_thresholds(&self) {
+        let mut thresholds = self.meta.progress_thresholds.write();
+        if thresholds.is_empty() {
+            return;
+        }
+        let progress = selected_progress_percent(
+            self.get_total_selected_bytes(),
+            self.get_left_to_download_bytes(),
+        );
+        let reached = take_reached(&mut thresholds, progress);
+        drop(thresholds);
+
+        for ProgressThreshold { percent, action } in reached {
+            let pause = match &action {
+                ProgressAction::Pause => {
+                    info!(percent, progress, "downloaded enough, pausing");
+                    true
+                }
+                ProgressAction::SwitchToSequential => {
+                    if self.meta.sequential.swap(true, Ordering::Relaxed) {
+                        continue;
+                    }
+                    info!(
+                        percent,
+                        progress, "near completion, switching to sequential"
+                    );
+                    false
+                }
+                ProgressAction::SampleComplete { files } => {
+                    info!(?files, "sample files downloaded, pausing");
+                    true
+                }
+            };
+            let _ = self
+                .meta
+                .events
+                .send(TorrentEvent::ProgressThresholdReached {
+                    percent,
+                    progress,
+                    action,
+                });
+            if pause {
+                self.pause_requested.notify_one();
+            }
+        }
+    }
+
+    pub(crate) async fn wait_for_pause_request(&self) {
+        self.pause_requested.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use peer_binary_protocol::{Message, Piece};
+
+    use crate::{
+        session::{AddTorrent, AddTorrentOptions, SessionOptions},
+        test_util::{
+            add_test_torrent, test_data, test_dir, test_session, test_torrent, RawPeer,
+            TEST_PIECE_LENGTH,
+        },
+        torrent_state::events::TorrentEvent,
+    };
+
+    use super::{
+        sample_files, selected_progress_percent, take_reached, threshold_percent, ProgressAction,
+        ProgressThreshold,
+    };
+
+    #[test]
+    fn test_selected_progress_percent() {
+        assert_eq!(selected_progress_percent(200, 200), 0.);
+        assert_eq!(selected_progress_percent(200, 190), 5.);
+        assert_eq!(selected_progress_percent(200, 0), 100.);
+        // Nothing selected, so there's nothing left to get.
+        assert_eq!(selected_progress_percent(0, 0), 100.);
+    }
+
+    #[test]
+    fn test_take_reached() {
+        let threshold = |percent, action| ProgressThreshold { percent, action };
+        let mut thresholds = vec![
+            threshold(90., ProgressAction::SwitchToSequential),
+            threshold(10., ProgressAction::Pause),
+            threshold(100., ProgressAction::SampleComplete { files: vec![2] }),
+        ];
+        assert!(take_reached(&mut thresholds, 5.).is_empty());
+        assert_eq!(
+            take_reached(&mut thresholds, 10.),
+            vec![threshold(10., ProgressAction::Pause)]
+        );
+        // Only once.
+        assert!(take_reached(&mut thresholds, 50.).is_empty());
+        assert_eq!(
+            threshold_percent(&thresholds, &ProgressAction::SwitchToSequential),
+            Some(90.)
+        );
+        assert_eq!(threshold_percent(&thresholds, &ProgressAction::Pause), None);
+        assert_eq!(sample_files(&thresholds), Some(vec![2]));
+
+        assert_eq!(take_reached(&mut thresholds, 100.).len(), 2);
+        assert!(thresholds.is_empty());
+    }
+
+    #[test]
+    fn test_thresholds_survive_the_session_file() {
+        let thresholds = vec![
+            ProgressThreshold {
+                percent: 50.,
+                action: ProgressAction::Pause,
+            },
+            ProgressThreshold {
+                percent: 100.,
+                action: ProgressAction::SampleComplete { files: vec![0, 3] },
+            },
+        ];
+        let json = serde_json::to_string(&thresholds).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<ProgressThreshold>>(&json).unwrap(),
+            thresholds
+        );
+    }
+
+    #[tokio::test]
+    async fn test_switches_to_sequential_after_percent() {
+        let dir = test_dir("sequential-after-percent");
+        let session = test_session(
+            &dir,
+            SessionOptions {
+                listen_port_range: Some(27300..27400),
+                ..Default::default()
+            },
+        )
+        .await;
+        let data = test_data(TEST_PIECE_LENGTH as usize * 4, 0);
+        let (_, handle) = add_test_torrent(
+            &session,
+            "t",
+            &[("f", &data)],
+            AddTorrentOptions {
+                sequential_after_percent: Some(50.),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(!handle.is_sequential());
+
+        // Serve whatever it asks for until it switches.
+        let port = session.listen_status().port.unwrap();
+        let mut peer = RawPeer::connect(port, handle.info_hash()).await;
+        peer.send(Message::HaveAll).await;
+        peer.send(Message::Unchoke).await;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !handle.is_sequential() {
+                let request =
+                    match tokio::time::timeout(Duration::from_millis(50), peer.read()).await {
+                        Ok(Message::Request(r)) => r,
+                        _ => continue,
+                    };
+                let offset = (request.index * TEST_PIECE_LENGTH + request.begin) as usize;
+                let block = data[offset..offset + request.length as usize].to_vec();
+                peer.send(Message::Piece(Piece::from_data(
+                    request.index,
+                    request.begin,
+                    block,
+                )))
+                .await;
+            }
+        })
+        .await
+        .expect("didn't switch to sequential");
+
+        let stats = handle.stats();
+        assert!(stats.progress_bytes * 2 >= stats.total_bytes);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pauses_once_sample_is_complete() {
+        let dir = test_dir("sample-files");
+        let session = test_session(&dir, Default::default()).await;
+        let a = test_data(TEST_PIECE_LENGTH as usize, 0);
+        let b = test_data(TEST_PIECE_LENGTH as usize, 1);
+        let files: &[(&str, &[u8])] = &[("a", &a), ("b", &b)];
+
+        for opts in [
+            AddTorrentOptions {
+                sample_files: Some(vec![0]),
+                only_files: Some(vec![1]),
+                ..Default::default()
+            },
+            AddTorrentOptions {
+                sample_files: Some(vec![]),
+                ..Default::default()
+            },
+        ] {
+            assert!(session
+                .add_torrent(AddTorrent::from_bytes(test_torrent("t", files)), Some(opts))
+                .await
+                .is_err());
+        }
+
+        // The sample is there already, the rest isn't.
+        std::fs::create_dir_all(dir.join("t")).unwrap();
+        std::fs::write(dir.join("t/a"), &a).unwrap();
+        let (_, handle) = add_test_torrent(
+            &session,
+            "t",
+            files,
+            AddTorrentOptions {
+                paused: true,
+                sample_files: Some(vec![0]),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(handle.sample_files(), Some(vec![0]));
+        assert_eq!(handle.only_files(), Some(vec![0]));
+        let mut events = handle.subscribe_events();
+        session.unpause(&handle).unwrap();
+
+        let action = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let TorrentEvent::ProgressThresholdReached { action, .. } =
+                    events.recv().await.unwrap()
+                {
+                    return action;
+                }
+            }
+        })
+        .await
+        .expect("no sample complete event");
+        assert_eq!(action, ProgressAction::SampleComplete { files: vec![0] });
+        for _ in 0..500 {
+            if handle.is_paused() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handle.is_paused());
+        assert_eq!(handle.sample_files(), None);
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+// Keeping slow peers from hoarding pieces, see SessionOptions::slow_peer_piece_limit. A
+// peer is slow when it scores below OUTSCORED_PEER_FRACTION of the average live peer, as
+// for prefer_good_peers. A slow peer doesn't take new pieces while it holds the limit, and
+// pieces it holds beyond the limit, e.g. taken before it slowed down, can be taken over by
+// peers that aren't slow.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use librqbit_core::lengths::ValidPieceIndex;
+use tokio::time::timeout;
+use tracing::{debug, trace};
+
+use crate::type_aliases::PeerHandle;
+
+use super::{
+    InflightPiece, PeerHandler, TorrentStateLive, TorrentStateLocked, OUTSCORED_PEER_FRACTION,
+};
+
+// How often a slow peer waiting to take more pieces checks again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+// How long a ranking of the live peers is used before it's computed again, as that goes
+// through all peers, and each peer asks before each piece.
+const RANKING_MAX_AGE: Duration = Duration::from_secs(1);
+
+pub(crate) struct PeerRanking {
+    computed_at: Instant,
+    pub average_score: Option<f64>,
+    // Below OUTSCORED_PEER_FRACTION of the average.
+    pub slow: HashSet<PeerHandle>,
+}
+
+impl PeerRanking {
+    fn new(scores: &[(PeerHandle, f64)], now: Instant) -> Self {
+        let average_score = if scores.is_empty() {
+            None
+        } else {
+            Some(scores.iter().map(|(_, s)| s).sum::<f64>() / scores.len() as f64)
+        };
+        let slow = match average_score {
+            Some(avg) => scores
+                .iter()
+                .filter(|(_, score)| *score < avg * OUTSCORED_PEER_FRACTION)
+                .map(|(peer, _)| *peer)
+                .collect(),
+            None => HashSet::new(),
+        };
+        Self {
+            computed_at: now,
+            average_score,
+            slow,
+        }
+    }
+}
+
+// The pieces each slow peer holds beyond the limit, newest first, as those have the least
+// data downloaded.
+fn excess_pieces(
+    inflight: &HashMap<ValidPieceIndex, InflightPiece>,
+    slow: &HashSet<PeerHandle>,
+    limit: usize,
+) -> Vec<ValidPieceIndex> {
+    let mut by_peer: HashMap<PeerHandle, Vec<(Instant, ValidPieceIndex)>> = HashMap::new();
+    for (piece, req) in inflight.iter().filter(|(_, r)| slow.contains(&r.peer)) {
+        by_peer
+            .entry(req.peer)
+            .or_default()
+            .push((req.started, *piece));
+    }
+    let mut excess: Vec<(Instant, ValidPieceIndex)> = by_peer
+        .into_values()
+        .flat_map(|mut pieces| {
+            pieces.sort_by_key(|(started, _)| *started);
+            pieces.into_iter().skip(limit)
+        })
+        .collect();
+    excess.sort_by_key(|(started, _)| std::cmp::Reverse(*started));
+    excess.into_iter().map(|(_, piece)| piece).collect()
+}
+
+impl TorrentStateLocked {
+    // How many in-flight pieces each peer is downloading.
+    pub(crate) fn held_pieces_by_peer(&self) -> HashMap<PeerHandle, u32> {
+        let mut held = HashMap::new();
+        for req in self.inflight_pieces.values() {
+            *held.entry(req.peer).or_default() += 1;
+        }
+        held
+    }
+
+    fn held_pieces_of(&self, peer: PeerHandle) -> usize {
+        self.inflight_pieces
+            .values()
+            .filter(|r| r.peer == peer)
+            .count()
+    }
+}
+
+impl TorrentStateLive {
+    // The scores of the live peers, at most RANKING_MAX_AGE old.
+    pub(crate) fn peer_ranking(&self) -> Arc<PeerRanking> {
+        let now = Instant::now();
+        let mut cached = self.peer_ranking.lock();
+        if let Some(ranking) = cached.as_ref() {
+            if now.duration_since(ranking.computed_at) < RANKING_MAX_AGE {
+                return ranking.clone();
+            }
+        }
+        let scores: Vec<(PeerHandle, f64)> = self
+            .peers
+            .states
+            .iter()
+            .filter(|e| e.value().state.get_live().is_some())
+            .filter_map(|e| Some((*e.key(), e.value().stats.counters.score()?)))
+            .collect();
+        let ranking = Arc::new(PeerRanking::new(&scores, now));
+        *cached = Some(ranking.clone());
+        ranking
+    }
+}
+
+impl PeerHandler {
+    // Returns once this peer may take another piece.
+    pub(super) async fn wait_while_holding_too_many_pieces(&self, limit: usize) {
+        loop {
+            let held = self
+                .state
+                .lock_read("held_pieces_of")
+                .held_pieces_of(self.addr);
+            if held < limit || !self.is_outscored() {
+                return;
+            }
+            trace!(held, "slow peer holding too many pieces, waiting");
+            let _ = timeout(RECHECK_INTERVAL, self.inflight_drained_notify.notified()).await;
+        }
+    }
+
+    // Take over a piece a slow peer holds beyond the limit, if we aren't slow ourselves.
+    pub(super) fn try_steal_excess_piece(&self) -> Option<ValidPieceIndex> {
+        let limit = self.state.meta.options.slow_peer_piece_limit?;
+        if self.is_outscored() {
+            return None;
+        }
+        let mut slow = self.state.peer_ranking().slow.clone();
+        slow.remove(&self.addr);
+        if slow.is_empty() {
+            return None;
+        }
+        let max_duplicates = self.state.meta.options.endgame_max_duplicates;
+        self.state
+            .peers
+            .with_live(self.addr, |live| {
+                let mut g = self.state.lock_write("try_steal_excess_piece");
+                let idx = excess_pieces(&g.inflight_pieces, &slow, limit)
+                    .into_iter()
+                    .filter(|p| live.bitfield.get(p.get() as usize).map(|v| *v) == Some(true))
+                    .find(|p| {
+                        g.inflight_pieces
+                            .get(p)
+                            .map(|r| r.requested_from < max_duplicates)
+                            .unwrap_or(false)
+                    })?;
+                let piece_req = g.inflight_pieces.get_mut(&idx)?;
+                debug!(
+                    "will steal piece {} from {}: slow peer holding more than {} pieces",
+                    idx, piece_req.peer, limit
+                );
+                piece_req.peer = self.addr;
+                piece_req.started = Instant::now();
+                piece_req.requested_from += 1;
+                Some(idx)
+            })
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    use librqbit_core::lengths::Lengths;
+
+    use super::{excess_pieces, InflightPiece, PeerRanking};
+
+    #[test]
+    fn test_peer_ranking() {
+        let peer = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let ranking = PeerRanking::new(
+            &[(peer(1), 10.), (peer(2), 10.), (peer(3), 1.)],
+            Instant::now(),
+        );
+        assert_eq!(ranking.average_score, Some(7.));
+        assert_eq!(ranking.slow, HashSet::from([peer(3)]));
+
+        let empty = PeerRanking::new(&[], Instant::now());
+        assert_eq!(empty.average_score, None);
+        assert!(empty.slow.is_empty());
+    }
+
+    #[test]
+    fn test_excess_pieces_of_slow_peers() {
+        let lengths = Lengths::new(16384 * 10, 16384, None).unwrap();
+        let piece = |i| lengths.validate_piece_index(i).unwrap();
+        let slow: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let fast: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let start = Instant::now();
+        let inflight: HashMap<_, _> = [(0, slow, 0), (1, slow, 1), (2, slow, 2), (3, fast, 3)]
+            .into_iter()
+            .map(|(i, peer, secs)| {
+                (
+                    piece(i),
+                    InflightPiece {
+                        peer,
+                        started: start + Duration::from_secs(secs),
+                        requested_from: 1,
+                    },
+                )
+            })
+            .collect();
+        let slow_peers = HashSet::from([slow]);
+
+        assert_eq!(
+            excess_pieces(&inflight, &slow_peers, 1),
+            vec![piece(2), piece(1)]
+        );
+        assert!(excess_pieces(&inflight, &slow_peers, 3).is_empty());
+        assert!(excess_pieces(&inflight, &HashSet::new(), 0).is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::atomic::AtomicU64;
 
+use crate::file_ops::DiskIoCounters;
+
 #[derive(Default, Debug)]
 pub struct AtomicStats {
     pub have_bytes: AtomicU64,
@@ -8,4 +10,13 @@ pub struct AtomicStats {
     pub uploaded_bytes: AtomicU64,
     pub fetched_bytes: AtomicU64,
     pub total_piece_download_ms: AtomicU64,
+    // Chunk requests for pieces stolen from slower peers, i.e. already requested elsewhere.
+    pub endgame_duplicate_requests: AtomicU64,
+    pub endgame_duplicate_bytes: AtomicU64,
+    // Pieces that weren't downloaded by their playback deadline.
+    pub deadline_misses: AtomicU64,
+    // Of the downloaded and checked pieces, the ones that came from web seeds.
+    pub web_seed_bytes: AtomicU64,
+    pub web_seed_pieces: AtomicU64,
+    pub disk_io: DiskIoCounters,
 }
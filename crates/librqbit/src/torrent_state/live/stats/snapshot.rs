@@ -4,7 +4,7 @@ use serde::Serialize;
 
 use crate::torrent_state::live::peers::stats::snapshot::AggregatePeerStats;
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Default, PartialEq)]
 pub struct StatsSnapshot {
     pub downloaded_and_checked_bytes: u64,
 
@@ -13,6 +13,20 @@ pub struct StatsSnapshot {
 
     pub downloaded_and_checked_pieces: u64,
     pub total_piece_download_ms: u64,
+    pub disk_read_bytes: u64,
+    pub disk_written_bytes: u64,
+    /// Chunks requested again from a faster peer while still in flight from a slower one.
+    /// Some of these bytes are downloaded twice.
+    pub endgame_duplicate_requests: u64,
+    pub endgame_duplicate_bytes: u64,
+    /// Pieces that weren't downloaded by their playback deadline, see
+    /// TorrentStateLive::set_piece_deadlines.
+    pub deadline_misses: u64,
+    /// Where the downloaded and checked pieces came from: web seeds (BEP 19), or peers.
+    pub web_seed_bytes: u64,
+    pub web_seed_pieces: u64,
+    pub peer_bytes: u64,
+    pub peer_pieces: u64,
     pub peer_stats: AggregatePeerStats,
 }
 
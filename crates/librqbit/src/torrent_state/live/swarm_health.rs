@@ -0,0 +1,99 @@
+// Estimating how many full copies of the torrent are out there, e.g. to tell whether it's
+// at risk of disappearing. The connected peers are only a sample of the swarm, and tracker
+// counts may be stale or include us, so all of this is an estimate.
+
+use serde::Serialize;
+
+use super::TorrentStateLive;
+
+/// An estimate of how well the torrent is replicated in the swarm, see
+/// [crate::Api::api_torrent_swarm_health].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SwarmHealth {
+    /// Estimated number of full copies: whichever is more of `distributed_copies` and
+    /// `tracker_seeders`.
+    pub estimated_copies: f64,
+    /// Full copies among the connected peers, not counting us: the availability of the
+    /// rarest piece, plus the fraction of pieces that are more available than that.
+    pub distributed_copies: f64,
+    /// How many connected peers have the rarest piece.
+    pub rarest_piece_availability: u32,
+    /// How many pieces are that rare.
+    pub rarest_pieces: u32,
+    pub connected_peers: u32,
+    /// The most seeders any tracker reported in its last announce. Trackers count us too
+    /// when we're seeding.
+    pub tracker_seeders: Option<u64>,
+    /// Whether we have all the pieces ourselves.
+    pub have_full_copy: bool,
+}
+
+// (distributed copies, rarest availability, pieces that rare) from how many peers have
+// each piece.
+fn distributed_copies(availability: &[u32]) -> (f64, u32, u32) {
+    let rarest = match availability.iter().min() {
+        Some(rarest) => *rarest,
+        None => return (0., 0, 0),
+    };
+    let rarest_pieces = availability.iter().filter(|a| **a == rarest).count();
+    let more_available = availability.len() - rarest_pieces;
+    let copies = rarest as f64 + more_available as f64 / availability.len() as f64;
+    (copies, rarest, rarest_pieces as u32)
+}
+
+impl TorrentStateLive {
+    // How many live peers have each piece, and how many live peers there are. Don't call
+    // this from inside with_live() or with_live_mut(), it goes through all peers.
+    pub(crate) fn piece_availability(&self) -> (Vec<u32>, u32) {
+        let total_pieces = self.lengths.total_pieces() as usize;
+        let mut availability = vec![0u32; total_pieces];
+        let mut connected_peers = 0;
+        for peer in self.peers.states.iter() {
+            let live = match peer.value().state.get_live() {
+                Some(live) => live,
+                None => continue,
+            };
+            connected_peers += 1;
+            for piece in live.bitfield.iter_ones().take_while(|p| *p < total_pieces) {
+                availability[piece] += 1;
+            }
+        }
+        (availability, connected_peers)
+    }
+
+    pub fn swarm_health(&self) -> SwarmHealth {
+        let total_pieces = self.lengths.total_pieces() as usize;
+        let (availability, connected_peers) = self.piece_availability();
+        let (distributed_copies, rarest_piece_availability, rarest_pieces) =
+            distributed_copies(&availability);
+        let tracker_seeders = self.max_tracker_seeders();
+        let have_full_copy = self
+            .lock_read("swarm_health")
+            .get_chunks()
+            .map(|c| c.get_have_pieces().count_ones() == total_pieces)
+            .unwrap_or(false);
+        SwarmHealth {
+            estimated_copies: distributed_copies.max(tracker_seeders.unwrap_or_default() as f64),
+            distributed_copies,
+            rarest_piece_availability,
+            rarest_pieces,
+            connected_peers,
+            tracker_seeders,
+            have_full_copy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::distributed_copies;
+
+    #[test]
+    fn test_distributed_copies() {
+        assert_eq!(distributed_copies(&[]), (0., 0, 0));
+        assert_eq!(distributed_copies(&[2, 2, 2, 2]), (2., 2, 4));
+        // The one piece nobody has makes it less than a full copy.
+        assert_eq!(distributed_copies(&[0, 1, 3, 1]), (0.75, 0, 1));
+        assert_eq!(distributed_copies(&[1, 2, 1, 2]), (1.5, 1, 2));
+    }
+}
@@ -0,0 +1,459 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::warn;
+use url::Url;
+
+use crate::{
+    tracker_comms::{TrackerRequest, TrackerRequestEvent},
+    TrackerHostSchedule,
+};
+
+use super::{peer_events::push_capped, TorrentStateLive, TrackerAnnounce};
+
+// How many announces to remember per torrent, across all its trackers.
+pub(crate) const ANNOUNCE_HISTORY_LEN: usize = 256;
+// Intervals come from the tracker unchecked, this keeps the deadline representable.
+const MAX_ANNOUNCE_WAIT: Duration = Duration::from_secs(365 * 24 * 3600);
+// Shorter seeding announce intervals are rejected, and raised to this when restoring a
+// session from a version that accepted them.
+pub(crate) const MIN_SEEDING_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) fn check_seeding_announce_interval(interval: Option<Duration>) -> anyhow::Result<()> {
+    match interval {
+        Some(interval) if interval < MIN_SEEDING_ANNOUNCE_INTERVAL => anyhow::bail!(
+            "seeding_announce_interval must be at least {MIN_SEEDING_ANNOUNCE_INTERVAL:?}"
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// The state of announcing to one tracker.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TrackerStats {
+    pub url: String,
+    pub announces: u64,
+    /// Unix timestamp of the last successful announce, in seconds.
+    pub last_announce: Option<u64>,
+    /// The interval the tracker asked for, in seconds.
+    pub tracker_interval: Option<u64>,
+    /// The shortest interval the tracker allows, in seconds.
+    pub tracker_min_interval: Option<u64>,
+    /// How long until the next announce after the last one, in seconds, with all overrides
+    /// applied.
+    pub effective_interval: Option<u64>,
+    /// How long until the next announce from now, in seconds.
+    pub next_announce_in: Option<u64>,
+    // When the next announce is due, for next_announce_in.
+    #[serde(skip)]
+    next_announce_at: Option<Instant>,
+    pub last_error: Option<String>,
+    /// How long every announce has been failing for, in seconds.
+    pub failing_for: Option<u64>,
+    #[serde(skip)]
+    failing_since: Option<Instant>,
+    /// Not announced to anymore as it kept failing, see
+    /// [crate::SessionOptions::dead_tracker_timeout] and [crate::ManagedTorrent::revive_tracker].
+    pub dead: bool,
+    /// Seeders in the swarm, as of the last successful announce.
+    pub seeders: Option<u64>,
+    /// Leechers in the swarm, as of the last successful announce.
+    pub leechers: Option<u64>,
+    /// When announces to this tracker's host are spaced out, see
+    /// [crate::SessionOptions::tracker_host_min_gap].
+    pub host_schedule: Option<TrackerHostSchedule>,
+}
+
+/// The event an announce sent to the tracker.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnounceEvent {
+    Started,
+    Stopped,
+    Completed,
+    /// A regular announce, without an event.
+    Periodic,
+}
+
+impl From<Option<TrackerRequestEvent>> for AnnounceEvent {
+    fn from(event: Option<TrackerRequestEvent>) -> Self {
+        match event {
+            Some(TrackerRequestEvent::Started) => AnnounceEvent::Started,
+            Some(TrackerRequestEvent::Stopped) => AnnounceEvent::Stopped,
+            Some(TrackerRequestEvent::Completed) => AnnounceEvent::Completed,
+            None => AnnounceEvent::Periodic,
+        }
+    }
+}
+
+/// One announce to a tracker, with what we reported and what came back.
+#[derive(Serialize, Debug, Clone)]
+pub struct AnnounceRecord {
+    pub url: String,
+    /// Unix timestamp, in seconds.
+    pub time: u64,
+    pub event: AnnounceEvent,
+    /// The byte counts reported to the tracker.
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    /// How many peers the tracker returned. None if the announce failed.
+    pub peers: Option<usize>,
+    pub error: Option<String>,
+}
+
+pub(crate) fn initial_tracker_stats<'a>(
+    urls: impl Iterator<Item = &'a url::Url>,
+) -> BTreeMap<String, TrackerStats> {
+    urls.map(|url| {
+        let url = url.to_string();
+        (
+            url.clone(),
+            TrackerStats {
+                url,
+                ..Default::default()
+            },
+        )
+    })
+    .collect()
+}
+
+impl TrackerStats {
+    fn schedule_next_announce(&mut self, now: Instant, wait: Duration) -> Instant {
+        let at = now + wait.min(MAX_ANNOUNCE_WAIT);
+        self.next_announce_at = Some(at);
+        at
+    }
+
+    fn update_next_announce_in(&mut self, now: Instant) {
+        self.next_announce_in = self
+            .next_announce_at
+            .map(|at| at.saturating_duration_since(now).as_secs());
+        self.failing_for = self
+            .failing_since
+            .map(|since| now.saturating_duration_since(since).as_secs());
+    }
+
+    fn failing_longer_than(&self, timeout: Duration, now: Instant) -> bool {
+        self.failing_since
+            .map(|since| now.saturating_duration_since(since) >= timeout)
+            .unwrap_or(false)
+    }
+}
+
+// Trackers may refuse announces that come more often than their "min interval".
+fn clamp_to_min_interval(interval: Duration, min_interval: Option<u64>) -> Duration {
+    interval.max(Duration::from_secs(min_interval.unwrap_or_default()))
+}
+
+impl TorrentStateLive {
+    /// The announce interval used while seeding: the torrent's own if set, otherwise the
+    /// session's.
+    pub fn seeding_announce_interval(&self) -> Option<Duration> {
+        let own = *self.meta.seeding_announce_interval.read();
+        own.or_else(|| {
+            self.meta
+                .options
+                .seeding_announce_interval
+                .as_ref()
+                .and_then(|rx| *rx.borrow())
+        })
+    }
+
+    // How long to wait before announcing again, given what the tracker asked for.
+    pub(crate) fn next_announce_interval(
+        &self,
+        tracker_interval: u64,
+        tracker_min_interval: Option<u64>,
+    ) -> Duration {
+        if let Some(forced) = self.meta.options.force_tracker_interval {
+            return forced;
+        }
+        if self.is_finished() {
+            if let Some(interval) = self.seeding_announce_interval() {
+                return clamp_to_min_interval(interval, tracker_min_interval);
+            }
+        }
+        self.meta
+            .options
+            .power_profile
+            .announce_interval(Duration::from_secs(tracker_interval))
+    }
+
+    pub(crate) fn record_announce(
+        &self,
+        url: &str,
+        request: &TrackerRequest,
+        result: Result<(&TrackerAnnounce, Duration), &anyhow::Error>,
+    ) {
+        let record = AnnounceRecord {
+            url: url.to_owned(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            event: request.event.into(),
+            uploaded: request.uploaded,
+            downloaded: request.downloaded,
+            left: request.left,
+            peers: result.as_ref().ok().map(|(announce, _)| announce.peers),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        };
+        push_capped(
+            &mut self.announce_history.lock(),
+            record,
+            ANNOUNCE_HISTORY_LEN,
+        );
+
+        let mut g = self.tracker_stats.lock();
+        let stats = g.entry(url.to_owned()).or_insert_with(|| TrackerStats {
+            url: url.to_owned(),
+            ..Default::default()
+        });
+        match result {
+            Ok((announce, effective)) => {
+                stats.announces += 1;
+                stats.last_announce = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .ok();
+                stats.tracker_interval = Some(announce.interval);
+                stats.tracker_min_interval = announce.min_interval;
+                stats.seeders = Some(announce.seeders);
+                stats.leechers = Some(announce.leechers);
+                stats.effective_interval = Some(effective.as_secs());
+                stats.last_error = None;
+                stats.failing_since = None;
+            }
+            Err(e) => {
+                stats.last_error = Some(format!("{e:#}"));
+                stats.failing_since.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    // Called after a failed announce. Returns true if the tracker failed every announce for
+    // longer than SessionOptions::dead_tracker_timeout, and marks it dead.
+    pub(crate) fn check_tracker_dead(&self, announce_url: &Url) -> bool {
+        let timeout = match self.meta.options.dead_tracker_timeout {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+        let failing = self
+            .tracker_stats
+            .lock()
+            .get(announce_url.as_str())
+            .map(|s| s.failing_longer_than(timeout, Instant::now()))
+            .unwrap_or(false);
+        if failing {
+            warn!(
+                tracker = %announce_url,
+                "every announce failed for {:?}, not announcing to the tracker anymore", timeout
+            );
+            self.meta.dead_trackers.write().insert(announce_url.clone());
+        }
+        failing
+    }
+
+    // Returns once the tracker isn't dead, see ManagedTorrent::revive_tracker.
+    pub(crate) async fn wait_while_tracker_dead(&self, announce_url: &Url) {
+        let mut was_dead = false;
+        loop {
+            let revived = self.meta.tracker_revived.notified();
+            if !self.meta.dead_trackers.read().contains(announce_url) {
+                break;
+            }
+            was_dead = true;
+            revived.await;
+        }
+        if was_dead {
+            if let Some(stats) = self.tracker_stats.lock().get_mut(announce_url.as_str()) {
+                stats.failing_since = None;
+            }
+        }
+    }
+
+    // Picks the deadline of the next announce to "url", "wait" from now, and records it.
+    pub(crate) fn schedule_next_announce(&self, url: &str, wait: Duration) -> Instant {
+        self.tracker_stats
+            .lock()
+            .entry(url.to_owned())
+            .or_insert_with(|| TrackerStats {
+                url: url.to_owned(),
+                ..Default::default()
+            })
+            .schedule_next_announce(Instant::now(), wait)
+    }
+
+    /// The last few announces to any of the torrent's trackers, oldest first, e.g. to check
+    /// what was reported to a private tracker.
+    pub fn announce_history(&self) -> Vec<AnnounceRecord> {
+        self.announce_history.lock().iter().cloned().collect()
+    }
+
+    /// The most seeders any tracker reported in its last successful announce.
+    pub(crate) fn max_tracker_seeders(&self) -> Option<u64> {
+        self.tracker_stats
+            .lock()
+            .values()
+            .filter_map(|s| s.seeders)
+            .max()
+    }
+
+    /// Announce state of every tracker of the torrent, sorted by URL.
+    pub fn tracker_stats(&self) -> Vec<TrackerStats> {
+        let mut stats: Vec<TrackerStats> = self.tracker_stats.lock().values().cloned().collect();
+        let now = Instant::now();
+        let dead = self.meta.dead_trackers.read();
+        for s in stats.iter_mut() {
+            s.update_next_announce_in(now);
+            s.dead = Url::parse(&s.url)
+                .map(|url| dead.contains(&url))
+                .unwrap_or(false);
+        }
+        drop(dead);
+        if let Some(scheduler) = self.meta.options.tracker_host_scheduler.as_ref() {
+            let redirects = self.meta.tracker_redirects.read();
+            for s in stats.iter_mut() {
+                let url = match url::Url::parse(&s.url) {
+                    Ok(url) => redirects.get(&url).cloned().unwrap_or(url),
+                    Err(_) => continue,
+                };
+                s.host_schedule = scheduler.host_schedule(url.host_str().unwrap_or_default());
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        check_seeding_announce_interval, clamp_to_min_interval, AnnounceEvent, TrackerStats,
+    };
+    use crate::{
+        session::{AddTorrent, AddTorrentResponse},
+        test_util::{
+            http_response, request_path, serve_http, test_data, test_dir, test_session,
+            test_torrent_with_announce, wait_until_live,
+        },
+        torrent_state::ManagedTorrentHandle,
+    };
+
+    #[test]
+    fn test_check_seeding_announce_interval() {
+        assert!(check_seeding_announce_interval(None).is_ok());
+        assert!(check_seeding_announce_interval(Some(Duration::from_secs(3600))).is_ok());
+        assert!(check_seeding_announce_interval(Some(Duration::ZERO)).is_err());
+        assert!(check_seeding_announce_interval(Some(Duration::from_secs(59))).is_err());
+    }
+
+    #[test]
+    fn test_clamp_to_min_interval() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(clamp_to_min_interval(hour, None), hour);
+        assert_eq!(clamp_to_min_interval(hour, Some(600)), hour);
+        assert_eq!(
+            clamp_to_min_interval(Duration::from_secs(60), Some(600)),
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn test_next_announce_in() {
+        let secs = Duration::from_secs;
+        let start = Instant::now();
+        let mut stats = TrackerStats::default();
+        stats.update_next_announce_in(start);
+        assert_eq!(stats.next_announce_in, None);
+
+        let due = stats.schedule_next_announce(start, secs(1800));
+        assert_eq!(due, start + secs(1800));
+        stats.update_next_announce_in(start + secs(600));
+        assert_eq!(stats.next_announce_in, Some(1200));
+
+        // Overdue reads as 0, not a huge wrapped number.
+        stats.update_next_announce_in(start + secs(4000));
+        assert_eq!(stats.next_announce_in, Some(0));
+    }
+
+    #[test]
+    fn test_failing_longer_than() {
+        let secs = Duration::from_secs;
+        let start = Instant::now();
+        let mut stats = TrackerStats::default();
+        assert!(!stats.failing_longer_than(secs(3600), start + secs(7200)));
+
+        stats.failing_since = Some(start);
+        stats.update_next_announce_in(start + secs(600));
+        assert_eq!(stats.failing_for, Some(600));
+        assert!(!stats.failing_longer_than(secs(3600), start + secs(600)));
+        assert!(stats.failing_longer_than(secs(3600), start + secs(3600)));
+    }
+
+    #[tokio::test]
+    async fn test_announce_history() {
+        let dir = test_dir("announce-history");
+        let addr = serve_http(|request| match request_path(request) {
+            // One peer, which refuses connections.
+            "/ok" => http_response(
+                "200 OK",
+                &[],
+                b"d8:intervali3600e5:peers6:\x7f\x00\x00\x01\x00\x01e",
+            ),
+            _ => http_response("500 Internal Server Error", &[], b""),
+        })
+        .await;
+        let session = test_session(&dir, Default::default()).await;
+        let add = |path: &str, seed: u8| {
+            let torrent = test_torrent_with_announce(
+                path,
+                &[("f", &test_data(100, seed))],
+                Some(&format!("http://{addr}/{path}")),
+            );
+            let session = session.clone();
+            async move {
+                match session
+                    .add_torrent(AddTorrent::from_bytes(torrent), None)
+                    .await
+                    .unwrap()
+                {
+                    AddTorrentResponse::Added(_, handle) => handle,
+                    _ => panic!("expected the torrent to be added"),
+                }
+            }
+        };
+        let first_announce = |handle: ManagedTorrentHandle| async move {
+            wait_until_live(&handle).await;
+            let live = handle.live().unwrap();
+            for _ in 0..500 {
+                if !live.announce_history().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            live.announce_history().remove(0)
+        };
+
+        let ok = first_announce(add("ok", 0).await).await;
+        assert_eq!(ok.url, format!("http://{addr}/ok"));
+        assert_eq!(ok.event, AnnounceEvent::Started);
+        assert_eq!((ok.uploaded, ok.downloaded, ok.left), (0, 0, 100));
+        assert_eq!(ok.peers, Some(1));
+        assert!(ok.error.is_none());
+        assert!(ok.time > 0);
+
+        let bad = first_announce(add("bad", 1).await).await;
+        assert_eq!(bad.url, format!("http://{addr}/bad"));
+        assert_eq!(bad.peers, None);
+        assert!(bad.error.unwrap().contains("500"));
+
+        session.stop().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+// Limiting how many peers a torrent uploads to at a time, see
+// ManagedTorrent::set_upload_slots. Without a limit every peer we may upload to is
+// unchoked right after the handshake. With one, an interested peer gets a free slot right
+// away, or waits in line for one. A slot is handed on once its peer isn't interested anymore
+// or disconnects, and every UPLOAD_SLOT_ROTATION_INTERVAL the slot held the longest goes to
+// the first peer in line, so that holders that stay interested but download little don't
+// keep the others waiting forever. Each of these only touches the peers involved, all peers
+// are gone through only when the torrent gets locked or the limit changes.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use peer_binary_protocol::MessageOwned;
+use tracing::debug;
+
+use crate::{peer_connection::WriterRequest, type_aliases::PeerHandle};
+
+use super::{peer::PeerState, TorrentStateLive};
+
+const UPLOAD_SLOT_ROTATION_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub(crate) struct UploadSlots {
+    // Held the longest first.
+    holders: VecDeque<PeerHandle>,
+    // Interested peers without a slot, first come first served. Always empty without a limit.
+    waiting: VecDeque<PeerHandle>,
+}
+
+impl UploadSlots {
+    fn is_full(&self, slots: Option<usize>) -> bool {
+        slots.map(|s| self.holders.len() >= s).unwrap_or(false)
+    }
+
+    // The peer may get a slot now. True if it got one, otherwise it waits in line.
+    fn want(&mut self, addr: PeerHandle, slots: Option<usize>) -> bool {
+        if self.holders.contains(&addr) || self.waiting.contains(&addr) {
+            return false;
+        }
+        if self.is_full(slots) {
+            self.waiting.push_back(addr);
+            return false;
+        }
+        self.holders.push_back(addr);
+        true
+    }
+
+    // The peer doesn't want a slot anymore. Returns whether it held one, and the peers in line
+    // that got the slots free now.
+    fn leave(&mut self, addr: PeerHandle, slots: Option<usize>) -> (bool, Vec<PeerHandle>) {
+        self.waiting.retain(|w| *w != addr);
+        let held = match self.holders.iter().position(|h| *h == addr) {
+            Some(pos) => {
+                self.holders.remove(pos);
+                true
+            }
+            None => false,
+        };
+        let mut granted = Vec::new();
+        while !self.is_full(slots) {
+            match self.waiting.pop_front() {
+                Some(next) => {
+                    self.holders.push_back(next);
+                    granted.push(next);
+                }
+                None => break,
+            }
+        }
+        (held, granted)
+    }
+
+    // Hands the slot held the longest to the first peer in line, and puts its holder at the
+    // back of the line. Returns the (choked, unchoked) peers, None if nobody is waiting.
+    fn rotate(&mut self) -> Option<(PeerHandle, PeerHandle)> {
+        if self.waiting.is_empty() {
+            return None;
+        }
+        let choked = self.holders.pop_front()?;
+        let unchoked = self.waiting.pop_front()?;
+        self.holders.push_back(unchoked);
+        self.waiting.push_back(choked);
+        Some((choked, unchoked))
+    }
+
+    // Starts over from "candidates", the peers that may have a slot now. The ones holding a
+    // slot keep it, then the ones in line get one, then the rest in order. Returns the
+    // (choked, unchoked) peers.
+    fn reassign(
+        &mut self,
+        candidates: &[PeerHandle],
+        slots: Option<usize>,
+    ) -> (Vec<PeerHandle>, Vec<PeerHandle>) {
+        let old_holders = std::mem::take(&mut self.holders);
+        let old_waiting = std::mem::take(&mut self.waiting);
+        let is_candidate = candidates.iter().copied().collect::<HashSet<_>>();
+        for addr in old_holders
+            .iter()
+            .chain(old_waiting.iter())
+            .chain(candidates.iter())
+        {
+            if is_candidate.contains(addr) {
+                self.want(*addr, slots);
+            }
+        }
+        let choked = old_holders
+            .iter()
+            .filter(|a| !self.holders.contains(a))
+            .copied()
+            .collect();
+        let unchoked = self
+            .holders
+            .iter()
+            .filter(|a| !old_holders.contains(a))
+            .copied()
+            .collect();
+        (choked, unchoked)
+    }
+}
+
+impl TorrentStateLive {
+    pub(crate) fn holds_upload_slot(&self, addr: PeerHandle) -> bool {
+        self.upload_slots.lock().holders.contains(&addr)
+    }
+
+    /// Peers unchoked, i.e. holding an upload slot.
+    pub(crate) fn upload_slots_used(&self) -> usize {
+        self.upload_slots.lock().holders.len()
+    }
+
+    fn send_choke_message(&self, addr: PeerHandle, msg: MessageOwned) {
+        if let Some(tx) = self.peers.with_live(addr, |live| live.tx.clone()) {
+            let _ = tx.send(WriterRequest::Message(msg));
+        }
+    }
+
+    // For a peer that just became live without a limit, or interested with one. Don't call
+    // this or leave_upload_slot() while holding a peer table entry.
+    pub(crate) fn want_upload_slot(&self, addr: PeerHandle) {
+        if !self.may_upload_to(addr) {
+            return;
+        }
+        let slots = self.meta.upload_slots();
+        if self.upload_slots.lock().want(addr, slots) {
+            self.send_choke_message(addr, MessageOwned::Unchoke);
+        }
+    }
+
+    // For a peer that isn't interested anymore, or is "gone", i.e. disconnected.
+    pub(crate) fn leave_upload_slot(&self, addr: PeerHandle, gone: bool) {
+        let slots = self.meta.upload_slots();
+        let (held, granted) = self.upload_slots.lock().leave(addr, slots);
+        if held && !gone {
+            self.send_choke_message(addr, MessageOwned::Choke);
+        }
+        for next in granted {
+            self.send_choke_message(next, MessageOwned::Unchoke);
+        }
+    }
+
+    // Chokes or unchokes all the live peers after who may get a slot changed for all of
+    // them, i.e. the torrent got locked or unlocked, or the limit changed. Don't call this
+    // while holding a peer table entry, it goes through all peers.
+    pub(crate) fn update_peer_chokes(&self) {
+        let slots = self.meta.upload_slots();
+        let mut candidates = Vec::new();
+        let mut live_peers = HashMap::new();
+        for pe in self.peers.states.iter() {
+            if let PeerState::Live(live) = pe.value().state.get() {
+                let addr = *pe.key();
+                if self.may_upload_to(addr) && (slots.is_none() || live.peer_interested) {
+                    candidates.push(addr);
+                }
+                live_peers.insert(addr, live.tx.clone());
+            }
+        }
+
+        let (choked, unchoked) = self.upload_slots.lock().reassign(&candidates, slots);
+        let messages = choked
+            .into_iter()
+            .map(|addr| (addr, MessageOwned::Choke))
+            .chain(
+                unchoked
+                    .into_iter()
+                    .map(|addr| (addr, MessageOwned::Unchoke)),
+            );
+        for (addr, msg) in messages {
+            if let Some(tx) = live_peers.get(&addr) {
+                let _ = tx.send(WriterRequest::Message(msg));
+            }
+        }
+    }
+
+    pub(crate) async fn task_rotate_upload_slots(self: Arc<Self>) -> anyhow::Result<()> {
+        let state = Arc::downgrade(&self);
+        drop(self);
+        loop {
+            tokio::time::sleep(UPLOAD_SLOT_ROTATION_INTERVAL).await;
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return Ok(()),
+            };
+            let rotated = state.upload_slots.lock().rotate();
+            if let Some((choked, unchoked)) = rotated {
+                debug!(%choked, %unchoked, "rotating upload slot");
+                state.send_choke_message(choked, MessageOwned::Choke);
+                state.send_choke_message(unchoked, MessageOwned::Unchoke);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::UploadSlots;
+
+    fn addr(p: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], p))
+    }
+
+    #[test]
+    fn test_want_and_leave() {
+        let mut slots = UploadSlots::default();
+        assert!(slots.want(addr(1), Some(2)));
+        assert!(slots.want(addr(2), Some(2)));
+        assert!(!slots.want(addr(3), Some(2)));
+        assert!(!slots.want(addr(4), Some(2)));
+        // Asking again keeps the place in line.
+        assert!(!slots.want(addr(3), Some(2)));
+        assert_eq!(slots.waiting, [addr(3), addr(4)]);
+
+        // e.g. it has all it wanted, and isn't interested anymore.
+        assert_eq!(slots.leave(addr(1), Some(2)), (true, vec![addr(3)]));
+        assert_eq!(slots.leave(addr(4), Some(2)), (false, vec![]));
+        assert_eq!(slots.holders, [addr(2), addr(3)]);
+        assert!(slots.waiting.is_empty());
+
+        // Unlimited.
+        let mut slots = UploadSlots::default();
+        assert!((1..100).all(|p| slots.want(addr(p), None)));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut slots = UploadSlots::default();
+        for p in 1..=3 {
+            slots.want(addr(p), Some(2));
+        }
+        assert_eq!(slots.rotate(), Some((addr(1), addr(3))));
+        assert_eq!(slots.holders, [addr(2), addr(3)]);
+        assert_eq!(slots.rotate(), Some((addr(2), addr(1))));
+        assert_eq!(slots.holders, [addr(3), addr(1)]);
+        assert_eq!(slots.waiting, [addr(2)]);
+
+        slots.leave(addr(2), Some(2));
+        assert_eq!(slots.rotate(), None);
+    }
+
+    #[test]
+    fn test_reassign() {
+        let candidates = [addr(1), addr(2), addr(3)];
+        let mut slots = UploadSlots::default();
+        assert_eq!(
+            slots.reassign(&candidates, None),
+            (vec![], candidates.to_vec())
+        );
+
+        let mut slots = UploadSlots::default();
+        assert_eq!(
+            slots.reassign(&candidates, Some(2)),
+            (vec![], vec![addr(1), addr(2)])
+        );
+        assert_eq!(slots.waiting, [addr(3)]);
+
+        // Holders keep their slot, and ones that aren't candidates anymore lose it. The one
+        // in line is next.
+        slots.holders = [addr(3), addr(4)].into();
+        slots.waiting = [addr(2)].into();
+        assert_eq!(
+            slots.reassign(&candidates, Some(2)),
+            (vec![addr(4)], vec![addr(2)])
+        );
+        assert_eq!(slots.holders, [addr(3), addr(2)]);
+        assert_eq!(slots.waiting, [addr(1)]);
+
+        // e.g. the torrent got locked.
+        assert_eq!(
+            slots.reassign(&[], Some(2)),
+            (vec![addr(3), addr(2)], vec![])
+        );
+        assert!(slots.waiting.is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+// Handling pieces that fail their hash check, see crate::SessionOptions::verification_mode.
+// With VerificationMode::LogOnly, the peer each chunk came from is remembered until its piece
+// is checked, so that a failure can tell who sent which bytes. Failed pieces are downloaded
+// again in both modes.
+
+use std::{
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use librqbit_core::lengths::{ChunkInfo, ValidPieceIndex};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::type_aliases::PeerHandle;
+
+use super::{peer_events::push_capped, TorrentStateLive};
+
+// How many failures to remember per torrent.
+pub(crate) const VERIFICATION_FAILURES_HISTORY: usize = 256;
+
+/// What to do about pieces that fail verification, see
+/// [crate::SessionOptions::verification_mode].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMode {
+    /// Count the failures towards poisoned piece detection, see
+    /// [crate::SessionOptions::poisoned_piece_threshold].
+    #[default]
+    Strict,
+    /// For diagnosing corrupt torrents. Failures don't count towards poisoned piece
+    /// detection. Instead, each one is recorded with the peers that sent the piece's chunks,
+    /// see [crate::api::Api::api_verification_failures].
+    LogOnly,
+}
+
+/// Who sent a chunk of a failed piece.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSource {
+    /// Offset within the piece.
+    pub offset: u32,
+    pub size: u32,
+    pub peer: SocketAddr,
+}
+
+/// The part of a file a failed piece covers.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FailedPieceFileRange {
+    pub file: usize,
+    /// Offset within the file.
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VerificationFailure {
+    pub piece: u32,
+    /// Unix timestamp, in seconds.
+    pub time: u64,
+    /// Offset of the piece in the torrent.
+    pub offset: u64,
+    pub len: u32,
+    pub files: Vec<FailedPieceFileRange>,
+    /// By offset. A piece from a web seed is a single chunk.
+    pub chunks: Vec<ChunkSource>,
+}
+
+// The parts of the files the bytes at offset..offset + len of the torrent are in.
+fn file_ranges(
+    file_lengths: impl Iterator<Item = u64>,
+    offset: u64,
+    len: u64,
+) -> Vec<FailedPieceFileRange> {
+    let end = offset + len;
+    let mut ranges = Vec::new();
+    let mut file_start = 0;
+    for (file, file_len) in file_lengths.enumerate() {
+        let file_end = file_start + file_len;
+        let start = offset.max(file_start);
+        if start < end.min(file_end) {
+            ranges.push(FailedPieceFileRange {
+                file,
+                offset: start - file_start,
+                len: end.min(file_end) - start,
+            });
+        }
+        if file_end >= end {
+            break;
+        }
+        file_start = file_end;
+    }
+    ranges
+}
+
+impl TorrentStateLive {
+    fn logs_verification_failures(&self) -> bool {
+        self.meta.options.verification_mode == VerificationMode::LogOnly
+    }
+
+    // Called for each chunk accepted from a peer.
+    pub(crate) fn record_chunk_source(&self, who_sent: PeerHandle, chunk: &ChunkInfo) {
+        if !self.logs_verification_failures() {
+            return;
+        }
+        let mut sources = self.chunk_sources.lock();
+        let chunks = sources.entry(chunk.piece_index).or_default();
+        // The chunk was sent again, e.g. after the piece failed or was stolen.
+        chunks.retain(|c| c.offset != chunk.offset);
+        chunks.push(ChunkSource {
+            offset: chunk.offset,
+            size: chunk.size,
+            peer: who_sent,
+        });
+    }
+
+    pub(crate) fn forget_chunk_sources(&self, piece: ValidPieceIndex) {
+        if self.logs_verification_failures() {
+            self.chunk_sources.lock().remove(&piece);
+        }
+    }
+
+    // The piece is marked as not had by then. "who_sent" completed it.
+    pub(crate) fn on_piece_failed_verification(
+        &self,
+        piece: ValidPieceIndex,
+        who_sent: PeerHandle,
+    ) -> anyhow::Result<()> {
+        match self.meta.options.verification_mode {
+            VerificationMode::Strict => {
+                let poisoned = self.lock_write("record_failed_piece").record_failed_piece(
+                    piece,
+                    who_sent,
+                    self.meta.options.poisoned_piece_threshold,
+                );
+                if let Some(poisoned) = poisoned {
+                    self.on_piece_poisoned(poisoned)?;
+                }
+            }
+            VerificationMode::LogOnly => self.record_verification_failure(piece, who_sent)?,
+        }
+        Ok(())
+    }
+
+    fn record_verification_failure(
+        &self,
+        piece: ValidPieceIndex,
+        who_sent: PeerHandle,
+    ) -> anyhow::Result<()> {
+        let offset = self.lengths.piece_offset(piece);
+        let len = self.lengths.piece_length(piece);
+        let mut chunks = self.chunk_sources.lock().remove(&piece).unwrap_or_default();
+        if chunks.is_empty() {
+            chunks.push(ChunkSource {
+                offset: 0,
+                size: len,
+                peer: who_sent,
+            });
+        }
+        chunks.sort_by_key(|c| c.offset);
+        let failure = VerificationFailure {
+            piece: piece.get(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            offset,
+            len,
+            files: file_ranges(self.meta.info.iter_file_lengths()?, offset, len as u64),
+            chunks,
+        };
+        debug!(?failure, "piece failed verification");
+        push_capped(
+            &mut self.verification_failures.lock(),
+            failure,
+            VERIFICATION_FAILURES_HISTORY,
+        );
+        Ok(())
+    }
+
+    /// The last few pieces that failed verification, oldest first. Only recorded with
+    /// [VerificationMode::LogOnly].
+    pub fn verification_failures(&self) -> Vec<VerificationFailure> {
+        self.verification_failures.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_ranges, FailedPieceFileRange};
+
+    #[test]
+    fn test_file_ranges() {
+        let range = |file, offset, len| FailedPieceFileRange { file, offset, len };
+        let files = [10u64, 0, 5, 20];
+
+        // Within a single file.
+        assert_eq!(
+            file_ranges(files.iter().copied(), 2, 4),
+            vec![range(0, 2, 4)]
+        );
+        // Across the empty file and into the last one.
+        assert_eq!(
+            file_ranges(files.iter().copied(), 8, 10),
+            vec![range(0, 8, 2), range(2, 0, 5), range(3, 0, 3)]
+        );
+        // The last, shorter piece.
+        assert_eq!(
+            file_ranges(files.iter().copied(), 30, 5),
+            vec![range(3, 15, 5)]
+        );
+    }
+}
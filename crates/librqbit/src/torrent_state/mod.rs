@@ -1,53 +1,107 @@
+pub mod events;
 pub mod initializing;
+pub mod lazy;
 pub mod live;
 pub mod paused;
 pub mod stats;
 pub mod utils;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
 use anyhow::Context;
 use buffers::ByteString;
 use dht::RequestPeersStream;
+use ipnet::IpNet;
 use librqbit_core::hash_id::Id20;
 use librqbit_core::lengths::Lengths;
 use librqbit_core::peer_id::generate_peer_id;
 
+pub use initializing::ExistingDataPolicy;
 use librqbit_core::spawn_utils::spawn_with_cancel;
 use librqbit_core::torrent_metainfo::TorrentMetaV1Info;
+use live::progress_thresholds::{
+    sample_files, threshold_percent, ProgressAction, ProgressThreshold,
+};
+use live::verification::VerificationMode;
 pub use live::*;
 use parking_lot::RwLock;
+use sha1w::{ISha1, Sha1};
 
+use tokio::sync::{broadcast, watch, Notify};
 use tokio::time::timeout;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use tracing::error_span;
+use tracing::info;
 use tracing::trace;
 use tracing::warn;
 use url::Url;
 
+use crate::bandwidth::BandwidthLimiter;
 use crate::chunk_tracker::ChunkTracker;
+use crate::connect_ramp::ConnectRamp;
+use crate::diagnose::{diagnose_live, LiveDiagnosisInput, TorrentDiagnosis};
+use crate::dns::DnsResolver;
+use crate::file_ops::{tag_read_only_fs_error, FsyncPolicy};
+use crate::file_permissions::FilePermissions;
+use crate::first_piece::FirstPieceStrategy;
+use crate::peer_budget::PriorityTier;
+use crate::peer_connection::PeerConnectionOptions;
+use crate::power_profile::PowerProfile;
+use crate::request_limiter::RequestLimiter;
 use crate::spawn_utils::BlockingSpawner;
 use crate::torrent_state::stats::LiveStats;
+use crate::tracker_comms::tracker_client;
+use crate::tracker_comms::DEFAULT_MAX_TRACKER_REDIRECTS;
+use crate::tracker_comms::DEFAULT_MAX_TRACKER_RESPONSE_SIZE;
+use crate::tracker_schedule::TrackerHostScheduler;
+use crate::type_aliases::BF;
+use crate::verify_from_memory::PieceMemoryBudget;
 
 use initializing::TorrentStateInitializing;
 
+use self::events::TorrentEvent;
+use self::lazy::{PersistedProgress, TorrentStateLazy};
 use self::paused::TorrentStatePaused;
-use self::stats::TorrentStats;
+use self::peer_source::PeerSource;
+use self::stats::{LifetimeTransfer, TorrentStats};
+use self::tracker_stats::check_seeding_announce_interval;
+use self::utils::with_incomplete_suffix;
+
+// A piece failing verification from this many distinct peers is considered poisoned.
+const DEFAULT_POISONED_PIECE_THRESHOLD: usize = 3;
+
+// Pieces can be stolen from slow peers any number of times.
+const DEFAULT_ENDGAME_MAX_DUPLICATES: usize = usize::MAX;
+
+// A live torrent with peers that hasn't verified a piece for this long is reported as stalled.
+const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(600);
+
+// How often live torrents look for files deleted from under them.
+const DEFAULT_MISSING_FILES_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 pub enum ManagedTorrentState {
     Initializing(Arc<TorrentStateInitializing>),
     Paused(TorrentStatePaused),
     Live(Arc<TorrentStateLive>),
     Error(anyhow::Error),
+    // Restored with SessionOptions::lazy_load, and not started or loaded since.
+    Lazy(TorrentStateLazy),
 
     // This is used when swapping between states, outside world should never see it.
     None,
@@ -64,6 +118,11 @@ impl ManagedTorrentState {
     pub(crate) fn take(&mut self) -> Self {
         std::mem::replace(self, Self::None)
     }
+
+    /// Paused, or lazy, which stays paused until it's started.
+    pub fn is_paused(&self) -> bool {
+        matches!(self, Self::Paused(_) | Self::Lazy(_))
+    }
 }
 
 pub(crate) struct ManagedTorrentLocked {
@@ -73,9 +132,59 @@ pub(crate) struct ManagedTorrentLocked {
 #[derive(Default)]
 pub(crate) struct ManagedTorrentOptions {
     pub force_tracker_interval: Option<Duration>,
+    // The session's defaults, see ManagedTorrent::set_peer_opts.
     pub peer_connect_timeout: Option<Duration>,
     pub peer_read_write_timeout: Option<Duration>,
-    pub overwrite: bool,
+    pub peer_handshake_timeout: Option<Duration>,
+    pub peer_choke_timeout: Option<Duration>,
+    pub existing_data_policy: ExistingDataPolicy,
+    pub file_mode: Option<u32>,
+    pub file_group: Option<u32>,
+    pub fsync_policy: FsyncPolicy,
+    pub power_profile: PowerProfile,
+    pub first_piece_strategy: FirstPieceStrategy,
+    pub rarest_first_randomness: Option<u32>,
+    pub no_persist: bool,
+    pub announce_ip: Option<IpAddr>,
+    pub contiguous_storage: bool,
+    pub request_limiter: Arc<RequestLimiter>,
+    pub bandwidth: Arc<BandwidthLimiter>,
+    pub connect_ramp: Arc<ConnectRamp>,
+    pub dns_resolver: Option<Arc<DnsResolver>>,
+    // Shared by the session, see tracker_comms::tracker_client().
+    pub tracker_client: reqwest::Client,
+    // Session-wide count of torrents stopped by a read-only filesystem.
+    pub read_only_fs_errors: Arc<AtomicU64>,
+    // Woken when the torrent stops taking up a download slot, see
+    // Session::task_download_queue.
+    pub state_changed: Option<Arc<Notify>>,
+    pub poisoned_piece_threshold: usize,
+    pub endgame_max_duplicates: usize,
+    pub stall_timeout: Duration,
+    pub missing_files_check_interval: Duration,
+    pub incomplete_suffix: Option<String>,
+    pub incomplete_dir: Option<PathBuf>,
+    pub max_tracker_response_size: usize,
+    pub max_tracker_redirects: usize,
+    pub dead_tracker_timeout: Option<Duration>,
+    pub pause_on_poisoned_piece: bool,
+    pub verification_mode: VerificationMode,
+    pub prefer_good_peers: bool,
+    pub slow_peer_piece_limit: Option<usize>,
+    // The session's default, see ManagedTorrent::set_upload_slots.
+    pub upload_slots: Option<usize>,
+    pub upload_subnets: Option<Vec<IpNet>>,
+    // See AddTorrentOptions::mirror_paths.
+    pub mirror_paths: Vec<PathBuf>,
+    pub verify_before_write: bool,
+    // The session-wide memory for SessionOptions::verify_from_memory.
+    pub verify_from_memory: Option<Arc<PieceMemoryBudget>>,
+    pub announce_enabled: Option<watch::Receiver<bool>>,
+    pub network_changed: Option<watch::Receiver<u64>>,
+    // The session-wide seeding announce interval, used unless the torrent has its own.
+    pub seeding_announce_interval: Option<watch::Receiver<Option<Duration>>>,
+    pub peer_source_priority: Vec<PeerSource>,
+    pub tracker_host_scheduler: Option<Arc<TrackerHostScheduler>>,
 }
 
 pub struct ManagedTorrentInfo {
@@ -84,16 +193,185 @@ pub struct ManagedTorrentInfo {
     pub out_dir: PathBuf,
     pub(crate) spawner: BlockingSpawner,
     pub trackers: HashSet<Url>,
+    // BEP 17 HTTP seeds from the torrent file.
+    pub http_seeds: Vec<Url>,
     pub peer_id: Id20,
     pub lengths: Lengths,
     pub span: tracing::Span,
     pub(crate) options: ManagedTorrentOptions,
+    pub(crate) events: broadcast::Sender<TorrentEvent>,
+    // When set, pieces are requested in order. Can be changed at any time.
+    pub(crate) sequential: AtomicBool,
+    // Overrides the session's seeding announce interval. Can be changed at any time.
+    pub(crate) seeding_announce_interval: RwLock<Option<Duration>>,
+    // The info dictionary as received from peers, if it came from them.
+    pub(crate) info_bytes: Option<ByteString>,
+    // Announce URLs (without the query) that redirected, and where they redirected to.
+    pub(crate) tracker_redirects: RwLock<HashMap<Url, Url>>,
+    // Announce URLs not announced to anymore, see ManagedTorrent::revive_tracker.
+    pub(crate) dead_trackers: RwLock<HashSet<Url>>,
+    pub(crate) tracker_revived: Notify,
+    // See ManagedTorrent::set_locked. Can be changed at any time.
+    pub(crate) locked: AtomicBool,
+    // Woken when it's unlocked.
+    pub(crate) unlocked: Notify,
+    // Whether it's in error because it failed to initialize, e.g. its files couldn't be
+    // opened, as opposed to failing while live. See SessionOptions::error_retry.
+    pub(crate) failed_initializing: AtomicBool,
+    // Woken on every verified piece, and when the torrent stops or is deleted, see
+    // file_sink.rs.
+    pub(crate) pieces_changed: Notify,
+    // When the boost ends, see crate::Session::boost. Can be changed at any time.
+    pub(crate) boosted_until: RwLock<Option<Instant>>,
+    // Overrides the session's upload slots. Can be changed at any time.
+    pub(crate) upload_slots: RwLock<Option<usize>>,
+    // See ManagedTorrent::set_priority_tier. Can be changed at any time.
+    pub(crate) priority_tier: RwLock<PriorityTier>,
+    // This torrent's share of SessionOptions::peer_connection_budget, None without one.
+    pub(crate) peer_limit: RwLock<Option<usize>>,
+    // Woken when peer_limit changes or a live peer goes away, i.e. there may be room for
+    // another peer.
+    pub(crate) peer_slots_changed: Notify,
+    // The ones not reached yet, see progress_thresholds.rs.
+    pub(crate) progress_thresholds: RwLock<Vec<ProgressThreshold>>,
+    // Overrides the session's peer timeouts. Can be changed at any time.
+    pub(crate) peer_opts: RwLock<PeerConnectionOptions>,
+}
+
+impl ManagedTorrentInfo {
+    /// Where the files end up once complete, in torrent order.
+    pub(crate) fn file_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        self.info
+            .iter_filenames_and_lengths()?
+            .map(|(path_bits, _)| {
+                let relative_path = path_bits
+                    .to_pathbuf()
+                    .context("error converting file to path")?;
+                Ok(self.out_dir.join(relative_path))
+            })
+            .collect()
+    }
+
+    // file_paths(), and where they are written until complete if that's somewhere else.
+    pub(crate) fn written_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = self.file_paths()?;
+        let incomplete: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|path| self.incomplete_file_path(path))
+            .collect();
+        paths.extend(incomplete);
+        Ok(paths)
+    }
+
+    // Called once it's paused, stopped with an error, done checking its files, or done
+    // downloading, any of which can free a download slot.
+    pub(crate) fn notify_state_changed(&self) {
+        if let Some(notify) = &self.options.state_changed {
+            notify.notify_one();
+        }
+        self.pieces_changed.notify_waiters();
+    }
+
+    pub(crate) fn boost_remaining(&self) -> Option<Duration> {
+        let until = (*self.boosted_until.read())?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return None;
+        }
+        Some(remaining)
+    }
+
+    // How many peers to upload to at a time. Unlimited if None.
+    pub(crate) fn upload_slots(&self) -> Option<usize> {
+        self.upload_slots.read().or(self.options.upload_slots)
+    }
+
+    pub(crate) fn peer_limit(&self) -> Option<usize> {
+        *self.peer_limit.read()
+    }
+
+    // The options new peer connections get: this torrent's timeouts, or the session's.
+    pub(crate) fn peer_connection_options(&self) -> PeerConnectionOptions {
+        let own = *self.peer_opts.read();
+        PeerConnectionOptions {
+            connect_timeout: own.connect_timeout.or(self.options.peer_connect_timeout),
+            read_write_timeout: own
+                .read_write_timeout
+                .or(self.options.peer_read_write_timeout),
+            handshake_timeout: own
+                .handshake_timeout
+                .or(self.options.peer_handshake_timeout),
+            keep_alive_interval: own.keep_alive_interval,
+            choke_timeout: own.choke_timeout.or(self.options.peer_choke_timeout),
+        }
+    }
+
+    // For everything the torrent creates, see SessionOptions::file_mode.
+    pub(crate) fn file_permissions(&self) -> FilePermissions {
+        FilePermissions {
+            mode: self.options.file_mode,
+            gid: self.options.file_group,
+        }
+    }
+
+    pub(crate) fn has_incomplete_paths(&self) -> bool {
+        self.options.incomplete_suffix.is_some() || self.options.incomplete_dir.is_some()
+    }
+
+    // The folder a path of the torrent is under, incomplete_dir or the output folder, the
+    // deeper one if it's under both.
+    pub(crate) fn root_of(&self, path: &Path) -> &Path {
+        match &self.options.incomplete_dir {
+            Some(dir)
+                if path.starts_with(dir)
+                    && (!path.starts_with(&self.out_dir) || dir.starts_with(&self.out_dir)) =>
+            {
+                dir
+            }
+            _ => &self.out_dir,
+        }
+    }
+
+    /// Where the file at "final_path" is written until complete, if that's somewhere else.
+    pub(crate) fn incomplete_file_path(&self, final_path: &Path) -> Option<PathBuf> {
+        if !self.has_incomplete_paths() {
+            return None;
+        }
+        let mut path = match &self.options.incomplete_dir {
+            Some(dir) => dir.join(final_path.strip_prefix(&self.out_dir).ok()?),
+            None => final_path.to_owned(),
+        };
+        if let Some(suffix) = &self.options.incomplete_suffix {
+            path = with_incomplete_suffix(&path, suffix);
+        }
+        Some(path)
+    }
+}
+
+/// The limit for [ManagedTorrent::set_user_metadata], as it's kept in memory and in the
+/// session file.
+pub const MAX_USER_METADATA_SIZE: usize = 64 * 1024;
+
+pub(crate) fn check_user_metadata_size(metadata: &serde_json::Value) -> anyhow::Result<()> {
+    let size = serde_json::to_vec(metadata)?.len();
+    if size > MAX_USER_METADATA_SIZE {
+        bail!("user metadata is {size} bytes, the limit is {MAX_USER_METADATA_SIZE}");
+    }
+    Ok(())
 }
 
 pub struct ManagedTorrent {
     pub info: Arc<ManagedTorrentInfo>,
     pub cancellation_token: CancellationToken,
     pub(crate) only_files: Option<Vec<usize>>,
+    labels: RwLock<Vec<String>>,
+    user_metadata: RwLock<serde_json::Value>,
+    // Unix timestamp (seconds) of when the torrent was first added, kept across restarts.
+    added_at: AtomicU64,
+    // Transfer totals of earlier live sessions, i.e. before the last pause or restart.
+    previous_transfer: RwLock<LifetimeTransfer>,
+    // See peer_cache().
+    peer_cache: RwLock<Vec<SocketAddr>>,
     locked: RwLock<ManagedTorrentLocked>,
 }
 
@@ -114,6 +392,281 @@ impl ManagedTorrent {
         self.only_files.clone()
     }
 
+    /// Free-form labels for the user to group torrents with. They have no effect on downloading.
+    pub fn labels(&self) -> Vec<String> {
+        self.labels.read().clone()
+    }
+
+    pub fn set_labels(&self, labels: Vec<String>) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        *self.labels.write() = labels;
+        Ok(())
+    }
+
+    /// Arbitrary JSON the embedder attached to the torrent. It's persisted with the torrent,
+    /// but otherwise not looked at. Null if not set.
+    pub fn user_metadata(&self) -> serde_json::Value {
+        self.user_metadata.read().clone()
+    }
+
+    /// Replace the user metadata. Fails if it's larger than [MAX_USER_METADATA_SIZE] bytes
+    /// serialized, or if the torrent is locked.
+    pub fn set_user_metadata(&self, metadata: serde_json::Value) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        check_user_metadata_size(&metadata)?;
+        *self.user_metadata.write() = metadata;
+        Ok(())
+    }
+
+    /// When the torrent was first added to the session.
+    pub fn added_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.added_at_secs())
+    }
+
+    pub(crate) fn added_at_secs(&self) -> u64 {
+        self.added_at.load(Ordering::Relaxed)
+    }
+
+    /// Transfer totals since the torrent was added, including the current live session.
+    pub fn lifetime_transfer(&self) -> LifetimeTransfer {
+        self.with_state(|s| self.lifetime_transfer_locked(s))
+    }
+
+    fn lifetime_transfer_locked(&self, state: &ManagedTorrentState) -> LifetimeTransfer {
+        let mut transfer = *self.previous_transfer.read();
+        if let ManagedTorrentState::Live(live) = state {
+            transfer.uploaded_bytes += live.get_uploaded_bytes();
+            transfer.downloaded_bytes += live.get_downloaded_bytes();
+            transfer.web_seed_bytes += live.get_web_seed_bytes();
+        }
+        transfer
+    }
+
+    // Called with the live state about to go away, so that its counters aren't lost.
+    fn fold_live_transfer(&self, live: &TorrentStateLive) {
+        let mut previous = self.previous_transfer.write();
+        previous.uploaded_bytes += live.get_uploaded_bytes();
+        previous.downloaded_bytes += live.get_downloaded_bytes();
+        previous.web_seed_bytes += live.get_web_seed_bytes();
+    }
+
+    /// Restore what was persisted for this torrent in an earlier run.
+    pub(crate) fn restore_lifetime(&self, added_at_secs: Option<u64>, transfer: LifetimeTransfer) {
+        if let Some(added_at_secs) = added_at_secs {
+            self.added_at.store(added_at_secs, Ordering::Relaxed);
+        }
+        *self.previous_transfer.write() = transfer;
+    }
+
+    /// Where the trackers redirected to, see [crate::SessionOptions::max_tracker_redirects].
+    pub(crate) fn tracker_redirects(&self) -> HashMap<Url, Url> {
+        self.info.tracker_redirects.read().clone()
+    }
+
+    /// The trackers that aren't announced to anymore, see
+    /// [crate::SessionOptions::dead_tracker_timeout].
+    pub fn dead_trackers(&self) -> HashSet<Url> {
+        self.info.dead_trackers.read().clone()
+    }
+
+    pub(crate) fn restore_dead_trackers(&self, dead: HashSet<Url>) {
+        *self.info.dead_trackers.write() = dead;
+    }
+
+    /// Start announcing to a dead tracker again. It gets the whole
+    /// [crate::SessionOptions::dead_tracker_timeout] again before it's considered dead.
+    pub fn revive_tracker(&self, url: &str) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        let url = Url::parse(url).context("invalid tracker URL")?;
+        if !self.info.dead_trackers.write().remove(&url) {
+            bail!("tracker {url} is not dead");
+        }
+        self.info.tracker_revived.notify_waiters();
+        Ok(())
+    }
+
+    /// The peers to dial right away whenever the torrent starts, see
+    /// [crate::SessionOptions::persist_peers]. Updated from the connected peers while live.
+    pub(crate) fn peer_cache(&self) -> Vec<SocketAddr> {
+        if let Some(live) = self.live() {
+            let peers = live.peers_to_cache();
+            if !peers.is_empty() {
+                *self.peer_cache.write() = peers;
+            }
+        }
+        self.peer_cache.read().clone()
+    }
+
+    pub fn is_sequential(&self) -> bool {
+        self.info.sequential.load(Ordering::Relaxed)
+    }
+
+    /// Request pieces in order, e.g. to be able to use the first files before the torrent
+    /// finishes. Takes effect for the next requested pieces.
+    pub fn set_sequential(&self, sequential: bool) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        self.info.sequential.store(sequential, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.info.locked.load(Ordering::Relaxed)
+    }
+
+    /// Where the torrent pauses, see [crate::AddTorrentOptions::pause_at_percent]. None
+    /// once it did.
+    pub fn pause_at_percent(&self) -> Option<f64> {
+        threshold_percent(
+            &self.info.progress_thresholds.read(),
+            &ProgressAction::Pause,
+        )
+    }
+
+    /// Where the torrent switches to sequential, see
+    /// [crate::AddTorrentOptions::sequential_after_percent]. None once it did.
+    pub fn sequential_after_percent(&self) -> Option<f64> {
+        threshold_percent(
+            &self.info.progress_thresholds.read(),
+            &ProgressAction::SwitchToSequential,
+        )
+    }
+
+    /// The sample files still to download before the torrent pauses, see
+    /// [crate::AddTorrentOptions::sample_files]. None once they are.
+    pub fn sample_files(&self) -> Option<Vec<usize>> {
+        sample_files(&self.info.progress_thresholds.read())
+    }
+
+    /// The thresholds not reached yet, see [crate::AddTorrentOptions::pause_at_percent],
+    /// [crate::AddTorrentOptions::sequential_after_percent] and
+    /// [crate::AddTorrentOptions::sample_files].
+    pub fn progress_thresholds(&self) -> Vec<ProgressThreshold> {
+        self.info.progress_thresholds.read().clone()
+    }
+
+    /// Freeze the torrent, e.g. an archived one. While locked, nothing is uploaded or
+    /// downloaded, [crate::Session] refuses to start or delete it, and the API also refuses
+    /// to pause it. Changing its settings, labels or metadata fails too. Rechecking still
+    /// works.
+    pub fn set_locked(&self, locked: bool) {
+        if self.info.locked.swap(locked, Ordering::Relaxed) == locked {
+            return;
+        }
+        if !locked {
+            self.info.unlocked.notify_waiters();
+        }
+        if let Some(live) = self.live() {
+            live.update_peer_chokes();
+        }
+    }
+
+    /// How much is left of the boost, see [crate::Session::boost].
+    pub fn boost_remaining(&self) -> Option<Duration> {
+        self.info.boost_remaining()
+    }
+
+    pub(crate) fn set_boosted_until(&self, until: Option<Instant>) {
+        *self.info.boosted_until.write() = until;
+    }
+
+    pub(crate) fn check_unlocked(&self) -> anyhow::Result<()> {
+        if self.is_locked() {
+            bail!("torrent is locked, unlock it first");
+        }
+        Ok(())
+    }
+
+    /// This torrent's own seeding announce interval, if it has one.
+    pub fn seeding_announce_interval(&self) -> Option<Duration> {
+        *self.info.seeding_announce_interval.read()
+    }
+
+    /// Announce this often to trackers once the torrent is complete, instead of the
+    /// session's [crate::SessionOptions::seeding_announce_interval]. None falls back to the
+    /// session's. Takes effect on the next announce. At least a minute.
+    pub fn set_seeding_announce_interval(&self, interval: Option<Duration>) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        check_seeding_announce_interval(interval)?;
+        *self.info.seeding_announce_interval.write() = interval;
+        Ok(())
+    }
+
+    /// This torrent's own peer timeouts. The ones not set are the session's.
+    pub fn peer_opts(&self) -> PeerConnectionOptions {
+        *self.info.peer_opts.read()
+    }
+
+    /// Use these timeouts for this torrent's peers instead of the session's
+    /// [crate::SessionOptions::peer_opts]. The ones left None fall back to the session's.
+    /// Connections already made keep the timeouts they were made with.
+    pub fn set_peer_opts(&self, opts: PeerConnectionOptions) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        opts.validate()?;
+        *self.info.peer_opts.write() = opts;
+        Ok(())
+    }
+
+    /// This torrent's own upload slot count, if it has one.
+    pub fn upload_slots(&self) -> Option<usize> {
+        *self.info.upload_slots.read()
+    }
+
+    /// Upload to at most this many peers at a time, instead of the session's
+    /// [crate::SessionOptions::upload_slots]. None falls back to the session's. Peers are
+    /// choked or unchoked right away to match.
+    pub fn set_upload_slots(&self, slots: Option<usize>) -> anyhow::Result<()> {
+        self.check_unlocked()?;
+        if slots == Some(0) {
+            bail!("upload slots must be at least 1, lock the torrent to stop uploading");
+        }
+        *self.info.upload_slots.write() = slots;
+        if let Some(live) = self.live() {
+            live.update_peer_chokes();
+        }
+        Ok(())
+    }
+
+    pub fn priority_tier(&self) -> PriorityTier {
+        *self.info.priority_tier.read()
+    }
+
+    /// Weight this torrent's share of [crate::SessionOptions::peer_connection_budget]. The
+    /// shares are only recomputed by the session, see [crate::Session::set_priority_tier].
+    pub(crate) fn set_priority_tier(&self, tier: PriorityTier) {
+        *self.info.priority_tier.write() = tier;
+    }
+
+    // Set by the session when it shares out the peer connection budget.
+    pub(crate) fn set_peer_limit(&self, limit: Option<usize>) {
+        *self.info.peer_limit.write() = limit;
+        self.info.peer_slots_changed.notify_waiters();
+    }
+
+    /// The bencoded info dictionary, byte for byte as it hashes to the info hash.
+    ///
+    /// If the metadata was received from peers, these are the received bytes. Otherwise the
+    /// parsed info is encoded again, and it's an error if that doesn't match the info hash,
+    /// e.g. because the original had keys this library doesn't know about.
+    pub fn raw_info(&self) -> anyhow::Result<ByteString> {
+        if let Some(bytes) = self.info.info_bytes.as_ref() {
+            return Ok(bytes.clone());
+        }
+        let mut buf = Vec::new();
+        bencode::bencode_serialize_to_writer(&self.info.info, &mut buf)
+            .context("error serializing torrent info")?;
+        let mut hash = Sha1::new();
+        hash.update(&buf);
+        if hash.finish() != self.info.info_hash.0 {
+            bail!("re-encoded info doesn't match the info hash and the original bytes weren't kept")
+        }
+        Ok(ByteString(buf))
+    }
+
+    /// Subscribe to notable things happening to this torrent, e.g. a poisoned piece.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<TorrentEvent> {
+        self.info.events.subscribe()
+    }
+
     pub fn with_state<R>(&self, f: impl FnOnce(&ManagedTorrentState) -> R) -> R {
         f(&self.locked.read().state)
     }
@@ -149,8 +702,13 @@ impl ManagedTorrent {
     fn stop_with_error(&self, error: anyhow::Error) {
         let mut g = self.locked.write();
 
+        let initializing = matches!(g.state, ManagedTorrentState::Initializing(_));
+        self.info
+            .failed_initializing
+            .store(initializing, Ordering::Relaxed);
         match g.state.take() {
             ManagedTorrentState::Live(live) => {
+                self.fold_live_transfer(&live);
                 if let Err(err) = live.pause() {
                     warn!(
                         "error pausing live torrent during fatal error handling: {:?}",
@@ -167,15 +725,28 @@ impl ManagedTorrent {
             _ => {}
         };
 
-        g.state = ManagedTorrentState::Error(error)
+        g.state = ManagedTorrentState::Error(tag_read_only_fs_error(
+            error,
+            &self.info.options.read_only_fs_errors,
+        ));
+        self.info.notify_state_changed();
     }
 
     pub(crate) fn start(
         self: &Arc<Self>,
-        initial_peers: Vec<SocketAddr>,
+        initial_peers: Vec<(SocketAddr, PeerSource)>,
         peer_rx: Option<RequestPeersStream>,
         start_paused: bool,
     ) -> anyhow::Result<()> {
+        let initial_peers: Vec<(SocketAddr, PeerSource)> = initial_peers
+            .into_iter()
+            .chain(
+                self.peer_cache
+                    .read()
+                    .iter()
+                    .map(|a| (*a, PeerSource::Cache)),
+            )
+            .collect();
         let mut g = self.locked.write();
 
         let spawn_fatal_errors_receiver =
@@ -201,9 +772,32 @@ impl ManagedTorrent {
                 );
             };
 
+        let spawn_pause_requests_receiver = |state: &Arc<Self>, live: &Arc<TorrentStateLive>| {
+            let state = Arc::downgrade(state);
+            live.spawn(
+                error_span!(parent: live.meta().span.clone(), "pause_requests_receiver"),
+                {
+                    let live = live.clone();
+                    async move {
+                        live.check_progress_thresholds();
+                        live.wait_for_pause_request().await;
+                        let state = match state.upgrade() {
+                            Some(state) => state,
+                            None => return Ok(()),
+                        };
+                        if state.is_locked() {
+                            info!("torrent is locked, not pausing");
+                            return Ok(());
+                        }
+                        state.pause()
+                    }
+                },
+            );
+        };
+
         fn spawn_peer_adder(
             live: &Arc<TorrentStateLive>,
-            initial_peers: Vec<SocketAddr>,
+            initial_peers: Vec<(SocketAddr, PeerSource)>,
             peer_rx: Option<RequestPeersStream>,
         ) {
             live.spawn(
@@ -212,8 +806,9 @@ impl ManagedTorrent {
                     let live = live.clone();
                     async move {
                         trace!("adding {} initial peers", initial_peers.len());
-                        for peer in initial_peers {
-                            live.add_peer_if_not_seen(peer).context("torrent closed")?;
+                        for (peer, source) in initial_peers {
+                            live.add_peer_if_not_seen(peer, source)
+                                .context("torrent closed")?;
                         }
 
                         let live = {
@@ -235,7 +830,8 @@ impl ManagedTorrent {
                                         Some(live) => live,
                                         None => return Ok(()),
                                     };
-                                    live.add_peer_if_not_seen(peer).context("torrent closed")?;
+                                    live.add_peer_if_not_seen(peer, PeerSource::Dht)
+                                        .context("torrent closed")?;
                                 }
                                 Ok(None) => return Ok(()),
                                 // If timeout, check if the torrent is live.
@@ -271,8 +867,9 @@ impl ManagedTorrent {
                                     return Ok(());
                                 }
 
-                                if start_paused {
+                                if start_paused || init.is_cancelled() {
                                     g.state = ManagedTorrentState::Paused(paused);
+                                    t.info.notify_state_changed();
                                     return Ok(());
                                 }
 
@@ -282,12 +879,19 @@ impl ManagedTorrent {
 
                                 spawn_fatal_errors_receiver(&t, rx);
                                 spawn_peer_adder(&live, initial_peers, peer_rx);
+                                spawn_pause_requests_receiver(&t, &live);
 
                                 Ok(())
                             }
                             Err(err) => {
+                                let err = tag_read_only_fs_error(
+                                    err,
+                                    &t.info.options.read_only_fs_errors,
+                                );
                                 let result = anyhow::anyhow!("{:?}", err);
+                                t.info.failed_initializing.store(true, Ordering::Relaxed);
                                 t.locked.write().state = ManagedTorrentState::Error(err);
+                                t.info.notify_state_changed();
                                 Err(result)
                             }
                         }
@@ -306,10 +910,11 @@ impl ManagedTorrent {
                 g.state = ManagedTorrentState::Live(live.clone());
                 spawn_fatal_errors_receiver(self, rx);
                 spawn_peer_adder(&live, initial_peers, peer_rx);
+                spawn_pause_requests_receiver(self, &live);
                 Ok(())
             }
             ManagedTorrentState::Error(_) => {
-                let initializing = Arc::new(TorrentStateInitializing::new(
+                let initializing = Arc::new(TorrentStateInitializing::for_recheck(
                     self.info.clone(),
                     self.only_files.clone(),
                 ));
@@ -319,6 +924,11 @@ impl ManagedTorrent {
                 // Recurse.
                 self.start(initial_peers, peer_rx, start_paused)
             }
+            ManagedTorrentState::Lazy(lazy) => {
+                g.state = ManagedTorrentState::Initializing(lazy.initializing.clone());
+                drop(g);
+                self.start(initial_peers, peer_rx, start_paused)
+            }
             ManagedTorrentState::None => bail!("bug: torrent is in empty state"),
         }
     }
@@ -329,13 +939,15 @@ impl ManagedTorrent {
         match &g.state {
             ManagedTorrentState::Live(live) => {
                 let paused = live.pause()?;
+                self.fold_live_transfer(live);
                 g.state = ManagedTorrentState::Paused(paused);
+                self.info.notify_state_changed();
                 Ok(())
             }
             ManagedTorrentState::Initializing(_) => {
                 bail!("torrent is initializing, can't pause");
             }
-            ManagedTorrentState::Paused(_) => {
+            ManagedTorrentState::Paused(_) | ManagedTorrentState::Lazy(_) => {
                 bail!("torrent is already paused");
             }
             ManagedTorrentState::Error(_) => {
@@ -345,6 +957,131 @@ impl ManagedTorrent {
         }
     }
 
+    /// Stop checking the data, e.g. a recheck started by mistake. The pieces verified so far
+    /// are kept, the rest is downloaded again, and the torrent is left paused.
+    pub fn cancel_verify(&self) -> anyhow::Result<()> {
+        match &self.locked.read().state {
+            ManagedTorrentState::Initializing(init) => {
+                init.cancel();
+                Ok(())
+            }
+            _ => bail!("torrent isn't checking its data"),
+        }
+    }
+
+    /// Whether the torrent was restored with [crate::SessionOptions::lazy_load], and hasn't
+    /// been started or loaded since.
+    pub fn is_lazy(&self) -> bool {
+        self.with_state(|s| matches!(s, ManagedTorrentState::Lazy(_)))
+    }
+
+    /// Open and check the files of a lazy torrent, see [Self::is_lazy], so that e.g. its
+    /// pieces can be looked at or its files streamed. It stays paused. Does nothing for
+    /// other torrents.
+    pub fn load(self: &Arc<Self>) -> anyhow::Result<()> {
+        if !self.is_lazy() {
+            return Ok(());
+        }
+        self.start(Vec::new(), None, true)
+    }
+
+    // Saved with the session, for lazy_load to show without loading the torrent.
+    pub(crate) fn persisted_progress(&self) -> Option<PersistedProgress> {
+        self.with_state(|s| match s {
+            ManagedTorrentState::Lazy(l) => Some(l.progress),
+            ManagedTorrentState::Paused(p) => {
+                let total_bytes = p.chunk_tracker.get_total_selected_bytes();
+                Some(PersistedProgress {
+                    progress_bytes: total_bytes - p.needed_bytes,
+                    total_bytes,
+                })
+            }
+            ManagedTorrentState::Live(l) => {
+                let total_bytes = l.get_total_selected_bytes();
+                Some(PersistedProgress {
+                    progress_bytes: total_bytes - l.get_left_to_download_bytes(),
+                    total_bytes,
+                })
+            }
+            _ => None,
+        })
+    }
+
+    // Drops what's known about the data, so that the next start() hashes it all again.
+    // Returns whether the torrent was paused.
+    pub(crate) fn reset_for_recheck(&self) -> anyhow::Result<bool> {
+        let mut g = self.locked.write();
+        let paused = match &g.state {
+            ManagedTorrentState::Live(live) => {
+                live.pause()?;
+                self.fold_live_transfer(live);
+                false
+            }
+            ManagedTorrentState::Paused(_) | ManagedTorrentState::Lazy(_) => true,
+            ManagedTorrentState::Error(_) => false,
+            ManagedTorrentState::Initializing(_) => {
+                bail!("torrent is initializing, its data is being checked already")
+            }
+            ManagedTorrentState::None => bail!("bug: torrent is in empty state"),
+        };
+        g.state = ManagedTorrentState::Initializing(Arc::new(
+            TorrentStateInitializing::for_recheck(self.info.clone(), self.only_files.clone()),
+        ));
+        Ok(paused)
+    }
+
+    /// Why the torrent is or isn't downloading, see [crate::Session::diagnose_torrent].
+    /// "queue_position" is its index in the session's download queue, if it's there.
+    pub(crate) fn diagnose(
+        &self,
+        queue_position: Option<usize>,
+        announce_enabled: bool,
+    ) -> TorrentDiagnosis {
+        // Queued torrents are paused until they get a slot.
+        let stopped = |finished: bool| {
+            if finished {
+                TorrentDiagnosis::Complete
+            } else if let Some(position) = queue_position {
+                TorrentDiagnosis::Queued {
+                    position: position + 1,
+                }
+            } else if self.is_locked() {
+                TorrentDiagnosis::Locked
+            } else {
+                TorrentDiagnosis::Paused
+            }
+        };
+        self.with_state(|s| match s {
+            ManagedTorrentState::Initializing(i) => TorrentDiagnosis::Checking {
+                progress_bytes: i.checked_bytes.load(Ordering::Relaxed),
+                total_bytes: self.info().lengths.total_length(),
+            },
+            ManagedTorrentState::Paused(p) => stopped(p.needed_bytes == 0),
+            ManagedTorrentState::Lazy(l) => stopped(l.is_finished()),
+            ManagedTorrentState::Live(l) => {
+                if l.get_left_to_download_bytes() == 0 {
+                    return TorrentDiagnosis::Complete;
+                }
+                if self.is_locked() {
+                    return TorrentDiagnosis::Locked;
+                }
+                let (connected_peers, choking_peers) = l.choking_peers();
+                let peer_stats = l.stats_snapshot().peer_stats;
+                diagnose_live(&LiveDiagnosisInput {
+                    connected_peers,
+                    choking_peers,
+                    known_peers: peer_stats.queued + peer_stats.connecting,
+                    announce_enabled,
+                    unavailable_pieces: l.stalled_unavailable_pieces(),
+                })
+            }
+            ManagedTorrentState::Error(e) => TorrentDiagnosis::from_error(e),
+            ManagedTorrentState::None => TorrentDiagnosis::Error {
+                error: "bug: torrent in broken \"None\" state".to_string(),
+            },
+        })
+    }
+
     /// Get stats.
     pub fn stats(&self) -> TorrentStats {
         let mut resp = TorrentStats {
@@ -353,10 +1090,20 @@ impl ManagedTorrent {
             error: None,
             progress_bytes: 0,
             finished: false,
+            lifetime: Default::default(),
+            stalled: false,
+            unavailable_pieces: None,
+            locked: self.is_locked(),
+            boost_remaining_ms: self.boost_remaining().map(|d| d.as_millis() as u64),
+            lazy: false,
+            priority_tier: self.priority_tier(),
+            sequential: self.is_sequential(),
+            sequential_after_percent: self.sequential_after_percent(),
             live: None,
         };
 
         self.with_state(|s| {
+            resp.lifetime = self.lifetime_transfer_locked(s);
             match s {
                 ManagedTorrentState::Initializing(i) => {
                     resp.state = "initializing";
@@ -368,6 +1115,13 @@ impl ManagedTorrent {
                     resp.progress_bytes = resp.total_bytes - p.needed_bytes;
                     resp.finished = resp.progress_bytes == resp.total_bytes;
                 }
+                ManagedTorrentState::Lazy(l) => {
+                    resp.state = "paused";
+                    resp.lazy = true;
+                    resp.total_bytes = l.total_bytes();
+                    resp.progress_bytes = l.progress_bytes();
+                    resp.finished = l.is_finished();
+                }
                 ManagedTorrentState::Live(l) => {
                     resp.state = "live";
                     let live_stats = LiveStats::from(l.as_ref());
@@ -378,6 +1132,8 @@ impl ManagedTorrent {
                     resp.progress_bytes = progress;
                     resp.total_bytes = total;
                     resp.finished = remaining == 0;
+                    resp.unavailable_pieces = l.stalled_unavailable_pieces();
+                    resp.stalled = resp.unavailable_pieces.is_some();
                     resp.live = Some(live_stats);
                 }
                 ManagedTorrentState::Error(e) => {
@@ -397,7 +1153,9 @@ impl ManagedTorrent {
         // TODO: rewrite, this polling is horrible
         let live = loop {
             let live = self.with_state(|s| match s {
-                ManagedTorrentState::Initializing(_) | ManagedTorrentState::Paused(_) => Ok(None),
+                ManagedTorrentState::Initializing(_)
+                | ManagedTorrentState::Paused(_)
+                | ManagedTorrentState::Lazy(_) => Ok(None),
                 ManagedTorrentState::Live(l) => Ok(Some(l.clone())),
                 ManagedTorrentState::Error(e) => bail!("{:?}", e),
                 ManagedTorrentState::None => bail!("bug: torrent state is None"),
@@ -420,10 +1178,64 @@ pub struct ManagedTorrentBuilder {
     force_tracker_interval: Option<Duration>,
     peer_connect_timeout: Option<Duration>,
     peer_read_write_timeout: Option<Duration>,
+    peer_handshake_timeout: Option<Duration>,
+    peer_choke_timeout: Option<Duration>,
+    peer_opts: PeerConnectionOptions,
     only_files: Option<Vec<usize>>,
     trackers: Vec<Url>,
+    http_seeds: Vec<Url>,
     peer_id: Option<Id20>,
-    overwrite: bool,
+    existing_data_policy: ExistingDataPolicy,
+    file_mode: Option<u32>,
+    file_group: Option<u32>,
+    fsync_policy: FsyncPolicy,
+    power_profile: PowerProfile,
+    first_piece_strategy: FirstPieceStrategy,
+    rarest_first_randomness: Option<u32>,
+    no_persist: bool,
+    announce_ip: Option<IpAddr>,
+    contiguous_storage: bool,
+    request_limiter: Option<Arc<RequestLimiter>>,
+    bandwidth: Option<Arc<BandwidthLimiter>>,
+    connect_ramp: Option<Arc<ConnectRamp>>,
+    lazy: Option<PersistedProgress>,
+    dns_resolver: Option<Arc<DnsResolver>>,
+    tracker_client: Option<reqwest::Client>,
+    read_only_fs_errors: Option<Arc<AtomicU64>>,
+    state_changed: Option<Arc<Notify>>,
+    poisoned_piece_threshold: usize,
+    endgame_max_duplicates: usize,
+    stall_timeout: Duration,
+    missing_files_check_interval: Duration,
+    incomplete_suffix: Option<String>,
+    incomplete_dir: Option<PathBuf>,
+    max_tracker_response_size: usize,
+    max_tracker_redirects: usize,
+    dead_tracker_timeout: Option<Duration>,
+    pause_on_poisoned_piece: bool,
+    verification_mode: VerificationMode,
+    prefer_good_peers: bool,
+    slow_peer_piece_limit: Option<usize>,
+    upload_slots: Option<usize>,
+    progress_thresholds: Vec<ProgressThreshold>,
+    upload_subnets: Option<Vec<IpNet>>,
+    mirror_paths: Vec<PathBuf>,
+    verify_before_write: bool,
+    verify_from_memory: Option<Arc<PieceMemoryBudget>>,
+    announce_enabled: Option<watch::Receiver<bool>>,
+    network_changed: Option<watch::Receiver<u64>>,
+    global_seeding_announce_interval: Option<watch::Receiver<Option<Duration>>>,
+    seeding_announce_interval: Option<Duration>,
+    peer_source_priority: Vec<PeerSource>,
+    tracker_host_scheduler: Option<Arc<TrackerHostScheduler>>,
+    sequential: bool,
+    labels: Vec<String>,
+    peer_cache: Vec<SocketAddr>,
+    tracker_redirects: HashMap<Url, Url>,
+    user_metadata: serde_json::Value,
+    info_bytes: Option<ByteString>,
+    trusted_have_pieces: Option<BF>,
+    cross_seed_copies: Vec<(PathBuf, PathBuf)>,
     spawner: Option<BlockingSpawner>,
     cancellation_token: Option<CancellationToken>,
 }
@@ -442,10 +1254,64 @@ impl ManagedTorrentBuilder {
             force_tracker_interval: None,
             peer_connect_timeout: None,
             peer_read_write_timeout: None,
+            peer_handshake_timeout: None,
+            peer_choke_timeout: None,
+            peer_opts: Default::default(),
             only_files: None,
             trackers: Default::default(),
+            http_seeds: Default::default(),
             peer_id: None,
-            overwrite: false,
+            existing_data_policy: ExistingDataPolicy::default(),
+            file_mode: None,
+            file_group: None,
+            fsync_policy: FsyncPolicy::default(),
+            power_profile: PowerProfile::default(),
+            first_piece_strategy: FirstPieceStrategy::default(),
+            rarest_first_randomness: None,
+            no_persist: false,
+            announce_ip: None,
+            contiguous_storage: false,
+            request_limiter: None,
+            bandwidth: None,
+            connect_ramp: None,
+            lazy: None,
+            dns_resolver: None,
+            tracker_client: None,
+            read_only_fs_errors: None,
+            state_changed: None,
+            poisoned_piece_threshold: DEFAULT_POISONED_PIECE_THRESHOLD,
+            endgame_max_duplicates: DEFAULT_ENDGAME_MAX_DUPLICATES,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            missing_files_check_interval: DEFAULT_MISSING_FILES_CHECK_INTERVAL,
+            incomplete_suffix: None,
+            incomplete_dir: None,
+            max_tracker_response_size: DEFAULT_MAX_TRACKER_RESPONSE_SIZE,
+            max_tracker_redirects: DEFAULT_MAX_TRACKER_REDIRECTS,
+            dead_tracker_timeout: None,
+            pause_on_poisoned_piece: false,
+            verification_mode: Default::default(),
+            prefer_good_peers: false,
+            slow_peer_piece_limit: None,
+            upload_slots: None,
+            progress_thresholds: Vec::new(),
+            upload_subnets: None,
+            mirror_paths: Vec::new(),
+            verify_before_write: false,
+            verify_from_memory: None,
+            sequential: false,
+            labels: Vec::new(),
+            peer_cache: Vec::new(),
+            tracker_redirects: HashMap::new(),
+            user_metadata: serde_json::Value::Null,
+            info_bytes: None,
+            trusted_have_pieces: None,
+            cross_seed_copies: Vec::new(),
+            announce_enabled: None,
+            network_changed: None,
+            global_seeding_announce_interval: None,
+            seeding_announce_interval: None,
+            peer_source_priority: Vec::new(),
+            tracker_host_scheduler: None,
             cancellation_token: None,
         }
     }
@@ -465,8 +1331,330 @@ impl ManagedTorrentBuilder {
         self
     }
 
-    pub fn overwrite(&mut self, overwrite: bool) -> &mut Self {
-        self.overwrite = overwrite;
+    /// BEP 17 HTTP seeds to download pieces from, in addition to peers.
+    pub fn http_seeds(&mut self, http_seeds: Vec<Url>) -> &mut Self {
+        self.http_seeds = http_seeds;
+        self
+    }
+
+    pub fn existing_data_policy(&mut self, policy: ExistingDataPolicy) -> &mut Self {
+        self.existing_data_policy = policy;
+        self
+    }
+
+    /// Unix permission bits for created files. Directories also get "x" wherever "r" is set.
+    pub fn file_mode(&mut self, mode: u32) -> &mut Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Unix group ID for created files and directories.
+    pub fn file_group(&mut self, gid: u32) -> &mut Self {
+        self.file_group = Some(gid);
+        self
+    }
+
+    pub fn fsync_policy(&mut self, policy: FsyncPolicy) -> &mut Self {
+        self.fsync_policy = policy;
+        self
+    }
+
+    pub fn power_profile(&mut self, profile: PowerProfile) -> &mut Self {
+        self.power_profile = profile;
+        self
+    }
+
+    pub fn first_piece_strategy(&mut self, strategy: FirstPieceStrategy) -> &mut Self {
+        self.first_piece_strategy = strategy;
+        self
+    }
+
+    pub fn rarest_first_randomness(&mut self, randomness: Option<u32>) -> &mut Self {
+        self.rarest_first_randomness = randomness;
+        self
+    }
+
+    /// Do not write this torrent to the session persistence file.
+    pub fn no_persist(&mut self, no_persist: bool) -> &mut Self {
+        self.no_persist = no_persist;
+        self
+    }
+
+    /// The IP to send to trackers in the "ip" announce parameter.
+    pub fn announce_ip(&mut self, ip: IpAddr) -> &mut Self {
+        self.announce_ip = Some(ip);
+        self
+    }
+
+    /// Fully preallocate files and keep writes close to sequential.
+    pub fn contiguous_storage(&mut self, contiguous_storage: bool) -> &mut Self {
+        self.contiguous_storage = contiguous_storage;
+        self
+    }
+
+    /// Request pieces in order. See [ManagedTorrent::set_sequential].
+    pub fn sequential(&mut self, sequential: bool) -> &mut Self {
+        self.sequential = sequential;
+        self
+    }
+
+    /// See [crate::AddTorrentOptions::pause_at_percent].
+    pub fn pause_at_percent(&mut self, percent: f64) -> &mut Self {
+        self.progress_threshold(percent, ProgressAction::Pause)
+    }
+
+    /// See [crate::AddTorrentOptions::sequential_after_percent].
+    pub fn sequential_after_percent(&mut self, percent: f64) -> &mut Self {
+        self.progress_threshold(percent, ProgressAction::SwitchToSequential)
+    }
+
+    /// Download only these files, and pause once they're complete. See
+    /// [crate::AddTorrentOptions::sample_files].
+    pub fn sample_files(&mut self, files: Vec<usize>) -> &mut Self {
+        self.only_files = Some(files.clone());
+        self.progress_threshold(100., ProgressAction::SampleComplete { files })
+    }
+
+    fn progress_threshold(&mut self, percent: f64, action: ProgressAction) -> &mut Self {
+        self.progress_thresholds
+            .push(ProgressThreshold { percent, action });
+        self
+    }
+
+    pub fn labels(&mut self, labels: Vec<String>) -> &mut Self {
+        self.labels = labels;
+        self
+    }
+
+    pub(crate) fn peer_cache(&mut self, peers: Vec<SocketAddr>) -> &mut Self {
+        self.peer_cache = peers;
+        self
+    }
+
+    // Where the trackers redirected to before a restart, so that the first announces go
+    // there directly.
+    pub(crate) fn tracker_redirects(&mut self, redirects: HashMap<Url, Url>) -> &mut Self {
+        self.tracker_redirects = redirects;
+        self
+    }
+
+    /// See [ManagedTorrent::set_user_metadata]. The size is checked in build().
+    pub fn user_metadata(&mut self, metadata: serde_json::Value) -> &mut Self {
+        self.user_metadata = metadata;
+        self
+    }
+
+    pub(crate) fn upload_subnets(&mut self, subnets: Option<Vec<IpNet>>) -> &mut Self {
+        self.upload_subnets = subnets;
+        self
+    }
+
+    /// See [crate::AddTorrentOptions::mirror_paths].
+    pub fn mirror_paths(&mut self, paths: Vec<PathBuf>) -> &mut Self {
+        self.mirror_paths = paths;
+        self
+    }
+
+    pub(crate) fn info_bytes(&mut self, info_bytes: Option<ByteString>) -> &mut Self {
+        self.info_bytes = info_bytes;
+        self
+    }
+
+    /// Take these pieces as downloaded instead of hashing them on the first check.
+    pub(crate) fn trusted_have_pieces(&mut self, have: BF) -> &mut Self {
+        self.trusted_have_pieces = Some(have);
+        self
+    }
+
+    /// (from, to) files to copy before the first check, see
+    /// [crate::AddTorrentOptions::cross_seed].
+    pub(crate) fn cross_seed_copies(&mut self, copies: Vec<(PathBuf, PathBuf)>) -> &mut Self {
+        self.cross_seed_copies = copies;
+        self
+    }
+
+    pub(crate) fn request_limiter(&mut self, limiter: Arc<RequestLimiter>) -> &mut Self {
+        self.request_limiter = Some(limiter);
+        self
+    }
+
+    pub(crate) fn bandwidth(&mut self, limiter: Arc<BandwidthLimiter>) -> &mut Self {
+        self.bandwidth = Some(limiter);
+        self
+    }
+
+    pub(crate) fn connect_ramp(&mut self, ramp: Arc<ConnectRamp>) -> &mut Self {
+        self.connect_ramp = Some(ramp);
+        self
+    }
+
+    // Builds the torrent in the lazy state, which start() doesn't have to be called for.
+    pub(crate) fn lazy(&mut self, progress: PersistedProgress) -> &mut Self {
+        self.lazy = Some(progress);
+        self
+    }
+
+    pub(crate) fn dns_resolver(&mut self, resolver: Option<Arc<DnsResolver>>) -> &mut Self {
+        self.dns_resolver = resolver;
+        self
+    }
+
+    pub(crate) fn tracker_client(&mut self, client: reqwest::Client) -> &mut Self {
+        self.tracker_client = Some(client);
+        self
+    }
+
+    pub(crate) fn read_only_fs_errors(&mut self, counter: Arc<AtomicU64>) -> &mut Self {
+        self.read_only_fs_errors = Some(counter);
+        self
+    }
+
+    pub(crate) fn state_changed(&mut self, notify: Arc<Notify>) -> &mut Self {
+        self.state_changed = Some(notify);
+        self
+    }
+
+    /// After how many failed verifications from distinct peers a piece is considered poisoned.
+    pub fn poisoned_piece_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.poisoned_piece_threshold = threshold.max(1);
+        self
+    }
+
+    /// [crate::SessionOptions::endgame_max_duplicates], at least 1.
+    pub fn endgame_max_duplicates(&mut self, max_duplicates: usize) -> &mut Self {
+        self.endgame_max_duplicates = max_duplicates;
+        self
+    }
+
+    /// [crate::SessionOptions::stall_timeout].
+    pub fn stall_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// [crate::SessionOptions::missing_files_check_interval]. Zero turns the check off.
+    pub fn missing_files_check_interval(&mut self, interval: Duration) -> &mut Self {
+        self.missing_files_check_interval = interval;
+        self
+    }
+
+    /// [crate::SessionOptions::incomplete_suffix]. An empty suffix turns it off.
+    pub fn incomplete_suffix(&mut self, suffix: String) -> &mut Self {
+        self.incomplete_suffix = Some(suffix).filter(|s| !s.is_empty());
+        self
+    }
+
+    /// [crate::SessionOptions::incomplete_dir].
+    pub fn incomplete_dir(&mut self, dir: PathBuf) -> &mut Self {
+        self.incomplete_dir = Some(dir);
+        self
+    }
+
+    /// Ignore tracker responses larger than this many bytes.
+    pub fn max_tracker_response_size(&mut self, max_size: usize) -> &mut Self {
+        self.max_tracker_response_size = max_size;
+        self
+    }
+
+    /// Follow at most this many HTTP redirects per tracker announce. 0 doesn't follow any.
+    pub fn max_tracker_redirects(&mut self, max_redirects: usize) -> &mut Self {
+        self.max_tracker_redirects = max_redirects;
+        self
+    }
+
+    /// Stop announcing to a tracker once every announce failed for this long. None keeps
+    /// announcing forever.
+    pub fn dead_tracker_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.dead_tracker_timeout = timeout;
+        self
+    }
+
+    /// Stop the torrent with an error once a piece is considered poisoned.
+    pub fn pause_on_poisoned_piece(&mut self, pause: bool) -> &mut Self {
+        self.pause_on_poisoned_piece = pause;
+        self
+    }
+
+    /// See [VerificationMode].
+    pub fn verification_mode(&mut self, mode: VerificationMode) -> &mut Self {
+        self.verification_mode = mode;
+        self
+    }
+
+    /// Hand out pieces to the peers that recently did best first. See
+    /// [crate::SessionOptions::prefer_good_peers].
+    pub fn prefer_good_peers(&mut self, prefer: bool) -> &mut Self {
+        self.prefer_good_peers = prefer;
+        self
+    }
+
+    /// [crate::SessionOptions::slow_peer_piece_limit].
+    pub fn slow_peer_piece_limit(&mut self, limit: usize) -> &mut Self {
+        self.slow_peer_piece_limit = Some(limit);
+        self
+    }
+
+    /// [crate::SessionOptions::upload_slots]. See [ManagedTorrent::set_upload_slots] to
+    /// override it for this torrent.
+    pub fn upload_slots(&mut self, slots: usize) -> &mut Self {
+        self.upload_slots = Some(slots);
+        self
+    }
+
+    /// Keep pieces in memory until they pass the hash check, and only then write them to
+    /// disk. See [crate::SessionOptions::verify_before_write] for the memory cost.
+    pub fn verify_before_write(&mut self, verify: bool) -> &mut Self {
+        self.verify_before_write = verify;
+        self
+    }
+
+    pub(crate) fn verify_from_memory(
+        &mut self,
+        budget: Option<Arc<PieceMemoryBudget>>,
+    ) -> &mut Self {
+        self.verify_from_memory = budget;
+        self
+    }
+
+    /// Trackers are only announced to while this is true.
+    pub(crate) fn announce_enabled(&mut self, enabled: watch::Receiver<bool>) -> &mut Self {
+        self.announce_enabled = Some(enabled);
+        self
+    }
+
+    /// Trackers are re-announced to right away whenever this changes.
+    pub(crate) fn network_changed(&mut self, changed: watch::Receiver<u64>) -> &mut Self {
+        self.network_changed = Some(changed);
+        self
+    }
+
+    /// The session-wide seeding announce interval, which can change at runtime.
+    pub(crate) fn global_seeding_announce_interval(
+        &mut self,
+        interval: watch::Receiver<Option<Duration>>,
+    ) -> &mut Self {
+        self.global_seeding_announce_interval = Some(interval);
+        self
+    }
+
+    /// See [ManagedTorrent::set_seeding_announce_interval].
+    pub fn seeding_announce_interval(&mut self, interval: Duration) -> &mut Self {
+        self.seeding_announce_interval = Some(interval);
+        self
+    }
+
+    /// Shared by all torrents, see [crate::SessionOptions::tracker_host_min_gap].
+    pub(crate) fn tracker_host_scheduler(
+        &mut self,
+        scheduler: Arc<TrackerHostScheduler>,
+    ) -> &mut Self {
+        self.tracker_host_scheduler = Some(scheduler);
+        self
+    }
+
+    /// See [crate::SessionOptions::peer_source_priority].
+    pub fn peer_source_priority(&mut self, priority: Vec<PeerSource>) -> &mut Self {
+        self.peer_source_priority = priority;
         self
     }
 
@@ -495,14 +1683,37 @@ impl ManagedTorrentBuilder {
         self
     }
 
+    pub fn peer_handshake_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.peer_handshake_timeout = Some(timeout);
+        self
+    }
+
+    pub fn peer_choke_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.peer_choke_timeout = Some(timeout);
+        self
+    }
+
+    /// This torrent's own peer timeouts, over the session's ones set above. See
+    /// [ManagedTorrent::set_peer_opts].
+    pub fn peer_opts(&mut self, opts: PeerConnectionOptions) -> &mut Self {
+        self.peer_opts = opts;
+        self
+    }
+
     pub(crate) fn build(mut self, span: tracing::Span) -> anyhow::Result<ManagedTorrentHandle> {
         let lengths = Lengths::from_torrent(&self.info)?;
+        check_user_metadata_size(&self.user_metadata)?;
+        let tracker_client = match self.tracker_client.take() {
+            Some(client) => client,
+            None => tracker_client(self.dns_resolver.as_ref())?,
+        };
         let info = Arc::new(ManagedTorrentInfo {
             span,
             info: self.info,
             info_hash: self.info_hash,
             out_dir: self.output_folder,
             trackers: self.trackers.into_iter().collect(),
+            http_seeds: self.http_seeds,
             spawner: self.spawner.unwrap_or_default(),
             peer_id: self.peer_id.unwrap_or_else(generate_peer_id),
             lengths,
@@ -510,18 +1721,94 @@ impl ManagedTorrentBuilder {
                 force_tracker_interval: self.force_tracker_interval,
                 peer_connect_timeout: self.peer_connect_timeout,
                 peer_read_write_timeout: self.peer_read_write_timeout,
-                overwrite: self.overwrite,
+                peer_handshake_timeout: self.peer_handshake_timeout,
+                peer_choke_timeout: self.peer_choke_timeout,
+                existing_data_policy: self.existing_data_policy,
+                file_mode: self.file_mode,
+                file_group: self.file_group,
+                fsync_policy: self.fsync_policy,
+                power_profile: self.power_profile,
+                first_piece_strategy: self.first_piece_strategy,
+                rarest_first_randomness: self.rarest_first_randomness,
+                no_persist: self.no_persist,
+                announce_ip: self.announce_ip,
+                contiguous_storage: self.contiguous_storage,
+                request_limiter: self.request_limiter.unwrap_or_default(),
+                bandwidth: self.bandwidth.unwrap_or_default(),
+                connect_ramp: self.connect_ramp.unwrap_or_default(),
+                dns_resolver: self.dns_resolver,
+                tracker_client,
+                read_only_fs_errors: self.read_only_fs_errors.unwrap_or_default(),
+                state_changed: self.state_changed,
+                poisoned_piece_threshold: self.poisoned_piece_threshold,
+                endgame_max_duplicates: self.endgame_max_duplicates,
+                stall_timeout: self.stall_timeout,
+                missing_files_check_interval: self.missing_files_check_interval,
+                incomplete_suffix: self.incomplete_suffix,
+                incomplete_dir: self.incomplete_dir,
+                max_tracker_response_size: self.max_tracker_response_size,
+                max_tracker_redirects: self.max_tracker_redirects,
+                dead_tracker_timeout: self.dead_tracker_timeout,
+                pause_on_poisoned_piece: self.pause_on_poisoned_piece,
+                verification_mode: self.verification_mode,
+                prefer_good_peers: self.prefer_good_peers,
+                slow_peer_piece_limit: self.slow_peer_piece_limit,
+                upload_slots: self.upload_slots,
+                upload_subnets: self.upload_subnets,
+                mirror_paths: self.mirror_paths,
+                verify_before_write: self.verify_before_write,
+                verify_from_memory: self.verify_from_memory,
+                announce_enabled: self.announce_enabled,
+                network_changed: self.network_changed,
+                seeding_announce_interval: self.global_seeding_announce_interval,
+                peer_source_priority: self.peer_source_priority,
+                tracker_host_scheduler: self.tracker_host_scheduler,
             },
+            events: broadcast::channel(16).0,
+            sequential: AtomicBool::new(self.sequential),
+            seeding_announce_interval: RwLock::new(self.seeding_announce_interval),
+            info_bytes: self.info_bytes,
+            tracker_redirects: RwLock::new(self.tracker_redirects),
+            dead_trackers: Default::default(),
+            tracker_revived: Notify::new(),
+            locked: AtomicBool::new(false),
+            unlocked: Notify::new(),
+            failed_initializing: AtomicBool::new(false),
+            pieces_changed: Notify::new(),
+            peer_slots_changed: Notify::new(),
+            boosted_until: Default::default(),
+            upload_slots: Default::default(),
+            priority_tier: Default::default(),
+            peer_limit: Default::default(),
+            progress_thresholds: RwLock::new(self.progress_thresholds),
+            peer_opts: RwLock::new(self.peer_opts),
         });
         let initializing = Arc::new(TorrentStateInitializing::new(
             info.clone(),
             self.only_files.clone(),
+            self.trusted_have_pieces,
+            self.cross_seed_copies,
         ));
+        let state = match self.lazy {
+            Some(progress) => ManagedTorrentState::Lazy(TorrentStateLazy {
+                initializing,
+                progress,
+            }),
+            None => ManagedTorrentState::Initializing(initializing),
+        };
         Ok(Arc::new(ManagedTorrent {
             only_files: self.only_files,
-            locked: RwLock::new(ManagedTorrentLocked {
-                state: ManagedTorrentState::Initializing(initializing),
-            }),
+            labels: RwLock::new(self.labels),
+            user_metadata: RwLock::new(self.user_metadata),
+            added_at: AtomicU64::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            ),
+            previous_transfer: Default::default(),
+            peer_cache: RwLock::new(self.peer_cache),
+            locked: RwLock::new(ManagedTorrentLocked { state }),
             cancellation_token: self.cancellation_token.take().unwrap_or_default(),
             info,
         }))
@@ -529,3 +1816,135 @@ impl ManagedTorrentBuilder {
 }
 
 pub type ManagedTorrentHandle = Arc<ManagedTorrent>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use buffers::ByteString;
+    use librqbit_core::{hash_id::Id20, torrent_metainfo::TorrentMetaV1Info};
+    use sha1w::{ISha1, Sha1};
+
+    use super::{
+        lazy::PersistedProgress, ManagedTorrentBuilder, ManagedTorrentHandle,
+        MAX_USER_METADATA_SIZE,
+    };
+    use crate::{
+        diagnose::TorrentDiagnosis,
+        test_util::{test_data, test_torrent_info},
+    };
+
+    // Single file info dictionaries, the second one with a key unknown to TorrentMetaV1Info.
+    const KNOWN_KEYS: &[u8] =
+        b"d6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+    const UNKNOWN_KEY: &[u8] =
+        b"d6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa3:zzzi1ee";
+
+    fn torrent(raw: &[u8], keep_bytes: bool) -> ManagedTorrentHandle {
+        let info = bencode::from_bytes::<TorrentMetaV1Info<ByteString>>(raw).unwrap();
+        let mut hash = Sha1::new();
+        hash.update(raw);
+        let mut builder =
+            ManagedTorrentBuilder::new(info, Id20::new(hash.finish()), std::env::temp_dir());
+        if keep_bytes {
+            builder.info_bytes(Some(ByteString(raw.to_vec())));
+        }
+        builder.build(tracing::Span::none()).unwrap()
+    }
+
+    #[test]
+    fn test_raw_info_returns_received_bytes() {
+        let t = torrent(UNKNOWN_KEY, true);
+        assert_eq!(t.raw_info().unwrap().0, UNKNOWN_KEY);
+    }
+
+    #[test]
+    fn test_raw_info_encodes_again_if_hash_matches() {
+        let t = torrent(KNOWN_KEYS, false);
+        assert_eq!(t.raw_info().unwrap().0, KNOWN_KEYS);
+    }
+
+    #[test]
+    fn test_raw_info_errors_if_encoding_again_changes_hash() {
+        let t = torrent(UNKNOWN_KEY, false);
+        assert!(t.raw_info().is_err());
+    }
+
+    #[test]
+    fn test_user_metadata_size_limit() {
+        let t = torrent(KNOWN_KEYS, false);
+        assert!(t.user_metadata().is_null());
+
+        let metadata = serde_json::json!({"category": "tv", "ids": [1, 2]});
+        t.set_user_metadata(metadata.clone()).unwrap();
+        assert_eq!(t.user_metadata(), metadata);
+
+        let too_large = serde_json::Value::String("a".repeat(MAX_USER_METADATA_SIZE));
+        assert!(t.set_user_metadata(too_large).is_err());
+        assert_eq!(t.user_metadata(), metadata);
+    }
+
+    #[test]
+    fn test_locked_torrent_refuses_changes() {
+        let t = torrent(KNOWN_KEYS, false);
+        t.set_locked(true);
+        assert!(t.set_labels(vec!["a".to_owned()]).is_err());
+        assert!(t.set_user_metadata(serde_json::json!({"a": 1})).is_err());
+        assert!(t.set_sequential(true).is_err());
+        assert!(t.set_upload_slots(Some(2)).is_err());
+        assert!(t
+            .set_seeding_announce_interval(Some(Duration::from_secs(3600)))
+            .is_err());
+        assert!(t.set_peer_opts(Default::default()).is_err());
+        assert!(t.labels().is_empty());
+        assert!(t.user_metadata().is_null());
+        assert!(!t.is_sequential());
+        assert_eq!(t.upload_slots(), None);
+
+        t.set_locked(false);
+        t.set_labels(vec!["a".to_owned()]).unwrap();
+        t.set_sequential(true).unwrap();
+        assert_eq!(t.labels(), vec!["a".to_owned()]);
+        assert!(t.is_sequential());
+    }
+
+    #[test]
+    fn test_lazy_torrent_shows_persisted_progress() {
+        let info = bencode::from_bytes::<TorrentMetaV1Info<ByteString>>(KNOWN_KEYS).unwrap();
+        let mut builder = ManagedTorrentBuilder::new(info, Id20::default(), std::env::temp_dir());
+        builder.lazy(PersistedProgress {
+            progress_bytes: 2,
+            total_bytes: 5,
+        });
+        let t = builder.build(tracing::Span::none()).unwrap();
+
+        assert!(t.is_lazy());
+        assert!(t.with_state(|s| s.is_paused()));
+        let stats = t.stats();
+        assert!(stats.lazy);
+        assert_eq!(stats.state, "paused");
+        assert_eq!((stats.progress_bytes, stats.total_bytes), (2, 5));
+        assert!(t.pause().is_err());
+        // Saved again as it was, without loading it.
+        let progress = t.persisted_progress().unwrap();
+        assert_eq!((progress.progress_bytes, progress.total_bytes), (2, 5));
+    }
+
+    #[test]
+    fn test_lazy_torrent_with_only_files_is_complete() {
+        let info = test_torrent_info(&[("a", &test_data(100, 0)), ("b", &test_data(200, 1))]);
+        let mut builder = ManagedTorrentBuilder::new(info, Id20::default(), std::env::temp_dir());
+        // Only "a" is selected, and it's all there.
+        builder.only_files(vec![0]);
+        builder.lazy(PersistedProgress {
+            progress_bytes: 100,
+            total_bytes: 100,
+        });
+        let t = builder.build(tracing::Span::none()).unwrap();
+
+        assert_eq!(t.diagnose(None, true), TorrentDiagnosis::Complete);
+        let stats = t.stats();
+        assert!(stats.finished);
+        assert_eq!((stats.progress_bytes, stats.total_bytes), (100, 100));
+    }
+}
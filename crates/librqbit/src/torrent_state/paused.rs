@@ -4,12 +4,13 @@ use parking_lot::Mutex;
 
 use crate::chunk_tracker::ChunkTracker;
 
-use super::ManagedTorrentInfo;
+use super::{live::MirrorFiles, ManagedTorrentInfo};
 
 pub struct TorrentStatePaused {
     pub(crate) info: Arc<ManagedTorrentInfo>,
     pub(crate) files: Vec<Arc<Mutex<File>>>,
     pub(crate) filenames: Vec<PathBuf>,
+    pub(crate) mirror_files: MirrorFiles,
     pub(crate) chunk_tracker: ChunkTracker,
     pub(crate) have_bytes: u64,
     pub(crate) needed_bytes: u64,
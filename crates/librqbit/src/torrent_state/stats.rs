@@ -2,16 +2,32 @@ use std::time::Duration;
 
 use serde::Serialize;
 
+use crate::{peer_budget::PriorityTier, peer_connection::PeerConnectionOptions};
+
 use super::{live::stats::snapshot::StatsSnapshot, TorrentStateLive};
 use size_format::SizeFormatterBinary as SF;
 
-#[derive(Serialize, Default, Debug)]
+#[derive(Serialize, Default, Debug, PartialEq)]
 pub struct LiveStats {
     pub snapshot: StatsSnapshot,
     pub average_piece_download_time: Option<Duration>,
     pub download_speed: Speed,
     pub upload_speed: Speed,
+    /// Disk throughput of this torrent's data, i.e. verifying, serving and storing pieces.
+    pub disk_read_speed: Speed,
+    pub disk_write_speed: Speed,
     pub time_remaining: Option<DurationWithHumanReadable>,
+    /// Peers being uploaded to, i.e. unchoked.
+    pub upload_slots_used: usize,
+    /// How many peers may be uploaded to at a time, see
+    /// [crate::ManagedTorrent::set_upload_slots]. Unlimited if not set.
+    pub upload_slots: Option<usize>,
+    /// The timeouts new peer connections get, see [crate::ManagedTorrent::set_peer_opts].
+    /// None where the built-in default is used.
+    pub peer_opts: PeerConnectionOptions,
+    /// This torrent's share of [crate::SessionOptions::peer_connection_budget], None without
+    /// a budget.
+    pub peer_limit: Option<usize>,
 }
 
 impl std::fmt::Display for LiveStats {
@@ -36,20 +52,67 @@ impl From<&TorrentStateLive> for LiveStats {
             snapshot,
             download_speed: down_estimator.mbps().into(),
             upload_speed: up_estimator.mbps().into(),
+            disk_read_speed: live.disk_read_speed_estimator().mbps().into(),
+            disk_write_speed: live.disk_write_speed_estimator().mbps().into(),
             time_remaining: down_estimator
                 .time_remaining()
                 .map(DurationWithHumanReadable),
+            upload_slots_used: live.upload_slots_used(),
+            upload_slots: live.meta().upload_slots(),
+            peer_opts: live.meta().peer_connection_options(),
+            peer_limit: live.meta().peer_limit(),
+        }
+    }
+}
+
+/// Transfer totals since the torrent was added, across pauses and restarts.
+#[derive(Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifetimeTransfer {
+    pub uploaded_bytes: u64,
+    /// Downloaded bytes that passed the hash check.
+    pub downloaded_bytes: u64,
+    /// Of downloaded_bytes, the ones that came from web seeds, the rest came from peers.
+    pub web_seed_bytes: u64,
+}
+
+impl LifetimeTransfer {
+    /// Uploaded divided by downloaded. None until something was downloaded.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.downloaded_bytes == 0 {
+            return None;
         }
+        Some(self.uploaded_bytes as f64 / self.downloaded_bytes as f64)
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Default, Debug, PartialEq)]
 pub struct TorrentStats {
     pub state: &'static str,
     pub error: Option<String>,
     pub progress_bytes: u64,
     pub total_bytes: u64,
     pub finished: bool,
+    pub lifetime: LifetimeTransfer,
+    /// Live with peers, but no piece was verified in a while. See
+    /// [crate::SessionOptions::stall_timeout].
+    pub stalled: bool,
+    /// When stalled, how many of the needed pieces none of the live peers have. If this is
+    /// above 0, the torrent won't finish until someone having them connects.
+    pub unavailable_pieces: Option<u32>,
+    /// See [crate::ManagedTorrent::set_locked].
+    pub locked: bool,
+    /// While boosted, how long until the boost ends. See [crate::Session::boost].
+    pub boost_remaining_ms: Option<u64>,
+    /// Restored with [crate::SessionOptions::lazy_load] and not loaded yet. The progress is
+    /// what it was when the session was last saved.
+    pub lazy: bool,
+    /// See [crate::Session::set_priority_tier].
+    pub priority_tier: PriorityTier,
+    /// See [crate::ManagedTorrent::set_sequential].
+    pub sequential: bool,
+    /// Until the torrent switches to sequential, see
+    /// [crate::AddTorrentOptions::sequential_after_percent].
+    pub sequential_after_percent: Option<f64>,
     pub live: Option<LiveStats>,
 }
 
@@ -68,6 +131,9 @@ impl std::fmt::Display for TorrentStats {
         if let Some(live) = &self.live {
             write!(f, " [{live}]")?;
         }
+        if let Some(unavailable) = self.unavailable_pieces {
+            write!(f, " stalled, {unavailable} pieces unavailable")?;
+        }
         Ok(())
     }
 }
@@ -124,6 +190,7 @@ fn format_seconds_to_time(seconds: u64, f: &mut core::fmt::Formatter<'_>) -> cor
     }
 }
 
+#[derive(PartialEq)]
 pub struct DurationWithHumanReadable(Duration);
 
 impl core::fmt::Display for DurationWithHumanReadable {
@@ -156,7 +223,7 @@ impl Serialize for DurationWithHumanReadable {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, PartialEq)]
 pub struct Speed {
     pub mbps: f64,
 }
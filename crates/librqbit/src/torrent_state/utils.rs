@@ -1,4 +1,16 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    net::IpAddr,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use anyhow::{bail, Context};
+use ipnet::IpNet;
+use librqbit_core::lengths::Lengths;
+
+use crate::type_aliases::BF;
 
 pub fn atomic_inc(c: &AtomicU32) -> u32 {
     c.fetch_add(1, Ordering::Relaxed)
@@ -8,6 +20,79 @@ pub fn atomic_dec(c: &AtomicU32) -> u32 {
     c.fetch_sub(1, Ordering::Relaxed)
 }
 
+/// Parse subnets like "10.0.0.0/8". A bare address is a subnet of just that address.
+pub(crate) fn parse_subnets(subnets: &[String]) -> anyhow::Result<Vec<IpNet>> {
+    subnets
+        .iter()
+        .map(|s| {
+            let s = s.trim();
+            IpNet::from_str(s)
+                .or_else(|_| IpAddr::from_str(s).map(IpNet::from))
+                .with_context(|| format!("invalid subnet {s:?}"))
+        })
+        .collect()
+}
+
+/// Whether "ip" is in one of "subnets". IPv4-mapped IPv6 addresses match IPv4 subnets.
+pub(crate) fn ip_in_subnets(ip: IpAddr, subnets: &[IpNet]) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    };
+    subnets.iter().any(|net| net.contains(&ip))
+}
+
+/// "path" with "suffix" appended to the file name, e.g. "a/b.mkv" becomes "a/b.mkv.part".
+pub(crate) fn with_incomplete_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// Refuse "path" unless it's inside "root". "root" itself may be a symlink, or be inside a
+/// symlinked directory, but nothing below it may be a symlink, even a dangling one, so that
+/// a symlink planted inside "root" can't redirect writes elsewhere.
+pub(crate) fn check_path_within(root: &Path, path: &Path) -> anyhow::Result<()> {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => bail!("{path:?} is not inside {root:?}"),
+    };
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        bail!("{path:?} escapes {root:?}");
+    }
+    let mut current = root.to_owned();
+    for component in relative.components() {
+        current.push(component);
+        match std::fs::symlink_metadata(&current) {
+            Ok(m) if m.file_type().is_symlink() => bail!("{current:?} in {path:?} is a symlink"),
+            Ok(_) => {}
+            // Nothing below it exists either.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("error checking {current:?}")),
+        }
+    }
+    Ok(())
+}
+
+/// The pieces overlapping a file at "offset" that is "len" bytes long. Empty for empty files.
+pub(crate) fn file_pieces(lengths: &Lengths, offset: u64, len: u64) -> Range<u32> {
+    if len == 0 {
+        return 0..0;
+    }
+    let piece_length = lengths.default_piece_length() as u64;
+    let first = offset / piece_length;
+    let last = (offset + len - 1) / piece_length;
+    first as u32..last as u32 + 1
+}
+
+/// Whether all pieces overlapping the file are in "have_pieces".
+pub(crate) fn is_file_complete(lengths: &Lengths, have_pieces: &BF, offset: u64, len: u64) -> bool {
+    file_pieces(lengths, offset, len).all(|p| have_pieces.get(p as usize).map(|v| *v) == Some(true))
+}
+
 // Used during debugging to see if some locks take too long.
 #[cfg(not(feature = "timed_existence"))]
 mod timed_existence {
@@ -106,3 +191,109 @@ mod timed_existence {
 }
 
 pub use timed_existence::{timeit, TimedExistence};
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use librqbit_core::lengths::Lengths;
+
+    use crate::type_aliases::BF;
+
+    use super::{
+        check_path_within, file_pieces, ip_in_subnets, is_file_complete, parse_subnets,
+        with_incomplete_suffix,
+    };
+
+    #[test]
+    fn test_ip_in_subnets() {
+        let subnets = parse_subnets(&[
+            "10.0.0.0/8".to_owned(),
+            " 192.168.1.0/24".to_owned(),
+            "203.0.113.7".to_owned(),
+            "2001:db8::/32".to_owned(),
+        ])
+        .unwrap();
+        let matches = |ip: &str| ip_in_subnets(ip.parse().unwrap(), &subnets);
+
+        assert!(matches("10.1.2.3"));
+        assert!(matches("192.168.1.200"));
+        assert!(matches("203.0.113.7"));
+        assert!(matches("2001:db8::1"));
+        assert!(matches("::ffff:10.0.0.1"));
+
+        assert!(!matches("11.0.0.1"));
+        assert!(!matches("192.168.2.1"));
+        assert!(!matches("203.0.113.8"));
+        assert!(!matches("2001:db9::1"));
+    }
+
+    #[test]
+    fn test_parse_subnets_rejects_garbage() {
+        assert!(parse_subnets(&["10.0.0.0/33".to_owned()]).is_err());
+        assert!(parse_subnets(&["partner-network".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_with_incomplete_suffix() {
+        assert_eq!(
+            with_incomplete_suffix(Path::new("out/dir/movie.mkv"), ".part"),
+            Path::new("out/dir/movie.mkv.part")
+        );
+    }
+
+    #[test]
+    fn test_file_pieces() {
+        let lengths = Lengths::new(100, 10, Some(10)).unwrap();
+        assert_eq!(file_pieces(&lengths, 0, 10), 0..1);
+        assert_eq!(file_pieces(&lengths, 5, 10), 0..2);
+        assert_eq!(file_pieces(&lengths, 95, 5), 9..10);
+        assert_eq!(file_pieces(&lengths, 40, 0), 0..0);
+
+        let mut have = BF::repeat(false, 10);
+        have.set(0, true);
+        assert!(is_file_complete(&lengths, &have, 0, 10));
+        assert!(!is_file_complete(&lengths, &have, 5, 10));
+        assert!(is_file_complete(&lengths, &have, 40, 0));
+    }
+
+    #[test]
+    fn test_check_path_within_rejects_traversal() {
+        let root = Path::new("/downloads");
+        assert!(check_path_within(root, &root.join("a/b.mkv")).is_ok());
+        assert!(check_path_within(root, &root.join("a/../../etc/passwd")).is_err());
+        assert!(check_path_within(root, Path::new("/etc/passwd")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_path_within_symlinked_root() {
+        let tmp = std::env::temp_dir().join(format!("librqbit-utils-{}", uuid::Uuid::new_v4()));
+        let real = tmp.join("real");
+        let outside = tmp.join("outside");
+        std::fs::create_dir_all(&real).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        let root = tmp.join("link");
+        std::os::unix::fs::symlink(&real, &root).unwrap();
+
+        // Writes through the symlinked root, including to directories not created yet.
+        assert!(check_path_within(&root, &root.join("file")).is_ok());
+        assert!(check_path_within(&root, &root.join("new/dir/file")).is_ok());
+
+        // A symlink inside the root pointing elsewhere.
+        std::os::unix::fs::symlink(&outside, real.join("escape")).unwrap();
+        assert!(check_path_within(&root, &root.join("escape/file")).is_err());
+        assert!(check_path_within(&root, &root.join("escape")).is_err());
+
+        // One pointing inside the root, as it could be changed later.
+        std::fs::create_dir_all(real.join("dir")).unwrap();
+        std::os::unix::fs::symlink(real.join("dir"), real.join("inside")).unwrap();
+        assert!(check_path_within(&root, &root.join("inside/file")).is_err());
+
+        // A dangling one, which would create the file wherever it points.
+        std::os::unix::fs::symlink(outside.join("missing"), real.join("dangling")).unwrap();
+        assert!(check_path_within(&root, &root.join("dangling")).is_err());
+
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
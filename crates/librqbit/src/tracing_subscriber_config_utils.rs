@@ -55,6 +55,7 @@ pub struct InitLoggingOptions<'a> {
     pub log_file_rust_log: Option<&'a str>,
 }
 
+#[derive(Clone)]
 pub struct InitLoggingResult {
     pub rust_log_reload_tx: tokio::sync::mpsc::UnboundedSender<String>,
     pub line_broadcast: LineBroadcast,
@@ -1,3 +1,4 @@
+use anyhow::{bail, Context};
 use buffers::ByteBuf;
 use byteorder::ByteOrder;
 use serde::{Deserialize, Deserializer};
@@ -6,9 +7,24 @@ use std::{
     marker::PhantomData,
     net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
 use librqbit_core::hash_id::Id20;
+use reqwest::{StatusCode, Url};
+use tracing::warn;
+
+use crate::{
+    dns::{client_builder, DnsResolver},
+    http_download::{read_capped_body, ReadBodyError},
+};
+
+/// Tracker responses are a few kilobytes even with lots of peers, so anything much larger
+/// is most likely an attempt to exhaust memory.
+pub(crate) const DEFAULT_MAX_TRACKER_RESPONSE_SIZE: usize = 1024 * 1024;
+
+pub(crate) const DEFAULT_MAX_TRACKER_REDIRECTS: usize = 5;
 
 #[derive(Clone, Copy)]
 pub enum TrackerRequestEvent {
@@ -155,11 +171,90 @@ pub struct TrackerResponse<'a> {
     pub interval: u64,
     #[serde(rename = "min interval")]
     pub min_interval: Option<u64>,
+    #[serde(rename = "tracker id", borrow)]
     pub tracker_id: Option<ByteBuf<'a>>,
     pub incomplete: u64,
     pub peers: Peers,
 }
 
+impl<'a> TrackerResponse<'a> {
+    /// The tracker id to send back in subsequent announces, if the tracker sent one.
+    pub fn tracker_id_string(&self) -> Option<String> {
+        self.tracker_id
+            .as_ref()
+            .map(|id| String::from_utf8_lossy(id.as_ref()).into_owned())
+    }
+}
+
+/// Read the body of a tracker's response, refusing to buffer more than "max_size" bytes.
+pub(crate) async fn read_tracker_response(
+    mut response: reqwest::Response,
+    max_size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match read_capped_body(&mut response, max_size, &mut buf).await {
+        Ok(()) => Ok(buf),
+        Err(ReadBodyError::TooLarge) => {
+            let url = response.url();
+            warn!("response from tracker {url} is larger than {max_size} bytes, ignoring it");
+            bail!("tracker response larger than {max_size} bytes")
+        }
+        Err(ReadBodyError::Interrupted(e)) => Err(e.into()),
+    }
+}
+
+// How long a tracker has to answer each request, including each redirect.
+const TRACKER_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The client for tracker_get(), one per session. It doesn't follow redirects itself.
+pub(crate) fn tracker_client(dns: Option<&Arc<DnsResolver>>) -> anyhow::Result<reqwest::Client> {
+    Ok(client_builder(dns)
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(TRACKER_REQUEST_TIMEOUT)
+        .build()?)
+}
+
+/// GET an HTTP tracker URL, following up to "max_redirects" redirects, but only to other
+/// http(s) URLs. Returns the response along with the URL it came from.
+pub(crate) async fn tracker_get(
+    client: &reqwest::Client,
+    mut url: Url,
+    max_redirects: usize,
+) -> anyhow::Result<(reqwest::Response, Url)> {
+    let mut redirects = 0;
+    loop {
+        let response = client.get(url.clone()).send().await?;
+        let is_redirect = matches!(
+            response.status(),
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        );
+        if !is_redirect {
+            return Ok((response, url));
+        }
+        if redirects == max_redirects {
+            bail!("tracker redirected more than {max_redirects} times");
+        }
+        redirects += 1;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .context("tracker redirected without a Location header")?
+            .to_str()
+            .context("tracker redirected to a non-ASCII location")?;
+        let next = url
+            .join(location)
+            .with_context(|| format!("tracker redirected to invalid URL {location:?}"))?;
+        if !matches!(next.scheme(), "http" | "https") {
+            bail!("tracker redirected to {next}, only http(s) redirects are followed");
+        }
+        url = next;
+    }
+}
+
 impl TrackerRequest {
     pub fn as_querystring(&self) -> String {
         use urlencoding as u;
@@ -196,7 +291,7 @@ impl TrackerRequest {
             write!(s, "&key={key}").unwrap();
         }
         if let Some(trackerid) = &self.trackerid {
-            write!(s, "&trackerid={trackerid}").unwrap();
+            write!(s, "&trackerid={}", u::encode(trackerid)).unwrap();
         }
         s
     }
@@ -205,6 +300,8 @@ impl TrackerRequest {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{http_response, request_path, serve_http};
+
     #[test]
     fn test_serialize() {
         let info_hash = Id20::new([
@@ -230,4 +327,110 @@ mod tests {
         };
         dbg!(request.as_querystring());
     }
+
+    #[test]
+    fn test_tracker_id_echoed_in_next_announce() {
+        let response =
+            b"d8:completei1e10:incompletei2e8:intervali1800e5:peers0:10:tracker id7:abc 123e";
+        let response = bencode::from_bytes::<TrackerResponse>(response).unwrap();
+        let trackerid = response.tracker_id_string();
+        assert_eq!(trackerid.as_deref(), Some("abc 123"));
+
+        let request = TrackerRequest {
+            info_hash: Id20::new([1u8; 20]),
+            peer_id: Id20::new([2u8; 20]),
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            compact: true,
+            no_peer_id: false,
+            event: None,
+            ip: None,
+            numwant: None,
+            key: None,
+            trackerid,
+        };
+        assert!(request.as_querystring().ends_with("&trackerid=abc%20123"));
+    }
+
+    // Serves "body", with or without a Content-Length header, and returns its URL.
+    async fn serve_body(body: Vec<u8>, content_length: bool) -> String {
+        let addr = serve_http(move |_| {
+            if content_length {
+                return http_response("200 OK", &[], &body);
+            }
+            // Without a length, the body ends when the connection is closed.
+            let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+            response.extend_from_slice(&body);
+            response
+        })
+        .await;
+        format!("http://{addr}/announce")
+    }
+
+    #[tokio::test]
+    async fn test_tracker_redirects() {
+        let addr = serve_http(|request| {
+            let location = match request_path(request) {
+                "/announce" => "/moved",
+                "/moved" => "/final",
+                "/final" => return http_response("200 OK", &[], b""),
+                "/loop" => "/loop",
+                "/udp" => "udp://tracker.example.com:80/announce",
+                _ => return http_response("404 Not Found", &[], b""),
+            };
+            http_response("302 Found", &[("Location", location)], b"")
+        })
+        .await;
+        let base = Url::parse(&format!("http://{addr}/")).unwrap();
+        let client = tracker_client(None).unwrap();
+
+        let mut url = base.join("/announce").unwrap();
+        url.set_query(Some("info_hash=x"));
+        let (response, final_url) = tracker_get(&client, url.clone(), 5).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(final_url.path(), "/final");
+
+        assert!(tracker_get(&client, url, 1).await.is_err());
+        assert!(tracker_get(&client, base.join("/loop").unwrap(), 5)
+            .await
+            .is_err());
+        let e = tracker_get(&client, base.join("/udp").unwrap(), 5)
+            .await
+            .unwrap_err();
+        assert!(format!("{e:#}").contains("only http(s) redirects are followed"));
+
+        // Not following redirects at all.
+        let (response, _) = tracker_get(&client, base.join("/final").unwrap(), 0)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_tracker_response_is_rejected() {
+        let peers = vec![0u8; 6 * 10000];
+        let mut body = format!("d8:intervali1800e5:peers{}:", peers.len()).into_bytes();
+        body.extend_from_slice(&peers);
+        body.push(b'e');
+
+        for content_length in [true, false] {
+            let url = serve_body(body.clone(), content_length).await;
+            let response = reqwest::get(&url).await.unwrap();
+            assert!(
+                read_tracker_response(response, 1024).await.is_err(),
+                "content_length={content_length}"
+            );
+        }
+
+        let url = serve_body(body.clone(), false).await;
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(
+            read_tracker_response(response, DEFAULT_MAX_TRACKER_RESPONSE_SIZE)
+                .await
+                .unwrap(),
+            body
+        );
+    }
 }
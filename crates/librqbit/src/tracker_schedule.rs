@@ -0,0 +1,118 @@
+// Spacing out announces to the same tracker host, see SessionOptions::tracker_host_min_gap.
+// Each announce reserves the next free slot for its host, so with many torrents on one
+// tracker the announces go out one by one instead of all at the same time.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Announce spacing for one tracker host.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrackerHostSchedule {
+    pub host: String,
+    pub min_gap_ms: u64,
+    /// Announces to this host waiting for their slot, from all torrents.
+    pub waiting: usize,
+    /// How long until the next free slot, in milliseconds. 0 if an announce can go now.
+    pub next_slot_in_ms: u64,
+}
+
+struct HostState {
+    next_slot: Instant,
+    waiting: usize,
+}
+
+pub(crate) struct TrackerHostScheduler {
+    min_gap: Duration,
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+// Un-counts a waiting announce, also if it gets cancelled while waiting.
+struct WaitingGuard<'a> {
+    scheduler: &'a TrackerHostScheduler,
+    host: &'a str,
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.scheduler.hosts.lock().get_mut(self.host) {
+            state.waiting = state.waiting.saturating_sub(1);
+        }
+    }
+}
+
+impl TrackerHostScheduler {
+    pub fn new(min_gap: Duration) -> Self {
+        Self {
+            min_gap,
+            hosts: Default::default(),
+        }
+    }
+
+    // Reserves the next slot for the host, and returns when it is.
+    fn reserve(&self, host: &str, now: Instant) -> Instant {
+        let mut hosts = self.hosts.lock();
+        let state = hosts.entry(host.to_owned()).or_insert(HostState {
+            next_slot: now,
+            waiting: 0,
+        });
+        let slot = state.next_slot.max(now);
+        state.next_slot = slot + self.min_gap;
+        state.waiting += 1;
+        slot
+    }
+
+    // Waits until it's this announce's turn to go to the host.
+    pub async fn wait_turn(&self, host: &str) {
+        let slot = self.reserve(host, Instant::now());
+        let _guard = WaitingGuard {
+            scheduler: self,
+            host,
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    pub fn host_schedule(&self, host: &str) -> Option<TrackerHostSchedule> {
+        let hosts = self.hosts.lock();
+        let state = hosts.get(host)?;
+        Some(TrackerHostSchedule {
+            host: host.to_owned(),
+            min_gap_ms: self.min_gap.as_millis() as u64,
+            waiting: state.waiting,
+            next_slot_in_ms: state
+                .next_slot
+                .saturating_duration_since(Instant::now())
+                .as_millis() as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::TrackerHostScheduler;
+
+    #[test]
+    fn test_announces_to_one_host_are_spaced() {
+        let gap = Duration::from_secs(2);
+        let s = TrackerHostScheduler::new(gap);
+        let now = Instant::now();
+
+        assert_eq!(s.reserve("a.example", now), now);
+        assert_eq!(s.reserve("a.example", now), now + gap);
+        assert_eq!(s.reserve("a.example", now), now + gap * 2);
+        // Other hosts aren't affected.
+        assert_eq!(s.reserve("b.example", now), now);
+        // After a quiet period, the next announce goes right away.
+        let later = now + Duration::from_secs(60);
+        assert_eq!(s.reserve("a.example", later), later);
+
+        assert_eq!(s.host_schedule("a.example").unwrap().waiting, 4);
+        assert!(s.host_schedule("c.example").is_none());
+    }
+}
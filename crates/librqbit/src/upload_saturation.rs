@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::TorrentId;
+
+const DEFAULT_THRESHOLD: f64 = 0.9;
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// What to do once the upload has been saturated for [UploadSaturationOptions::window].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadSaturationAction {
+    /// Only report it in [crate::SessionStats::upload_saturation].
+    Report,
+    /// Pause the torrents that are seeding, i.e. only uploading, so that downloads and
+    /// interactive traffic get the upstream back. They stay paused until resumed.
+    #[default]
+    PauseSeeding,
+}
+
+/// Detect the upload saturating the upstream link, see
+/// [crate::SessionOptions::upload_saturation].
+#[derive(Debug, Clone, Default)]
+pub struct UploadSaturationOptions {
+    /// Upstream bandwidth of the link, in bytes per second.
+    pub capacity_bytes_per_sec: u64,
+    /// Fraction of the capacity at which the upload counts as saturated, above 0 and at most
+    /// 1. Defaults to 0.9.
+    pub threshold: Option<f64>,
+    /// How long the upload has to stay saturated before acting. Defaults to 1 minute.
+    pub window: Option<Duration>,
+    pub action: UploadSaturationAction,
+}
+
+impl UploadSaturationOptions {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.capacity_bytes_per_sec == 0 {
+            anyhow::bail!("the upload capacity must be at least 1 byte per second");
+        }
+        if let Some(threshold) = self.threshold {
+            if threshold.is_nan() || threshold <= 0. || threshold > 1. {
+                anyhow::bail!("the threshold must be above 0 and at most 1, got {threshold}");
+            }
+        }
+        if self.window == Some(Duration::ZERO) {
+            anyhow::bail!("the window must be longer than 0");
+        }
+        Ok(())
+    }
+}
+
+/// Shown in [crate::SessionStats] when [crate::SessionOptions::upload_saturation] is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UploadSaturationStats {
+    /// Whether the session-wide upload rate is at the threshold right now.
+    pub saturated: bool,
+    /// How many times the upload stayed saturated for the whole window.
+    pub triggered: u64,
+    /// Unix timestamp of the last trigger, in seconds.
+    pub last_triggered: Option<u64>,
+    /// Torrents paused by the last trigger.
+    pub paused: Vec<TorrentId>,
+}
+
+impl UploadSaturationStats {
+    pub(crate) fn record_trigger(&mut self, paused: Vec<TorrentId>) {
+        self.triggered += 1;
+        self.last_triggered = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .ok();
+        self.paused = paused;
+    }
+}
+
+// Tracks how long the upload rate has been at the threshold.
+pub(crate) struct UploadSaturationDetector {
+    threshold_bps: u64,
+    window: Duration,
+    saturated_since: Option<Instant>,
+}
+
+impl UploadSaturationDetector {
+    pub fn new(opts: &UploadSaturationOptions) -> Self {
+        let threshold = opts.threshold.unwrap_or(DEFAULT_THRESHOLD);
+        Self {
+            threshold_bps: (opts.capacity_bytes_per_sec as f64 * threshold) as u64,
+            window: opts.window.unwrap_or(DEFAULT_WINDOW),
+            saturated_since: None,
+        }
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.saturated_since.is_some()
+    }
+
+    // Returns true once the upload has been saturated for the whole window. The window
+    // then starts over, so a sustained saturation triggers again one window later.
+    pub fn update(&mut self, upload_bps: u64, now: Instant) -> bool {
+        if upload_bps < self.threshold_bps {
+            self.saturated_since = None;
+            return false;
+        }
+        let since = *self.saturated_since.get_or_insert(now);
+        if now.duration_since(since) < self.window {
+            return false;
+        }
+        self.saturated_since = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{UploadSaturationDetector, UploadSaturationOptions};
+
+    #[test]
+    fn test_validate() {
+        let valid = UploadSaturationOptions {
+            capacity_bytes_per_sec: 1000,
+            threshold: Some(1.),
+            window: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+        let invalid = [
+            UploadSaturationOptions {
+                capacity_bytes_per_sec: 0,
+                ..valid.clone()
+            },
+            UploadSaturationOptions {
+                threshold: Some(0.),
+                ..valid.clone()
+            },
+            UploadSaturationOptions {
+                threshold: Some(1.5),
+                ..valid.clone()
+            },
+            UploadSaturationOptions {
+                threshold: Some(f64::NAN),
+                ..valid.clone()
+            },
+            UploadSaturationOptions {
+                window: Some(Duration::ZERO),
+                ..valid.clone()
+            },
+        ];
+        for opts in invalid {
+            assert!(opts.validate().is_err(), "{opts:?}");
+        }
+    }
+
+    #[test]
+    fn test_detector_needs_sustained_saturation() {
+        let mut d = UploadSaturationDetector::new(&UploadSaturationOptions {
+            capacity_bytes_per_sec: 1000,
+            threshold: Some(0.5),
+            window: Some(Duration::from_secs(10)),
+            ..Default::default()
+        });
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        assert!(!d.update(600, at(0)));
+        assert!(d.is_saturated());
+        assert!(!d.update(600, at(5)));
+        // A dip below the threshold starts the window over.
+        assert!(!d.update(100, at(6)));
+        assert!(!d.is_saturated());
+        assert!(!d.update(600, at(7)));
+        assert!(!d.update(600, at(16)));
+        assert!(d.update(600, at(17)));
+        // Still saturated, triggers again after another window.
+        assert!(!d.update(600, at(20)));
+        assert!(d.update(600, at(27)));
+    }
+}
@@ -0,0 +1,130 @@
+// Verifying pieces without reading them back, for download folders on slow network storage,
+// see SessionOptions::verify_from_memory. Like SessionOptions::verify_before_write, a piece is
+// kept in memory while it downloads, hashed there once complete, and only written if it
+// passes. Unlike it, the memory is capped across the session: pieces started while the cap is
+// reached are written chunk by chunk as usual, and read back with several reads in flight at
+// once, as a network filesystem is mostly latency and serves parallel reads about as fast as
+// a single one.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MAX_BUFFERED_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_PARALLEL_READS: usize = 4;
+
+/// See [crate::SessionOptions::verify_from_memory].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyFromMemoryOptions {
+    /// Memory for the pieces being downloaded, across all torrents. Defaults to 256 MiB.
+    pub max_buffered_bytes: Option<u64>,
+    /// How many reads to have in flight when a piece that didn't fit in memory is read back.
+    /// Defaults to 4.
+    pub parallel_reads: Option<usize>,
+}
+
+/// Shown in [crate::SessionStats] when [crate::SessionOptions::verify_from_memory] is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyFromMemoryStats {
+    pub buffered_bytes: u64,
+    pub max_buffered_bytes: u64,
+    /// Pieces hashed in memory since the session started.
+    pub pieces_from_memory: u64,
+    /// Pieces read back from disk as there was no memory left when they started.
+    pub pieces_read_back: u64,
+}
+
+// Shared by all torrents of a session.
+pub(crate) struct PieceMemoryBudget {
+    max_bytes: u64,
+    pub parallel_reads: usize,
+    used: AtomicU64,
+    from_memory: AtomicU64,
+    read_back: AtomicU64,
+}
+
+impl PieceMemoryBudget {
+    pub fn new(opts: VerifyFromMemoryOptions) -> Self {
+        Self {
+            max_bytes: opts
+                .max_buffered_bytes
+                .unwrap_or(DEFAULT_MAX_BUFFERED_BYTES),
+            parallel_reads: opts.parallel_reads.unwrap_or(DEFAULT_PARALLEL_READS).max(1),
+            used: AtomicU64::new(0),
+            from_memory: AtomicU64::new(0),
+            read_back: AtomicU64::new(0),
+        }
+    }
+
+    // None if "bytes" don't fit.
+    pub fn try_reserve(self: &Arc<Self>, bytes: u64) -> Option<PieceMemoryReservation> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|new| *new <= self.max_bytes)
+            })
+            .ok()?;
+        Some(PieceMemoryReservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    pub fn on_piece_checked(&self, from_memory: bool) {
+        let counter = if from_memory {
+            &self.from_memory
+        } else {
+            &self.read_back
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> VerifyFromMemoryStats {
+        VerifyFromMemoryStats {
+            buffered_bytes: self.used.load(Ordering::Relaxed),
+            max_buffered_bytes: self.max_bytes,
+            pieces_from_memory: self.from_memory.load(Ordering::Relaxed),
+            pieces_read_back: self.read_back.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Given back to the budget when dropped.
+pub(crate) struct PieceMemoryReservation {
+    budget: Arc<PieceMemoryBudget>,
+    bytes: u64,
+}
+
+impl Drop for PieceMemoryReservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{PieceMemoryBudget, VerifyFromMemoryOptions};
+
+    #[test]
+    fn test_piece_memory_budget() {
+        let budget = Arc::new(PieceMemoryBudget::new(VerifyFromMemoryOptions {
+            max_buffered_bytes: Some(100),
+            parallel_reads: Some(0),
+        }));
+        assert_eq!(budget.parallel_reads, 1);
+        let a = budget.try_reserve(60).unwrap();
+        assert!(budget.try_reserve(50).is_none());
+        let b = budget.try_reserve(40).unwrap();
+        assert_eq!(budget.stats().buffered_bytes, 100);
+        drop(a);
+        assert_eq!(budget.stats().buffered_bytes, 40);
+        assert!(budget.try_reserve(50).is_some());
+        drop(b);
+        assert_eq!(budget.stats().buffered_bytes, 0);
+        assert!(budget.try_reserve(101).is_none());
+    }
+}
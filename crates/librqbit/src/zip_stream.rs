@@ -0,0 +1,518 @@
+// Streaming several files of a torrent as one ZIP archive, for the HTTP API.
+//
+// The archive is "stored", i.e. not compressed, so that its size doesn't depend on the
+// data, and it's written as it's sent: each file is read from disk once, and its CRC is
+// only known after its data, so it goes into a data descriptor following the file. ZIP64
+// fields are used only where sizes or offsets don't fit in 32 bits. All files get the DOS
+// epoch (1980-01-01) as their modification time.
+
+use std::{io::Read, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, error_span};
+
+use crate::torrent_state::{utils::is_file_complete, ManagedTorrent, ManagedTorrentState};
+
+const LOCAL_HEADER_SIG: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIG: u32 = 0x02014b50;
+const ZIP64_END_SIG: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x07064b50;
+const END_SIG: u32 = 0x06054b50;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+// Sizes and CRC in a data descriptor, UTF-8 names.
+const FLAGS: u16 = 0x0008 | 0x0800;
+const DOS_DATE_EPOCH: u16 = (1 << 5) | 1;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const READ_BUF_SIZE: usize = 64 * 1024;
+// Chunks queued for the client before reading from disk pauses.
+const CHANNEL_CAPACITY: usize = 8;
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn needs_zip64(v: u64) -> bool {
+    v >= u32::MAX as u64
+}
+
+// u32::MAX says "look in the ZIP64 extra field".
+fn u32_or_max(v: u64) -> u32 {
+    if needs_zip64(v) {
+        u32::MAX
+    } else {
+        v as u32
+    }
+}
+
+// Names are stored with a 16 bit length.
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.len() > u16::MAX as usize {
+        bail!(
+            "file name is {} bytes long, at most {} fit in a zip",
+            name.len(),
+            u16::MAX
+        );
+    }
+    Ok(())
+}
+
+struct CentralEntry {
+    name: String,
+    len: u64,
+    offset: u64,
+    crc: u32,
+}
+
+// Produces the archive around the file data, keeping only the names, sizes and CRCs of the
+// files written so far for the central directory at the end.
+#[derive(Default)]
+pub(crate) struct ZipBuilder {
+    written: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl ZipBuilder {
+    // The header to send before the file's "len" bytes of data.
+    pub fn start_file(&mut self, name: &str, len: u64) -> anyhow::Result<Vec<u8>> {
+        check_name(name)?;
+        let zip64 = needs_zip64(len);
+        let mut buf = Vec::with_capacity(30 + name.len() + 20);
+        put_u32(&mut buf, LOCAL_HEADER_SIG);
+        put_u16(
+            &mut buf,
+            if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            },
+        );
+        put_u16(&mut buf, FLAGS);
+        // Stored.
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, DOS_DATE_EPOCH);
+        // CRC and sizes are in the data descriptor.
+        put_u32(&mut buf, 0);
+        let size = if zip64 { u32::MAX } else { 0 };
+        put_u32(&mut buf, size);
+        put_u32(&mut buf, size);
+        put_u16(&mut buf, name.len() as u16);
+        put_u16(&mut buf, if zip64 { 20 } else { 0 });
+        buf.extend_from_slice(name.as_bytes());
+        if zip64 {
+            put_u16(&mut buf, ZIP64_EXTRA_ID);
+            put_u16(&mut buf, 16);
+            put_u64(&mut buf, 0);
+            put_u64(&mut buf, 0);
+        }
+
+        self.entries.push(CentralEntry {
+            name: name.to_owned(),
+            len,
+            offset: self.written,
+            crc: 0,
+        });
+        self.written += buf.len() as u64 + len;
+        Ok(buf)
+    }
+
+    // The data descriptor to send after the data of the file last started.
+    pub fn end_file(&mut self, crc: u32) -> Vec<u8> {
+        let entry = self
+            .entries
+            .last_mut()
+            .expect("bug: end_file() without start_file()");
+        entry.crc = crc;
+        let mut buf = Vec::with_capacity(24);
+        put_u32(&mut buf, DATA_DESCRIPTOR_SIG);
+        put_u32(&mut buf, crc);
+        if needs_zip64(entry.len) {
+            put_u64(&mut buf, entry.len);
+            put_u64(&mut buf, entry.len);
+        } else {
+            put_u32(&mut buf, entry.len as u32);
+            put_u32(&mut buf, entry.len as u32);
+        }
+        self.written += buf.len() as u64;
+        buf
+    }
+
+    // The central directory and end records.
+    pub fn finish(self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for entry in self.entries.iter() {
+            let mut extra = Vec::new();
+            if needs_zip64(entry.len) {
+                put_u64(&mut extra, entry.len);
+                put_u64(&mut extra, entry.len);
+            }
+            if needs_zip64(entry.offset) {
+                put_u64(&mut extra, entry.offset);
+            }
+            let version = if extra.is_empty() {
+                VERSION_DEFAULT
+            } else {
+                VERSION_ZIP64
+            };
+
+            put_u32(&mut buf, CENTRAL_HEADER_SIG);
+            // Made by, with the same version as needed.
+            put_u16(&mut buf, version);
+            put_u16(&mut buf, version);
+            put_u16(&mut buf, FLAGS);
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, DOS_DATE_EPOCH);
+            put_u32(&mut buf, entry.crc);
+            put_u32(&mut buf, u32_or_max(entry.len));
+            put_u32(&mut buf, u32_or_max(entry.len));
+            put_u16(&mut buf, entry.name.len() as u16);
+            put_u16(
+                &mut buf,
+                if extra.is_empty() {
+                    0
+                } else {
+                    extra.len() as u16 + 4
+                },
+            );
+            // Comment length, disk number, internal and external attributes.
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, 0);
+            put_u32(&mut buf, 0);
+            put_u32(&mut buf, u32_or_max(entry.offset));
+            buf.extend_from_slice(entry.name.as_bytes());
+            if !extra.is_empty() {
+                put_u16(&mut buf, ZIP64_EXTRA_ID);
+                put_u16(&mut buf, extra.len() as u16);
+                buf.extend_from_slice(&extra);
+            }
+        }
+
+        let count = self.entries.len() as u64;
+        let cd_offset = self.written;
+        let cd_size = buf.len() as u64;
+        let zip64 = count >= u16::MAX as u64 || needs_zip64(cd_size) || needs_zip64(cd_offset);
+        if zip64 {
+            let zip64_end_offset = cd_offset + cd_size;
+            put_u32(&mut buf, ZIP64_END_SIG);
+            // Size of the rest of this record.
+            put_u64(&mut buf, 44);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u32(&mut buf, 0);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, cd_size);
+            put_u64(&mut buf, cd_offset);
+
+            put_u32(&mut buf, ZIP64_LOCATOR_SIG);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, zip64_end_offset);
+            // Total disks.
+            put_u32(&mut buf, 1);
+        }
+
+        let count = if zip64 { u16::MAX } else { count as u16 };
+        put_u32(&mut buf, END_SIG);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, count);
+        put_u16(&mut buf, count);
+        put_u32(&mut buf, u32_or_max(cd_size));
+        put_u32(&mut buf, u32_or_max(cd_offset));
+        // Comment length.
+        put_u16(&mut buf, 0);
+        buf
+    }
+}
+
+struct ZipFile {
+    idx: usize,
+    name: String,
+    path: PathBuf,
+    offset: u64,
+    len: u64,
+}
+
+type ZipSender = mpsc::Sender<anyhow::Result<Bytes>>;
+
+async fn send(tx: &ZipSender, buf: Vec<u8>) -> anyhow::Result<()> {
+    tx.send(Ok(Bytes::from(buf)))
+        .await
+        .ok()
+        .context("client went away")
+}
+
+// Sends the file's data, returns its CRC.
+fn send_file_blocking(file: &ZipFile, tx: &ZipSender) -> anyhow::Result<u32> {
+    let mut f = std::fs::File::open(&file.path)
+        .with_context(|| format!("error opening {:?}", file.path))?
+        .take(file.len);
+    let mut crc = crc32fast::Hasher::new();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .with_context(|| format!("error reading {:?}", file.path))?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&buf[..n]);
+        sent += n as u64;
+        tx.blocking_send(Ok(Bytes::copy_from_slice(&buf[..n])))
+            .ok()
+            .context("client went away")?;
+    }
+    if sent != file.len {
+        // The header promised the full length, so the archive can't be finished.
+        bail!(
+            "{:?} is {} bytes long, expected {}",
+            file.path,
+            sent,
+            file.len
+        );
+    }
+    Ok(crc.finalize())
+}
+
+impl ManagedTorrent {
+    fn is_file_complete_now(&self, offset: u64, len: u64) -> anyhow::Result<bool> {
+        self.with_chunk_tracker(|ct| {
+            is_file_complete(&self.info().lengths, ct.get_have_pieces(), offset, len)
+        })
+    }
+
+    async fn wait_until_file_complete(&self, offset: u64, len: u64) -> anyhow::Result<()> {
+        loop {
+            let ready = self.with_state(|s| match s {
+                ManagedTorrentState::Paused(_) | ManagedTorrentState::Live(_) => Ok(true),
+                ManagedTorrentState::Initializing(_) => Ok(false),
+                ManagedTorrentState::Lazy(_) => {
+                    bail!("torrent isn't loaded, start or load it first")
+                }
+                ManagedTorrentState::Error(e) => bail!("torrent is in error: {:#}", e),
+                ManagedTorrentState::None => bail!("bug: torrent state is None"),
+            })?;
+            if ready && self.is_file_complete_now(offset, len)? {
+                return Ok(());
+            }
+            tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn write_zip(
+        &self,
+        files: Vec<ZipFile>,
+        wait: bool,
+        tx: &ZipSender,
+    ) -> anyhow::Result<()> {
+        let mut zip = ZipBuilder::default();
+        for file in files {
+            if wait {
+                self.wait_until_file_complete(file.offset, file.len)
+                    .await
+                    .with_context(|| format!("error waiting for file {}", file.idx))?;
+            }
+            send(tx, zip.start_file(&file.name, file.len)?).await?;
+            let tx_file = tx.clone();
+            let crc = tokio::task::spawn_blocking(move || send_file_blocking(&file, &tx_file))
+                .await
+                .context("bug: file reader panicked")??;
+            send(tx, zip.end_file(crc)).await?;
+        }
+        send(tx, zip.finish()).await
+    }
+
+    /// Stream the given files, all of them if None, as an uncompressed ZIP archive, reading
+    /// each from disk as it's sent. Files that aren't complete yet are left out, or with
+    /// "wait", waited for, in which case they have to be selected for download. Fails if no
+    /// file would be in the archive. If reading a file fails midway, so does the stream.
+    pub(crate) fn zip_stream(
+        self: &Arc<Self>,
+        files: Option<Vec<usize>>,
+        wait: bool,
+    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<Bytes>> + Send + 'static> {
+        let info = self.info();
+        let paths = info.file_paths()?;
+        let only_files = self.only_files();
+        let mut all = Vec::new();
+        let mut offset = 0u64;
+        for (idx, (name, len)) in info.info.iter_filenames_and_lengths()?.enumerate() {
+            let name = name.to_vec()?.join("/");
+            all.push(ZipFile {
+                idx,
+                name,
+                path: paths[idx].clone(),
+                offset,
+                len,
+            });
+            offset += len;
+        }
+
+        let selected = match files {
+            Some(files) => {
+                let mut selected = Vec::new();
+                for idx in files {
+                    if idx >= all.len() {
+                        bail!("torrent has no file {}", idx);
+                    }
+                    if !selected.contains(&idx) {
+                        selected.push(idx);
+                    }
+                }
+                selected
+            }
+            None => (0..all.len()).collect(),
+        };
+        let mut all = all.into_iter().map(Some).collect::<Vec<_>>();
+        let mut files = Vec::new();
+        for idx in selected {
+            let file = all[idx].take().expect("bug: file selected twice");
+            // Fail before anything is sent rather than midway.
+            check_name(&file.name).with_context(|| format!("can't zip file {}", idx))?;
+            if wait {
+                let skipped = only_files
+                    .as_ref()
+                    .map(|only| !only.contains(&idx))
+                    .unwrap_or(false);
+                if skipped {
+                    bail!(
+                        "file {} is not selected for download, it won't complete",
+                        idx
+                    );
+                }
+            } else if !self.is_file_complete_now(file.offset, file.len)? {
+                debug!(idx, "file not complete, leaving it out of the zip");
+                continue;
+            }
+            files.push(file);
+        }
+        if files.is_empty() {
+            bail!("none of the files are complete");
+        }
+
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let torrent = self.clone();
+        crate::spawn_utils::spawn(
+            "zip_stream",
+            error_span!(parent: info.span.clone(), "zip_stream"),
+            async move {
+                if let Err(e) = torrent.write_zip(files, wait, &tx).await {
+                    if tx.is_closed() {
+                        debug!("client went away while streaming zip");
+                        return Ok(());
+                    }
+                    let _ = tx.send(Err(anyhow::anyhow!("{:#}", e))).await;
+                    return Err(e);
+                }
+                Ok(())
+            },
+        );
+        Ok(ReceiverStream::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZipBuilder;
+
+    fn u16_at(buf: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes(buf[pos..pos + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_zip_layout() {
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"hello"), ("dir/b.txt", b"")];
+        let mut zip = ZipBuilder::default();
+        let mut archive = Vec::new();
+        for (name, data) in files {
+            archive.extend(zip.start_file(name, data.len() as u64).unwrap());
+            archive.extend_from_slice(data);
+            archive.extend(zip.end_file(crc32fast::hash(data)));
+        }
+        archive.extend(zip.finish());
+
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(&archive, end), 0x06054b50);
+        assert_eq!(u16_at(&archive, end + 10), 2);
+        let cd_size = u32_at(&archive, end + 12) as usize;
+        let cd_offset = u32_at(&archive, end + 16) as usize;
+        assert_eq!(cd_offset + cd_size, end);
+
+        // Walk the central directory, and check each entry against its local header.
+        let mut pos = cd_offset;
+        for (name, data) in files {
+            assert_eq!(u32_at(&archive, pos), 0x02014b50);
+            assert_eq!(u32_at(&archive, pos + 16), crc32fast::hash(data));
+            assert_eq!(u32_at(&archive, pos + 24), data.len() as u32);
+            let name_len = u16_at(&archive, pos + 28) as usize;
+            assert_eq!(&archive[pos + 46..pos + 46 + name_len], name.as_bytes());
+
+            let local = u32_at(&archive, pos + 42) as usize;
+            assert_eq!(u32_at(&archive, local), 0x04034b50);
+            let data_start = local + 30 + name.len();
+            assert_eq!(&archive[data_start..data_start + data.len()], data);
+            let descriptor = data_start + data.len();
+            assert_eq!(u32_at(&archive, descriptor), 0x08074b50);
+            assert_eq!(u32_at(&archive, descriptor + 4), crc32fast::hash(data));
+            pos += 46 + name_len;
+        }
+        assert_eq!(pos, end);
+    }
+
+    #[test]
+    fn test_zip64_for_large_files() {
+        let mut zip = ZipBuilder::default();
+        let len = 5 << 30;
+        let header = zip.start_file("big", len).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(header[22..26].try_into().unwrap()),
+            u32::MAX
+        );
+        // 16 byte sizes in the descriptor.
+        assert_eq!(zip.end_file(0).len(), 24);
+        let tail = zip.finish();
+        // The offset of the central directory doesn't fit, so there are ZIP64 end records.
+        assert_eq!(
+            u32::from_le_bytes(tail[tail.len() - 6..tail.len() - 2].try_into().unwrap()),
+            u32::MAX
+        );
+        assert!(tail.windows(4).any(|w| w == 0x06064b50u32.to_le_bytes()));
+    }
+
+    #[test]
+    fn test_long_names_are_rejected() {
+        let mut zip = ZipBuilder::default();
+        assert!(zip.start_file(&"a".repeat(u16::MAX as usize), 0).is_ok());
+        zip.end_file(0);
+        assert!(zip
+            .start_file(&"a".repeat(u16::MAX as usize + 1), 0)
+            .is_err());
+        // Nothing was added for it.
+        assert_eq!(zip.entries.len(), 1);
+    }
+}
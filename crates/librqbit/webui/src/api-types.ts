@@ -2,6 +2,7 @@
 export interface TorrentId {
   id: number;
   info_hash: string;
+  labels?: Array<string>;
 }
 
 export interface TorrentFile {
@@ -16,6 +17,8 @@ export interface TorrentDetails {
   name: string | null;
   info_hash: string;
   files: Array<TorrentFile>;
+  // Arbitrary JSON attached with POST /torrents/{index}/metadata.
+  user_metadata?: unknown;
 }
 
 export interface AddTorrentResponse {
@@ -46,6 +49,12 @@ export interface LiveTorrentStats {
     remaining_bytes: number;
     total_bytes: number;
     total_piece_download_ms: number;
+    endgame_duplicate_requests: number;
+    endgame_duplicate_bytes: number;
+    web_seed_bytes: number;
+    web_seed_pieces: number;
+    peer_bytes: number;
+    peer_pieces: number;
     peer_stats: {
       queued: number;
       connecting: number;
@@ -53,6 +62,7 @@ export interface LiveTorrentStats {
       seen: number;
       dead: number;
       not_needed: number;
+      choke_rotated: number;
     };
   };
   average_piece_download_time: {
@@ -71,6 +81,10 @@ export interface LiveTorrentStats {
       secs: number;
     };
   } | null;
+  upload_slots_used: number;
+  upload_slots: number | null;
+  peer_opts: PeerConnectionOptions;
+  peer_limit: number | null;
 }
 
 export const STATE_INITIALIZING = "initializing";
@@ -84,9 +98,51 @@ export interface TorrentStats {
   progress_bytes: number;
   finished: boolean;
   total_bytes: number;
+  lifetime: {
+    uploaded_bytes: number;
+    downloaded_bytes: number;
+    web_seed_bytes: number;
+  };
+  stalled: boolean;
+  unavailable_pieces: number | null;
+  locked: boolean;
+  boost_remaining_ms: number | null;
+  lazy: boolean;
+  priority_tier: "low" | "normal" | "high";
+  sequential: boolean;
+  sequential_after_percent: number | null;
   live: LiveTorrentStats | null;
 }
 
+// From GET /torrents/{id}/diagnose: why a torrent is or isn't downloading.
+export type TorrentDiagnosis =
+  | { reason: "downloading"; peers: number }
+  | { reason: "complete" }
+  | { reason: "checking"; progress_bytes: number; total_bytes: number }
+  | { reason: "paused" }
+  | { reason: "locked" }
+  | { reason: "queued"; position: number }
+  | {
+      reason: "metadata_not_resolved";
+      peers_seen: number;
+      peers_connecting: number;
+      peers_failed: number;
+    }
+  | { reason: "disk_error"; error: string }
+  | { reason: "error"; error: string }
+  | { reason: "no_peers"; known_peers: number; announce_enabled: boolean }
+  | { reason: "all_peers_choking"; peers: number }
+  | { reason: "pieces_unavailable"; peers: number; unavailable_pieces: number };
+
+// From GET /torrents/stats_stream. With "snapshot", "torrents" has the full stats of every
+// torrent. Otherwise the entries are JSON merge patches (RFC 7396) of the previous stats, or
+// the full stats of new torrents.
+export interface StatsDelta {
+  snapshot?: boolean;
+  torrents?: { [id: string]: Partial<TorrentStats> };
+  removed?: number[];
+}
+
 export interface ErrorDetails {
   id?: number;
   method?: string;
@@ -101,7 +157,9 @@ export type Duration = number;
 export interface PeerConnectionOptions {
   connect_timeout?: Duration | null;
   read_write_timeout?: Duration | null;
+  handshake_timeout?: Duration | null;
   keep_alive_interval?: Duration | null;
+  choke_timeout?: Duration | null;
 }
 
 export interface AddTorrentOptions {
@@ -169,6 +227,9 @@ export interface RqbitAPI {
     data: string | File,
     opts?: AddTorrentOptions
   ) => Promise<AddTorrentResponse>;
+  // If set, used instead of the browser's file input to choose a .torrent file.
+  // Resolves to something uploadTorrent() accepts, or null if cancelled.
+  pickTorrentFile?: () => Promise<string | null>;
 
   pause: (index: number) => Promise<void>;
   start: (index: number) => Promise<void>;
@@ -1,11 +1,14 @@
 import { FileInput } from "./buttons/FileInput";
 import { MagnetInput } from "./buttons/MagnetInput";
+import { useContext } from "react";
+import { ReadOnlyContext } from "../context";
 
 // @ts-ignore
 import Logo from "../../assets/logo.svg?react";
 
 export const Header = ({ title }: { title: string }) => {
   const [name, version] = title.split("-");
+  const readOnly = useContext(ReadOnlyContext);
   return (
     <header className="bg-slate-50 drop-shadow-lg flex flex-wrap justify-center lg:justify-between items-center dark:bg-slate-800 mb-3">
       <div className="flex flex-nowrap items-center justify-between m-2">
@@ -17,10 +20,12 @@ export const Header = ({ title }: { title: string }) => {
           </div>
         </h1>
       </div>
-      <div className="flex flex-wrap gap-1 m-2">
-        <MagnetInput className="flex-grow justify-center dark:text-white" />
-        <FileInput className="flex-grow justify-center dark:text-white" />
-      </div>
+      {!readOnly && (
+        <div className="flex flex-wrap gap-1 m-2">
+          <MagnetInput className="flex-grow justify-center dark:text-white" />
+          <FileInput className="flex-grow justify-center dark:text-white" />
+        </div>
+      )}
     </header>
   );
 };
@@ -11,13 +11,17 @@ import { Speed } from "./Speed";
 import { formatBytes } from "../helper/formatBytes";
 import { torrentDisplayName } from "../helper/getTorrentDisplayName";
 import { getCompletionETA } from "../helper/getCompletionETA";
+import { formatSecondsToTime } from "../helper/formatSecondsToTime";
 import { StatusIcon } from "./StatusIcon";
+import { useContext } from "react";
+import { ReadOnlyContext } from "../context";
 
 export const TorrentRow: React.FC<{
   id: number;
   detailsResponse: TorrentDetails | null;
   statsResponse: TorrentStats | null;
 }> = ({ id, detailsResponse, statsResponse }) => {
+  const readOnly = useContext(ReadOnlyContext);
   const state = statsResponse?.state ?? "";
   const error = statsResponse?.error ?? null;
   const totalBytes = statsResponse?.total_bytes ?? 1;
@@ -99,11 +103,27 @@ export const TorrentRow: React.FC<{
                 </>
               )}
             </div>
+            {statsResponse?.boost_remaining_ms != null && (
+              <p className="text-blue-600 text-sm">
+                <strong>Boosted:</strong>{" "}
+                {formatSecondsToTime(
+                  Math.ceil(statsResponse.boost_remaining_ms / 1000),
+                )}{" "}
+                left.
+              </p>
+            )}
+            {statsResponse?.stalled && (
+              <p className="text-amber-600 text-sm">
+                <strong>Stalled:</strong> no progress in a while.
+                {!!statsResponse.unavailable_pieces &&
+                  ` ${statsResponse.unavailable_pieces} pieces aren't available from any connected peer, it won't finish until a peer having them connects.`}
+              </p>
+            )}
           </>
         )}
       </div>
       {/* Actions */}
-      {statsResponse && (
+      {statsResponse && !readOnly && (
         <div className="">
           <TorrentActions id={id} statsResponse={statsResponse} />
         </div>
@@ -1,10 +1,12 @@
-import { RefObject, useRef, useState } from "react";
+import { RefObject, useContext, useRef, useState } from "react";
 import { UploadButton } from "./UploadButton";
 import { CgFileAdd } from "react-icons/cg";
+import { APIContext } from "../../context";
 
 export const FileInput = ({ className }: { className?: string }) => {
   const inputRef = useRef<HTMLInputElement>() as RefObject<HTMLInputElement>;
-  const [file, setFile] = useState<File | null>(null);
+  const [file, setFile] = useState<File | string | null>(null);
+  const API = useContext(APIContext);
 
   const onFileChange = async () => {
     if (!inputRef?.current?.files) {
@@ -22,7 +24,11 @@ export const FileInput = ({ className }: { className?: string }) => {
     setFile(null);
   };
 
-  const onClick = () => {
+  const onClick = async () => {
+    if (API.pickTorrentFile) {
+      setFile(await API.pickTorrentFile());
+      return;
+    }
     if (!inputRef?.current) {
       return;
     }
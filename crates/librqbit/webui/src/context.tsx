@@ -32,3 +32,6 @@ export const APIContext = createContext<RqbitAPI>({
   },
 });
 export const RefreshTorrentStatsContext = createContext({ refresh: () => {} });
+// True when the server only exposes read-only APIs, so there's no point showing
+// controls that would modify state.
+export const ReadOnlyContext = createContext<boolean>(false);
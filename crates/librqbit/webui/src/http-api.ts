@@ -63,7 +63,15 @@ const makeRequest = async (
   return result;
 };
 
-export const API: RqbitAPI & { getVersion: () => Promise<string> } = {
+export interface ServerInfo {
+  version: string;
+  read_only: boolean;
+}
+
+export const API: RqbitAPI & {
+  getVersion: () => Promise<string>;
+  getServerInfo: () => Promise<ServerInfo>;
+} = {
   getStreamLogsUrl: () => apiUrl + "/stream_logs",
   listTorrents: (): Promise<ListTorrentsResponse> =>
     makeRequest("GET", "/torrents"),
@@ -119,4 +127,8 @@ export const API: RqbitAPI & { getVersion: () => Promise<string> } = {
     const r = await makeRequest("GET", "/");
     return r.version;
   },
+  getServerInfo: async (): Promise<ServerInfo> => {
+    const r = await makeRequest("GET", "/");
+    return { version: r.version, read_only: r.read_only === true };
+  },
 };
@@ -2,18 +2,20 @@ import { StrictMode, useEffect, useState } from "react";
 import ReactDOM from "react-dom/client";
 import { RqbitWebUI } from "./rqbit-web";
 import { customSetInterval } from "./helper/customSetInterval";
-import { APIContext } from "./context";
+import { APIContext, ReadOnlyContext } from "./context";
 import { API } from "./http-api";
 import "./globals.css";
 
 const RootWithVersion = () => {
   let [title, setTitle] = useState<string>("rqbit web UI");
+  let [readOnly, setReadOnly] = useState<boolean>(false);
   useEffect(() => {
     const refreshVersion = () =>
-      API.getVersion().then(
-        (version) => {
+      API.getServerInfo().then(
+        ({ version, read_only }) => {
           const title = `rqbit web UI - v${version}`;
           setTitle(title);
+          setReadOnly(read_only);
           document.title = title;
           return 10000;
         },
@@ -26,7 +28,9 @@ const RootWithVersion = () => {
 
   return (
     <APIContext.Provider value={API}>
-      <RqbitWebUI title={title} />
+      <ReadOnlyContext.Provider value={readOnly}>
+        <RqbitWebUI title={title} />
+      </ReadOnlyContext.Provider>
     </APIContext.Provider>
   );
 };
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How much periodic network tasks are randomized by default: ±10%.
+pub const DEFAULT_JITTER: f64 = 0.1;
+
+/// Randomize a duration uniformly within ±`ratio` of it.
+///
+/// Periodic tasks that were started at the same moment, e.g. all announces after waking
+/// up from sleep, would otherwise keep firing in bursts forever.
+pub fn jitter(duration: Duration, ratio: f64) -> Duration {
+    let ratio = ratio.clamp(0., 1.);
+    if ratio == 0. || duration.is_zero() {
+        return duration;
+    }
+    let factor = rand::thread_rng().gen_range(1. - ratio..=1. + ratio);
+    duration.mul_f64(factor)
+}
+
+/// Sleep for the duration with [DEFAULT_JITTER] applied.
+pub async fn sleep_jittered(duration: Duration) {
+    tokio::time::sleep(jitter(duration, DEFAULT_JITTER)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::jitter;
+
+    #[test]
+    fn test_jitter_within_bounds() {
+        let base = Duration::from_secs(60);
+        let min = base.mul_f64(0.9);
+        let max = base.mul_f64(1.1);
+        let mut seen_different = false;
+        for _ in 0..1000 {
+            let d = jitter(base, 0.1);
+            assert!(d >= min && d <= max, "{d:?} is out of [{min:?}, {max:?}]");
+            seen_different |= d != base;
+        }
+        assert!(seen_different);
+    }
+
+    #[test]
+    fn test_jitter_zero_ratio_or_duration_is_exact() {
+        assert_eq!(jitter(Duration::from_secs(5), 0.), Duration::from_secs(5));
+        assert_eq!(jitter(Duration::ZERO, 0.5), Duration::ZERO);
+    }
+}
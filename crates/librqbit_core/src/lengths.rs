@@ -65,7 +65,27 @@ impl Lengths {
         torrent: &TorrentMetaV1Info<ByteBuf>,
     ) -> anyhow::Result<Lengths> {
         let total_length = torrent.iter_file_lengths()?.sum();
-        Lengths::new(total_length, torrent.piece_length, None)
+        let lengths = Lengths::new(total_length, torrent.piece_length, None)?;
+
+        // Every piece needs its hash, or verifying it would go out of bounds of "pieces".
+        let hashes_len = torrent.pieces.as_ref().len();
+        if hashes_len % 20 != 0 {
+            anyhow::bail!(
+                "malformed torrent: \"pieces\" is {} bytes long, not a multiple of 20",
+                hashes_len
+            );
+        }
+        if hashes_len / 20 != lengths.total_pieces() as usize {
+            anyhow::bail!(
+                "malformed torrent: it has {} piece hashes, but {} bytes in pieces of {} bytes \
+                 make {} pieces",
+                hashes_len / 20,
+                total_length,
+                torrent.piece_length,
+                lengths.total_pieces()
+            );
+        }
+        Ok(lengths)
     }
 
     pub fn new(
@@ -253,6 +273,29 @@ mod tests {
         Lengths::new(1174243328, 262144, None).unwrap()
     }
 
+    fn make_info(length: u64, piece_length: u32, hashes: usize) -> TorrentMetaV1Info<Vec<u8>> {
+        TorrentMetaV1Info {
+            name: None,
+            pieces: vec![0; hashes],
+            piece_length,
+            length: Some(length),
+            md5sum: None,
+            files: None,
+        }
+    }
+
+    #[test]
+    fn test_from_torrent_piece_hashes() {
+        // 3 pieces, the last one short.
+        let l = Lengths::from_torrent(&make_info(40000, 16384, 60)).unwrap();
+        assert_eq!(l.total_pieces(), 3);
+
+        // Truncated and padded hash strings.
+        for hashes in [0, 40, 59, 61, 80] {
+            assert!(Lengths::from_torrent(&make_info(40000, 16384, hashes)).is_err());
+        }
+    }
+
     #[test]
     fn test_total_pieces() {
         let l = make_lengths();
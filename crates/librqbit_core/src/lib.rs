@@ -1,6 +1,7 @@
 pub mod constants;
 pub mod directories;
 pub mod hash_id;
+pub mod jitter;
 pub mod lengths;
 pub mod magnet;
 pub mod peer_id;
@@ -1,11 +1,19 @@
-use std::{iter::once, path::PathBuf};
+use std::{
+    iter::once,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
 use bencode::BencodeDeserializer;
 use buffers::{ByteBuf, ByteString};
 use clone_to_owned::CloneToOwned;
 use itertools::Either;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use tracing::warn;
 
 use crate::hash_id::Id20;
 
@@ -26,21 +34,50 @@ pub fn torrent_from_bytes<'de, ByteBuf: Deserialize<'de>>(
     Ok(t)
 }
 
+/// Serialize torrent metainfo back to .torrent file bytes.
+///
+/// The optional top-level fields, e.g. "comment", "created by" and "creation date", are
+/// written back as they were parsed. The info dictionary hashes the same as the original
+/// one as long as it had no keys unknown to [TorrentMetaV1Info].
+pub fn torrent_to_bytes<ByteBuf: Serialize>(
+    torrent: &TorrentMetaV1<ByteBuf>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    bencode::bencode_serialize_to_writer(torrent, &mut buf).context("error serializing torrent")?;
+    Ok(buf)
+}
+
 /// A parsed .torrent file.
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TorrentMetaV1<BufType> {
     pub announce: BufType,
-    #[serde(rename = "announce-list", default = "Vec::new")]
+    #[serde(
+        rename = "announce-list",
+        default = "Vec::new",
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub announce_list: Vec<Vec<BufType>>,
+    /// BEP 17 HTTP seeds, which serve whole pieces by info hash and piece index.
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub httpseeds: Vec<BufType>,
     pub info: TorrentMetaV1Info<BufType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<BufType>,
-    #[serde(rename = "created by")]
+    #[serde(rename = "created by", skip_serializing_if = "Option::is_none")]
     pub created_by: Option<BufType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<BufType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub publisher: Option<BufType>,
-    #[serde(rename = "publisher-url")]
+    #[serde(rename = "publisher-url", skip_serializing_if = "Option::is_none")]
     pub publisher_url: Option<BufType>,
-    #[serde(rename = "creation date")]
+    /// Unix timestamp. None if it's missing or bogus, e.g. not an integer or in the future.
+    #[serde(
+        rename = "creation date",
+        default,
+        deserialize_with = "deserialize_creation_date",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub creation_date: Option<usize>,
 
     #[serde(skip)]
@@ -53,6 +90,65 @@ impl<BufType> TorrentMetaV1<BufType> {
     }
 }
 
+// Creation dates this far ahead of our clock are still believed.
+const CREATION_DATE_MAX_CLOCK_SKEW: Duration = Duration::from_secs(86400);
+
+fn check_creation_date(date: i64) -> Option<usize> {
+    let max = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + CREATION_DATE_MAX_CLOCK_SKEW;
+    match u64::try_from(date) {
+        Ok(secs) if secs <= max.as_secs() => usize::try_from(secs).ok(),
+        _ => {
+            warn!(date, "ignoring out of range creation date in torrent");
+            None
+        }
+    }
+}
+
+// Bogus creation dates are dropped instead of failing the whole torrent, as nothing needs
+// them.
+fn deserialize_creation_date<'de, D>(de: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct CreationDateVisitor;
+
+    impl<'de> Visitor<'de> for CreationDateVisitor {
+        type Value = Option<usize>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a unix timestamp")
+        }
+
+        fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+            Ok(check_creation_date(v))
+        }
+
+        fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(check_creation_date(v.try_into().unwrap_or(i64::MAX)))
+        }
+
+        fn visit_bytes<E>(self, _v: &[u8]) -> Result<Self::Value, E> {
+            warn!("ignoring creation date in torrent, it's not an integer");
+            Ok(None)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            while seq.next_element::<IgnoredAny>()?.is_some() {}
+            self.visit_bytes(&[])
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+            self.visit_bytes(&[])
+        }
+    }
+
+    de.deserialize_any(CreationDateVisitor)
+}
+
 /// Main torrent information, shared by .torrent files and magnet link contents.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct TorrentMetaV1Info<BufType> {
@@ -250,6 +346,7 @@ where
         TorrentMetaV1 {
             announce: self.announce.clone_to_owned(),
             announce_list: self.announce_list.clone_to_owned(),
+            httpseeds: self.httpseeds.clone_to_owned(),
             info: self.info.clone_to_owned(),
             comment: self.comment.clone_to_owned(),
             created_by: self.created_by.clone_to_owned(),
@@ -327,4 +424,76 @@ mod tests {
 
         assert_eq!(torrent, deserialized);
     }
+
+    #[test]
+    fn test_export_round_trip_keeps_info_hash_and_optional_fields() {
+        let mut buf = Vec::new();
+        std::fs::File::open(TORRENT_FILENAME)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+
+        let torrent: TorrentMetaV1Borrowed = torrent_from_bytes(&buf).unwrap();
+        let exported = torrent_to_bytes(&torrent).unwrap();
+        let reparsed: TorrentMetaV1Borrowed = torrent_from_bytes(&exported).unwrap();
+
+        assert_eq!(torrent.info_hash, reparsed.info_hash);
+        assert_eq!(torrent.info, reparsed.info);
+        assert_eq!(torrent.comment, reparsed.comment);
+        assert_eq!(torrent.created_by, reparsed.created_by);
+        assert_eq!(torrent.creation_date, reparsed.creation_date);
+        assert_eq!(torrent.announce_list, reparsed.announce_list);
+        // All keys of this file are known, so it should even be byte-identical.
+        assert_eq!(exported, buf);
+    }
+
+    #[test]
+    fn test_bogus_creation_date_is_ignored() {
+        let mut buf = Vec::new();
+        std::fs::File::open(TORRENT_FILENAME)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        let mut torrent: TorrentMetaV1Borrowed = torrent_from_bytes(&buf).unwrap();
+        assert!(torrent.creation_date.is_some());
+
+        // Year 5138.
+        torrent.creation_date = Some(99999999999);
+        let exported = torrent_to_bytes(&torrent).unwrap();
+        let reparsed: TorrentMetaV1Borrowed = torrent_from_bytes(&exported).unwrap();
+        assert_eq!(reparsed.creation_date, None);
+        assert_eq!(reparsed.info_hash, torrent.info_hash);
+
+        let date = b"13:creation datei99999999999e";
+        let pos = exported
+            .windows(date.len())
+            .position(|w| w == date)
+            .unwrap();
+        for bogus in [&b"i-1e"[..], b"3:abc", b"li1ee"] {
+            let mut patched = exported[..pos + 16].to_vec();
+            patched.extend_from_slice(bogus);
+            patched.extend_from_slice(&exported[pos + date.len()..]);
+            let reparsed: TorrentMetaV1Borrowed = torrent_from_bytes(&patched).unwrap();
+            assert_eq!(reparsed.creation_date, None);
+            assert_eq!(reparsed.info_hash, torrent.info_hash);
+        }
+    }
+
+    #[test]
+    fn test_httpseeds_round_trip() {
+        let mut buf = Vec::new();
+        std::fs::File::open(TORRENT_FILENAME)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+
+        let mut torrent: TorrentMetaV1Borrowed = torrent_from_bytes(&buf).unwrap();
+        assert!(torrent.httpseeds.is_empty());
+
+        torrent.httpseeds = vec![ByteBuf(b"http://seed.example/seed.php")];
+        let exported = torrent_to_bytes(&torrent).unwrap();
+        let reparsed: TorrentMetaV1Borrowed = torrent_from_bytes(&exported).unwrap();
+        assert_eq!(reparsed.httpseeds, torrent.httpseeds);
+        assert_eq!(torrent.info_hash, reparsed.info_hash);
+    }
 }
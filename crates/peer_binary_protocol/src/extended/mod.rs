@@ -10,6 +10,7 @@ use super::MessageDeserializeError;
 
 pub mod handshake;
 pub mod ut_metadata;
+pub mod ut_pex;
 
 use super::MY_EXTENDED_UT_METADATA;
 
@@ -0,0 +1,89 @@
+use serde::Serialize;
+
+/// The per-peer flags byte of ut_pex (BEP 11), sent in "added.f" / "added6.f" alongside
+/// the compact peer addresses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PexPeerFlags {
+    /// The peer prefers encrypted (MSE) connections.
+    pub prefers_encryption: bool,
+    /// The peer is a seed, or only uploads.
+    pub seed: bool,
+    pub supports_utp: bool,
+    pub supports_holepunch: bool,
+    /// The peer that sent the message connected to this one directly, so it's reachable.
+    pub reachable: bool,
+}
+
+impl PexPeerFlags {
+    const PREFERS_ENCRYPTION: u8 = 0x01;
+    const SEED: u8 = 0x02;
+    const SUPPORTS_UTP: u8 = 0x04;
+    const SUPPORTS_HOLEPUNCH: u8 = 0x08;
+    const REACHABLE: u8 = 0x10;
+
+    pub fn from_byte(b: u8) -> Self {
+        Self {
+            prefers_encryption: b & Self::PREFERS_ENCRYPTION != 0,
+            seed: b & Self::SEED != 0,
+            supports_utp: b & Self::SUPPORTS_UTP != 0,
+            supports_holepunch: b & Self::SUPPORTS_HOLEPUNCH != 0,
+            reachable: b & Self::REACHABLE != 0,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        let mut b = 0;
+        for (set, bit) in [
+            (self.prefers_encryption, Self::PREFERS_ENCRYPTION),
+            (self.seed, Self::SEED),
+            (self.supports_utp, Self::SUPPORTS_UTP),
+            (self.supports_holepunch, Self::SUPPORTS_HOLEPUNCH),
+            (self.reachable, Self::REACHABLE),
+        ] {
+            if set {
+                b |= bit;
+            }
+        }
+        b
+    }
+
+    /// Parse the "added.f" string, one byte per peer in the same order as "added". Peers
+    /// without a flags byte get the defaults.
+    pub fn parse_many(flags: &[u8], peers: usize) -> Vec<Self> {
+        (0..peers)
+            .map(|i| {
+                flags
+                    .get(i)
+                    .map(|b| Self::from_byte(*b))
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PexPeerFlags;
+
+    #[test]
+    fn test_pex_flags_roundtrip() {
+        let flags = PexPeerFlags::from_byte(0x13);
+        assert!(flags.prefers_encryption);
+        assert!(flags.seed);
+        assert!(!flags.supports_utp);
+        assert!(!flags.supports_holepunch);
+        assert!(flags.reachable);
+        assert_eq!(flags.to_byte(), 0x13);
+
+        // Unknown bits are ignored.
+        assert_eq!(PexPeerFlags::from_byte(0xe0), PexPeerFlags::default());
+    }
+
+    #[test]
+    fn test_pex_flags_parse_many_pads_missing() {
+        let flags = PexPeerFlags::parse_many(&[0x01], 2);
+        assert_eq!(flags.len(), 2);
+        assert!(flags[0].prefers_encryption);
+        assert_eq!(flags[1], PexPeerFlags::default());
+    }
+}
@@ -31,6 +31,8 @@ const LEN_PREFIX_NOT_INTERESTED: u32 = 1;
 const LEN_PREFIX_HAVE: u32 = 5;
 const LEN_PREFIX_PIECE: u32 = 9;
 const LEN_PREFIX_REQUEST: u32 = 13;
+const LEN_PREFIX_HAVE_ALL: u32 = 1;
+const LEN_PREFIX_HAVE_NONE: u32 = 1;
 
 const MSGID_CHOKE: u8 = 0;
 const MSGID_UNCHOKE: u8 = 1;
@@ -41,6 +43,12 @@ const MSGID_BITFIELD: u8 = 5;
 const MSGID_REQUEST: u8 = 6;
 const MSGID_PIECE: u8 = 7;
 const MSGID_CANCEL: u8 = 8;
+// BEP 6, the fast extension.
+const MSGID_SUGGEST_PIECE: u8 = 0x0d;
+const MSGID_HAVE_ALL: u8 = 0x0e;
+const MSGID_HAVE_NONE: u8 = 0x0f;
+const MSGID_REJECT_REQUEST: u8 = 0x10;
+const MSGID_ALLOWED_FAST: u8 = 0x11;
 const MSGID_EXTENDED: u8 = 20;
 
 pub const MY_EXTENDED_UT_METADATA: u8 = 3;
@@ -180,6 +188,16 @@ pub enum Message<ByteBuf: std::hash::Hash + Eq> {
     NotInterested,
     Piece(Piece<ByteBuf>),
     Extended(ExtendedMessage<ByteBuf>),
+    // The fast extension messages below are only valid if both sides set the fast bit in
+    // the handshake, see [Handshake::supports_fast].
+    /// Sent instead of a bitfield by peers that have every piece.
+    HaveAll,
+    /// Sent instead of a bitfield by peers that have no pieces.
+    HaveNone,
+    SuggestPiece(u32),
+    /// The request won't be answered.
+    RejectRequest(Request),
+    AllowedFast(u32),
 }
 
 pub type MessageBorrowed<'a> = Message<ByteBuf<'a>>;
@@ -216,6 +234,11 @@ where
             Message::Have(v) => Message::Have(*v),
             Message::NotInterested => Message::NotInterested,
             Message::Extended(e) => Message::Extended(e.clone_to_owned()),
+            Message::HaveAll => Message::HaveAll,
+            Message::HaveNone => Message::HaveNone,
+            Message::SuggestPiece(v) => Message::SuggestPiece(*v),
+            Message::RejectRequest(req) => Message::RejectRequest(*req),
+            Message::AllowedFast(v) => Message::AllowedFast(*v),
         }
     }
 }
@@ -256,6 +279,11 @@ where
             Message::KeepAlive => (LEN_PREFIX_KEEPALIVE, 0),
             Message::Have(_) => (LEN_PREFIX_HAVE, MSGID_HAVE),
             Message::Extended(_) => (0, MSGID_EXTENDED),
+            Message::HaveAll => (LEN_PREFIX_HAVE_ALL, MSGID_HAVE_ALL),
+            Message::HaveNone => (LEN_PREFIX_HAVE_NONE, MSGID_HAVE_NONE),
+            Message::SuggestPiece(_) => (LEN_PREFIX_HAVE, MSGID_SUGGEST_PIECE),
+            Message::RejectRequest(_) => (LEN_PREFIX_REQUEST, MSGID_REJECT_REQUEST),
+            Message::AllowedFast(_) => (LEN_PREFIX_HAVE, MSGID_ALLOWED_FAST),
         }
     }
     pub fn serialize(
@@ -273,7 +301,9 @@ where
         let ser = bopts();
 
         match self {
-            Message::Request(request) | Message::Cancel(request) => {
+            Message::Request(request)
+            | Message::Cancel(request)
+            | Message::RejectRequest(request) => {
                 const MSG_LEN: usize = PREAMBLE_LEN + 12;
                 out.resize(MSG_LEN, 0);
                 debug_assert_eq!(out[PREAMBLE_LEN..].len(), 12);
@@ -288,9 +318,12 @@ where
                 out[PREAMBLE_LEN..PREAMBLE_LEN + block_len].copy_from_slice(b.as_ref());
                 Ok(msg_len)
             }
-            Message::Choke | Message::Unchoke | Message::Interested | Message::NotInterested => {
-                Ok(PREAMBLE_LEN)
-            }
+            Message::Choke
+            | Message::Unchoke
+            | Message::Interested
+            | Message::NotInterested
+            | Message::HaveAll
+            | Message::HaveNone => Ok(PREAMBLE_LEN),
             Message::Piece(p) => {
                 let block_len = p.block.as_ref().len();
                 let payload_len = 8 + block_len;
@@ -304,7 +337,7 @@ where
                 // the len prefix was already written out to buf
                 Ok(4)
             }
-            Message::Have(v) => {
+            Message::Have(v) | Message::SuggestPiece(v) | Message::AllowedFast(v) => {
                 let msg_len = PREAMBLE_LEN + 4;
                 out.resize(msg_len, 0);
                 BE::write_u32(&mut out[PREAMBLE_LEN..], *v);
@@ -384,16 +417,36 @@ where
                 }
                 Ok((Message::NotInterested, NO_PAYLOAD_MSG_LEN))
             }
-            MSGID_HAVE => {
+            MSGID_HAVE | MSGID_SUGGEST_PIECE | MSGID_ALLOWED_FAST => {
                 let expected_len = 4;
+                let (name, msg): (_, fn(u32) -> Message<ByteBuf>) = match msg_id {
+                    MSGID_HAVE => ("have", Message::Have),
+                    MSGID_SUGGEST_PIECE => ("suggest piece", Message::SuggestPiece),
+                    _ => ("allowed fast", Message::AllowedFast),
+                };
                 match rest.get(..expected_len) {
-                    Some(h) => Ok((Message::Have(BE::read_u32(h)), PREAMBLE_LEN + expected_len)),
+                    Some(h) => Ok((msg(BE::read_u32(h)), PREAMBLE_LEN + expected_len)),
                     None => {
                         let missing = expected_len - rest.len();
-                        Err(MessageDeserializeError::NotEnoughData(missing, "have"))
+                        Err(MessageDeserializeError::NotEnoughData(missing, name))
                     }
                 }
             }
+            MSGID_HAVE_ALL | MSGID_HAVE_NONE => {
+                if len_prefix != LEN_PREFIX_HAVE_ALL {
+                    return Err(MessageDeserializeError::IncorrectLenPrefix {
+                        received: len_prefix,
+                        expected: LEN_PREFIX_HAVE_ALL,
+                        msg_id,
+                    });
+                }
+                let msg = if msg_id == MSGID_HAVE_ALL {
+                    Message::HaveAll
+                } else {
+                    Message::HaveNone
+                };
+                Ok((msg, NO_PAYLOAD_MSG_LEN))
+            }
             MSGID_BITFIELD => {
                 if len_prefix <= 1 {
                     return Err(MessageDeserializeError::IncorrectLenPrefix {
@@ -414,28 +467,21 @@ where
                     }
                 }
             }
-            MSGID_REQUEST | MSGID_CANCEL => {
+            MSGID_REQUEST | MSGID_CANCEL | MSGID_REJECT_REQUEST => {
                 let expected_len = 12;
+                let (name, msg): (_, fn(Request) -> Message<ByteBuf>) = match msg_id {
+                    MSGID_REQUEST => ("request", Message::Request),
+                    MSGID_CANCEL => ("cancel", Message::Cancel),
+                    _ => ("reject request", Message::RejectRequest),
+                };
                 match rest.get(..expected_len) {
                     Some(b) => {
                         let request = decoder_config.deserialize::<Request>(b).unwrap();
-                        let req = if msg_id == MSGID_REQUEST {
-                            Message::Request(request)
-                        } else {
-                            Message::Cancel(request)
-                        };
-                        Ok((req, PREAMBLE_LEN + expected_len))
+                        Ok((msg(request), PREAMBLE_LEN + expected_len))
                     }
                     None => {
                         let missing = expected_len - rest.len();
-                        Err(MessageDeserializeError::NotEnoughData(
-                            missing,
-                            if msg_id == MSGID_REQUEST {
-                                "request"
-                            } else {
-                                "cancel"
-                            },
-                        ))
+                        Err(MessageDeserializeError::NotEnoughData(missing, name))
                     }
                 }
             }
@@ -507,6 +553,8 @@ impl Handshake<ByteBuf<'static>> {
         let mut reserved: u64 = 0;
         // supports extended messaging
         reserved |= 1 << 20;
+        // supports the fast extension
+        reserved |= 1 << 2;
         let mut reserved_arr = [0u8; 8];
         BE::write_u64(&mut reserved_arr, reserved);
 
@@ -544,6 +592,10 @@ impl<B> Handshake<B> {
     pub fn supports_extended(&self) -> bool {
         self.reserved[5] & 0x10 > 0
     }
+    /// BEP 6. Both sides have to support it before any of its messages can be sent.
+    pub fn supports_fast(&self) -> bool {
+        self.reserved[7] & 0x04 > 0
+    }
     fn bopts() -> impl bincode::Options {
         bincode::DefaultOptions::new()
     }
@@ -607,6 +659,43 @@ mod tests {
         assert_eq!(buf.len(), 20 + 20 + 8 + 19 + 1);
     }
 
+    #[test]
+    fn test_handshake_supports_fast() {
+        let mut buf = Vec::new();
+        Handshake::new(Id20::new([0; 20]), Id20::new([1; 20])).serialize(&mut buf);
+        // The fast bit is the third least significant bit of the last reserved byte.
+        assert_eq!(buf[20..28], [0, 0, 0, 0, 0, 0x10, 0, 0x04]);
+        let (h, _) = Handshake::deserialize(&buf).unwrap();
+        assert!(h.supports_fast());
+        assert!(h.supports_extended());
+    }
+
+    #[test]
+    fn test_fast_extension_messages() {
+        // Wire format from BEP 6.
+        let cases: [(MessageOwned, &[u8]); 5] = [
+            (Message::HaveAll, &[0, 0, 0, 1, 0x0e]),
+            (Message::HaveNone, &[0, 0, 0, 1, 0x0f]),
+            (Message::SuggestPiece(7), &[0, 0, 0, 5, 0x0d, 0, 0, 0, 7]),
+            (
+                Message::RejectRequest(Request::new(1, 0x4000, 0x4000)),
+                &[0, 0, 0, 13, 0x10, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0],
+            ),
+            (Message::AllowedFast(3), &[0, 0, 0, 5, 0x11, 0, 0, 0, 3]),
+        ];
+        for (msg, wire) in cases {
+            let mut out = Vec::new();
+            let len = msg.serialize(&mut out, &|| None).unwrap();
+            assert_eq!(&out[..len], wire, "{msg:?}");
+
+            let (parsed, size) = MessageBorrowed::deserialize(wire).unwrap();
+            assert_eq!(size, wire.len());
+            let mut reserialized = Vec::new();
+            parsed.serialize(&mut reserialized, &|| None).unwrap();
+            assert_eq!(reserialized, wire, "{parsed:?}");
+        }
+    }
+
     #[test]
     fn test_extended_serialize() {
         let msg = Message::Extended(ExtendedMessage::Handshake(ExtendedHandshake::new()));
@@ -0,0 +1,33 @@
+// Compares the SHA-1 throughput of the backends "sha1-accelerated" picks from:
+//
+//   cargo run --release -p librqbit-sha1-wrapper --features sha1-accelerated --example sha1_bench
+
+use std::time::Instant;
+
+use librqbit_sha1_wrapper::{cpu_has_sha_extensions, ISha1, Sha1Accelerated, Sha1Rust, Sha1System};
+
+const PIECE_LEN: usize = 4 * 1024 * 1024;
+const ROUNDS: usize = 64;
+
+fn bench<H: ISha1>(name: &str, piece: &[u8]) {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut h = H::new();
+        h.update(piece);
+        std::hint::black_box(h.finish());
+    }
+    let mib = (PIECE_LEN * ROUNDS) as f64 / 1024. / 1024.;
+    println!(
+        "{:>12}: {:.0} MiB/s",
+        name,
+        mib / start.elapsed().as_secs_f64()
+    );
+}
+
+fn main() {
+    println!("CPU SHA extensions: {}", cpu_has_sha_extensions());
+    let piece: Vec<u8> = (0..PIECE_LEN).map(|i| i as u8).collect();
+    bench::<Sha1System>("system", &piece);
+    bench::<Sha1Rust>("rust", &piece);
+    bench::<Sha1Accelerated>("accelerated", &piece);
+}
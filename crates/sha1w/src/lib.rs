@@ -2,16 +2,24 @@
 // e.g. to measure performance, or change implementations depending on platform.
 //
 // Sha1 computation is the majority of CPU usage of librqbit.
-// openssl is 2-3x faster than rust's sha1.
-// system library is the best choice probably (it's the default anyway).
+// openssl is 2-3x faster than rust's sha1 on CPUs without SHA extensions. With them
+// (SHA-NI on x86, the ARMv8 crypto extensions on aarch64), rust's sha1 uses them and
+// is the fastest. "sha1-accelerated" picks between the two at runtime. It's opt-in until
+// the sha1_bench example has numbers showing it's no slower than "sha1-system" (the default).
+//
+// "sha1-accelerated" wins if several features are enabled, so that it can be turned on
+// for a binary even if some dependency still asks for another one.
 
-#[cfg(feature = "sha1-openssl")]
+#[cfg(feature = "sha1-accelerated")]
+pub type Sha1 = Sha1Accelerated;
+
+#[cfg(all(feature = "sha1-openssl", not(feature = "sha1-accelerated")))]
 pub type Sha1 = Sha1Openssl;
 
-#[cfg(feature = "sha1-rust")]
+#[cfg(all(feature = "sha1-rust", not(feature = "sha1-accelerated")))]
 pub type Sha1 = Sha1Rust;
 
-#[cfg(feature = "sha1-system")]
+#[cfg(all(feature = "sha1-system", not(feature = "sha1-accelerated")))]
 pub type Sha1 = Sha1System;
 
 pub trait ISha1 {
@@ -20,12 +28,12 @@ pub trait ISha1 {
     fn finish(self) -> [u8; 20];
 }
 
-#[cfg(feature = "sha1-rust")]
+#[cfg(any(feature = "sha1-rust", feature = "sha1-accelerated"))]
 pub struct Sha1Rust {
     inner: sha1::Sha1,
 }
 
-#[cfg(feature = "sha1-rust")]
+#[cfg(any(feature = "sha1-rust", feature = "sha1-accelerated"))]
 impl ISha1 for Sha1Rust {
     fn new() -> Self {
         Sha1Rust {
@@ -68,12 +76,12 @@ impl ISha1 for Sha1Openssl {
     }
 }
 
-#[cfg(feature = "sha1-system")]
+#[cfg(any(feature = "sha1-system", feature = "sha1-accelerated"))]
 pub struct Sha1System {
     inner: crypto_hash::Hasher,
 }
 
-#[cfg(feature = "sha1-system")]
+#[cfg(any(feature = "sha1-system", feature = "sha1-accelerated"))]
 impl ISha1 for Sha1System {
     fn new() -> Self {
         Self {
@@ -94,3 +102,74 @@ impl ISha1 for Sha1System {
         result_arr
     }
 }
+
+/// Whether the CPU has SHA-1 instructions. Detected at runtime, so a binary built for a
+/// generic target still uses them.
+pub fn cpu_has_sha_extensions() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::is_x86_feature_detected!("sha")
+            && std::is_x86_feature_detected!("sse2")
+            && std::is_x86_feature_detected!("ssse3")
+            && std::is_x86_feature_detected!("sse4.1")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("sha2")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+/// Rust's sha1 if the CPU has SHA extensions, the system library otherwise.
+#[cfg(feature = "sha1-accelerated")]
+pub enum Sha1Accelerated {
+    Hardware(Sha1Rust),
+    Fallback(Sha1System),
+}
+
+#[cfg(feature = "sha1-accelerated")]
+impl ISha1 for Sha1Accelerated {
+    fn new() -> Self {
+        if cpu_has_sha_extensions() {
+            Self::Hardware(Sha1Rust::new())
+        } else {
+            Self::Fallback(Sha1System::new())
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            Self::Hardware(h) => h.update(buf),
+            Self::Fallback(h) => h.update(buf),
+        }
+    }
+
+    fn finish(self) -> [u8; 20] {
+        match self {
+            Self::Hardware(h) => h.finish(),
+            Self::Fallback(h) => h.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ISha1, Sha1};
+
+    #[test]
+    fn test_sha1() {
+        let mut h = Sha1::new();
+        h.update(b"ab");
+        h.update(b"c");
+        assert_eq!(
+            h.finish(),
+            [
+                0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50,
+                0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+            ]
+        );
+    }
+}
@@ -1,13 +1,15 @@
 use anyhow::{bail, Context};
 use futures::{stream::FuturesUnordered, StreamExt, TryFutureExt};
+use librqbit_core::jitter::sleep_jittered;
 use network_interface::NetworkInterfaceConfig;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
 use std::{
-    collections::{HashMap, HashSet},
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    time::Duration,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing::{debug, error_span, trace, warn, Instrument, Span};
@@ -56,42 +58,56 @@ fn get_local_ip_relative_to(local_dest: Ipv4Addr) -> anyhow::Result<Ipv4Addr> {
     bail!("couldn't find a local ip address")
 }
 
-async fn forward_port(
-    control_url: Url,
-    local_ip: Ipv4Addr,
-    port: u16,
-    lease_duration: Duration,
-) -> anyhow::Result<()> {
+/// All IP addresses of all local network interfaces. Comparing these over time is a
+/// cheap way to detect network changes.
+pub fn list_local_ips() -> anyhow::Result<BTreeSet<IpAddr>> {
+    let interfaces =
+        network_interface::NetworkInterface::show().context("error listing network interfaces")?;
+    Ok(interfaces
+        .into_iter()
+        .flat_map(|i| i.addr)
+        .map(|addr| match addr {
+            network_interface::Addr::V4(v4) => IpAddr::V4(v4.ip),
+            network_interface::Addr::V6(v6) => IpAddr::V6(v6.ip),
+        })
+        .collect())
+}
+
+// The text inside the first element with this name, ignoring namespace prefixes.
+fn xml_element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("{name}>"))? + name.len() + 1;
+    let rest = &xml[start..];
+    Some(rest[..rest.find("</")?].trim())
+}
+
+// Describes the UPnP error in a SOAP fault, e.g. "718 ConflictInMappingEntry".
+fn soap_fault(response: &str) -> Option<String> {
+    let code = xml_element_text(response, "errorCode")?;
+    match xml_element_text(response, "errorDescription") {
+        Some(description) => Some(format!("{code} {description}")),
+        None => Some(code.to_owned()),
+    }
+}
+
+async fn soap_request(control_url: Url, action: &str, arguments: &str) -> anyhow::Result<String> {
     let request_body = format!(
         r#"
         <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"
             s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
             <s:Body>
-                <u:AddPortMapping xmlns:u="{SERVICE_TYPE_WAN_IP_CONNECTION}">
-                    <NewRemoteHost></NewRemoteHost>
-                    <NewExternalPort>{port}</NewExternalPort>
-                    <NewProtocol>TCP</NewProtocol>
-                    <NewInternalPort>{port}</NewInternalPort>
-                    <NewInternalClient>{local_ip}</NewInternalClient>
-                    <NewEnabled>1</NewEnabled>
-                    <NewPortMappingDescription>rust UPnP</NewPortMappingDescription>
-                    <NewLeaseDuration>{}</NewLeaseDuration>
-                </u:AddPortMapping>
+                <u:{action} xmlns:u="{SERVICE_TYPE_WAN_IP_CONNECTION}">{arguments}</u:{action}>
             </s:Body>
         </s:Envelope>
-    "#,
-        lease_duration.as_secs()
+    "#
     );
 
-    let url = control_url;
-
     let client = reqwest::Client::new();
     let response = client
-        .post(url.clone())
+        .post(control_url)
         .header("Content-Type", "text/xml")
         .header(
             "SOAPAction",
-            format!("\"{}#AddPortMapping\"", SERVICE_TYPE_WAN_IP_CONNECTION),
+            format!("\"{}#{}\"", SERVICE_TYPE_WAN_IP_CONNECTION, action),
         )
         .body(request_body)
         .send()
@@ -105,15 +121,138 @@ async fn forward_port(
         .await
         .context("error reading response text")?;
 
-    trace!(status = %status, text=response_text, "AddPortMapping response");
+    trace!(status = %status, text=response_text, "{action} response");
     if !status.is_success() {
-        bail!("failed port forwarding: {}", status);
-    } else {
-        debug!(%local_ip, port, "successfully port forwarded");
+        match soap_fault(&response_text) {
+            Some(fault) => bail!("router refused {action}: {fault} ({status})"),
+            None => bail!("{action} failed: {status}"),
+        }
     }
+    Ok(response_text)
+}
+
+async fn forward_port(
+    control_url: Url,
+    local_ip: Ipv4Addr,
+    port: u16,
+    lease_duration: Duration,
+) -> anyhow::Result<()> {
+    let arguments = format!(
+        r#"
+                    <NewRemoteHost></NewRemoteHost>
+                    <NewExternalPort>{port}</NewExternalPort>
+                    <NewProtocol>TCP</NewProtocol>
+                    <NewInternalPort>{port}</NewInternalPort>
+                    <NewInternalClient>{local_ip}</NewInternalClient>
+                    <NewEnabled>1</NewEnabled>
+                    <NewPortMappingDescription>rust UPnP</NewPortMappingDescription>
+                    <NewLeaseDuration>{}</NewLeaseDuration>
+                "#,
+        lease_duration.as_secs()
+    );
+    soap_request(control_url, "AddPortMapping", &arguments)
+        .await
+        .context("failed port forwarding")?;
+    debug!(%local_ip, port, "successfully port forwarded");
     Ok(())
 }
 
+async fn get_external_ip(control_url: Url) -> anyhow::Result<IpAddr> {
+    let response = soap_request(control_url, "GetExternalIPAddress", "").await?;
+    let ip = xml_element_text(&response, "NewExternalIPAddress")
+        .context("no NewExternalIPAddress in response")?;
+    ip.parse()
+        .with_context(|| format!("bad external IP address {ip:?}"))
+}
+
+/// The state of one port mapping, see [UpnpPortForwarder::status].
+#[derive(Debug, Clone, Serialize)]
+pub struct PortMappingStatus {
+    /// The control URL of the router's WANIPConnection service.
+    pub control_url: String,
+    pub local_ip: Ipv4Addr,
+    pub port: u16,
+    /// As reported by the router, once mapped.
+    pub external_ip: Option<IpAddr>,
+    /// How long until the lease runs out, in seconds. 0 if not mapped.
+    pub lease_remaining_secs: u64,
+    /// Unix timestamp of the last attempt to map or renew, in seconds.
+    pub last_refresh: Option<u64>,
+    /// Why the last attempt failed, e.g. the router refused the mapping.
+    pub last_error: Option<String>,
+}
+
+struct MappingState {
+    local_ip: Ipv4Addr,
+    external_ip: Option<IpAddr>,
+    leased_until: Option<Instant>,
+    last_refresh: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+/// A live view of the mappings of a [UpnpPortForwarder], that can be kept after it was
+/// moved into [UpnpPortForwarder::run_forever].
+#[derive(Clone, Default)]
+pub struct UpnpStatus {
+    mappings: Arc<Mutex<BTreeMap<(String, u16), MappingState>>>,
+}
+
+impl UpnpStatus {
+    /// Every mapping tried so far, by control URL and port.
+    pub fn mappings(&self) -> Vec<PortMappingStatus> {
+        let now = Instant::now();
+        self.mappings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((control_url, port), m)| PortMappingStatus {
+                control_url: control_url.clone(),
+                local_ip: m.local_ip,
+                port: *port,
+                external_ip: m.external_ip,
+                lease_remaining_secs: m
+                    .leased_until
+                    .map(|until| until.saturating_duration_since(now).as_secs())
+                    .unwrap_or_default(),
+                last_refresh: m
+                    .last_refresh
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                last_error: m.last_error.clone(),
+            })
+            .collect()
+    }
+
+    fn record(
+        &self,
+        control_url: &Url,
+        local_ip: Ipv4Addr,
+        port: u16,
+        result: Result<(Option<IpAddr>, Instant), &anyhow::Error>,
+    ) {
+        let mut g = self.mappings.lock().unwrap();
+        let m = g
+            .entry((control_url.to_string(), port))
+            .or_insert(MappingState {
+                local_ip,
+                external_ip: None,
+                leased_until: None,
+                last_refresh: None,
+                last_error: None,
+            });
+        m.local_ip = local_ip;
+        m.last_refresh = Some(SystemTime::now());
+        match result {
+            Ok((external_ip, leased_until)) => {
+                m.external_ip = external_ip.or(m.external_ip);
+                m.leased_until = Some(leased_until);
+                m.last_error = None;
+            }
+            Err(e) => m.last_error = Some(format!("{e:#}")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct RootDesc {
     #[serde(rename = "device")]
@@ -284,7 +423,10 @@ fn parse_upnp_discover_response(
 }
 
 pub struct UpnpPortForwarderOptions {
+    /// At least a second, UPnP leases are in whole seconds and 0 means forever.
     pub lease_duration: Duration,
+    /// How often to renew the lease. None renews at half the lease duration.
+    pub refresh_interval: Option<Duration>,
     pub discover_interval: Duration,
     pub discover_timeout: Duration,
 }
@@ -295,13 +437,37 @@ impl Default for UpnpPortForwarderOptions {
             discover_interval: Duration::from_secs(60),
             discover_timeout: Duration::from_secs(10),
             lease_duration: Duration::from_secs(60),
+            refresh_interval: None,
         }
     }
 }
 
+impl UpnpPortForwarderOptions {
+    /// Every interval has to be more than 0, and the lease has to be renewed before it runs
+    /// out.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.lease_duration.as_secs() == 0 {
+            bail!("lease_duration must be at least a second");
+        }
+        match self.refresh_interval {
+            Some(refresh) if refresh.is_zero() => bail!("refresh_interval must be more than 0"),
+            Some(refresh) if refresh >= self.lease_duration => bail!(
+                "refresh_interval must be shorter than the lease duration of {:?}",
+                self.lease_duration
+            ),
+            _ => {}
+        }
+        if self.discover_interval.is_zero() {
+            bail!("discover_interval must be more than 0");
+        }
+        Ok(())
+    }
+}
+
 pub struct UpnpPortForwarder {
     ports: Vec<u16>,
     opts: UpnpPortForwarderOptions,
+    status: UpnpStatus,
 }
 
 impl UpnpPortForwarder {
@@ -309,12 +475,20 @@ impl UpnpPortForwarder {
         if ports.is_empty() {
             bail!("empty ports")
         }
+        let opts = opts.unwrap_or_default();
+        opts.validate()?;
         Ok(Self {
             ports,
-            opts: opts.unwrap_or_default(),
+            opts,
+            status: Default::default(),
         })
     }
 
+    /// The state of the mappings, also once this is running.
+    pub fn status(&self) -> UpnpStatus {
+        self.status.clone()
+    }
+
     async fn parse_endpoint(
         &self,
         discover_response: UpnpDiscoverResponse,
@@ -376,25 +550,42 @@ impl UpnpPortForwarder {
     }
 
     async fn discovery(&self, tx: UnboundedSender<UpnpDiscoverResponse>) -> anyhow::Result<()> {
-        let mut discover_interval = tokio::time::interval(self.opts.discover_interval);
-
         loop {
-            discover_interval.tick().await;
             if let Err(e) = self.discover_once(&tx).await {
                 warn!("failed to run discovery: {e:#}");
             }
+            sleep_jittered(self.opts.discover_interval).await;
         }
     }
 
     async fn manage_port(&self, control_url: Url, local_ip: Ipv4Addr, port: u16) -> ! {
         let lease_duration = self.opts.lease_duration;
-        let mut interval = tokio::time::interval(lease_duration / 2);
+        // By default renew at half the lease, so the jitter can't make it expire.
+        let refresh_interval = self.opts.refresh_interval.unwrap_or(lease_duration / 2);
         loop {
-            interval.tick().await;
-            if let Err(e) = forward_port(control_url.clone(), local_ip, port, lease_duration).await
-            {
-                warn!("failed to forward port: {e:#}");
+            let started = Instant::now();
+            match forward_port(control_url.clone(), local_ip, port, lease_duration).await {
+                Ok(()) => {
+                    let external_ip = match get_external_ip(control_url.clone()).await {
+                        Ok(ip) => Some(ip),
+                        Err(e) => {
+                            debug!("failed to get external IP: {e:#}");
+                            None
+                        }
+                    };
+                    self.status.record(
+                        &control_url,
+                        local_ip,
+                        port,
+                        Ok((external_ip, started + lease_duration)),
+                    );
+                }
+                Err(e) => {
+                    warn!("failed to forward port: {e:#}");
+                    self.status.record(&control_url, local_ip, port, Err(&e));
+                }
             }
+            sleep_jittered(refresh_interval).await;
         }
     }
 
@@ -465,12 +656,60 @@ impl UpnpPortForwarder {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use serde_xml_rs::from_str;
 
-    use crate::RootDesc;
+    use crate::{soap_fault, xml_element_text, RootDesc, UpnpPortForwarderOptions};
 
     #[test]
     fn test_parse() {
         dbg!(from_str::<RootDesc>(include_str!("resources/test/devices-0.xml")).unwrap());
     }
+
+    #[test]
+    fn test_soap_fault() {
+        let fault = r#"<s:Envelope><s:Body><s:Fault>
+            <faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>
+            <detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+                <errorCode>718</errorCode>
+                <errorDescription>ConflictInMappingEntry</errorDescription>
+            </UPnPError></detail>
+        </s:Fault></s:Body></s:Envelope>"#;
+        assert_eq!(
+            soap_fault(fault).as_deref(),
+            Some("718 ConflictInMappingEntry")
+        );
+        assert_eq!(soap_fault("<s:Envelope></s:Envelope>"), None);
+
+        let ip = r#"<s:Body><u:GetExternalIPAddressResponse xmlns:u="x">
+            <NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>
+        </u:GetExternalIPAddressResponse></s:Body>"#;
+        assert_eq!(
+            xml_element_text(ip, "NewExternalIPAddress"),
+            Some("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let opts = |lease, refresh| UpnpPortForwarderOptions {
+            lease_duration: Duration::from_secs(lease),
+            refresh_interval: refresh.map(Duration::from_secs),
+            ..Default::default()
+        };
+        assert!(UpnpPortForwarderOptions::default().validate().is_ok());
+        assert!(opts(60, Some(30)).validate().is_ok());
+        assert!(opts(0, None).validate().is_err());
+        assert!(opts(60, Some(0)).validate().is_err());
+        // The default lease is 60 seconds, a refresh after that lets the mapping lapse.
+        assert!(opts(60, Some(120)).validate().is_err());
+        assert!(opts(60, Some(60)).validate().is_err());
+        assert!(UpnpPortForwarderOptions {
+            discover_interval: Duration::ZERO,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
 }
@@ -4,7 +4,7 @@ use std::{
     time::Duration,
 };
 
-use librqbit::{dht::PersistentDht, Session};
+use librqbit::{dht::PersistentDht, PowerProfile, Session};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
@@ -14,6 +14,9 @@ pub struct RqbitDesktopConfigDht {
     pub disable: bool,
     pub disable_persistence: bool,
     pub persistence_filename: PathBuf,
+    pub read_only: bool,
+    // Outgoing queries per second.
+    pub max_query_rate: usize,
 }
 
 impl Default for RqbitDesktopConfigDht {
@@ -22,6 +25,8 @@ impl Default for RqbitDesktopConfigDht {
             disable: false,
             disable_persistence: false,
             persistence_filename: PersistentDht::default_persistence_filename().unwrap(),
+            read_only: false,
+            max_query_rate: 250,
         }
     }
 }
@@ -100,10 +105,27 @@ impl Default for RqbitDesktopConfigHttpApi {
     }
 }
 
-#[derive(Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde_as]
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(default)]
 pub struct RqbitDesktopConfigUpnp {
     pub disable: bool,
+
+    #[serde_as(as = "serde_with::DurationSeconds")]
+    pub lease_duration: Duration,
+
+    #[serde_as(as = "serde_with::DurationSeconds")]
+    pub refresh_interval: Duration,
+}
+
+impl Default for RqbitDesktopConfigUpnp {
+    fn default() -> Self {
+        Self {
+            disable: false,
+            lease_duration: Duration::from_secs(60),
+            refresh_interval: Duration::from_secs(30),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -116,6 +138,10 @@ pub struct RqbitDesktopConfig {
     pub persistence: RqbitDesktopConfigPersistence,
     pub peer_opts: RqbitDesktopConfigPeerOpts,
     pub http_api: RqbitDesktopConfigHttpApi,
+    pub power_profile: PowerProfile,
+    // A profile saved from another instance's GET /profile. Its startup settings override
+    // the ones above, and its runtime settings are applied once the session starts.
+    pub profile: Option<PathBuf>,
 }
 
 impl Default for RqbitDesktopConfig {
@@ -134,6 +160,8 @@ impl Default for RqbitDesktopConfig {
             persistence: Default::default(),
             peer_opts: Default::default(),
             http_api: Default::default(),
+            power_profile: Default::default(),
+            profile: None,
         }
     }
 }
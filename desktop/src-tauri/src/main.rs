@@ -4,10 +4,12 @@
 mod config;
 
 use std::{
+    collections::BTreeMap,
     fs::{File, OpenOptions},
     io::{BufReader, BufWriter},
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::Context;
@@ -15,29 +17,45 @@ use config::RqbitDesktopConfig;
 use http::StatusCode;
 use librqbit::{
     api::{
-        ApiAddTorrentResponse, EmptyJsonResponse, TorrentDetailsResponse, TorrentListResponse,
-        TorrentStats,
+        ApiAddTorrentResponse, ConfigReloadFuture, ConfigReloadResponse, EmptyJsonResponse,
+        QueueResponse, QueueSortRequest, RelocateStateFilesRequest, StartupConnectRateRequest,
+        StartupConnectRateResponse, TorrentDetailsResponse, TorrentListResponse,
+        TorrentOptionsPatch, TorrentStats, TorrentsBulkUpdateResponse,
     },
-    dht::PersistentDhtConfig,
+    dht::{DhtLookupStats, PersistentDhtConfig},
     tracing_subscriber_config_utils::{init_logging, InitLoggingOptions, InitLoggingResult},
-    AddTorrent, AddTorrentOptions, Api, ApiError, PeerConnectionOptions, Session, SessionOptions,
+    AddTorrent, AddTorrentOptions, Api, ApiError, CollectionStats, ListenStatus,
+    PeerConnectionOptions, PriorityTier, QueueSortKey, ResolvingMagnet, Session, SessionOptions,
+    SessionProfile, StatsDelta, StatsDeltaTracker, StorageRootStats, TorrentDiagnosis, UpnpStatus,
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::Serialize;
 use tracing::{error, error_span, info, warn};
 
 const ERR_NOT_CONFIGURED: ApiError =
     ApiError::new_from_text(StatusCode::FAILED_DEPENDENCY, "not configured");
+const ERR_RELOADER_GONE: ApiError = ApiError::new_from_text(
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "config reloader is not running",
+);
+
+type ConfigReloadReply = tokio::sync::oneshot::Sender<Result<ConfigReloadResponse, ApiError>>;
 
 struct StateShared {
     config: config::RqbitDesktopConfig,
     api: Option<Api>,
 }
 
+#[derive(Clone)]
 struct State {
     config_filename: String,
     shared: Arc<RwLock<Option<StateShared>>>,
     init_logging: InitLoggingResult,
+    // Reloads requested through the API. They run in a task of their own, as reloading may
+    // restart the session, and with it the HTTP API that asked for it.
+    reload_requests: tokio::sync::mpsc::UnboundedSender<ConfigReloadReply>,
+    // What the UI was last sent by torrents_stats_delta.
+    stats_deltas: Arc<Mutex<StatsDeltaTracker>>,
 }
 
 fn read_config(path: &str) -> anyhow::Result<RqbitDesktopConfig> {
@@ -61,49 +79,76 @@ fn write_config(path: &str, config: &RqbitDesktopConfig) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn api_from_config(
-    init_logging: &InitLoggingResult,
-    config: &RqbitDesktopConfig,
-) -> anyhow::Result<Api> {
-    let session = Session::new_with_opts(
-        config.default_download_location.clone(),
-        SessionOptions {
-            disable_dht: config.dht.disable,
-            disable_dht_persistence: config.dht.disable_persistence,
-            dht_config: Some(PersistentDhtConfig {
-                config_filename: Some(config.dht.persistence_filename.clone()),
-                ..Default::default()
-            }),
-            persistence: !config.persistence.disable,
-            persistence_filename: Some(config.persistence.filename.clone()),
-            peer_opts: Some(PeerConnectionOptions {
-                connect_timeout: Some(config.peer_opts.connect_timeout),
-                read_write_timeout: Some(config.peer_opts.read_write_timeout),
-                ..Default::default()
-            }),
-            listen_port_range: if !config.tcp_listen.disable {
-                Some(config.tcp_listen.min_port..config.tcp_listen.max_port)
-            } else {
-                None
-            },
-            enable_upnp_port_forwarding: !config.upnp.disable,
+async fn api_from_config(state: &State, config: &RqbitDesktopConfig) -> anyhow::Result<Api> {
+    let init_logging = &state.init_logging;
+    let mut opts = SessionOptions {
+        disable_dht: config.dht.disable,
+        disable_dht_persistence: config.dht.disable_persistence,
+        dht_config: Some(PersistentDhtConfig {
+            config_filename: Some(config.dht.persistence_filename.clone()),
+            read_only: config.dht.read_only,
+            max_query_rate: Some(config.dht.max_query_rate),
+            ..Default::default()
+        }),
+        persistence: !config.persistence.disable,
+        persistence_filename: Some(config.persistence.filename.clone()),
+        peer_opts: Some(PeerConnectionOptions {
+            connect_timeout: Some(config.peer_opts.connect_timeout),
+            read_write_timeout: Some(config.peer_opts.read_write_timeout),
             ..Default::default()
+        }),
+        listen_port_range: if !config.tcp_listen.disable {
+            Some(config.tcp_listen.min_port..config.tcp_listen.max_port)
+        } else {
+            None
         },
-    )
-    .await
-    .context("couldn't set up librqbit session")?;
+        enable_upnp_port_forwarding: !config.upnp.disable,
+        upnp_lease_duration: Some(config.upnp.lease_duration),
+        upnp_refresh_interval: Some(config.upnp.refresh_interval),
+        power_profile: config.power_profile,
+        ..Default::default()
+    };
+    let profile = config
+        .profile
+        .as_deref()
+        .map(SessionProfile::from_file)
+        .transpose()
+        .context("error reading the profile")?;
+    if let Some(profile) = &profile {
+        profile.startup.apply_to(&mut opts);
+    }
+    let session = Session::new_with_opts(config.default_download_location.clone(), opts)
+        .await
+        .context("couldn't set up librqbit session")?;
+    if let Some(profile) = &profile {
+        session
+            .import_profile(profile)
+            .context("error applying the profile")?;
+    }
 
+    let reload_requests = state.reload_requests.clone();
     let api = Api::new(
         session.clone(),
         Some(init_logging.rust_log_reload_tx.clone()),
         Some(init_logging.line_broadcast.clone()),
-    );
+    )
+    .with_config_reload_hook(Arc::new(move || -> ConfigReloadFuture {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let sent = reload_requests.send(tx).is_ok();
+        Box::pin(async move {
+            if !sent {
+                return Err(ERR_RELOADER_GONE);
+            }
+            rx.await.unwrap_or(Err(ERR_RELOADER_GONE))
+        })
+    }));
 
     if !config.http_api.disable {
         let http_api_task = librqbit::http_api::HttpApi::new(
             api.clone(),
             Some(librqbit::http_api::HttpApiOptions {
                 read_only: config.http_api.read_only,
+                ..Default::default()
             }),
         )
         .make_http_api_and_run(config.http_api.listen_addr);
@@ -123,22 +168,28 @@ impl State {
             .expect("to_str()")
             .to_owned();
 
-        if let Ok(config) = read_config(&config_filename) {
-            let api = api_from_config(&init_logging, &config).await.ok();
-            let shared = Arc::new(RwLock::new(Some(StateShared { config, api })));
-
-            return Self {
-                config_filename,
-                shared,
-                init_logging,
-            };
-        }
-
-        Self {
+        let (reload_requests, mut reload_rx) = tokio::sync::mpsc::unbounded_channel();
+        let state = Self {
             config_filename,
             init_logging,
             shared: Arc::new(RwLock::new(None)),
+            reload_requests,
+            stats_deltas: Default::default(),
+        };
+
+        let reloader = state.clone();
+        tokio::spawn(async move {
+            while let Some(reply) = reload_rx.recv().await {
+                let _ = reply.send(reloader.reload_config().await);
+            }
+        });
+
+        if let Ok(config) = read_config(&state.config_filename) {
+            let api = api_from_config(&state, &config).await.ok();
+            *state.shared.write() = Some(StateShared { config, api });
         }
+
+        state
     }
 
     fn api(&self) -> Result<Api, ApiError> {
@@ -166,7 +217,7 @@ impl State {
             api.session().stop().await;
         }
 
-        let api = api_from_config(&self.init_logging, &config).await?;
+        let api = api_from_config(self, &config).await?;
         if let Err(e) = write_config(&self.config_filename, &config) {
             error!("error writing config: {:#}", e);
         }
@@ -180,6 +231,126 @@ impl State {
     }
 }
 
+impl State {
+    /// Re-read the config file. Changes of the state file locations and of DHT read-only
+    /// mode are applied in place, anything else restarts the session.
+    async fn reload_config(&self) -> Result<ConfigReloadResponse, ApiError> {
+        let new = read_config(&self.config_filename).context("error reading config")?;
+        let current = self
+            .shared
+            .read()
+            .as_ref()
+            .filter(|s| s.api.is_some())
+            .map(|s| s.config.clone());
+
+        let mut response = ConfigReloadResponse::default();
+        let current = match current {
+            Some(current) => current,
+            None => {
+                self.configure(new).await?;
+                response.restart_required.push("all".to_owned());
+                return Ok(response);
+            }
+        };
+
+        let mut relocated = current.clone();
+        let mut session_persistence_filename = None;
+        let mut dht_persistence_filename = None;
+        if new.persistence.filename != current.persistence.filename {
+            relocated.persistence.filename = new.persistence.filename.clone();
+            session_persistence_filename = Some(new.persistence.filename.clone());
+            response.applied.push("persistence.filename".to_owned());
+        }
+        if new.dht.persistence_filename != current.dht.persistence_filename {
+            relocated.dht.persistence_filename = new.dht.persistence_filename.clone();
+            dht_persistence_filename = Some(new.dht.persistence_filename.clone());
+            response.applied.push("dht.persistence_filename".to_owned());
+        }
+        let mut dht_read_only = None;
+        if new.dht.read_only != current.dht.read_only {
+            relocated.dht.read_only = new.dht.read_only;
+            dht_read_only = Some(new.dht.read_only);
+            response.applied.push("dht.read_only".to_owned());
+        }
+
+        if relocated == new {
+            if session_persistence_filename.is_some() || dht_persistence_filename.is_some() {
+                self.relocate_state_files(session_persistence_filename, dht_persistence_filename)?;
+            }
+            if let Some(read_only) = dht_read_only {
+                self.set_dht_read_only(read_only)?;
+            }
+            return Ok(response);
+        }
+
+        // The session gets recreated from scratch, so the new locations apply too.
+        response.restart_required = changed_sections(&current, &new);
+        response.applied.clear();
+        self.configure(new).await?;
+        Ok(response)
+    }
+
+    fn relocate_state_files(
+        &self,
+        session_persistence_filename: Option<PathBuf>,
+        dht_persistence_filename: Option<PathBuf>,
+    ) -> Result<(), ApiError> {
+        let mut g = self.shared.write();
+        let shared = g.as_mut().ok_or(ERR_NOT_CONFIGURED)?;
+        let api = shared.api.as_ref().ok_or(ERR_NOT_CONFIGURED)?;
+        api.api_relocate_state_files(RelocateStateFilesRequest {
+            session_persistence_filename: session_persistence_filename.clone(),
+            dht_persistence_filename: dht_persistence_filename.clone(),
+        })?;
+
+        // Remember the new locations, so that they're used after restart too.
+        if let Some(filename) = session_persistence_filename {
+            shared.config.persistence.filename = filename;
+        }
+        if let Some(filename) = dht_persistence_filename {
+            shared.config.dht.persistence_filename = filename;
+        }
+        if let Err(e) = write_config(&self.config_filename, &shared.config) {
+            error!("error writing config: {:#}", e);
+        }
+        Ok(())
+    }
+
+    fn set_dht_read_only(&self, read_only: bool) -> Result<(), ApiError> {
+        let mut g = self.shared.write();
+        let shared = g.as_mut().ok_or(ERR_NOT_CONFIGURED)?;
+        let api = shared.api.as_ref().ok_or(ERR_NOT_CONFIGURED)?;
+        api.api_dht_set_read_only(read_only)?;
+
+        // Keep it after restart too.
+        shared.config.dht.read_only = read_only;
+        if let Err(e) = write_config(&self.config_filename, &shared.config) {
+            error!("error writing config: {:#}", e);
+        }
+        Ok(())
+    }
+}
+
+fn changed_sections(a: &RqbitDesktopConfig, b: &RqbitDesktopConfig) -> Vec<String> {
+    [
+        (
+            "default_download_location",
+            a.default_download_location != b.default_download_location,
+        ),
+        ("dht", a.dht != b.dht),
+        ("tcp_listen", a.tcp_listen != b.tcp_listen),
+        ("upnp", a.upnp != b.upnp),
+        ("persistence", a.persistence != b.persistence),
+        ("peer_opts", a.peer_opts != b.peer_opts),
+        ("http_api", a.http_api != b.http_api),
+        ("power_profile", a.power_profile != b.power_profile),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| name.to_owned())
+    .collect()
+}
+
 #[derive(Default, Serialize)]
 struct CurrentState {
     config: Option<RqbitDesktopConfig>,
@@ -211,6 +382,113 @@ async fn config_change(
     state.configure(config).await.map(|_| EmptyJsonResponse {})
 }
 
+#[tauri::command]
+async fn config_reload(state: tauri::State<'_, State>) -> Result<ConfigReloadResponse, ApiError> {
+    state.reload_config().await
+}
+
+#[tauri::command]
+fn state_files_relocate(
+    state: tauri::State<'_, State>,
+    session_persistence_filename: Option<PathBuf>,
+    dht_persistence_filename: Option<PathBuf>,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state
+        .relocate_state_files(session_persistence_filename, dht_persistence_filename)
+        .map(|_| EmptyJsonResponse {})
+}
+
+#[tauri::command]
+fn dht_set_read_only(
+    state: tauri::State<'_, State>,
+    read_only: bool,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state
+        .set_dht_read_only(read_only)
+        .map(|_| EmptyJsonResponse {})
+}
+
+#[tauri::command]
+fn announce_set_enabled(
+    state: tauri::State<State>,
+    enabled: bool,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_set_announce_enabled(enabled)
+}
+
+#[tauri::command]
+fn dht_active_lookups(state: tauri::State<State>) -> Result<Vec<DhtLookupStats>, ApiError> {
+    state.api()?.api_dht_active_lookups()
+}
+
+#[tauri::command]
+fn listen_status(state: tauri::State<State>) -> Result<ListenStatus, ApiError> {
+    Ok(state.api()?.api_listen_status())
+}
+
+#[tauri::command]
+fn upnp_status(state: tauri::State<State>) -> Result<UpnpStatus, ApiError> {
+    Ok(state.api()?.api_upnp_status())
+}
+
+#[tauri::command]
+fn upnp_remap(state: tauri::State<State>) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_upnp_remap()
+}
+
+#[tauri::command]
+fn connect_rate(state: tauri::State<State>) -> Result<StartupConnectRateResponse, ApiError> {
+    Ok(state.api()?.api_startup_connect_rate())
+}
+
+#[tauri::command]
+fn connect_rate_set(
+    state: tauri::State<State>,
+    request: StartupConnectRateRequest,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_set_startup_connect_rate(request)
+}
+
+#[tauri::command]
+async fn storage_stats(state: tauri::State<'_, State>) -> Result<Vec<StorageRootStats>, ApiError> {
+    state.api()?.api_storage_stats().await
+}
+
+#[tauri::command]
+fn queue(state: tauri::State<State>) -> Result<QueueResponse, ApiError> {
+    Ok(state.api()?.api_queue())
+}
+
+#[tauri::command]
+fn queue_sort(
+    state: tauri::State<State>,
+    key: QueueSortKey,
+    reverse: bool,
+) -> Result<QueueResponse, ApiError> {
+    Ok(state
+        .api()?
+        .api_queue_sort(QueueSortRequest { key, reverse }))
+}
+
+#[tauri::command]
+fn queue_dequeue(state: tauri::State<State>, id: usize) -> Result<QueueResponse, ApiError> {
+    state.api()?.api_queue_dequeue(id)
+}
+
+#[tauri::command]
+fn torrents_resolving(state: tauri::State<State>) -> Result<Vec<ResolvingMagnet>, ApiError> {
+    Ok(state.api()?.api_resolving_magnets())
+}
+
+#[tauri::command]
+fn torrents_bulk_update(
+    state: tauri::State<State>,
+    ids: Vec<usize>,
+    changes: TorrentOptionsPatch,
+) -> Result<TorrentsBulkUpdateResponse, ApiError> {
+    Ok(state.api()?.api_torrents_bulk_update(ids, changes))
+}
+
 #[tauri::command]
 fn torrents_list(state: tauri::State<State>) -> Result<TorrentListResponse, ApiError> {
     Ok(state.api()?.api_torrent_list())
@@ -245,6 +523,18 @@ async fn torrent_create_from_base64_file(
         .await
 }
 
+#[tauri::command]
+async fn torrent_create_from_path(
+    state: tauri::State<'_, State>,
+    path: PathBuf,
+    opts: Option<AddTorrentOptions>,
+) -> Result<ApiAddTorrentResponse, ApiError> {
+    state
+        .api()?
+        .api_add_torrent(AddTorrent::TorrentFilePath(path), opts)
+        .await
+}
+
 #[tauri::command]
 async fn torrent_details(
     state: tauri::State<'_, State>,
@@ -261,6 +551,24 @@ async fn torrent_stats(
     state.api()?.api_stats_v1(id)
 }
 
+#[tauri::command]
+fn torrent_diagnose(state: tauri::State<State>, id: usize) -> Result<TorrentDiagnosis, ApiError> {
+    state.api()?.api_torrent_diagnose(id)
+}
+
+#[tauri::command]
+async fn torrents_stats_delta(
+    state: tauri::State<'_, State>,
+    reset: bool,
+) -> Result<StatsDelta, ApiError> {
+    let api = state.api()?;
+    let mut tracker = state.stats_deltas.lock();
+    if reset {
+        tracker.reset();
+    }
+    api.api_stats_delta(&mut tracker)
+}
+
 #[tauri::command]
 async fn torrent_action_delete(
     state: tauri::State<'_, State>,
@@ -277,6 +585,44 @@ async fn torrent_action_pause(
     state.api()?.api_torrent_action_pause(id)
 }
 
+#[tauri::command]
+async fn torrent_set_locked(
+    state: tauri::State<'_, State>,
+    id: usize,
+    locked: bool,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_set_locked(id, locked)
+}
+
+#[tauri::command]
+async fn torrent_boost(
+    state: tauri::State<'_, State>,
+    id: usize,
+    duration_secs: u64,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state
+        .api()?
+        .api_torrent_boost(id, Duration::from_secs(duration_secs))
+}
+
+#[tauri::command]
+fn torrent_set_upload_slots(
+    state: tauri::State<State>,
+    id: usize,
+    slots: Option<usize>,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_set_upload_slots(id, slots)
+}
+
+#[tauri::command]
+fn torrent_set_priority_tier(
+    state: tauri::State<State>,
+    id: usize,
+    tier: PriorityTier,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_set_priority_tier(id, tier)
+}
+
 #[tauri::command]
 async fn torrent_action_forget(
     state: tauri::State<'_, State>,
@@ -293,6 +639,123 @@ async fn torrent_action_start(
     state.api()?.api_torrent_action_start(id)
 }
 
+#[tauri::command]
+async fn torrent_action_load(
+    state: tauri::State<'_, State>,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_action_load(id)
+}
+
+#[tauri::command]
+fn torrent_action_recheck(
+    state: tauri::State<State>,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_action_recheck(id)
+}
+
+#[tauri::command]
+fn torrent_magnet(
+    state: tauri::State<State>,
+    id: usize,
+    include_peers: bool,
+) -> Result<String, ApiError> {
+    state.api()?.api_torrent_magnet(id, include_peers)
+}
+
+#[tauri::command]
+fn torrent_cancel_verify(
+    state: tauri::State<State>,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_cancel_verify(id)
+}
+
+#[tauri::command]
+fn torrent_action_solo(
+    state: tauri::State<State>,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_torrent_solo(id)
+}
+
+#[tauri::command]
+fn solo_clear(state: tauri::State<State>) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_clear_solo()
+}
+
+#[tauri::command]
+fn collections_list(state: tauri::State<State>) -> Result<BTreeMap<String, Vec<usize>>, ApiError> {
+    Ok(state.api()?.api_collections())
+}
+
+#[tauri::command]
+fn collection_create(
+    state: tauri::State<State>,
+    name: String,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_collection_create(&name)
+}
+
+#[tauri::command]
+fn collection_delete(
+    state: tauri::State<State>,
+    name: String,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_collection_delete(&name)
+}
+
+#[tauri::command]
+fn collection_add(
+    state: tauri::State<State>,
+    name: String,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_collection_add(&name, id)
+}
+
+#[tauri::command]
+fn collection_remove(
+    state: tauri::State<State>,
+    name: String,
+    id: usize,
+) -> Result<EmptyJsonResponse, ApiError> {
+    state.api()?.api_collection_remove(&name, id)
+}
+
+#[tauri::command]
+fn collection_stats(state: tauri::State<State>, name: String) -> Result<CollectionStats, ApiError> {
+    state.api()?.api_collection_stats(&name)
+}
+
+#[tauri::command]
+fn collection_pause(
+    state: tauri::State<State>,
+    name: String,
+) -> Result<TorrentsBulkUpdateResponse, ApiError> {
+    state.api()?.api_collection_pause(&name)
+}
+
+#[tauri::command]
+fn collection_start(
+    state: tauri::State<State>,
+    name: String,
+) -> Result<TorrentsBulkUpdateResponse, ApiError> {
+    state.api()?.api_collection_start(&name)
+}
+
+#[tauri::command]
+fn collection_delete_torrents(
+    state: tauri::State<State>,
+    name: String,
+    delete_files: bool,
+) -> Result<TorrentsBulkUpdateResponse, ApiError> {
+    state
+        .api()?
+        .api_collection_delete_torrents(&name, delete_files)
+}
+
 #[tauri::command]
 fn get_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -314,22 +777,85 @@ async fn start() {
 
     let state = State::new(init_logging_result).await;
 
+    #[cfg(unix)]
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut sighup =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("error setting up SIGHUP handler: {:#}", e);
+                        return;
+                    }
+                };
+            while sighup.recv().await.is_some() {
+                match state.reload_config().await {
+                    Ok(r) => info!(
+                        applied = ?r.applied,
+                        restart_required = ?r.restart_required,
+                        "reloaded config on SIGHUP"
+                    ),
+                    Err(e) => error!("error reloading config on SIGHUP: {:#}", e),
+                }
+            }
+        });
+    }
+
     tauri::Builder::default()
         .manage(state)
         .invoke_handler(tauri::generate_handler![
             torrents_list,
             torrent_details,
             torrent_stats,
+            torrent_diagnose,
+            torrents_stats_delta,
             torrent_create_from_url,
             torrent_action_delete,
             torrent_action_pause,
+            torrent_set_locked,
+            torrent_boost,
+            torrent_set_upload_slots,
+            torrent_set_priority_tier,
             torrent_action_forget,
             torrent_action_start,
+            torrent_action_load,
+            torrent_action_recheck,
+            torrent_cancel_verify,
+            torrent_magnet,
+            torrent_action_solo,
+            solo_clear,
             torrent_create_from_base64_file,
+            torrent_create_from_path,
             get_version,
             config_default,
             config_current,
             config_change,
+            config_reload,
+            state_files_relocate,
+            dht_active_lookups,
+            torrents_resolving,
+            torrents_bulk_update,
+            announce_set_enabled,
+            dht_set_read_only,
+            listen_status,
+            upnp_status,
+            upnp_remap,
+            connect_rate,
+            connect_rate_set,
+            storage_stats,
+            queue,
+            queue_sort,
+            queue_dequeue,
+            collections_list,
+            collection_create,
+            collection_delete,
+            collection_add,
+            collection_remove,
+            collection_stats,
+            collection_pause,
+            collection_start,
+            collection_delete_torrents,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -342,3 +868,43 @@ fn main() {
         .expect("couldn't set up tokio runtime")
         .block_on(start())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::RqbitDesktopConfig;
+
+    use super::changed_sections;
+
+    // Not RqbitDesktopConfig::default(), there may be no download folder to default to.
+    fn test_config() -> RqbitDesktopConfig {
+        RqbitDesktopConfig {
+            default_download_location: "/downloads".into(),
+            dht: Default::default(),
+            tcp_listen: Default::default(),
+            upnp: Default::default(),
+            persistence: Default::default(),
+            peer_opts: Default::default(),
+            http_api: Default::default(),
+            power_profile: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_changed_sections() {
+        let a = test_config();
+        assert!(changed_sections(&a, &a.clone()).is_empty());
+
+        let mut b = a.clone();
+        b.dht.read_only = !a.dht.read_only;
+        b.tcp_listen.max_port += 1;
+        b.http_api.read_only = !a.http_api.read_only;
+        assert_eq!(
+            changed_sections(&a, &b),
+            vec!["dht", "tcp_listen", "http_api"]
+        );
+
+        let mut c = a.clone();
+        c.default_download_location = "/elsewhere".into();
+        assert_eq!(changed_sections(&a, &c), vec!["default_download_location"]);
+    }
+}
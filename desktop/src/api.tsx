@@ -9,6 +9,7 @@ import {
 } from "rqbit-webui/src/api-types";
 
 import { InvokeArgs, invoke } from "@tauri-apps/api/tauri";
+import { open } from "@tauri-apps/api/dialog";
 
 interface InvokeErrorResponse {
   error_kind: string;
@@ -88,7 +89,21 @@ export const makeAPI = (configuration: RqbitDesktopConfig): RqbitAPI => {
     getTorrentStats: async function (id: number): Promise<TorrentStats> {
       return await invokeAPI<TorrentStats>("torrent_stats", { id });
     },
+    pickTorrentFile: async function (): Promise<string | null> {
+      const path = await open({
+        multiple: false,
+        filters: [{ name: "Torrent", extensions: ["torrent"] }],
+      });
+      return typeof path === "string" ? path : null;
+    },
     uploadTorrent: async function (data, opts): Promise<AddTorrentResponse> {
+      // Local paths come from pickTorrentFile().
+      if (typeof data === "string" && !/^(magnet|https?):/.test(data)) {
+        return await invokeAPI<AddTorrentResponse>("torrent_create_from_path", {
+          path: data,
+          opts: opts ?? {},
+        });
+      }
       if (data instanceof File) {
         let contents = await readFileAsBase64(data);
         return await invokeAPI<AddTorrentResponse>(
@@ -6,6 +6,8 @@ interface RqbitDesktopConfigDht {
   disable: boolean;
   disable_persistence: boolean;
   persistence_filename: PathLike;
+  read_only: boolean;
+  max_query_rate: number;
 }
 
 interface RqbitDesktopConfigTcpListen {
@@ -33,8 +35,12 @@ interface RqbitDesktopConfigHttpApi {
 
 interface RqbitDesktopConfigUpnp {
   disable: boolean;
+  lease_duration: Duration;
+  refresh_interval: Duration;
 }
 
+type PowerProfile = "performance" | "balanced" | "low_power";
+
 export interface RqbitDesktopConfig {
   default_download_location: PathLike;
   dht: RqbitDesktopConfigDht;
@@ -43,6 +49,8 @@ export interface RqbitDesktopConfig {
   persistence: RqbitDesktopConfigPersistence;
   peer_opts: RqbitDesktopConfigPeerOpts;
   http_api: RqbitDesktopConfigHttpApi;
+  power_profile: PowerProfile;
+  profile?: PathLike | null;
 }
 
 export interface CurrentDesktopState {
@@ -53,6 +53,45 @@ const FormInput: React.FC<{
   );
 };
 
+const FormSelect: React.FC<{
+  label: string;
+  name: string;
+  value: string;
+  options: readonly { value: string; label: string }[];
+  onChange: React.ChangeEventHandler<HTMLSelectElement>;
+  help?: string;
+}> = ({ label, name, value, options, onChange, help }) => {
+  return (
+    <div className="flex flex-col gap-2 text-sm mb-2">
+      <label htmlFor={name} className="dark:text-white">
+        {label}
+      </label>
+      <select
+        className="block border rounded bg-transparent py-1.5 pl-2 text-gray-800 focus:ring-0 sm:text-sm sm:leading-6 dark:text-slate-300"
+        id={name}
+        name={name}
+        value={value}
+        onChange={onChange}
+      >
+        {options.map((o) => (
+          <option key={o.value} value={o.value}>
+            {o.label}
+          </option>
+        ))}
+      </select>
+      {help && (
+        <div className="text-xs text-slate-500 dark:text-slate-300">{help}</div>
+      )}
+    </div>
+  );
+};
+
+const POWER_PROFILES = [
+  { value: "performance", label: "Performance" },
+  { value: "balanced", label: "Balanced" },
+  { value: "low_power", label: "Low power" },
+] as const;
+
 type TAB =
   | "Home"
   | "DHT"
@@ -238,6 +277,25 @@ export const ConfigModal: React.FC<{
               onChange={handleInputChange}
               help="The filename to store DHT state into"
             />
+
+            <FormCheck
+              label="Read-only DHT"
+              name="dht.read_only"
+              checked={config.dht.read_only}
+              onChange={handleToggleChange}
+              disabled={config.dht.disable}
+              help="Use the DHT to find peers, but don't answer queries from other nodes. Less traffic, e.g. on metered or slow connections."
+            />
+
+            <FormInput
+              inputType="number"
+              label="Max queries per second"
+              name="dht.max_query_rate"
+              value={config.dht.max_query_rate}
+              disabled={config.dht.disable}
+              onChange={handleInputChange}
+              help="Limit how many queries the DHT sends out per second. Lower saves bandwidth on metered connections, but finding peers for magnet links takes longer."
+            />
           </Fieldset>
         </Tab>
 
@@ -259,6 +317,26 @@ export const ConfigModal: React.FC<{
               help="Advertise your port over UPnP. This is required for peers to be able to connect to you from the internet. Will only work if your router has a static IP."
             />
 
+            <FormInput
+              inputType="number"
+              label="UPnP lease duration (seconds)"
+              name="upnp.lease_duration"
+              value={config.upnp.lease_duration}
+              disabled={config.upnp.disable}
+              onChange={handleInputChange}
+              help="How long the router should keep the port mapping."
+            />
+
+            <FormInput
+              inputType="number"
+              label="UPnP refresh interval (seconds)"
+              name="upnp.refresh_interval"
+              value={config.upnp.refresh_interval}
+              disabled={config.upnp.disable}
+              onChange={handleInputChange}
+              help="How often to renew the port mapping. Must be shorter than the lease. Lower it if your router drops mappings early."
+            />
+
             <FormInput
               inputType="number"
               label="Min port"
@@ -299,6 +377,20 @@ export const ConfigModal: React.FC<{
               onChange={handleInputChange}
               disabled={config.persistence.disable}
             />
+
+            <FormSelect
+              label="Power profile"
+              name="power_profile"
+              value={config.power_profile}
+              options={POWER_PROFILES}
+              onChange={(e) =>
+                setConfig((prevConfig) => ({
+                  ...prevConfig,
+                  power_profile: e.target.value as RqbitDesktopConfig["power_profile"],
+                }))
+              }
+              help="Low power wakes the CPU up less often to save battery. Peers are found and reconnected to slower, so downloads may take longer to ramp up."
+            />
           </Fieldset>
         </Tab>
 